@@ -0,0 +1,52 @@
+use chip8::system::rewind::{RewindBuffer, RewindConfig};
+use chip8::system::{Keys, System};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+/// frames recorded per iteration, ten segments at the default keyframe interval
+const FRAMES: usize = 600;
+
+/// instructions executed per frame
+const STEPS: u32 = 10;
+
+/// ADD V0, 1 / LD I, 200 / DRW V0, V1, 1 / JP 200, moves a sprite across the screen
+const ROM: [u8; 8] = [0x70, 0x01, 0xA2, 0x00, 0xD0, 0x11, 0x12, 0x00];
+
+/// the save state of every frame, as the frontend records them
+fn states() -> Vec<Vec<u8>> {
+    let mut system = System::default();
+    system.load(&ROM[..]).unwrap();
+    let inputs = Keys::default();
+    (0..FRAMES)
+        .map(|_| {
+            system.run_batch(STEPS, &inputs);
+            system.save_state()
+        })
+        .collect()
+}
+
+fn filled(states: &[Vec<u8>]) -> RewindBuffer {
+    let mut buffer = RewindBuffer::new(RewindConfig::default());
+    for state in states {
+        buffer.push(state.clone());
+    }
+    buffer
+}
+
+fn push(c: &mut Criterion) {
+    let states = states();
+    c.bench_function("rewind_push", |b| b.iter(|| filled(&states)));
+}
+
+fn pop(c: &mut Criterion) {
+    let states = states();
+    c.bench_function("rewind_pop", |b| {
+        b.iter_batched(
+            || filled(&states),
+            |mut buffer| while buffer.pop().is_some() {},
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(benches, push, pop);
+criterion_main!(benches);
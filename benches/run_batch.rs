@@ -0,0 +1,36 @@
+use chip8::system::debug::Debugger;
+use chip8::system::{Keys, System};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// instructions executed per iteration
+const N: u32 = 10_000;
+
+/// ADD V0, 1 / JP 200, runs without ever stopping a batch
+const ROM: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+fn system() -> System {
+    let mut system = System::default();
+    system.load(&ROM[..]).unwrap();
+    system
+}
+
+fn tick(c: &mut Criterion) {
+    let mut system = system();
+    let mut dbg = Debugger::disabled();
+    c.bench_function("tick", |b| {
+        b.iter(|| {
+            for _ in 0..N {
+                system.tick(&mut dbg).unwrap();
+            }
+        })
+    });
+}
+
+fn run_batch(c: &mut Criterion) {
+    let mut system = system();
+    let inputs = Keys::default();
+    c.bench_function("run_batch", |b| b.iter(|| system.run_batch(N, &inputs)));
+}
+
+criterion_group!(benches, tick, run_batch);
+criterion_main!(benches);
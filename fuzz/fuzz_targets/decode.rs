@@ -0,0 +1,24 @@
+#![no_main]
+
+use chip8::system::debug::Debugger;
+use chip8::system::opcode::Instruction;
+use chip8::system::{System, SystemError};
+use chip8::{asm, disasm};
+use libfuzzer_sys::fuzz_target;
+
+// Any word: an instruction disassembles to text that assembles back to it, and the cpu knows
+// it. Other words disassemble to data
+fuzz_target!(|word: u16| {
+    let instruction = Instruction::decode(word);
+    let text = disasm::format(instruction);
+
+    let mut system = System::default();
+    system.load(&word.to_be_bytes()[..]).unwrap();
+    let executed = system.tick(&mut Debugger::disabled());
+
+    if let Ok(instruction) = instruction {
+        assert_eq!(instruction.encode(), word);
+        assert_eq!(asm::assemble(&text).unwrap(), word.to_be_bytes(), "{}", text);
+        assert!(!matches!(executed, Err(SystemError::UnknownOpcode { .. })), "{}", text);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use chip8::system::opcode::Instruction;
+use chip8::{asm, disasm};
+use libfuzzer_sys::fuzz_target;
+
+// Any instruction: its word decodes to the same instruction, and its disassembly assembles to
+// that word
+fuzz_target!(|instruction: Instruction| {
+    let word = instruction.encode();
+    let decoded = Instruction::decode(word).unwrap();
+    assert_eq!(decoded.info.opcode, instruction.info.opcode);
+    assert_eq!(decoded.encode(), word);
+
+    let text = disasm::format(Ok(instruction));
+    assert_eq!(asm::assemble(&text).unwrap(), word.to_be_bytes(), "{}", text);
+});
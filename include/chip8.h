@@ -0,0 +1,60 @@
+/*
+ * C API of the chip8 emulator core, see src/capi.rs. Build the library with
+ *
+ *     cargo rustc --lib --release --features capi --crate-type cdylib
+ *
+ * (or staticlib) and link against target/release/libchip8.
+ *
+ * Functions that can fail return 0 on success and -1 on failure, the message is then
+ * available from chip8_last_error. Every function accepts a NULL handle and fails.
+ */
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define CHIP8_API_VERSION 1
+#define CHIP8_SCREEN_WIDTH 64
+#define CHIP8_SCREEN_HEIGHT 32
+
+/* A machine with its memory, registers, timers, keys and screen */
+typedef struct Chip8 chip8_t;
+
+/* The version of the API the library implements, compare with CHIP8_API_VERSION */
+uint32_t chip8_api_version(void);
+
+/* A new machine with the default quirks, free it with chip8_free */
+chip8_t *chip8_new(void);
+
+void chip8_free(chip8_t *chip8);
+
+/* Loads `len` bytes of a rom at 0x200 and starts it from scratch */
+int chip8_load_rom(chip8_t *chip8, const uint8_t *rom, size_t len);
+
+/* Executes one instruction */
+int chip8_step(chip8_t *chip8);
+
+/* Decrements the delay and sound timers, call it 60 times per second. Returns 1 while the
+ * sound timer runs (the beep should play), 0 otherwise */
+int chip8_tick_timers(chip8_t *chip8);
+
+/* Presses (pressed != 0) or releases hex key `key`, 0 to 0xF */
+int chip8_set_key(chip8_t *chip8, uint8_t key, int pressed);
+
+/* Copies the screen to `out`, one byte per pixel from the top left: 0 unlit, 255 lit.
+ * Returns CHIP8_SCREEN_WIDTH * CHIP8_SCREEN_HEIGHT, nothing is copied if `len` is less */
+size_t chip8_framebuffer(const chip8_t *chip8, uint8_t *out, size_t len);
+
+/* Message of the last failed call, valid until the next call on `chip8` */
+const char *chip8_last_error(const chip8_t *chip8);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
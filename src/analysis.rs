@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::match_opcodes;
+use crate::system::opcode::Opcode;
+use crate::system::PROGRAM_START;
+
+pub mod decompile;
+pub mod regions;
+pub mod registers;
+
+/// What an instruction does to the control flow
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    Next,
+    /// continues at the next or the one after
+    Skip,
+    Jump(u16),
+    Call(u16),
+    Return,
+    /// the target is not known statically
+    Computed,
+    Stop,
+}
+
+/// Control flow of a single instruction, unknown instructions stop
+pub fn flow(opcode: u16) -> Flow {
+    match_opcodes! {
+        opcode;
+
+        noarg Opcode::ClearScreen => Flow::Next,
+        noarg Opcode::Return => Flow::Return,
+        long addr = Opcode::Jump => Flow::Jump(addr),
+        long addr = Opcode::Call => Flow::Call(addr),
+        long _addr = Opcode::JumpPlus => Flow::Computed,
+        (_reg, _val) = Opcode::SkipIfEq => Flow::Skip,
+        (_reg, _val) = Opcode::SkipIfNeq => Flow::Skip,
+        (_reg1, _reg2) = Opcode::SkipIfRegEq => Flow::Skip,
+        (_reg1, _reg2) = Opcode::SkipIfRegNeq => Flow::Skip,
+        _reg = Opcode::SkipIfKeyPressed => Flow::Skip,
+        _reg = Opcode::SkipIfKeyNotPressed => Flow::Skip,
+        (_reg, _val) = Opcode::SetReg => Flow::Next,
+        (_reg, _val) = Opcode::SAddReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::MovReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::OrReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::AndReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::XorReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::AddReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::SubReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::RShiftReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::RSubReg => Flow::Next,
+        (_reg1, _reg2) = Opcode::LShiftReg => Flow::Next,
+        long _addr = Opcode::SetIndex => Flow::Next,
+        (_reg, _val) = Opcode::Rand => Flow::Next,
+        (_x, _y, _height) = Opcode::Draw => Flow::Next,
+        _reg = Opcode::GetDelay => Flow::Next,
+        _reg = Opcode::BlockGetKey => Flow::Next,
+        _reg = Opcode::SetDelay => Flow::Next,
+        _reg = Opcode::SetSound => Flow::Next,
+        _reg = Opcode::AddIndex => Flow::Next,
+        _reg = Opcode::GetSprite => Flow::Next,
+        _reg = Opcode::BinCoded => Flow::Next,
+        _reg = Opcode::RegDump => Flow::Next,
+        _reg = Opcode::RegLoad => Flow::Next,
+
+        otherwise _x => Flow::Stop
+    }
+}
+
+/// The instruction at `pc` of a rom loaded at the program start
+pub fn fetch(rom: &[u8], pc: u16) -> Option<u16> {
+    let offset = pc.checked_sub(PROGRAM_START)? as usize;
+    match rom.get(offset..offset + 2)? {
+        [fst, snd] => Some(u16::from(*fst) << 8 | u16::from(*snd)),
+        _ => None,
+    }
+}
+
+/// `main` for the program start, `sub_<addr>` for subroutines
+pub fn routine_name(entry: u16) -> String {
+    if entry == PROGRAM_START {
+        "main".to_string()
+    } else {
+        format!("sub_{:03x}", entry)
+    }
+}
+
+/// Straight-line instructions, entered only at the start and left only at the end
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub start: u16,
+    /// (address, opcode) of every instruction
+    pub instructions: Vec<(u16, u16)>,
+    /// addresses of the blocks that may run next, in the same routine
+    pub successors: Vec<u16>,
+}
+
+/// Code reachable from an entry point without following calls
+#[derive(Clone, Debug)]
+pub struct Routine {
+    pub entry: u16,
+    pub blocks: BTreeMap<u16, Block>,
+    /// entry points of the routines it calls
+    pub calls: BTreeSet<u16>,
+}
+
+impl Routine {
+    /// every instruction of the routine in address order
+    pub fn instructions(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.blocks.values().flat_map(|block| block.instructions.iter().copied())
+    }
+}
+
+/// Control flow graph of a rom: the main program and every subroutine it calls
+#[derive(Clone, Debug, Default)]
+pub struct Cfg {
+    pub routines: BTreeMap<u16, Routine>,
+}
+
+impl Cfg {
+    pub fn build(rom: &[u8]) -> Self {
+        let mut cfg = Cfg::default();
+        let mut pending = vec![PROGRAM_START];
+        while let Some(entry) = pending.pop() {
+            if cfg.routines.contains_key(&entry) {
+                continue;
+            }
+            let routine = routine(rom, entry);
+            pending.extend(routine.calls.iter().copied());
+            cfg.routines.insert(entry, routine);
+        }
+
+        cfg
+    }
+}
+
+fn routine(rom: &[u8], entry: u16) -> Routine {
+    let mut code = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    let mut calls = BTreeSet::new();
+    leaders.insert(entry);
+
+    let mut queue = vec![entry];
+    while let Some(pc) = queue.pop() {
+        if code.contains_key(&pc) {
+            continue;
+        }
+        let opcode = match fetch(rom, pc) {
+            Some(opcode) => opcode,
+            None => continue,
+        };
+        code.insert(pc, opcode);
+
+        match flow(opcode) {
+            Flow::Next => queue.push(pc.wrapping_add(2)),
+            Flow::Call(target) => {
+                calls.insert(target);
+                queue.push(pc.wrapping_add(2));
+            }
+            Flow::Skip => {
+                for next in [pc.wrapping_add(2), pc.wrapping_add(4)] {
+                    leaders.insert(next);
+                    queue.push(next);
+                }
+            }
+            Flow::Jump(target) => {
+                leaders.insert(target);
+                queue.push(target);
+            }
+            Flow::Return | Flow::Computed | Flow::Stop => {}
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter().filter(|start| code.contains_key(start)) {
+        let mut instructions = Vec::new();
+        let mut pc = start;
+        let successors = loop {
+            let opcode = code[&pc];
+            instructions.push((pc, opcode));
+            let next = pc.wrapping_add(2);
+            match flow(opcode) {
+                Flow::Next | Flow::Call(_) => {
+                    if leaders.contains(&next) || !code.contains_key(&next) {
+                        break vec![next];
+                    }
+                    pc = next;
+                }
+                Flow::Skip => break vec![next, pc.wrapping_add(4)],
+                Flow::Jump(target) => break vec![target],
+                Flow::Return | Flow::Computed | Flow::Stop => break Vec::new(),
+            }
+        };
+        let successors = successors.into_iter().filter(|next| code.contains_key(next)).collect();
+
+        blocks.insert(start, Block {
+            start,
+            instructions,
+            successors,
+        });
+    }
+
+    Routine { entry, blocks, calls }
+}
@@ -0,0 +1,337 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::{flow, routine_name, Cfg, Flow, Routine};
+use crate::match_opcodes;
+use crate::system::opcode::Opcode;
+
+/// Condition under which a skip instruction skips
+fn skip_condition(opcode: u16) -> Option<String> {
+    match_opcodes! {
+        opcode;
+
+        (reg, val) = Opcode::SkipIfEq => Some(format!("v{:x} == {:#04x}", reg, val)),
+        (reg, val) = Opcode::SkipIfNeq => Some(format!("v{:x} != {:#04x}", reg, val)),
+        (reg1, reg2) = Opcode::SkipIfRegEq => Some(format!("v{:x} == v{:x}", reg1, reg2)),
+        (reg1, reg2) = Opcode::SkipIfRegNeq => Some(format!("v{:x} != v{:x}", reg1, reg2)),
+        reg = Opcode::SkipIfKeyPressed => Some(format!("key_pressed(v{:x})", reg)),
+        reg = Opcode::SkipIfKeyNotPressed => Some(format!("!key_pressed(v{:x})", reg)),
+        otherwise _x => None
+    }
+}
+
+fn negate(condition: &str) -> String {
+    if let Some(positive) = condition.strip_prefix('!') {
+        positive.to_string()
+    } else if condition.contains(" == ") {
+        condition.replace(" == ", " != ")
+    } else if condition.contains(" != ") {
+        condition.replace(" != ", " == ")
+    } else {
+        format!("!{}", condition)
+    }
+}
+
+fn label(addr: u16) -> String {
+    format!("label_{:03x}", addr)
+}
+
+fn registers(last: u8) -> String {
+    if last == 0 {
+        "v0".to_string()
+    } else {
+        format!("v0..v{:x}", last)
+    }
+}
+
+/// A statement for an instruction that does not branch
+fn statement(opcode: u16) -> String {
+    match_opcodes! {
+        opcode;
+
+        noarg Opcode::ClearScreen => "clear_screen();".to_string(),
+        noarg Opcode::Return => "return;".to_string(),
+        long addr = Opcode::Call => format!("{}();", routine_name(addr)),
+        long addr = Opcode::Jump => format!("goto {};", label(addr)),
+        long addr = Opcode::JumpPlus => format!("goto *({:#05x} + v0);", addr),
+        (reg, val) = Opcode::SetReg => format!("v{:x} = {:#04x};", reg, val),
+        (reg, val) = Opcode::SAddReg => format!("v{:x} += {:#04x};", reg, val),
+        (reg1, reg2) = Opcode::MovReg => format!("v{:x} = v{:x};", reg1, reg2),
+        (reg1, reg2) = Opcode::OrReg => format!("v{:x} |= v{:x};", reg1, reg2),
+        (reg1, reg2) = Opcode::AndReg => format!("v{:x} &= v{:x};", reg1, reg2),
+        (reg1, reg2) = Opcode::XorReg => format!("v{:x} ^= v{:x};", reg1, reg2),
+        (reg1, reg2) = Opcode::AddReg => format!("v{:x} += v{:x}; // vf = carry", reg1, reg2),
+        (reg1, reg2) = Opcode::SubReg => format!("v{:x} -= v{:x}; // vf = no borrow", reg1, reg2),
+        (reg1, reg2) = Opcode::RSubReg => format!("v{0:x} = v{1:x} - v{0:x}; // vf = no borrow", reg1, reg2),
+        (reg1, reg2) = Opcode::RShiftReg => {
+            if reg1 == reg2 {
+                format!("v{:x} >>= 1; // vf = shifted out bit", reg1)
+            } else {
+                format!("v{:x} = v{:x} >> 1; // vf = shifted out bit", reg1, reg2)
+            }
+        },
+        (reg1, reg2) = Opcode::LShiftReg => {
+            if reg1 == reg2 {
+                format!("v{:x} <<= 1; // vf = shifted out bit", reg1)
+            } else {
+                format!("v{:x} = v{:x} << 1; // vf = shifted out bit", reg1, reg2)
+            }
+        },
+        long addr = Opcode::SetIndex => format!("i = {:#05x};", addr),
+        (reg, val) = Opcode::Rand => format!("v{:x} = rand() & {:#04x};", reg, val),
+        (x, y, height) = Opcode::Draw => format!("vf = draw(v{:x}, v{:x}, {}); // collision", x, y, height),
+        reg = Opcode::GetDelay => format!("v{:x} = delay;", reg),
+        reg = Opcode::BlockGetKey => format!("v{:x} = wait_key();", reg),
+        reg = Opcode::SetDelay => format!("delay = v{:x};", reg),
+        reg = Opcode::SetSound => format!("sound = v{:x};", reg),
+        reg = Opcode::AddIndex => format!("i += v{:x};", reg),
+        reg = Opcode::GetSprite => format!("i = font(v{:x});", reg),
+        reg = Opcode::BinCoded => format!("store_bcd(i, v{:x});", reg),
+        reg = Opcode::RegDump => format!("store(i, {});", registers(reg)),
+        reg = Opcode::RegLoad => format!("load({}, i);", registers(reg)),
+
+        otherwise x => {
+            if x == 0 {
+                "halt();".to_string()
+            } else {
+                format!("unknown({:#06x});", x)
+            }
+        }
+    }
+}
+
+/// Control structures recognized from skip and jump patterns
+enum Kind {
+    /// `skip if c; JP else` at the start: `if (c) { .. }`, with an else branch if the body
+    /// ends with `JP end`
+    If { condition: String, otherwise: Option<u16> },
+    /// `skip if c; JP start` at the end: `do { .. } while (!c);`
+    DoWhile { condition: String },
+    /// `JP start` at the end: `while (1) { .. }`
+    Loop,
+}
+
+struct Structure {
+    start: u16,
+    end: u16,
+    kind: Kind,
+}
+
+impl Structure {
+    /// structures must not partially overlap to be printed as nested blocks
+    fn nests_with(&self, other: &Structure) -> bool {
+        self.end <= other.start
+            || other.end <= self.start
+            || (self.start <= other.start && other.end <= self.end)
+            || (other.start <= self.start && self.end <= other.end)
+    }
+}
+
+/// Pseudo-C for one routine of the control flow graph
+struct Decompiler<'a> {
+    code: &'a BTreeMap<u16, u16>,
+    /// sorted by start, outer structures first
+    structures: Vec<Structure>,
+    /// instructions printed as part of a structure or of the skip before them
+    consumed: BTreeSet<u16>,
+    /// addresses that need a label
+    labels: BTreeSet<u16>,
+    /// branch targets, instructions that can be entered from elsewhere
+    targets: BTreeSet<u16>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(code: &'a BTreeMap<u16, u16>) -> Self {
+        let mut targets = BTreeSet::new();
+        for (&pc, &opcode) in code {
+            match flow(opcode) {
+                Flow::Jump(target) => {
+                    targets.insert(target);
+                }
+                Flow::Skip => {
+                    targets.insert(pc.wrapping_add(4));
+                }
+                _ => {}
+            }
+        }
+
+        let mut decompiler = Decompiler {
+            code,
+            structures: Vec::new(),
+            consumed: BTreeSet::new(),
+            labels: BTreeSet::new(),
+            targets,
+        };
+        decompiler.find_structures();
+        decompiler.find_labels();
+        decompiler
+    }
+
+    fn add(&mut self, structure: Structure, consumed: &[u16]) {
+        if self.structures.iter().all(|other| other.nests_with(&structure)) {
+            self.structures.push(structure);
+            self.consumed.extend(consumed);
+        }
+    }
+
+    fn find_structures(&mut self) {
+        for (&pc, &opcode) in self.code {
+            let next = pc.wrapping_add(2);
+            let after = pc.wrapping_add(4);
+            let condition = skip_condition(opcode);
+            match (condition, self.code.get(&next).map(|&next| flow(next))) {
+                // a jump into the middle of the pattern rules it out
+                (Some(condition), Some(Flow::Jump(target))) if !self.targets.contains(&next) => {
+                    if target > after {
+                        // the jump over the else branch
+                        let last = target.wrapping_sub(2);
+                        match self.code.get(&last).map(|&last| flow(last)) {
+                            Some(Flow::Jump(end)) if end > target && last > next && !self.targets.contains(&last) => {
+                                let kind = Kind::If {
+                                    condition,
+                                    otherwise: Some(target),
+                                };
+                                self.add(Structure { start: pc, end, kind }, &[pc, next, last]);
+                            }
+                            _ => {
+                                let kind = Kind::If { condition, otherwise: None };
+                                self.add(Structure { start: pc, end: target, kind }, &[pc, next]);
+                            }
+                        }
+                    } else if target <= pc {
+                        let kind = Kind::DoWhile {
+                            condition: negate(&condition),
+                        };
+                        self.add(Structure { start: target, end: after, kind }, &[pc, next]);
+                    }
+                }
+                (None, _) => {
+                    if let Flow::Jump(target) = flow(opcode) {
+                        if target <= pc && !self.consumed.contains(&pc) {
+                            self.add(Structure { start: target, end: next, kind: Kind::Loop }, &[pc]);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.structures.sort_by_key(|structure| (structure.start, std::cmp::Reverse(structure.end)));
+    }
+
+    /// whether the instruction skipped by the skip at `pc` can be printed as `if (..) statement`
+    fn inline_skipped(&self, pc: u16) -> bool {
+        let next = pc.wrapping_add(2);
+        let boundary = self
+            .structures
+            .iter()
+            .any(|structure| structure.start == next || structure.end == next);
+        match self.code.get(&next) {
+            Some(&opcode) => {
+                flow(opcode) != Flow::Skip
+                    && !boundary
+                    && !self.targets.contains(&next)
+                    && !self.consumed.contains(&next)
+            }
+            None => false,
+        }
+    }
+
+    fn find_labels(&mut self) {
+        for (&pc, &opcode) in self.code {
+            if self.consumed.contains(&pc) {
+                continue;
+            }
+            match flow(opcode) {
+                Flow::Jump(target) => {
+                    self.labels.insert(target);
+                }
+                Flow::Skip if !self.inline_skipped(pc) => {
+                    self.labels.insert(pc.wrapping_add(4));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn write(&self, name: &str, out: &mut String) {
+        let indent = |depth: usize| "    ".repeat(depth);
+        writeln!(out, "void {}() {{", name).unwrap();
+
+        let mut open: Vec<&Structure> = Vec::new();
+        let mut structures = self.structures.iter().peekable();
+        let mut inlined = BTreeSet::new();
+        let mut printed_else = BTreeSet::new();
+        for (&pc, &opcode) in self.code {
+            while let Some(structure) = open.last().filter(|structure| structure.end <= pc) {
+                close(structure, &indent(open.len()), out);
+                open.pop();
+            }
+            if let Some(Kind::If { otherwise: Some(otherwise), .. }) = open.last().map(|structure| &structure.kind) {
+                if *otherwise <= pc && !printed_else.contains(otherwise) {
+                    writeln!(out, "{}}} else {{", indent(open.len())).unwrap();
+                    printed_else.insert(*otherwise);
+                }
+            }
+            if self.labels.contains(&pc) {
+                writeln!(out, "{}{}:", indent(open.len()), label(pc)).unwrap();
+            }
+            while let Some(structure) = structures.next_if(|structure| structure.start <= pc) {
+                let header = match &structure.kind {
+                    Kind::If { condition, .. } => format!("if ({}) {{", condition),
+                    Kind::DoWhile { .. } => "do {".to_string(),
+                    Kind::Loop => "while (1) {".to_string(),
+                };
+                writeln!(out, "{}{}", indent(open.len() + 1), header).unwrap();
+                open.push(structure);
+            }
+
+            if self.consumed.contains(&pc) || inlined.contains(&pc) {
+                continue;
+            }
+            let depth = indent(open.len() + 1);
+            match skip_condition(opcode) {
+                Some(condition) if self.inline_skipped(pc) => {
+                    let next = pc.wrapping_add(2);
+                    let statement = statement(self.code[&next]);
+                    writeln!(out, "{}if ({}) {}", depth, negate(&condition), statement).unwrap();
+                    inlined.insert(next);
+                }
+                Some(condition) => {
+                    writeln!(out, "{}if ({}) goto {};", depth, condition, label(pc.wrapping_add(4))).unwrap();
+                }
+                None => writeln!(out, "{}{}", depth, statement(opcode)).unwrap(),
+            }
+        }
+        while let Some(structure) = open.pop() {
+            close(structure, &indent(open.len() + 1), out);
+        }
+
+        writeln!(out, "}}").unwrap();
+    }
+}
+
+fn close(structure: &Structure, indent: &str, out: &mut String) {
+    match &structure.kind {
+        Kind::DoWhile { condition } => writeln!(out, "{}}} while ({});", indent, condition).unwrap(),
+        Kind::If { .. } | Kind::Loop => writeln!(out, "{}}}", indent).unwrap(),
+    }
+}
+
+fn routine(routine: &Routine, out: &mut String) {
+    let code = routine.instructions().collect::<BTreeMap<_, _>>();
+    Decompiler::new(&code).write(&routine_name(routine.entry), out);
+}
+
+/// Experimental: pseudo-C for every routine reachable from the program start.
+/// `if` and loops are recovered from skip and jump patterns, everything else is a goto
+pub fn decompile(rom: &[u8]) -> String {
+    let cfg = Cfg::build(rom);
+    let mut out = String::new();
+    for (i, entry) in cfg.routines.values().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        routine(entry, &mut out);
+    }
+    out
+}
@@ -0,0 +1,129 @@
+use std::ops::Range;
+
+use super::Cfg;
+use crate::system::{fonts, MEMORY_SIZE, PROGRAM_START};
+
+/// Where the COSMAC VIP interpreter keeps its stack, a program writing there would crash it
+pub const VIP_STACK: Range<u16> = 0xEA0..0xF00;
+/// Where the COSMAC VIP keeps the screen
+pub const VIP_DISPLAY: Range<u16> = 0xF00..0x1000;
+
+/// What a byte of memory holds, as far as the analysis and the program run so far tell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    Font,
+    /// below the program start, past the fonts
+    Interpreter,
+    /// instructions the control flow reaches from the program start
+    Code,
+    /// read by a DRW
+    Sprite,
+    /// loaded from the rom or written by the program, but neither run nor drawn
+    Data,
+    Stack,
+    Display,
+    /// never written
+    Free,
+}
+
+impl Region {
+    pub fn label(self) -> &'static str {
+        match self {
+            Region::Font => "font",
+            Region::Interpreter => "interpreter",
+            Region::Code => "code",
+            Region::Sprite => "sprite",
+            Region::Data => "data",
+            Region::Stack => "stack (VIP)",
+            Region::Display => "screen (VIP)",
+            Region::Free => "free",
+        }
+    }
+
+    /// ANSI escape code of the color its bytes are shown in
+    pub fn color(self) -> &'static str {
+        match self {
+            Region::Font => "\x1b[36m",
+            Region::Interpreter | Region::Free => "\x1b[90m",
+            Region::Code => "\x1b[32m",
+            Region::Sprite => "\x1b[33m",
+            Region::Data => "\x1b[34m",
+            Region::Stack | Region::Display => "\x1b[35m",
+        }
+    }
+}
+
+/// The region of every address: code from the control flow graph, sprites as the program
+/// draws them
+pub struct MemoryMap {
+    code: Vec<bool>,
+    sprites: Vec<bool>,
+}
+
+impl MemoryMap {
+    pub fn new(cfg: &Cfg) -> Self {
+        let mut code = vec![false; usize::from(MEMORY_SIZE)];
+        for (addr, _) in cfg.routines.values().flat_map(|routine| routine.instructions()) {
+            for byte in code.iter_mut().skip(usize::from(addr)).take(2) {
+                *byte = true;
+            }
+        }
+        MemoryMap {
+            code,
+            sprites: vec![false; usize::from(MEMORY_SIZE)],
+        }
+    }
+
+    /// Marks the `len` bytes from `start` a DRW read
+    pub fn add_sprite(&mut self, start: u16, len: u16) {
+        for byte in self.sprites.iter_mut().skip(usize::from(start)).take(usize::from(len)) {
+            *byte = true;
+        }
+    }
+
+    /// `written` tells whether the rom or the program ever wrote `addr`
+    pub fn region(&self, addr: u16, written: bool) -> Region {
+        let marked = |bytes: &[bool]| bytes.get(usize::from(addr)).copied().unwrap_or(false);
+        if usize::from(addr) < fonts::FONT_LEN {
+            Region::Font
+        } else if addr < PROGRAM_START {
+            Region::Interpreter
+        } else if marked(&self.sprites) {
+            Region::Sprite
+        } else if marked(&self.code) {
+            Region::Code
+        } else if VIP_STACK.contains(&addr) {
+            Region::Stack
+        } else if VIP_DISPLAY.contains(&addr) {
+            Region::Display
+        } else if written {
+            Region::Data
+        } else {
+            Region::Free
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_code_sprites_and_data() {
+        let rom = [
+            0xA2, 0x06, // LD I, 0x206
+            0xD0, 0x02, // DRW V0, V0, 2
+            0x12, 0x04, // JP 0x204
+            0xF0, 0x90, // sprite
+            0x12, 0x34, // never run
+        ];
+        let mut map = MemoryMap::new(&Cfg::build(&rom));
+        map.add_sprite(0x206, 2);
+
+        let regions = (0x200..0x20A).step_by(2).map(|addr| map.region(addr, true)).collect::<Vec<_>>();
+        assert_eq!(regions, [Region::Code, Region::Code, Region::Code, Region::Sprite, Region::Data]);
+        assert_eq!(map.region(0x20A, false), Region::Free);
+        assert_eq!(map.region(0x10, true), Region::Font);
+        assert_eq!(map.region(0xEA0, false), Region::Stack);
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use super::{flow, routine_name, Cfg, Flow, Routine};
+use crate::disasm;
+use crate::lint::{Lint, Severity};
+use crate::match_opcodes;
+use crate::system::opcode::Opcode;
+
+const VF: u16 = 1 << 0xF;
+
+/// A set of registers, bit n is Vn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Registers(pub u16);
+
+impl Registers {
+    pub fn contains(self, reg: u8) -> bool {
+        self.0 & 1 << (reg & 0xF) != 0
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "-");
+        }
+        let regs = (0..16)
+            .filter(|&reg| self.contains(reg))
+            .map(|reg| format!("V{:X}", reg))
+            .collect::<Vec<_>>();
+        write!(f, "{}", regs.join(" "))
+    }
+}
+
+/// Registers an instruction reads and writes, calls are handled by the caller
+#[derive(Clone, Copy, Default)]
+struct Effect {
+    reads: u16,
+    writes: u16,
+    /// VF is set as a carry, borrow, shifted out bit or collision flag
+    flag: bool,
+}
+
+fn reg(reg: u8) -> u16 {
+    1 << reg
+}
+
+/// V0 to `last` inclusive
+fn up_to(last: u8) -> u16 {
+    ((1u32 << (last + 1)) - 1) as u16
+}
+
+fn effect(opcode: u16) -> Effect {
+    let reads = |reads| Effect { reads, ..Effect::default() };
+    let writes = |writes| Effect { writes, ..Effect::default() };
+    let flag = |reads, writes| Effect {
+        reads,
+        writes: writes | VF,
+        flag: true,
+    };
+
+    match_opcodes! {
+        opcode;
+
+        long _addr = Opcode::JumpPlus => reads(reg(0)),
+        (x, _val) = Opcode::SkipIfEq => reads(reg(x)),
+        (x, _val) = Opcode::SkipIfNeq => reads(reg(x)),
+        (x, y) = Opcode::SkipIfRegEq => reads(reg(x) | reg(y)),
+        (x, y) = Opcode::SkipIfRegNeq => reads(reg(x) | reg(y)),
+        (x, _val) = Opcode::SetReg => writes(reg(x)),
+        (x, _val) = Opcode::SAddReg => Effect { reads: reg(x), writes: reg(x), flag: false },
+        (x, y) = Opcode::MovReg => Effect { reads: reg(y), writes: reg(x), flag: false },
+        (x, y) = Opcode::OrReg => Effect { reads: reg(x) | reg(y), writes: reg(x), flag: false },
+        (x, y) = Opcode::AndReg => Effect { reads: reg(x) | reg(y), writes: reg(x), flag: false },
+        (x, y) = Opcode::XorReg => Effect { reads: reg(x) | reg(y), writes: reg(x), flag: false },
+        (x, y) = Opcode::AddReg => flag(reg(x) | reg(y), reg(x)),
+        (x, y) = Opcode::SubReg => flag(reg(x) | reg(y), reg(x)),
+        (x, y) = Opcode::RSubReg => flag(reg(x) | reg(y), reg(x)),
+        // which of the two is shifted is a quirk
+        (x, y) = Opcode::RShiftReg => flag(reg(x) | reg(y), reg(x)),
+        (x, y) = Opcode::LShiftReg => flag(reg(x) | reg(y), reg(x)),
+        (x, _val) = Opcode::Rand => writes(reg(x)),
+        (x, y, _height) = Opcode::Draw => flag(reg(x) | reg(y), 0),
+        x = Opcode::SkipIfKeyPressed => reads(reg(x)),
+        x = Opcode::SkipIfKeyNotPressed => reads(reg(x)),
+        x = Opcode::GetDelay => writes(reg(x)),
+        x = Opcode::BlockGetKey => writes(reg(x)),
+        x = Opcode::SetDelay => reads(reg(x)),
+        x = Opcode::SetSound => reads(reg(x)),
+        x = Opcode::AddIndex => reads(reg(x)),
+        x = Opcode::GetSprite => reads(reg(x)),
+        x = Opcode::BinCoded => reads(reg(x)),
+        x = Opcode::RegDump => reads(up_to(x)),
+        x = Opcode::RegLoad => writes(up_to(x)),
+
+        otherwise _x => Effect::default()
+    }
+}
+
+/// How a routine uses the registers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// registers whose value on entry may be read, by the routine or the ones it calls
+    pub reads: Registers,
+    /// registers the routine itself writes
+    pub writes: Registers,
+    /// registers that may have changed on return, including by the routines it calls
+    pub clobbers: Registers,
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "reads {}, writes {}, clobbers {}", self.reads, self.writes, self.clobbers)
+    }
+}
+
+/// Effect of the instruction, with calls taking the effect of the routine
+fn effect_with_calls(opcode: u16, usage: &BTreeMap<u16, Usage>) -> Effect {
+    match flow(opcode) {
+        Flow::Call(target) => {
+            let callee = usage.get(&target).copied().unwrap_or_default();
+            Effect {
+                reads: callee.reads.0,
+                // the callee may or may not write them
+                writes: 0,
+                flag: false,
+            }
+        }
+        _ => effect(opcode),
+    }
+}
+
+fn summarize(routine: &Routine, usage: &BTreeMap<u16, Usage>) -> Usage {
+    let mut writes = 0;
+    let mut clobbers = 0;
+    for (_, opcode) in routine.instructions() {
+        match flow(opcode) {
+            Flow::Call(target) => clobbers |= usage.get(&target).map_or(0, |callee| callee.clobbers.0),
+            _ => writes |= effect(opcode).writes,
+        }
+    }
+
+    // backwards liveness until nothing changes
+    let mut live_in = BTreeMap::new();
+    loop {
+        let mut changed = false;
+        for block in routine.blocks.values().rev() {
+            let mut live = block
+                .successors
+                .iter()
+                .fold(0, |live, next| live | live_in.get(next).copied().unwrap_or(0));
+            for &(_, opcode) in block.instructions.iter().rev() {
+                let effect = effect_with_calls(opcode, usage);
+                live = live & !effect.writes | effect.reads;
+            }
+            if live_in.insert(block.start, live) != Some(live) {
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    Usage {
+        reads: Registers(live_in.get(&routine.entry).copied().unwrap_or(0)),
+        writes: Registers(writes),
+        clobbers: Registers(writes | clobbers),
+    }
+}
+
+/// Register usage of every routine, keyed by entry point
+pub fn analyze(cfg: &Cfg) -> BTreeMap<u16, Usage> {
+    let mut usage = cfg
+        .routines
+        .keys()
+        .map(|&entry| (entry, Usage::default()))
+        .collect::<BTreeMap<_, _>>();
+
+    // recursive calls need the summaries to settle
+    loop {
+        let mut changed = false;
+        for routine in cfg.routines.values() {
+            let summary = summarize(routine, &usage);
+            if usage.insert(routine.entry, summary) != Some(summary) {
+                changed = true;
+            }
+        }
+        if !changed {
+            return usage;
+        }
+    }
+}
+
+/// One line per routine for `chip8 lint`
+pub fn report(usage: &BTreeMap<u16, Usage>) -> String {
+    usage
+        .iter()
+        .map(|(&entry, usage)| format!("{:03X} {}: {}\n", entry, routine_name(entry), usage))
+        .collect()
+}
+
+/// Values written to VF that a flag overwrites before they are read: VF used as a general
+/// register, while the instructions that set the flag are in between
+pub fn vf_lints(cfg: &Cfg, usage: &BTreeMap<u16, Usage>) -> Vec<Lint> {
+    let mut found = BTreeSet::new();
+    for routine in cfg.routines.values() {
+        // unread general writes to VF at the start of each block
+        let mut pending_in: BTreeMap<u16, BTreeSet<u16>> = BTreeMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in routine.blocks.values() {
+                let mut pending = pending_in.get(&block.start).cloned().unwrap_or_default();
+                for &(pc, opcode) in &block.instructions {
+                    let (reads, overwrites, writes) = match flow(opcode) {
+                        Flow::Call(target) => {
+                            let callee = usage.get(&target).copied().unwrap_or_default();
+                            (callee.reads.contains(0xF), callee.clobbers.contains(0xF), false)
+                        }
+                        _ => {
+                            let effect = effect(opcode);
+                            (effect.reads & VF != 0, effect.flag, effect.writes & VF != 0)
+                        }
+                    };
+                    if reads {
+                        pending.clear();
+                    }
+                    if overwrites {
+                        found.extend(pending.iter().map(|&write| (write, pc, opcode)));
+                        pending.clear();
+                    } else if writes {
+                        pending = BTreeSet::from([pc]);
+                    }
+                }
+                for next in &block.successors {
+                    let entry = pending_in.entry(*next).or_default();
+                    let len = entry.len();
+                    entry.extend(pending.iter().copied());
+                    changed |= entry.len() != len;
+                }
+            }
+        }
+    }
+
+    found
+        .into_iter()
+        .map(|(write, pc, opcode)| Lint {
+            addr: write,
+            severity: Severity::Warning,
+            message: format!(
+                "VF is used as a general register, the value is overwritten by {} at {:03X} before it is read",
+                disasm::disassemble(opcode),
+                pc
+            ),
+        })
+        .collect()
+}
@@ -0,0 +1,340 @@
+use failure::Fail;
+use std::collections::HashMap;
+
+use crate::system::device::{BANKED_START, BANKS, BANK_SIZE};
+use crate::system::opcode::{Token, OPCODES};
+use crate::system::PROGRAM_START;
+
+pub mod octo;
+
+#[derive(Debug, Fail)]
+#[fail(display = "line {}: {}", line, message)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(u32),
+    Label(String),
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Reg(u8),
+    I,
+    IndirectI,
+    Delay,
+    Sound,
+    Key,
+    Font,
+    Bcd,
+    Value(Expr),
+}
+
+struct Statement {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<Operand>,
+}
+
+/// Assembles source in the common CHIP-8 mnemonic syntax (the one `disasm` produces).
+///
+/// Supports `label:` definitions, `;` comments and the `DB`/`DW` data directives.
+/// `BANK n` puts what follows in bank `n` of the `bank_port` extension, at 0x800 once the bank
+/// is selected. `META key = value` lines, in TOML, go to a metadata block at the end of the
+/// rom (see `rom::append_metadata`), e.g. `META quirks.wrap_sprites = true`.
+/// Numbers can be decimal, hex (`0x1F` or `#1F`) or binary (`0b0101`)
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut metadata = Vec::new();
+    let mut addr = u32::from(PROGRAM_START);
+
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let mut line = line.split(';').next().unwrap_or("").trim();
+
+        // before labels, a value may contain a colon
+        if let Some(entry) = meta_entry(line) {
+            toml::from_str::<toml::value::Table>(entry)
+                .map_err(|e| error(line_no, format!("invalid metadata: {}", e)))?;
+            metadata.push(entry);
+            continue;
+        }
+
+        while let Some(colon) = line.find(':') {
+            let label = line[..colon].trim();
+            if !is_identifier(label) {
+                return Err(error(line_no, format!("invalid label: {}", label)));
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(error(line_no, format!("duplicate label: {}", label)));
+            }
+            line = line[colon + 1..].trim();
+        }
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(line_no, line)?;
+        if statement.mnemonic == "BANK" {
+            addr = BANKED_START as u32;
+        } else {
+            addr += statement_size(&statement);
+        }
+        statements.push(statement);
+    }
+
+    let mut out = Vec::new();
+    let mut bank = None;
+    for statement in &statements {
+        if statement.mnemonic == "BANK" {
+            start_bank(statement, &mut bank, &mut out)?;
+        } else {
+            encode(statement, &labels, &mut out)?;
+        }
+    }
+    if let Some(bank) = bank {
+        check_bank_size(statements.last().map_or(0, |s| s.line), bank, &out)?;
+    }
+    if !metadata.is_empty() {
+        crate::rom::append_metadata(&mut out, &(metadata.join("\n") + "\n"))
+            .map_err(|e| error(src.lines().count(), e.to_string()))?;
+    }
+
+    Ok(out)
+}
+
+/// The key/value of a `META` line
+fn meta_entry(line: &str) -> Option<&str> {
+    let (directive, entry) = line.split_at(line.find(char::is_whitespace)?);
+    Some(entry.trim()).filter(|_| directive.eq_ignore_ascii_case("META"))
+}
+
+/// offset in the rom where the memory or bank `bank` ends, bank 0 being what fits in memory
+fn bank_end(bank: usize) -> usize {
+    BANKED_START + BANK_SIZE - PROGRAM_START as usize + bank * BANK_SIZE
+}
+
+fn check_bank_size(line: usize, bank: usize, out: &[u8]) -> Result<(), AsmError> {
+    if out.len() > bank_end(bank) {
+        let place = if bank == 0 { "memory".to_string() } else { format!("bank {}", bank) };
+        return Err(error(line, format!("{} is {} bytes too large", place, out.len() - bank_end(bank))));
+    }
+    Ok(())
+}
+
+/// Pads `out` to where bank `n` of a `BANK n` statement starts
+fn start_bank(statement: &Statement, bank: &mut Option<usize>, out: &mut Vec<u8>) -> Result<(), AsmError> {
+    let line = statement.line;
+    let n = match statement.operands.as_slice() {
+        [Operand::Value(Expr::Number(n))] => *n as usize,
+        _ => return Err(error(line, "expected a bank number")),
+    };
+    let current = bank.unwrap_or(0);
+    if n == 0 || n >= BANKS {
+        return Err(error(line, format!("banks go from 1 to {}", BANKS - 1)));
+    }
+    if n <= current {
+        return Err(error(line, format!("bank {} comes after bank {}", n, current)));
+    }
+
+    check_bank_size(line, current, out)?;
+    out.resize(bank_end(n - 1), 0);
+    *bank = Some(n);
+    Ok(())
+}
+
+fn error(line: usize, message: impl Into<String>) -> AsmError {
+    AsmError {
+        line,
+        message: message.into(),
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_statement(line: usize, text: &str) -> Result<Statement, AsmError> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(pos) => (&text[..pos], text[pos..].trim()),
+        None => (text, ""),
+    };
+
+    let operands = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',')
+            .map(|operand| parse_operand(line, operand.trim()))
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    Ok(Statement {
+        line,
+        mnemonic: mnemonic.to_uppercase(),
+        operands,
+    })
+}
+
+fn parse_operand(line: usize, text: &str) -> Result<Operand, AsmError> {
+    let upper = text.to_uppercase();
+    let operand = match upper.as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Delay,
+        "ST" => Operand::Sound,
+        "K" => Operand::Key,
+        "F" => Operand::Font,
+        "B" => Operand::Bcd,
+        _ if upper.len() == 2 && upper.starts_with('V') => {
+            match u8::from_str_radix(&upper[1..], 16) {
+                Ok(reg) => Operand::Reg(reg),
+                Err(_) => return Err(error(line, format!("invalid register: {}", text))),
+            }
+        }
+        _ => Operand::Value(parse_value(line, text)?),
+    };
+
+    Ok(operand)
+}
+
+fn parse_value(line: usize, text: &str) -> Result<Expr, AsmError> {
+    let lower = text.to_lowercase();
+    let number = if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('#')) {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2)
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse()
+    } else if is_identifier(text) {
+        return Ok(Expr::Label(text.to_string()));
+    } else {
+        return Err(error(line, format!("invalid operand: {}", text)));
+    };
+
+    number
+        .map(Expr::Number)
+        .map_err(|_| error(line, format!("invalid number: {}", text)))
+}
+
+fn statement_size(statement: &Statement) -> u32 {
+    match statement.mnemonic.as_str() {
+        "DB" => statement.operands.len() as u32,
+        "DW" => statement.operands.len() as u32 * 2,
+        _ => 2,
+    }
+}
+
+fn resolve(
+    line: usize,
+    value: &Expr,
+    labels: &HashMap<String, u32>,
+    max: u32,
+) -> Result<u16, AsmError> {
+    let number = match value {
+        Expr::Number(number) => *number,
+        Expr::Label(label) => *labels
+            .get(label)
+            .ok_or_else(|| error(line, format!("unknown label: {}", label)))?,
+    };
+
+    if number > max {
+        return Err(error(line, format!("value {:#X} does not fit in {:#X}", number, max)));
+    }
+
+    Ok(number as u16)
+}
+
+fn encode(
+    statement: &Statement,
+    labels: &HashMap<String, u32>,
+    out: &mut Vec<u8>,
+) -> Result<(), AsmError> {
+    use Operand::*;
+
+    let line = statement.line;
+    let addr = |value: &Expr| resolve(line, value, labels, 0xFFF);
+    let byte = |value: &Expr| resolve(line, value, labels, 0xFF);
+    let nibble = |value: &Expr| resolve(line, value, labels, 0xF);
+    let xy = |x: &u8, y: &u8| u16::from(*x) << 8 | u16::from(*y) << 4;
+
+    let mnemonic = statement.mnemonic.as_str();
+    let opcode = match (mnemonic, statement.operands.as_slice()) {
+        ("DB", values) | ("DW", values) => {
+            for value in values {
+                match value {
+                    Value(value) if mnemonic == "DB" => out.push(byte(value)? as u8),
+                    Value(value) => {
+                        out.extend_from_slice(&resolve(line, value, labels, 0xFFFF)?.to_be_bytes())
+                    }
+                    _ => return Err(error(line, "data directives only accept values")),
+                }
+            }
+            return Ok(());
+        }
+
+        ("SYS", [Value(a)]) => addr(a)?,
+        // short forms of the shifts
+        ("SHR", [Reg(x)]) => 0x8006 | xy(x, &0),
+        ("SHL", [Reg(x)]) => 0x800E | xy(x, &0),
+
+        (mnemonic, operands) => {
+            let info = OPCODES
+                .iter()
+                .find(|info| info.mnemonic() == mnemonic && fits(&info.tokens(), operands));
+            let info = match info {
+                Some(info) => info,
+                None => return Err(error(line, format!("invalid instruction: {}", mnemonic))),
+            };
+
+            let mut opcode = info.pattern;
+            for (token, operand) in info.tokens().into_iter().zip(operands) {
+                opcode |= match (token, operand) {
+                    (Token::X, Reg(x)) => xy(x, &0),
+                    (Token::Y, Reg(y)) => xy(&0, y),
+                    (Token::Nibble, Value(n)) => nibble(n)?,
+                    (Token::Byte, Value(b)) => byte(b)?,
+                    (Token::Addr, Value(a)) => addr(a)?,
+                    _ => 0,
+                };
+            }
+            opcode
+        }
+    };
+
+    out.extend_from_slice(&opcode.to_be_bytes());
+    Ok(())
+}
+
+/// Whether the operands have the kinds the syntax of an instruction asks for
+fn fits(tokens: &[Token], operands: &[Operand]) -> bool {
+    use Operand::*;
+
+    tokens.len() == operands.len()
+        && tokens.iter().zip(operands).all(|(token, operand)| {
+            matches!(
+                (token, operand),
+                (Token::X, Reg(_))
+                    | (Token::Y, Reg(_))
+                    | (Token::Nibble, Value(_))
+                    | (Token::Byte, Value(_))
+                    | (Token::Addr, Value(_))
+                    | (Token::Literal("V0"), Reg(0))
+                    | (Token::Literal("I"), I)
+                    | (Token::Literal("[I]"), IndirectI)
+                    | (Token::Literal("DT"), Delay)
+                    | (Token::Literal("ST"), Sound)
+                    | (Token::Literal("K"), Key)
+                    | (Token::Literal("F"), Font)
+                    | (Token::Literal("B"), Bcd)
+            )
+        })
+}
@@ -0,0 +1,874 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::{error, AsmError};
+use crate::system::PROGRAM_START;
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// Addresses filled in once every label is known
+enum Fixup {
+    /// lower 12 bits of the instruction at `pos`
+    Addr { pos: usize, label: String },
+    /// the `v0 := ..` / `v1 := ..` pair of `:unpack`, with the high nibble of the first byte
+    Unpack { pos: usize, nibble: u8, label: String },
+}
+
+/// A condition compiled to the instructions that evaluate it and the skips taken
+/// when it does not hold and when it does
+struct Condition {
+    setup: Vec<u16>,
+    skip_if_false: u16,
+    skip_if_true: u16,
+}
+
+enum Block {
+    If {
+        /// the jump over the `begin` body, pointed at `else` or `end`
+        jump: usize,
+        has_else: bool,
+    },
+    Loop {
+        start: u16,
+        /// jumps out of the loop from `while`
+        exits: Vec<usize>,
+    },
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    /// memory from `PROGRAM_START` on
+    rom: Vec<u8>,
+    here: u16,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, u32>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup)>,
+    blocks: Vec<(usize, Block)>,
+    line: usize,
+}
+
+/// Assembles Octo source: `: label` definitions, `#` comments, the `vX := ..` style statements,
+/// `if`/`begin`/`else`/`end` and `loop`/`while`/`again` blocks, and the `:const`, `:alias`,
+/// `:macro`, `:calc`, `:byte`, `:org`, `:next`, `:unpack` and `:call` directives.
+///
+/// As in Octo, execution starts with a jump to the `main` label
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmError> {
+    let mut compiler = Compiler {
+        tokens: tokenize(src),
+        rom: Vec::new(),
+        here: PROGRAM_START,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        line: 1,
+    };
+
+    compiler.fixup(Fixup::Addr {
+        pos: 0,
+        label: "main".to_string(),
+    });
+    compiler.op(0x1000)?;
+
+    while let Some(token) = compiler.tokens.pop_front() {
+        compiler.line = token.line;
+        compiler.statement(token)?;
+    }
+
+    if let Some((line, _)) = compiler.blocks.last() {
+        return Err(error(*line, "block is never closed"));
+    }
+
+    compiler.finish()
+}
+
+fn tokenize(src: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (i, line) in src.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        for word in code.split_whitespace() {
+            tokens.push_back(Token {
+                text: word.to_string(),
+                line: i + 1,
+            });
+        }
+    }
+    tokens
+}
+
+fn number(text: &str) -> Option<u32> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value } as u32)
+}
+
+fn register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => {
+            digit.to_digit(16).map(|reg| reg as u8)
+        }
+        _ => None,
+    }
+}
+
+impl Compiler {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        error(self.line, message)
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| self.error("unexpected end of source"))?;
+        self.line = token.line;
+        Ok(token)
+    }
+
+    fn next_text(&mut self) -> Result<String, AsmError> {
+        Ok(self.next()?.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let token = self.next_text()?;
+        if token != text {
+            return Err(self.error(format!("expected `{}`, found `{}`", text, token)));
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        let pos = usize::from(self.here - PROGRAM_START);
+        if self.here > 0xFFF {
+            return Err(self.error("program does not fit in memory"));
+        }
+        if self.rom.len() <= pos {
+            self.rom.resize(pos + 1, 0);
+        }
+        self.rom[pos] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn op(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let [high, low] = opcode.to_be_bytes();
+        self.byte(high)?;
+        self.byte(low)
+    }
+
+    /// position in the rom of the next byte
+    fn pos(&self) -> usize {
+        usize::from(self.here - PROGRAM_START)
+    }
+
+    fn fixup(&mut self, fixup: Fixup) {
+        self.fixups.push((self.line, fixup));
+    }
+
+    fn patch_addr(&mut self, pos: usize, addr: u16) {
+        self.rom[pos] = (self.rom[pos] & 0xF0) | (addr >> 8) as u8 & 0xF;
+        self.rom[pos + 1] = addr as u8;
+    }
+
+    fn label(&mut self, name: String, addr: u16) -> Result<(), AsmError> {
+        if self.labels.insert(name.clone(), addr).is_some() {
+            return Err(self.error(format!("duplicate label: {}", name)));
+        }
+        Ok(())
+    }
+
+    fn reg(&mut self) -> Result<u8, AsmError> {
+        let text = self.next_text()?;
+        self.reg_of(&text)
+    }
+
+    fn reg_of(&self, text: &str) -> Result<u8, AsmError> {
+        register(text)
+            .or_else(|| self.aliases.get(text).copied())
+            .ok_or_else(|| self.error(format!("expected a register, found `{}`", text)))
+    }
+
+    /// a number, constant or already defined label
+    fn value_of(&self, text: &str) -> Option<u32> {
+        number(text)
+            .or_else(|| self.constants.get(text).copied())
+            .or_else(|| self.labels.get(text).map(|addr| u32::from(*addr)))
+            .or_else(|| (text == "HERE").then_some(u32::from(self.here)))
+    }
+
+    fn value(&mut self, max: u32) -> Result<u16, AsmError> {
+        let text = self.next_text()?;
+        let value = match self.value_of(&text) {
+            Some(value) => value,
+            None => return Err(self.error(format!("unknown value: {}", text))),
+        };
+        self.fit(value, max)
+    }
+
+    /// Numbers may be negative, which wraps them around to the top of the range
+    fn fit(&self, value: u32, max: u32) -> Result<u16, AsmError> {
+        let signed = value as i32;
+        if signed > max as i32 || signed < -(max as i32 + 1) {
+            return Err(self.error(format!("value {} does not fit in {:#X}", signed, max)));
+        }
+        Ok((value & max) as u16)
+    }
+
+    /// An address operand: a number, a constant or a label, which may be defined later
+    fn addr_op(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let text = self.next_text()?;
+        match number(&text).or_else(|| self.constants.get(&text).copied()) {
+            Some(value) => {
+                let addr = self.fit(value, 0xFFF)?;
+                self.op(opcode | addr)
+            }
+            None => {
+                self.fixup(Fixup::Addr {
+                    pos: self.pos(),
+                    label: text,
+                });
+                self.op(opcode)
+            }
+        }
+    }
+
+    /// tokens up to the matching `}`, after the opening `{` was consumed
+    fn braced(&mut self) -> Result<Vec<Token>, AsmError> {
+        let mut depth = 0;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => return Ok(body),
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        let text = token.text.as_str();
+
+        if let Some(reg) = register(text).or_else(|| self.aliases.get(text).copied()) {
+            return self.register_statement(reg);
+        }
+
+        match text {
+            ":" => {
+                let name = self.next_text()?;
+                self.label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.next_text()?;
+                self.label(name, self.here + 1)?;
+            }
+            ":const" => {
+                let name = self.next_text()?;
+                let value = self.value(0xFFFF)?;
+                self.constants.insert(name, u32::from(value));
+            }
+            ":alias" => {
+                let name = self.next_text()?;
+                let reg = self.reg()?;
+                self.aliases.insert(name, reg);
+            }
+            ":calc" => {
+                let name = self.next_text()?;
+                self.expect("{")?;
+                let expr = self.braced()?;
+                let value = self.calc(&expr)?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    self.next()?;
+                    let expr = self.braced()?;
+                    self.calc(&expr)?
+                } else {
+                    u32::from(self.value(0xFF)?)
+                };
+                self.byte(value as u8)?;
+            }
+            ":org" => {
+                let addr = self.value(0xFFF)?;
+                if addr < PROGRAM_START {
+                    return Err(self.error(format!(":org {:#X} is below the program start", addr)));
+                }
+                self.here = addr;
+            }
+            ":call" => self.addr_op(0x2000)?,
+            ":unpack" => {
+                let nibble = self.value(0xF)? as u8;
+                let label = self.next_text()?;
+                match self.labels.get(&label).copied() {
+                    Some(addr) => self.unpack(nibble, addr)?,
+                    None => {
+                        self.fixup(Fixup::Unpack {
+                            pos: self.pos(),
+                            nibble,
+                            label,
+                        });
+                        self.unpack(nibble, 0)?;
+                    }
+                }
+            }
+            ":macro" => {
+                let name = self.next_text()?;
+                let mut params = Vec::new();
+                loop {
+                    match self.next_text()? {
+                        brace if brace == "{" => break,
+                        param => params.push(param),
+                    }
+                }
+                let body = self.braced()?;
+                self.macros.insert(name, Macro { params, body });
+            }
+            ":breakpoint" => {
+                self.next()?;
+            }
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            }
+
+            "clear" => self.op(0x00E0)?,
+            "return" | ";" => self.op(0x00EE)?,
+            "jump" => self.addr_op(0x1000)?,
+            "jump0" => self.addr_op(0xB000)?,
+            "bcd" => self.x_op(0xF033)?,
+            "save" => self.x_op(0xF055)?,
+            "load" => self.x_op(0xF065)?,
+            "sprite" => {
+                let x = self.reg()?;
+                let y = self.reg()?;
+                let n = self.value(0xF)?;
+                self.op(0xD000 | u16::from(x) << 8 | u16::from(y) << 4 | n)?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let opcode = if text == "delay" { 0xF015 } else { 0xF018 };
+                self.x_op(opcode)?;
+            }
+            "i" => match self.next_text()?.as_str() {
+                ":=" => match self.peek() {
+                    Some("hex") => {
+                        self.next()?;
+                        self.x_op(0xF029)?;
+                    }
+                    Some("bighex") => {
+                        self.next()?;
+                        self.x_op(0xF030)?;
+                    }
+                    _ => self.addr_op(0xA000)?,
+                },
+                "+=" => self.x_op(0xF01E)?,
+                op => return Err(self.error(format!("invalid operator for i: {}", op))),
+            },
+
+            "if" => {
+                let condition = self.condition()?;
+                for opcode in &condition.setup {
+                    self.op(*opcode)?;
+                }
+                match self.next_text()?.as_str() {
+                    "then" => self.op(condition.skip_if_false)?,
+                    "begin" => {
+                        self.op(condition.skip_if_true)?;
+                        self.blocks.push((
+                            self.line,
+                            Block::If {
+                                jump: self.pos(),
+                                has_else: false,
+                            },
+                        ));
+                        self.op(0x1000)?;
+                    }
+                    other => return Err(self.error(format!("expected `then` or `begin`, found `{}`", other))),
+                }
+            }
+            "else" => {
+                let end_jump = self.pos();
+                let else_jump = match self.blocks.last_mut() {
+                    Some((_, Block::If { jump, has_else })) if !*has_else => {
+                        *has_else = true;
+                        std::mem::replace(jump, end_jump)
+                    }
+                    _ => return Err(self.error("`else` outside of an `if .. begin` block")),
+                };
+                self.op(0x1000)?;
+                self.patch_addr(else_jump, self.here);
+            }
+            "end" => match self.blocks.pop() {
+                Some((_, Block::If { jump, .. })) => self.patch_addr(jump, self.here),
+                _ => return Err(self.error("`end` outside of an `if .. begin` block")),
+            },
+            "loop" => self.blocks.push((
+                self.line,
+                Block::Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                },
+            )),
+            "while" => {
+                let condition = self.condition()?;
+                for opcode in &condition.setup {
+                    self.op(*opcode)?;
+                }
+                self.op(condition.skip_if_true)?;
+                let exit = self.pos();
+                match self.blocks.iter_mut().rev().find(|(_, block)| matches!(block, Block::Loop { .. })) {
+                    Some((_, Block::Loop { exits, .. })) => exits.push(exit),
+                    _ => return Err(self.error("`while` outside of a loop")),
+                }
+                self.op(0x1000)?;
+            }
+            "again" => match self.blocks.pop() {
+                Some((_, Block::Loop { start, exits })) => {
+                    self.op(0x1000 | start)?;
+                    for exit in exits {
+                        self.patch_addr(exit, self.here);
+                    }
+                }
+                _ => return Err(self.error("`again` outside of a loop")),
+            },
+
+            "hires" => self.op(0x00FF)?,
+            "lores" => self.op(0x00FE)?,
+            "exit" => self.op(0x00FD)?,
+            "scroll-down" => {
+                let n = self.value(0xF)?;
+                self.op(0x00C0 | n)?;
+            }
+            "scroll-left" => self.op(0x00FC)?,
+            "scroll-right" => self.op(0x00FB)?,
+
+            _ => {
+                if let Some(mac) = self.macros.get(text) {
+                    let params = mac.params.clone();
+                    let body = mac.body.clone();
+                    let mut args = HashMap::new();
+                    for param in params {
+                        args.insert(param, self.next_text()?);
+                    }
+                    let line = self.line;
+                    for token in body.into_iter().rev() {
+                        let text = args.get(&token.text).cloned().unwrap_or(token.text);
+                        self.tokens.push_front(Token { text, line });
+                    }
+                } else if let Some(value) = number(text).or_else(|| self.constants.get(text).copied()) {
+                    let byte = self.fit(value, 0xFF)?;
+                    self.byte(byte as u8)?;
+                } else if text.starts_with(':') {
+                    return Err(self.error(format!("unsupported directive: {}", text)));
+                } else {
+                    // a bare label name calls it
+                    self.fixup(Fixup::Addr {
+                        pos: self.pos(),
+                        label: text.to_string(),
+                    });
+                    self.op(0x2000)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `opcode` with the next register in the X position
+    fn x_op(&mut self, opcode: u16) -> Result<(), AsmError> {
+        let x = self.reg()?;
+        self.op(opcode | u16::from(x) << 8)
+    }
+
+    fn unpack(&mut self, nibble: u8, addr: u16) -> Result<(), AsmError> {
+        self.op(0x6000 | u16::from(nibble) << 4 | (addr >> 8) & 0xF)?;
+        self.op(0x6100 | addr & 0xFF)
+    }
+
+    /// statements starting with a register: `vX <op> <operand>`
+    fn register_statement(&mut self, x: u8) -> Result<(), AsmError> {
+        let x16 = u16::from(x) << 8;
+        let op = self.next_text()?;
+        let operand = self.next_text()?;
+        let other = register(&operand).or_else(|| self.aliases.get(&operand).copied());
+
+        let opcode = match (op.as_str(), other) {
+            (":=", Some(y)) => 0x8000 | x16 | u16::from(y) << 4,
+            ("|=", Some(y)) => 0x8001 | x16 | u16::from(y) << 4,
+            ("&=", Some(y)) => 0x8002 | x16 | u16::from(y) << 4,
+            ("^=", Some(y)) => 0x8003 | x16 | u16::from(y) << 4,
+            ("+=", Some(y)) => 0x8004 | x16 | u16::from(y) << 4,
+            ("-=", Some(y)) => 0x8005 | x16 | u16::from(y) << 4,
+            (">>=", Some(y)) => 0x8006 | x16 | u16::from(y) << 4,
+            ("=-", Some(y)) => 0x8007 | x16 | u16::from(y) << 4,
+            ("<<=", Some(y)) => 0x800E | x16 | u16::from(y) << 4,
+
+            (":=", None) => match operand.as_str() {
+                "delay" => 0xF007 | x16,
+                "key" => 0xF00A | x16,
+                "random" => 0xC000 | x16 | self.value(0xFF)?,
+                _ => 0x6000 | x16 | self.operand_value(&operand)?,
+            },
+            ("+=", None) => 0x7000 | x16 | self.operand_value(&operand)?,
+            ("-=", None) => {
+                let value = self.operand_value(&operand)?;
+                0x7000 | x16 | (value as u8).wrapping_neg() as u16
+            }
+
+            _ => return Err(self.error(format!("invalid statement: v{:X} {} {}", x, op, operand))),
+        };
+
+        self.op(opcode)
+    }
+
+    fn operand_value(&self, text: &str) -> Result<u16, AsmError> {
+        match self.value_of(text) {
+            Some(value) => self.fit(value, 0xFF),
+            None => Err(self.error(format!("unknown value: {}", text))),
+        }
+    }
+
+    /// `vX == N`, `vX != vY`, `vX key`, `vX -key`, and the `<`, `>`, `<=` and `>=` comparisons,
+    /// which are computed in vF like Octo does
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.reg()?;
+        let x16 = u16::from(x) << 8;
+        let op = self.next_text()?;
+
+        if op == "key" || op == "-key" {
+            let (pressed, released) = (0xE09E | x16, 0xE0A1 | x16);
+            return Ok(if op == "key" {
+                Condition {
+                    setup: Vec::new(),
+                    skip_if_false: released,
+                    skip_if_true: pressed,
+                }
+            } else {
+                Condition {
+                    setup: Vec::new(),
+                    skip_if_false: pressed,
+                    skip_if_true: released,
+                }
+            });
+        }
+
+        let operand = self.next_text()?;
+        let y = register(&operand).or_else(|| self.aliases.get(&operand).copied());
+        let (eq, neq) = match y {
+            Some(y) => (0x5000 | x16 | u16::from(y) << 4, 0x9000 | x16 | u16::from(y) << 4),
+            None => {
+                let value = self.operand_value(&operand)?;
+                (0x3000 | x16 | value, 0x4000 | x16 | value)
+            }
+        };
+
+        let (setup, holds_when_vf) = match op.as_str() {
+            "==" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    skip_if_false: neq,
+                    skip_if_true: eq,
+                })
+            }
+            "!=" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    skip_if_false: eq,
+                    skip_if_true: neq,
+                })
+            }
+            ">=" => (self.greater_or_equal(x, y, &operand, false)?, 1),
+            "<" => (self.greater_or_equal(x, y, &operand, false)?, 0),
+            "<=" => (self.greater_or_equal(x, y, &operand, true)?, 1),
+            ">" => (self.greater_or_equal(x, y, &operand, true)?, 0),
+            _ => return Err(self.error(format!("invalid comparison: {}", op))),
+        };
+
+        Ok(Condition {
+            setup,
+            skip_if_false: 0x4F00 | holds_when_vf,
+            skip_if_true: 0x3F00 | holds_when_vf,
+        })
+    }
+
+    /// Instructions leaving vF = 1 if `vX >= operand` (or `operand >= vX` when `swapped`), else 0
+    fn greater_or_equal(&self, x: u8, y: Option<u8>, operand: &str, swapped: bool) -> Result<Vec<u16>, AsmError> {
+        let x16 = u16::from(x) << 4;
+        Ok(match (y, swapped) {
+            // vF := vX, vF -= vY
+            (Some(y), false) => vec![0x8F00 | x16, 0x8F05 | u16::from(y) << 4],
+            (Some(y), true) => vec![0x8F00 | u16::from(y) << 4, 0x8F05 | x16],
+            // vF := N, vF =- vX
+            (None, false) => vec![0x6F00 | self.operand_value(operand)?, 0x8F07 | x16],
+            // vF := N, vF -= vX
+            (None, true) => vec![0x6F00 | self.operand_value(operand)?, 0x8F05 | x16],
+        })
+    }
+
+    /// Evaluates a `:calc` expression. As in Octo, operators have no precedence and are applied
+    /// right to left: `2 * 3 + 1` is 8
+    fn calc(&self, tokens: &[Token]) -> Result<u32, AsmError> {
+        let (value, rest) = self.calc_expr(tokens)?;
+        if let Some(token) = rest.first() {
+            return Err(error(token.line, format!("unexpected `{}` in expression", token.text)));
+        }
+        Ok(value)
+    }
+
+    fn calc_expr<'a>(&self, tokens: &'a [Token]) -> Result<(u32, &'a [Token]), AsmError> {
+        let (left, rest) = self.calc_term(tokens)?;
+        let op = match rest.first() {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok((left, rest)),
+        };
+
+        let (right, rest) = self.calc_expr(&rest[1..])?;
+        let (left, right) = (left as i64, right as i64);
+        let value = match op.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" if right != 0 => left / right,
+            "%" if right != 0 => left % right,
+            "/" | "%" => return Err(error(op.line, "division by zero")),
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left << (right & 31),
+            ">>" => left >> (right & 31),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            other => return Err(error(op.line, format!("unknown operator: {}", other))),
+        };
+        Ok((value as u32, rest))
+    }
+
+    fn calc_term<'a>(&self, tokens: &'a [Token]) -> Result<(u32, &'a [Token]), AsmError> {
+        let (token, rest) = tokens
+            .split_first()
+            .ok_or_else(|| self.error("expression ends early"))?;
+
+        match token.text.as_str() {
+            "(" => {
+                let (value, rest) = self.calc_expr(rest)?;
+                match rest.split_first() {
+                    Some((close, rest)) if close.text == ")" => Ok((value, rest)),
+                    _ => Err(error(token.line, "unclosed `(`")),
+                }
+            }
+            "-" => {
+                let (value, rest) = self.calc_term(rest)?;
+                Ok(((value as i64).wrapping_neg() as u32, rest))
+            }
+            "~" => {
+                let (value, rest) = self.calc_term(rest)?;
+                Ok((!value, rest))
+            }
+            text => match self.value_of(text) {
+                Some(value) => Ok((value, rest)),
+                None => Err(error(token.line, format!("unknown value in expression: {}", text))),
+            },
+        }
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        for (line, fixup) in std::mem::take(&mut self.fixups) {
+            let (pos, label) = match &fixup {
+                Fixup::Addr { pos, label } | Fixup::Unpack { pos, label, .. } => (*pos, label),
+            };
+            let addr = *self
+                .labels
+                .get(label)
+                .ok_or_else(|| error(line, format!("unknown label: {}", label)))?;
+
+            match fixup {
+                Fixup::Addr { .. } => self.patch_addr(pos, addr),
+                Fixup::Unpack { nibble, .. } => {
+                    self.rom[pos + 1] = nibble << 4 | (addr >> 8) as u8 & 0xF;
+                    self.rom[pos + 3] = addr as u8;
+                }
+            }
+        }
+
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the rom after the jump to `main`, which has to come first
+    fn body(src: &str) -> Vec<u8> {
+        let rom = assemble(src).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(rom[..2], [0x12, 0x02]);
+        rom[2..].to_vec()
+    }
+
+    fn error_of(src: &str) -> (usize, String) {
+        let error = assemble(src).unwrap_err();
+        (error.line, error.message)
+    }
+
+    #[test]
+    fn assembles_directives() {
+        let src = "
+            : main
+              v0 := 5
+              :const SPEED 3
+              v1 += SPEED
+              v1 -= 1
+              :alias score v2
+              score := v0
+              :calc BIG { 2 * 3 + 1 }
+              v3 := BIG
+              :unpack 0xA data
+              :call sub
+              i := target
+            : sub
+              :next target v4 := 7
+              return
+            : data
+              :byte { BIG + 1 }
+              :byte 0xAB
+        ";
+        let expected = [
+            0x60, 0x05,
+            0x71, 0x03,
+            0x71, 0xFF,
+            0x82, 0x00,
+            // operators apply right to left, 2 * (3 + 1)
+            0x63, 0x08,
+            // patched once `data` is known
+            0x60, 0xA2, 0x61, 0x18,
+            0x22, 0x14,
+            // the byte after the start of `v4 := 7`
+            0xA2, 0x15,
+            0x64, 0x07,
+            0x00, 0xEE,
+            0x09, 0xAB,
+        ];
+        assert_eq!(body(src), expected);
+    }
+
+    #[test]
+    fn expands_macros_and_places_code() {
+        let src = "
+            :macro twice reg n { reg += n reg += n }
+            : main
+              twice v5 2
+              :calc BACKWARDS { 10 - 2 - 3 }
+              v6 := BACKWARDS
+              :unpack 1 main
+              :org 0x20E
+              :byte 1
+        ";
+        // 10 - (2 - 3), and zeros up to the `:org`
+        let expected = [0x75, 0x02, 0x75, 0x02, 0x66, 0x0B, 0x60, 0x12, 0x61, 0x02, 0x00, 0x00, 0x01];
+        assert_eq!(body(src), expected);
+    }
+
+    #[test]
+    fn compiles_conditions() {
+        let src = "
+            : main
+              if v1 == 3 then v0 := 1
+              if v1 != v2 then v0 := 2
+              if v1 key then v0 := 3
+              if v1 -key then v0 := 4
+              if v1 >= v2 then v0 := 5
+              if v1 < 7 then v0 := 6
+              if v1 <= v2 then v0 := 7
+              if v1 > 7 then v0 := 8
+        ";
+        let expected = [
+            0x41, 0x03, 0x60, 0x01,
+            0x51, 0x20, 0x60, 0x02,
+            0xE1, 0xA1, 0x60, 0x03,
+            0xE1, 0x9E, 0x60, 0x04,
+            // vF := v1, vF -= v2, no borrow is 1
+            0x8F, 0x10, 0x8F, 0x25, 0x4F, 0x01, 0x60, 0x05,
+            // vF := 7, vF =- v1
+            0x6F, 0x07, 0x8F, 0x17, 0x4F, 0x00, 0x60, 0x06,
+            // the operands swapped
+            0x8F, 0x20, 0x8F, 0x15, 0x4F, 0x01, 0x60, 0x07,
+            0x6F, 0x07, 0x8F, 0x15, 0x4F, 0x00, 0x60, 0x08,
+        ];
+        assert_eq!(body(src), expected);
+    }
+
+    #[test]
+    fn compiles_blocks() {
+        let src = "
+            : main
+              if v0 == 1 begin
+                v1 := 1
+              else
+                v1 := 2
+              end
+              loop
+                v2 += 1
+                while v2 != 5
+                v3 += 1
+              again
+        ";
+        let expected = [
+            // the jump over the `begin` body goes to `else`, the one at `else` to `end`
+            0x30, 0x01, 0x12, 0x0A,
+            0x61, 0x01,
+            0x12, 0x0C,
+            0x61, 0x02,
+            0x72, 0x01,
+            // `while` leaves the loop past `again`
+            0x42, 0x05, 0x12, 0x16,
+            0x73, 0x01,
+            0x12, 0x0C,
+        ];
+        assert_eq!(body(src), expected);
+    }
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        assert_eq!(error_of("v0 := 1"), (1, "unknown label: main".to_string()));
+        assert_eq!(error_of(": main\n\njump nowhere"), (3, "unknown label: nowhere".to_string()));
+        assert_eq!(error_of(": main\nloop\n  v0 += 1"), (2, "block is never closed".to_string()));
+        assert_eq!(error_of(": main\nif v0 == 1 begin\nv0 := 2"), (2, "block is never closed".to_string()));
+        assert_eq!(error_of(": main\nelse"), (2, "`else` outside of an `if .. begin` block".to_string()));
+        assert_eq!(error_of(": main\nloop\nelse"), (3, "`else` outside of an `if .. begin` block".to_string()));
+        assert_eq!(error_of(": main\nagain"), (2, "`again` outside of a loop".to_string()));
+        assert_eq!(error_of(": main\nif v0 == 1 begin\nagain"), (3, "`again` outside of a loop".to_string()));
+    }
+}
@@ -0,0 +1,418 @@
+#[cfg(feature = "audio")]
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(feature = "audio")]
+use std::path::Path;
+#[cfg(feature = "audio")]
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+#[cfg(feature = "audio")]
+use std::sync::Arc;
+
+#[cfg(feature = "audio")]
+pub mod wav;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub sample_rate: u32,
+    /// samples the device asks for at once, smaller is more responsive but may crackle
+    pub buffer_samples: u16,
+    /// how far ahead of the device the emulator generates sound
+    pub latency_ms: u32,
+    /// buzzer pitch in Hz
+    pub frequency: f32,
+    /// 0 to 1
+    pub volume: f32,
+    /// shortest beep in emulated milliseconds, so a sound timer of 1 or 2 is still heard
+    pub min_beep_ms: u32,
+    /// WAV file looped while the sound timer runs, instead of the tone.
+    /// Relative to the directory of the config file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<PathBuf>,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            sample_rate: 44100,
+            buffer_samples: 512,
+            latency_ms: 50,
+            frequency: 440.0,
+            volume: 0.1,
+            min_beep_ms: 50,
+            sample: None,
+        }
+    }
+}
+
+impl AudioConfig {
+    /// `min_beep_ms` in timer ticks, rounded up
+    pub fn min_beep_ticks(&self) -> u32 {
+        crate::timing::frames_in(Duration::from_millis(u64::from(self.min_beep_ms)))
+    }
+}
+
+/// When the beep plays, from the sound timer at every timer tick. Counting ticks rather than
+/// instructions or real time keeps beeps as long at any cpu speed, and a timer set and run out
+/// within one iteration of the run loop still beeps
+#[derive(Default)]
+pub struct BeepSchedule {
+    /// ticks the beep plays for at least, counted from its start
+    hold: u32,
+    playing: bool,
+}
+
+impl BeepSchedule {
+    /// Called before the timers tick with the sound timer, returns whether the beep plays
+    /// during the tick. A timer of `n` beeps for `n` ticks, `min_ticks` at least
+    pub fn tick(&mut self, sound: u8, min_ticks: u32) -> bool {
+        if sound > 0 && !self.playing {
+            self.hold = min_ticks;
+        }
+        self.playing = sound > 0 || self.hold > 0;
+        self.hold = self.hold.saturating_sub(1);
+        self.playing
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+}
+
+/// Where the emulator sends its sound, so the core can beep without knowing the host API
+pub trait AudioSink {
+    /// rate of the samples `queue_samples` takes
+    fn sample_rate(&self) -> u32;
+
+    /// Plays the tone or the beep sample until `stop_beep`. Does nothing if already beeping
+    fn start_beep(&mut self);
+
+    fn stop_beep(&mut self);
+
+    /// Queues mono samples from -1 to 1 to play after those already queued
+    fn queue_samples(&mut self, samples: &[f32]);
+
+    /// Sound played for the beep instead of the tone, None for the tone
+    #[cfg(feature = "audio")]
+    fn set_beep(&mut self, _sample: Option<&Sample>) {}
+
+    /// Called every iteration of the run loop, sinks feeding a device top up its queue here
+    fn update(&mut self) {}
+
+    /// Called every time the timers tick, `timing::TIMER_HZ` times per emulated second.
+    /// Sinks that do not play in real time keep time with this
+    fn timer_tick(&mut self) {}
+
+    /// Starts or stops the beep to match the sound timer
+    fn set_beeping(&mut self, playing: bool) {
+        if playing {
+            self.start_beep();
+        } else {
+            self.stop_beep();
+        }
+    }
+}
+
+/// Discards all sound, for running without a window
+#[derive(Default)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        AudioConfig::default().sample_rate
+    }
+
+    fn start_beep(&mut self) {}
+
+    fn stop_beep(&mut self) {}
+
+    fn queue_samples(&mut self, _samples: &[f32]) {}
+}
+
+#[cfg(feature = "audio")]
+impl AudioConfig {
+    fn latency_samples(&self) -> usize {
+        (self.sample_rate as usize * self.latency_ms as usize / 1000).max(1)
+    }
+}
+
+#[cfg(feature = "audio")]
+/// Fixed size single producer, single consumer queue of samples.
+/// Samples are stored as bits in atomics, so neither side ever blocks
+struct Ring {
+    samples: Box<[AtomicU32]>,
+    /// total samples read and written, the positions are these modulo the capacity
+    read: AtomicUsize,
+    write: AtomicUsize,
+}
+
+#[cfg(feature = "audio")]
+pub struct Producer {
+    ring: Arc<Ring>,
+}
+
+#[cfg(feature = "audio")]
+pub struct Consumer {
+    ring: Arc<Ring>,
+}
+
+#[cfg(feature = "audio")]
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let ring = Arc::new(Ring {
+        samples: (0..capacity.max(1)).map(|_| AtomicU32::new(0)).collect(),
+        read: AtomicUsize::new(0),
+        write: AtomicUsize::new(0),
+    });
+
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+#[cfg(feature = "audio")]
+impl Producer {
+    /// samples waiting to be played
+    pub fn len(&self) -> usize {
+        let write = self.ring.write.load(Ordering::Relaxed);
+        write.wrapping_sub(self.ring.read.load(Ordering::Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Queues a sample, returns false if the buffer is full
+    pub fn push(&mut self, sample: f32) -> bool {
+        let capacity = self.ring.samples.len();
+        let write = self.ring.write.load(Ordering::Relaxed);
+        if write.wrapping_sub(self.ring.read.load(Ordering::Acquire)) >= capacity {
+            return false;
+        }
+
+        self.ring.samples[write % capacity].store(sample.to_bits(), Ordering::Relaxed);
+        self.ring.write.store(write.wrapping_add(1), Ordering::Release);
+        true
+    }
+}
+
+#[cfg(feature = "audio")]
+impl Consumer {
+    pub fn pop(&mut self) -> Option<f32> {
+        let capacity = self.ring.samples.len();
+        let read = self.ring.read.load(Ordering::Relaxed);
+        if read == self.ring.write.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let sample = f32::from_bits(self.ring.samples[read % capacity].load(Ordering::Relaxed));
+        self.ring.read.store(read.wrapping_add(1), Ordering::Release);
+        Some(sample)
+    }
+
+    /// Fills `out` with queued samples, silence where there are none
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for sample in out {
+            *sample = self.pop().unwrap_or(0.0);
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+/// Mono sound loaded from a WAV file
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub rate: u32,
+    /// -1 to 1
+    pub samples: Vec<f32>,
+}
+
+#[cfg(feature = "audio")]
+impl Sample {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let data = std::fs::read(path)?;
+        Self::parse_wav(&data).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    /// Reads an uncompressed WAV: 8, 16, 24 or 32 bit integer or 32 bit float samples.
+    /// Channels are mixed down to mono
+    pub fn parse_wav(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            bail!("not a WAV file");
+        }
+
+        let u16_at = |bytes: &[u8], at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let u32_at = |bytes: &[u8], at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+
+        let mut format = None;
+        let mut samples = None;
+        let mut pos = 12;
+        while pos + 8 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let len = u32_at(data, pos + 4) as usize;
+            let chunk = &data[pos + 8..(pos + 8 + len).min(data.len())];
+            match id {
+                b"fmt " if chunk.len() >= 16 => {
+                    let mut tag = u16_at(chunk, 0);
+                    // WAVE_FORMAT_EXTENSIBLE keeps the real format in the sub format
+                    if tag == 0xFFFE && chunk.len() >= 26 {
+                        tag = u16_at(chunk, 24);
+                    }
+                    let channels = u16_at(chunk, 2).max(1);
+                    let rate = u32_at(chunk, 4);
+                    let bits = u16_at(chunk, 14);
+                    format = Some((tag, channels, rate, bits));
+                }
+                b"data" => samples = Some(chunk),
+                _ => {}
+            }
+            // chunks are padded to an even length
+            pos += 8 + len + len % 2;
+        }
+
+        let (tag, channels, rate, bits) = format.ok_or_else(|| format_err!("no fmt chunk"))?;
+        let data = samples.ok_or_else(|| format_err!("no data chunk"))?;
+        let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+            (1, 8) => |b| (f32::from(b[0]) - 128.0) / 128.0,
+            (1, 16) => |b| f32::from(i16::from_le_bytes([b[0], b[1]])) / 32768.0,
+            (1, 24) => |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0,
+            (1, 32) => |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0,
+            (3, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            _ => bail!("unsupported format {} with {} bits per sample", tag, bits),
+        };
+
+        let width = usize::from(bits / 8);
+        let frame = width * usize::from(channels);
+        let samples = data
+            .chunks_exact(frame)
+            .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / f32::from(channels))
+            .collect();
+
+        Ok(Sample { rate, samples })
+    }
+
+    /// the samples at another rate, linearly interpolated
+    pub fn resample(&self, rate: u32) -> Vec<f32> {
+        if self.rate == rate || self.samples.is_empty() {
+            return self.samples.clone();
+        }
+
+        let step = f64::from(self.rate) / f64::from(rate.max(1));
+        let len = (self.samples.len() as f64 / step) as usize;
+        (0..len)
+            .map(|i| {
+                let pos = i as f64 * step;
+                let index = pos as usize;
+                let next = self.samples.get(index + 1).copied().unwrap_or(self.samples[index]);
+                let frac = (pos - index as f64) as f32;
+                self.samples[index] * (1.0 - frac) + next * frac
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "audio")]
+/// Square wave or looped sample generator for the beep
+pub struct Tone {
+    phase: f32,
+    phase_inc: f32,
+    volume: f32,
+    sample_rate: u32,
+    /// played in a loop instead of the tone, at `sample_rate`
+    sample: Option<Vec<f32>>,
+    position: usize,
+}
+
+#[cfg(feature = "audio")]
+impl Tone {
+    pub fn new(config: &AudioConfig, sample_rate: u32) -> Self {
+        Tone {
+            phase: 0.0,
+            phase_inc: config.frequency / sample_rate.max(1) as f32,
+            volume: config.volume.clamp(0.0, 1.0),
+            sample_rate,
+            sample: None,
+            position: 0,
+        }
+    }
+
+    /// Plays `sample` instead of the tone, or the tone again if None
+    pub fn set_sample(&mut self, sample: Option<&Sample>) {
+        self.sample = sample
+            .map(|sample| sample.resample(self.sample_rate))
+            .filter(|samples| !samples.is_empty());
+        self.position = 0;
+    }
+
+    /// The next sample of the tone or the sample if `playing`, silence otherwise.
+    /// The sample starts from the beginning with every beep
+    pub fn next(&mut self, playing: bool) -> f32 {
+        let sample = match &self.sample {
+            _ if !playing => {
+                self.position = 0;
+                0.0
+            }
+            Some(samples) => {
+                let sample = samples[self.position % samples.len()];
+                self.position = (self.position + 1) % samples.len();
+                sample * self.volume
+            }
+            None if self.phase < 0.5 => self.volume,
+            None => -self.volume,
+        };
+        self.phase = (self.phase + self.phase_inc) % 1.0;
+        sample
+    }
+}
+
+#[cfg(feature = "audio")]
+/// Tone generator run on the emulation thread, keeping the queue of the audio device
+/// filled `latency_ms` ahead
+pub struct Buzzer {
+    producer: Producer,
+    tone: Tone,
+    target: usize,
+    sample_rate: u32,
+}
+
+#[cfg(feature = "audio")]
+impl Buzzer {
+    /// the buzzer and the consumer end to hand to the audio device
+    pub fn new(config: &AudioConfig, sample_rate: u32) -> (Self, Consumer) {
+        let target = config.latency_samples();
+        let (producer, consumer) = ring_buffer(target * 2);
+        let buzzer = Buzzer {
+            producer,
+            tone: Tone::new(config, sample_rate),
+            target,
+            sample_rate,
+        };
+
+        (buzzer, consumer)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample(&mut self, sample: Option<&Sample>) {
+        self.tone.set_sample(sample);
+    }
+
+    /// Tops up the queue with the tone if `playing`, silence otherwise
+    pub fn fill(&mut self, playing: bool) {
+        while self.producer.len() < self.target {
+            if !self.producer.push(self.tone.next(playing)) {
+                break;
+            }
+        }
+    }
+
+    /// Queues generated samples, dropping those that do not fit
+    pub fn queue(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            if !self.producer.push(sample) {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use failure::Error;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{AudioConfig, AudioSink, Sample, Tone};
+use crate::timing;
+
+/// bytes before the samples: the RIFF header and the fmt, fact and data chunk headers
+const HEADER_LEN: u32 = 12 + 8 + 16 + 12 + 8;
+
+/// Writes the sound to a 32 bit float WAV file exactly as generated, in emulated time
+/// rather than real time, so a recording does not depend on how fast the emulator ran
+pub struct WavSink<W: Write + Seek> {
+    out: W,
+    rate: u32,
+    tone: Tone,
+    beeping: bool,
+    /// timer ticks so far, the file holds `timing::samples_in(ticks, rate)` samples after each one
+    ticks: u64,
+    written: u64,
+    /// set after a write fails, nothing more is written
+    failed: bool,
+}
+
+impl WavSink<BufWriter<File>> {
+    pub fn create(path: &Path, config: &AudioConfig) -> Result<Self, Error> {
+        let file = File::create(path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        WavSink::new(BufWriter::new(file), config)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    /// Writes the header to `out`, the sizes in it are filled in by `finish`
+    pub fn new(mut out: W, config: &AudioConfig) -> Result<Self, Error> {
+        let rate = config.sample_rate.max(1);
+        out.write_all(&header(rate, 0))?;
+
+        Ok(WavSink {
+            out,
+            rate,
+            tone: Tone::new(config, rate),
+            beeping: false,
+            ticks: 0,
+            written: 0,
+            failed: false,
+        })
+    }
+
+    fn write(&mut self, sample: f32) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.out.write_all(&sample.to_le_bytes()) {
+            log::warn!(target: "audio", "Stopped writing the WAV file: {}", e);
+            self.failed = true;
+        }
+        self.written += 1;
+    }
+
+    /// Fills in the sizes in the header. Also done on drop, where errors are only logged
+    pub fn finish(&mut self) -> Result<(), Error> {
+        let samples = self.written.min(u64::from((u32::MAX - HEADER_LEN) / 4)) as u32;
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header(self.rate, samples))?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    fn start_beep(&mut self) {
+        self.beeping = true;
+    }
+
+    fn stop_beep(&mut self) {
+        self.beeping = false;
+    }
+
+    /// Queued samples take the place of the tone until the time they cover has passed
+    fn queue_samples(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            self.write(sample);
+        }
+    }
+
+    fn set_beep(&mut self, sample: Option<&Sample>) {
+        self.tone.set_sample(sample);
+    }
+
+    fn timer_tick(&mut self) {
+        self.ticks += 1;
+        let due = timing::samples_in(self.ticks, self.rate);
+        while self.written < due {
+            let sample = self.tone.next(self.beeping);
+            self.write(sample);
+        }
+    }
+}
+
+impl<W: Write + Seek> Drop for WavSink<W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            log::warn!(target: "audio", "Could not finish the WAV file: {}", e);
+        }
+    }
+}
+
+/// RIFF header of a mono 32 bit float WAV with `samples` samples
+fn header(rate: u32, samples: u32) -> Vec<u8> {
+    let data_len = samples * 4;
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(HEADER_LEN - 8 + data_len).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // IEEE float, one channel
+    header.extend_from_slice(&3u16.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&rate.to_le_bytes());
+    header.extend_from_slice(&(rate * 4).to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    header.extend_from_slice(&32u16.to_le_bytes());
+
+    // required for formats other than integer PCM
+    header.extend_from_slice(b"fact");
+    header.extend_from_slice(&4u32.to_le_bytes());
+    header.extend_from_slice(&samples.to_le_bytes());
+
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    header
+}
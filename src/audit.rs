@@ -0,0 +1,103 @@
+use failure::Error;
+use std::path::Path;
+
+use crate::headless::Headless;
+use crate::system::diff::StateDiff;
+use crate::system::System;
+
+/// Runs the rom headlessly and returns the hash of the complete system state after every frame
+pub fn trace(rom: &[u8], seed: u64, frames: u64) -> Result<Vec<u64>, Error> {
+    let mut system = System::default();
+    system.seed(seed);
+    system.load(rom)?;
+
+    let mut headless = Headless::new(system);
+    let mut hashes = Vec::with_capacity(frames as usize);
+    for _ in 0..frames {
+        headless.run_frame()?;
+        hashes.push(headless.system.state_hash());
+    }
+
+    Ok(hashes)
+}
+
+/// The system after running the rom headlessly for `frames` frames
+fn state_after(rom: &[u8], seed: u64, frames: u64) -> Result<System, Error> {
+    let mut system = System::default();
+    system.seed(seed);
+    system.load(rom)?;
+
+    let mut headless = Headless::new(system);
+    for _ in 0..frames {
+        headless.run_frame()?;
+    }
+
+    Ok(headless.system)
+}
+
+/// index of the first frame where the traces differ
+pub fn first_mismatch(a: &[u64], b: &[u64]) -> Option<usize> {
+    a.iter()
+        .zip(b)
+        .position(|(a, b)| a != b)
+        .or_else(|| if a.len() != b.len() { Some(a.len().min(b.len())) } else { None })
+}
+
+/// one `<frame> <hash>` line per frame
+pub fn write_log(path: &Path, hashes: &[u64]) -> Result<(), Error> {
+    let log = hashes
+        .iter()
+        .enumerate()
+        .map(|(frame, hash)| format!("{} {:016x}\n", frame, hash))
+        .collect::<String>();
+    std::fs::write(path, log)?;
+    Ok(())
+}
+
+pub fn read_log(path: &Path) -> Result<Vec<u64>, Error> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(|line| {
+            let hash = line
+                .split_whitespace()
+                .nth(1)
+                .ok_or_else(|| format_err!("Malformed audit log line: {}", line))?;
+            Ok(u64::from_str_radix(hash, 16)?)
+        })
+        .collect()
+}
+
+/// Runs the rom twice and compares the traces, and against the log at `verify` if given.
+/// Writes the trace to `log` if given
+pub fn run(
+    rom: &[u8],
+    seed: u64,
+    frames: u64,
+    log: Option<&Path>,
+    verify: Option<&Path>,
+) -> Result<(), Error> {
+    let first = trace(rom, seed, frames)?;
+    let second = trace(rom, seed, frames)?;
+    if let Some(frame) = first_mismatch(&first, &second) {
+        // the first frame is 0, its state is the one after one frame
+        let diff = StateDiff::between(
+            &state_after(rom, seed, frame as u64 + 1)?,
+            &state_after(rom, seed, frame as u64 + 1)?,
+        );
+        bail!("Nondeterminism detected: runs diverge at frame {}: {}", frame, diff);
+    }
+
+    if let Some(path) = log {
+        write_log(path, &first)?;
+    }
+
+    if let Some(path) = verify {
+        let expected = read_log(path)?;
+        if let Some(frame) = first_mismatch(&expected, &first) {
+            bail!("State differs from {} at frame {}", path.display(), frame);
+        }
+    }
+
+    println!("{} frames, all state hashes match", frames);
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use failure::Error;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::headless::CYCLES_PER_FRAME;
+use crate::system::{debug::Debugger, Execution, System, SystemError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    /// reached the zero instruction
+    Halted,
+    /// stopped with an error, or could not be loaded
+    Crashed,
+    /// still running after all cycles
+    Running,
+    /// stopped early: the state at the end of a frame repeated an earlier one and the keys were
+    /// not read in between, so it would have gone round the same loop until the last cycle
+    Stable,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RomResult {
+    pub rom: String,
+    pub status: Status,
+    /// what it crashed with, or the frames of the stable loop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// instructions executed
+    pub cycles: u32,
+    /// hex `rom::hash` of the final screen
+    pub frame_hash: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Runs the rom for up to `cycles` instructions, decrementing the timers once per frame
+fn run_rom(path: &Path, cycles: u32) -> RomResult {
+    let rom = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mut system = System::default();
+    let mut executed = 0;
+    // state hashes at the end of the frames since the keys were last read, and the frame of each
+    let mut seen = HashMap::new();
+
+    let (status, error) = match system.load_from_file(path) {
+        Err(e) => (Status::Crashed, Some(e.to_string())),
+        Ok(()) => {
+            let mut dbg = Debugger::disabled();
+            loop {
+                if executed == cycles {
+                    break (Status::Running, None);
+                }
+                match system.tick(&mut dbg) {
+                    Ok(()) => {}
+                    Err(SystemError::ZeroInstruction) => break (Status::Halted, None),
+                    Err(e) => break (Status::Crashed, Some(e.to_string())),
+                }
+                executed += 1;
+                if executed % CYCLES_PER_FRAME == 0 {
+                    system.dec_timers();
+
+                    let frame = executed / CYCLES_PER_FRAME;
+                    if system.polled_keys != 0 || system.execution == Execution::WaitingForKey {
+                        // a key press could still change what happens
+                        seen.clear();
+                        system.polled_keys = 0;
+                    } else if let Some(start) = seen.insert(system.state_hash(), frame) {
+                        let loop_ = format!("stable loop detected, frame {} repeats frame {}", frame, start);
+                        break (Status::Stable, Some(loop_));
+                    }
+                }
+            }
+        }
+    };
+
+    RomResult {
+        rom,
+        status,
+        error,
+        cycles: executed,
+        frame_hash: format!("{:016x}", crate::rom::hash(&system.screen())),
+    }
+}
+
+/// Runs every `.ch8` rom in `dir` on `jobs` threads, returning the results sorted by name
+pub fn run(dir: &Path, cycles: u32, jobs: usize) -> Result<Vec<RomResult>, Error> {
+    let mut roms = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    roms.retain(|path| path.extension().is_some_and(|ext| ext == "ch8"));
+    roms.sort();
+
+    // workers take the next rom from a shared counter, so slow roms don't hold up a whole share
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|scope| {
+        let workers = (0..jobs.clamp(1, roms.len().max(1)))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    while let Some(rom) = roms.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        results.push(run_rom(rom, cycles));
+                    }
+                    results
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("batch worker panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by(|a, b| a.rom.cmp(&b.rom));
+
+    Ok(results)
+}
+
+pub fn write_report(results: &[RomResult], format: Format, mut out: impl std::io::Write) -> Result<(), Error> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut out, results)?;
+            writeln!(out)?;
+        }
+        Format::Csv => {
+            writeln!(out, "rom,status,error,cycles,frame_hash")?;
+            for result in results {
+                let status = match result.status {
+                    Status::Halted => "halted",
+                    Status::Crashed => "crashed",
+                    Status::Running => "running",
+                    Status::Stable => "stable",
+                };
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    csv_field(&result.rom),
+                    status,
+                    csv_field(result.error.as_deref().unwrap_or("")),
+                    result.cycles,
+                    result.frame_hash
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Quotes fields containing separators, quotes or line breaks
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
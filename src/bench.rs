@@ -0,0 +1,145 @@
+use failure::Error;
+use std::time::{Duration, Instant};
+
+use crate::headless::CYCLES_PER_FRAME;
+use crate::system::cache::CachedCore;
+use crate::system::debug::Debugger;
+use crate::system::machine::Chip8Core;
+use crate::system::rewind::{RewindBuffer, RewindConfig};
+use crate::system::{System, SystemError};
+
+/// How long a core took to run the rom
+struct Timing {
+    executed: u64,
+    elapsed: Duration,
+}
+
+impl Timing {
+    fn describe(&self, name: &str) -> String {
+        let rate = self.executed as f64 / self.elapsed.as_secs_f64().max(1e-9) / 1e6;
+        format!(
+            "{:<12} {} instructions in {:.3} s, {:.2} M/s",
+            name,
+            self.executed,
+            self.elapsed.as_secs_f64(),
+            rate
+        )
+    }
+}
+
+/// Runs `core` for up to `cycles` instructions with no keys pressed, ticking the timers every
+/// `CYCLES_PER_FRAME` like a headless run. The zero instruction ends it early
+fn time<C: Chip8Core>(core: &mut C, cycles: u64) -> Result<Timing, Error> {
+    let mut dbg = Debugger::disabled();
+    let start = Instant::now();
+    let mut executed = 0;
+    while executed < cycles {
+        match core.step(&mut dbg) {
+            Ok(()) => executed += 1,
+            Err(SystemError::ZeroInstruction) => break,
+            Err(e) => bail!("{} after {} instructions", e, executed),
+        }
+        if executed % u64::from(CYCLES_PER_FRAME) == 0 {
+            core.tick_timers();
+        }
+    }
+
+    Ok(Timing {
+        executed,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Runs `system` for up to `cycles` instructions, recording its state in a rewind buffer
+/// every frame, then plays all of them back. Returns what the recorded frames cost in memory
+/// and time, and fails if a frame comes back different
+fn rewind(mut system: System, cycles: u64) -> Result<String, Error> {
+    let mut buffer = RewindBuffer::new(RewindConfig::default());
+    let mut dbg = Debugger::disabled();
+    let (mut frames, mut full_size) = (0, 0);
+    let mut hashes = Vec::new();
+    let mut store = Duration::default();
+    for executed in 1..=cycles {
+        match system.tick(&mut dbg) {
+            Ok(()) => {}
+            Err(SystemError::ZeroInstruction) => break,
+            Err(e) => bail!("{} after {} instructions", e, executed),
+        }
+        if executed % u64::from(CYCLES_PER_FRAME) == 0 {
+            system.dec_timers();
+            let state = system.save_state();
+            frames += 1;
+            full_size = state.len();
+            hashes.push(crc32fast::hash(&state));
+
+            let start = Instant::now();
+            buffer.push(state);
+            store += start.elapsed();
+        }
+    }
+
+    let (kept, size) = (buffer.len(), buffer.size());
+    let start = Instant::now();
+    let mut restored = Vec::with_capacity(kept);
+    while let Some(state) = buffer.pop() {
+        restored.push(state);
+    }
+    let restore = start.elapsed();
+    if restored.iter().map(|state| crc32fast::hash(state)).ne(hashes.iter().rev().take(kept).copied()) {
+        bail!("The rewind buffer gave back different states than it was given");
+    }
+
+    let per_frame = size as f64 / kept.max(1) as f64;
+    let micros = |total: Duration, count: usize| total.as_secs_f64() * 1e6 / count.max(1) as f64;
+    Ok(format!(
+        "{:<12} {} of {} frames kept in {} KiB, {:.0} bytes per frame against {} for full states ({:.1}x smaller), {:.1} us to store and {:.1} us to restore a frame\n",
+        "rewind",
+        kept,
+        frames,
+        size / 1024,
+        per_frame,
+        full_size,
+        full_size as f64 / per_frame.max(1.0),
+        micros(store, frames),
+        micros(restore, kept)
+    ))
+}
+
+/// Runs `rom` on the interpreter and on the cached core from the same seed, returning the
+/// speed of each and what recording it for rewind costs. Fails if they end in different states
+pub fn run(rom: &[u8], cycles: u64, seed: u64) -> Result<String, Error> {
+    let fresh = || -> Result<System, Error> {
+        let mut system = System::default();
+        system.seed(seed);
+        system.load(rom)?;
+        Ok(system)
+    };
+    let mut interpreter = fresh()?;
+    let mut cached = CachedCore::new(fresh()?);
+    let recorded = rewind(fresh()?, cycles)?;
+
+    let slow = time(&mut interpreter, cycles)?;
+    let fast = time(&mut cached, cycles)?;
+    if cached.state().state_hash() != interpreter.state_hash() {
+        bail!("The cached core ended in a different state than the interpreter");
+    }
+
+    let stats = cached.stats;
+    let modified = match cached.state().self_modification.modified().next() {
+        Some((addr, pc)) if stats.disabled => format!(
+            "\nThe rom modifies its own code ({:03X} writes {:03X}), the cached core interpreted it from there",
+            pc, addr
+        ),
+        _ => String::new(),
+    };
+    Ok(format!(
+        "{}\n{}, {} blocks translated, {} invalidated{}\nThe cached core ran {:.2}x as fast, the final states match\n{}",
+        slow.describe("interpreter"),
+        fast.describe("cached"),
+        stats.blocks,
+        stats.invalidated,
+        modified,
+        slow.elapsed.as_secs_f64() / fast.elapsed.as_secs_f64().max(1e-9),
+        recorded
+    ))
+}
@@ -0,0 +1,8 @@
+#version 150
+
+in vec2 tex_pos;
+uniform sampler2D tex;
+
+void main() {
+    gl_FragColor = texture(tex, vec2(tex_pos.x, 1.0 - tex_pos.y));
+}
@@ -0,0 +1,282 @@
+use failure::Error;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::audio::{AudioConfig, AudioSink};
+use crate::clock::{self, Clock};
+use crate::framebuffer::FrameBuffer;
+use crate::frontend::{blend, palette, Frontend, Input, Region, Scaling};
+use crate::overlay::Rect;
+use crate::rom::Builder;
+use crate::timing;
+
+/// seconds `chip8 calibrate` runs for without `--seconds`
+pub const DEFAULT_SECONDS: u32 = 5;
+/// timer ticks of every beep of the calibration rom
+const BEEP_TICKS: u8 = 30;
+/// measured rates this far from the configured ones are fine
+const TOLERANCE: f64 = 0.02;
+
+/// The calibration rom: sets the delay timer to a second and the sound timer to half of that,
+/// waits for the delay timer in the usual `LD V0, DT` loop and starts over
+pub fn rom() -> Vec<u8> {
+    Builder::new()
+        .label("start")
+        .set_reg(1, timing::TIMER_HZ as u8)
+        .op(0xF115) // LD DT, V1
+        .set_reg(2, BEEP_TICKS)
+        .op(0xF218) // LD ST, V2
+        .label("wait")
+        .op(0xF007) // LD V0, DT
+        .skip_eq(0, 0)
+        .jump("wait")
+        .jump("start")
+        .build()
+        .expect("the calibration rom has no undefined labels")
+}
+
+/// What the run loop did against the wall clock
+#[derive(Default)]
+pub struct Measurements {
+    started: Option<Instant>,
+    timer_ticks: u64,
+    frames: u64,
+    /// start of the beep playing now
+    beep_start: Option<Instant>,
+    beeps: Vec<Duration>,
+    /// how long after each deadline the clock woke
+    wake_delays: Vec<Duration>,
+}
+
+impl Measurements {
+    fn elapsed(&self) -> Duration {
+        self.started.map(|started| started.elapsed()).unwrap_or_default()
+    }
+}
+
+/// Frontend without a window that counts the frames presented and closes after `frames`
+pub struct Probe {
+    measurements: Rc<RefCell<Measurements>>,
+    sink: BeepMeter,
+    frames: u64,
+}
+
+impl Probe {
+    pub fn new(measurements: Rc<RefCell<Measurements>>, frames: u64) -> Self {
+        Probe {
+            sink: BeepMeter(measurements.clone()),
+            measurements,
+            frames,
+        }
+    }
+}
+
+impl Frontend for Probe {
+    fn draw(&mut self, _: FrameBuffer, _: Option<&[Region]>, _: &[Rect]) -> Result<(), Error> {
+        self.measurements.borrow_mut().frames += 1;
+        Ok(())
+    }
+
+    fn poll_input(&mut self) -> Vec<Input> {
+        let mut measurements = self.measurements.borrow_mut();
+        measurements.started.get_or_insert_with(Instant::now);
+        if measurements.frames >= self.frames {
+            vec![Input::Close]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn set_palette(&mut self, _: palette::Palette) {}
+
+    fn set_anti_flicker(&mut self, _: blend::AntiFlicker) {}
+
+    fn set_tone(&mut self, _: palette::Tone) {}
+
+    fn set_scaling(&mut self, _: Scaling) {}
+
+    fn audio_sink(&mut self) -> Option<&mut dyn AudioSink> {
+        Some(&mut self.sink)
+    }
+}
+
+/// Times the beeps and counts the timer ticks instead of playing anything
+struct BeepMeter(Rc<RefCell<Measurements>>);
+
+impl AudioSink for BeepMeter {
+    fn sample_rate(&self) -> u32 {
+        AudioConfig::default().sample_rate
+    }
+
+    fn start_beep(&mut self) {
+        self.0.borrow_mut().beep_start.get_or_insert_with(Instant::now);
+    }
+
+    fn stop_beep(&mut self) {
+        let mut measurements = self.0.borrow_mut();
+        if let Some(start) = measurements.beep_start.take() {
+            measurements.beeps.push(start.elapsed());
+        }
+    }
+
+    fn queue_samples(&mut self, _samples: &[f32]) {}
+
+    fn timer_tick(&mut self) {
+        self.0.borrow_mut().timer_ticks += 1;
+    }
+}
+
+/// `RealClock` that records how late every sleep wakes
+pub struct MeasuredClock {
+    clock: clock::RealClock,
+    measurements: Rc<RefCell<Measurements>>,
+}
+
+impl MeasuredClock {
+    pub fn new(clock: clock::RealClock, measurements: Rc<RefCell<Measurements>>) -> Self {
+        MeasuredClock { clock, measurements }
+    }
+}
+
+impl Clock for MeasuredClock {
+    fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    fn sleep_until(&mut self, time: Duration) {
+        self.clock.sleep_until(time);
+        let late = self.clock.now().saturating_sub(time);
+        self.measurements.borrow_mut().wake_delays.push(late);
+    }
+}
+
+/// The rates that were configured and what the run came to
+pub struct Report {
+    pub seconds: f64,
+    pub cpu_hz: (f64, f64),
+    pub timer_hz: f64,
+    pub refresh_hz: (f64, f64),
+    /// expected length and the average and longest error of the beeps
+    pub beeps: Option<(Duration, Duration, Duration)>,
+    /// average and worst time a sleep overshot its deadline
+    pub wake_delay: (Duration, Duration),
+    pub spin_wait: Duration,
+}
+
+impl Report {
+    /// `instructions` run while `measurements` were taken, against the rates the run was set to
+    pub fn new(measurements: &Measurements, instructions: u64, timing: &clock::Timing, power: &clock::Power, audio: &AudioConfig) -> Self {
+        let seconds = measurements.elapsed().as_secs_f64().max(1e-9);
+        let expected = timing::FRAME * u32::from(BEEP_TICKS).max(audio.min_beep_ticks());
+        // the first beep may have started before the measurements
+        let beeps = measurements.beeps.get(1..).unwrap_or_default();
+        let errors = beeps.iter().map(|beep| beep.abs_diff(expected)).collect::<Vec<_>>();
+        let average = |durations: &[Duration]| durations.iter().sum::<Duration>().checked_div(durations.len() as u32).unwrap_or_default();
+        Report {
+            seconds,
+            cpu_hz: (timing.cpu_hz, instructions as f64 / seconds),
+            timer_hz: measurements.timer_ticks as f64 / seconds,
+            refresh_hz: (timing.refresh_hz, measurements.frames as f64 / seconds),
+            beeps: errors.iter().max().map(|worst| (expected, average(&errors), *worst)),
+            wake_delay: (average(&measurements.wake_delays), measurements.wake_delays.iter().max().copied().unwrap_or_default()),
+            spin_wait: power.spin_wait(),
+        }
+    }
+
+    /// Config changes that would bring the rates closer to what they are set to
+    pub fn suggestions(&self) -> Vec<String> {
+        let off = |(configured, measured): (f64, f64)| (measured - configured).abs() > configured * TOLERANCE;
+        let paced = !off(self.cpu_hz) && !off((f64::from(timing::TIMER_HZ), self.timer_hz));
+        let late = !paced || off(self.refresh_hz);
+        // a beep starts as soon as the timer is set and stops on a tick, so it is a frame long
+        // at worst
+        let beeps_off = self.beeps.is_some_and(|(_, average, _)| average > timing::FRAME);
+
+        let mut suggestions = Vec::new();
+        let (average, worst) = self.wake_delay;
+        if (late || beeps_off) && self.spin_wait < worst {
+            // past the longest overshoot, rounded up to 100 us
+            let spin = (worst.as_micros() as u64 / 100 + 1) * 100;
+            suggestions.push(format!(
+                "power.spin_wait_us = {}, sleeps overshoot by {:.2} ms on average and {:.2} ms at worst",
+                spin,
+                ms(average),
+                ms(worst)
+            ));
+        } else if off(self.cpu_hz) && self.cpu_hz.1 < self.cpu_hz.0 {
+            // the sleeps are on time, the instructions themselves are too slow
+            suggestions.push(format!(
+                "timing.cpu_hz = {:.0}, this machine does not keep up with {:.0}",
+                (self.cpu_hz.1 / 100.0).floor() * 100.0,
+                self.cpu_hz.0
+            ));
+        }
+        if off(self.refresh_hz) && paced && suggestions.is_empty() {
+            suggestions.push(format!("timing.refresh_hz = {:.0}, frames are presented at that rate", self.refresh_hz.1));
+        }
+        suggestions
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |(configured, measured): (f64, f64)| (measured - configured) / configured * 100.0;
+        let timer = (f64::from(timing::TIMER_HZ), self.timer_hz);
+        writeln!(f, "Measured over {:.1} s", self.seconds)?;
+        writeln!(f, "instructions  {:>9.1}/s against {:.1}/s ({:+.1}%)", self.cpu_hz.1, self.cpu_hz.0, percent(self.cpu_hz))?;
+        writeln!(f, "timer ticks   {:>9.2}/s against {:.2}/s ({:+.1}%)", timer.1, timer.0, percent(timer))?;
+        writeln!(f, "frames        {:>9.2}/s against {:.2}/s ({:+.1}%)", self.refresh_hz.1, self.refresh_hz.0, percent(self.refresh_hz))?;
+        match self.beeps {
+            Some((expected, average, worst)) => writeln!(
+                f,
+                "beeps of {:.1} ms off by {:.2} ms on average, {:.2} ms at worst",
+                ms(expected),
+                ms(average),
+                ms(worst)
+            )?,
+            None => writeln!(f, "no beeps were measured, run for longer")?,
+        }
+        writeln!(
+            f,
+            "sleeps overshoot by {:.2} ms on average, {:.2} ms at worst, with {} us of spin wait",
+            ms(self.wake_delay.0),
+            ms(self.wake_delay.1),
+            self.spin_wait.as_micros()
+        )?;
+        match self.suggestions().as_slice() {
+            [] => writeln!(f, "Everything runs within {}% of its rate, nothing to change", TOLERANCE * 100.0),
+            suggestions => {
+                writeln!(f, "Suggested config changes:")?;
+                suggestions.iter().try_for_each(|suggestion| writeln!(f, "  {}", suggestion))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suggests_spin_waiting_for_late_sleeps() {
+        let report = |cpu_hz: f64, worst: Duration| Report {
+            seconds: 5.0,
+            cpu_hz: (2400.0, cpu_hz),
+            timer_hz: 60.0,
+            refresh_hz: (60.0, 60.0),
+            beeps: Some((Duration::from_millis(500), Duration::from_millis(1), Duration::from_millis(2))),
+            wake_delay: (worst / 2, worst),
+            spin_wait: Duration::from_micros(0),
+        };
+        assert!(report(2400.0, Duration::from_millis(3)).suggestions().is_empty());
+        assert_eq!(report(2200.0, Duration::from_micros(1450)).suggestions()[0].split(',').next(), Some("power.spin_wait_us = 1500"));
+        // on time sleeps leave a slow machine
+        assert!(report(2200.0, Duration::ZERO).suggestions()[0].starts_with("timing.cpu_hz = 2200"));
+    }
+}
@@ -0,0 +1,196 @@
+//! C functions over `System`, declared in `include/chip8.h`. The handle owns the machine and
+//! the message of the last error; functions that can fail return 0 on success and -1 on
+//! failure, with the message from `chip8_last_error`. Null handles fail the same way, nothing
+//! here panics across the boundary
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+use crate::system::debug::Debugger;
+use crate::system::{System, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Bumped when a function changes in a way existing callers would notice
+pub const API_VERSION: u32 = 1;
+
+/// The opaque `chip8_t` of the header
+pub struct Chip8 {
+    system: System,
+    debugger: Debugger,
+    error: CString,
+}
+
+impl Chip8 {
+    /// 0 for `Ok`, -1 with the message kept for an error
+    fn status<E: std::fmt::Display>(&mut self, result: Result<(), E>) -> c_int {
+        match result {
+            Ok(()) => 0,
+            Err(e) => {
+                // a message can't hold a nul, the text up to it is enough
+                let message = e.to_string();
+                let message = message.split('\0').next().unwrap_or_default();
+                self.error = CString::new(message).unwrap_or_default();
+                -1
+            }
+        }
+    }
+}
+
+/// The version of the API the library implements, `CHIP8_API_VERSION` in the header it was
+/// built with
+#[no_mangle]
+pub extern "C" fn chip8_api_version() -> u32 {
+    API_VERSION
+}
+
+/// A new machine with the default quirks, free it with `chip8_free`
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    Box::into_raw(Box::new(Chip8 {
+        system: System::default(),
+        debugger: Debugger::disabled(),
+        error: CString::default(),
+    }))
+}
+
+/// # Safety
+/// `chip8` is null or from `chip8_new`, and not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Loads `len` bytes of a rom and starts it from scratch, see `System::load`
+///
+/// # Safety
+/// `chip8` is null or from `chip8_new`, `rom` points to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, rom: *const u8, len: usize) -> c_int {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return -1,
+    };
+    if rom.is_null() {
+        return chip8.status(Err("the rom is null"));
+    }
+    let rom = std::slice::from_raw_parts(rom, len);
+    let result = chip8.system.load(rom);
+    chip8.status(result)
+}
+
+/// Executes one instruction
+///
+/// # Safety
+/// `chip8` is null or from `chip8_new`
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> c_int {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return -1,
+    };
+    let result = chip8.system.tick(&mut chip8.debugger);
+    chip8.status(result)
+}
+
+/// Decrements the delay and sound timers, to be called 60 times per second. Returns 1 if the
+/// sound timer is running (the beep should play), 0 if not, -1 for a null handle
+///
+/// # Safety
+/// `chip8` is null or from `chip8_new`
+#[no_mangle]
+pub unsafe extern "C" fn chip8_tick_timers(chip8: *mut Chip8) -> c_int {
+    match chip8.as_mut() {
+        Some(chip8) => {
+            chip8.system.dec_timers();
+            c_int::from(chip8.system.timers.sound > 0)
+        }
+        None => -1,
+    }
+}
+
+/// Presses (`pressed` not 0) or releases hex key `key`, 0 to F
+///
+/// # Safety
+/// `chip8` is null or from `chip8_new`
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: c_int) -> c_int {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return -1,
+    };
+    let result = chip8.system.process_key_event(key, pressed != 0);
+    chip8.status(result)
+}
+
+/// Copies the screen to `out`, one byte per pixel row by row from the top left, 0 for unlit
+/// pixels and 255 for lit ones. Returns the bytes the screen takes (`CHIP8_SCREEN_WIDTH *
+/// CHIP8_SCREEN_HEIGHT`), nothing is copied if `len` is less or `out` is null
+///
+/// # Safety
+/// `chip8` is null or from `chip8_new`, `out` is null or points to `len` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(chip8: *const Chip8, out: *mut u8, len: usize) -> usize {
+    let size = usize::from(SCREEN_WIDTH) * usize::from(SCREEN_HEIGHT);
+    if let (Some(chip8), false) = (chip8.as_ref(), out.is_null()) {
+        if len >= size {
+            let screen = chip8.system.screen();
+            ptr::copy_nonoverlapping(screen.as_ptr(), out, size);
+        }
+    }
+    size
+}
+
+/// The message of the last failed call, empty if none failed. It stays valid until the next
+/// call on `chip8`
+///
+/// # Safety
+/// `chip8` is null or from `chip8_new`
+#[no_mangle]
+pub unsafe extern "C" fn chip8_last_error(chip8: *const Chip8) -> *const c_char {
+    match chip8.as_ref() {
+        Some(chip8) => chip8.error.as_ptr(),
+        None => b"the chip8 handle is null\0".as_ptr() as *const c_char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_declares_every_function() {
+        let header = include_str!("../include/chip8.h");
+        let source = include_str!("capi.rs");
+        for line in source.lines().filter(|line| line.contains("extern \"C\" fn chip8_")) {
+            let name = line.split("fn ").nth(1).and_then(|rest| rest.split('(').next()).unwrap();
+            assert!(header.contains(&format!("{}(", name)), "{} is not in chip8.h", name);
+        }
+        assert!(header.contains(&format!("#define CHIP8_API_VERSION {}", API_VERSION)));
+    }
+
+    #[test]
+    fn runs_a_rom() {
+        let rom = [
+            0x60, 0x05, // LD V0, 5
+            0xF0, 0x29, // LD F, V0
+            0xD1, 0x15, // DRW V1, V1, 5
+        ];
+        unsafe {
+            let chip8 = chip8_new();
+            assert_eq!(chip8_load_rom(chip8, rom.as_ptr(), rom.len()), 0);
+            for _ in 0..3 {
+                assert_eq!(chip8_step(chip8), 0);
+            }
+            let mut screen = vec![0; 64 * 32];
+            assert_eq!(chip8_framebuffer(chip8, screen.as_mut_ptr(), screen.len()), screen.len());
+            // the top row of the 5 digit
+            assert_eq!(screen[..4], [255; 4]);
+
+            assert_eq!(chip8_set_key(chip8, 0x10, 1), -1);
+            assert!(!std::ffi::CStr::from_ptr(chip8_last_error(chip8)).to_bytes().is_empty());
+            chip8_free(chip8);
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use failure::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::framebuffer::FrameBuffer;
+use crate::frontend::palette::Palette;
+
+/// Where `--frames-out` writes the presented frames
+enum Target {
+    /// raw RGBA frames one after another, for a pipe into an encoder
+    Stream(BufWriter<File>),
+    /// one numbered PNG per frame
+    Directory(PathBuf),
+}
+
+/// Writes every presented frame with the palette applied, at the resolution of the emulated screen
+pub struct FrameWriter {
+    target: Target,
+    frame: u64,
+    /// size of the frames written to the stream, which an encoder expects to stay the same
+    size: Option<(u32, u32)>,
+}
+
+impl FrameWriter {
+    /// PNG files if `path` is a directory, a raw RGBA stream otherwise (a fifo or a file)
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let target = if path.is_dir() {
+            Target::Directory(path.to_owned())
+        } else {
+            Target::Stream(BufWriter::new(File::create(path)?))
+        };
+
+        Ok(FrameWriter {
+            target,
+            frame: 0,
+            size: None,
+        })
+    }
+
+    pub fn write(&mut self, frame: &FrameBuffer, palette: &Palette) -> Result<(), Error> {
+        let (width, height) = (frame.width(), frame.height());
+        let rgba = frame.rgba(palette);
+
+        match &mut self.target {
+            Target::Stream(stream) => {
+                if self.size.is_none() {
+                    log::info!(target: "display", "Writing {}x{} RGBA frames", width, height);
+                } else if self.size != Some((width, height)) {
+                    log::warn!(target: "display", "The frame size changed to {}x{}", width, height);
+                }
+                self.size = Some((width, height));
+                stream.write_all(&rgba)?;
+                stream.flush()?;
+            }
+            Target::Directory(dir) => {
+                let path = dir.join(format!("frame-{:06}.png", self.frame));
+                std::fs::write(path, png(&rgba, width, height))?;
+            }
+        }
+
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+/// An uncompressed RGBA PNG: the pixel data goes into stored deflate blocks
+pub fn png(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = out.len();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        let crc = crc32fast::hash(&out[start..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    // every row starts with the filter type, 0 is none
+    let row = width as usize * 4;
+    let mut raw = Vec::with_capacity((row + 1) * height as usize);
+    for line in rgba.chunks(row.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(line);
+    }
+
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        zlib.push(u8::from(last));
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, no filtering method, no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib);
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
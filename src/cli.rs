@@ -0,0 +1,895 @@
+use failure::Error;
+use std::convert::TryInto;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::batch;
+use crate::calibrate;
+use crate::frontend::{self, palette::Preset, Renderer};
+use crate::overlay;
+use crate::paths;
+use crate::ratings;
+use crate::scores;
+use crate::system::diagnostics::MemoryFill;
+use crate::system::MEMORY_SIZE;
+
+pub const USAGE: &str = "\
+Usage: chip8 <command> [options]
+
+Without a command a window opens with a demo and a rom picker for the current directory.
+  --config <path>           config file to use (default: chip8.toml in the current directory
+                            if there is one, else in the platform's config directory)
+  --watch-dir <dir>         list the roms in <dir> instead, refreshing the list, and run a rom
+                            as soon as it is written there, e.g. by an external assembler
+
+Commands:
+  run <rom>                 run a rom, or the rom of that name in `rom_dirs` of the config,
+                            e.g. `chip8 run pong` for pong.ch8
+      --config <path>       config file to use (default: chip8.toml)
+      --compat-report       skip unsupported opcodes and report them on exit
+      --diagnostics <mode>  warn about odd addresses, jumps below 0x200 and instructions the
+                            program writes over: `warn` prints them, `break` also enters
+                            the debugger
+      --vf-warnings         warn about arithmetic with VF as an operand or destination
+      --seed <n>            seed the random number generator for reproducible runs
+      --fill-memory <fill>  fill memory and registers with `zero` (default), `ff` or `random`
+                            bytes before loading the rom, to catch reads of uninitialized memory
+      --auto-speed          fast-forward through loops that wait for the delay timer
+      --cycles-per-poll <n> instructions between polls of the window's events, more for less
+                            CPU on slow window systems (X forwarding, VMs), default ~1 ms worth
+      --dump-screen-on-exit print the final screen as text
+      --frames-out <path>   write every presented frame: numbered PNGs if the path is a
+                            directory, raw RGBA otherwise (e.g. a fifo read by ffmpeg)
+      --record-audio <path> write the sound to a WAV file, in emulated time
+      --record-replay <path>
+                            write the inputs against the instructions they came before,
+                            for `chip8 replay`. Loading a state ends the replay
+      --summary-json <path> on exit write the cycles, frames, opcode counts, errors, deepest
+                            stack, draw collisions and wall-clock rates as JSON
+      --teach               run 2 instructions per second with a panel explaining each one
+                            and highlighting the registers it touched, Space runs one more
+      --teach-hz <n>        the same at <n> instructions per second, 0 to only step with Space
+      --trace               log every instruction with its cycle, and the key presses and
+                            releases between them, to see where a press landed relative to
+                            the instructions that poll the keys
+      --patch-mem <addr>=<path>
+                            write a file to memory at <addr> after loading the rom, e.g.
+                            0x300=patch.bin. Can be given more than once
+      --dump-mem <start>..<end>=<path>
+                            write memory from <start> up to <end> to a file after the rom is
+                            loaded and patched, e.g. 0x200..0x400=out.bin
+      --palette <name>      color palette: classic, high-contrast, deuteranopia or protanopia
+      --scale <n>           window size in screen pixels per chip8 pixel
+      --integer-scaling     only scale the screen by whole multiples, bordering the rest
+      --pixel-aspect <w:h>  pixel proportions, e.g. 1:1.2 for pixels taller than wide
+      --renderer <name>     glium, wgpu, sdl2 or tui. wgpu and sdl2 need the cargo features
+                            of the same name, wgpu is the default when it is built.
+                            tui draws in the terminal, and is used when no window opens
+      --debug-window        start paused in the debugger (registers, stack, disassembly,
+                            memory and a console for commands), F1 toggles it
+  debug <rom>               step through a rom, printing registers after every instruction.
+                            supports breakpoints and stepping backwards, see `help` inside
+      --config <path>       config file to use (default: chip8.toml)
+      --diagnostics warn    print diagnostics while stepping
+      --vf-warnings         warn about arithmetic with VF as an operand or destination
+      --fill-memory <fill>  fill memory and registers with `zero`, `ff` or `random` bytes
+      --patch-mem, --dump-mem
+                            as for `run`
+      --dump-screen-on-exit print the final screen as text
+  latency                   run a diagnostic rom and measure the time from a key event to
+                            the frame that shows it, printing the average and worst case
+      --config <path>       config file to use (default: chip8.toml)
+      --renderer <name>     the window backend to measure
+  calibrate                 run a generated rom without a window and measure the instruction
+                            rate, timer ticks, frames and beep lengths against the wall clock
+                            and the config, then print a report with suggested config changes
+      --config <path>       config file with the rates (default: chip8.toml)
+      --seconds <n>         how long to measure (default: 5)
+  disasm <rom>              print the disassembly of a rom
+  decompile <rom>           print experimental pseudo-C of a rom: if/while from skip and jump
+                            patterns, gotos for the rest
+  asm <src> <output>        assemble a source file into a rom
+  opcodes                   print every instruction: pattern, syntax, approximate cycles,
+                            quirks and what it does
+  info <rom>                print information about a rom, the platform its opcodes are for,
+                            and how well it runs as rated
+      --config <path>       config file with the game database (default: chip8.toml)
+  rate <rom> <status>       record how well a rom runs, `works`, `minor-issues` or `broken`,
+                            with the quirks it runs with in chip8-ratings.toml next to the
+                            config. A second rating of the same rom replaces the first
+      --notes <text>        what works or does not
+      --config <path>       config file with the quirks (default: chip8.toml)
+  ratings export <path>     write the ratings given with `rate` to a JSON file to share
+  ratings import <path>     add the ratings of a file written by `ratings export` to those
+                            `info` shows, skipping those already there
+      --config <path>       config file the ratings are next to (default: chip8.toml)
+  lint <rom>                check a rom for jumps outside of it, too deep calls, reads past
+                            the end of memory, quirk-dependent instructions, VF used as a
+                            general register and writes over its own code without running it,
+                            and print the register usage of every routine
+  test-suite <dir>          run every .ch8 rom in a directory headlessly and compare
+                            the final screen with the .txt file next to it
+      --cycles <n>          instructions to run per rom (default: 10000)
+      --bless               write the reference screens instead of comparing
+      --report <dir>        where to write an image of each mismatch: the expected screen,
+                            the actual one and their difference (default: <dir>/report)
+  check <rom> <script>      run a rom headlessly and check its state against an assertion
+                            script, e.g. `at cycle 5000 assert v3 == 7 and pixel(10, 12) == on`
+      --seed <n>            random number generator seed (default: 0)
+  tournament <rom> <bot>... let bot scripts play the rom headlessly, each its own game, and
+                            print a leaderboard of their best scores. Bots are `check`
+                            scripts that hold keys with `when <conditions> press <key>`
+      --frames <n>          frames each bot plays (default: 3600)
+      --jobs <n>            threads the games take turns on (default: the number of cpus)
+      --score <addr:len:binary|bcd>
+                            where the score is, e.g. `0x2F0:3:bcd` (default: the score of
+                            the game in the database)
+      --seed <n>            random number generator seed (default: 0)
+      --config <path>       config file with the game database (default: chip8.toml)
+  batch <dir>               run every .ch8 rom in a directory headlessly in parallel and print
+                            a report: halted, crashed (with the error) or still running,
+                            and a hash of the final screen
+      --cycles <n>          instructions to run per rom (default: 10000)
+      --jobs <n>            threads to use (default: the number of cpus)
+      --format <csv|json>   report format (default: csv)
+      --output <path>       write the report to a file instead of stdout
+  replay <replay>           play back a replay written by `run --record-replay` headlessly,
+                            then print the final screen and state hash
+  audit <rom>               run a rom twice headlessly, comparing state hashes after every frame
+      --frames <n>          frames to run (default: 600)
+      --seed <n>            random number generator seed (default: 0)
+      --log <path>          write the per-frame hashes to a file
+      --verify <path>       also compare against hashes written by --log
+  bench <rom>               run a rom headlessly on the interpreter and on the experimental
+                            cached core, which decodes blocks of instructions once, and print
+                            the speed of each. Fails if they end in different states
+      --cycles <n>          instructions to run (default: 10000000)
+      --seed <n>            random number generator seed (default: 0)
+  quirk-test                run a generated rom that detects how the shift, load/store,
+                            jump with offset and sprite wrapping instructions behave with the
+                            quirks of the config, and print what it found. Fails if that is
+                            not what the quirks select
+      --config <path>       config file with the quirks (default: chip8.toml)
+      --output <path>       write the rom instead, to run it elsewhere. It shows the results
+                            as digits: 0 is the first behavior listed for each test
+  selftest                  check that this build runs roms correctly: small roms test the
+                            arithmetic, the flags, draw collisions, BCD and the timers, and
+                            each area prints PASS or FAIL. Fails if any area does
+  statediff <a> <b>         print what differs between two save states (registers, timers,
+                            stack, memory and screen), e.g. to find where a game keeps a score
+  rom trim <input> <output> strip trailing zero bytes
+  rom pad <input> <output>  pad an odd-length rom to even length
+
+Exit status: 0 on success, 1 for other errors, 2 for an invalid command line, 3 when the rom is
+not found, 4 when it does not fit in memory, 5 for an unknown opcode, 6 when the program stops
+with another error, 7 when no window can be opened, 8 when assertions of `check` fail, and
+100 plus the number of failed roms (up to 125) for `test-suite`
+";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DiagnosticsMode {
+    Off,
+    Warn,
+    Break,
+}
+
+/// display settings given on the command line, they take precedence over the config
+#[derive(Clone, Copy, Default)]
+pub struct DisplayArgs {
+    pub palette: Option<Preset>,
+    pub scale: Option<u32>,
+    pub integer_scaling: bool,
+    /// pixel height over width
+    pub pixel_aspect: Option<f64>,
+    pub renderer: Renderer,
+    /// start paused in the debugger
+    pub debug_window: bool,
+}
+
+pub struct RunArgs {
+    pub rom: PathBuf,
+    pub config: PathBuf,
+    pub compat_report: bool,
+    pub diagnostics: DiagnosticsMode,
+    /// report `Diagnostic::FlagRegister`
+    pub vf_warnings: bool,
+    pub seed: Option<u64>,
+    pub memory_fill: MemoryFill,
+    /// overrides `timing.auto_speed` of the config
+    pub auto_speed: bool,
+    /// overrides `timing.cycles_per_poll` of the config
+    pub cycles_per_poll: Option<u32>,
+    /// print the screen with `System::screen_ascii` when the emulator exits
+    pub dump_screen: bool,
+    /// a directory for numbered PNG frames, or a file or fifo for a raw RGBA stream
+    pub frames_out: Option<PathBuf>,
+    /// WAV file for the sound as it was generated
+    pub record_audio: Option<PathBuf>,
+    /// file for the `replay::Replay` of the run
+    pub record_replay: Option<PathBuf>,
+    /// file for the `summary::RunSummary` of the run
+    pub summary_json: Option<PathBuf>,
+    /// instructions per second of `--teach`, 0 to step with Space
+    pub teach: Option<f64>,
+    /// log every instruction and key event
+    pub trace: bool,
+    /// files written to memory after the rom is loaded, in order
+    pub patch_mem: Vec<(u16, PathBuf)>,
+    /// memory written to files after the patches, before the program runs
+    pub dump_mem: Vec<(Range<u16>, PathBuf)>,
+    pub display: DisplayArgs,
+}
+
+pub enum RomCommand {
+    Trim,
+    Pad,
+}
+
+pub enum RatingsCommand {
+    Export,
+    Import,
+}
+
+pub enum Command {
+    /// no command given: the splash screen and the rom picker
+    Start { config: PathBuf, watch: Option<PathBuf> },
+    Run(RunArgs),
+    Debug(RunArgs),
+    Latency { config: PathBuf, display: DisplayArgs },
+    Calibrate { config: PathBuf, seconds: u32 },
+    Disasm { rom: PathBuf },
+    Decompile { rom: PathBuf },
+    Asm { src: PathBuf, output: PathBuf },
+    Opcodes,
+    Info { rom: PathBuf, config: PathBuf },
+    Lint { rom: PathBuf },
+    TestSuite {
+        dir: PathBuf,
+        cycles: u32,
+        bless: bool,
+        /// for the images of mismatches
+        report: PathBuf,
+    },
+    Check { rom: PathBuf, script: PathBuf, seed: u64 },
+    Tournament {
+        rom: PathBuf,
+        bots: Vec<PathBuf>,
+        frames: u64,
+        jobs: usize,
+        /// the score region of the game in the database if not given
+        score: Option<scores::ScoreRegion>,
+        seed: u64,
+        config: PathBuf,
+    },
+    Batch {
+        dir: PathBuf,
+        cycles: u32,
+        jobs: usize,
+        format: batch::Format,
+        output: Option<PathBuf>,
+    },
+    Audit {
+        rom: PathBuf,
+        frames: u64,
+        seed: u64,
+        log: Option<PathBuf>,
+        verify: Option<PathBuf>,
+    },
+    Bench { rom: PathBuf, cycles: u64, seed: u64 },
+    /// run the quirk detection rom, or write it to `output`
+    QuirkTest { config: PathBuf, output: Option<PathBuf> },
+    /// check the core with the roms of `selftest`
+    SelfTest,
+    Replay { replay: PathBuf },
+    StateDiff { before: PathBuf, after: PathBuf },
+    Rom { command: RomCommand, input: PathBuf, output: PathBuf },
+    Rate {
+        rom: PathBuf,
+        status: ratings::Status,
+        notes: String,
+        config: PathBuf,
+    },
+    Ratings { command: RatingsCommand, path: PathBuf, config: PathBuf },
+    Help,
+}
+
+struct Args<I> {
+    args: I,
+    positional: Vec<String>,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    /// next option, collecting positional arguments along the way
+    fn next_option(&mut self) -> Option<String> {
+        for arg in self.args.by_ref() {
+            if arg.starts_with("--") {
+                return Some(arg);
+            }
+            self.positional.push(arg);
+        }
+        None
+    }
+
+    fn value(&mut self, option: &str) -> Result<String, Error> {
+        self.args
+            .next()
+            .ok_or_else(|| format_err!("Missing value for {}", option))
+    }
+
+    /// checks that exactly `N` positional arguments were given
+    fn positional<const N: usize>(self) -> Result<[String; N], Error> {
+        let count = self.positional.len();
+        self.positional
+            .try_into()
+            .map_err(|_| format_err!("Expected {} arguments, got {}", N, count))
+    }
+}
+
+/// `<left>=<path>` of `--patch-mem` and `--dump-mem`
+fn split_assignment(value: &str) -> Result<(String, PathBuf), Error> {
+    match value.split_once('=') {
+        Some((left, path)) if !path.is_empty() => Ok((left.to_string(), path.into())),
+        _ => bail!("Expected <address>=<path>, got {}", value),
+    }
+}
+
+/// A number in hex with `0x` or in decimal
+fn parse_number(text: &str) -> Result<u16, Error> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => text.parse(),
+    }
+    .map_err(|_| format_err!("Invalid address: {}", text))
+}
+
+fn parse_address(text: &str) -> Result<u16, Error> {
+    match parse_number(text)? {
+        addr if addr < MEMORY_SIZE => Ok(addr),
+        addr => bail!("Address {:#X} is past the end of memory", addr),
+    }
+}
+
+/// `<start>..<end>`, the end not included
+fn parse_memory_range(text: &str) -> Result<Range<u16>, Error> {
+    let (start, end) = text
+        .split_once("..")
+        .ok_or_else(|| format_err!("Expected <start>..<end>, got {}", text))?;
+    let range = parse_address(start)?..parse_number(end)?;
+    if range.end > MEMORY_SIZE {
+        bail!("Address {:#X} is past the end of memory", range.end);
+    }
+    if range.is_empty() {
+        bail!("The memory range {} is empty", text);
+    }
+    Ok(range)
+}
+
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Command, Error> {
+    let mut args = Args {
+        args,
+        positional: Vec::new(),
+    };
+
+    let command = match args.args.next() {
+        Some(command) => command,
+        None => {
+            return Ok(Command::Start {
+                config: paths::default_config(),
+                watch: None,
+            })
+        }
+    };
+
+    let command = match command.as_str() {
+        "run" | "debug" => {
+            let mut config = paths::default_config();
+            let mut compat_report = false;
+            let mut diagnostics = DiagnosticsMode::Off;
+            let mut vf_warnings = false;
+            let mut seed = None;
+            let mut memory_fill = MemoryFill::Zero;
+            let mut auto_speed = false;
+            let mut cycles_per_poll = None;
+            let mut dump_screen = false;
+            let mut frames_out = None;
+            let mut record_audio = None;
+            let mut record_replay = None;
+            let mut summary_json = None;
+            let mut teach = None;
+            let mut trace = false;
+            let mut patch_mem = Vec::new();
+            let mut dump_mem = Vec::new();
+            let mut display = DisplayArgs::default();
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--config" => config = args.value(&option)?.into(),
+                    "--compat-report" if command == "run" => compat_report = true,
+                    "--vf-warnings" => vf_warnings = true,
+                    "--seed" => seed = Some(args.value(&option)?.parse()?),
+                    "--fill-memory" => {
+                        let name = args.value(&option)?;
+                        match MemoryFill::from_name(&name) {
+                            Some(fill) => memory_fill = fill,
+                            None => bail!("Unknown memory fill: {}", name),
+                        }
+                    }
+                    "--auto-speed" if command == "run" => auto_speed = true,
+                    "--cycles-per-poll" if command == "run" => match args.value(&option)?.parse()? {
+                        0 => bail!("Cycles per poll must be at least 1"),
+                        cycles => cycles_per_poll = Some(cycles),
+                    },
+                    "--dump-screen-on-exit" => dump_screen = true,
+                    "--frames-out" if command == "run" => frames_out = Some(args.value(&option)?.into()),
+                    "--record-audio" if command == "run" => record_audio = Some(args.value(&option)?.into()),
+                    "--record-replay" if command == "run" => record_replay = Some(args.value(&option)?.into()),
+                    "--summary-json" if command == "run" => summary_json = Some(args.value(&option)?.into()),
+                    "--teach" if command == "run" => teach = Some(teach.unwrap_or(overlay::teach::DEFAULT_HZ)),
+                    "--teach-hz" if command == "run" => match args.value(&option)?.parse::<f64>()? {
+                        hz if hz.is_finite() && hz >= 0.0 => teach = Some(hz),
+                        _ => bail!("The teaching rate must be 0 or more instructions per second"),
+                    },
+                    "--trace" if command == "run" => trace = true,
+                    "--patch-mem" => {
+                        let (addr, path) = split_assignment(&args.value(&option)?)?;
+                        patch_mem.push((parse_address(&addr)?, path));
+                    }
+                    "--dump-mem" => {
+                        let (range, path) = split_assignment(&args.value(&option)?)?;
+                        dump_mem.push((parse_memory_range(&range)?, path));
+                    }
+                    "--palette" => {
+                        let name = args.value(&option)?;
+                        match Preset::from_name(&name) {
+                            Some(preset) => display.palette = Some(preset),
+                            None => bail!("Unknown palette: {}", name),
+                        }
+                    }
+                    "--renderer" => {
+                        let name = args.value(&option)?;
+                        match Renderer::from_name(&name) {
+                            Some(renderer) => display.renderer = renderer,
+                            None => bail!("Unknown renderer: {}", name),
+                        }
+                    }
+                    "--integer-scaling" => display.integer_scaling = true,
+                    "--pixel-aspect" => display.pixel_aspect = Some(frontend::parse_pixel_aspect(&args.value(&option)?)?),
+                    "--debug-window" if command == "run" && !cfg!(feature = "debugger") => {
+                        bail!("`--debug-window` needs the debugger, rebuild with `--features debugger`")
+                    }
+                    "--debug-window" if command == "run" => display.debug_window = true,
+                    "--scale" => match args.value(&option)?.parse()? {
+                        0 => bail!("Scale must be at least 1"),
+                        scale => display.scale = Some(scale),
+                    },
+                    "--diagnostics" => {
+                        diagnostics = match args.value(&option)?.as_str() {
+                            "warn" => DiagnosticsMode::Warn,
+                            "break" if cfg!(feature = "debugger") => DiagnosticsMode::Break,
+                            "break" => bail!("`--diagnostics break` needs the debugger, rebuild with `--features debugger`"),
+                            mode => bail!("Unknown diagnostics mode: {}", mode),
+                        }
+                    }
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+
+            let [rom] = args.positional()?;
+            let run = RunArgs {
+                rom: rom.into(),
+                config,
+                compat_report,
+                diagnostics,
+                vf_warnings,
+                seed,
+                memory_fill,
+                auto_speed,
+                cycles_per_poll,
+                dump_screen,
+                frames_out,
+                record_audio,
+                record_replay,
+                summary_json,
+                teach,
+                trace,
+                patch_mem,
+                dump_mem,
+                display,
+            };
+            if command == "run" {
+                Command::Run(run)
+            } else {
+                Command::Debug(run)
+            }
+        }
+
+        "disasm" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [rom] = args.positional()?;
+            Command::Disasm { rom: rom.into() }
+        }
+
+        "decompile" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [rom] = args.positional()?;
+            Command::Decompile { rom: rom.into() }
+        }
+
+        "opcodes" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [] = args.positional()?;
+            Command::Opcodes
+        }
+
+        "asm" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [src, output] = args.positional()?;
+            Command::Asm {
+                src: src.into(),
+                output: output.into(),
+            }
+        }
+
+        "info" => {
+            let mut config = paths::default_config();
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--config" => config = args.value(&option)?.into(),
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [rom] = args.positional()?;
+            Command::Info {
+                rom: rom.into(),
+                config,
+            }
+        }
+
+        "latency" => {
+            let mut config = paths::default_config();
+            let mut display = DisplayArgs::default();
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--config" => config = args.value(&option)?.into(),
+                    "--renderer" => {
+                        let name = args.value(&option)?;
+                        match Renderer::from_name(&name) {
+                            Some(renderer) => display.renderer = renderer,
+                            None => bail!("Unknown renderer: {}", name),
+                        }
+                    }
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [] = args.positional()?;
+            Command::Latency { config, display }
+        }
+
+        "calibrate" => {
+            let mut config = paths::default_config();
+            let mut seconds = calibrate::DEFAULT_SECONDS;
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--config" => config = args.value(&option)?.into(),
+                    "--seconds" => match args.value(&option)?.parse()? {
+                        0 => bail!("Seconds must be at least 1"),
+                        n => seconds = n,
+                    },
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [] = args.positional()?;
+            Command::Calibrate { config, seconds }
+        }
+
+        "lint" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [rom] = args.positional()?;
+            Command::Lint { rom: rom.into() }
+        }
+
+        "test-suite" => {
+            let mut cycles = 10000;
+            let mut bless = false;
+            let mut report = None;
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--cycles" => cycles = args.value(&option)?.parse()?,
+                    "--bless" => bless = true,
+                    "--report" => report = Some(args.value(&option)?.into()),
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [dir] = args.positional()?;
+            let dir = PathBuf::from(dir);
+            Command::TestSuite {
+                report: report.unwrap_or_else(|| dir.join("report")),
+                dir,
+                cycles,
+                bless,
+            }
+        }
+
+        "batch" => {
+            let mut cycles = 10000;
+            let mut jobs = std::thread::available_parallelism().map_or(1, |jobs| jobs.get());
+            let mut format = batch::Format::Csv;
+            let mut output = None;
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--cycles" => cycles = args.value(&option)?.parse()?,
+                    "--jobs" => match args.value(&option)?.parse()? {
+                        0 => bail!("Jobs must be at least 1"),
+                        n => jobs = n,
+                    },
+                    "--format" => {
+                        let name = args.value(&option)?;
+                        match batch::Format::from_name(&name) {
+                            Some(f) => format = f,
+                            None => bail!("Unknown report format: {}", name),
+                        }
+                    }
+                    "--output" => output = Some(args.value(&option)?.into()),
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [dir] = args.positional()?;
+            Command::Batch {
+                dir: dir.into(),
+                cycles,
+                jobs,
+                format,
+                output,
+            }
+        }
+
+        "check" => {
+            let mut seed = 0;
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--seed" => seed = args.value(&option)?.parse()?,
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [rom, script] = args.positional()?;
+            Command::Check {
+                rom: rom.into(),
+                script: script.into(),
+                seed,
+            }
+        }
+
+        "tournament" => {
+            let mut frames = 3600;
+            let mut jobs = std::thread::available_parallelism().map_or(1, |jobs| jobs.get());
+            let mut score = None;
+            let mut seed = 0;
+            let mut config = paths::default_config();
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--frames" => frames = args.value(&option)?.parse()?,
+                    "--jobs" => match args.value(&option)?.parse()? {
+                        0 => bail!("Jobs must be at least 1"),
+                        n => jobs = n,
+                    },
+                    "--score" => score = Some(scores::ScoreRegion::parse(&args.value(&option)?)?),
+                    "--seed" => seed = args.value(&option)?.parse()?,
+                    "--config" => config = args.value(&option)?.into(),
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let mut positional = args.positional.into_iter();
+            let rom = positional.next().ok_or_else(|| format_err!("Expected a rom and bot scripts"))?;
+            let bots = positional.map(PathBuf::from).collect::<Vec<_>>();
+            if bots.is_empty() {
+                bail!("Expected at least one bot script after the rom");
+            }
+            Command::Tournament {
+                rom: rom.into(),
+                bots,
+                frames,
+                jobs,
+                score,
+                seed,
+                config,
+            }
+        }
+
+        "replay" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [replay] = args.positional()?;
+            Command::Replay { replay: replay.into() }
+        }
+
+        "statediff" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [before, after] = args.positional()?;
+            Command::StateDiff {
+                before: before.into(),
+                after: after.into(),
+            }
+        }
+
+        "audit" => {
+            let mut frames = 600;
+            let mut seed = 0;
+            let mut log = None;
+            let mut verify = None;
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--frames" => frames = args.value(&option)?.parse()?,
+                    "--seed" => seed = args.value(&option)?.parse()?,
+                    "--log" => log = Some(args.value(&option)?.into()),
+                    "--verify" => verify = Some(args.value(&option)?.into()),
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [rom] = args.positional()?;
+            Command::Audit {
+                rom: rom.into(),
+                frames,
+                seed,
+                log,
+                verify,
+            }
+        }
+
+        "bench" => {
+            let mut cycles = 10_000_000;
+            let mut seed = 0;
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--cycles" => cycles = args.value(&option)?.parse()?,
+                    "--seed" => seed = args.value(&option)?.parse()?,
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [rom] = args.positional()?;
+            Command::Bench {
+                rom: rom.into(),
+                cycles,
+                seed,
+            }
+        }
+
+        "quirk-test" => {
+            let mut config = paths::default_config();
+            let mut output = None;
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--config" => config = args.value(&option)?.into(),
+                    "--output" => output = Some(args.value(&option)?.into()),
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [] = args.positional()?;
+            Command::QuirkTest { config, output }
+        }
+
+        "selftest" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [] = args.positional()?;
+            Command::SelfTest
+        }
+
+        "rom" => {
+            if let Some(option) = args.next_option() {
+                bail!("Unknown option: {}", option);
+            }
+            let [command, input, output] = args.positional()?;
+            let command = match command.as_str() {
+                "trim" => RomCommand::Trim,
+                "pad" => RomCommand::Pad,
+                _ => bail!("Unknown rom command: {}", command),
+            };
+            Command::Rom {
+                command,
+                input: input.into(),
+                output: output.into(),
+            }
+        }
+
+        "rate" => {
+            let mut config = paths::default_config();
+            let mut notes = String::new();
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--config" => config = args.value(&option)?.into(),
+                    "--notes" => notes = args.value(&option)?,
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [rom, status] = args.positional()?;
+            let status = match ratings::Status::from_name(&status) {
+                Some(status) => status,
+                None => bail!("Unknown status: {}, use works, minor-issues or broken", status),
+            };
+            Command::Rate {
+                rom: rom.into(),
+                status,
+                notes,
+                config,
+            }
+        }
+
+        "ratings" => {
+            let mut config = paths::default_config();
+            while let Some(option) = args.next_option() {
+                match option.as_str() {
+                    "--config" => config = args.value(&option)?.into(),
+                    _ => bail!("Unknown option: {}", option),
+                }
+            }
+            let [command, path] = args.positional()?;
+            let command = match command.as_str() {
+                "export" => RatingsCommand::Export,
+                "import" => RatingsCommand::Import,
+                _ => bail!("Unknown ratings command: {}", command),
+            };
+            Command::Ratings {
+                command,
+                path: path.into(),
+                config,
+            }
+        }
+
+        "help" | "--help" | "-h" => Command::Help,
+
+        // options without a command are the start screen's
+        "--config" | "--watch-dir" => {
+            let mut config = paths::default_config();
+            let mut watch = None;
+            let mut option = Some(command.clone());
+            while let Some(name) = option {
+                match name.as_str() {
+                    "--config" => config = args.value(&name)?.into(),
+                    "--watch-dir" => watch = Some(args.value(&name)?.into()),
+                    _ => bail!("Unknown option: {}", name),
+                }
+                option = args.next_option();
+            }
+            let [] = args.positional()?;
+            Command::Start { config, watch }
+        }
+
+        _ => bail!("Unknown command: {}", command),
+    };
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_memory_patches_and_dumps() {
+        let args = ["run", "game.ch8", "--patch-mem", "0x300=patch.bin", "--dump-mem", "512..0x1000=out.bin"];
+        let run = match parse(args.iter().map(|arg| arg.to_string())).unwrap() {
+            Command::Run(run) => run,
+            _ => panic!("not a run command"),
+        };
+        assert_eq!(run.patch_mem, [(0x300, PathBuf::from("patch.bin"))]);
+        assert_eq!(run.dump_mem, [(0x200..0x1000, PathBuf::from("out.bin"))]);
+
+        assert!(parse_memory_range("0x400..0x200").is_err());
+        assert!(parse_memory_range("0x200..0x1001").is_err());
+        assert!(parse_address("0x1000").is_err());
+        assert!(split_assignment("0x300").is_err());
+    }
+}
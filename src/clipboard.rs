@@ -0,0 +1,34 @@
+use failure::Error;
+use std::process::Command;
+
+/// Commands that print the clipboard, tried in order
+fn commands() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbpaste", &[])]
+    } else if cfg!(windows) {
+        vec![("powershell", &["-NoProfile", "-Command", "Get-Clipboard"])]
+    } else {
+        let mut commands: Vec<(&'static str, &'static [&'static str])> = vec![
+            ("xclip", &["-selection", "clipboard", "-o"]),
+            ("xsel", &["--clipboard", "--output"]),
+        ];
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.insert(0, ("wl-paste", &["--no-newline"]));
+        }
+        commands
+    }
+}
+
+/// Text on the host clipboard, read with the first of the usual command line tools that works.
+/// Frontends that have a clipboard of their own use that instead, see `Frontend::clipboard_text`
+pub fn read() -> Result<String, Error> {
+    for (program, args) in commands() {
+        match Command::new(program).args(args).output() {
+            Ok(output) if output.status.success() => return Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            Ok(output) => log::debug!(target: "input", "{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim()),
+            Err(e) => log::debug!(target: "input", "Could not run {}: {}", program, e),
+        }
+    }
+    let names = commands().iter().map(|(program, _)| *program).collect::<Vec<_>>();
+    bail!("could not read the clipboard, install one of {}", names.join(", "))
+}
@@ -0,0 +1,450 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use crate::timing;
+
+/// Source of time for the run loop, so the schedule can be driven by a fake clock
+pub trait Clock {
+    /// time since the clock was created
+    fn now(&self) -> Duration;
+
+    fn sleep_until(&mut self, time: Duration);
+}
+
+pub struct RealClock {
+    start: Instant,
+    /// how long before a deadline sleeping stops and busy-waiting takes over
+    spin: Duration,
+}
+
+impl RealClock {
+    pub fn new(spin: Duration) -> Self {
+        RealClock {
+            start: Instant::now(),
+            spin,
+        }
+    }
+}
+
+impl Default for RealClock {
+    fn default() -> Self {
+        RealClock::new(Duration::from_secs(0))
+    }
+}
+
+impl Clock for RealClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep_until(&mut self, time: Duration) {
+        let now = self.now();
+        if time > now + self.spin {
+            std::thread::sleep(time - now - self.spin);
+        }
+        // the OS may wake the thread late, spinning keeps the deadline at the cost of a busy core
+        while self.now() < time {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Clock that only moves when told to: sleeping jumps straight to the wake up time
+#[derive(Default)]
+pub struct MockClock {
+    pub now: Duration,
+}
+
+impl MockClock {
+    pub fn advance(&mut self, time: Duration) {
+        self.now += time;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+
+    fn sleep_until(&mut self, time: Duration) {
+        self.now = self.now.max(time);
+    }
+}
+
+/// how far behind a ticker may fall before it skips the missed ticks, e.g. after the window
+/// was dragged
+const MAX_LAG: Duration = Duration::from_millis(250);
+
+/// Periodic event at a fixed rate
+pub struct Ticker {
+    period: Duration,
+    next: Duration,
+}
+
+impl Ticker {
+    pub fn new(hz: f64, start: Duration) -> Self {
+        let period = timing::period_of(hz);
+        Ticker {
+            period,
+            next: start + period,
+        }
+    }
+
+    /// Changes the rate, the next tick is one new period from `now`
+    pub fn set_rate(&mut self, hz: f64, now: Duration) {
+        *self = Ticker::new(hz, now);
+    }
+
+    /// time of the next tick
+    pub fn next(&self) -> Duration {
+        self.next
+    }
+
+    /// time the next `ticks` ticks are due by, at least the next one
+    pub fn after(&self, ticks: u32) -> Duration {
+        self.next + self.period * ticks.saturating_sub(1)
+    }
+
+    /// Counts the next tick from `now`, dropping those missed while the emulation was stopped
+    pub fn resume(&mut self, now: Duration) {
+        self.next = now + self.period;
+    }
+
+    /// Number of ticks that happened up to `now`, which are consumed
+    pub fn due(&mut self, now: Duration) -> u32 {
+        if now < self.next {
+            return 0;
+        }
+
+        if now - self.next > MAX_LAG {
+            self.next = now + self.period;
+            return 1;
+        }
+
+        let count = ((now - self.next).as_nanos() / self.period.as_nanos()) as u32 + 1;
+        self.next += self.period * count;
+        count
+    }
+}
+
+/// Speeds of the run loop
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timing {
+    /// instructions per second
+    pub cpu_hz: f64,
+    /// screen redraws per second, if the monitor's refresh rate is unknown or not used
+    pub refresh_hz: f64,
+    /// Redraw at the refresh rate of the monitor (`Frontend::refresh_rate`), showing emulated
+    /// frames more than once on fast monitors and skipping some on slow ones.
+    /// The timers and everything else counted in frames stay at 60Hz either way
+    pub sync_to_display: bool,
+    /// speed multiplier while the turbo key (Tab) is held
+    pub turbo: f64,
+    /// skip over loops that only wait for the delay timer, see `System::in_delay_wait`
+    pub auto_speed: bool,
+    /// frames each press and each release of a held turbo key lasts
+    pub rapid_fire_frames: u32,
+    /// Instructions run between polls of the window's events, 0 derives it from the speed,
+    /// see `cycles_per_poll`. Input is also polled once per frame and before the program
+    /// reads the keys, whatever this is
+    pub cycles_per_poll: u32,
+    /// seconds the run loop can go without finishing a frame before the watchdog reports it,
+    /// 0 turns the watchdog off
+    pub watchdog_secs: u32,
+    /// seconds of a stall after which the watchdog gives up and exits, 0 never does
+    pub watchdog_exit_secs: u32,
+}
+
+/// polls per second `Timing::cycles_per_poll` aims for when it is left at 0
+const AUTO_POLL_HZ: f64 = 1000.0;
+
+impl Timing {
+    /// Instructions to run between polls at `cpu_hz`: `cycles_per_poll`, or as many as run in
+    /// a millisecond. More costs less CPU on window systems where polling is slow (X forwarding,
+    /// VMs), fewer gets input to the program sooner
+    pub fn cycles_per_poll(&self, cpu_hz: f64) -> u32 {
+        match self.cycles_per_poll {
+            0 => (cpu_hz / AUTO_POLL_HZ).ceil().max(1.0) as u32,
+            cycles => cycles,
+        }
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Timing {
+            cpu_hz: 2400.0,
+            refresh_hz: 60.0,
+            sync_to_display: true,
+            turbo: 4.0,
+            auto_speed: false,
+            rapid_fire_frames: 3,
+            cycles_per_poll: 0,
+            watchdog_secs: 5,
+            watchdog_exit_secs: 60,
+        }
+    }
+}
+
+/// Options that trade timing precision and responsiveness for less CPU usage
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Power {
+    /// Busy-wait this many microseconds before each deadline instead of sleeping. Steadier
+    /// pacing, as sleeps often overshoot, but keeps a core busy. 0 only sleeps
+    pub spin_wait_us: u64,
+    /// run at `idle_cpu_hz` while the program waits for a key, see `System::in_key_wait`
+    pub idle_detection: bool,
+    /// instructions per second while waiting for a key
+    pub idle_cpu_hz: f64,
+    /// don't redraw frames identical to the last one, see `RedrawFilter`
+    pub skip_unchanged_frames: bool,
+    /// what to do while the program is stuck in a loop, see `System::in_halt_loop`
+    pub on_halt: HaltAction,
+}
+
+/// instructions per second of a program stuck in a loop, with `HaltAction::Throttle`
+pub const HALT_CPU_HZ: f64 = 60.0;
+
+/// What the emulator does when the program ends in a loop it can never leave
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HaltAction {
+    /// keep running it at full speed
+    Run,
+    /// run it at `HALT_CPU_HZ` and say the program halted
+    #[default]
+    Throttle,
+    /// break into the debugger, throttle without it
+    Break,
+}
+
+impl Power {
+    pub fn spin_wait(&self) -> Duration {
+        Duration::from_micros(self.spin_wait_us)
+    }
+}
+
+impl Default for Power {
+    fn default() -> Self {
+        Power {
+            spin_wait_us: 0,
+            idle_detection: false,
+            idle_cpu_hz: 120.0,
+            skip_unchanged_frames: false,
+            on_halt: HaltAction::default(),
+        }
+    }
+}
+
+/// What a turn of the run loop has to do, from `Driver::due`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Due {
+    pub instructions: u32,
+    pub timer_ticks: u32,
+    /// 60Hz frames of banners, the heatmap and high scores, which count emulated frames
+    pub emulated_frames: u32,
+    /// a frame is to be presented
+    pub redraw: bool,
+}
+
+/// The schedule of the run loop: instructions, timers and redraws each at their own rate.
+/// It only says what is due and when to wake for the next of them, the `ControlFlow::WaitUntil`
+/// of an event loop, so the timing is the same with every frontend and runs on `MockClock`
+pub struct Driver {
+    cpu: Ticker,
+    cycles_per_poll: u32,
+    timers: Ticker,
+    emulated_frames: Ticker,
+    frames: Ticker,
+}
+
+impl Driver {
+    pub fn new(timing: &Timing, refresh_hz: f64, start: Duration) -> Self {
+        Driver {
+            cpu: Ticker::new(timing.cpu_hz, start),
+            cycles_per_poll: timing.cycles_per_poll(timing.cpu_hz),
+            timers: Ticker::new(f64::from(timing::TIMER_HZ), start),
+            emulated_frames: Ticker::new(f64::from(timing::TIMER_HZ), start),
+            frames: Ticker::new(refresh_hz, start),
+        }
+    }
+
+    /// Counts the instructions and timer ticks from `now`, after the emulation was stopped
+    pub fn resume(&mut self, now: Duration) {
+        self.cpu.resume(now);
+        self.timers.resume(now);
+    }
+
+    pub fn set_cpu_rate(&mut self, timing: &Timing, cpu_hz: f64, now: Duration) {
+        self.cpu.set_rate(cpu_hz, now);
+        self.cycles_per_poll = timing.cycles_per_poll(cpu_hz);
+    }
+
+    /// Runs the timers `speed` times faster than 60Hz, for turbo
+    pub fn set_timer_speed(&mut self, speed: f64, now: Duration) {
+        self.timers.set_rate(f64::from(timing::TIMER_HZ) * speed, now);
+    }
+
+    pub fn set_refresh_rate(&mut self, hz: f64, now: Duration) {
+        self.frames.set_rate(hz, now);
+    }
+
+    /// Everything that happened up to `now`, which is consumed
+    pub fn due(&mut self, now: Duration) -> Due {
+        Due {
+            instructions: self.cpu.due(now),
+            timer_ticks: self.timers.due(now),
+            emulated_frames: self.emulated_frames.due(now),
+            redraw: self.frames.due(now) > 0,
+        }
+    }
+
+    /// When the loop has to run again: after `cycles_per_poll` instructions, or sooner for a
+    /// timer tick or a frame
+    pub fn wake_time(&self) -> Duration {
+        self.cpu
+            .after(self.cycles_per_poll)
+            .min(self.timers.next())
+            .min(self.emulated_frames.next())
+            .min(self.frames.next())
+    }
+}
+
+/// Pausing waits for the end of the current frame, the next timer tick, so the cpu and the
+/// timers never fall out of step however often the game is paused and resumed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Pause {
+    #[default]
+    Running,
+    /// pauses at the end of the frame
+    Requested,
+    Paused,
+}
+
+impl Pause {
+    pub fn toggle(&mut self) {
+        *self = match self {
+            Pause::Running => Pause::Requested,
+            Pause::Requested | Pause::Paused => Pause::Running,
+        };
+    }
+
+    /// Runs one more frame when paused
+    pub fn step(&mut self) {
+        if *self == Pause::Paused {
+            *self = Pause::Requested;
+        }
+    }
+
+    /// Called at the end of every frame
+    pub fn frame_end(&mut self) {
+        if *self == Pause::Requested {
+            *self = Pause::Paused;
+        }
+    }
+
+    pub fn is_paused(self) -> bool {
+        self == Pause::Paused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Totals of what `driver` found due, checked every millisecond for `millis` milliseconds
+    /// of `clock`: instructions, timer ticks, emulated frames and redraws
+    fn run_for(driver: &mut Driver, clock: &mut MockClock, millis: u32) -> (u32, u32, u32, u32) {
+        let mut totals = (0, 0, 0, 0);
+        for _ in 0..millis {
+            clock.advance(Duration::from_millis(1));
+            let due = driver.due(clock.now());
+            totals.0 += due.instructions;
+            totals.1 += due.timer_ticks;
+            totals.2 += due.emulated_frames;
+            totals.3 += u32::from(due.redraw);
+        }
+        totals
+    }
+
+    #[test]
+    fn timers_tick_at_60_hz() {
+        let mut clock = MockClock::default();
+        let mut driver = Driver::new(&Timing::default(), 60.0, clock.now());
+        let mut system = crate::system::System::default();
+        system.timers.delay = 255;
+        system.timers.sound = 100;
+        let mut ticks = 0;
+        for _ in 0..1001 {
+            clock.advance(Duration::from_millis(1));
+            for _ in 0..driver.due(clock.now()).timer_ticks {
+                system.dec_timers();
+                ticks += 1;
+            }
+            assert_eq!(u32::from(255 - system.timers.delay), ticks);
+        }
+        assert_eq!((ticks, system.timers.delay, system.timers.sound), (60, 195, 40));
+    }
+
+    #[test]
+    fn redraws_follow_the_refresh_rate() {
+        for refresh_hz in [30, 60, 144] {
+            let mut clock = MockClock::default();
+            let mut driver = Driver::new(&Timing::default(), f64::from(refresh_hz), clock.now());
+            let (_, timer_ticks, emulated_frames, redraws) = run_for(&mut driver, &mut clock, 1001);
+            assert_eq!(redraws, refresh_hz);
+            // frames are still emulated at 60Hz whatever the monitor does
+            assert_eq!((timer_ticks, emulated_frames), (60, 60));
+        }
+    }
+
+    #[test]
+    fn turbo_scales_the_instructions() {
+        let timing = Timing {
+            cpu_hz: 1000.0,
+            turbo: 4.0,
+            ..Timing::default()
+        };
+        let mut clock = MockClock::default();
+        let mut driver = Driver::new(&timing, 60.0, clock.now());
+        let (instructions, timer_ticks, _, _) = run_for(&mut driver, &mut clock, 1001);
+        assert_eq!((instructions, timer_ticks), (1001, 60));
+
+        // as the run loop does while the turbo key is held
+        driver.set_cpu_rate(&timing, timing.cpu_hz * timing.turbo, clock.now());
+        driver.set_timer_speed(timing.turbo, clock.now());
+        let (instructions, timer_ticks, emulated_frames, redraws) = run_for(&mut driver, &mut clock, 1001);
+        assert_eq!((instructions, timer_ticks), (4004, 240));
+        // the screen and what counts emulated frames keep their rate
+        assert_eq!((emulated_frames, redraws), (60, 60));
+    }
+
+    #[test]
+    fn driver_wakes_for_the_next_deadline() {
+        let timing = Timing {
+            cpu_hz: 500.0,
+            cycles_per_poll: 4,
+            ..Timing::default()
+        };
+        let mut driver = Driver::new(&timing, 50.0, Duration::ZERO);
+        let due = |instructions, timer_ticks, redraw| Due {
+            instructions,
+            timer_ticks,
+            emulated_frames: timer_ticks,
+            redraw,
+        };
+        // polls every 4 instructions, and for the timer tick and the frame in between
+        let expected = [
+            (Duration::from_millis(8), due(4, 0, false)),
+            (Duration::from_millis(16), due(4, 0, false)),
+            (timing::FRAME, due(0, 1, false)),
+            (Duration::from_millis(20), due(2, 0, true)),
+        ];
+        for (time, turn) in expected {
+            assert_eq!(driver.wake_time(), time);
+            assert_eq!(driver.due(time), turn);
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::system::opcode::{self, Opcode};
+use crate::system::PROGRAM_START;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    SuperChip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::SuperChip => write!(f, "SCHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+impl Platform {
+    /// the profile of `quirks::PROFILES` for roms written for it
+    pub fn profile(self) -> &'static str {
+        match self {
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xo-chip",
+        }
+    }
+}
+
+/// Identifies opcodes that belong to CHIP-8 extensions
+pub fn classify(opcode: u16) -> Option<(Platform, &'static str)> {
+    let feature = match opcode {
+        0x00FB => (Platform::SuperChip, "scroll right"),
+        0x00FC => (Platform::SuperChip, "scroll left"),
+        0x00FD => (Platform::SuperChip, "exit interpreter"),
+        0x00FE => (Platform::SuperChip, "low resolution mode"),
+        0x00FF => (Platform::SuperChip, "high resolution mode"),
+        0xF000 => (Platform::XoChip, "long index load"),
+        0xF002 => (Platform::XoChip, "audio pattern"),
+        _ => match opcode & 0xF0FF {
+            0xF030 => (Platform::SuperChip, "large font"),
+            0xF075 | 0xF085 => (Platform::SuperChip, "persistent flag registers"),
+            0xF001 => (Platform::XoChip, "bitplane selection"),
+            0xF03A => (Platform::XoChip, "audio pitch"),
+            _ => match opcode & 0xFFF0 {
+                0x00C0 => (Platform::SuperChip, "scroll down"),
+                0x00D0 => (Platform::XoChip, "scroll up"),
+                _ => match opcode & 0xF00F {
+                    0x5002 | 0x5003 => (Platform::XoChip, "register range save/load"),
+                    0xD000 => (Platform::SuperChip, "16x16 sprite"),
+                    _ => return None,
+                },
+            },
+        },
+    };
+
+    Some(feature)
+}
+
+/// Bytes the instruction takes: 4 for XO-CHIP's `F000 NNNN`, which is followed by its address
+pub fn length(opcode: u16) -> u16 {
+    match opcode {
+        0xF000 => 4,
+        _ => 2,
+    }
+}
+
+/// The extension a rom was written for, from the extension opcodes in the code the control flow
+/// reaches from the program start. Data is not looked at, sprites often read as such opcodes.
+/// XO-CHIP, which includes SCHIP, wins when both are found, None is plain CHIP-8
+pub fn detect(rom: &[u8]) -> Option<Platform> {
+    const SKIPS: [Opcode; 6] = [
+        Opcode::SkipIfEq,
+        Opcode::SkipIfNeq,
+        Opcode::SkipIfRegEq,
+        Opcode::SkipIfRegNeq,
+        Opcode::SkipIfKeyPressed,
+        Opcode::SkipIfKeyNotPressed,
+    ];
+
+    let mut seen = BTreeSet::new();
+    let mut found = None;
+    let mut queue = vec![PROGRAM_START];
+    while let Some(pc) = queue.pop() {
+        if pc < PROGRAM_START || !seen.insert(pc) {
+            continue;
+        }
+        let offset = usize::from(pc - PROGRAM_START);
+        let Some([high, low]) = rom.get(offset..offset + 2) else {
+            continue;
+        };
+        let opcode = u16::from_be_bytes([*high, *low]);
+        let (next, target) = (pc.wrapping_add(2), opcode & 0x0FFF);
+        if let Some((platform, _)) = classify(opcode) {
+            found = found.max(Some(platform));
+            // 00FD exits the interpreter
+            if opcode != 0x00FD {
+                queue.push(pc.wrapping_add(length(opcode)));
+            }
+        } else if Opcode::Jump.matches(opcode) {
+            queue.push(target);
+        } else if Opcode::Call.matches(opcode) {
+            queue.extend([target, next]);
+        } else if SKIPS.iter().any(|skip| skip.matches(opcode)) {
+            queue.extend([next, pc.wrapping_add(4)]);
+        } else if opcode::decode(opcode).is_some() {
+            // where `JP V0, nnn` goes is not known before it runs
+            if !Opcode::Return.matches(opcode) && !Opcode::JumpPlus.matches(opcode) {
+                queue.push(next);
+            }
+        }
+    }
+    found
+}
+
+/// A guess at what an unknown opcode was meant to be, e.g. "8XY8 is not a known instruction,
+/// did the assembler mean 8XY7 (SUBN Vx, Vy)?". None for the opcodes of extensions, which are
+/// no mistake
+pub fn suggestion(opcode: u16) -> Option<String> {
+    if classify(opcode).is_some() {
+        return None;
+    }
+    let nearest = opcode::nearest(opcode);
+    // the opcode with the operands of the closest one in letters
+    let written = nearest
+        .first()?
+        .pattern_name()
+        .chars()
+        .zip(format!("{:04X}", opcode).chars())
+        .map(|(pattern, digit)| if "XYN".contains(pattern) { pattern } else { digit })
+        .collect::<String>();
+    let names = nearest
+        .iter()
+        .map(|info| format!("{} ({})", info.pattern_name(), info.syntax))
+        .collect::<Vec<_>>();
+    Some(format!(
+        "{} is not a known instruction, did the assembler mean {}?",
+        written,
+        names.join(" or ")
+    ))
+}
+
+struct Occurrence {
+    count: u32,
+    first_pc: u16,
+}
+
+fn record(opcodes: &mut BTreeMap<u16, Occurrence>, pc: u16, opcode: u16) {
+    opcodes
+        .entry(opcode)
+        .or_insert(Occurrence { count: 0, first_pc: pc })
+        .count += 1;
+}
+
+/// Collects unknown opcodes encountered while running, and the known ones that interpreters
+/// disagree on
+#[derive(Default)]
+pub struct CompatReport {
+    unknown: BTreeMap<u16, Occurrence>,
+    /// `DRW Vx, Vy, 0`, run as the `zero_height_draw` quirk says
+    zero_height: BTreeMap<u16, Occurrence>,
+    /// `SYS nnn`, run as the `machine_code_call` quirk says
+    machine_code_calls: BTreeMap<u16, Occurrence>,
+}
+
+impl CompatReport {
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        record(&mut self.unknown, pc, opcode);
+    }
+
+    /// Returns true the first time the opcode is seen
+    pub fn record_zero_height(&mut self, pc: u16, opcode: u16) -> bool {
+        let first = !self.zero_height.contains_key(&opcode);
+        record(&mut self.zero_height, pc, opcode);
+        first
+    }
+
+    pub fn record_machine_code_call(&mut self, pc: u16, opcode: u16) {
+        record(&mut self.machine_code_calls, pc, opcode);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unknown.is_empty() && self.zero_height.is_empty() && self.machine_code_calls.is_empty()
+    }
+
+    /// extensions the rom used, according to the opcodes encountered
+    pub fn platforms(&self) -> Vec<Platform> {
+        let mut platforms = self
+            .unknown
+            .keys()
+            .chain(self.zero_height.keys())
+            .filter_map(|opcode| classify(*opcode).map(|(platform, _)| platform))
+            .collect::<Vec<_>>();
+        platforms.sort();
+        platforms.dedup();
+        platforms
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Compatibility report:")?;
+        if self.is_empty() {
+            return writeln!(f, "  no unsupported opcodes encountered");
+        }
+
+        for (opcode, occurrence) in &self.machine_code_calls {
+            writeln!(
+                f,
+                "  {:04X} x{} (first at {:03X}): call to 1802 machine code, run as the `machine_code_call` quirk says",
+                opcode, occurrence.count, occurrence.first_pc
+            )?;
+        }
+        for (opcode, occurrence) in &self.zero_height {
+            writeln!(
+                f,
+                "  {:04X} x{} (first at {:03X}): height 0, a 16x16 sprite on SCHIP, run as the `zero_height_draw` quirk says",
+                opcode, occurrence.count, occurrence.first_pc
+            )?;
+        }
+
+        for (opcode, occurrence) in &self.unknown {
+            write!(
+                f,
+                "  {:04X} x{} (first at {:03X}): ",
+                opcode, occurrence.count, occurrence.first_pc
+            )?;
+            match classify(*opcode) {
+                Some((platform, feature)) => writeln!(f, "{} {}", platform, feature)?,
+                None => match suggestion(*opcode) {
+                    Some(suggestion) => writeln!(f, "unknown, {}", suggestion)?,
+                    None => writeln!(f, "unknown")?,
+                },
+            }
+        }
+
+        let platforms = self.platforms();
+        if !platforms.is_empty() {
+            let names = platforms.iter().map(ToString::to_string).collect::<Vec<_>>();
+            writeln!(f, "The rom requires: {}", names.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_platform_from_reachable_code() {
+        // CLS, JP 206, then data that reads as SCHIP's HIGH
+        let chip8 = [0x00, 0xE0, 0x12, 0x06, 0x00, 0xFF, 0x12, 0x06];
+        assert_eq!(detect(&chip8), None);
+        // HIGH, SCR, JP 204
+        let schip = [0x00, 0xFF, 0x00, 0xFB, 0x12, 0x04];
+        assert_eq!(detect(&schip), Some(Platform::SuperChip));
+        // HIGH, then past the address of I := long 0300 to plane 3
+        let xo_chip = [0x00, 0xFF, 0xF0, 0x00, 0x03, 0x00, 0xF3, 0x01, 0x12, 0x08];
+        assert_eq!(detect(&xo_chip), Some(Platform::XoChip));
+    }
+
+    #[test]
+    fn suggests_the_nearest_instruction() {
+        assert_eq!(
+            suggestion(0x8128).unwrap(),
+            "8XY8 is not a known instruction, did the assembler mean 8XY7 (SUBN Vx, Vy)?"
+        );
+        assert_eq!(
+            suggestion(0xE39F).unwrap(),
+            "EX9F is not a known instruction, did the assembler mean EX9E (SKP Vx)?"
+        );
+        // SCHIP, not a mistake
+        assert_eq!(suggestion(0x00FF), None);
+    }
+}
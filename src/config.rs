@@ -0,0 +1,213 @@
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::audio::AudioConfig;
+use crate::clock::{Power, Timing};
+use crate::compat::Platform;
+use crate::input::{Keymap, KeymapOverride, Player};
+use crate::scores::ScoreRegion;
+use crate::system::fonts::FontConfig;
+use crate::system::quirks::Quirks;
+use crate::system::rewind::RewindConfig;
+use crate::frontend::bezel::BezelConfig;
+use crate::frontend::shader::ShaderConfig;
+use crate::frontend::blend::AntiFlicker;
+use crate::frontend::palette::{PaletteConfig, Tone};
+use crate::frontend::PresentMode;
+
+pub mod watch;
+
+pub const DEFAULT_PATH: &str = "chip8.toml";
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub keymap: Keymap,
+    /// local multiplayer: the hex keys of each player and the device they use
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub players: Vec<Player>,
+    pub display: DisplayConfig,
+    pub quirks: Quirks,
+    /// how the quirks of a rom are chosen when neither the game database nor its container
+    /// has them
+    pub detect_platform: DetectPlatform,
+    /// the glyphs of the hex digits at 0x000
+    pub font: FontConfig,
+    pub timing: Timing,
+    pub power: Power,
+    pub audio: AudioConfig,
+    pub rewind: RewindConfig,
+    /// where `chip8 run <name>` looks for roms given by name, relative to the config file
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rom_dirs: Vec<PathBuf>,
+    /// game database: per-game overrides keyed by the hex `rom::hash` of the rom
+    pub games: BTreeMap<String, GameConfig>,
+}
+
+/// Whether a rom's opcodes decide its quirks, see `compat::detect`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DetectPlatform {
+    /// the profile of the SCHIP or XO-CHIP opcodes the rom uses over `quirks`, `quirks` as
+    /// they are for plain CHIP-8
+    #[default]
+    Auto,
+    /// always `quirks`
+    Off,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    pub anti_flicker: AntiFlicker,
+    pub palette: PaletteConfig,
+    /// brightness and gamma for the monitor, `PageUp`/`PageDown` and `Home`/`End` change them
+    pub tone: Tone,
+    /// window size in screen pixels per chip8 pixel, 800x400 if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<u32>,
+    /// scale the screen by whole multiples only, with a border around it
+    pub integer_scaling: bool,
+    /// pixel width to height as `1:1.2`, square if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_aspect: Option<String>,
+    /// start with the keypad overlay shown
+    pub show_keypad: bool,
+    /// vsync, mailbox or immediate presentation of frames
+    pub present_mode: PresentMode,
+    /// image drawn around or over the screen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bezel: Option<BezelConfig>,
+    /// GLSL files to draw the screen with, the built-in shaders if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shader: Option<ShaderConfig>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anti_flicker: Option<AntiFlicker>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<PaletteConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<Quirks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font: Option<FontConfig>,
+    /// memory holding the score, enables high score tracking for the game
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<ScoreRegion>,
+    /// WAV file for the beep, overrides `audio.sample`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample: Option<PathBuf>,
+    /// overrides `display.bezel`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bezel: Option<BezelConfig>,
+    /// overrides `display.pixel_aspect`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_aspect: Option<String>,
+    /// bindings that replace those of `[keymap]` for the game
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<KeymapOverride>,
+}
+
+impl Config {
+    /// Loads the config, falling back to the defaults if the file does not exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(src) => Ok(toml::from_str(&src)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn game(&self, rom_hash: u64) -> Option<&GameConfig> {
+        self.games.get(&format!("{:016x}", rom_hash))
+    }
+
+    /// display settings with the overrides of the game applied
+    pub fn display_for(&self, rom_hash: u64) -> DisplayConfig {
+        let mut display = self.display.clone();
+        if let Some(game) = self.game(rom_hash) {
+            if let Some(anti_flicker) = game.anti_flicker {
+                display.anti_flicker = anti_flicker;
+            }
+            if let Some(palette) = &game.palette {
+                display.palette = palette.clone();
+            }
+            if let Some(bezel) = &game.bezel {
+                display.bezel = Some(bezel.clone());
+            }
+            if let Some(pixel_aspect) = &game.pixel_aspect {
+                display.pixel_aspect = Some(pixel_aspect.clone());
+            }
+        }
+
+        display
+    }
+
+    /// the keymap with the game's bindings applied
+    pub fn keymap_for(&self, rom_hash: u64) -> Keymap {
+        let mut keymap = self.keymap.clone();
+        if let Some(game) = self.game(rom_hash).and_then(|game| game.keymap.as_ref()) {
+            keymap.apply(game);
+        }
+        keymap
+    }
+
+    /// quirks of the game, falling back to the global ones
+    pub fn quirks_for(&self, rom_hash: u64) -> Quirks {
+        self.game(rom_hash)
+            .and_then(|game| game.quirks)
+            .unwrap_or(self.quirks)
+    }
+
+    /// quirks of the game, falling back to those of its platform when it is detected, then to
+    /// the global ones
+    pub fn quirks_on(&self, rom_hash: u64, platform: Option<Platform>) -> Quirks {
+        self.game(rom_hash)
+            .and_then(|game| game.quirks)
+            .or_else(|| self.detected_quirks(platform))
+            .unwrap_or(self.quirks)
+    }
+
+    /// `quirks` with the profile of `platform`, if it is to be detected
+    pub fn detected_quirks(&self, platform: Option<Platform>) -> Option<Quirks> {
+        match self.detect_platform {
+            DetectPlatform::Auto => self.quirks.with_profile(platform?.profile()),
+            DetectPlatform::Off => None,
+        }
+    }
+
+    /// font of the game, falling back to the global one
+    pub fn font_for(&self, rom_hash: u64) -> &FontConfig {
+        self.game(rom_hash)
+            .and_then(|game| game.font.as_ref())
+            .unwrap_or(&self.font)
+    }
+
+    /// beep sample of the game, falling back to the global one
+    pub fn sample_for(&self, rom_hash: u64) -> Option<&PathBuf> {
+        self.game(rom_hash)
+            .and_then(|game| game.sample.as_ref())
+            .or(self.audio.sample.as_ref())
+    }
+
+    /// Writes the config, creating the directory it goes in
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        if let Some(dir) = path.as_ref().parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        // going through `Value` puts tables after plain values, which toml requires
+        Ok(toml::to_string(&toml::Value::try_from(self)?)?)
+    }
+}
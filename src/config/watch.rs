@@ -0,0 +1,121 @@
+use failure::Error;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::Config;
+use crate::audio::AudioConfig;
+
+/// how often the modification time of the file is checked
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sections of the config that differ between two versions of the file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Changes {
+    /// the keymap or the players
+    pub keys: bool,
+    pub display: bool,
+    pub quirks: bool,
+    pub font: bool,
+    pub timing: bool,
+    pub power: bool,
+    pub audio: bool,
+    pub rewind: bool,
+    /// settings that only take effect when the emulator is started again, by name
+    pub restart: Vec<&'static str>,
+}
+
+impl Changes {
+    pub fn between(old: &Config, new: &Config) -> Self {
+        // a game's entry can override any of the sections it has settings for
+        let games = differ(&old.games, &new.games);
+
+        let mut restart = Vec::new();
+        if old.display.scale != new.display.scale {
+            restart.push("window scale");
+        }
+        if old.display.present_mode != new.display.present_mode {
+            restart.push("present mode");
+        }
+        // the beep sample and length are the only audio settings the sound device is not
+        // opened with
+        let device = |audio: &AudioConfig| AudioConfig {
+            sample: None,
+            min_beep_ms: 0,
+            ..audio.clone()
+        };
+        if differ(&device(&old.audio), &device(&new.audio)) {
+            restart.push("audio");
+        }
+        if old.power.spin_wait_us != new.power.spin_wait_us {
+            restart.push("spin wait");
+        }
+
+        Changes {
+            keys: games || differ(&old.keymap, &new.keymap) || differ(&old.players, &new.players),
+            display: games || differ(&old.display, &new.display),
+            quirks: games || differ(&old.quirks, &new.quirks) || old.detect_platform != new.detect_platform,
+            font: games || differ(&old.font, &new.font),
+            timing: differ(&old.timing, &new.timing),
+            power: differ(&old.power, &new.power),
+            audio: games || differ(&old.audio, &new.audio),
+            rewind: differ(&old.rewind, &new.rewind),
+            restart,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        *self != Changes::default()
+    }
+}
+
+fn differ<T: Serialize>(a: &T, b: &T) -> bool {
+    toml::Value::try_from(a).ok() != toml::Value::try_from(b).ok()
+}
+
+/// Notices when the config file is written to and loads it again
+pub struct ConfigWatcher {
+    path: PathBuf,
+    /// the file as it was last loaded, changes are relative to it rather than to the config in
+    /// use, which roms and the command line override
+    loaded: Config,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf, loaded: Config) -> Self {
+        let modified = modified(&path);
+        ConfigWatcher {
+            path,
+            loaded,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// The new config and what changed in it, if the file was modified since the last call.
+    /// A file that fails to load is reported once, until it is modified again
+    pub fn poll(&mut self) -> Option<Result<(Config, Changes), Error>> {
+        if self.last_check.elapsed() < INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        Some(Config::load(&self.path).map(|config| {
+            let changes = Changes::between(&self.loaded, &config);
+            self.loaded = config.clone();
+            (config, changes)
+        }))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
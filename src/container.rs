@@ -0,0 +1,228 @@
+use failure::Error;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+use crate::frontend::palette::{Color, PaletteConfig};
+use crate::system::quirks::Quirks;
+
+/// extensions of files read as containers instead of raw roms
+pub const EXTENSIONS: &[&str] = &["c8x", "json"];
+
+/// extension of Octo source files, which are assembled when loaded
+pub const OCTO_EXTENSION: &str = "8o";
+
+/// Settings that come with a rom in a container, all optional
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// e.g. `chip8`, `schip` or `xo-chip`
+    pub platform: Option<String>,
+    pub quirks: Option<Quirks>,
+    /// [background, foreground] or four colors, as in the `palette` config setting
+    pub colors: Option<Vec<Color>>,
+    /// instructions per second
+    pub cpu_hz: Option<f64>,
+    /// Octo's run options, used for whatever is not set above
+    pub options: Option<OctoOptions>,
+}
+
+/// The subset of Octo's cartridge options that maps onto this emulator's settings
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OctoOptions {
+    /// instructions per 60 Hz frame
+    pub tickrate: Option<f64>,
+    pub background_color: Option<Color>,
+    pub fill_color: Option<Color>,
+    pub fill_color2: Option<Color>,
+    pub blend_color: Option<Color>,
+    /// sprites are clipped at the screen edges instead of wrapping
+    pub clip_quirks: Option<bool>,
+}
+
+impl Metadata {
+    pub fn quirks(&self) -> Option<Quirks> {
+        self.quirks.or_else(|| {
+            let clip = self.options.as_ref()?.clip_quirks?;
+            Some(Quirks {
+                wrap_sprites: !clip,
+                ..Quirks::default()
+            })
+        })
+    }
+
+    pub fn palette(&self) -> Option<PaletteConfig> {
+        if let Some(colors) = &self.colors {
+            return Some(PaletteConfig::Custom(colors.clone()));
+        }
+
+        let options = self.options.as_ref()?;
+        let background = options.background_color?;
+        let fill = options.fill_color?;
+        let colors = match (options.fill_color2, options.blend_color) {
+            (Some(fill2), Some(blend)) => vec![background, fill, fill2, blend],
+            _ => vec![background, fill],
+        };
+        Some(PaletteConfig::Custom(colors))
+    }
+
+    pub fn cpu_hz(&self) -> Option<f64> {
+        self.cpu_hz
+            .or_else(|| Some(self.options.as_ref()?.tickrate? * f64::from(crate::timing::TIMER_HZ)))
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields = [
+            ("Title", &self.title),
+            ("Author", &self.author),
+            ("Description", &self.description),
+            ("Platform", &self.platform),
+        ];
+        for (name, value) in &fields {
+            if let Some(value) = value {
+                writeln!(f, "{}: {}", name, value)?;
+            }
+        }
+
+        if let Some(quirks) = self.quirks() {
+            writeln!(f, "Quirks: wrap_sprites = {}, stack_size = {}", quirks.wrap_sprites, quirks.stack_size)?;
+        }
+        if let Some(PaletteConfig::Custom(colors)) = self.palette() {
+            let colors = colors.iter().map(|color| String::from(*color)).collect::<Vec<_>>();
+            writeln!(f, "Colors: {}", colors.join(", "))?;
+        }
+        if let Some(cpu_hz) = self.cpu_hz() {
+            writeln!(f, "Speed: {} instructions per second", cpu_hz)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The program of a container: a list of bytes, or a hex string that may contain whitespace
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RomData {
+    Bytes(Vec<u8>),
+    Hex(String),
+}
+
+impl RomData {
+    fn into_bytes(self) -> Result<Vec<u8>, Error> {
+        let hex = match self {
+            RomData::Bytes(bytes) => return Ok(bytes),
+            RomData::Hex(hex) => hex.chars().filter(|c| !c.is_whitespace()).collect::<String>(),
+        };
+        if !hex.len().is_multiple_of(2) {
+            bail!("Rom hex data has an odd number of digits");
+        }
+
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| {
+                u8::from_str_radix(hex.get(i..i + 2).unwrap_or_default(), 16)
+                    .map_err(|_| format_err!("Invalid hex digits in rom data at {}", i))
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct Json {
+    #[serde(flatten)]
+    metadata: Metadata,
+    rom: RomData,
+}
+
+/// A rom with the metadata it was distributed with
+pub struct Container {
+    pub metadata: Metadata,
+    pub rom: Vec<u8>,
+}
+
+impl Container {
+    /// Parses a JSON container: the metadata fields and `rom`, e.g.
+    /// `{"title": "Pong", "options": {"tickrate": 20}, "rom": "6A02 6B0C ..."}`
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let json: Json = serde_json::from_str(text)?;
+        Ok(Container {
+            metadata: json.metadata,
+            rom: json.rom.into_bytes()?,
+        })
+    }
+
+    /// A raw rom, with the settings of its metadata block if it ends in one. The block is
+    /// not part of the rom that is loaded, see `rom::split_metadata`
+    pub fn from_rom(data: Vec<u8>) -> Result<Self, Error> {
+        let (rom, text) = crate::rom::split_metadata(&data);
+        let metadata = match text {
+            Some(text) => toml::from_str(text).map_err(|e| format_err!("Invalid metadata block: {}", e))?,
+            None => return Ok(Container { metadata: Metadata::default(), rom: data }),
+        };
+        Ok(Container {
+            metadata,
+            rom: rom.to_vec(),
+        })
+    }
+}
+
+/// Reads a rom, as a container if it has one of the container `EXTENSIONS`, assembling it
+/// if it is Octo source and as a raw rom without metadata otherwise
+pub fn load(path: &Path) -> Result<Container, Error> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    if extension == OCTO_EXTENSION {
+        #[cfg(not(feature = "assembler"))]
+        bail!("{}: Octo sources need the assembler, rebuild with `--features assembler`", path.display());
+        #[cfg(feature = "assembler")]
+        {
+            let src = std::fs::read_to_string(path)?;
+            let rom = crate::asm::octo::assemble(&src)
+                .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+            Ok(Container {
+                metadata: Metadata::default(),
+                rom,
+            })
+        }
+    } else if EXTENSIONS.contains(&extension.as_str()) {
+        Container::parse(&std::fs::read_to_string(path)?)
+            .map_err(|e| format_err!("Invalid rom container {}: {}", path.display(), e))
+    } else {
+        Container::from_rom(std::fs::read(path)?).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+}
+
+#[cfg(all(test, feature = "assembler"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_metadata_block_of_assembled_roms() {
+        let rom = crate::asm::assemble(
+            "META title = \"Loop: forever\"\n\
+             META cpu_hz = 1000\n\
+             META quirks.wrap_sprites = true\n\
+             loop: JP loop\n",
+        )
+        .unwrap();
+        let container = Container::from_rom(rom).unwrap();
+        assert_eq!(container.rom, [0x12, 0x00]);
+        assert_eq!(container.metadata.title.as_deref(), Some("Loop: forever"));
+        assert_eq!(container.metadata.cpu_hz(), Some(1000.0));
+        assert!(container.metadata.quirks().unwrap().wrap_sprites);
+
+        let plain = Container::from_rom(vec![0x12, 0x00]).unwrap();
+        assert_eq!(plain.rom, [0x12, 0x00]);
+        assert!(plain.metadata.title.is_none());
+    }
+}
@@ -0,0 +1,111 @@
+use failure::Error;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::Config;
+use crate::disasm;
+use crate::system::System;
+
+/// instructions kept for a crash dump
+pub const TRACE_LEN: usize = 1000;
+
+/// message of the last panic, set by the hook from `install_panic_hook`
+static PANIC_MESSAGE: Mutex<Option<String>> = Mutex::new(None);
+
+/// Remembers the message of every panic for `take_panic_message`, then reports it as before
+pub fn install_panic_hook() {
+    let default = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        *PANIC_MESSAGE.lock().unwrap_or_else(|e| e.into_inner()) = Some(info.to_string());
+        default(info);
+    }));
+}
+
+pub fn take_panic_message() -> Option<String> {
+    PANIC_MESSAGE.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// The last `TRACE_LEN` executed instructions as (address, opcode)
+#[derive(Default)]
+pub struct RecentInstructions {
+    entries: VecDeque<(u16, u16)>,
+    /// instructions recorded since the last clear
+    pub count: u64,
+}
+
+impl RecentInstructions {
+    /// Records the instruction the system is about to execute
+    pub fn record(&mut self, system: &System) {
+        let pc = system.registers.pc;
+        if self.entries.len() == TRACE_LEN {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((pc, system.read_mem_pair(pc).unwrap_or(0)));
+        self.count += 1;
+    }
+
+    /// the instruction recorded last, the one that failed if the system stopped with an error
+    pub fn last(&self) -> Option<(u16, u16)> {
+        self.entries.back().copied()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.count = 0;
+    }
+}
+
+/// Everything needed to reproduce a crash, written as JSON to attach to bug reports
+#[derive(Serialize)]
+pub struct CrashDump {
+    /// seconds since the Unix epoch
+    pub time: u64,
+    /// the error or panic message
+    pub reason: String,
+    /// hex `rom::hash` of the rom
+    pub rom_hash: String,
+    /// `System::save_state` as hex, loadable into a save slot
+    pub state: String,
+    /// oldest first, as "addr: opcode  mnemonic"
+    pub trace: Vec<String>,
+    /// the calls on the stack, see `disasm::backtrace`
+    pub stack: Vec<String>,
+    /// the config the rom ran with, as TOML
+    pub config: String,
+}
+
+impl CrashDump {
+    pub fn new(reason: &str, system: &System, rom_hash: u64, recent: &RecentInstructions, config: &Config) -> Self {
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let state = system.save_state().iter().map(|byte| format!("{:02x}", byte)).collect();
+        let trace = recent
+            .entries
+            .iter()
+            .map(|&(pc, opcode)| format!("{:03X}: {:04X}  {}", pc, opcode, disasm::disassemble(opcode)))
+            .collect();
+        let config = config
+            .to_toml()
+            .unwrap_or_else(|e| format!("# the config could not be serialized: {}", e));
+
+        CrashDump {
+            time,
+            reason: reason.to_string(),
+            rom_hash: format!("{:016x}", rom_hash),
+            state,
+            trace,
+            stack: disasm::backtrace(system),
+            config,
+        }
+    }
+
+    /// Writes `crash-<time>.json` to `dir`, creating it if needed, and returns its path
+    pub fn write(&self, dir: &Path) -> Result<PathBuf, Error> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("crash-{}.json", self.time));
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,820 @@
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::regions::{MemoryMap, Region};
+use crate::frontend::Input;
+use crate::overlay::Rect;
+use crate::system::machine::Chip8Core;
+use crate::system::quirks::{self, Quirks};
+use crate::{analysis, disasm, input, overlay, system, Chip8};
+
+impl<C: Chip8Core> Chip8<C> {
+    /// Shows the debugger in a window of its own, or over the game if the frontend cannot open
+    /// a second window, or hides it
+    fn toggle_debugger(&mut self) {
+        use overlay::debugger::Placement;
+
+        match self.debugger.take() {
+            Some(Placement::Window) => self.window.close_debug_window(),
+            Some(Placement::Overlay) => {}
+            None => {
+                self.debugger = match self.window.open_debug_window() {
+                    Ok(true) => Some(Placement::Window),
+                    Ok(false) => Some(Placement::Overlay),
+                    Err(e) => {
+                        log::warn!(target: "display", "Showing the debugger over the game, its window did not open: {}", e);
+                        Some(Placement::Overlay)
+                    }
+                };
+            }
+        }
+    }
+
+    /// Shows or hides the debugger view together with the console that takes its commands in
+    /// the game window. The game is paused while they are open. In the terminal frontend,
+    /// which draws no overlay, `chip8 debug` takes the commands instead
+    pub(super) fn toggle_window_debugger(&mut self) {
+        self.toggle_debugger();
+        if self.debugger.is_none() || self.terminal_debugger || !self.window.draws_overlay() {
+            self.window_debugger = None;
+            return;
+        }
+        let mut console = overlay::console::Console::default();
+        console.print("Debugger: s steps, c continues, help lists commands");
+        let (session, restored) = self.restore_session(CONSOLE_HELP, false);
+        if let Some(restored) = restored {
+            console.print(restored);
+        }
+        self.window_debugger = Some(WindowDebugger {
+            console,
+            session,
+            running: None,
+            show_screen: false,
+        });
+    }
+
+    /// Runs a command typed into the console, printing what it prints there
+    fn window_debug_command(&mut self, line: &str) {
+        let mut debugger = match self.window_debugger.take() {
+            Some(debugger) => debugger,
+            None => return,
+        };
+        if line.trim() == "show" {
+            debugger.show_screen = !debugger.show_screen;
+            self.window_debugger = Some(debugger);
+            return;
+        }
+
+        let mut out = Vec::new();
+        match self.debug_command(&mut debugger.session, line, &mut out) {
+            Ok(DebugAction::Prompt) => {}
+            Ok(DebugAction::Run(until)) => debugger.running = Some(until),
+            Ok(DebugAction::Quit) => {
+                self.window_debugger = Some(debugger);
+                self.toggle_window_debugger();
+                return;
+            }
+            Err(e) => out.push(format!("Error: {}", e)),
+        }
+        for line in out {
+            debugger.console.print(line);
+        }
+        self.window_debugger = Some(debugger);
+    }
+
+    /// Continues the program for the instructions and timer ticks that are due, at the speed
+    /// of the game, until the debugger stops it
+    pub(super) fn window_debugger_run(&mut self, instructions: u32, timer_ticks: u32) {
+        let mut debugger = match self.window_debugger.take() {
+            Some(debugger) => debugger,
+            None => return,
+        };
+        if let Some(until) = debugger.running {
+            let mut out = Vec::new();
+            let stopped = self.debug_continue(&mut debugger.session, until, u64::from(instructions), false, &mut out);
+            if !debugger.session.timers_frozen {
+                for _ in 0..timer_ticks {
+                    if self.core.tick_timers() {
+                        log::info!(target: "audio", "Beep!");
+                    }
+                }
+            }
+            for line in out {
+                debugger.console.print(line);
+            }
+            if stopped {
+                debugger.running = None;
+            }
+        }
+        self.window_debugger = Some(debugger);
+    }
+
+    /// Steps through the program with commands read from stdin. Every executed instruction
+    /// is recorded, so execution can also go backwards
+    pub fn run_debug(&mut self) -> Result<(), Error> {
+        // the terminal takes the commands, F1 only shows and hides the debugger view
+        self.terminal_debugger = true;
+        self.window_debugger = None;
+        // the loop waits on the terminal
+        self.disarm_watchdog();
+        let (mut session, restored) = self.restore_session(DEBUG_HELP, std::io::stdout().is_terminal());
+        println!("{}", DEBUG_HELP);
+        if let Some(restored) = restored {
+            println!("{}", restored);
+        }
+
+        loop {
+            println!("{}", self.core.state().registers);
+            println!("{}", session.timers(self.core.state()));
+            self.draw()?;
+
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let mut out = Vec::new();
+            let action = self.debug_command(&mut session, &line, &mut out)?;
+            print_lines(&mut out);
+
+            match action {
+                DebugAction::Prompt => {}
+                DebugAction::Run(until) => loop {
+                    let timers = !session.timers_frozen;
+                    let stopped = self.debug_continue(&mut session, until, 1000, timers, &mut out);
+                    print_lines(&mut out);
+                    if stopped {
+                        break;
+                    }
+                    // keeps the window responsive and lets the program see key presses
+                    if self.poll_events()? {
+                        return Ok(());
+                    }
+                },
+                DebugAction::Quit => return Ok(()),
+            }
+        }
+    }
+
+    /// The session file of the loaded rom, in its directory of the data directory
+    fn session_path(&self) -> PathBuf {
+        self.paths.data.join(format!("{:016x}", self.rom_hash)).join(SESSION_FILE)
+    }
+
+    /// A new debugger session with the breakpoints and settings the last one on this rom left,
+    /// and a line saying what came back if anything did
+    fn restore_session(&self, help: &'static str, colors: bool) -> (DebugSession, Option<String>) {
+        let mut session = DebugSession::new(self.core.state(), help, colors);
+        let restored = SavedSession::load(&self.session_path()).and_then(|saved| saved.apply(&mut session));
+        (session, restored)
+    }
+
+    /// Runs one debugger command, writing what it prints to `out`. Changes to the breakpoints
+    /// and settings are saved to the rom's session file
+    fn debug_command(&mut self, session: &mut DebugSession, line: &str, out: &mut Vec<String>) -> Result<DebugAction, Error> {
+        let saved = SavedSession::of(session);
+        let action = self.run_debug_command(session, line, out)?;
+        let changed = SavedSession::of(session);
+        if changed != saved {
+            if let Err(e) = changed.save(&self.session_path()) {
+                log::warn!("Could not save the debugger session: {}", e);
+            }
+        }
+        Ok(action)
+    }
+
+    fn run_debug_command(&mut self, session: &mut DebugSession, line: &str, out: &mut Vec<String>) -> Result<DebugAction, Error> {
+        let mut words = line.split_whitespace();
+
+        match words.next().unwrap_or("step") {
+            "s" | "step" => {
+                let call = self
+                    .core
+                    .state()
+                    .fetch_instruction()
+                    .is_ok_and(|opcode| system::opcode::Opcode::Call.matches(opcode));
+                let timers = !session.timers_frozen;
+                // every step is traced, with or without `--trace`
+                let tracing = self.trace.is_enabled();
+                self.trace.set_enabled(true);
+                let res = self.debug_step(session, timers);
+                self.trace.set_enabled(tracing);
+                if let Err(e) = res {
+                    out.push(format!("Error: {}", e));
+                } else if call {
+                    let entry = self.core.state().registers.pc;
+                    if let Some(usage) = session.routines.get(&entry) {
+                        out.push(format!("Entering {}: {}", analysis::routine_name(entry), usage));
+                    }
+                }
+            }
+
+            "c" | "continue" => return Ok(DebugAction::Run(RunUntil::Breakpoint)),
+
+            "nd" | "next-draw" => return Ok(DebugAction::Run(RunUntil::Draw)),
+
+            "nx" | "next-diff" => return Ok(DebugAction::Run(RunUntil::ScreenChange)),
+
+            "rs" | "reverse-step" => match session.trace.step_back(self.core.state_mut())? {
+                Some((pc, opcode)) => out.push(format!("Undid {:03X}: {}", pc, disasm::disassemble(opcode))),
+                None => out.push("Reached the start of the trace".to_string()),
+            },
+
+            "rc" | "reverse-continue" => loop {
+                if session.trace.step_back(self.core.state_mut())?.is_none() {
+                    out.push("Reached the start of the trace".to_string());
+                    break;
+                }
+                if let Some(hit) = session.breakpoints.hit(self.core.state()) {
+                    out.push(hit);
+                    break;
+                }
+            },
+
+            "b" | "break" => match words.next().map(|addr| u16::from_str_radix(addr, 16)) {
+                Some(Ok(addr)) => {
+                    if session.breakpoints.addresses.insert(addr) {
+                        out.push(format!("Breakpoint set at {:03X}", addr));
+                    } else {
+                        session.breakpoints.addresses.remove(&addr);
+                        out.push(format!("Breakpoint removed at {:03X}", addr));
+                    }
+                }
+                _ => out.push("Usage: break <hex address>".to_string()),
+            },
+
+            "bo" | "break-op" => match words.next() {
+                Some(name) => {
+                    let opcodes = Breakpoints::parse_opcodes(name);
+                    if opcodes.is_empty() {
+                        out.push(format!("No instruction is written {}, see `chip8 opcodes`", name));
+                    }
+                    let breakpoints = &mut session.breakpoints;
+                    for opcode in opcodes {
+                        let info = opcode.info();
+                        if breakpoints.opcodes.contains(&opcode) {
+                            breakpoints.opcodes.retain(|set| *set != opcode);
+                            out.push(format!("Breakpoint removed on {} ({})", info.pattern_name(), info.syntax));
+                        } else {
+                            breakpoints.opcodes.push(opcode);
+                            out.push(format!("Breakpoint set on {} ({})", info.pattern_name(), info.syntax));
+                        }
+                    }
+                }
+                None => out.push("Usage: break-op <pattern like DXYN, or mnemonic like DRW>".to_string()),
+            },
+
+            "timer" => match (words.next(), words.next().map(|value| value.parse::<u8>())) {
+                (Some("delay"), Some(Ok(value))) => self.core.state_mut().timers.delay = value,
+                (Some("sound"), Some(Ok(value))) => self.core.state_mut().timers.sound = value,
+                (Some("freeze"), None) => {
+                    session.timers_frozen = !session.timers_frozen;
+                    out.push(format!("Timers {}", if session.timers_frozen { "frozen" } else { "running" }));
+                }
+                _ => out.push("Usage: timer delay <n> | timer sound <n> | timer freeze".to_string()),
+            },
+
+            "bt" | "backtrace" => {
+                let frames = disasm::backtrace(self.core.state());
+                if frames.is_empty() {
+                    out.push("The stack is empty".to_string());
+                }
+                out.extend(frames.iter().map(ToString::to_string));
+            }
+
+            "x" | "hexdump" => {
+                let system = self.core.state();
+                let start = words.next().map(|addr| u16::from_str_radix(addr, 16));
+                let len = words.next().map(str::parse::<u16>);
+                match (start.unwrap_or(Ok(system.registers.index)), len.unwrap_or(Ok(64))) {
+                    (Ok(start), Ok(len)) => out.extend(hexdump(system, &session.memory, start, len, session.colors)),
+                    _ => out.push("Usage: hexdump [hex address] [length]".to_string()),
+                }
+            }
+
+            "poke" => {
+                let addr = words.next().map(|addr| u16::from_str_radix(addr, 16));
+                let bytes = words.map(|byte| u8::from_str_radix(byte, 16)).collect::<Result<Vec<_>, _>>();
+                match (addr, bytes) {
+                    (Some(Ok(addr)), Ok(bytes)) if !bytes.is_empty() => {
+                        let index = self.core.state_mut().patch(addr, &bytes)?;
+                        out.push(format!("Patch {} written at {:03X}", index, addr));
+                    }
+                    _ => out.push("Usage: poke <hex address> <hex byte>...".to_string()),
+                }
+            }
+
+            "patches" => {
+                let patches = self.core.state().patches.list();
+                if patches.is_empty() {
+                    out.push("No patches, write one with poke".to_string());
+                }
+                for (index, patch) in patches.iter().enumerate() {
+                    let bytes = patch.bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>();
+                    let state = if patch.enabled { "on" } else { "off" };
+                    out.push(format!("{:>3}  {:03X}  {:<3}  {}", index, patch.addr, state, bytes.join(" ")));
+                }
+            }
+
+            "patch" => match (words.next(), words.next()) {
+                (Some("export"), Some(path)) => {
+                    std::fs::write(path, self.core.state().patches.to_ips()?)?;
+                    out.push(format!("Patches written to {}", path));
+                }
+                (Some("import"), Some(path)) => {
+                    let records = system::patch::parse_ips(&std::fs::read(path)?)?;
+                    for (addr, bytes) in &records {
+                        self.core.state_mut().patch(*addr, bytes)?;
+                    }
+                    out.push(format!("{} patches read from {}", records.len(), path));
+                }
+                (Some(index), None) => match index.parse().ok().and_then(|index| self.core.state_mut().toggle_patch(index)) {
+                    Some(enabled) => out.push(format!("Patch {} {}", index, if enabled { "on" } else { "off" })),
+                    None => out.push(format!("No patch {}, see `patches`", index)),
+                },
+                _ => out.push("Usage: patch <n> | patch export <file.ips> | patch import <file.ips>".to_string()),
+            },
+
+            "panel" => match words.next() {
+                Some(name) => match self.debug_panels.toggle(name) {
+                    Some(shown) => out.push(format!("{} panel {}", name, if shown { "shown" } else { "hidden" })),
+                    None => out.push(format!("No panel is called {}, try {}", name, overlay::debugger::Panels::NAMES.join(", "))),
+                },
+                None => out.push(format!("Usage: panel <{}>", overlay::debugger::Panels::NAMES.join("|"))),
+            },
+
+            "quirks" => {
+                let current = self.core.state().quirks;
+                let quirks = match (words.next(), words.next()) {
+                    (None, _) => None,
+                    (Some(profile), None) => {
+                        let quirks = current.with_profile(profile);
+                        if quirks.is_none() {
+                            out.push(format!("No profile is called {}, try {}", profile, quirks::PROFILES.join(", ")));
+                        }
+                        quirks
+                    }
+                    (Some(name), Some(value)) => Some(set_quirk(current, name, value)?),
+                };
+                if let Some(quirks) = quirks {
+                    for kept in self.apply_quirks(quirks) {
+                        out.push(format!("Kept the {}, load the rom again to change it", kept));
+                    }
+                }
+                out.extend(toml::to_string(&self.core.state().quirks)?.lines().map(str::to_string));
+            }
+
+            "show" => {
+                let mut chars = words.filter_map(|word| word.chars().next());
+                let on = chars.next().unwrap_or('#');
+                let off = chars.next().unwrap_or('.');
+                out.extend(self.core.state().screen_ascii(on, off).lines().map(str::to_string));
+            }
+
+            "q" | "quit" => return Ok(DebugAction::Quit),
+
+            _ => out.extend(session.help.lines().map(str::to_string)),
+        }
+
+        Ok(DebugAction::Prompt)
+    }
+
+    /// Runs until a breakpoint, an error or what `until` asks for, at most `limit` instructions.
+    /// Returns true if it stopped before the limit. `timers` ticks them with every instruction
+    fn debug_continue(
+        &mut self,
+        session: &mut DebugSession,
+        until: RunUntil,
+        limit: u64,
+        timers: bool,
+        out: &mut Vec<String>,
+    ) -> bool {
+        for _ in 0..limit {
+            let pc = self.core.state().registers.pc;
+            let draw = self
+                .core
+                .state()
+                .fetch_instruction()
+                .is_ok_and(|opcode| system::opcode::Opcode::Draw.matches(opcode));
+            let screen = self.core.state().screen;
+
+            if let Err(e) = self.debug_step(session, timers) {
+                out.push(format!("Error: {}", e));
+                return true;
+            }
+            match until {
+                RunUntil::Draw if draw => {
+                    out.push(format!("Drew at {:03X}", pc));
+                    return true;
+                }
+                RunUntil::ScreenChange if self.core.state().screen != screen => {
+                    out.push(format!("Screen changed at {:03X}", pc));
+                    return true;
+                }
+                _ => {}
+            }
+            if let Some(hit) = session.breakpoints.hit(self.core.state()) {
+                out.push(hit);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// executes one instruction, and a timer tick if `timers`, recording it in the trace and
+    /// the sprite it draws in the memory map
+    fn debug_step(&mut self, session: &mut DebugSession, timers: bool) -> Result<(), system::SystemError> {
+        let system = self.core.state();
+        let draw = system.fetch_instruction().ok().filter(|&opcode| system::opcode::Opcode::Draw.matches(opcode));
+        if let Some(opcode) = draw {
+            let height = match opcode & 0xF {
+                0 => system.quirks.zero_height_draw.rows(),
+                height => height as u8,
+            };
+            session.memory.add_sprite(system.registers.index, u16::from(height));
+        }
+
+        let debug = &mut self.trace;
+        let beep = session.trace.record(&mut self.core, |core| {
+            core.step(debug)?;
+            Ok(timers && core.tick_timers())
+        })?;
+        self.report_diagnostics();
+
+        if beep {
+            log::info!(target: "audio", "Beep!");
+        }
+
+        Ok(())
+    }
+
+    /// Draws the debugger view in its window or over the game, with the console
+    pub(super) fn draw_debugger(&mut self, overlay: &mut Vec<Rect>) -> Result<(), Error> {
+        let show_screen = self.window_debugger.as_ref().is_some_and(|debugger| debugger.show_screen);
+        match self.debugger {
+            // closed by the user
+            Some(overlay::debugger::Placement::Window) if !self.window.debug_window_open() => {
+                self.debugger = None;
+                self.window_debugger = None;
+            }
+            Some(overlay::debugger::Placement::Window) => {
+                let rects = overlay::debugger::rects(self.core.state(), overlay::debugger::ROWS, &self.debug_panels);
+                self.window.draw_debug_window(&rects)?
+            }
+            Some(overlay::debugger::Placement::Overlay) if show_screen => {}
+            Some(overlay::debugger::Placement::Overlay) => {
+                // the console takes the bottom lines
+                let rows = overlay::debugger::ROWS - overlay::console::LINES * usize::from(self.window_debugger.is_some());
+                overlay.extend(overlay::debugger::rects(self.core.state(), rows, &self.debug_panels));
+            }
+            None => {}
+        }
+        if let Some(debugger) = &self.window_debugger {
+            let status = debugger.running.map(|_| "Running, Esc stops");
+            overlay.extend(debugger.console.rects(status));
+        }
+        Ok(())
+    }
+
+    /// Handles input while the window debugger is open, returns true if it took it.
+    /// Typed keys go to the console, while the program runs only Esc and F1 do
+    pub(super) fn debugger_input(&mut self, input: &Input) -> bool {
+        if let Some(debugger) = &mut self.window_debugger {
+            use winit::VirtualKeyCode;
+
+            match (input::keyboard_input(input), debugger.running) {
+                // stops the program instead of exiting
+                (Some((VirtualKeyCode::Escape, pressed)), Some(_)) => {
+                    if pressed {
+                        debugger.running = None;
+                        debugger.console.print(format!("Stopped at {:03X}", self.core.state().registers.pc));
+                    }
+                    return true;
+                }
+                (Some((VirtualKeyCode::F1, true)), Some(_)) => {
+                    self.toggle_window_debugger();
+                    return true;
+                }
+                (Some((keycode, true)), None) => {
+                    match debugger.console.handle_key(keycode) {
+                        overlay::console::ConsoleAction::Submit(line) => self.window_debug_command(&line),
+                        overlay::console::ConsoleAction::Close => self.toggle_window_debugger(),
+                        overlay::console::ConsoleAction::None => {}
+                    }
+                    return true;
+                }
+                // releases still reach the program, for keys held when the debugger opened
+                (Some((VirtualKeyCode::Escape, false)), None) => return true,
+                _ => {}
+            }
+        }
+
+        false
+    }
+}
+
+/// `len` bytes of memory from `start` in rows of 16, each followed by the regions its bytes are
+/// in. `colors` shows every byte in the color of its region
+fn hexdump(system: &system::System, memory: &MemoryMap, start: u16, len: u16, colors: bool) -> Vec<String> {
+    let end = start.saturating_add(len).min(system::MEMORY_SIZE);
+    let mut lines = Vec::new();
+    for row in (start..end).step_by(16) {
+        let bytes = system.read_slice(row..end.min(row.saturating_add(16))).unwrap_or_default();
+        let mut line = format!("{:03X} ", row);
+        let mut regions = Vec::<Region>::new();
+        for (addr, byte) in (row..).zip(bytes) {
+            let region = memory.region(addr, system.diagnostics.initialized(addr));
+            if colors {
+                line += &format!(" {}{:02X}\x1b[0m", region.color(), byte);
+            } else {
+                line += &format!(" {:02X}", byte);
+            }
+            if !regions.contains(&region) {
+                regions.push(region);
+            }
+        }
+        let labels = regions.iter().map(|region| region.label()).collect::<Vec<_>>();
+        line += &"   ".repeat(16 - bytes.len());
+        line += &format!("  {}", labels.join(", "));
+        lines.push(line);
+    }
+    lines
+}
+
+/// `quirks` with the one called `name` set to `value`, both written as in the config
+fn set_quirk(quirks: Quirks, name: &str, value: &str) -> Result<Quirks, Error> {
+    let name = name.replace('-', "_");
+    // the extensions are left out while they are off
+    let known = toml::Value::try_from(Quirks {
+        frame_counter: Some(0),
+        bank_port: Some(0),
+        ..quirks
+    })?;
+    if known.get(&name).is_none() {
+        bail!("No quirk is called {}", name);
+    }
+    // a bare word like sixteen-rows is a string
+    let value = toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()));
+    let mut table = toml::Value::try_from(quirks)?;
+    if let toml::Value::Table(table) = &mut table {
+        table.insert(name.clone(), value);
+    }
+    table.try_into().map_err(|e| format_err!("Invalid value for {}: {}", name, e))
+}
+
+fn print_lines(lines: &mut Vec<String>) {
+    for line in lines.drain(..) {
+        println!("{}", line);
+    }
+}
+
+/// What a debugger is left with between commands
+struct DebugSession {
+    trace: system::trace::Trace,
+    breakpoints: Breakpoints,
+    timers_frozen: bool,
+    /// register usage of the routines, shown when a step enters one
+    routines: BTreeMap<u16, analysis::registers::Usage>,
+    /// what `hexdump` labels memory as, with the sprites drawn since the session started
+    memory: MemoryMap,
+    /// printed for an unknown command
+    help: &'static str,
+    /// whether output may have ANSI colors
+    colors: bool,
+}
+
+impl DebugSession {
+    fn new(system: &system::System, help: &'static str, colors: bool) -> Self {
+        let program = system.read_slice(system::PROGRAM_START..system::MEMORY_SIZE).unwrap_or_default();
+        let cfg = analysis::Cfg::build(program);
+        DebugSession {
+            trace: Default::default(),
+            breakpoints: Default::default(),
+            timers_frozen: false,
+            routines: analysis::registers::analyze(&cfg),
+            memory: MemoryMap::new(&cfg),
+            help,
+            colors,
+        }
+    }
+
+    fn timers(&self, system: &system::System) -> String {
+        format!(
+            "DT: {:02X} ST: {:02X}{}",
+            system.timers.delay,
+            system.timers.sound,
+            if self.timers_frozen { " (frozen)" } else { "" }
+        )
+    }
+}
+
+/// What is left to do after a debugger command
+enum DebugAction {
+    /// wait for the next one
+    Prompt,
+    /// run until a breakpoint, an error or what `RunUntil` says
+    Run(RunUntil),
+    Quit,
+}
+
+/// The debugger typed into in the game window, open together with the debugger view
+pub(super) struct WindowDebugger {
+    console: overlay::console::Console,
+    session: DebugSession,
+    /// set by `continue` and the commands like it, the game runs until it stops
+    running: Option<RunUntil>,
+    /// `show` hides the debugger view to see the screen behind it
+    show_screen: bool,
+}
+
+/// Where the debugger stops before executing an instruction
+#[derive(Default)]
+struct Breakpoints {
+    addresses: BTreeSet<u16>,
+    /// every instruction of these kinds
+    opcodes: Vec<system::opcode::Opcode>,
+}
+
+impl Breakpoints {
+    /// What stops the system before its next instruction, if anything does
+    fn hit(&self, system: &system::System) -> Option<String> {
+        let pc = system.registers.pc;
+        if self.addresses.contains(&pc) {
+            return Some(format!("Breakpoint at {:03X}", pc));
+        }
+
+        let opcode = system.fetch_instruction().ok()?;
+        let info = system::opcode::decode(opcode).filter(|info| self.opcodes.contains(&info.opcode))?;
+        Some(format!(
+            "Breakpoint on {} at {:03X}: {:04X} {}",
+            info.pattern_name(),
+            pc,
+            opcode,
+            disasm::disassemble(opcode)
+        ))
+    }
+
+    /// The instructions with the pattern (`DXYN`, `00E0`) or mnemonic (`DRW`, `LD`) `name`
+    fn parse_opcodes(name: &str) -> Vec<system::opcode::Opcode> {
+        let name = name.to_uppercase();
+        system::opcode::OPCODES
+            .iter()
+            .filter(|info| info.pattern_name() == name || info.mnemonic() == name)
+            .map(|info| info.opcode)
+            .collect()
+    }
+}
+
+/// File in the directory of a rom in the data directory, next to its save states, its last
+/// debugger session is kept in
+pub const SESSION_FILE: &str = "debugger.toml";
+
+/// What of a debugger session is kept for the next one on the same rom
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct SavedSession {
+    /// hex addresses
+    breakpoints: Vec<String>,
+    /// patterns like DXYN
+    break_ops: Vec<String>,
+    timers_frozen: bool,
+}
+
+impl SavedSession {
+    fn of(session: &DebugSession) -> Self {
+        SavedSession {
+            breakpoints: session.breakpoints.addresses.iter().map(|addr| format!("{:03X}", addr)).collect(),
+            break_ops: session.breakpoints.opcodes.iter().map(|opcode| opcode.info().pattern_name()).collect(),
+            timers_frozen: session.timers_frozen,
+        }
+    }
+
+    /// Sets the breakpoints and settings of `session`, returns what it restored if anything.
+    /// Entries that do not parse are skipped
+    fn apply(self, session: &mut DebugSession) -> Option<String> {
+        let breakpoints = &mut session.breakpoints;
+        breakpoints.addresses = self.breakpoints.iter().filter_map(|addr| u16::from_str_radix(addr, 16).ok()).collect();
+        breakpoints.opcodes = self.break_ops.iter().flat_map(|name| Breakpoints::parse_opcodes(name)).collect();
+        session.timers_frozen = self.timers_frozen;
+
+        let count = breakpoints.addresses.len() + breakpoints.opcodes.len();
+        match (count, self.timers_frozen) {
+            (0, false) => None,
+            (count, frozen) => Some(format!(
+                "Restored {} breakpoint{} from the last session{}",
+                count,
+                if count == 1 { "" } else { "s" },
+                if frozen { ", timers frozen" } else { "" }
+            )),
+        }
+    }
+
+    /// The saved session, None if there is none or it can not be read
+    fn load(path: &Path) -> Option<Self> {
+        let src = std::fs::read_to_string(path).ok()?;
+        toml::from_str(&src)
+            .map_err(|e| log::warn!("Ignoring {}: {}", path.display(), e))
+            .ok()
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// Where `continue` and the commands like it stop, besides breakpoints and errors
+#[derive(Clone, Copy)]
+enum RunUntil {
+    Breakpoint,
+    /// after the next DRW
+    Draw,
+    /// after the next instruction that changes the screen
+    ScreenChange,
+}
+
+/// the console shows a few lines, the commands are the same as in the terminal
+const CONSOLE_HELP: &str = "\
+s step, c continue, nd next draw, nx next diff, rs rc reverse
+b <addr> break, bo <op> break on op, bt backtrace, x [addr] dump
+timer delay|sound <n>, timer freeze, quirks [vip|schip|xo-chip]
+quirks <name> <value> switches a quirk live, q quit
+poke <addr> <bytes> patch, patches list, patch <n> toggle
+panel registers|stack|disassembly|memory hides or shows a panel
+show hides the debugger view, Esc stops a run, F1 closes";
+
+const DEBUG_HELP: &str = "\
+Commands:
+  (empty), s, step          execute one instruction
+  c, continue               run until a breakpoint
+  nd, next-draw             run until the next DRW has executed
+  nx, next-diff             run until the screen changes
+  rs, reverse-step          undo the last instruction
+  rc, reverse-continue      undo instructions until a breakpoint or the start of the trace
+  b, break <addr>           set or remove a breakpoint at a hex address
+  bo, break-op <op>         set or remove a breakpoint on every instruction of a kind,
+                            by pattern (DXYN) or mnemonic (RND)
+  bt, backtrace             list the calls on the stack and where they were made
+  timer delay|sound <n>     set the delay or sound timer (decimal)
+  timer freeze              stop or resume the timers counting down
+  quirks [profile]          print the quirks, or switch them to those of vip, schip or xo-chip
+  quirks <name> <value>     change one quirk, e.g. `quirks wrap_sprites true`. Both apply at
+                            once without a reset, except a bank port or a stack too small for
+                            the return addresses on it
+  x, hexdump [addr] [len]   print len bytes of memory (64) from a hex address (I), labeled
+                            font, code, sprite, data, stack or free
+  poke <addr> <byte>...     write hex bytes over memory as a patch, kept apart from the rom
+  patches                   list the patches, with their address, bytes and whether they are on
+  patch <n>                 turn a patch off, putting back the bytes under it, or on again
+  patch export|import <f>   write the patches that are on to an IPS file against the rom, or
+                            apply the records of one as patches
+  show [on] [off]           print the screen as text, `#` and `.` by default
+  panel <name>              hide or show a panel of the debugger view: registers, stack,
+                            disassembly or memory
+  q, quit                   exit";
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_come_back_as_they_were_saved() {
+        let system = system::System::default();
+        let mut session = DebugSession::new(&system, DEBUG_HELP, false);
+        session.breakpoints.addresses.insert(0x2A4);
+        session.breakpoints.opcodes = Breakpoints::parse_opcodes("DXYN");
+        session.timers_frozen = true;
+
+        let saved = toml::to_string(&SavedSession::of(&session)).unwrap();
+        let mut restored = DebugSession::new(&system, DEBUG_HELP, false);
+        let message = toml::from_str::<SavedSession>(&saved).unwrap().apply(&mut restored);
+        assert_eq!(message.as_deref(), Some("Restored 2 breakpoints from the last session, timers frozen"));
+        assert_eq!(SavedSession::of(&restored), SavedSession::of(&session));
+    }
+
+    #[test]
+    fn sets_quirks_by_name_and_profile() {
+        let quirks = set_quirk(Quirks::default(), "wrap-sprites", "true").unwrap();
+        let quirks = set_quirk(quirks, "zero_height_draw", "sixteen-rows").unwrap();
+        let quirks = set_quirk(quirks, "frame_counter", "0xFF0").unwrap();
+        assert!(quirks.wrap_sprites);
+        assert_eq!(quirks.zero_height_draw, quirks::ZeroHeightDraw::SixteenRows);
+        assert_eq!(quirks.frame_counter, Some(0xFF0));
+        assert!(set_quirk(quirks, "wrap_everything", "true").is_err());
+        assert!(set_quirk(quirks, "stack_size", "deep").is_err());
+
+        let vip = quirks.with_profile("vip").unwrap();
+        assert!(!vip.wrap_sprites);
+        assert_eq!(vip.frame_counter, Some(0xFF0));
+        assert_eq!(quirks.with_profile("vip").unwrap().with_profile("xo-chip"), Some(quirks));
+    }
+}
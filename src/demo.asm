@@ -0,0 +1,37 @@
+; Attract mode: "C8" in the built-in font bouncing around the screen
+    LD V0, 10       ; position of the logo
+    LD V1, 4
+    LD V2, 1        ; direction, 0xFF is -1
+    LD V3, 1
+    LD VA, 0xC
+    LD VB, 8
+    CALL draw
+loop:
+    LD V5, 2        ; move every other frame
+    LD DT, V5
+wait:
+    LD V5, DT
+    SE V5, 0
+    JP wait
+    CALL draw       ; erase it where it was
+    ADD V0, V2
+    ADD V1, V3
+    SNE V0, 0       ; bounce off the edges, the logo is 9x5
+    LD V2, 1
+    SNE V0, 55
+    LD V2, 0xFF
+    SNE V1, 0
+    LD V3, 1
+    SNE V1, 27
+    LD V3, 0xFF
+    CALL draw
+    JP loop
+
+draw:
+    LD F, VA
+    DRW V0, V1, 5
+    LD V4, V0
+    ADD V4, 5
+    LD F, VB
+    DRW V4, V1, 5
+    RET
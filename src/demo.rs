@@ -0,0 +1,36 @@
+/// Source of the demo shown when the emulator starts without a rom, built into the executable
+pub const SOURCE: &str = include_str!("demo.asm");
+
+/// The demo, assembled from `SOURCE`. None without the assembler
+pub fn rom() -> Option<Vec<u8>> {
+    #[cfg(feature = "assembler")]
+    return Some(crate::asm::assemble(SOURCE).expect("the demo assembles"));
+    #[cfg(not(feature = "assembler"))]
+    return None;
+}
+
+#[cfg(all(test, feature = "assembler"))]
+mod tests {
+    use crate::headless::Headless;
+    use crate::system::System;
+
+    #[test]
+    fn logo_bounces_off_every_edge() {
+        let mut system = System::default();
+        system.load(&super::rom().unwrap()[..]).unwrap();
+        let mut demo = Headless::new(system);
+
+        let (mut xs, mut ys) = (Vec::new(), Vec::new());
+        for _ in 0..300 {
+            demo.run_frame().unwrap();
+            let system = &demo.system;
+            xs.push(system.registers.reg[0]);
+            ys.push(system.registers.reg[1]);
+            // "C" and "8" light 11 and 16 pixels, a frame can end while one of them is erased
+            let lit = system.screen().iter().filter(|&&pixel| pixel != 0).count();
+            assert!([0, 11, 16, 27].contains(&lit), "{} pixels lit", lit);
+        }
+        assert_eq!((xs.iter().min(), xs.iter().max()), (Some(&0), Some(&55)));
+        assert_eq!((ys.iter().min(), ys.iter().max()), (Some(&0), Some(&27)));
+    }
+}
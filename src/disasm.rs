@@ -0,0 +1,127 @@
+use crate::system::opcode::{self, Instruction, Opcode, OpcodeInfo, Token};
+use crate::system::System;
+
+/// Mnemonic for a single instruction, words that are not instructions are shown as data
+pub fn disassemble(opcode: u16) -> String {
+    format(Instruction::decode(opcode))
+}
+
+/// Mnemonic for a word as `opcode::instructions` decodes it
+pub fn format(instruction: Result<Instruction, u16>) -> String {
+    match instruction {
+        Ok(instruction) => render(instruction.info, instruction.word),
+        Err(word) if word != 0 && word & 0xF000 == 0 => format!("SYS {:#05X}", word),
+        Err(word) => format!("DW {:#06X}", word),
+    }
+}
+
+/// The syntax of the instruction with the operands of `opcode` filled in
+fn render(info: &OpcodeInfo, opcode: u16) -> String {
+    let operands = info.tokens().into_iter().map(|token| operand(token, opcode)).collect::<Vec<_>>();
+
+    if operands.is_empty() {
+        info.mnemonic().to_string()
+    } else {
+        format!("{} {}", info.mnemonic(), operands.join(", "))
+    }
+}
+
+fn operand(token: Token, opcode: u16) -> String {
+    match token {
+        Token::X => format!("V{:X}", (opcode >> 8) & 0xF),
+        Token::Y => format!("V{:X}", (opcode >> 4) & 0xF),
+        Token::Nibble => format!("{}", opcode & 0xF),
+        Token::Byte => format!("{:#04X}", opcode & 0xFF),
+        Token::Addr => format!("{:#05X}", opcode & 0xFFF),
+        Token::Literal(literal) => literal.to_string(),
+    }
+}
+
+/// The description of the instruction with the operands of `opcode` filled in, e.g.
+/// "V3 += 0X01, without carry" for 7301
+pub fn explain(info: &OpcodeInfo, opcode: u16) -> String {
+    info.description
+        .split_inclusive(|c: char| !c.is_ascii_alphanumeric())
+        .map(|piece| {
+            let end = piece.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(piece.len());
+            let word = match &piece[..end] {
+                "VX" => operand(Token::X, opcode),
+                "VY" => operand(Token::Y, opcode),
+                "n" => operand(Token::Nibble, opcode),
+                "nn" => operand(Token::Byte, opcode),
+                "nnn" => operand(Token::Addr, opcode),
+                word => word.to_string(),
+            };
+            word + &piece[end..]
+        })
+        .collect()
+}
+
+/// Disassembles `rom` as if it was loaded at `start`.
+/// Returns (address, raw word, mnemonic) for every word
+pub fn listing(rom: &[u8], start: u16) -> Vec<(u16, u16, String)> {
+    let mut listing = opcode::instructions(rom, start)
+        .map(|(addr, instruction)| {
+            let word = instruction.map_or_else(|word| word, |instruction| instruction.word);
+            (addr, word, format(instruction))
+        })
+        .collect::<Vec<_>>();
+    if let [.., last] = rom {
+        if rom.len() % 2 == 1 {
+            let addr = start.wrapping_add(rom.len() as u16 - 1);
+            listing.push((addr, u16::from(*last) << 8, format!("DB {:#04X}", last)));
+        }
+    }
+    listing
+}
+
+/// The calls on the stack of `system`, outermost first, as "frame 1: called from 246 -> 3A0".
+/// The stack holds the address of each CALL, the call returns past it. If the instruction there
+/// has been overwritten since, it is shown instead of the target
+pub fn backtrace(system: &System) -> Vec<String> {
+    let depth = (system.stack.sp as usize).min(system.stack.stack.len());
+    system.stack.stack[..depth]
+        .iter()
+        .enumerate()
+        .map(|(i, &addr)| match system.read_mem_pair(addr) {
+            Ok(opcode) if Opcode::Call.matches(opcode) => {
+                format!("frame {}: called from {:03X} -> {:03X}", i + 1, addr, opcode & 0xFFF)
+            }
+            Ok(opcode) => format!(
+                "frame {}: called from {:03X}, now {:04X} {}",
+                i + 1,
+                addr,
+                opcode,
+                disassemble(opcode)
+            ),
+            Err(_) => format!("frame {}: called from {:03X}, outside of memory", i + 1, addr),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::SystemError;
+
+    #[test]
+    fn every_instruction_survives_a_round_trip() {
+        for word in 0..=u16::MAX {
+            let instruction = match Instruction::decode(word) {
+                Ok(instruction) => instruction,
+                Err(_) => continue,
+            };
+            assert_eq!(instruction.encode(), word);
+            let mut system = System::default();
+            system.load(&word.to_be_bytes()[..]).unwrap();
+            let executed = system.tick(&mut crate::system::debug::Debugger::disabled());
+            assert!(!matches!(executed, Err(SystemError::UnknownOpcode { .. })), "{:04X}", word);
+            #[cfg(feature = "assembler")]
+            {
+                let text = format(Ok(instruction));
+                let assembled = crate::asm::assemble(&text).unwrap_or_else(|e| panic!("{}: {}", text, e));
+                assert_eq!(assembled, word.to_be_bytes(), "{}", text);
+            }
+        }
+    }
+}
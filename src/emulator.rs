@@ -0,0 +1,507 @@
+use failure::Error;
+use std::path::{Path, PathBuf};
+
+use crate::audio::AudioConfig;
+use crate::cli::{DiagnosticsMode, DisplayArgs};
+use crate::frontend::{Frontend, Renderer};
+use crate::headless::Headless;
+use crate::system::diagnostics::MemoryFill;
+use crate::system::machine::Chip8Core;
+use crate::system::quirks::Quirks;
+use crate::system::{debug::Debugger, System, SystemError};
+use crate::{capture, clock, compat, config, container, overlay, paths, replay, rom, summary, timing, watch, Chip8};
+
+/// Sets up an `Emulator`: the config, speed, quirks, audio, the rom and where it is shown.
+/// What is not set comes from the config file, `chip8.toml` by default, see `paths`
+///
+/// ```
+/// use chip8::config::Config;
+/// use chip8::emulator::EmulatorBuilder;
+///
+/// // LD V0, 7, then the zero instruction ends the program
+/// let mut emulator = EmulatorBuilder::new().config(Config::default()).headless().rom(vec![0x60, 0x07]).build()?;
+/// emulator.run()?;
+/// assert_eq!(emulator.system().registers.reg[0], 7);
+/// # Ok::<(), failure::Error>(())
+/// ```
+#[derive(Default)]
+pub struct EmulatorBuilder {
+    config: Option<config::Config>,
+    config_path: Option<PathBuf>,
+    display: DisplayArgs,
+    window: Option<Box<dyn Frontend>>,
+    headless: bool,
+    cpu_hz: Option<f64>,
+    quirks: Option<Quirks>,
+    audio: Option<AudioConfig>,
+    rom: Option<RomSource>,
+    seed: Option<u64>,
+    memory_fill: MemoryFill,
+    diagnostics: Option<DiagnosticsMode>,
+    vf_warnings: bool,
+    compat_report: bool,
+    frames_out: Option<PathBuf>,
+    record_audio: Option<PathBuf>,
+    record_replay: bool,
+    summary: bool,
+    watch_dir: Option<PathBuf>,
+    teach: Option<f64>,
+    trace: bool,
+}
+
+enum RomSource {
+    /// a rom, container or Octo source, see `container::load`
+    File(PathBuf),
+    Data(Vec<u8>),
+}
+
+impl EmulatorBuilder {
+    pub fn new() -> Self {
+        EmulatorBuilder::default()
+    }
+
+    /// The config file, which is also where the data directory is found
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    /// Settings to use instead of those in the config file
+    pub fn config(mut self, config: config::Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn renderer(mut self, renderer: Renderer) -> Self {
+        self.display.renderer = renderer;
+        self
+    }
+
+    /// Display settings that take precedence over the config, as on the command line
+    pub fn display(mut self, display: DisplayArgs) -> Self {
+        self.display = display;
+        self
+    }
+
+    /// Shows the game in a frontend opened elsewhere instead of one made for `renderer`
+    pub fn window(mut self, window: Box<dyn Frontend>) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Runs without a window, `headless::CYCLES_PER_FRAME` instructions per frame. Needs a rom
+    pub fn headless(mut self) -> Self {
+        self.headless = true;
+        self
+    }
+
+    /// instructions per second
+    pub fn cpu_hz(mut self, cpu_hz: f64) -> Self {
+        self.cpu_hz = Some(cpu_hz);
+        self
+    }
+
+    /// Quirks for roms the game database has none for
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    pub fn audio(mut self, audio: AudioConfig) -> Self {
+        self.audio = Some(audio);
+        self
+    }
+
+    /// A rom in memory, with the settings of the metadata block at its end if it has one
+    pub fn rom(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.rom = Some(RomSource::Data(data.into()));
+        self
+    }
+
+    /// A rom, a rom container or Octo source. Without a rom the emulator starts at the splash
+    /// screen and the rom picker
+    pub fn rom_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rom = Some(RomSource::File(path.into()));
+        self
+    }
+
+    /// Seeds the random number generator, for reproducible runs
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn memory_fill(mut self, fill: MemoryFill) -> Self {
+        self.memory_fill = fill;
+        self
+    }
+
+    /// Reports `system::diagnostics`, `vf_warnings` also `Diagnostic::FlagRegister`
+    pub fn diagnostics(mut self, mode: DiagnosticsMode, vf_warnings: bool) -> Self {
+        self.diagnostics = Some(mode);
+        self.vf_warnings = vf_warnings;
+        self
+    }
+
+    /// Skips unsupported opcodes, recording them in `Emulator::compat_report`
+    pub fn compat_report(mut self) -> Self {
+        self.compat_report = true;
+        self
+    }
+
+    /// Writes every presented frame, see `capture::FrameWriter`
+    pub fn frames_out(mut self, path: impl Into<PathBuf>) -> Self {
+        self.frames_out = Some(path.into());
+        self
+    }
+
+    /// Writes the sound to a WAV file, in emulated time
+    pub fn record_audio(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record_audio = Some(path.into());
+        self
+    }
+
+    /// Records the inputs for `Emulator::replay`
+    pub fn record_replay(mut self) -> Self {
+        self.record_replay = true;
+        self
+    }
+
+    /// Counts what ran for `Emulator::write_summary`
+    pub fn summary(mut self) -> Self {
+        self.summary = true;
+        self
+    }
+
+    /// Runs `hz` instructions per second, or one per press of Space with 0, explaining each in
+    /// an overlay
+    pub fn teach(mut self, hz: f64) -> Self {
+        self.teach = Some(hz);
+        self
+    }
+
+    /// Logs every instruction and key event to the `cpu` target at debug level, see
+    /// `system::debug::Debugger`
+    pub fn trace(mut self) -> Self {
+        self.trace = true;
+        self
+    }
+
+    /// Lists the roms in `dir` in the rom picker instead of the current directory, and runs a
+    /// rom as soon as it is written there
+    pub fn watch_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.watch_dir = Some(dir.into());
+        self
+    }
+
+    /// Loads the config and opens the window, or loads the rom into a headless system
+    pub fn build(self) -> Result<Emulator, Error> {
+        let config_path = self.config_path.clone().unwrap_or_else(paths::default_config);
+        let mut config = match self.config.clone() {
+            Some(config) => config,
+            None => config::Config::load(&config_path)?,
+        };
+        if let Some(cpu_hz) = self.cpu_hz {
+            config.timing.cpu_hz = cpu_hz;
+        }
+        if let Some(quirks) = self.quirks {
+            // not replaced by those of the platform the rom is detected to be for
+            config.quirks = quirks;
+            config.detect_platform = config::DetectPlatform::Off;
+        }
+        if let Some(audio) = self.audio.clone() {
+            config.audio = audio;
+        }
+
+        if self.headless {
+            return self.build_headless(&config);
+        }
+
+        let mut chip = match self.window {
+            Some(window) => Chip8::<System>::with_window(config, config_path, self.display, window)?,
+            None => Chip8::new(config, config_path, self.display)?,
+        };
+        if self.compat_report {
+            chip.compat = Some(compat::CompatReport::default());
+        }
+        if let Some(path) = &self.frames_out {
+            chip.frames_out = Some(capture::FrameWriter::open(path)?);
+        }
+        #[cfg(feature = "audio")]
+        if let Some(path) = &self.record_audio {
+            chip.record_audio = Some(Box::new(crate::audio::wav::WavSink::create(path, &chip.config.audio)?));
+        }
+        #[cfg(not(feature = "audio"))]
+        if self.record_audio.is_some() {
+            bail!("Recording audio is not available, rebuild with `--features audio`");
+        }
+        if self.record_replay {
+            chip.replay = Some(replay::Recorder::default());
+        }
+        if self.summary {
+            chip.summary = Some(summary::RunSummary::start());
+        }
+        chip.watch = self.watch_dir.map(watch::RomWatcher::new);
+        chip.teach = self.teach.map(overlay::teach::Teacher::new);
+        chip.trace.set_enabled(self.trace);
+        let diagnostics = self.diagnostics.unwrap_or(DiagnosticsMode::Off);
+        chip.diagnostics = diagnostics;
+        setup(chip.core.state_mut(), diagnostics, self.vf_warnings, self.memory_fill, self.seed);
+
+        let (rom, loaded) = match self.rom {
+            Some(RomSource::File(path)) => (Some(path), false),
+            Some(RomSource::Data(data)) => {
+                chip.load_container(&container::Container::from_rom(data)?)?;
+                (None, true)
+            }
+            None => (None, false),
+        };
+        Ok(Emulator {
+            kind: Kind::Window { chip: Box::new(chip), rom, loaded },
+            paused: false,
+        })
+    }
+
+    fn build_headless(&self, config: &config::Config) -> Result<Emulator, Error> {
+        if self.cpu_hz.is_some() {
+            bail!("A headless emulator runs {} instructions per frame, it has no cpu_hz", crate::headless::CYCLES_PER_FRAME);
+        }
+        let (rom, metadata_quirks) = match &self.rom {
+            Some(RomSource::File(path)) => {
+                let container = container::load(path)?;
+                let quirks = container.metadata.quirks();
+                (container.rom, quirks)
+            }
+            Some(RomSource::Data(data)) => {
+                let container = container::Container::from_rom(data.clone())?;
+                let quirks = container.metadata.quirks();
+                (container.rom, quirks)
+            }
+            None => bail!("A headless emulator needs a rom"),
+        };
+
+        let mut system = System::default();
+        let game_quirks = config.game(rom::hash(&rom)).and_then(|game| game.quirks);
+        let detected = config.detected_quirks(compat::detect(&rom));
+        system.quirks = game_quirks.or(metadata_quirks).or(detected).unwrap_or(config.quirks);
+        setup(
+            &mut system,
+            self.diagnostics.unwrap_or(DiagnosticsMode::Off),
+            self.vf_warnings,
+            self.memory_fill,
+            self.seed,
+        );
+        system.load(&rom[..])?;
+        Ok(Emulator {
+            kind: Kind::Headless(Box::new(Headless::new(system))),
+            paused: false,
+        })
+    }
+}
+
+/// The settings of a system that come before the rom is loaded
+fn setup(system: &mut System, diagnostics: DiagnosticsMode, vf_warnings: bool, fill: MemoryFill, seed: Option<u64>) {
+    system.diagnostics.enabled = diagnostics != DiagnosticsMode::Off;
+    system.diagnostics.flag_register = vf_warnings;
+    system.self_modification.enabled = system.diagnostics.enabled;
+    system.memory_fill = fill;
+    if let Some(seed) = seed {
+        system.seed(seed);
+    }
+}
+
+enum Kind {
+    Window {
+        chip: Box<Chip8>,
+        /// loaded when it runs, which shows its errors on the screen
+        rom: Option<PathBuf>,
+        /// a rom was loaded, without one `run` starts at the splash screen
+        loaded: bool,
+    },
+    Headless(Box<Headless>),
+}
+
+/// A configured emulator, made by `EmulatorBuilder`
+pub struct Emulator {
+    kind: Kind,
+    /// for a headless emulator, `run` returns at once
+    paused: bool,
+}
+
+impl Emulator {
+    /// Runs until the window is closed, or a headless emulator until the program halts at a
+    /// 0000 instruction. In a window, errors are shown on the screen with the rom picker, the
+    /// splash screen comes first without a rom
+    pub fn run(&mut self) -> Result<(), Error> {
+        match &mut self.kind {
+            Kind::Window { chip, rom: Some(path), .. } => chip.play(Some(path.clone())),
+            Kind::Window { chip, loaded: true, .. } => chip.run_with_crash_dump(),
+            Kind::Window { chip, .. } => chip.play(None),
+            Kind::Headless(headless) => {
+                while !self.paused && !headless.halted() {
+                    headless.run_frame()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Pauses at the end of the current frame, as `F10` does. `step_frame` still runs frames
+    pub fn pause(&mut self) {
+        self.paused = true;
+        if let Kind::Window { chip, .. } = &mut self.kind {
+            chip.pause = clock::Pause::Requested;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+        if let Kind::Window { chip, .. } = &mut self.kind {
+            chip.pause = clock::Pause::Running;
+        }
+    }
+
+    /// Runs one frame: the instructions of a 60th of a second and a timer tick, and in a
+    /// window draws it. Returns true if the window was asked to close
+    pub fn step_frame(&mut self) -> Result<bool, Error> {
+        self.load_pending()?;
+        match &mut self.kind {
+            Kind::Window { chip, .. } => chip.step_frame(),
+            Kind::Headless(headless) => {
+                headless.run_frame()?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// The state of the machine, for `load_state`
+    pub fn save_state(&mut self) -> Result<Vec<u8>, Error> {
+        self.load_pending()?;
+        Ok(self.system().save_state())
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Error> {
+        self.load_pending()?;
+        match &mut self.kind {
+            Kind::Window { chip, .. } => chip.core.state_mut().load_state(state)?,
+            Kind::Headless(headless) => headless.system.load_state(state)?,
+        }
+        Ok(())
+    }
+
+    pub fn system(&self) -> &System {
+        match &self.kind {
+            Kind::Window { chip, .. } => chip.core.state(),
+            Kind::Headless(headless) => &headless.system,
+        }
+    }
+
+    /// The system with the rom loaded, to change it before `run`
+    pub fn system_mut(&mut self) -> Result<&mut System, Error> {
+        self.load_pending()?;
+        Ok(match &mut self.kind {
+            Kind::Window { chip, .. } => chip.core.state_mut(),
+            Kind::Headless(headless) => &mut headless.system,
+        })
+    }
+
+    /// The report of `EmulatorBuilder::compat_report`
+    pub fn compat_report(&self) -> Option<&compat::CompatReport> {
+        self.chip().and_then(|chip| chip.compat.as_ref())
+    }
+
+    /// The replay of `EmulatorBuilder::record_replay`, if the rom ran
+    pub fn replay(&self) -> Option<&replay::Replay> {
+        self.chip().and_then(|chip| chip.replay.as_ref()).and_then(|recorder| recorder.replay())
+    }
+
+    /// Writes the summary of `EmulatorBuilder::summary` to `path` as JSON
+    pub fn write_summary(&self, path: &Path) -> Result<(), Error> {
+        if let Some(summary) = self.chip().and_then(|chip| chip.summary.as_ref()) {
+            summary.write(path, self.system())?;
+        }
+        Ok(())
+    }
+
+    /// Steps through the program with commands read from stdin, see `Chip8::run_debug`
+    #[cfg(feature = "debugger")]
+    pub fn run_debug(&mut self) -> Result<(), Error> {
+        self.load_pending()?;
+        match &mut self.kind {
+            Kind::Window { chip, .. } => chip.run_debug(),
+            Kind::Headless(_) => bail!("The debugger needs a window"),
+        }
+    }
+
+    fn chip(&self) -> Option<&Chip8> {
+        match &self.kind {
+            Kind::Window { chip, .. } => Some(chip),
+            Kind::Headless(_) => None,
+        }
+    }
+
+    /// Loads the rom file that `run` would, for the methods that need it loaded before
+    fn load_pending(&mut self) -> Result<(), Error> {
+        if let Kind::Window { chip, rom, loaded } = &mut self.kind {
+            if let Some(path) = rom.take() {
+                chip.load_container(&container::load(&path)?)?;
+                *loaded = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<C: Chip8Core> Chip8<C> {
+    /// Runs a 60th of a second's instructions at the configured speed, ticks the timers once
+    /// and draws the frame. Returns true if the window was asked to close
+    fn step_frame(&mut self) -> Result<bool, Error> {
+        let mut debug = Debugger::disabled();
+        let instructions = (self.config.timing.cpu_hz / f64::from(timing::TIMER_HZ)).round().max(1.0) as u32;
+        for _ in 0..instructions {
+            match self.core.step(&mut debug) {
+                Ok(()) => {}
+                Err(SystemError::ZeroInstruction) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if self.core.tick_timers() {
+            log::info!(target: "audio", "Beep!");
+        }
+        for key in self.hold.frame_end() {
+            self.key_event(key, false)?;
+        }
+        self.draw()?;
+        self.poll_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_a_headless_emulator() {
+        // counts frames in V0
+        let rom = [0x70, 0x01, 0x12, 0x00];
+        let mut emulator = EmulatorBuilder::new()
+            .config(config::Config::default())
+            .headless()
+            .rom(&rom[..])
+            .seed(1)
+            .build()
+            .unwrap();
+        emulator.step_frame().unwrap();
+        let state = emulator.save_state().unwrap();
+        emulator.step_frame().unwrap();
+        assert_eq!(emulator.system().registers.reg[0], 40);
+
+        emulator.load_state(&state).unwrap();
+        assert_eq!(emulator.system().registers.reg[0], 20);
+        emulator.pause();
+        emulator.run().unwrap();
+        assert_eq!(emulator.system().registers.reg[0], 20);
+
+        assert!(EmulatorBuilder::new().config(config::Config::default()).headless().build().is_err());
+    }
+}
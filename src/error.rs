@@ -0,0 +1,160 @@
+use failure::{Error, Fail};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::compat;
+use crate::disasm;
+use crate::frontend::Renderer;
+use crate::romsearch;
+use crate::system::SystemError;
+
+/// Exit status of the binary by what went wrong, so scripts can branch on it without reading
+/// stderr. 0 is success
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// any error without a status of its own
+    Error = 1,
+    /// the command line could not be parsed
+    Usage = 2,
+    RomNotFound = 3,
+    RomTooLarge = 4,
+    UnknownOpcode = 5,
+    /// the program stopped with another `SystemError`
+    Crashed = 6,
+    /// no window could be opened, see `WindowError`
+    Window = 7,
+    /// assertions of `chip8 check` failed
+    Assertions = 8,
+    /// roms of `chip8 test-suite` failed: this plus their number, up to `MAX_EXIT_STATUS`
+    TestSuite = 100,
+}
+
+/// shells take statuses above this for commands that could not run or were killed
+const MAX_EXIT_STATUS: i32 = 125;
+
+impl ExitStatus {
+    /// The status to exit with after `err`
+    pub fn of(err: &Error) -> i32 {
+        let status = err.iter_chain().find_map(|cause| {
+            if let Some(failed) = cause.downcast_ref::<Failed>() {
+                return Some(match failed {
+                    Failed::Assertions { .. } => ExitStatus::Assertions as i32,
+                    Failed::TestSuite { failed } => (ExitStatus::TestSuite as i32).saturating_add(*failed as i32).min(MAX_EXIT_STATUS),
+                });
+            }
+            if cause.downcast_ref::<WindowError>().is_some() {
+                return Some(ExitStatus::Window as i32);
+            }
+            if cause.downcast_ref::<romsearch::NotFound>().is_some() {
+                return Some(ExitStatus::RomNotFound as i32);
+            }
+            if let Some(error) = cause.downcast_ref::<std::io::Error>() {
+                return (error.kind() == std::io::ErrorKind::NotFound).then_some(ExitStatus::RomNotFound as i32);
+            }
+            cause.downcast_ref::<SystemError>().map(|error| match error {
+                SystemError::ProgramTooLarge => ExitStatus::RomTooLarge as i32,
+                SystemError::UnknownOpcode { .. } => ExitStatus::UnknownOpcode as i32,
+                _ => ExitStatus::Crashed as i32,
+            })
+        });
+        status.unwrap_or(ExitStatus::Error as i32)
+    }
+}
+
+/// Checks that ran and found problems, rather than errors
+#[derive(Debug, Fail)]
+pub enum Failed {
+    #[fail(display = "{} of {} assertions failed", failed, total)]
+    Assertions { failed: usize, total: usize },
+    #[fail(display = "{} roms failed", failed)]
+    TestSuite { failed: usize },
+}
+
+/// No window could be opened, not even in the terminal
+#[derive(Debug, Fail)]
+#[fail(
+    display = "Could not open a window with the {:?} renderer: {}. Try another one with `--renderer`, or run in a terminal",
+    renderer, reason
+)]
+pub struct WindowError {
+    pub renderer: Renderer,
+    pub reason: String,
+}
+
+/// A `SystemError` with where it happened, added where the run loop hands it to the frontend
+#[derive(Debug)]
+pub struct RunError {
+    pub rom: PathBuf,
+    /// address and opcode of the instruction that failed, None if none ran
+    pub instruction: Option<(u16, u16)>,
+    /// instructions executed since the rom was loaded, including the one that failed
+    pub cycles: u64,
+    pub error: SystemError,
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.rom.display())?;
+        if let Some((pc, opcode)) = self.instruction {
+            write!(
+                f,
+                " stopped at {:03X} ({:04X} {}) after {} instructions",
+                pc,
+                opcode,
+                disasm::disassemble(opcode),
+                self.cycles
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Fail for RunError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        Some(&self.error)
+    }
+}
+
+/// The `SystemError` anywhere in the chain of `err`
+pub fn system_error(err: &Error) -> Option<&SystemError> {
+    err.iter_chain().find_map(|cause| cause.downcast_ref::<SystemError>())
+}
+
+/// `err` and its causes on one line, outermost first. An unknown opcode gets a guess at the
+/// instruction that was meant, running past memory the quirk that wraps instead
+pub fn describe(err: &Error) -> String {
+    let description = err.iter_chain().map(|cause| cause.to_string()).collect::<Vec<_>>().join(": ");
+    match system_error(err) {
+        Some(SystemError::UnknownOpcode { opcode }) => match compat::suggestion(*opcode) {
+            Some(suggestion) => format!("{}. {}", description, suggestion),
+            None => description,
+        },
+        Some(SystemError::RanPastEndOfMemory { .. }) => {
+            format!("{}. `pc_past_memory = \"wrap\"` in [quirks] goes on at 0x200 instead", description)
+        }
+        _ => description,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_status_by_failure() {
+        let status = |err: Error| ExitStatus::of(&err);
+        assert_eq!(status(format_err!("anything")), 1);
+        assert_eq!(status(SystemError::UnknownOpcode { opcode: 0xF0FF }.into()), 5);
+        // found under the context the run loop adds
+        let crash = RunError {
+            rom: PathBuf::from("pong.ch8"),
+            instruction: None,
+            cycles: 0,
+            error: SystemError::StackOverflow,
+        };
+        assert_eq!(status(crash.into()), 6);
+        assert_eq!(status(std::fs::read("no/such/rom.ch8").unwrap_err().into()), 3);
+        assert_eq!(status(Failed::TestSuite { failed: 3 }.into()), 103);
+        assert_eq!(status(Failed::TestSuite { failed: 300 }.into()), 125);
+    }
+}
@@ -2,7 +2,15 @@
 
 in vec2 tex_pos;
 uniform sampler2D tex;
+uniform vec3 background;
+uniform vec3 foreground;
+// see palette::Tone
+uniform float brightness;
+uniform float gamma;
 
 void main() {
-    gl_FragColor = vec4(vec3(texture(tex, vec2(tex_pos.x, 1.0 - tex_pos.y)).x), 1);
+    // the screen is a single channel of shades, 0 is background and 1 foreground
+    float value = texture(tex, vec2(tex_pos.x, 1.0 - tex_pos.y)).r;
+    vec3 color = mix(background, foreground, value);
+    gl_FragColor = vec4(min(pow(color, vec3(1.0 / gamma)) * brightness, 1.0), 1);
 }
@@ -0,0 +1,114 @@
+use crate::frontend::palette::Palette;
+use crate::system::System;
+
+/// A frame as the frontends get it, one shade per pixel: 0 and 255 for a bare screen, the
+/// shades in between from frame blending. Always has a pixel for every row and column
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// The pixels given for a frame are not `width` times `height`
+#[derive(Debug, Fail)]
+#[fail(display = "A {}x{} frame needs {} pixels, got {}", width, height, expected, len)]
+pub struct FrameSizeError {
+    pub width: u32,
+    pub height: u32,
+    pub expected: usize,
+    pub len: usize,
+}
+
+impl FrameBuffer {
+    pub fn new(pixels: Vec<u8>, width: u32, height: u32) -> Result<Self, FrameSizeError> {
+        let expected = width as usize * height as usize;
+        if pixels.len() != expected {
+            return Err(FrameSizeError {
+                width,
+                height,
+                expected,
+                len: pixels.len(),
+            });
+        }
+        Ok(FrameBuffer { width, height, pixels })
+    }
+
+    pub fn from_system(system: &System) -> Self {
+        let (width, height) = system.resolution();
+        // the screen has the size of the resolution
+        Self::new(system.screen(), width, height).expect("screen size")
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn into_pixels(self) -> Vec<u8> {
+        self.pixels
+    }
+
+    /// `n` by `n` pixels for every pixel
+    pub fn scale(&self, n: u32) -> Self {
+        let n = n.max(1) as usize;
+        let width = self.width as usize;
+        let pixels = self
+            .pixels
+            .chunks(width.max(1))
+            .flat_map(|row| {
+                let row = row.iter().flat_map(|&pixel| std::iter::repeat_n(pixel, n)).collect::<Vec<_>>();
+                std::iter::repeat_n(row, n).flatten()
+            })
+            .collect();
+        FrameBuffer {
+            width: self.width * n as u32,
+            height: self.height * n as u32,
+            pixels,
+        }
+    }
+
+    /// 4 bytes per pixel, the palette applied and fully opaque
+    pub fn rgba(&self, palette: &Palette) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&value| {
+                let [r, g, b] = palette.apply(value);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "image")]
+    pub fn to_image(&self, palette: &Palette) -> image::RgbaImage {
+        // the buffer has exactly the size given
+        image::RgbaImage::from_raw(self.width, self.height, self.rgba(palette)).expect("frame size")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_every_pixel() {
+        let frame = FrameBuffer::new(vec![0, 255, 255, 0], 2, 2).unwrap().scale(2);
+        assert_eq!((frame.width, frame.height), (4, 4));
+        assert_eq!(frame.pixels, [0, 0, 255, 255, 0, 0, 255, 255, 255, 255, 0, 0, 255, 255, 0, 0]);
+        assert_eq!(&frame.rgba(&Palette::default())[8..12], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn refuses_pixels_of_another_size() {
+        let error = FrameBuffer::new(vec![0; 64 * 32], 128, 64).unwrap_err();
+        assert_eq!((error.expected, error.len), (128 * 64, 64 * 32));
+        assert!(FrameBuffer::new(Vec::new(), 0, 0).is_ok());
+    }
+}
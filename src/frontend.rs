@@ -0,0 +1,432 @@
+use failure::{bail, format_err, Error};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use winit::VirtualKeyCode;
+
+use crate::audio::{AudioConfig, AudioSink};
+use crate::framebuffer::FrameBuffer;
+use crate::overlay::Rect;
+
+pub mod bezel;
+pub mod blend;
+pub mod geometry;
+pub mod palette;
+pub mod redraw;
+#[cfg(feature = "sdl2")]
+pub mod sdl;
+pub mod shader;
+#[cfg(all(unix, feature = "tui"))]
+pub mod tui;
+#[cfg(feature = "wgpu")]
+pub mod wgpu;
+
+/// Input events, translated to the key codes the keymap uses whatever the backend
+pub enum Input {
+    Key {
+        keycode: VirtualKeyCode,
+        pressed: bool,
+        /// the game controller a button was pressed on, in the order they were connected.
+        /// None for the keyboard
+        controller: Option<usize>,
+    },
+    /// the mouse moved over the window, None when it left. Frontends without a mouse never
+    /// send it
+    Cursor(Option<Cursor>),
+    /// the window was closed
+    Close,
+}
+
+/// Where the mouse is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cursor {
+    /// in the coordinates of `Rect`
+    pub position: [f32; 2],
+    /// the pixel of the last frame under it, None outside of the screen
+    pub pixel: Option<(u32, u32)>,
+}
+
+/// A window the emulator presents frames to and receives input from
+pub trait Frontend {
+    /// Presents a frame with the overlay on top.
+    /// `dirty` holds the parts of the frame that changed since the last one drawn, None when
+    /// all of it may have. Frontends that redraw everything anyway can ignore it, the others
+    /// still redraw more when their own output changed, e.g. with blending or a new palette
+    fn draw(&mut self, screen: FrameBuffer, dirty: Option<&[Region]>, overlay: &[Rect]) -> Result<(), Error>;
+
+    /// Input received since the last call
+    fn poll_input(&mut self) -> Vec<Input>;
+
+    /// Sleeps until input arrives or `timeout` passes, for frontends whose event loop can
+    /// wait for a deadline. The input is returned by the next `poll_input`. Returns false if
+    /// the frontend did not wait, the run loop sleeps on its own then
+    fn wait_input(&mut self, _timeout: Duration) -> bool {
+        false
+    }
+
+    fn set_palette(&mut self, palette: palette::Palette);
+
+    fn set_anti_flicker(&mut self, settings: blend::AntiFlicker);
+
+    fn set_tone(&mut self, tone: palette::Tone);
+
+    /// How the screen is fitted into the window
+    fn set_scaling(&mut self, scaling: Scaling);
+
+    /// Image drawn around or over the screen, None to remove it
+    fn set_bezel(&mut self, _bezel: Option<bezel::Bezel>) {}
+
+    /// Draws the screen with `shader`, None for the built-in one. Fails if the frontend has no
+    /// GLSL shaders or the sources do not compile, the screen is drawn as before then
+    fn set_shader(&mut self, shader: Option<shader::Shader>) -> Result<(), Error> {
+        match shader {
+            Some(_) => bail!("Only the glium frontend runs custom shaders"),
+            None => Ok(()),
+        }
+    }
+
+    /// Opens a second window for the debugger, returns false if the frontend has only one
+    fn open_debug_window(&mut self) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn close_debug_window(&mut self) {}
+
+    /// Whether the debugger window is open, the user can close it
+    fn debug_window_open(&self) -> bool {
+        false
+    }
+
+    /// Draws the debugger window, in the coordinates of `Rect`
+    fn draw_debug_window(&mut self, _rects: &[Rect]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Where the sound goes, None for frontends without audio
+    fn audio_sink(&mut self) -> Option<&mut dyn AudioSink> {
+        None
+    }
+
+    /// Refresh rate of the monitor the window is on, if the backend can tell
+    fn refresh_rate(&self) -> Option<f64> {
+        None
+    }
+
+    /// Whether the overlay is drawn, false when it is ignored
+    fn draws_overlay(&self) -> bool {
+        true
+    }
+
+    /// Where the window is and how large, None for frontends without a window
+    fn geometry(&self) -> Option<geometry::WindowGeometry> {
+        None
+    }
+
+    /// Puts the window back as `geometry` says, the size only if `size`
+    fn set_geometry(&mut self, _geometry: &geometry::WindowGeometry, _size: bool) {}
+
+    fn set_fullscreen(&mut self, _fullscreen: bool) {}
+
+    /// Names the window, for frontends that have one
+    fn set_title(&mut self, _title: &str) {}
+
+    /// Text on the host clipboard, for pasting hex keys
+    fn clipboard_text(&mut self) -> Result<String, Error> {
+        crate::clipboard::read()
+    }
+}
+
+/// Part of a frame, in pixels from the top left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Area of the window the screen is drawn to, in pixels from the top left corner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Where the last frame was drawn, to tell which of its pixels the mouse is over
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub window: (u32, u32),
+    /// the area the screen took, all of the window unless scaled by whole multiples or in a bezel
+    pub screen: Viewport,
+    pub frame: (u32, u32),
+}
+
+impl Layout {
+    pub fn new(window: (u32, u32), screen: Option<Viewport>, frame: (u32, u32)) -> Self {
+        let screen = screen.unwrap_or(Viewport {
+            x: 0,
+            y: 0,
+            width: window.0,
+            height: window.1,
+        });
+        Layout { window, screen, frame }
+    }
+
+    /// The cursor at (`x`, `y`) in pixels from the top left corner of the window
+    pub fn cursor(&self, x: f64, y: f64) -> Cursor {
+        let position = [
+            (x / f64::from(self.window.0.max(1))) as f32,
+            (y / f64::from(self.window.1.max(1))) as f32,
+        ];
+        let (x, y) = (x - f64::from(self.screen.x), y - f64::from(self.screen.y));
+        let (width, height) = (f64::from(self.screen.width), f64::from(self.screen.height));
+        let pixel = if x >= 0.0 && y >= 0.0 && x < width && y < height {
+            Some((
+                (x / width * f64::from(self.frame.0)) as u32,
+                (y / height * f64::from(self.frame.1)) as u32,
+            ))
+        } else {
+            None
+        };
+        Cursor { position, pixel }
+    }
+}
+
+/// How the screen is fitted into the window, or into the screen area of a bezel
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Scaling {
+    /// by whole multiples only, see `integer_viewport`
+    pub integer: bool,
+    /// height of a pixel over its width, None to stretch the screen over all of the area
+    pub pixel_aspect: Option<f64>,
+    /// the largest resolution the game switched to, see `switch_mode`
+    pub fit: Option<(u32, u32)>,
+}
+
+impl Scaling {
+    /// Where the screen goes in `area`, None for all of it
+    pub fn viewport(self, area: (u32, u32), image: (u32, u32)) -> Option<Viewport> {
+        let image = self.fit.filter(|&fit| covers(fit, image)).unwrap_or(image);
+        match (self.integer, self.pixel_aspect) {
+            (false, None) => None,
+            (true, aspect) => Some(integer_viewport(area, image, aspect.unwrap_or(1.0))),
+            (false, Some(aspect)) => Some(aspect_viewport(area, image, aspect)),
+        }
+    }
+
+    /// Fits the screen as if it was always in the largest resolution seen, so a game switching
+    /// between the 64x32 and 128x64 modes keeps the same area. Whole multiples of 128x64 are
+    /// whole multiples of 64x32 too, the other way round the screen would jump in size with
+    /// integer scaling. Returns true when `image` is larger than the ones before
+    pub fn switch_mode(&mut self, image: (u32, u32)) -> bool {
+        match self.fit {
+            Some(fit) if covers(fit, image) => false,
+            _ => {
+                self.fit = Some(image);
+                true
+            }
+        }
+    }
+}
+
+/// whether `image` scaled by a whole number is `fit`
+fn covers(fit: (u32, u32), image: (u32, u32)) -> bool {
+    image.0 > 0 && image.1 > 0 && fit.0.is_multiple_of(image.0) && fit.1.is_multiple_of(image.1) && fit.0 / image.0 == fit.1 / image.1
+}
+
+/// Reads a pixel aspect ratio as `width:height` (`1:1.2`) or as the height alone (`1.2`)
+pub fn parse_pixel_aspect(text: &str) -> Result<f64, Error> {
+    let number = |part: &str| part.trim().parse::<f64>().map_err(|_| format_err!("invalid pixel aspect ratio: {}", text));
+    let ratio = match text.split_once(':') {
+        Some((width, height)) => number(height)? / number(width)?,
+        None => number(text)?,
+    };
+    if !(0.25..=4.0).contains(&ratio) {
+        bail!("pixel aspect ratio {} is not between 1:0.25 and 1:4", text);
+    }
+    Ok(ratio)
+}
+
+/// The largest area with the proportions of `image` drawn with pixels `aspect` times as tall
+/// as they are wide that fits in `window`, centered
+pub fn aspect_viewport(window: (u32, u32), image: (u32, u32), aspect: f64) -> Viewport {
+    let (image_width, image_height) = (f64::from(image.0.max(1)), f64::from(image.1.max(1)) * aspect);
+    let scale = (f64::from(window.0) / image_width).min(f64::from(window.1) / image_height);
+    let width = ((image_width * scale).round() as u32).clamp(1, window.0.max(1));
+    let height = ((image_height * scale).round() as u32).clamp(1, window.1.max(1));
+
+    Viewport {
+        x: window.0.saturating_sub(width) / 2,
+        y: window.1.saturating_sub(height) / 2,
+        width,
+        height,
+    }
+}
+
+/// The largest whole multiple of `image` that fits in `window`, centered, with pixels `aspect`
+/// times as tall as they are wide. Nearest-neighbor scaling by fractional amounts makes some
+/// pixels wider than others, which shimmers as sprites move. Only the width is a whole multiple
+/// with non-square pixels
+pub fn integer_viewport(window: (u32, u32), image: (u32, u32), aspect: f64) -> Viewport {
+    let (image_width, image_height) = (image.0.max(1), f64::from(image.1.max(1)) * aspect);
+    let scale = (window.0 / image_width).min((f64::from(window.1) / image_height) as u32).max(1);
+    let (width, height) = (image.0 * scale, (image_height * f64::from(scale)).round() as u32);
+
+    Viewport {
+        x: window.0.saturating_sub(width) / 2,
+        y: window.1.saturating_sub(height) / 2,
+        width,
+        height,
+    }
+}
+
+/// How finished frames are handed to the screen, `present_mode` in `[display]`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresentMode {
+    /// whatever the renderer does by default: vsync with wgpu and SDL2, immediate with glium
+    #[default]
+    Auto,
+    /// waits for the vertical blank, never tears
+    Vsync,
+    /// replaces a frame still waiting for the vertical blank with a newer one: never tears,
+    /// with less latency than vsync. Only wgpu has it, the others use vsync instead
+    Mailbox,
+    /// shows each frame at once, the least latency but frames can tear
+    Immediate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Renderer {
+    Glium,
+    Wgpu,
+    Sdl2,
+    /// draws in the terminal, the fallback when no window can be opened
+    Tui,
+}
+
+impl Renderer {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "glium" => Some(Renderer::Glium),
+            "wgpu" => Some(Renderer::Wgpu),
+            "sdl2" => Some(Renderer::Sdl2),
+            "tui" => Some(Renderer::Tui),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Renderer {
+    /// wgpu if it was compiled in, since glium does not work on some modern setups, then the
+    /// first other one that was
+    fn default() -> Self {
+        if cfg!(feature = "wgpu") {
+            Renderer::Wgpu
+        } else if cfg!(feature = "gui") {
+            Renderer::Glium
+        } else if cfg!(feature = "sdl2") {
+            Renderer::Sdl2
+        } else {
+            Renderer::Tui
+        }
+    }
+}
+
+/// Opens a window of the given size with the selected renderer
+#[cfg_attr(not(feature = "sdl2"), allow(unused_variables))]
+pub fn create(
+    renderer: Renderer,
+    dimensions: (u32, u32),
+    present: PresentMode,
+    audio: &AudioConfig,
+) -> Result<Box<dyn Frontend>, Error> {
+    log::debug!(target: "display", "Opening a {}x{} window with the {:?} renderer", dimensions.0, dimensions.1, renderer);
+    if present == PresentMode::Mailbox && matches!(renderer, Renderer::Glium | Renderer::Sdl2) {
+        log::warn!(target: "display", "The {:?} renderer cannot present in mailbox mode, using vsync", renderer);
+    }
+
+    match renderer {
+        // winit panics instead of returning an error when there is no display server
+        #[cfg(feature = "gui")]
+        Renderer::Glium => Ok(Box::new(catch_panic(|| crate::window::Window::new(dimensions, present))?)),
+        #[cfg(not(feature = "gui"))]
+        Renderer::Glium => bail!("The glium renderer is not available, rebuild with `--features gui`"),
+
+        #[cfg(feature = "wgpu")]
+        Renderer::Wgpu => Ok(Box::new(wgpu::WgpuWindow::new(dimensions, present)?)),
+        #[cfg(not(feature = "wgpu"))]
+        Renderer::Wgpu => bail!("The wgpu renderer is not available, rebuild with `--features wgpu`"),
+
+        #[cfg(feature = "sdl2")]
+        Renderer::Sdl2 => Ok(Box::new(sdl::SdlWindow::new(dimensions, present, audio)?)),
+        #[cfg(not(feature = "sdl2"))]
+        Renderer::Sdl2 => bail!("The SDL2 frontend is not available, rebuild with `--features sdl2`"),
+
+        #[cfg(all(unix, feature = "tui"))]
+        Renderer::Tui => Ok(Box::new(tui::TuiWindow::new()?)),
+        #[cfg(all(unix, not(feature = "tui")))]
+        Renderer::Tui => bail!("The terminal frontend is not available, rebuild with `--features tui`"),
+        #[cfg(not(unix))]
+        Renderer::Tui => bail!("The terminal frontend is only available on Unix"),
+    }
+}
+
+/// Whether `Renderer::Tui` can be used
+pub fn terminal_available() -> bool {
+    #[cfg(all(unix, feature = "tui"))]
+    return tui::available();
+    #[cfg(not(all(unix, feature = "tui")))]
+    return false;
+}
+
+/// Runs `f`, turning a panic into an error without printing it
+#[cfg(feature = "gui")]
+fn catch_panic<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f));
+    std::panic::set_hook(hook);
+
+    match res {
+        Ok(res) => res,
+        Err(panic) => match panic.downcast_ref::<String>() {
+            Some(message) => bail!("{}", message),
+            None => bail!("{}", panic.downcast_ref::<&str>().unwrap_or(&"unknown panic")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_screen_area_when_the_mode_switches() {
+        let mut scaling = Scaling {
+            integer: true,
+            ..Default::default()
+        };
+        let window = (760, 400);
+        assert!(scaling.switch_mode((64, 32)));
+        assert_eq!(scaling.viewport(window, (64, 32)).unwrap().width, 704);
+
+        // 11 times 64 is no whole multiple of 128, both modes take 5 times 128 from now on
+        assert!(scaling.switch_mode((128, 64)));
+        let hires = scaling.viewport(window, (128, 64));
+        assert_eq!(hires.unwrap().width, 640);
+        assert!(!scaling.switch_mode((64, 32)));
+        assert_eq!(scaling.viewport(window, (64, 32)), hires);
+
+        // the first frame in the new mode is not blended with the last one of the old
+        let mut blender = blend::FrameBlender::default();
+        blender.set_settings(blend::AntiFlicker {
+            mode: blend::BlendMode::Or,
+            frames: 2,
+            ghosting: 0.5,
+        });
+        blender.blend(vec![255; 64 * 32]);
+        assert_eq!(blender.blend(vec![0; 128 * 64]), vec![0; 128 * 64]);
+    }
+}
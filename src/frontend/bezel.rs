@@ -0,0 +1,107 @@
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::{Scaling, Viewport};
+use crate::png;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+    /// over the screen, the screen shows through transparent parts
+    #[default]
+    Front,
+    Back,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BezelConfig {
+    /// PNG file, relative to the directory of the config
+    pub image: PathBuf,
+    /// x, y, width and height of the screen in image pixels, the whole image if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub screen: Option<[u32; 4]>,
+    #[serde(default)]
+    pub layer: Layer,
+}
+
+/// An image drawn around or over the screen, scaled with it to fit the window
+pub struct Bezel {
+    pub width: u32,
+    pub height: u32,
+    /// 4 bytes per pixel, top row first
+    pub rgba: Vec<u8>,
+    pub screen: Viewport,
+    pub layer: Layer,
+}
+
+impl Bezel {
+    pub fn load(config: &BezelConfig, dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(&config.image);
+        let data = std::fs::read(&path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+        let image = png::decode(&data).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+
+        let screen = match config.screen {
+            Some([x, y, width, height]) => {
+                if width == 0 || height == 0 || x + width > image.width || y + height > image.height {
+                    bail!(
+                        "{}: screen area {}x{} at {},{} is outside the {}x{} image",
+                        path.display(),
+                        width,
+                        height,
+                        x,
+                        y,
+                        image.width,
+                        image.height
+                    );
+                }
+                Viewport { x, y, width, height }
+            }
+            None => Viewport {
+                x: 0,
+                y: 0,
+                width: image.width,
+                height: image.height,
+            },
+        };
+
+        Ok(Bezel {
+            width: image.width,
+            height: image.height,
+            rgba: image.rgba,
+            screen,
+            layer: config.layer,
+        })
+    }
+
+    /// Where the bezel and the screen go in `window`. The bezel is as large as fits with its
+    /// aspect ratio kept, and the screen fills its screen area, or the part of it `scaling`
+    /// says
+    pub fn layout(&self, window: (u32, u32), image: (u32, u32), scaling: Scaling) -> (Viewport, Viewport) {
+        let scale = (window.0 as f64 / self.width as f64).min(window.1 as f64 / self.height as f64);
+        let (width, height) = ((self.width as f64 * scale) as u32, (self.height as f64 * scale) as u32);
+        let bezel = Viewport {
+            x: window.0.saturating_sub(width) / 2,
+            y: window.1.saturating_sub(height) / 2,
+            width,
+            height,
+        };
+
+        let area = Viewport {
+            x: bezel.x + (self.screen.x as f64 * scale) as u32,
+            y: bezel.y + (self.screen.y as f64 * scale) as u32,
+            width: ((self.screen.width as f64 * scale) as u32).max(1),
+            height: ((self.screen.height as f64 * scale) as u32).max(1),
+        };
+        let screen = match scaling.viewport((area.width, area.height), image) {
+            Some(inner) => Viewport {
+                x: area.x + inner.x,
+                y: area.y + inner.y,
+                ..inner
+            },
+            None => area,
+        };
+
+        (bezel, screen)
+    }
+}
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    Off,
+    /// a pixel is lit if it was lit in any of the recent frames
+    Or,
+    /// weighted average of the recent frames, newer frames weigh more
+    Average,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AntiFlicker {
+    pub mode: BlendMode,
+    /// how many recent frames are blended together
+    pub frames: usize,
+    /// LCD response time, the share of its last shade a pixel keeps each frame: pixels fade in
+    /// and out over a few frames. 0 is off, applied after `mode`
+    pub ghosting: f32,
+}
+
+/// more would keep pixels from ever fully switching
+const MAX_GHOSTING: f32 = 0.95;
+
+impl Default for AntiFlicker {
+    fn default() -> Self {
+        AntiFlicker {
+            mode: BlendMode::Off,
+            frames: 2,
+            ghosting: 0.0,
+        }
+    }
+}
+
+impl AntiFlicker {
+    /// Frames it takes for the output to stop changing after the screen does
+    pub fn settle_frames(&self) -> usize {
+        let blend = if self.mode == BlendMode::Off { 0 } else { self.frames };
+        let ghosting = self.ghosting.clamp(0.0, MAX_GHOSTING);
+        let fade = if ghosting > 0.0 {
+            // until less than one shade of the difference is left
+            ((1.0f32 / 255.0).ln() / ghosting.ln()).ceil() as usize
+        } else {
+            0
+        };
+        blend + fade
+    }
+}
+
+/// Blends presented frames together to hide the flicker of sprites that are
+/// erased and redrawn every frame
+#[derive(Default)]
+pub struct FrameBlender {
+    settings: AntiFlicker,
+    history: VecDeque<Vec<u8>>,
+    /// shade of every pixel as shown with ghosting
+    shown: Vec<f32>,
+}
+
+impl FrameBlender {
+    pub fn set_settings(&mut self, settings: AntiFlicker) {
+        self.settings = settings;
+        self.history.clear();
+        self.shown.clear();
+    }
+
+    /// see `AntiFlicker::settle_frames`, 0 when frames are shown as they are
+    pub fn settle_frames(&self) -> usize {
+        self.settings.settle_frames()
+    }
+
+    pub fn blend(&mut self, frame: Vec<u8>) -> Vec<u8> {
+        let frame = self.blend_frames(frame);
+        self.ghost(frame)
+    }
+
+    /// Moves every pixel part of the way from the shade it was shown at to the one in `frame`
+    fn ghost(&mut self, mut frame: Vec<u8>) -> Vec<u8> {
+        let ghosting = self.settings.ghosting.clamp(0.0, MAX_GHOSTING);
+        if ghosting <= 0.0 {
+            return frame;
+        }
+
+        if self.shown.len() != frame.len() {
+            self.shown = frame.iter().map(|&pixel| f32::from(pixel)).collect();
+        }
+        for (shown, pixel) in self.shown.iter_mut().zip(frame.iter_mut()) {
+            *shown = *shown * ghosting + f32::from(*pixel) * (1.0 - ghosting);
+            *pixel = shown.round() as u8;
+        }
+
+        frame
+    }
+
+    fn blend_frames(&mut self, frame: Vec<u8>) -> Vec<u8> {
+        if self.settings.mode == BlendMode::Off || self.settings.frames < 2 {
+            return frame;
+        }
+
+        if self.history.front().is_some_and(|prev| prev.len() != frame.len()) {
+            self.history.clear();
+        }
+        self.history.push_front(frame);
+        self.history.truncate(self.settings.frames);
+
+        let mut out = vec![0u8; self.history[0].len()];
+        match self.settings.mode {
+            BlendMode::Off => unreachable!(),
+
+            BlendMode::Or => {
+                for frame in &self.history {
+                    for (out, pixel) in out.iter_mut().zip(frame) {
+                        *out = (*out).max(*pixel);
+                    }
+                }
+            }
+
+            BlendMode::Average => {
+                let count = self.history.len() as u32;
+                let total_weight = count * (count + 1) / 2;
+                for (i, out) in out.iter_mut().enumerate() {
+                    let sum = self
+                        .history
+                        .iter()
+                        .enumerate()
+                        .map(|(age, frame)| u32::from(frame[i]) * (count - age as u32))
+                        .sum::<u32>();
+                    *out = (sum / total_weight) as u8;
+                }
+            }
+        }
+
+        out
+    }
+}
@@ -0,0 +1,62 @@
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File in the data directory the geometry of the last window is kept in
+pub const FILE_NAME: &str = "window.toml";
+
+/// pixels of the window's corner that must be on a monitor to put it back there
+const VISIBLE: i32 = 32;
+
+/// Size, position and fullscreen state of the window, kept from one run to the next.
+/// In physical pixels, the position of the outer corner on the desktop
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub width: u32,
+    pub height: u32,
+    /// None if the backend could not tell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<(i32, i32)>,
+    #[serde(default)]
+    pub fullscreen: bool,
+}
+
+/// Part of the desktop a monitor shows, in physical pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Monitor {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Monitor {
+    fn contains(&self, (x, y): (i32, i32)) -> bool {
+        let (right, bottom) = (self.x + self.width as i32, self.y + self.height as i32);
+        x >= self.x && y >= self.y && x + VISIBLE <= right && y + VISIBLE <= bottom
+    }
+}
+
+impl WindowGeometry {
+    /// The saved geometry, None if there is none or it can not be read
+    pub fn load(dir: &Path) -> Option<Self> {
+        let path = dir.join(FILE_NAME);
+        let src = std::fs::read_to_string(&path).ok()?;
+        toml::from_str(&src)
+            .map_err(|e| log::warn!(target: "display", "Ignoring {}: {}", path.display(), e))
+            .ok()
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), Error> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(FILE_NAME), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The position if enough of the window's corner is on one of `monitors`. A monitor that
+    /// was unplugged or rearranged leaves the window where the system puts new ones
+    pub fn position_on(&self, monitors: &[Monitor]) -> Option<(i32, i32)> {
+        self.position
+            .filter(|&position| monitors.iter().any(|monitor| monitor.contains(position)))
+    }
+}
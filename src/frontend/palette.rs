@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// RGB color, written as "#RRGGBB" in the config
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Color(pub [u8; 3]);
+
+impl Color {
+    pub fn to_f32(self) -> [f32; 3] {
+        let [r, g, b] = self.0;
+        [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0]
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let hex = s.strip_prefix('#').unwrap_or(&s);
+        let value = match u32::from_str_radix(hex, 16) {
+            Ok(value) if hex.len() == 6 => value,
+            _ => return Err(format!("invalid color: {}, expected #RRGGBB", s)),
+        };
+
+        Ok(Color([(value >> 16) as u8, (value >> 8) as u8, value as u8]))
+    }
+}
+
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        let [r, g, b] = color.0;
+        format!("#{:02X}{:02X}{:02X}", r, g, b)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    Classic,
+    HighContrast,
+    /// colorblind-safe colors from the Okabe-Ito palette, avoiding red/green contrasts
+    Deuteranopia,
+    Protanopia,
+}
+
+impl Preset {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let preset = match name {
+            "classic" => Preset::Classic,
+            "high-contrast" => Preset::HighContrast,
+            "deuteranopia" => Preset::Deuteranopia,
+            "protanopia" => Preset::Protanopia,
+            _ => return None,
+        };
+        Some(preset)
+    }
+}
+
+/// Either a preset name or a list of 2 or 4 colors
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PaletteConfig {
+    Preset(Preset),
+    Custom(Vec<Color>),
+}
+
+impl Default for PaletteConfig {
+    fn default() -> Self {
+        PaletteConfig::Preset(Preset::Classic)
+    }
+}
+
+/// Colors of the display: background, first plane, second plane, and both planes lit.
+/// Plain CHIP-8 only uses the first two
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    pub fn preset(preset: Preset) -> Self {
+        let colors = match preset {
+            Preset::Classic => [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555],
+            Preset::HighContrast => [0x000000, 0xFFFF00, 0x00FFFF, 0xFFFFFF],
+            Preset::Deuteranopia => [0x000000, 0xE69F00, 0x56B4E9, 0xF0E442],
+            Preset::Protanopia => [0x000000, 0xF0E442, 0x0072B2, 0xFFFFFF],
+        };
+
+        Palette {
+            colors: [
+                rgb(colors[0]),
+                rgb(colors[1]),
+                rgb(colors[2]),
+                rgb(colors[3]),
+            ],
+        }
+    }
+
+    pub fn from_config(config: &PaletteConfig) -> Result<Self, String> {
+        match config {
+            PaletteConfig::Preset(preset) => Ok(Palette::preset(*preset)),
+            PaletteConfig::Custom(colors) => match *colors.as_slice() {
+                [background, foreground] => Ok(Palette {
+                    colors: [background, foreground, foreground, foreground],
+                }),
+                [a, b, c, d] => Ok(Palette { colors: [a, b, c, d] }),
+                _ => Err(format!("a palette needs 2 or 4 colors, got {}", colors.len())),
+            },
+        }
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    /// Color of a screen pixel, blending from the background at 0 to the foreground at 255
+    pub fn apply(&self, value: u8) -> [u8; 3] {
+        let (background, foreground) = (self.colors[0].0, self.colors[1].0);
+        let value = u32::from(value);
+        let mut color = [0; 3];
+        for (i, channel) in color.iter_mut().enumerate() {
+            let (bg, fg) = (u32::from(background[i]), u32::from(foreground[i]));
+            *channel = ((bg * (255 - value) + fg * value) / 255) as u8;
+        }
+        color
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::preset(Preset::Classic)
+    }
+}
+
+const BRIGHTNESS: (f64, f64) = (0.2, 2.0);
+const GAMMA: (f64, f64) = (0.4, 3.0);
+
+/// Brightness and gamma of the colors sent to the monitor, applied after the palette and the
+/// anti-flicker blend, for palettes that look washed out or too dark on some monitors
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tone {
+    /// multiplies every channel, 1 leaves the colors as they are
+    pub brightness: f64,
+    /// above 1 lightens the shades between off and full, below 1 darkens them
+    pub gamma: f64,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            brightness: 1.0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Tone {
+    /// Both within the range the hotkeys go through, out of range values from the config
+    /// are clamped
+    pub fn clamped(self) -> Self {
+        Tone {
+            brightness: self.brightness.clamp(BRIGHTNESS.0, BRIGHTNESS.1),
+            gamma: self.gamma.clamp(GAMMA.0, GAMMA.1),
+        }
+    }
+
+    /// Adds to the brightness and gamma, rounded to tenths so repeated steps stay even
+    pub fn adjust(self, brightness: f64, gamma: f64) -> Self {
+        let round = |value: f64| (value * 10.0).round() / 10.0;
+        Tone {
+            brightness: round(self.brightness + brightness),
+            gamma: round(self.gamma + gamma),
+        }
+        .clamped()
+    }
+
+    /// A channel from 0 to 1 with the tone applied, what the shaders compute
+    pub fn apply(self, channel: f64) -> f64 {
+        let tone = self.clamped();
+        (channel.max(0.0).powf(1.0 / tone.gamma) * tone.brightness).min(1.0)
+    }
+
+    pub fn apply_f32(self, color: [f32; 3]) -> [f32; 3] {
+        color.map(|channel| self.apply(f64::from(channel)) as f32)
+    }
+
+    pub fn apply_rgb(self, color: [u8; 3]) -> [u8; 3] {
+        if self == Tone::default() {
+            return color;
+        }
+        color.map(|channel| (self.apply(f64::from(channel) / 255.0) * 255.0).round() as u8)
+    }
+}
+
+fn rgb(value: u32) -> Color {
+    Color([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
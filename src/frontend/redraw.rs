@@ -0,0 +1,90 @@
+use super::Region;
+use crate::overlay::Rect;
+
+/// redraws skipped in a row before one is done anyway, in case the window was damaged
+const MAX_SKIPPED: u32 = 60;
+
+/// Skips redraws of frames identical to the last presented one, for `power.skip_unchanged_frames`
+#[derive(Default)]
+pub struct RedrawFilter {
+    last: Option<(Vec<u8>, Vec<Rect>)>,
+    /// identical frames drawn or skipped since the last change
+    unchanged: u32,
+    /// identical frames that are still drawn, so blended frames (`AntiFlicker`) catch up
+    settle: u32,
+}
+
+impl RedrawFilter {
+    pub fn set_settle(&mut self, frames: usize) {
+        self.settle = frames as u32;
+        self.last = None;
+    }
+
+    /// Whether the frame has to be drawn
+    pub fn needs_redraw(&mut self, screen: &[u8], overlay: &[Rect]) -> bool {
+        let unchanged = match &self.last {
+            Some((last_screen, last_overlay)) => last_screen[..] == *screen && last_overlay[..] == *overlay,
+            None => false,
+        };
+        if !unchanged {
+            self.last = Some((screen.to_vec(), overlay.to_vec()));
+            self.unchanged = 0;
+            return true;
+        }
+
+        self.unchanged += 1;
+        self.unchanged <= self.settle || self.unchanged.is_multiple_of(MAX_SKIPPED)
+    }
+}
+
+/// Finds the parts of each frame that differ from the one before, for `Frontend::draw`
+#[derive(Default)]
+pub struct DirtyTracker {
+    last: Option<(Vec<u8>, u32)>,
+}
+
+impl DirtyTracker {
+    /// Forgets the last frame, after something else was drawn
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    /// Bounding rectangles of runs of rows that changed since the last frame, None when the
+    /// frame has nothing to be compared with
+    pub fn regions(&mut self, frame: &[u8], width: u32) -> Option<Vec<Region>> {
+        let regions = match &self.last {
+            Some((last, last_width)) if *last_width == width && last.len() == frame.len() && width > 0 => {
+                let width = width as usize;
+                let mut regions: Vec<Region> = Vec::new();
+                let rows = last.chunks(width).zip(frame.chunks(width));
+                for (y, (last, row)) in rows.enumerate() {
+                    let changed = |x: &usize| last[*x] != row[*x];
+                    let (left, right) = match ((0..width).find(changed), (0..width).rev().find(changed)) {
+                        (Some(left), Some(right)) => (left as u32, right as u32 + 1),
+                        _ => continue,
+                    };
+                    match regions.last_mut() {
+                        // continues the run that ended on the row above
+                        Some(region) if region.y + region.height == y as u32 => {
+                            let end = (region.x + region.width).max(right);
+                            region.x = region.x.min(left);
+                            region.width = end - region.x;
+                            region.height += 1;
+                        }
+                        _ => regions.push(Region {
+                            x: left,
+                            y: y as u32,
+                            width: right - left,
+                            height: 1,
+                        }),
+                    }
+                }
+                Some(regions)
+            }
+            _ => None,
+        };
+
+        self.last = Some((frame.to_vec(), width));
+        regions
+    }
+}
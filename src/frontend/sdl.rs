@@ -0,0 +1,433 @@
+use failure::Error;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::{FullscreenType, Window, WindowPos};
+
+use super::geometry::{Monitor, WindowGeometry};
+use super::{bezel, blend, palette, Frontend, Input, Layout, PresentMode, Region, Scaling, Viewport};
+use crate::audio::{self, AudioConfig, AudioSink};
+use crate::framebuffer::FrameBuffer;
+use crate::input;
+use crate::overlay::Rect;
+
+/// Plays the samples the emulation thread queued, silence if it falls behind
+struct Output {
+    samples: audio::Consumer,
+}
+
+impl AudioCallback for Output {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.samples.fill(out);
+    }
+}
+
+struct Sound {
+    /// kept open to be able to reopen the device after it is unplugged
+    subsystem: sdl2::AudioSubsystem,
+    config: AudioConfig,
+    /// kept to hand to the buzzer of a reopened device
+    sample: Option<audio::Sample>,
+    device: Option<(AudioDevice<Output>, audio::Buzzer)>,
+    playing: bool,
+}
+
+impl Sound {
+    fn new(subsystem: sdl2::AudioSubsystem, config: AudioConfig) -> Self {
+        let mut sound = Sound {
+            subsystem,
+            config,
+            sample: None,
+            device: None,
+            playing: false,
+        };
+        sound.open();
+        sound
+    }
+
+    /// Opens the default playback device, the emulator stays silent until the next
+    /// device is plugged in if that fails
+    fn open(&mut self) {
+        let desired = AudioSpecDesired {
+            freq: Some(self.config.sample_rate as i32),
+            channels: Some(1),
+            samples: Some(self.config.buffer_samples),
+        };
+
+        let mut buzzer = None;
+        let (config, sample) = (&self.config, self.sample.as_ref());
+        let device = self.subsystem.open_playback(None, &desired, |spec| {
+            let (mut new_buzzer, samples) = audio::Buzzer::new(config, spec.freq as u32);
+            new_buzzer.set_sample(sample);
+            buzzer = Some(new_buzzer);
+            Output { samples }
+        });
+
+        match (device, buzzer) {
+            (Ok(device), Some(buzzer)) => {
+                log::debug!(target: "audio", "Opened the audio device at {} Hz", device.spec().freq);
+                device.resume();
+                self.device = Some((device, buzzer));
+            }
+            (Err(e), _) => {
+                log::warn!(target: "audio", "Sound is disabled, could not open the audio device: {}", e);
+                self.device = None;
+            }
+            (Ok(_), None) => self.device = None,
+        }
+    }
+
+    fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::AudioDeviceRemoved { iscapture: false, .. } => {
+                log::warn!(target: "audio", "Audio device removed");
+                self.device = None;
+            }
+            Event::AudioDeviceAdded { iscapture: false, .. } if self.device.is_none() => {
+                log::info!(target: "audio", "Audio device added, reopening");
+                self.open();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl AudioSink for Sound {
+    fn sample_rate(&self) -> u32 {
+        match &self.device {
+            Some((_, buzzer)) => buzzer.sample_rate(),
+            None => self.config.sample_rate,
+        }
+    }
+
+    fn start_beep(&mut self) {
+        if !self.playing {
+            log::debug!(target: "audio", "Sound on");
+            self.playing = true;
+        }
+    }
+
+    fn stop_beep(&mut self) {
+        if self.playing {
+            log::debug!(target: "audio", "Sound off");
+            self.playing = false;
+        }
+    }
+
+    fn queue_samples(&mut self, samples: &[f32]) {
+        if let Some((_, buzzer)) = &mut self.device {
+            buzzer.queue(samples);
+        }
+    }
+
+    fn set_beep(&mut self, sample: Option<&audio::Sample>) {
+        if let Some((_, buzzer)) = &mut self.device {
+            buzzer.set_sample(sample);
+        }
+        self.sample = sample.cloned();
+    }
+
+    fn update(&mut self) {
+        if let Some((_, buzzer)) = &mut self.device {
+            buzzer.fill(self.playing);
+        }
+    }
+}
+
+/// The SDL2 frontend: video, sound and game controllers without OpenGL
+pub struct SdlWindow {
+    canvas: Canvas<Window>,
+    events: sdl2::EventPump,
+    /// none if the audio subsystem could not be initialized
+    sound: Option<Sound>,
+    controller_subsystem: Option<sdl2::GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+    blender: blend::FrameBlender,
+    palette: palette::Palette,
+    tone: palette::Tone,
+    scaling: Scaling,
+    /// where the last frame went, None before the first
+    layout: Option<Layout>,
+    bezel: Option<bezel::Bezel>,
+}
+
+fn sdl_rect(viewport: Viewport) -> sdl2::rect::Rect {
+    sdl2::rect::Rect::new(viewport.x as i32, viewport.y as i32, viewport.width, viewport.height)
+}
+
+impl SdlWindow {
+    pub fn new(dimensions: (u32, u32), present: PresentMode, audio: &AudioConfig) -> Result<Self, Error> {
+        let sdl = sdl2::init().map_err(|e| format_err!("{}", e))?;
+        let video = sdl.video().map_err(|e| format_err!("{}", e))?;
+        let window = video
+            .window("Chip8", dimensions.0, dimensions.1)
+            .position_centered()
+            .resizable()
+            .build()?;
+        let canvas = match present {
+            PresentMode::Immediate => window.into_canvas().build()?,
+            // SDL has no mailbox, vsync is the closest without tearing
+            PresentMode::Auto | PresentMode::Vsync | PresentMode::Mailbox => window.into_canvas().present_vsync().build()?,
+        };
+        let events = sdl.event_pump().map_err(|e| format_err!("{}", e))?;
+
+        let sound = match sdl.audio() {
+            Ok(subsystem) => Some(Sound::new(subsystem, audio.clone())),
+            Err(e) => {
+                log::warn!(target: "audio", "Sound is disabled, could not initialize audio: {}", e);
+                None
+            }
+        };
+
+        Ok(SdlWindow {
+            canvas,
+            events,
+            sound,
+            controller_subsystem: sdl.game_controller().ok(),
+            controllers: Vec::new(),
+            blender: blend::FrameBlender::default(),
+            palette: palette::Palette::default(),
+            tone: palette::Tone::default(),
+            scaling: Scaling::default(),
+            layout: None,
+            bezel: None,
+        })
+    }
+
+    /// Screen as RGB24 with the palette applied, SDL renderers have no shaders
+    fn colorize(&self, data: &[u8]) -> Vec<u8> {
+        data.iter()
+            .flat_map(|value| self.tone.apply_rgb(self.palette.apply(*value)))
+            .collect()
+    }
+}
+
+impl Frontend for SdlWindow {
+    fn clipboard_text(&mut self) -> Result<String, Error> {
+        self.canvas.window().subsystem().clipboard().clipboard_text().map_err(|e| format_err!("{}", e))
+    }
+
+    fn draw(&mut self, screen: FrameBuffer, _dirty: Option<&[Region]>, overlay: &[Rect]) -> Result<(), Error> {
+        let (width, height) = (screen.width(), screen.height());
+        let data = self.blender.blend(screen.into_pixels());
+        let pixels = self.colorize(&data);
+
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture =
+            texture_creator.create_texture_static(PixelFormatEnum::RGB24, width, height)?;
+        texture.update(None, &pixels, width as usize * 3)?;
+
+        let [r, g, b] = self.tone.apply_rgb(self.palette.background().0);
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
+        self.canvas.clear();
+
+        let (window_width, window_height) = self.canvas.output_size().map_err(|e| format_err!("{}", e))?;
+        let window = (window_width, window_height);
+        // recreated every frame like the screen, textures can not outlive their creator
+        let (bezel, screen) = match &self.bezel {
+            Some(bezel) => {
+                let mut image = texture_creator.create_texture_static(PixelFormatEnum::RGBA32, bezel.width, bezel.height)?;
+                image.update(None, &bezel.rgba, bezel.width as usize * 4)?;
+                image.set_blend_mode(BlendMode::Blend);
+                let (area, screen) = bezel.layout(window, (width, height), self.scaling);
+                (Some((image, sdl_rect(area), bezel.layer)), Some(screen))
+            }
+            None => (None, self.scaling.viewport(window, (width, height))),
+        };
+        let target = screen.map(sdl_rect);
+        self.layout = Some(Layout::new(window, screen, (width, height)));
+
+        if let Some((image, area, bezel::Layer::Back)) = &bezel {
+            self.canvas.copy(image, None, *area).map_err(|e| format_err!("{}", e))?;
+        }
+        self.canvas
+            .copy(&texture, None, target)
+            .map_err(|e| format_err!("{}", e))?;
+        if let Some((image, area, bezel::Layer::Front)) = &bezel {
+            self.canvas.copy(image, None, *area).map_err(|e| format_err!("{}", e))?;
+        }
+
+        self.canvas.set_blend_mode(BlendMode::Blend);
+        for rect in overlay {
+            let [r, g, b, a] = rect.color;
+            let channel = |value: f32| (value * 255.0) as u8;
+            self.canvas
+                .set_draw_color(Color::RGBA(channel(r), channel(g), channel(b), channel(a)));
+
+            let x = (rect.pos[0] * window_width as f32) as i32;
+            let y = (rect.pos[1] * window_height as f32) as i32;
+            let right = ((rect.pos[0] + rect.size[0]) * window_width as f32) as i32;
+            let bottom = ((rect.pos[1] + rect.size[1]) * window_height as f32) as i32;
+            if right > x && bottom > y {
+                self.canvas
+                    .fill_rect(sdl2::rect::Rect::new(x, y, (right - x) as u32, (bottom - y) as u32))
+                    .map_err(|e| format_err!("{}", e))?;
+            }
+        }
+
+        self.canvas.present();
+
+        Ok(())
+    }
+
+    fn poll_input(&mut self) -> Vec<Input> {
+        let mut input = Vec::new();
+        for event in self.events.poll_iter() {
+            if let Some(sound) = &mut self.sound {
+                sound.handle_event(&event);
+            }
+
+            let (keycode, pressed, controller) = match event {
+                Event::Quit { .. } => {
+                    input.push(Input::Close);
+                    continue;
+                }
+                Event::MouseMotion { x, y, .. } => {
+                    // in window coordinates, the layout is in those of the output
+                    let (width, height) = self.canvas.window().size();
+                    let (output_width, output_height) = self.canvas.output_size().unwrap_or((width, height));
+                    let x = f64::from(x) * f64::from(output_width) / f64::from(width.max(1));
+                    let y = f64::from(y) * f64::from(output_height) / f64::from(height.max(1));
+                    input.push(Input::Cursor(self.layout.map(|layout| layout.cursor(x, y))));
+                    continue;
+                }
+                Event::Window {
+                    win_event: WindowEvent::Leave,
+                    ..
+                } => {
+                    input.push(Input::Cursor(None));
+                    continue;
+                }
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    ..
+                } => (input::sdl::scancode(scancode), true, None),
+                Event::KeyUp {
+                    scancode: Some(scancode),
+                    ..
+                } => (input::sdl::scancode(scancode), false, None),
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(controller) = self
+                        .controller_subsystem
+                        .as_ref()
+                        .and_then(|subsystem| subsystem.open(which).ok())
+                    {
+                        self.controllers.push(controller);
+                    }
+                    continue;
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.controllers
+                        .retain(|controller| controller.instance_id() != which);
+                    continue;
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    (input::sdl::button(button), true, controller_index(&self.controllers, which))
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    (input::sdl::button(button), false, controller_index(&self.controllers, which))
+                }
+                _ => continue,
+            };
+
+            if let Some(keycode) = keycode {
+                input.push(Input::Key {
+                    keycode,
+                    pressed,
+                    controller,
+                });
+            }
+        }
+
+        input
+    }
+
+    fn set_palette(&mut self, palette: palette::Palette) {
+        self.palette = palette;
+    }
+
+    fn set_anti_flicker(&mut self, settings: blend::AntiFlicker) {
+        self.blender.set_settings(settings);
+    }
+
+    fn set_tone(&mut self, tone: palette::Tone) {
+        self.tone = tone.clamped();
+    }
+
+    fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    fn set_bezel(&mut self, bezel: Option<bezel::Bezel>) {
+        self.bezel = bezel;
+    }
+
+    fn refresh_rate(&self) -> Option<f64> {
+        let mode = self.canvas.window().display_mode().ok()?;
+        // 0 means unknown
+        Some(f64::from(mode.refresh_rate)).filter(|&hz| hz > 0.0)
+    }
+
+    fn audio_sink(&mut self) -> Option<&mut dyn AudioSink> {
+        self.sound.as_mut().map(|sound| sound as &mut dyn AudioSink)
+    }
+
+    /// in SDL's window coordinates, which are the physical pixels unless the display is scaled
+    fn geometry(&self) -> Option<WindowGeometry> {
+        let window = self.canvas.window();
+        let (width, height) = window.size();
+        Some(WindowGeometry {
+            width,
+            height,
+            position: Some(window.position()),
+            fullscreen: window.fullscreen_state() != FullscreenType::Off,
+        })
+    }
+
+    fn set_geometry(&mut self, geometry: &WindowGeometry, size: bool) {
+        let window = self.canvas.window_mut();
+        let video = window.subsystem();
+        let monitors = (0..video.num_video_displays().unwrap_or(0))
+            .filter_map(|display| video.display_bounds(display).ok())
+            .map(|bounds| Monitor {
+                x: bounds.x(),
+                y: bounds.y(),
+                width: bounds.width(),
+                height: bounds.height(),
+            })
+            .collect::<Vec<_>>();
+        if let Some((x, y)) = geometry.position_on(&monitors) {
+            window.set_position(WindowPos::Positioned(x), WindowPos::Positioned(y));
+        }
+        if size {
+            if let Err(e) = window.set_size(geometry.width, geometry.height) {
+                log::warn!(target: "display", "Could not resize the window: {}", e);
+            }
+        }
+        self.set_fullscreen(geometry.fullscreen);
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) {
+        let mode = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        if let Err(e) = self.canvas.window_mut().set_fullscreen(mode) {
+            log::warn!(target: "display", "Could not change fullscreen: {}", e);
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Err(e) = self.canvas.window_mut().set_title(title) {
+            log::warn!(target: "display", "Could not name the window: {}", e);
+        }
+    }
+}
+
+/// position of the controller with the SDL instance id among the connected ones
+fn controller_index(controllers: &[GameController], instance_id: u32) -> Option<usize> {
+    controllers
+        .iter()
+        .position(|controller| controller.instance_id() == instance_id)
+}
@@ -0,0 +1,57 @@
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The `[display.shader]` config section: GLSL files drawing the screen in place of the
+/// built-in shaders, for CRT or LCD effects
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShaderConfig {
+    /// relative to the directory of the config, the built-in one if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vertex: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fragment: Option<PathBuf>,
+}
+
+/// The built-in vertex shader: `pos` is the corner of the screen quad from -1 to 1, `tex_pos`
+/// the texture coordinate the fragment shader gets
+pub const VERTEX: &str = include_str!("../vertex.glsl");
+/// The built-in fragment shader. It gets the uniforms `tex` (one channel of shades, 0 for the
+/// background and 1 for the foreground), `background` and `foreground`, `brightness` and
+/// `gamma` (see `palette::Tone`), `time` in seconds since the window opened and `resolution`,
+/// the size of the frame in chip8 pixels, with `output_size` that of the screen in the window
+pub const FRAGMENT: &str = include_str!("../fragment.glsl");
+
+/// Sources of the screen program
+#[derive(Clone, Debug, PartialEq)]
+pub struct Shader {
+    pub vertex: String,
+    pub fragment: String,
+}
+
+impl Default for Shader {
+    fn default() -> Self {
+        Shader {
+            vertex: VERTEX.to_string(),
+            fragment: FRAGMENT.to_string(),
+        }
+    }
+}
+
+impl Shader {
+    /// Reads the files of `config`, the built-in sources fill in for those it does not give
+    pub fn load(config: &ShaderConfig, dir: &Path) -> Result<Self, Error> {
+        let read = |file: &Option<PathBuf>, builtin: &str| match file {
+            Some(file) => {
+                let path = dir.join(file);
+                std::fs::read_to_string(&path).map_err(|e| format_err!("{}: {}", path.display(), e))
+            }
+            None => Ok(builtin.to_string()),
+        };
+        Ok(Shader {
+            vertex: read(&config.vertex, VERTEX)?,
+            fragment: read(&config.fragment, FRAGMENT)?,
+        })
+    }
+}
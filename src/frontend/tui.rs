@@ -0,0 +1,319 @@
+use failure::{bail, Error};
+use winit::VirtualKeyCode;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::{Duration, Instant};
+
+use crate::framebuffer::FrameBuffer;
+use crate::frontend::{blend, palette, Frontend, Input, Region};
+use crate::overlay::Rect;
+
+/// Terminals only report presses, repeated while a key is held. A key is released when it has
+/// not repeated for this long
+const RELEASE_AFTER: Duration = Duration::from_millis(150);
+
+const LETTERS: [VirtualKeyCode; 26] = {
+    use VirtualKeyCode::*;
+    [A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z]
+};
+
+const DIGITS: [VirtualKeyCode; 10] = {
+    use VirtualKeyCode::*;
+    [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9]
+};
+
+/// Whether stdin and stdout are a terminal
+pub fn available() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Draws the screen in the terminal, two pixels per character with the upper half block.
+/// The fallback when no window can be opened (VMs without OpenGL, ssh sessions).
+/// Needs a terminal with 24-bit color, the overlay is not shown. Only the characters that
+/// changed since the last frame are sent, which keeps slow connections usable
+pub struct TuiWindow {
+    /// terminal settings restored on drop
+    original: libc::termios,
+    blender: blend::FrameBlender,
+    palette: palette::Palette,
+    tone: palette::Tone,
+    /// keys reported as pressed and when they were last seen
+    held: Vec<(VirtualKeyCode, Instant)>,
+    /// colors of the upper and lower pixel of every character on the terminal, row by row
+    cells: Vec<([u8; 3], [u8; 3])>,
+    /// characters per row in `cells`
+    cells_width: usize,
+}
+
+/// Cursor movement and colors are only written when they differ from where the last character
+/// left them
+#[derive(Default)]
+struct CellWriter {
+    out: String,
+    cursor: Option<(usize, usize)>,
+    colors: Option<([u8; 3], [u8; 3])>,
+}
+
+impl CellWriter {
+    fn cell(&mut self, row: usize, column: usize, colors: ([u8; 3], [u8; 3])) -> std::fmt::Result {
+        if self.cursor != Some((row, column)) {
+            write!(self.out, "\x1b[{};{}H", row + 1, column + 1)?;
+        }
+        if self.colors != Some(colors) {
+            let ([r, g, b], [br, bg, bb]) = colors;
+            write!(self.out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m", r, g, b, br, bg, bb)?;
+            self.colors = Some(colors);
+        }
+        self.out.push('\u{2580}');
+        self.cursor = Some((row, column + 1));
+        Ok(())
+    }
+}
+
+impl TuiWindow {
+    pub fn new() -> Result<Self, Error> {
+        if !available() {
+            bail!("The terminal frontend needs stdin and stdout to be a terminal");
+        }
+
+        let mut original = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        // no echo or line buffering, and reads return at once even if nothing was typed
+        let mut raw = original;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        // alternate screen, hidden cursor
+        print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+
+        Ok(TuiWindow {
+            original,
+            blender: blend::FrameBlender::default(),
+            palette: palette::Palette::default(),
+            tone: palette::Tone::default(),
+            held: Vec::new(),
+            cells: Vec::new(),
+            cells_width: 0,
+        })
+    }
+
+    fn read_input() -> Vec<u8> {
+        let mut input = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            let read = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+            if read <= 0 {
+                return input;
+            }
+            input.extend_from_slice(&buf[..read as usize]);
+        }
+    }
+
+    fn press(&mut self, keycode: VirtualKeyCode, now: Instant, inputs: &mut Vec<Input>) {
+        match self.held.iter_mut().find(|(held, _)| *held == keycode) {
+            Some((_, seen)) => *seen = now,
+            None => {
+                self.held.push((keycode, now));
+                inputs.push(Input::Key {
+                    keycode,
+                    pressed: true,
+                    controller: None,
+                });
+            }
+        }
+    }
+}
+
+/// The key and the length of the sequence at the start of `input`, None for the key if it is
+/// not one the emulator uses
+fn parse_key(input: &[u8]) -> (Option<VirtualKeyCode>, usize) {
+    use VirtualKeyCode::*;
+
+    let key = match input[0] {
+        b'a'..=b'z' => LETTERS[usize::from(input[0] - b'a')],
+        b'A'..=b'Z' => LETTERS[usize::from(input[0] - b'A')],
+        b'0'..=b'9' => DIGITS[usize::from(input[0] - b'0')],
+        b'\t' => Tab,
+        b'\r' | b'\n' => Return,
+        b' ' => Space,
+        0x7F => Back,
+        0x1B => return parse_escape(input),
+        _ => return (None, 1),
+    };
+    (Some(key), 1)
+}
+
+/// Arrows, F1-F12, PageUp/PageDown, Home/End or Esc on its own
+fn parse_escape(input: &[u8]) -> (Option<VirtualKeyCode>, usize) {
+    use VirtualKeyCode::*;
+
+    match input.get(1) {
+        Some(b'[') | Some(b'O') => {}
+        _ => return (Some(Escape), 1),
+    }
+    // the sequence ends with a letter or `~`
+    let end = match input[2..].iter().position(|c| c.is_ascii_alphabetic() || *c == b'~') {
+        Some(end) => end + 2,
+        None => return (None, input.len()),
+    };
+
+    let key = match &input[2..=end] {
+        b"A" => Some(Up),
+        b"B" => Some(Down),
+        b"C" => Some(Right),
+        b"D" => Some(Left),
+        b"H" | b"1~" => Some(Home),
+        b"F" | b"4~" => Some(End),
+        b"5~" => Some(PageUp),
+        b"6~" => Some(PageDown),
+        b"P" => Some(F1),
+        b"Q" => Some(F2),
+        b"R" => Some(F3),
+        b"S" => Some(F4),
+        b"15~" => Some(F5),
+        b"17~" => Some(F6),
+        b"18~" => Some(F7),
+        b"19~" => Some(F8),
+        b"20~" => Some(F9),
+        b"21~" => Some(F10),
+        b"23~" => Some(F11),
+        b"24~" => Some(F12),
+        _ => None,
+    };
+    (key, end + 1)
+}
+
+impl Frontend for TuiWindow {
+    fn draw(&mut self, screen: FrameBuffer, dirty: Option<&[Region]>, _overlay: &[Rect]) -> Result<(), Error> {
+        let (width, height) = (screen.width(), screen.height());
+        // blending changes pixels outside of the dirty regions
+        let dirty = dirty.filter(|_| self.blender.settle_frames() == 0);
+        let data = self.blender.blend(screen.into_pixels());
+        let (palette, tone) = (self.palette, self.tone);
+        let (width, height) = (width as usize, height as usize);
+        let pixel = |x: usize, y: usize| match data.get(y * width + x) {
+            Some(value) if y < height => tone.apply_rgb(palette.apply(*value)),
+            _ => tone.apply_rgb(palette.background().0),
+        };
+
+        // everything is drawn again when the size changes
+        let rows = height.div_ceil(2);
+        let full = rows * width != self.cells.len() || width != self.cells_width;
+        let mut writer = CellWriter::default();
+        if full {
+            writer.out.push_str("\x1b[0m\x1b[2J");
+            self.cells = vec![Default::default(); rows * width];
+            self.cells_width = width;
+        }
+
+        // only the cells under the dirty regions are compared with what the terminal shows
+        let all = [Region {
+            x: 0,
+            y: 0,
+            width: width as u32,
+            height: height as u32,
+        }];
+        let regions = match dirty {
+            Some(regions) if !full => regions,
+            _ => &all[..],
+        };
+        for region in regions {
+            let (x, y) = (region.x as usize, region.y as usize);
+            let (right, bottom) = (x + region.width as usize, y + region.height as usize);
+            for row in y / 2..bottom.div_ceil(2).min(rows) {
+                for column in x..right.min(width) {
+                    let colors = (pixel(column, row * 2), pixel(column, row * 2 + 1));
+                    let cell = &mut self.cells[row * width + column];
+                    if full || *cell != colors {
+                        writer.cell(row, column, colors)?;
+                        *cell = colors;
+                    }
+                }
+            }
+        }
+
+        if writer.out.is_empty() {
+            return Ok(());
+        }
+        writer.out.push_str("\x1b[0m");
+        let mut stdout = std::io::stdout();
+        stdout.write_all(writer.out.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn poll_input(&mut self) -> Vec<Input> {
+        let now = Instant::now();
+        let mut inputs = Vec::new();
+
+        let input = Self::read_input();
+        let mut rest = &input[..];
+        while !rest.is_empty() {
+            // raw mode turns Ctrl-C into a byte instead of a signal
+            if rest[0] == 0x03 {
+                inputs.push(Input::Close);
+                return inputs;
+            }
+            // and Ctrl-V into 0x16, pressed as the chord it was
+            if rest[0] == 0x16 {
+                self.press(VirtualKeyCode::LControl, now, &mut inputs);
+                self.press(VirtualKeyCode::V, now, &mut inputs);
+                rest = &rest[1..];
+                continue;
+            }
+            let (key, len) = parse_key(rest);
+            if let Some(keycode) = key {
+                self.press(keycode, now, &mut inputs);
+            }
+            rest = &rest[len..];
+        }
+
+        self.held.retain(|&(keycode, seen)| {
+            let held = now - seen < RELEASE_AFTER;
+            if !held {
+                inputs.push(Input::Key {
+                    keycode,
+                    pressed: false,
+                    controller: None,
+                });
+            }
+            held
+        });
+        inputs
+    }
+
+    fn set_palette(&mut self, palette: palette::Palette) {
+        self.palette = palette;
+        // every cell changes color, not only the dirty ones
+        self.cells.clear();
+    }
+
+    fn set_anti_flicker(&mut self, settings: blend::AntiFlicker) {
+        self.blender.set_settings(settings);
+    }
+
+    fn set_tone(&mut self, tone: palette::Tone) {
+        self.tone = tone.clamped();
+    }
+
+    /// characters are drawn one per pixel pair whatever the terminal size
+    fn set_scaling(&mut self, _scaling: super::Scaling) {}
+
+    fn draws_overlay(&self) -> bool {
+        false
+    }
+}
+
+impl Drop for TuiWindow {
+    fn drop(&mut self) {
+        print!("\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = std::io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
@@ -0,0 +1,608 @@
+use failure::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wgpu::util::DeviceExt;
+use winit_wgpu::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
+use winit_wgpu::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit_wgpu::event_loop::{ControlFlow, EventLoop};
+use winit_wgpu::keyboard::PhysicalKey;
+use winit_wgpu::platform::pump_events::EventLoopExtPumpEvents;
+use winit_wgpu::window::{Fullscreen, Window, WindowBuilder};
+
+use super::geometry::{Monitor, WindowGeometry};
+use super::{bezel, blend, palette, Frontend, Input, Layout, PresentMode, Region, Scaling, Viewport};
+use crate::framebuffer::FrameBuffer;
+use crate::input;
+use crate::overlay::Rect;
+
+/// position and color of an overlay vertex
+const OVERLAY_VERTEX_SIZE: usize = 6 * 4;
+
+/// The wgpu frontend, for setups where glium's OpenGL context can not be created
+pub struct WgpuWindow {
+    event_loop: EventLoop<()>,
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    surface_config: wgpu::SurfaceConfiguration,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    screen_pipeline: wgpu::RenderPipeline,
+    screen_layout: wgpu::BindGroupLayout,
+    overlay_pipeline: wgpu::RenderPipeline,
+    bezel_pipeline: wgpu::RenderPipeline,
+    /// the bind group of the image, in the layout of the screen's
+    bezel: Option<(bezel::Bezel, wgpu::BindGroup)>,
+    sampler: wgpu::Sampler,
+    /// background and foreground for the screen shader, rewritten when the palette changes
+    palette_buffer: wgpu::Buffer,
+    /// the single-channel texture of the last frame and its bind group, kept while the size holds
+    screen: Option<(wgpu::Texture, wgpu::BindGroup)>,
+    blender: blend::FrameBlender,
+    palette: palette::Palette,
+    tone: palette::Tone,
+    scaling: Scaling,
+    /// where the last frame went, None before the first
+    layout: Option<Layout>,
+    /// received while waiting, for the next `poll_input`
+    pending: Vec<Input>,
+}
+
+impl WgpuWindow {
+    pub fn new(dimensions: (u32, u32), present: PresentMode) -> Result<Self, Error> {
+        let event_loop = EventLoop::new().map_err(|e| format_err!("{}", e))?;
+        let window = WindowBuilder::new()
+            .with_title("Chip8")
+            .with_inner_size(LogicalSize::new(dimensions.0, dimensions.1))
+            .build(&event_loop)
+            .map_err(|e| format_err!("{}", e))?;
+        let window = Arc::new(window);
+
+        let instance = wgpu::Instance::default();
+        let surface = instance.create_surface(window.clone())?;
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            compatible_surface: Some(&surface),
+            ..Default::default()
+        }))
+        .ok_or_else(|| format_err!("No graphics adapter supports the window surface"))?;
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))?;
+
+        let size = window.inner_size();
+        let mut surface_config = surface
+            .get_default_config(&adapter, size.width.max(1), size.height.max(1))
+            .ok_or_else(|| format_err!("The window surface is not supported by the adapter"))?;
+        let capabilities = surface.get_capabilities(&adapter);
+        // palette colors are given as they should appear, like with glium
+        if let Some(&format) = capabilities.formats.iter().find(|format| !format.is_srgb()) {
+            surface_config.format = format;
+        }
+        let mode = match present {
+            PresentMode::Auto => None,
+            PresentMode::Vsync => Some(wgpu::PresentMode::Fifo),
+            PresentMode::Mailbox => Some(wgpu::PresentMode::Mailbox),
+            PresentMode::Immediate => Some(wgpu::PresentMode::Immediate),
+        };
+        match mode {
+            Some(mode) if capabilities.present_modes.contains(&mode) => surface_config.present_mode = mode,
+            // every adapter has Fifo
+            Some(mode) => {
+                log::warn!(target: "display", "The graphics adapter cannot present in {:?} mode, using vsync", mode);
+                surface_config.present_mode = wgpu::PresentMode::Fifo;
+            }
+            None => {}
+        }
+        surface.configure(&device, &surface_config);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(include_str!("wgpu.wgsl").into()),
+        });
+
+        let screen_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let screen_pipeline = Self::pipeline(
+            &device,
+            &shader,
+            surface_config.format,
+            &[&screen_layout],
+            ("screen_vs", "screen_fs"),
+            &[],
+            None,
+        );
+        let bezel_pipeline = Self::pipeline(
+            &device,
+            &shader,
+            surface_config.format,
+            &[&screen_layout],
+            ("screen_vs", "bezel_fs"),
+            &[],
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        );
+        let overlay_pipeline = Self::pipeline(
+            &device,
+            &shader,
+            surface_config.format,
+            &[],
+            ("overlay_vs", "overlay_fs"),
+            &[wgpu::VertexBufferLayout {
+                array_stride: OVERLAY_VERTEX_SIZE as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4],
+            }],
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let palette = palette::Palette::default();
+        let palette_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &palette_colors(&palette, palette::Tone::default()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Ok(WgpuWindow {
+            event_loop,
+            window,
+            surface,
+            surface_config,
+            device,
+            queue,
+            screen_pipeline,
+            screen_layout,
+            overlay_pipeline,
+            bezel_pipeline,
+            bezel: None,
+            sampler,
+            palette_buffer,
+            screen: None,
+            blender: blend::FrameBlender::default(),
+            palette,
+            tone: palette::Tone::default(),
+            scaling: Scaling::default(),
+            layout: None,
+            pending: Vec::new(),
+        })
+    }
+
+    fn pipeline(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        (vertex, fragment): (&str, &str),
+        buffers: &[wgpu::VertexBufferLayout],
+        blend: Option<wgpu::BlendState>,
+    ) -> wgpu::RenderPipeline {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: vertex,
+                buffers,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: fragment,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+
+    /// Handles the window's events for up to `timeout`, returning as soon as there were some.
+    /// The event loop sleeps with `ControlFlow::WaitUntil` meanwhile
+    fn pump(&mut self, timeout: Duration) -> Vec<Input> {
+        let mut input = Vec::new();
+        let mut resized = None;
+        let layout = self.layout;
+        let deadline = Instant::now() + timeout;
+        self.event_loop.pump_events(Some(timeout), |event, target| {
+            target.set_control_flow(ControlFlow::WaitUntil(deadline));
+            if let Event::WindowEvent { event, .. } = event {
+                match event {
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                physical_key: PhysicalKey::Code(code),
+                                state,
+                                ..
+                            },
+                        ..
+                    } => {
+                        if let Some(keycode) = input::wgpu::key_code(code) {
+                            input.push(Input::Key {
+                                keycode,
+                                pressed: state == ElementState::Pressed,
+                                controller: None,
+                            });
+                        }
+                    }
+                    WindowEvent::CloseRequested => input.push(Input::Close),
+                    WindowEvent::Resized(size) => resized = Some(size),
+                    WindowEvent::CursorMoved { position, .. } => {
+                        input.push(Input::Cursor(layout.map(|layout| layout.cursor(position.x, position.y))));
+                    }
+                    WindowEvent::CursorLeft { .. } => input.push(Input::Cursor(None)),
+                    _ => {}
+                }
+            }
+        });
+
+        if let Some(size) = resized {
+            self.resize(size);
+        }
+
+        input
+    }
+
+    fn resize(&mut self, size: PhysicalSize<u32>) {
+        if size.width > 0 && size.height > 0 {
+            self.surface_config.width = size.width;
+            self.surface_config.height = size.height;
+            self.surface.configure(&self.device, &self.surface_config);
+        }
+    }
+
+    /// A texture holding `data`, `width`x`height` pixels of `format`
+    fn texture(&self, data: &[u8], width: u32, height: u32, format: wgpu::TextureFormat) -> wgpu::Texture {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        self.write_texture(&texture, data);
+
+        texture
+    }
+
+    /// Replaces all of `texture` with `data`
+    fn write_texture(&self, texture: &wgpu::Texture, data: &[u8]) {
+        let size = texture.size();
+        self.queue.write_texture(
+            texture.as_image_copy(),
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(size.width * texture.format().block_copy_size(None).unwrap_or(1)),
+                rows_per_image: Some(size.height),
+            },
+            size,
+        );
+    }
+
+    fn bind_group(&self, texture: &wgpu::Texture, palette: &wgpu::Buffer) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.screen_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: palette.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// Writes the shades of a frame to the screen texture, making a new one when the size changes.
+    /// The palette is applied by the shader
+    fn upload_screen(&mut self, data: &[u8], width: u32, height: u32) {
+        match &self.screen {
+            Some((texture, _)) if (texture.width(), texture.height()) == (width, height) => {
+                self.write_texture(texture, data);
+            }
+            _ => {
+                let texture = self.texture(data, width, height, wgpu::TextureFormat::R8Unorm);
+                let group = self.bind_group(&texture, &self.palette_buffer);
+                self.screen = Some((texture, group));
+            }
+        }
+    }
+
+    fn overlay_vertices(rects: &[Rect]) -> Vec<u8> {
+        let mut vertices = Vec::with_capacity(rects.len() * 6 * OVERLAY_VERTEX_SIZE);
+        for rect in rects {
+            let [x, y] = rect.pos;
+            let [w, h] = rect.size;
+            for pos in &[
+                [x, y],
+                [x + w, y],
+                [x + w, y + h],
+                [x + w, y + h],
+                [x, y + h],
+                [x, y],
+            ] {
+                for value in pos.iter().chain(&rect.color) {
+                    vertices.extend_from_slice(&value.to_ne_bytes());
+                }
+            }
+        }
+
+        vertices
+    }
+}
+
+impl Frontend for WgpuWindow {
+    fn draw(&mut self, screen: FrameBuffer, _dirty: Option<&[Region]>, overlay: &[Rect]) -> Result<(), Error> {
+        let (width, height) = (screen.width(), screen.height());
+        let frame = match self.surface.get_current_texture() {
+            Ok(frame) => frame,
+            Err(wgpu::SurfaceError::Lost) | Err(wgpu::SurfaceError::Outdated) => {
+                // the next frame goes to the reconfigured surface
+                self.resize(self.window.inner_size());
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let view = frame.texture.create_view(&Default::default());
+
+        let data = self.blender.blend(screen.into_pixels());
+        self.upload_screen(&data, width, height);
+        let overlay = if overlay.is_empty() {
+            None
+        } else {
+            let vertices = Self::overlay_vertices(overlay);
+            let buffer = self
+                .device
+                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: &vertices,
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+            Some((buffer, (vertices.len() / OVERLAY_VERTEX_SIZE) as u32))
+        };
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let [r, g, b] = self.tone.apply_f32(self.palette.background().to_f32());
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: r.into(),
+                            g: g.into(),
+                            b: b.into(),
+                            a: 1.0,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            let size = (self.surface_config.width, self.surface_config.height);
+            let (bezel, viewport) = match &self.bezel {
+                Some((bezel, group)) => {
+                    let (area, screen) = bezel.layout(size, (width, height), self.scaling);
+                    (Some((area, bezel.layer, group)), Some(screen))
+                }
+                None => (None, self.scaling.viewport(size, (width, height))),
+            };
+            self.layout = Some(Layout::new(size, viewport, (width, height)));
+
+            if let Some((area, bezel::Layer::Back, group)) = bezel {
+                set_viewport(&mut pass, area, size);
+                pass.set_pipeline(&self.bezel_pipeline);
+                pass.set_bind_group(0, group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+            if let Some(viewport) = viewport {
+                set_viewport(&mut pass, viewport, size);
+            }
+            pass.set_pipeline(&self.screen_pipeline);
+            // always there after `upload_screen`
+            if let Some((_, screen)) = &self.screen {
+                pass.set_bind_group(0, screen, &[]);
+                pass.draw(0..3, 0..1);
+            }
+            if let Some((area, bezel::Layer::Front, group)) = bezel {
+                set_viewport(&mut pass, area, size);
+                pass.set_pipeline(&self.bezel_pipeline);
+                pass.set_bind_group(0, group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+
+            if let Some((buffer, count)) = &overlay {
+                pass.set_viewport(0.0, 0.0, size.0 as f32, size.1 as f32, 0.0, 1.0);
+                pass.set_pipeline(&self.overlay_pipeline);
+                pass.set_vertex_buffer(0, buffer.slice(..));
+                pass.draw(0..*count, 0..1);
+            }
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+        frame.present();
+
+        Ok(())
+    }
+
+    fn poll_input(&mut self) -> Vec<Input> {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend(self.pump(Duration::ZERO));
+        input
+    }
+
+    fn wait_input(&mut self, timeout: Duration) -> bool {
+        let input = self.pump(timeout);
+        self.pending.extend(input);
+        true
+    }
+
+    fn set_palette(&mut self, palette: palette::Palette) {
+        self.queue.write_buffer(&self.palette_buffer, 0, &palette_colors(&palette, self.tone));
+        self.palette = palette;
+    }
+
+    fn set_tone(&mut self, tone: palette::Tone) {
+        self.tone = tone.clamped();
+        self.queue.write_buffer(&self.palette_buffer, 0, &palette_colors(&self.palette, self.tone));
+    }
+
+    fn set_anti_flicker(&mut self, settings: blend::AntiFlicker) {
+        self.blender.set_settings(settings);
+    }
+
+    fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    fn set_bezel(&mut self, bezel: Option<bezel::Bezel>) {
+        self.bezel = bezel.map(|bezel| {
+            let texture = self.texture(&bezel.rgba, bezel.width, bezel.height, wgpu::TextureFormat::Rgba8Unorm);
+            // the bezel shader takes its colors from the image and ignores the palette
+            let group = self.bind_group(&texture, &self.palette_buffer);
+            (bezel, group)
+        });
+    }
+
+    fn refresh_rate(&self) -> Option<f64> {
+        let millihertz = self.window.current_monitor()?.refresh_rate_millihertz()?;
+        Some(f64::from(millihertz) / 1000.0)
+    }
+
+    fn geometry(&self) -> Option<WindowGeometry> {
+        let size = self.window.inner_size();
+        Some(WindowGeometry {
+            width: size.width,
+            height: size.height,
+            position: self.window.outer_position().ok().map(|position| (position.x, position.y)),
+            fullscreen: self.window.fullscreen().is_some(),
+        })
+    }
+
+    fn set_geometry(&mut self, geometry: &WindowGeometry, size: bool) {
+        let monitors = self
+            .window
+            .available_monitors()
+            .map(|monitor| {
+                let (position, size) = (monitor.position(), monitor.size());
+                Monitor {
+                    x: position.x,
+                    y: position.y,
+                    width: size.width,
+                    height: size.height,
+                }
+            })
+            .collect::<Vec<_>>();
+        if let Some((x, y)) = geometry.position_on(&monitors) {
+            self.window.set_outer_position(PhysicalPosition::new(x, y));
+        }
+        if size {
+            let _ = self.window.request_inner_size(PhysicalSize::new(geometry.width, geometry.height));
+        }
+        self.set_fullscreen(geometry.fullscreen);
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) {
+        self.window.set_fullscreen(if fullscreen { Some(Fullscreen::Borderless(None)) } else { None });
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+}
+
+/// Sets the viewport of `pass` to `viewport`, cut to the surface since wgpu rejects viewports
+/// past its edges, which happens below 1x
+/// The background, foreground and tone as the shader's uniform, three vec4s of f32. The tone
+/// is the brightness and gamma, then two unused
+fn palette_colors(palette: &palette::Palette, tone: palette::Tone) -> Vec<u8> {
+    let mut colors = Vec::with_capacity(48);
+    for color in &palette.colors[..2] {
+        let [r, g, b] = color.to_f32();
+        for value in &[r, g, b, 1.0] {
+            colors.extend_from_slice(&value.to_ne_bytes());
+        }
+    }
+    let tone = tone.clamped();
+    for value in &[tone.brightness as f32, tone.gamma as f32, 0.0, 0.0] {
+        colors.extend_from_slice(&value.to_ne_bytes());
+    }
+    colors
+}
+
+fn set_viewport(pass: &mut wgpu::RenderPass, viewport: Viewport, size: (u32, u32)) {
+    let x = viewport.x.min(size.0);
+    let y = viewport.y.min(size.1);
+    pass.set_viewport(
+        x as f32,
+        y as f32,
+        viewport.width.min(size.0 - x).max(1) as f32,
+        viewport.height.min(size.1 - y).max(1) as f32,
+        0.0,
+        1.0,
+    );
+}
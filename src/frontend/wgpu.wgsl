@@ -0,0 +1,58 @@
+struct Palette {
+    background: vec4<f32>,
+    foreground: vec4<f32>,
+    // brightness and gamma, see palette::Tone
+    tone: vec4<f32>,
+}
+
+@group(0) @binding(0) var screen: texture_2d<f32>;
+@group(0) @binding(1) var screen_sampler: sampler;
+@group(0) @binding(2) var<uniform> palette: Palette;
+
+struct ScreenVertex {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) tex_pos: vec2<f32>,
+}
+
+// a single triangle covering the whole window
+@vertex
+fn screen_vs(@builtin(vertex_index) i: u32) -> ScreenVertex {
+    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+
+    var out: ScreenVertex;
+    out.pos = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.tex_pos = uv;
+    return out;
+}
+
+@fragment
+fn screen_fs(in: ScreenVertex) -> @location(0) vec4<f32> {
+    let value = textureSample(screen, screen_sampler, in.tex_pos).r;
+    let color = mix(palette.background.rgb, palette.foreground.rgb, value);
+    let toned = pow(color, vec3<f32>(1.0 / palette.tone.y)) * palette.tone.x;
+    return vec4<f32>(min(toned, vec3<f32>(1.0)), 1.0);
+}
+
+struct OverlayVertex {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn overlay_vs(@location(0) pos: vec2<f32>, @location(1) color: vec4<f32>) -> OverlayVertex {
+    var out: OverlayVertex;
+    out.pos = vec4<f32>(pos.x * 2.0 - 1.0, 1.0 - pos.y * 2.0, 0.0, 1.0);
+    out.color = color;
+    return out;
+}
+
+@fragment
+fn overlay_fs(in: OverlayVertex) -> @location(0) vec4<f32> {
+    return in.color;
+}
+
+// drawn with the screen bindings, the image in place of the screen and the palette unused
+@fragment
+fn bezel_fs(in: ScreenVertex) -> @location(0) vec4<f32> {
+    return textureSample(screen, screen_sampler, in.tex_pos);
+}
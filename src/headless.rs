@@ -0,0 +1,72 @@
+use crate::audio::{AudioSink, NullSink};
+use crate::system::{debug::Debugger, Execution, System, SystemError};
+
+/// Instructions executed per 60 Hz frame when running without a window
+pub const CYCLES_PER_FRAME: u32 = 40;
+
+/// Runs a system without a window, one frame at a time
+pub struct Headless {
+    pub system: System,
+    pub frame: u64,
+    /// discards the sound unless replaced
+    pub audio: Box<dyn AudioSink>,
+}
+
+impl Headless {
+    pub fn new(system: System) -> Self {
+        Headless {
+            system,
+            frame: 0,
+            audio: Box::new(NullSink),
+        }
+    }
+
+    /// the program reached the zero instruction
+    pub fn halted(&self) -> bool {
+        self.system.execution == Execution::Halted
+    }
+
+    /// Executes the rest of the frame's instructions and decrements the timers once.
+    /// A system restored mid-frame only runs what was left of the frame
+    pub fn run_frame(&mut self) -> Result<(), SystemError> {
+        if !self.halted() {
+            let mut dbg = Debugger::disabled();
+            for _ in self.system.frame_cycles..CYCLES_PER_FRAME {
+                match self.system.tick(&mut dbg) {
+                    Ok(()) => {}
+                    Err(SystemError::ZeroInstruction) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        self.end_frame();
+        Ok(())
+    }
+
+    /// Executes one instruction, and decrements the timers after the last one of the frame.
+    /// A halted program spends the instruction doing nothing, so frames keep their length
+    pub fn step(&mut self) -> Result<(), SystemError> {
+        let executed = !self.halted()
+            && match self.system.tick(&mut Debugger::disabled()) {
+                Ok(()) => true,
+                Err(SystemError::ZeroInstruction) => false,
+                Err(e) => return Err(e),
+            };
+        if !executed {
+            self.system.frame_cycles += 1;
+        }
+
+        if self.system.frame_cycles >= CYCLES_PER_FRAME {
+            self.end_frame();
+        }
+        Ok(())
+    }
+
+    fn end_frame(&mut self) {
+        self.system.dec_timers();
+        self.audio.set_beeping(self.system.timers.sound > 0);
+        self.audio.timer_tick();
+        self.frame += 1;
+    }
+}
@@ -0,0 +1,653 @@
+use failure::Error;
+use winit as g;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use crate::frontend::Input;
+use crate::overlay::keymap::LAYOUT;
+
+pub mod auto;
+#[cfg(feature = "gui")]
+pub mod glutin;
+#[cfg(feature = "sdl2")]
+pub mod sdl;
+pub mod web;
+#[cfg(feature = "wgpu")]
+pub mod wgpu;
+
+pub type Key = u8;
+pub type Pressed = bool;
+
+/// What the emulator does for a key that is not bound to the game, the same for every frontend
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Exit,
+    ToggleOverlay,
+    ToggleKeymapEditor,
+    ToggleHeatmap,
+    TogglePerfGraph,
+    ToggleKeypad,
+    /// show the debugger in a window of its own
+    ToggleDebugWindow,
+    TogglePause,
+    /// run one frame while paused
+    StepFrame,
+    ToggleFullscreen,
+    /// type the hex digits on the host clipboard, Ctrl+V
+    Paste,
+    SaveState,
+    LoadState,
+    /// select the save state slot this far from the current one
+    SelectSlot {
+        offset: isize,
+    },
+    Turbo {
+        pressed: bool,
+    },
+    /// play the recorded frames backwards while held
+    Rewind {
+        pressed: bool,
+    },
+    /// add to the brightness and gamma of the display
+    AdjustTone {
+        brightness: f64,
+        gamma: f64,
+    },
+}
+
+/// A frontend's `Input` after the keymap: a hex key of the game or an action of the emulator
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Key(Key, Pressed),
+    UiAction(Action),
+}
+
+/// Physical key bound to each of the 16 hex keys
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "KeymapConfig", into = "KeymapConfig")]
+pub struct Keymap {
+    keys: [g::VirtualKeyCode; 16],
+    /// hex keys that pulse while held, see `RapidFire`
+    turbo: [bool; 16],
+    /// bind the keys the game polls to the arrows and space, see `auto::bindings`
+    pub auto: bool,
+    /// press keys the way a keypad wired as a plain matrix would, see `Ghosting`
+    pub ghosting: bool,
+}
+
+/// The `[keymap]` config section: `5 = "Up"` entries, `turbo = ["5"]`, `auto = true` and
+/// `ghosting = true`
+#[derive(Serialize, Deserialize)]
+pub struct KeymapConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    turbo: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auto: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ghosting: Option<bool>,
+    #[serde(flatten)]
+    keys: BTreeMap<String, g::VirtualKeyCode>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use winit::VirtualKeyCode::*;
+
+        Keymap {
+            keys: [
+                X, Key1, Key2, Key3, //
+                Q, W, E, A, //
+                S, D, Z, C, //
+                Key4, R, F, V, //
+            ],
+            turbo: [false; 16],
+            auto: false,
+            ghosting: false,
+        }
+    }
+}
+
+impl Keymap {
+    pub fn get(&self, key: Key) -> g::VirtualKeyCode {
+        self.keys[key as usize & 0xF]
+    }
+
+    /// Binds `keycode` to `key`. If the keycode was bound to another hex key,
+    /// that key gets the previous binding of `key`, so the mapping stays one-to-one
+    pub fn bind(&mut self, key: Key, keycode: g::VirtualKeyCode) {
+        let key = key as usize & 0xF;
+        if let Some(other) = self.keys.iter().position(|bound| *bound == keycode) {
+            self.keys.swap(key, other);
+        } else {
+            self.keys[key] = keycode;
+        }
+    }
+
+    pub fn is_turbo(&self, key: Key) -> bool {
+        self.turbo[key as usize & 0xF]
+    }
+
+    pub fn set_turbo(&mut self, key: Key, turbo: bool) {
+        self.turbo[key as usize & 0xF] = turbo;
+    }
+
+    pub fn hex_key(&self, keycode: g::VirtualKeyCode) -> Option<Key> {
+        self.keys
+            .iter()
+            .position(|bound| *bound == keycode)
+            .map(|key| key as Key)
+    }
+
+    /// Applies the bindings, turbo keys and auto and ghosting settings a game's entry sets
+    pub fn apply(&mut self, keymap: &KeymapOverride) {
+        for (key, keycode) in keymap.keys.iter().enumerate() {
+            if let Some(keycode) = keycode {
+                self.bind(key as Key, *keycode);
+            }
+        }
+        for &key in &keymap.turbo {
+            self.set_turbo(key, true);
+        }
+        if let Some(auto) = keymap.auto {
+            self.auto = auto;
+        }
+        if let Some(ghosting) = keymap.ghosting {
+            self.ghosting = ghosting;
+        }
+    }
+}
+
+/// The `keymap` of a game's entry, in the form of the `[keymap]` section. Keys it does not
+/// mention keep the bindings of the section
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "KeymapConfig", into = "KeymapConfig")]
+pub struct KeymapOverride {
+    keys: [Option<g::VirtualKeyCode>; 16],
+    turbo: Vec<Key>,
+    auto: Option<bool>,
+    ghosting: Option<bool>,
+}
+
+fn parse_hex_key(key: &str) -> Result<Key, String> {
+    match u8::from_str_radix(key, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(format!("invalid hex key: {}", key)),
+    }
+}
+
+/// A player of a local multiplayer game: the hex keys that belong to them and the keys of
+/// their device that press them. Set in `[[players]]` sections of the config
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "PlayerConfig", into = "PlayerConfig")]
+pub struct Player {
+    pub name: Option<String>,
+    /// the game controller the player uses, counting from 0 in the order they were connected.
+    /// None for a region of the keyboard
+    pub controller: Option<usize>,
+    keys: [Option<g::VirtualKeyCode>; 16],
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlayerConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    controller: Option<usize>,
+    keys: BTreeMap<String, g::VirtualKeyCode>,
+}
+
+impl Player {
+    /// the hex key `keycode` presses, if it comes from the player's device
+    pub fn hex_key(&self, keycode: g::VirtualKeyCode, controller: Option<usize>) -> Option<Key> {
+        if controller != self.controller {
+            return None;
+        }
+        self.keys
+            .iter()
+            .position(|bound| *bound == Some(keycode))
+            .map(|key| key as Key)
+    }
+}
+
+impl TryFrom<PlayerConfig> for Player {
+    type Error = String;
+
+    fn try_from(config: PlayerConfig) -> Result<Self, Self::Error> {
+        let mut keys = [None; 16];
+        for (key, keycode) in config.keys {
+            keys[parse_hex_key(&key)? as usize] = Some(keycode);
+        }
+
+        Ok(Player {
+            name: config.name,
+            controller: config.controller,
+            keys,
+        })
+    }
+}
+
+impl From<Player> for PlayerConfig {
+    fn from(player: Player) -> Self {
+        PlayerConfig {
+            name: player.name,
+            controller: player.controller,
+            keys: player
+                .keys
+                .iter()
+                .enumerate()
+                .filter_map(|(key, keycode)| Some((format!("{:X}", key), (*keycode)?)))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<KeymapConfig> for Keymap {
+    type Error = String;
+
+    fn try_from(config: KeymapConfig) -> Result<Self, Self::Error> {
+        let mut keymap = Keymap::default();
+        for (key, keycode) in config.keys {
+            keymap.bind(parse_hex_key(&key)?, keycode);
+        }
+        for key in config.turbo {
+            keymap.set_turbo(parse_hex_key(&key)?, true);
+        }
+        keymap.auto = config.auto.unwrap_or(false);
+        keymap.ghosting = config.ghosting.unwrap_or(false);
+
+        Ok(keymap)
+    }
+}
+
+impl From<Keymap> for KeymapConfig {
+    fn from(keymap: Keymap) -> Self {
+        KeymapConfig {
+            turbo: (0..16)
+                .filter(|&key| keymap.is_turbo(key))
+                .map(|key| format!("{:X}", key))
+                .collect(),
+            auto: Some(keymap.auto).filter(|auto| *auto),
+            ghosting: Some(keymap.ghosting).filter(|ghosting| *ghosting),
+            keys: keymap
+                .keys
+                .iter()
+                .enumerate()
+                .map(|(key, keycode)| (format!("{:X}", key), *keycode))
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<KeymapConfig> for KeymapOverride {
+    type Error = String;
+
+    fn try_from(config: KeymapConfig) -> Result<Self, Self::Error> {
+        let mut keys = [None; 16];
+        for (key, keycode) in config.keys {
+            keys[parse_hex_key(&key)? as usize] = Some(keycode);
+        }
+
+        Ok(KeymapOverride {
+            keys,
+            turbo: config.turbo.iter().map(|key| parse_hex_key(key)).collect::<Result<_, _>>()?,
+            auto: config.auto,
+            ghosting: config.ghosting,
+        })
+    }
+}
+
+impl From<KeymapOverride> for KeymapConfig {
+    fn from(keymap: KeymapOverride) -> Self {
+        KeymapConfig {
+            turbo: keymap.turbo.iter().map(|key| format!("{:X}", key)).collect(),
+            auto: keymap.auto,
+            ghosting: keymap.ghosting,
+            keys: keymap
+                .keys
+                .iter()
+                .enumerate()
+                .filter_map(|(key, keycode)| Some((format!("{:X}", key), (*keycode)?)))
+                .collect(),
+        }
+    }
+}
+
+/// Turns holding a turbo key into repeated presses and releases, one change every
+/// `frames` frames of 1/60 s, so games that only react to new presses fire continuously
+#[derive(Default)]
+pub struct RapidFire {
+    /// frames each held turbo key has been down for
+    held: [Option<u32>; 16],
+}
+
+impl RapidFire {
+    /// the physical key of a turbo hex key was pressed or released
+    pub fn key_event(&mut self, key: Key, pressed: Pressed) {
+        self.held[key as usize & 0xF] = if pressed { Some(0) } else { None };
+    }
+
+    /// Advances by one frame, returns the key events the held keys generate
+    pub fn tick(&mut self, frames: u32) -> Vec<(Key, Pressed)> {
+        let frames = frames.max(1);
+        let mut events = Vec::new();
+        for (key, held) in self.held.iter_mut().enumerate() {
+            if let Some(count) = held {
+                *count += 1;
+                if count.is_multiple_of(frames) {
+                    events.push((key as Key, (*count / frames).is_multiple_of(2)));
+                }
+            }
+        }
+        events
+    }
+}
+
+/// frames a pasted key is held, and then released before the next one
+const PASTE_FRAMES: u32 = 6;
+
+/// Hex keys pasted from the clipboard, pressed one after the other for `PASTE_FRAMES` each so
+/// games that wait for a key and its release see every one
+#[derive(Default)]
+pub struct KeySequence {
+    keys: VecDeque<Key>,
+    /// frames since the first key of `keys` was pressed
+    frame: u32,
+}
+
+impl KeySequence {
+    /// The hex digits in `text`. Spaces, dashes and colons between them are skipped, anything
+    /// else is an error so pasting the wrong thing types nothing
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut keys = VecDeque::new();
+        for c in text.trim().chars() {
+            match c.to_digit(16) {
+                Some(key) => keys.push_back(key as Key),
+                None if c.is_whitespace() || c == '-' || c == ':' => {}
+                None => bail!("`{}` is not a hex key", c),
+            }
+        }
+        if keys.is_empty() {
+            bail!("there are no hex keys in it");
+        }
+
+        Ok(KeySequence { keys, frame: 0 })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The key being typed, if any
+    pub fn current(&self) -> Option<Key> {
+        self.keys.front().copied()
+    }
+
+    /// Advances by one frame, returns the key events it generates
+    pub fn tick(&mut self) -> Vec<(Key, Pressed)> {
+        let key = match self.current() {
+            Some(key) => key,
+            None => return Vec::new(),
+        };
+        let event = match self.frame {
+            0 => Some((key, true)),
+            frame if frame == PASTE_FRAMES => Some((key, false)),
+            _ => None,
+        };
+        self.frame += 1;
+        if self.frame == PASTE_FRAMES * 2 {
+            self.keys.pop_front();
+            self.frame = 0;
+        }
+        event.into_iter().collect()
+    }
+}
+
+/// The Ctrl keys of the keyboard, for chords like Ctrl+V
+#[derive(Clone, Copy, Default)]
+pub struct Modifiers {
+    /// left and right
+    ctrl: [bool; 2],
+}
+
+impl Modifiers {
+    pub fn update(&mut self, input: &Input) {
+        if let Input::Key { keycode, pressed, controller: None } = *input {
+            match keycode {
+                g::VirtualKeyCode::LControl => self.ctrl[0] = pressed,
+                g::VirtualKeyCode::RControl => self.ctrl[1] = pressed,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn ctrl(self) -> bool {
+        self.ctrl[0] || self.ctrl[1]
+    }
+}
+
+/// Input is polled once per pass of the run loop, which wakes at least once per frame, and also
+/// right before an instruction that reads the keys once this long has passed since the last
+/// poll. Batches of instructions, e.g. at turbo speed, then still see the keys as they are
+pub const KEY_READ_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// `LD Vx, K`, `SKP Vx` and `SKNP Vx`, before which input is polled
+pub fn reads_keys(opcode: u16) -> bool {
+    matches!(opcode & 0xF0FF, 0xF00A | 0xE09E | 0xE0A1)
+}
+
+/// Keeps a hex key down for at least one whole frame. A tap shorter than that, even one pressed
+/// and released between two polls, would otherwise be missed by games that look at the keys
+/// once per frame, or never seen at all
+#[derive(Default)]
+pub struct MinimumHold {
+    /// frame ends since each held key was pressed
+    frames: [Option<u8>; 16],
+    /// keys released too early, released once their frame is over
+    pending: u16,
+}
+
+impl MinimumHold {
+    pub fn press(&mut self, key: Key) {
+        self.frames[key as usize & 0xF] = Some(0);
+        self.pending &= !(1 << (key & 0xF));
+    }
+
+    /// Whether the release goes through now, false if it waits for the end of the frame
+    pub fn release(&mut self, key: Key) -> bool {
+        match self.frames[key as usize & 0xF] {
+            Some(frames) if frames < 2 => {
+                self.pending |= 1 << (key & 0xF);
+                false
+            }
+            _ => {
+                self.frames[key as usize & 0xF] = None;
+                true
+            }
+        }
+    }
+
+    /// A frame ended, returns the releases that waited for it
+    pub fn frame_end(&mut self) -> Vec<Key> {
+        let mut released = Vec::new();
+        for (key, frames) in self.frames.iter_mut().enumerate() {
+            if let Some(count) = frames {
+                *count = count.saturating_add(1);
+                if *count >= 2 && self.pending & (1 << key) != 0 {
+                    *frames = None;
+                    self.pending &= !(1 << key);
+                    released.push(key as Key);
+                }
+            }
+        }
+        released
+    }
+}
+
+/// Presses the keys the way a keypad wired as a 4x4 matrix without diodes does: with three
+/// corners of a rectangle held, the current flows around it and the fourth reads as pressed too.
+/// A release can take such ghost keys away again. For testing roms against the combinations a
+/// real keypad could not tell apart
+#[derive(Default)]
+pub struct Ghosting {
+    /// hex keys physically held
+    held: u16,
+    /// hex keys the game sees as pressed
+    seen: u16,
+}
+
+impl Ghosting {
+    /// The presses and releases the game sees when `key` is physically pressed or released:
+    /// the key itself, unless a ghost already pressed it, and the keys ghosted in or out with it
+    pub fn key_event(&mut self, key: Key, pressed: bool) -> Vec<(Key, Pressed)> {
+        let bit = 1 << (key & 0xF);
+        if pressed {
+            self.held |= bit;
+        } else {
+            self.held &= !bit;
+        }
+        let seen = ghosted(self.held);
+        let changed = seen ^ self.seen;
+        self.seen = seen;
+        (0..16)
+            .filter(|key| changed & (1 << key) != 0)
+            .map(|key| (key, seen & (1 << key) != 0))
+            .collect()
+    }
+}
+
+/// The keys a matrix keypad reads with the keys of `held` down, its rows and columns those of
+/// the keypad's `LAYOUT`
+fn ghosted(held: u16) -> u16 {
+    let position = |key: Key| {
+        let i = LAYOUT.iter().position(|&k| k == key).unwrap_or(0);
+        (i / 4, i % 4)
+    };
+    let mut seen = held;
+    loop {
+        let mut next = seen;
+        let keys = (0..16).filter(|key| seen & (1 << key) != 0).map(position).collect::<Vec<_>>();
+        // a and b share a row, b and c a column: the key in the row of c and the column of a
+        // closes the rectangle
+        for &a in &keys {
+            for &b in keys.iter().filter(|b| b.0 == a.0) {
+                for &c in keys.iter().filter(|c| c.1 == b.1) {
+                    next |= 1 << LAYOUT[c.0 * 4 + a.1];
+                }
+            }
+        }
+        if next == seen {
+            return seen;
+        }
+        seen = next;
+    }
+}
+
+/// Human-readable name of a physical key
+pub fn key_name(keycode: g::VirtualKeyCode) -> String {
+    let name = format!("{:?}", keycode);
+    match name.strip_prefix("Key") {
+        Some(digit) if !digit.is_empty() => digit.to_string(),
+        _ => name,
+    }
+}
+
+pub fn keyboard_input(input: &Input) -> Option<(g::VirtualKeyCode, Pressed)> {
+    match *input {
+        Input::Key { keycode, pressed, .. } => Some((keycode, pressed)),
+        Input::Cursor(_) | Input::Close => None,
+    }
+}
+
+/// The hex key a key of the keyboard or of a controller presses. Player mappings come first,
+/// the keymap applies to the keyboard and to the controllers no player has claimed
+fn hex_key(keycode: g::VirtualKeyCode, controller: Option<usize>, keymap: &Keymap, players: &[Player]) -> Option<Key> {
+    if let Some(key) = players.iter().find_map(|player| player.hex_key(keycode, controller)) {
+        return Some(key);
+    }
+    let claimed = controller.is_some_and(|controller| {
+        players
+            .iter()
+            .any(|player| player.controller == Some(controller))
+    });
+    if claimed {
+        None
+    } else {
+        keymap.hex_key(keycode)
+    }
+}
+
+/// The action of the hotkey `keycode` is, if it is one
+fn action(keycode: g::VirtualKeyCode, pressed: Pressed, controller: Option<usize>, modifiers: Modifiers) -> Option<Action> {
+    let action = match keycode {
+        g::VirtualKeyCode::Escape => Action::Exit,
+        // a bound V still presses its hex key without Ctrl
+        g::VirtualKeyCode::V if pressed && modifiers.ctrl() && controller.is_none() => Action::Paste,
+        g::VirtualKeyCode::F1 if pressed => Action::ToggleDebugWindow,
+        g::VirtualKeyCode::F2 if pressed => Action::ToggleOverlay,
+        g::VirtualKeyCode::F3 if pressed => Action::ToggleKeymapEditor,
+        g::VirtualKeyCode::F4 if pressed => Action::ToggleHeatmap,
+        g::VirtualKeyCode::F5 if pressed => Action::SaveState,
+        g::VirtualKeyCode::F6 if pressed => Action::SelectSlot { offset: -1 },
+        g::VirtualKeyCode::F7 if pressed => Action::SelectSlot { offset: 1 },
+        g::VirtualKeyCode::F8 if pressed => Action::TogglePerfGraph,
+        g::VirtualKeyCode::F9 if pressed => Action::LoadState,
+        g::VirtualKeyCode::F10 if pressed => Action::TogglePause,
+        g::VirtualKeyCode::F11 if pressed => Action::StepFrame,
+        g::VirtualKeyCode::F12 if pressed => Action::ToggleKeypad,
+        g::VirtualKeyCode::Insert if pressed => Action::ToggleFullscreen,
+        g::VirtualKeyCode::Tab => Action::Turbo { pressed },
+        g::VirtualKeyCode::Back => Action::Rewind { pressed },
+        g::VirtualKeyCode::PageUp if pressed => Action::AdjustTone { brightness: 0.1, gamma: 0.0 },
+        g::VirtualKeyCode::PageDown if pressed => Action::AdjustTone { brightness: -0.1, gamma: 0.0 },
+        g::VirtualKeyCode::Home if pressed => Action::AdjustTone { brightness: 0.0, gamma: 0.1 },
+        g::VirtualKeyCode::End if pressed => Action::AdjustTone { brightness: 0.0, gamma: -0.1 },
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// The mapping layer between the frontends and the emulator: hotkeys first, then the players'
+/// bindings and the keymap. None for keys bound to nothing and for the mouse
+pub fn map_key(input: &Input, keymap: &Keymap, players: &[Player], modifiers: Modifiers) -> Option<InputEvent> {
+    match *input {
+        Input::Close => Some(InputEvent::UiAction(Action::Exit)),
+        Input::Key { keycode, pressed, controller } => match action(keycode, pressed, controller, modifiers) {
+            Some(action) => Some(InputEvent::UiAction(action)),
+            None => hex_key(keycode, controller, keymap, players).map(|key| InputEvent::Key(key, pressed)),
+        },
+        Input::Cursor(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_map_alike() {
+        let keymap = Keymap::default();
+        let map = |input: Option<Input>| map_key(&input.unwrap(), &keymap, &[], Modifiers::default());
+
+        assert_eq!(map(web::key("keydown", "KeyW", false)), Some(InputEvent::Key(5, true)));
+        assert_eq!(map(web::key("keyup", "Digit1", false)), Some(InputEvent::Key(1, false)));
+        assert_eq!(map(web::key("keydown", "F5", false)), Some(InputEvent::UiAction(Action::SaveState)));
+        assert_eq!(map(web::key("keydown", "KeyP", false)), None);
+        assert!(web::key("keydown", "KeyW", true).is_none());
+    }
+
+    #[test]
+    fn ghosts_the_fourth_corner() {
+        let mut ghosting = Ghosting::default();
+        assert_eq!(ghosting.key_event(0x1, true), vec![(0x1, true)]);
+        assert_eq!(ghosting.key_event(0x2, true), vec![(0x2, true)]);
+        // 1 2 in the top row and 4 below 1 make 5 read as pressed
+        assert_eq!(ghosting.key_event(0x4, true), vec![(0x4, true), (0x5, true)]);
+        // already seen, so the game gets nothing
+        assert_eq!(ghosting.key_event(0x5, true), vec![]);
+        assert_eq!(ghosting.key_event(0x5, false), vec![]);
+        assert_eq!(ghosting.key_event(0x1, false), vec![(0x1, false), (0x5, false)]);
+        assert_eq!(ghosted(1 << 0xA | 1 << 0xF | 1 << 0xC), 1 << 0xA | 1 << 0xF | 1 << 0xC | 1 << 0x1);
+    }
+}
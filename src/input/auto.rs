@@ -0,0 +1,101 @@
+use winit::VirtualKeyCode;
+
+use super::Key;
+use crate::overlay::keymap::LAYOUT;
+
+/// The arrows and their direction on the keypad, x to the right and y down
+const DIRECTIONS: [(VirtualKeyCode, (f32, f32)); 4] = [
+    (VirtualKeyCode::Up, (0.0, -1.0)),
+    (VirtualKeyCode::Down, (0.0, 1.0)),
+    (VirtualKeyCode::Left, (-1.0, 0.0)),
+    (VirtualKeyCode::Right, (1.0, 0.0)),
+];
+
+/// Keyboard keys for the hex keys in `polled`, one bit per key, from where they are on the
+/// keypad: each arrow goes to the key furthest in its direction from the middle of the polled
+/// keys, and space to the remaining key nearest the middle, which games tend to use for fire.
+/// Games with two players or more than one action key need a `keymap` in their entry
+pub fn bindings(polled: u16) -> Vec<(Key, VirtualKeyCode)> {
+    let keys = LAYOUT
+        .iter()
+        .enumerate()
+        .filter(|(_, &key)| polled & 1 << key != 0)
+        .map(|(i, &key)| (key, ((i % 4) as f32, (i / 4) as f32)))
+        .collect::<Vec<_>>();
+    if keys.is_empty() {
+        return Vec::new();
+    }
+
+    let count = keys.len() as f32;
+    let middle = (
+        keys.iter().map(|(_, pos)| pos.0).sum::<f32>() / count,
+        keys.iter().map(|(_, pos)| pos.1).sum::<f32>() / count,
+    );
+    let offset = |pos: (f32, f32)| (pos.0 - middle.0, pos.1 - middle.1);
+
+    // how far each key is in each direction, less half of how far it is off to the side
+    let mut candidates = Vec::new();
+    for &(key, pos) in &keys {
+        let (x, y) = offset(pos);
+        for &(keycode, (dx, dy)) in &DIRECTIONS {
+            let score = (x * dx + y * dy) - (x * dy - y * dx).abs() / 2.0;
+            if score > 0.0 {
+                candidates.push((score, key, keycode));
+            }
+        }
+    }
+    // stable, ties go to the key that comes first on the keypad
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut bindings: Vec<(Key, VirtualKeyCode)> = Vec::new();
+    for (_, key, keycode) in candidates {
+        if bindings.iter().all(|&(bound, bound_keycode)| bound != key && bound_keycode != keycode) {
+            bindings.push((key, keycode));
+        }
+    }
+
+    let distance = |pos: (f32, f32)| {
+        let (x, y) = offset(pos);
+        x * x + y * y
+    };
+    let fire = keys
+        .iter()
+        .filter(|(key, _)| bindings.iter().all(|(bound, _)| bound != key))
+        .min_by(|a, b| distance(a.1).total_cmp(&distance(b.1)));
+    if let Some(&(key, _)) = fire {
+        bindings.push((key, VirtualKeyCode::Space));
+    }
+
+    bindings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VirtualKeyCode::*;
+
+    fn polled(keys: &[Key]) -> u16 {
+        keys.iter().fold(0, |polled, key| polled | 1 << key)
+    }
+
+    fn sorted(mut bindings: Vec<(Key, VirtualKeyCode)>) -> Vec<(Key, VirtualKeyCode)> {
+        bindings.sort_by_key(|(key, _)| *key);
+        bindings
+    }
+
+    #[test]
+    fn layouts() {
+        assert_eq!(
+            sorted(bindings(polled(&[2, 4, 5, 6, 8]))),
+            vec![(2, Up), (4, Left), (5, Space), (6, Right), (8, Down)]
+        );
+        assert_eq!(
+            sorted(bindings(polled(&[5, 7, 8, 9]))),
+            vec![(5, Up), (7, Left), (8, Down), (9, Right)]
+        );
+        assert_eq!(sorted(bindings(polled(&[4, 5, 6]))), vec![(4, Left), (5, Space), (6, Right)]);
+        assert_eq!(sorted(bindings(polled(&[1, 4]))), vec![(1, Up), (4, Down)]);
+        assert_eq!(bindings(polled(&[0xA])), vec![(0xA, Space)]);
+        assert_eq!(bindings(0), vec![]);
+    }
+}
@@ -0,0 +1,13 @@
+use glium::glutin::{ElementState, KeyboardInput};
+
+use crate::frontend::Input;
+
+/// A key of the glium window. Glutin already reports the key codes the keymap uses, keys it
+/// can not name are dropped
+pub fn key(input: KeyboardInput) -> Option<Input> {
+    Some(Input::Key {
+        keycode: input.virtual_keycode?,
+        pressed: input.state == ElementState::Pressed,
+        controller: None,
+    })
+}
@@ -0,0 +1,52 @@
+use sdl2::controller::Button;
+use sdl2::keyboard::Scancode;
+use winit::VirtualKeyCode;
+
+/// Controller buttons act as keyboard keys, so they can be bound in the keymap
+pub fn button(button: Button) -> Option<VirtualKeyCode> {
+    let keycode = match button {
+        Button::DPadUp => VirtualKeyCode::Up,
+        Button::DPadDown => VirtualKeyCode::Down,
+        Button::DPadLeft => VirtualKeyCode::Left,
+        Button::DPadRight => VirtualKeyCode::Right,
+        Button::A => VirtualKeyCode::Space,
+        Button::B => VirtualKeyCode::Return,
+        Button::X => VirtualKeyCode::LShift,
+        Button::Y => VirtualKeyCode::LControl,
+        _ => return None,
+    };
+    Some(keycode)
+}
+
+/// Translates physical keys to the key codes stored in the keymap
+pub fn scancode(scancode: Scancode) -> Option<VirtualKeyCode> {
+    macro_rules! keycodes {
+        ($($scancode:ident => $keycode:ident),* $(,)?) => {
+            match scancode {
+                $(Scancode::$scancode => Some(VirtualKeyCode::$keycode),)*
+                _ => None,
+            }
+        };
+    }
+
+    keycodes! {
+        Num0 => Key0, Num1 => Key1, Num2 => Key2, Num3 => Key3, Num4 => Key4,
+        Num5 => Key5, Num6 => Key6, Num7 => Key7, Num8 => Key8, Num9 => Key9,
+        A => A, B => B, C => C, D => D, E => E, F => F, G => G, H => H, I => I,
+        J => J, K => K, L => L, M => M, N => N, O => O, P => P, Q => Q, R => R,
+        S => S, T => T, U => U, V => V, W => W, X => X, Y => Y, Z => Z,
+        F1 => F1, F2 => F2, F3 => F3, F4 => F4, F5 => F5, F6 => F6,
+        F7 => F7, F8 => F8, F9 => F9, F10 => F10, F11 => F11, F12 => F12,
+        Kp0 => Numpad0, Kp1 => Numpad1, Kp2 => Numpad2, Kp3 => Numpad3, Kp4 => Numpad4,
+        Kp5 => Numpad5, Kp6 => Numpad6, Kp7 => Numpad7, Kp8 => Numpad8, Kp9 => Numpad9,
+        Up => Up, Down => Down, Left => Left, Right => Right,
+        Escape => Escape, Return => Return, Space => Space, Tab => Tab, Backspace => Back,
+        Insert => Insert, Delete => Delete, Home => Home, End => End,
+        PageUp => PageUp, PageDown => PageDown,
+        LShift => LShift, RShift => RShift, LCtrl => LControl, RCtrl => RControl,
+        LAlt => LAlt, RAlt => RAlt,
+        Minus => Minus, Equals => Equals, LeftBracket => LBracket, RightBracket => RBracket,
+        Semicolon => Semicolon, Apostrophe => Apostrophe, Comma => Comma, Period => Period,
+        Slash => Slash, Backslash => Backslash, Grave => Grave,
+    }
+}
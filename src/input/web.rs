@@ -0,0 +1,54 @@
+use winit::VirtualKeyCode;
+
+use crate::frontend::Input;
+
+/// A browser `KeyboardEvent`, from its `type`, `code` and `repeat`. `code` names the physical
+/// key like `KeyA` or `ArrowUp`, so the keymap applies whatever the keyboard layout. Held keys
+/// repeat their `keydown`, only the first one is a press
+pub fn key(event_type: &str, code: &str, repeat: bool) -> Option<Input> {
+    let pressed = match event_type {
+        "keydown" if !repeat => true,
+        "keyup" => false,
+        _ => return None,
+    };
+    Some(Input::Key {
+        keycode: key_code(code)?,
+        pressed,
+        controller: None,
+    })
+}
+
+/// Translates the `code` of a `KeyboardEvent` to the key codes stored in the keymap
+pub fn key_code(code: &str) -> Option<VirtualKeyCode> {
+    macro_rules! keycodes {
+        ($($code:ident => $keycode:ident),* $(,)?) => {
+            match code {
+                $(stringify!($code) => Some(VirtualKeyCode::$keycode),)*
+                _ => None,
+            }
+        };
+    }
+
+    keycodes! {
+        Digit0 => Key0, Digit1 => Key1, Digit2 => Key2, Digit3 => Key3, Digit4 => Key4,
+        Digit5 => Key5, Digit6 => Key6, Digit7 => Key7, Digit8 => Key8, Digit9 => Key9,
+        KeyA => A, KeyB => B, KeyC => C, KeyD => D, KeyE => E, KeyF => F, KeyG => G,
+        KeyH => H, KeyI => I, KeyJ => J, KeyK => K, KeyL => L, KeyM => M, KeyN => N,
+        KeyO => O, KeyP => P, KeyQ => Q, KeyR => R, KeyS => S, KeyT => T, KeyU => U,
+        KeyV => V, KeyW => W, KeyX => X, KeyY => Y, KeyZ => Z,
+        F1 => F1, F2 => F2, F3 => F3, F4 => F4, F5 => F5, F6 => F6,
+        F7 => F7, F8 => F8, F9 => F9, F10 => F10, F11 => F11, F12 => F12,
+        Numpad0 => Numpad0, Numpad1 => Numpad1, Numpad2 => Numpad2, Numpad3 => Numpad3,
+        Numpad4 => Numpad4, Numpad5 => Numpad5, Numpad6 => Numpad6, Numpad7 => Numpad7,
+        Numpad8 => Numpad8, Numpad9 => Numpad9,
+        ArrowUp => Up, ArrowDown => Down, ArrowLeft => Left, ArrowRight => Right,
+        Escape => Escape, Enter => Return, Space => Space, Tab => Tab, Backspace => Back,
+        Insert => Insert, Delete => Delete, Home => Home, End => End,
+        PageUp => PageUp, PageDown => PageDown,
+        ShiftLeft => LShift, ShiftRight => RShift, ControlLeft => LControl,
+        ControlRight => RControl, AltLeft => LAlt, AltRight => RAlt,
+        Minus => Minus, Equal => Equals, BracketLeft => LBracket, BracketRight => RBracket,
+        Semicolon => Semicolon, Quote => Apostrophe, Comma => Comma, Period => Period,
+        Slash => Slash, Backslash => Backslash, Backquote => Grave,
+    }
+}
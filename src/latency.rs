@@ -0,0 +1,106 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::rom::Builder;
+
+/// Key events the screen has not changed in response to within this time are dropped
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// keypad key the diagnostic rom reacts to
+pub const KEY: u8 = 5;
+
+/// The diagnostic rom: shows a block while keypad key 5 is held, so every press and release
+/// changes the screen within a couple of instructions
+pub fn rom() -> Vec<u8> {
+    // with I pointing at the block, V0/V1 are its position and V5 the key
+    Builder::new()
+        .set_reg(5, KEY)
+        .set_reg(0, 28)
+        .set_reg(1, 12)
+        .set_index("block")
+        .label("wait_press")
+        .op(0xE59E) // SKP V5
+        .jump("wait_press")
+        .op(0xD018) // DRW V0, V1, 8
+        .label("wait_release")
+        .op(0xE5A1) // SKNP V5
+        .jump("wait_release")
+        .op(0xD018)
+        .jump("wait_press")
+        .label("block")
+        .bytes(&[0xFF; 8])
+        .build()
+        .expect("the latency rom has no undefined labels")
+}
+
+/// Measures the time from receiving a key event to presenting the first frame that differs
+/// from the screen at the time of the event
+#[derive(Default)]
+pub struct LatencyProbe {
+    /// when the event was received and the screen back then
+    pending: Option<(Instant, Vec<u8>)>,
+    samples: Vec<Duration>,
+}
+
+impl LatencyProbe {
+    /// Called when a key event is passed to the system.
+    /// Events arriving while an earlier one is still waiting for its frame are not measured
+    pub fn key_event(&mut self, screen: Vec<u8>) {
+        if self.pending.is_none() {
+            self.pending = Some((Instant::now(), screen));
+        }
+    }
+
+    /// Called after every frame has been presented
+    pub fn frame_presented(&mut self, screen: &[u8]) {
+        if let Some((received, before)) = &self.pending {
+            let latency = received.elapsed();
+            if latency > TIMEOUT {
+                self.pending = None;
+            } else if before.as_slice() != screen {
+                log::debug!(target: "input", "Latency {:.1} ms", latency.as_secs_f64() * 1000.0);
+                self.samples.push(latency);
+                self.pending = None;
+            }
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            count: self.samples.len(),
+            average: self
+                .samples
+                .iter()
+                .sum::<Duration>()
+                .checked_div(self.samples.len() as u32)
+                .unwrap_or_default(),
+            best: self.samples.iter().min().copied().unwrap_or_default(),
+            worst: self.samples.iter().max().copied().unwrap_or_default(),
+        }
+    }
+}
+
+pub struct LatencyReport {
+    pub count: usize,
+    pub average: Duration,
+    pub best: Duration,
+    pub worst: Duration,
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "No key events were measured");
+        }
+
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{} key events: average {:.1} ms, best {:.1} ms, worst {:.1} ms",
+            self.count,
+            ms(self.average),
+            ms(self.best),
+            ms(self.worst)
+        )
+    }
+}
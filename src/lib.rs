@@ -0,0 +1,1617 @@
+//! The emulator: the machine, the roms it runs, the assembler and the disassembler, and `Chip8`,
+//! the run loop with its window, overlays and tools, which `emulator::EmulatorBuilder` sets up
+//! for embedders. `headless` runs the machine without a window, with `stream` (feature `async`)
+//! for network frontends, and `framebuffer` has its frames, as `image` images with the feature.
+//! The `chip8` binary is its command line, and `capi` and `python` make the machine available
+//! to other languages
+
+#![allow(non_local_definitions)]
+
+#[cfg(feature = "gui")]
+#[macro_use]
+extern crate glium;
+#[macro_use]
+extern crate failure;
+
+use failure::Error;
+use std::path::{Path, PathBuf};
+
+use system::machine::Chip8Core;
+
+#[cfg(feature = "analysis")]
+pub mod analysis;
+#[cfg(feature = "assembler")]
+pub mod asm;
+pub mod audio;
+pub mod audit;
+pub mod batch;
+pub mod bench;
+pub mod calibrate;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod capture;
+pub mod cli;
+pub mod clipboard;
+pub mod clock;
+pub mod compat;
+pub mod config;
+pub mod container;
+pub mod crash;
+#[cfg(feature = "debugger")]
+pub mod debugger;
+pub mod demo;
+pub mod disasm;
+pub mod emulator;
+pub mod error;
+pub mod framebuffer;
+pub mod frontend;
+pub mod headless;
+pub mod input;
+pub mod latency;
+#[cfg(feature = "analysis")]
+pub mod lint;
+pub mod overlay;
+pub mod paths;
+pub mod png;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod quirktest;
+pub mod ratings;
+pub mod replay;
+pub mod rom;
+pub mod romsearch;
+pub mod scores;
+pub mod script;
+pub mod selftest;
+pub mod splash;
+pub mod storage;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod summary;
+pub mod system;
+pub mod testsuite;
+pub mod timing;
+pub mod tournament;
+pub mod watch;
+pub mod watchdog;
+#[cfg(feature = "gui")]
+pub mod window;
+
+pub struct Chip8<C: Chip8Core = system::System> {
+    /// runs the program, see `Chip8Core`
+    core: C,
+    window: Box<dyn frontend::Frontend>,
+    overlay: overlay::Overlay,
+    heatmap: overlay::heatmap::Heatmap,
+    perf: overlay::perf::PerfGraph,
+    /// how long the overlays take to build each frame
+    overlay_cost: overlay::cost::CostMeter,
+    keypad: overlay::keypad::Keypad,
+    pause: clock::Pause,
+    keymap_editor: Option<overlay::keymap::KeymapEditor>,
+    /// where the debugger is shown, None when it is hidden
+    #[cfg(feature = "debugger")]
+    debugger: Option<overlay::debugger::Placement>,
+    /// commands typed into the game window, while the debugger view is shown
+    #[cfg(feature = "debugger")]
+    window_debugger: Option<debugger::WindowDebugger>,
+    /// `chip8 debug` reads the commands from the terminal instead
+    #[cfg(feature = "debugger")]
+    terminal_debugger: bool,
+    /// the parts of the debugger view that are shown
+    #[cfg(feature = "debugger")]
+    debug_panels: overlay::debugger::Panels,
+    compat: Option<compat::CompatReport>,
+    diagnostics: cli::DiagnosticsMode,
+    pub config: config::Config,
+    /// the config file and the directories for everything else
+    paths: paths::Paths,
+    /// reloads the config when the file changes
+    config_watch: config::watch::ConfigWatcher,
+    /// what the last config reload and hotkeys did
+    notifications: overlay::notify::Notifications,
+    display_args: cli::DisplayArgs,
+    /// `[keymap]` with the bindings of the loaded rom's entry and the automatic ones applied
+    keymap: input::Keymap,
+    /// the keys the program had polled when the automatic bindings were made
+    auto_polled: u16,
+    /// the turbo key is held
+    turbo: bool,
+    rapid_fire: input::RapidFire,
+    /// Ctrl is held, for the paste chord
+    modifiers: input::Modifiers,
+    /// hex keys pasted from the clipboard still to be typed
+    paste: input::KeySequence,
+    /// taps shorter than a frame
+    hold: input::MinimumHold,
+    /// the keys a matrix keypad would read, with `ghosting` in `[keymap]`
+    ghosting: input::Ghosting,
+    /// the states of the last frames, played back while the rewind key is held
+    rewind: system::rewind::RewindBuffer,
+    rewinding: bool,
+    scores: scores::HighScores,
+    score_tracker: Option<scores::ScoreTracker>,
+    /// new high score and for how many more frames it is shown
+    high_score_banner: Option<(u64, u32)>,
+    /// set by `chip8 latency`
+    pub latency: Option<latency::LatencyProbe>,
+    /// `rom::hash` of the loaded rom
+    rom_hash: u64,
+    /// the extension the loaded rom's opcodes are of, see `compat::detect`
+    platform: Option<compat::Platform>,
+    /// save state slots, `run` of the storage keys is the slot number
+    storage: storage::FileStorage,
+    slots: overlay::slots::SlotBrowser,
+    /// the palette given to the window, for exported frames
+    palette: frontend::palette::Palette,
+    /// set by `--frames-out`
+    frames_out: Option<capture::FrameWriter>,
+    /// set by `--record-audio`, gets everything the frontend's sink does
+    record_audio: Option<Box<dyn audio::AudioSink>>,
+    beep: audio::BeepSchedule,
+    /// set by `--record-replay`
+    replay: Option<replay::Recorder>,
+    /// set by `--summary-json`
+    summary: Option<summary::RunSummary>,
+    /// set by `--watch-dir`, the rom picker lists its directory
+    watch: Option<watch::RomWatcher>,
+    /// the program is stuck in a loop it can never leave, see `clock::HaltAction`
+    halted: bool,
+    /// set by `--teach`, paces the program and explains each instruction
+    teach: Option<overlay::teach::Teacher>,
+    /// the instruction trace with the key events between the instructions, enabled by
+    /// `--trace` and while stepping in the debugger
+    pub trace: system::debug::Debugger,
+    /// a watched rom that changed, to run instead of the current one
+    next_rom: Option<PathBuf>,
+    /// started by the first run of a rom, unless `timing.watchdog_secs` is 0
+    watchdog: Option<watchdog::Watchdog>,
+    /// `System::save_state` right after the rom was loaded, to start it over after a stall
+    start_state: Vec<u8>,
+    redraw: frontend::redraw::RedrawFilter,
+    /// the game screen last drawn, for the dirty regions of the next
+    dirty: frontend::redraw::DirtyTracker,
+    /// given to the window, kept to fit the screen to the resolutions the game switches to
+    scaling: frontend::Scaling,
+    /// for crash dumps
+    recent: crash::RecentInstructions,
+    /// over the window, for the pixel inspector while paused
+    cursor: Option<frontend::Cursor>,
+}
+
+impl<C: Chip8Core> Chip8<C> {
+    pub fn new(
+        config: config::Config,
+        config_path: PathBuf,
+        display_args: cli::DisplayArgs,
+    ) -> Result<Self, Error> {
+        let scale = display_args.scale.or(config.display.scale);
+        let dimensions = match scale {
+            Some(scale) => (64 * scale, 32 * scale),
+            None => (800, 400),
+        };
+
+        let renderer = display_args.renderer;
+        let window = match frontend::create(renderer, dimensions, config.display.present_mode, &config.audio) {
+            Ok(window) => window,
+            Err(e) if renderer != frontend::Renderer::Tui && frontend::terminal_available() => {
+                log::warn!(target: "display", "Could not open a window with the {:?} renderer ({}), drawing in the terminal instead", renderer, e);
+                frontend::create(frontend::Renderer::Tui, dimensions, config.display.present_mode, &config.audio).map_err(|e| {
+                    error::WindowError {
+                        renderer: frontend::Renderer::Tui,
+                        reason: e.to_string(),
+                    }
+                })?
+            }
+            Err(e) => {
+                return Err(error::WindowError {
+                    renderer,
+                    reason: e.to_string(),
+                }
+                .into())
+            }
+        };
+        Chip8::with_window(config, config_path, display_args, window)
+    }
+
+    /// `new` with a window opened elsewhere, e.g. a frontend of an embedder
+    pub fn with_window(
+        config: config::Config,
+        config_path: PathBuf,
+        display_args: cli::DisplayArgs,
+        mut window: Box<dyn frontend::Frontend>,
+    ) -> Result<Self, Error> {
+        let scale = display_args.scale.or(config.display.scale);
+        let paths = paths::Paths::for_config(config_path);
+        log::debug!("Config {}, data in {}", paths.config.display(), paths.data.display());
+        let storage = storage::FileStorage::new(&paths.data);
+        // a scale that was asked for wins over the size the window was left at
+        if let Some(geometry) = frontend::geometry::WindowGeometry::load(&paths.data) {
+            window.set_geometry(&geometry, scale.is_none());
+        }
+
+        #[cfg_attr(not(feature = "debugger"), allow(unused_mut))]
+        let mut chip8 = Chip8 {
+            core: C::default(),
+            window,
+            overlay: overlay::Overlay::default(),
+            heatmap: overlay::heatmap::Heatmap::default(),
+            perf: overlay::perf::PerfGraph::default(),
+            overlay_cost: overlay::cost::CostMeter::default(),
+            keypad: overlay::keypad::Keypad {
+                enabled: config.display.show_keypad,
+            },
+            pause: clock::Pause::default(),
+            keymap_editor: None,
+            #[cfg(feature = "debugger")]
+            debugger: None,
+            #[cfg(feature = "debugger")]
+            window_debugger: None,
+            #[cfg(feature = "debugger")]
+            terminal_debugger: false,
+            #[cfg(feature = "debugger")]
+            debug_panels: Default::default(),
+            compat: None,
+            diagnostics: cli::DiagnosticsMode::Off,
+            config_watch: config::watch::ConfigWatcher::new(paths.config.clone(), config.clone()),
+            notifications: Default::default(),
+            rewind: system::rewind::RewindBuffer::new(config.rewind),
+            rewinding: false,
+            config,
+            paths,
+            display_args,
+            keymap: Default::default(),
+            auto_polled: 0,
+            turbo: false,
+            rapid_fire: input::RapidFire::default(),
+            modifiers: input::Modifiers::default(),
+            paste: input::KeySequence::default(),
+            hold: input::MinimumHold::default(),
+            ghosting: input::Ghosting::default(),
+            scores: Default::default(),
+            score_tracker: None,
+            high_score_banner: None,
+            latency: None,
+            rom_hash: 0,
+            platform: None,
+            storage,
+            slots: overlay::slots::SlotBrowser::default(),
+            palette: Default::default(),
+            frames_out: None,
+            record_audio: None,
+            beep: audio::BeepSchedule::default(),
+            replay: None,
+            summary: None,
+            watch: None,
+            halted: false,
+            teach: None,
+            trace: system::debug::Debugger::disabled(),
+            next_rom: None,
+            watchdog: None,
+            start_state: Vec::new(),
+            redraw: Default::default(),
+            dirty: Default::default(),
+            scaling: Default::default(),
+            recent: Default::default(),
+            cursor: None,
+        };
+        #[cfg(feature = "debugger")]
+        if chip8.display_args.debug_window {
+            chip8.toggle_window_debugger();
+        }
+        Ok(chip8)
+    }
+
+    /// loads the rom and applies its settings from the game database, `quirks` are used
+    /// when the database has none for it
+    pub fn load_rom(&mut self, data: &[u8], quirks: Option<system::quirks::Quirks>) -> Result<(), Error> {
+        let game_quirks = self.config.game(rom::hash(data)).and_then(|game| game.quirks);
+        self.platform = compat::detect(data);
+        let detected = self.config.detected_quirks(self.platform);
+        let platform = self.platform.map_or("CHIP-8".to_string(), |platform| platform.to_string());
+        if let (None, Some(detected), Some(_)) = (game_quirks.or(quirks), self.platform, detected) {
+            log::info!(target: "cpu", "{} rom, running it with the {} quirks", detected, detected.profile());
+        }
+        self.window.set_title(&format!("Chip8 - {}", platform));
+        // before loading, `bank_port` decides where a large rom goes
+        self.core.state_mut().quirks = game_quirks.or(quirks).or(detected).unwrap_or(self.config.quirks);
+        self.core.state_mut().font = self.font_for(rom::hash(data));
+        self.core.load(data)?;
+        self.start_state = self.core.state().save_state();
+        self.rom_hash = rom::hash(data);
+        self.slots.refresh(&self.storage, self.rom_hash);
+
+        self.score_tracker = None;
+        self.recent.clear();
+        self.paste = input::KeySequence::default();
+        self.hold = input::MinimumHold::default();
+        self.rewind.clear();
+        self.scaling.fit = None;
+        let region = self.config.game(rom::hash(data)).and_then(|game| game.score);
+        if let Some(region) = region {
+            self.scores = scores::HighScores::load(self.scores_path())?;
+            self.score_tracker = Some(scores::ScoreTracker::new(rom::hash(data), region, &self.scores));
+        }
+
+        self.apply_display()?;
+        #[cfg(feature = "audio")]
+        self.apply_sample();
+        self.apply_keymap();
+
+        Ok(())
+    }
+
+    /// The font of the rom from the config, the default one if its file cannot be loaded
+    fn font_for(&self, rom_hash: u64) -> system::fonts::Font {
+        let dir = self.paths.config.parent().unwrap_or(Path::new(""));
+        self.config.font_for(rom_hash).load(dir).unwrap_or_else(|e| {
+            log::warn!("Using the default font: {}", e);
+            system::fonts::SCHIP
+        })
+    }
+
+    /// Makes the keymap of the loaded rom, with the keys the program polled so far bound to
+    /// the arrows and space if `auto` is set. Keys whose binding changed are released
+    fn apply_keymap(&mut self) {
+        let mut keymap = self.config.keymap_for(self.rom_hash);
+        self.auto_polled = self.core.state().polled_keys;
+        if keymap.auto {
+            let bindings = input::auto::bindings(self.auto_polled);
+            for &(key, keycode) in &bindings {
+                keymap.bind(key, keycode);
+            }
+            if !bindings.is_empty() {
+                let bindings = bindings
+                    .iter()
+                    .map(|(key, keycode)| format!("{:X}: {}", key, input::key_name(*keycode)))
+                    .collect::<Vec<_>>();
+                log::info!(target: "input", "Bound the keys the game polls: {}", bindings.join(", "));
+            }
+        }
+
+        for key in 0..16 {
+            if keymap.get(key) != self.keymap.get(key) {
+                if let (Some(recorder), true) = (&mut self.replay, self.core.state().keys.keys[key as usize] != 0) {
+                    recorder.event(replay::Action::Release(key));
+                }
+                self.core.state_mut().keys.keys[key as usize] = 0;
+                self.rapid_fire.key_event(key, false);
+            }
+        }
+        self.keymap = keymap;
+    }
+
+    /// Gives the display settings of the loaded rom to the window
+    fn apply_display(&mut self) -> Result<(), Error> {
+        let display = self.config.display_for(self.rom_hash);
+        self.window.set_anti_flicker(display.anti_flicker);
+        self.redraw.set_settle(display.anti_flicker.settle_frames());
+        self.window.set_tone(display.tone);
+
+        let palette = match self.display_args.palette {
+            Some(preset) => frontend::palette::Palette::preset(preset),
+            None => frontend::palette::Palette::from_config(&display.palette)
+                .map_err(|e| format_err!("Invalid palette in config: {}", e))?,
+        };
+        self.set_palette(palette);
+        let pixel_aspect = match (self.display_args.pixel_aspect, &display.pixel_aspect) {
+            (Some(aspect), _) => Some(aspect),
+            (None, Some(text)) => Some(
+                frontend::parse_pixel_aspect(text).map_err(|e| format_err!("Invalid pixel aspect in config: {}", e))?,
+            ),
+            (None, None) => None,
+        };
+        self.scaling = frontend::Scaling {
+            integer: self.display_args.integer_scaling || display.integer_scaling,
+            pixel_aspect,
+            fit: self.scaling.fit,
+        };
+        self.window.set_scaling(self.scaling);
+        let bezel = display.bezel.as_ref().and_then(|config| {
+            let dir = self.paths.config.parent().unwrap_or(Path::new(""));
+            frontend::bezel::Bezel::load(config, dir)
+                .map_err(|e| log::warn!(target: "display", "Not showing the bezel: {}", e))
+                .ok()
+        });
+        self.window.set_bezel(bezel);
+        let shader = display.shader.as_ref().map(|config| {
+            let dir = self.paths.config.parent().unwrap_or(Path::new(""));
+            frontend::shader::Shader::load(config, dir)
+        });
+        if let Err(e) = shader.transpose().and_then(|shader| self.window.set_shader(shader)) {
+            log::warn!(target: "display", "Using the built-in shader: {}", e);
+            self.window.set_shader(None)?;
+        }
+        // every pixel may look different now
+        self.dirty.reset();
+
+        Ok(())
+    }
+
+    /// Loads the beep sample of the loaded rom, the tone if there is none
+    #[cfg(feature = "audio")]
+    fn apply_sample(&mut self) {
+        let sample = self.config.sample_for(self.rom_hash).and_then(|path| {
+            let path = self.paths.config.parent().unwrap_or(Path::new("")).join(path);
+            audio::Sample::load(&path)
+                .map_err(|e| log::warn!(target: "audio", "Using the tone for the beep: {}", e))
+                .ok()
+        });
+        self.audio(|sink| sink.set_beep(sample.as_ref()));
+    }
+
+    /// Checks whether the config file changed and applies what can be while the rom runs.
+    /// The rest waits for the rom to be loaded again or for a restart, which the banner says
+    fn reload_config(&mut self) {
+        let (config, changes) = match self.config_watch.poll() {
+            None => return,
+            Some(Ok((_, changes))) if !changes.any() => return,
+            Some(Ok(reloaded)) => reloaded,
+            Some(Err(e)) => {
+                log::warn!("Keeping the old config, {} could not be loaded: {}", self.paths.config.display(), e);
+                self.notifications.show("config", "config error");
+                return;
+            }
+        };
+        log::info!("Reloaded {}", self.paths.config.display());
+        // the game database applies to everything that follows
+        self.config.games = config.games.clone();
+
+        if changes.keys {
+            self.config.keymap = config.keymap;
+            self.config.players = config.players;
+            self.apply_keymap();
+        }
+        if changes.timing {
+            self.config.timing = config.timing;
+        }
+        if changes.power {
+            self.config.power = config.power;
+        }
+        if changes.audio {
+            self.config.audio = config.audio;
+            #[cfg(feature = "audio")]
+            self.apply_sample();
+        }
+        if changes.rewind {
+            self.config.rewind = config.rewind;
+            self.rewind.set_config(self.config.rewind);
+        }
+
+        let mut reload_rom = Vec::new();
+        if changes.quirks {
+            self.config.quirks = config.quirks;
+            self.config.detect_platform = config.detect_platform;
+            reload_rom.extend(self.apply_quirks(self.config.quirks_on(self.rom_hash, self.platform)));
+        }
+        if changes.font {
+            self.config.font = config.font;
+            // the program may have copied the digits elsewhere already
+            if self.font_for(self.rom_hash) != self.core.state().font {
+                reload_rom.push("font");
+            }
+        }
+        if changes.display {
+            self.config.display = config.display;
+            if let Err(e) = self.apply_display() {
+                log::warn!(target: "display", "{}", e);
+            }
+        }
+
+        let message = if !changes.restart.is_empty() {
+            log::info!("Restart to apply the new {} settings", changes.restart.join(", "));
+            format!("RESTART FOR {}", changes.restart.join(", "))
+        } else if !reload_rom.is_empty() {
+            log::info!("Load the rom again to apply the new {} settings", reload_rom.join(", "));
+            format!("RELOAD ROM FOR {}", reload_rom.join(", "))
+        } else {
+            "CONFIG RELOADED".to_string()
+        };
+        self.notifications.show("config", message);
+    }
+
+    /// Switches the running program to `quirks` without a reset, except for those that cannot
+    /// change under it, whose names are returned
+    pub fn apply_quirks(&mut self, mut quirks: system::quirks::Quirks) -> Vec<&'static str> {
+        let mut kept = Vec::new();
+        let current = self.core.state().quirks;
+        // the banks of the rom were filled when it loaded
+        if quirks.bank_port != current.bank_port {
+            quirks.bank_port = current.bank_port;
+            kept.push("bank port");
+        }
+        // return addresses past a smaller stack would be lost
+        if quirks.stack_depth() < usize::from(self.core.state().stack.sp) {
+            quirks.stack_size = current.stack_size;
+            kept.push("stack size");
+        }
+        self.core.state_mut().quirks = quirks;
+        kept
+    }
+
+    /// Loads the rom of a container and applies its metadata.
+    /// Settings from the game database and the command line take precedence
+    pub fn load_container(&mut self, container: &container::Container) -> Result<(), Error> {
+        if !rom::is_aligned(&container.rom) {
+            log::warn!(target: "cpu", "Rom has odd length, the last instruction will read past the loaded data");
+        }
+        let metadata = &container.metadata;
+        self.load_rom(&container.rom, metadata.quirks())?;
+
+        if let Some(title) = &metadata.title {
+            let author = metadata.author.as_ref().map(|author| format!(" by {}", author));
+            log::info!("Loaded {}{}", title, author.unwrap_or_default());
+        }
+
+        let game = self.config.game(self.rom_hash).cloned().unwrap_or_default();
+        if let (Some(palette), None, None) = (metadata.palette(), &game.palette, self.display_args.palette) {
+            let palette = frontend::palette::Palette::from_config(&palette)
+                .map_err(|e| format_err!("Invalid colors in rom container: {}", e))?;
+            self.set_palette(palette);
+        }
+        if let Some(cpu_hz) = metadata.cpu_hz() {
+            self.config.timing.cpu_hz = cpu_hz;
+        }
+        if let Some(recorder) = &mut self.replay {
+            recorder.start(self.core.state());
+        }
+
+        Ok(())
+    }
+
+    /// Runs the rom, or shows the splash screen without one. Errors and crashes are shown on
+    /// the emulated screen, from where another rom can be picked. The window's geometry is
+    /// kept for the next run
+    pub fn play(&mut self, rom: Option<PathBuf>) -> Result<(), Error> {
+        let res = self.play_roms(rom);
+        if let Some(geometry) = self.window.geometry() {
+            if let Err(e) = geometry.save(&self.paths.data) {
+                log::warn!(target: "display", "Could not save the window geometry: {}", e);
+            }
+        }
+        res
+    }
+
+    fn play_roms(&mut self, mut rom: Option<PathBuf>) -> Result<(), Error> {
+        let mut first = true;
+        loop {
+            let path = match rom.take() {
+                Some(path) => path,
+                // straight to the list of the watched directory, leaving it quits
+                None if self.watch.is_some() => match self.pick_rom()? {
+                    Some(path) => path,
+                    None => return Ok(()),
+                },
+                None => {
+                    let open = match demo::rom() {
+                        Some(demo) => self.attract_mode(&demo)?,
+                        None => {
+                            let mut lines = vec!["CHIP-8".to_string(), String::new()];
+                            lines.extend(splash::wrap("NO ROM LOADED"));
+                            lines.push("ENTER: OPEN".to_string());
+                            lines.push("ESC: QUIT".to_string());
+                            self.message_screen(&lines)?
+                        }
+                    };
+                    if !open {
+                        return Ok(());
+                    }
+                    match self.pick_rom()? {
+                        Some(path) => path,
+                        None => continue,
+                    }
+                }
+            };
+
+            // the first rom keeps the seed and settings given on the command line
+            if !first {
+                self.core.reset();
+            }
+            first = false;
+
+            let res = container::load(&path)
+                .and_then(|container| self.load_container(&container))
+                .and_then(|()| self.run_with_crash_dump())
+                .map_err(|err| self.add_context(err, &path));
+            self.save_scores()?;
+            self.report_stack_depth(&res);
+            let err = match res {
+                Ok(()) if self.next_rom.is_some() => {
+                    rom = self.next_rom.take();
+                    continue;
+                }
+                Ok(()) => return Ok(()),
+                Err(err) => err,
+            };
+            log::error!("{}", error::describe(&err));
+            if let Some(summary) = &mut self.summary {
+                summary.error(error::describe(&err));
+            }
+
+            let mut lines = match error::system_error(&err) {
+                Some(_) => vec![format!("CRASHED AT {:03X}", self.core.state().registers.pc)],
+                None => vec!["ERROR".to_string()],
+            };
+            lines.extend(splash::wrap(&error::describe(&err)));
+            lines.truncate(splash::LINES - 1);
+            lines.push("ENTER:OPEN ESC:QUIT".to_string());
+            if !self.message_screen(&lines)? {
+                return Err(err);
+            }
+            rom = self.pick_rom()?;
+        }
+    }
+
+    /// Adds the rom to an error from loading or running it, and for a `SystemError` the
+    /// instruction that failed and how many ran before it
+    fn add_context(&self, err: Error, rom: &Path) -> Error {
+        match err.downcast::<system::SystemError>() {
+            Ok(error) => error::RunError {
+                rom: rom.to_owned(),
+                instruction: self.recent.last(),
+                cycles: self.recent.count,
+                error,
+            }
+            .into(),
+            Err(err) => err.context(rom.display().to_string()).into(),
+        }
+    }
+
+    /// Runs the loaded rom, writing a crash dump to the data directory if it stops with an error
+    /// or panics
+    fn run_with_crash_dump(&mut self) -> Result<(), Error> {
+        let mut clock = clock::RealClock::new(self.config.power.spin_wait());
+        let timing = self.config.timing;
+        if self.watchdog.is_none() && timing.watchdog_secs > 0 {
+            let secs = |secs| std::time::Duration::from_secs(u64::from(secs));
+            match watchdog::Watchdog::spawn(secs(timing.watchdog_secs), secs(timing.watchdog_exit_secs), self.paths.data.clone()) {
+                Ok(watchdog) => self.watchdog = Some(watchdog),
+                Err(e) => log::warn!("Could not start the watchdog: {}", e),
+            }
+        }
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| self.run(&mut clock)));
+        self.disarm_watchdog();
+        let (res, reason) = match res {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(err)) => {
+                let reason = err.to_string();
+                (Err(err), reason)
+            }
+            Err(_) => {
+                let reason = crash::take_panic_message().unwrap_or_else(|| "panic".to_string());
+                (Err(format_err!("The emulator panicked: {}", reason)), reason)
+            }
+        };
+
+        let dump = crash::CrashDump::new(&reason, self.core.state(), self.rom_hash, &self.recent, &self.config);
+        match dump.write(&self.paths.data) {
+            Ok(path) => log::error!("Crash dump written to {}", path.display()),
+            Err(e) => log::error!("Could not write a crash dump: {}", e),
+        }
+        res
+    }
+
+    /// Writes a crash dump of a stall the watchdog reported, then asks whether to start the
+    /// rom over (returns true) or quit (returns false)
+    fn recover_from_stall(&mut self, stalled: std::time::Duration) -> Result<bool, Error> {
+        self.disarm_watchdog();
+        log::warn!("The emulator is responding again after {:.1} s", stalled.as_secs_f64());
+        let reason = format!("the emulator stalled for {:.1} s", stalled.as_secs_f64());
+        let dump = crash::CrashDump::new(&reason, self.core.state(), self.rom_hash, &self.recent, &self.config);
+        match dump.write(&self.paths.data) {
+            Ok(path) => log::error!("Crash dump written to {}", path.display()),
+            Err(e) => log::error!("Could not write a crash dump: {}", e),
+        }
+
+        let mut lines = vec![format!("STALLED AT {:03X}", self.core.state().registers.pc)];
+        lines.extend(splash::wrap(&format!("NOT RESPONDING FOR {:.0} S, CRASH DUMP WRITTEN", stalled.as_secs_f64())));
+        lines.truncate(splash::LINES - 1);
+        lines.push("ENTER:RESET ESC:QUIT".to_string());
+        if !self.message_screen(&lines)? {
+            return Ok(false);
+        }
+        let state = self.start_state.clone();
+        self.core.state_mut().load_state(&state)?;
+        self.rewind.clear();
+        self.dirty.reset();
+        if let Some(recorder) = &mut self.replay {
+            log::warn!("The replay ends here, it cannot reset");
+            recorder.stop();
+        }
+        Ok(true)
+    }
+
+    /// Stops the watchdog until the run loop beats again, before waiting on the user
+    fn disarm_watchdog(&self) {
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.disarm();
+        }
+    }
+
+    /// Logs how deep the calls went, and on an overflow whether a deeper stack could help
+    fn report_stack_depth(&self, res: &Result<(), Error>) {
+        let size = self.core.state().quirks.stack_depth();
+        let overflowed = res
+            .as_ref()
+            .err()
+            .and_then(error::system_error)
+            .is_some_and(|err| matches!(err, system::SystemError::StackOverflow));
+
+        if !overflowed {
+            let depth = self.core.state().stack.max_depth;
+            log::info!(target: "cpu", "Deepest call nesting: {} of {} stack entries", depth, size);
+        } else if size < system::MAX_STACK_SIZE {
+            log::warn!(
+                target: "cpu",
+                "The stack overflowed with all {} entries in use. Recursion that never returns is a rom bug, \
+                 but some roms need a deeper stack than the original interpreter: try `stack_size = {}` in [quirks]",
+                size,
+                (size * 2).min(system::MAX_STACK_SIZE)
+            );
+        } else {
+            log::warn!(target: "cpu", "The stack overflowed with all {} entries in use, the rom likely recurses without returning", size);
+        }
+    }
+
+    /// Calls `f` with the frontend's audio sink and the `--record-audio` file
+    fn audio(&mut self, mut f: impl FnMut(&mut dyn audio::AudioSink)) {
+        if let Some(sink) = self.window.audio_sink() {
+            f(sink);
+        }
+        if let Some(sink) = &mut self.record_audio {
+            f(sink.as_mut());
+        }
+    }
+
+    /// Shows text on the emulated screen until Enter (returns true) or Esc (returns false)
+    fn message_screen(&mut self, lines: &[String]) -> Result<bool, Error> {
+        let screen = splash::text_screen(lines);
+        self.wait_for_choice(|| Ok((screen.clone(), Vec::new())))
+    }
+
+    /// Runs the demo rom with the keys to press over it, until Enter (returns true) or Esc
+    /// (returns false). It runs on its own system, the one for roms is left alone
+    fn attract_mode(&mut self, demo: &[u8]) -> Result<bool, Error> {
+        let mut system = system::System::default();
+        system.load(demo)?;
+        let mut demo = headless::Headless::new(system);
+        let mut hint = Vec::new();
+        overlay::text::draw(&mut hint, 0.02, 0.92, 0.004, [1.0, 1.0, 1.0, 1.0], "ENTER: OPEN ROM  ESC: QUIT");
+        log::info!("No rom loaded, press Enter to open one or Esc to quit");
+
+        self.wait_for_choice(|| {
+            // two 60 Hz frames per screen drawn
+            demo.run_frame()?;
+            demo.run_frame()?;
+            Ok((demo.system.screen(), hint.clone()))
+        })
+    }
+
+    /// Draws the screens and overlays made by `frame` 30 times per second until Enter (returns
+    /// true) or Esc (returns false)
+    fn wait_for_choice(
+        &mut self,
+        mut frame: impl FnMut() -> Result<(Vec<u8>, Vec<overlay::Rect>), Error>,
+    ) -> Result<bool, Error> {
+        self.audio(|sink| {
+            sink.stop_beep();
+            sink.update();
+        });
+        loop {
+            if let Some(rom) = self.watched_rom() {
+                self.next_rom = Some(rom);
+                return Ok(true);
+            }
+            for input in self.window.poll_input() {
+                match input {
+                    frontend::Input::Close => return Ok(false),
+                    input => match input::keyboard_input(&input) {
+                        Some((winit::VirtualKeyCode::Return, true)) => return Ok(true),
+                        Some((winit::VirtualKeyCode::Escape, true)) => return Ok(false),
+                        _ => {}
+                    },
+                }
+            }
+            let (screen, overlay) = frame()?;
+            self.window.draw(framebuffer::FrameBuffer::new(screen, 64, 32)?, None, &overlay)?;
+            self.dirty.reset();
+            std::thread::sleep(timing::MENU_FRAME);
+        }
+    }
+
+    /// Lists the roms in the current or watched directory, returns the chosen one or None to
+    /// go back. A watched rom that changes is chosen without a key press
+    fn pick_rom(&mut self) -> Result<Option<PathBuf>, Error> {
+        use winit::VirtualKeyCode;
+
+        if let Some(rom) = self.next_rom.take() {
+            return Ok(Some(rom));
+        }
+        let dir = self.watch.as_ref().map_or(Path::new("."), |watch| &watch.dir).to_owned();
+        let mut picker = splash::RomPicker::new(&dir);
+        let mut frames = 0u32;
+        loop {
+            // once a second
+            frames = frames.wrapping_add(1);
+            if self.watch.is_some() && frames.is_multiple_of(30) {
+                picker.refresh();
+            }
+            if let Some(rom) = self.watched_rom() {
+                return Ok(Some(rom));
+            }
+            for input in self.window.poll_input() {
+                match input {
+                    frontend::Input::Close => return Ok(None),
+                    input => match input::keyboard_input(&input) {
+                        Some((VirtualKeyCode::Up, true)) => picker.up(),
+                        Some((VirtualKeyCode::Down, true)) => picker.down(),
+                        Some((VirtualKeyCode::Return, true)) => {
+                            if let Some(rom) = picker.current() {
+                                return Ok(Some(rom.clone()));
+                            }
+                        }
+                        Some((VirtualKeyCode::Escape, true)) => return Ok(None),
+                        _ => {}
+                    },
+                }
+            }
+            self.window.draw(framebuffer::FrameBuffer::new(splash::text_screen(&picker.lines()), 64, 32)?, None, &[])?;
+            self.dirty.reset();
+            std::thread::sleep(timing::MENU_FRAME);
+        }
+    }
+
+    /// The watched rom that was written last, if any was since the last call
+    fn watched_rom(&mut self) -> Option<PathBuf> {
+        let rom = self.watch.as_mut()?.poll()?;
+        log::info!("{} changed, running it", rom.display());
+        Some(rom)
+    }
+
+    fn set_palette(&mut self, palette: frontend::palette::Palette) {
+        self.palette = palette;
+        self.window.set_palette(palette);
+    }
+
+    fn scores_path(&self) -> PathBuf {
+        self.paths.config.with_file_name(scores::FILE_NAME)
+    }
+
+    /// writes the high score table if the session set a new record
+    pub fn save_scores(&mut self) -> Result<(), Error> {
+        if let Some(tracker) = &self.score_tracker {
+            if tracker.record(&mut self.scores) {
+                self.scores.save(self.scores_path())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// checks the score once per frame
+    fn update_score(&mut self) {
+        if let Some(tracker) = &mut self.score_tracker {
+            if let Some(score) = tracker.update(self.core.state()) {
+                // about three seconds
+                self.high_score_banner = Some((score, 180));
+            }
+        }
+
+        if let Some((_, frames)) = &mut self.high_score_banner {
+            *frames = frames.saturating_sub(1);
+            if *frames == 0 {
+                self.high_score_banner = None;
+            }
+        }
+    }
+
+    pub fn draw(&mut self) -> Result<(), Error> {
+        self.overlay_cost.start();
+        let mut overlay = self.overlay.rects(self.core.state());
+        self.overlay_cost.lap("stack and timers overlay (F2)");
+        overlay.extend(self.heatmap.rects(self.core.state()));
+        self.overlay_cost.lap("heatmap (F4)");
+        overlay.extend(self.perf.rects(self.overlay_cost.last));
+        self.overlay_cost.lap("performance graph (F8)");
+        overlay.extend(self.keypad.rects(&self.core.state().keys, &self.keymap));
+        if let Some((score, _)) = self.high_score_banner {
+            overlay.extend(overlay::banner(&format!("NEW HIGH SCORE {}", score)));
+        }
+        if let Some(teach) = &self.teach {
+            overlay.extend(teach.rects(self.core.state()));
+        }
+        overlay.extend(self.notifications.rects());
+        overlay.extend(self.slots.rects());
+        if self.halted && !self.pause.is_paused() {
+            overlay.extend(overlay::banner("PROGRAM HALTED"));
+        }
+        if self.pause.is_paused() {
+            overlay.extend(overlay::banner("PAUSED"));
+            if let Some(frontend::Cursor { position, pixel: Some(pixel) }) = self.cursor {
+                overlay.extend(overlay::pixel::tooltip(self.core.state(), pixel, position));
+            }
+        }
+        self.overlay_cost.lap("notifications and banners");
+        #[cfg(feature = "debugger")]
+        self.draw_debugger(&mut overlay)?;
+        self.overlay_cost.lap("debugger (F1)");
+        if let Some(editor) = &self.keymap_editor {
+            overlay.extend(editor.rects(&self.config.keymap));
+        }
+        self.overlay_cost.finish();
+        let screen = self.core.state().screen();
+        if self.config.power.skip_unchanged_frames && !self.redraw.needs_redraw(&screen, &overlay) {
+            return Ok(());
+        }
+        let (width, height) = self.core.state().resolution();
+        if self.scaling.switch_mode((width, height)) {
+            self.window.set_scaling(self.scaling);
+        }
+        let dirty = self.dirty.regions(&screen, width);
+        self.window.draw(framebuffer::FrameBuffer::new(screen, width, height)?, dirty.as_deref(), &overlay)
+    }
+
+    fn display_loop(&mut self) -> Result<(), Error> {
+        self.disarm_watchdog();
+        loop {
+            if self.poll_events()? {
+                return Ok(());
+            }
+            self.notifications.update();
+            self.draw()?;
+            std::thread::sleep(timing::MENU_FRAME);
+        }
+    }
+
+    /// Redraws per second
+    fn refresh_hz(&self, timing: &clock::Timing) -> f64 {
+        match self.window.refresh_rate() {
+            Some(hz) if timing.sync_to_display => hz,
+            _ => timing.refresh_hz,
+        }
+    }
+
+    /// Runs the program with instructions, timers and redraws each at their own rate
+    pub fn run(&mut self, clock: &mut impl clock::Clock) -> Result<(), Error> {
+        let mut timing = self.config.timing;
+        let mut power = self.config.power;
+
+        let refresh_hz = self.refresh_hz(&timing);
+        log::debug!(target: "display", "Presenting {} frames per second", refresh_hz);
+        self.perf.reset(refresh_hz);
+
+        let start = clock.now();
+        let mut driver = clock::Driver::new(&timing, refresh_hz, start);
+        if let Some(teach) = &self.teach {
+            driver.set_cpu_rate(&timing, teach.cpu_hz(), start);
+        }
+        let mut turbo = false;
+        let mut idle = false;
+        let mut running = true;
+        self.halted = false;
+        let mut polled = start;
+        loop {
+            if let Some(stalled) = self.watchdog.as_ref().and_then(|watchdog| watchdog.beat(self.core.state(), self.rom_hash)) {
+                if !self.recover_from_stall(stalled)? {
+                    return Ok(());
+                }
+                // without catching up on the frames of the stall
+                driver.resume(clock.now());
+            }
+            let now = clock.now();
+            // emulation stops at the end of a frame when paused, while rewinding, and while the
+            // keymap is edited or the debugger is open, which runs the program itself
+            let was_running = running;
+            running = !self.pause.is_paused()
+                && !self.rewinding
+                && self.keymap_editor.is_none()
+                && !self.debugger_open();
+            if running && !was_running {
+                driver.resume(now);
+            }
+            // a reloaded config changes the speeds
+            let reloaded = timing != self.config.timing || power != self.config.power;
+            if reloaded {
+                timing = self.config.timing;
+                power = self.config.power;
+                let refresh_hz = self.refresh_hz(&timing);
+                driver.set_refresh_rate(refresh_hz, now);
+                self.perf.reset(refresh_hz);
+            }
+            let waiting = power.idle_detection && self.core.state().in_key_wait();
+            let halted = power.on_halt != clock::HaltAction::Run && self.core.state().in_halt_loop();
+            if halted != self.halted {
+                let pc = self.core.state().registers.pc;
+                if halted {
+                    log::info!(target: "cpu", "Program halted in a loop at {:03X}, running it at {} Hz", pc, clock::HALT_CPU_HZ);
+                } else {
+                    log::debug!(target: "cpu", "Left the halt loop at {:03X}", pc);
+                }
+                #[cfg(feature = "debugger")]
+                if halted && power.on_halt == clock::HaltAction::Break {
+                    println!("Program halted in a loop at {:03X}, breaking into the debugger", pc);
+                    return self.run_debug();
+                }
+            }
+            if self.turbo != turbo || waiting != idle || halted != self.halted || reloaded {
+                if waiting != idle {
+                    log::debug!(target: "cpu", "{} waiting for a key", if waiting { "Started" } else { "Stopped" });
+                }
+                let speed = if self.turbo { timing.turbo } else { 1.0 };
+                let cpu_hz = match &self.teach {
+                    Some(teach) => teach.cpu_hz(),
+                    None if halted => clock::HALT_CPU_HZ,
+                    None if waiting => power.idle_cpu_hz,
+                    None => timing.cpu_hz * speed,
+                };
+                driver.set_cpu_rate(&timing, cpu_hz, now);
+                if self.turbo != turbo || reloaded {
+                    driver.set_timer_speed(speed, now);
+                }
+                turbo = self.turbo;
+                idle = waiting;
+                self.halted = halted;
+            }
+
+            let due = driver.due(now);
+            let (instructions, timer_ticks) = (due.instructions, due.timer_ticks);
+
+            if running {
+                let instructions = match &mut self.teach {
+                    Some(teach) => teach.instructions(instructions),
+                    None => instructions,
+                };
+                self.perf.instructions(instructions);
+                for _ in 0..instructions {
+                    let reads_keys = self.core.state().fetch_instruction().is_ok_and(input::reads_keys);
+                    if reads_keys && clock.now() >= polled + input::KEY_READ_POLL_INTERVAL {
+                        polled = clock.now();
+                        if self.poll_events()? {
+                            return Ok(());
+                        }
+                    }
+                    self.recent.record(self.core.state());
+                    if let (Some(report), Ok(opcode)) = (&mut self.compat, self.core.state().fetch_instruction()) {
+                        let pc = self.core.state().registers.pc;
+                        if system::is_machine_code_call(opcode) && opcode != 0 {
+                            report.record_machine_code_call(pc, opcode);
+                        }
+                        if opcode & 0xF00F == 0xD000 && report.record_zero_height(pc, opcode) {
+                            log::warn!(
+                                target: "cpu",
+                                "{:04X} at {:03X} draws {} rows for a height of 0 as the `zero_height_draw` quirk says, SCHIP draws a 16x16 sprite",
+                                opcode,
+                                pc,
+                                self.core.state().quirks.zero_height_draw.rows()
+                            );
+                        }
+                    }
+                    if let Some(teach) = &mut self.teach {
+                        teach.before(self.core.state());
+                    }
+                    let res = self.core.step(&mut self.trace);
+                    if let Some(teach) = &mut self.teach {
+                        teach.after(self.core.state());
+                    }
+                    if let Some(recorder) = &mut self.replay {
+                        recorder.instruction();
+                    }
+                    match res {
+                        Ok(()) => {
+                            if let (Some(summary), Some((_, opcode))) = (&mut self.summary, self.recent.last()) {
+                                summary.instruction(opcode, self.core.state());
+                            }
+                        }
+                        Err(system::SystemError::ZeroInstruction) => {
+                            use system::quirks::ZeroInstruction;
+                            match self.core.state().quirks.zero_instruction {
+                                ZeroInstruction::Error => return Err(system::SystemError::ZeroInstruction.into()),
+                                #[cfg(feature = "debugger")]
+                                ZeroInstruction::Break => {
+                                    let pc = self.core.state().registers.pc;
+                                    println!("Reached 0000 at {:03X}, breaking into the debugger", pc);
+                                    return self.run_debug();
+                                }
+                                _ => {
+                                    log::info!(target: "cpu", "Reached the end of the program, entering infinite loop");
+                                    return self.display_loop();
+                                }
+                            }
+                        }
+                        Err(system::SystemError::UnknownOpcode { opcode }) if self.compat.is_some() => {
+                            if let Some(report) = &mut self.compat {
+                                report.record(self.core.state().registers.pc, opcode);
+                            }
+                            self.core.state_mut().skip_instruction();
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+
+                    if timing.auto_speed && self.core.state().timers.delay > 0 && self.core.state().in_delay_wait() {
+                        let delay = self.core.state().timers.delay;
+                        log::trace!(target: "cpu", "Skipping {} frames of waiting for the delay timer", delay);
+                        if let Some(recorder) = &mut self.replay {
+                            for _ in 0..delay {
+                                recorder.event(replay::Action::Timer);
+                            }
+                        }
+                        if self.core.state_mut().skip_delay_wait() {
+                            log::info!(target: "audio", "Beep!");
+                        }
+                    }
+
+                    // without the debugger, `--diagnostics break` is refused when parsed
+                    if self.report_diagnostics() && self.diagnostics == cli::DiagnosticsMode::Break {
+                        #[cfg(feature = "debugger")]
+                        {
+                            println!("Breaking into the debugger, press Enter to step");
+                            return self.run_debug();
+                        }
+                    }
+                }
+
+                for _ in 0..timer_ticks {
+                    let playing = self.beep.tick(self.core.state().timers.sound, self.config.audio.min_beep_ticks());
+                    self.audio(|sink| sink.set_beeping(playing));
+                    if self.core.tick_timers() {
+                        log::info!(target: "audio", "Beep!");
+                    }
+                    if let Some(summary) = &mut self.summary {
+                        summary.timer_tick();
+                    }
+                    if let Some(recorder) = &mut self.replay {
+                        recorder.event(replay::Action::Timer);
+                    }
+                    for (key, pressed) in self.rapid_fire.tick(timing.rapid_fire_frames) {
+                        self.key_event(key, pressed)?;
+                    }
+                    for (key, pressed) in self.paste.tick() {
+                        self.key_event(key, pressed)?;
+                    }
+                    for key in self.hold.frame_end() {
+                        self.key_event(key, false)?;
+                    }
+                    self.audio(|sink| sink.timer_tick());
+
+                    self.pause.frame_end();
+                    if self.pause.is_paused() {
+                        log::debug!(target: "cpu", "Paused at {:03X}", self.core.state().registers.pc);
+                        break;
+                    }
+                }
+                // a timer just set starts the beep now rather than at the next tick
+                let playing = (self.beep.playing() || self.core.state().timers.sound > 0) && !self.pause.is_paused();
+                self.audio(|sink| {
+                    sink.set_beeping(playing);
+                    sink.update();
+                });
+            }
+            #[cfg(feature = "debugger")]
+            if !running {
+                self.window_debugger_run(instructions, timer_ticks);
+            }
+
+            for _ in 0..due.emulated_frames {
+                if self.rewinding {
+                    self.rewind_frame();
+                } else if running {
+                    self.rewind.push(self.core.state().save_state());
+                }
+                self.core.state_mut().activity.decay();
+                if self.keymap.auto && self.core.state().polled_keys != self.auto_polled {
+                    self.apply_keymap();
+                }
+                self.update_score();
+                self.slots.update();
+                self.notifications.update();
+            }
+            self.reload_config();
+            if let Some(rom) = self.watched_rom() {
+                self.next_rom = Some(rom);
+                return Ok(());
+            }
+
+            if due.redraw {
+                self.perf.frame(now);
+                self.draw()?;
+                if let Some(summary) = &mut self.summary {
+                    summary.frame_rendered();
+                }
+                if let Some(writer) = &mut self.frames_out {
+                    writer.write(&framebuffer::FrameBuffer::from_system(self.core.state()), &self.palette)?;
+                }
+                if let Some(probe) = &mut self.latency {
+                    probe.frame_presented(&self.core.state().screen());
+                }
+            }
+
+            // at least once per frame, the loop wakes for every emulated frame
+            polled = clock.now();
+            if self.poll_events()? {
+                return Ok(());
+            }
+
+            // frontends with an event loop sleep in it until the deadline, waking early for input,
+            // the clock spins the rest of the way
+            let next = driver.wake_time();
+            let timeout = next.saturating_sub(clock.now() + power.spin_wait());
+            if timeout.is_zero() || !self.window.wait_input(timeout) {
+                clock.sleep_until(next);
+            }
+        }
+    }
+
+    /// Whether the debugger is open in the game window, it runs the program instead of the
+    /// run loop
+    fn debugger_open(&self) -> bool {
+        #[cfg(feature = "debugger")]
+        return self.window_debugger.is_some();
+        #[cfg(not(feature = "debugger"))]
+        return false;
+    }
+
+    /// Presses or releases a hex key, recording it in the replay
+    fn key_event(&mut self, key: u8, pressed: bool) -> Result<(), system::SystemError> {
+        let events = if self.keymap.ghosting {
+            self.ghosting.key_event(key, pressed)
+        } else {
+            vec![(key, pressed)]
+        };
+        for (key, pressed) in events {
+            if let Some(recorder) = &mut self.replay {
+                recorder.event(if pressed { replay::Action::Press(key) } else { replay::Action::Release(key) });
+            }
+            self.trace.key_event(key, pressed);
+            self.core.key_event(key, pressed)?;
+        }
+        Ok(())
+    }
+
+    /// Goes back to the state of the frame before, while the rewind key is held
+    fn rewind_frame(&mut self) {
+        let state = match self.rewind.pop() {
+            Some(state) => state,
+            None => return,
+        };
+        if let Err(e) = self.core.state_mut().load_state(&state) {
+            log::warn!("Could not rewind: {}", e);
+            self.rewind.clear();
+        }
+        if self.rewind.is_empty() {
+            self.notifications.show("rewind", "oldest frame reached");
+        }
+    }
+
+    /// Types the hex digits on the clipboard, one key after the other
+    fn paste(&mut self) {
+        if !self.paste.is_empty() {
+            log::info!(target: "input", "Still typing the last paste");
+            return self.notifications.show("paste", "still typing the last paste");
+        }
+        let text = match self.window.clipboard_text() {
+            Ok(text) => text,
+            Err(e) => {
+                log::warn!(target: "input", "Could not paste: {}", e);
+                return self.notifications.show("paste", "could not read the clipboard");
+            }
+        };
+        match input::KeySequence::parse(&text) {
+            Ok(sequence) => {
+                log::info!(target: "input", "Typing {} pasted keys", text.trim());
+                self.notifications.show("paste", format!("typing {}", text.trim()));
+                self.paste = sequence;
+            }
+            Err(e) => {
+                log::warn!(target: "input", "Could not paste {:?}: {}", text.trim(), e);
+                self.notifications.show("paste", "no hex keys to paste");
+            }
+        }
+    }
+
+    /// prints diagnostics reported by the system, returns true if there were any
+    fn report_diagnostics(&mut self) -> bool {
+        let diagnostics = self.core.state_mut().diagnostics.take();
+        for diagnostic in &diagnostics {
+            log::warn!(target: "cpu", "{}", diagnostic);
+        }
+        !diagnostics.is_empty()
+    }
+
+    /// handles pending window events, returns true if the user asked to exit
+    fn poll_events(&mut self) -> Result<bool, Error> {
+        let mut err = None;
+        let mut exit = false;
+        let mut save_config = false;
+        for input in self.window.poll_input() {
+            if let frontend::Input::Cursor(cursor) = input {
+                self.cursor = cursor;
+                continue;
+            }
+            if let frontend::Input::Key { keycode, pressed, controller } = &input {
+                let device = controller.map_or(String::new(), |controller| format!(" on controller {}", controller));
+                log::trace!(target: "input", "{:?}{} {}", keycode, device, if *pressed { "pressed" } else { "released" });
+            }
+            self.modifiers.update(&input);
+            if let Some(editor) = &mut self.keymap_editor {
+                if let Some((keycode, true)) = input::keyboard_input(&input) {
+                    match editor.handle_key(keycode, &mut self.config.keymap) {
+                        overlay::keymap::EditorAction::Close => self.keymap_editor = None,
+                        overlay::keymap::EditorAction::Bound => save_config = true,
+                        overlay::keymap::EditorAction::None => {}
+                    }
+                }
+                continue;
+            }
+            #[cfg(feature = "debugger")]
+            if self.debugger_input(&input) {
+                continue;
+            }
+
+            if let (Some(teach), Some((keycode, pressed))) = (&mut self.teach, input::keyboard_input(&input)) {
+                if keycode == winit::VirtualKeyCode::Space {
+                    if pressed {
+                        teach.request_step();
+                    }
+                    continue;
+                }
+            }
+
+            let action = match input::map_key(&input, &self.keymap, &self.config.players, self.modifiers) {
+                Some(input::InputEvent::Key(key, pressed)) => {
+                    if let Some(probe) = &mut self.latency {
+                        probe.key_event(self.core.state().screen());
+                    }
+                    if self.keymap.is_turbo(key) {
+                        self.rapid_fire.key_event(key, pressed);
+                    }
+                    if pressed {
+                        self.hold.press(key);
+                    } else if !self.hold.release(key) {
+                        continue;
+                    }
+                    if let Err(e) = self.key_event(key, pressed) {
+                        err = Some(e);
+                    }
+                    continue;
+                }
+                Some(input::InputEvent::UiAction(action)) => action,
+                None => continue,
+            };
+
+            match action {
+                input::Action::Exit => {
+                    exit = true;
+                }
+
+                input::Action::ToggleOverlay => {
+                    self.overlay.toggle();
+                }
+
+                input::Action::SaveState => {
+                    let slot = self.slots.selected as u64;
+                    match storage::save_state(&mut self.storage, self.rom_hash, slot, self.core.state()) {
+                        Ok(()) => {
+                            log::info!("Saved state to slot {}", slot);
+                            self.notifications.show("state", format!("state saved to slot {}", slot));
+                            self.slots.saved(self.core.state().screen());
+                        }
+                        Err(e) => {
+                            log::warn!("Could not save state to slot {}: {}", slot, e);
+                            self.notifications.show("state", format!("could not save to slot {}", slot));
+                        }
+                    }
+                }
+
+                input::Action::LoadState => {
+                    let slot = self.slots.selected as u64;
+                    match storage::load_state(&self.storage, self.rom_hash, slot, self.core.state_mut()) {
+                        Ok(true) => {
+                            log::info!("Loaded state from slot {}", slot);
+                            self.notifications.show("state", format!("state loaded from slot {}", slot));
+                            if let Some(recorder) = &mut self.replay {
+                                log::warn!("The replay ends here, it cannot load states");
+                                recorder.stop();
+                            }
+                        }
+                        Ok(false) => {
+                            log::info!("Slot {} is empty", slot);
+                            self.notifications.show("state", format!("slot {} is empty", slot));
+                        }
+                        Err(e) => {
+                            log::warn!("Could not load state from slot {}: {}", slot, e);
+                            self.notifications.show("state", format!("could not load slot {}", slot));
+                        }
+                    }
+                    self.slots.show();
+                }
+
+                input::Action::SelectSlot { offset } => {
+                    self.slots.select(offset);
+                }
+
+                input::Action::Turbo { pressed } => {
+                    if pressed != self.turbo {
+                        self.notifications.show("turbo", if pressed { "turbo on" } else { "turbo off" });
+                    }
+                    self.turbo = pressed;
+                }
+
+                input::Action::Rewind { pressed } if pressed != self.rewinding => {
+                    self.rewinding = pressed;
+                    if !pressed {
+                        continue;
+                    }
+                    if !self.config.rewind.enabled {
+                        self.notifications.show("rewind", "rewind is disabled");
+                        self.rewinding = false;
+                        continue;
+                    }
+                    let seconds = self.rewind.len() as f64 / f64::from(timing::TIMER_HZ);
+                    log::info!("Rewinding, {:.1} s recorded in {} KiB", seconds, self.rewind.size() / 1024);
+                    self.notifications.show("rewind", "rewinding");
+                    if let Some(recorder) = &mut self.replay {
+                        log::warn!("The replay ends here, it cannot rewind");
+                        recorder.stop();
+                    }
+                }
+
+                input::Action::Rewind { .. } => {}
+
+                input::Action::ToggleHeatmap => {
+                    self.heatmap.toggle();
+                }
+
+                input::Action::TogglePerfGraph => {
+                    self.perf.toggle();
+                }
+
+                input::Action::ToggleKeypad => {
+                    self.keypad.toggle();
+                }
+
+                input::Action::ToggleDebugWindow => {
+                    #[cfg(feature = "debugger")]
+                    self.toggle_window_debugger();
+                }
+
+                input::Action::TogglePause => {
+                    self.pause.toggle();
+                }
+
+                input::Action::StepFrame => {
+                    self.pause.step();
+                }
+
+                input::Action::ToggleFullscreen => {
+                    let fullscreen = self.window.geometry().is_some_and(|geometry| !geometry.fullscreen);
+                    self.window.set_fullscreen(fullscreen);
+                    self.dirty.reset();
+                }
+
+                input::Action::Paste => {
+                    self.paste();
+                }
+
+                input::Action::ToggleKeymapEditor => {
+                    // keys held while the editor opens would never see their release
+                    for key in 0..16 {
+                        if let Err(e) = self.key_event(key, false) {
+                            err = Some(e);
+                        }
+                    }
+                    self.rapid_fire = input::RapidFire::default();
+                    self.hold = input::MinimumHold::default();
+                    self.keymap_editor = Some(overlay::keymap::KeymapEditor::default());
+                }
+
+                input::Action::AdjustTone { brightness, gamma } => {
+                    let tone = self.config.display.tone.adjust(brightness, gamma);
+                    self.config.display.tone = tone;
+                    self.window.set_tone(tone);
+                    self.dirty.reset();
+                    self.notifications
+                        .show("tone", format!("brightness {:.1} gamma {:.1}", tone.brightness, tone.gamma));
+                    save_config = true;
+                }
+            }
+        }
+
+        if save_config {
+            self.config.save(&self.paths.config)?;
+            self.apply_keymap();
+        }
+
+        match err {
+            Some(err) => Err(err.into()),
+            None => Ok(exit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use winit::VirtualKeyCode;
+
+    /// Returns one entry of `polls` per poll, and closes once they run out
+    struct ScriptedWindow {
+        polls: VecDeque<Vec<frontend::Input>>,
+    }
+
+    impl frontend::Frontend for ScriptedWindow {
+        fn draw(&mut self, _: framebuffer::FrameBuffer, _: Option<&[frontend::Region]>, _: &[overlay::Rect]) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn poll_input(&mut self) -> Vec<frontend::Input> {
+            self.polls.pop_front().unwrap_or_else(|| vec![frontend::Input::Close])
+        }
+
+        fn set_palette(&mut self, _: frontend::palette::Palette) {}
+
+        fn set_anti_flicker(&mut self, _: frontend::blend::AntiFlicker) {}
+
+        fn set_tone(&mut self, _: frontend::palette::Tone) {}
+
+        fn set_scaling(&mut self, _: frontend::Scaling) {}
+    }
+
+    /// Runs `rom` on the mock clock, tapping W (hex key 5) within a single poll after a while
+    fn run_with_tap(rom: &[u8]) -> Chip8 {
+        let key = |pressed| frontend::Input::Key {
+            keycode: VirtualKeyCode::W,
+            pressed,
+            controller: None,
+        };
+        let mut polls = (0..30).map(|_| Vec::new()).collect::<Vec<_>>();
+        polls.push(vec![key(true), key(false)]);
+        polls.extend((0..200).map(|_| Vec::new()));
+
+        let config_path = std::env::temp_dir().join("chip8-input-test").join("chip8.toml");
+        let window = Box::new(ScriptedWindow { polls: polls.into() });
+        let mut chip = Chip8::with_window(config::Config::default(), config_path, cli::DisplayArgs::default(), window).unwrap();
+        chip.load_rom(rom, None).unwrap();
+        chip.run(&mut clock::MockClock::default()).unwrap();
+        chip
+    }
+
+    #[test]
+    fn taps_shorter_than_a_frame_are_seen() {
+        let chip = run_with_tap(&[
+            0x65, 0x05, // LD V5, 5
+            0xE5, 0xA1, // SKNP V5
+            0x71, 0x01, // ADD V1, 1
+            0x12, 0x02, // JP 0x202
+        ]);
+        let state = chip.core.state();
+        assert!(state.registers.reg[1] > 0, "SKNP never saw the key pressed");
+        assert!(!state.keys.pressed(5).unwrap(), "the key was never released");
+
+        let chip = run_with_tap(&[
+            0xF1, 0x0A, // LD V1, K
+            0x62, 0x01, // LD V2, 1
+            0x12, 0x04, // JP 0x204
+        ]);
+        assert_eq!(chip.core.state().registers.reg[1..3], [5, 1]);
+    }
+
+    #[test]
+    fn compat_skips_whole_extension_instructions() {
+        let rom = [
+            0xF0, 0x00, 0xF0, 0xFF, // I := long F0FF, whose address is no instruction
+            0x62, 0x01, // LD V2, 1
+            0x12, 0x06, // JP 0x206
+        ];
+        let window = Box::new(ScriptedWindow { polls: (0..10).map(|_| Vec::new()).collect() });
+        let config_path = std::env::temp_dir().join("chip8-compat-test").join("chip8.toml");
+        let mut chip = Chip8::<system::System>::with_window(config::Config::default(), config_path, cli::DisplayArgs::default(), window).unwrap();
+        chip.compat = Some(compat::CompatReport::default());
+        chip.load_rom(&rom, None).unwrap();
+        chip.run(&mut clock::MockClock::default()).unwrap();
+
+        assert_eq!(chip.core.state().registers.reg[2], 1);
+        let report = chip.compat.as_ref().unwrap().to_string();
+        assert!(report.contains("F000 x1 (first at 200): XO-CHIP long index load"), "{}", report);
+        assert!(!report.contains("F0FF"), "{}", report);
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use crate::analysis::{self, Flow};
+use crate::compat;
+use crate::match_opcodes;
+use crate::system::opcode::Opcode;
+use crate::system::quirks::DEFAULT_STACK_SIZE;
+use crate::system::PROGRAM_START;
+
+const MEMORY_SIZE: u32 = 4096;
+/// explored (address, I, stack depth) combinations before giving up
+const MAX_STATES: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// behaves differently between interpreters
+    Warning,
+    /// fails or does something unintended on every interpreter
+    Error,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Lint {
+    pub addr: u16,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{:03X}: {}: {}", self.addr, severity, self.message)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct State {
+    pc: u16,
+    /// value of I if it is known
+    index: Option<u16>,
+    depth: u8,
+}
+
+struct Linter<'a> {
+    rom: &'a [u8],
+    lints: BTreeSet<Lint>,
+    visited: HashSet<State>,
+    queue: Vec<State>,
+    /// address, start and length of the memory writes to a known I
+    writes: BTreeSet<(u16, u16, u16)>,
+}
+
+/// Walks every statically reachable path of the rom from the program start and reports
+/// problems: jumps outside the rom or memory, calls nested deeper than the stack,
+/// sprite and register reads past the end of memory, instructions that depend on quirks,
+/// writes over the code and VF used as a general register
+pub fn lint(rom: &[u8]) -> Vec<Lint> {
+    let mut linter = Linter {
+        rom,
+        lints: BTreeSet::new(),
+        visited: HashSet::new(),
+        queue: Vec::new(),
+        writes: BTreeSet::new(),
+    };
+
+    linter.push(State {
+        pc: PROGRAM_START,
+        index: None,
+        depth: 0,
+    });
+    while let Some(state) = linter.queue.pop() {
+        if linter.visited.len() >= MAX_STATES {
+            linter.report(PROGRAM_START, Severity::Warning, "too many paths, only part of the rom was checked");
+            break;
+        }
+        linter.step(state);
+    }
+    linter.check_writes();
+
+    let cfg = analysis::Cfg::build(rom);
+    let usage = analysis::registers::analyze(&cfg);
+    linter.lints.extend(analysis::registers::vf_lints(&cfg, &usage));
+
+    linter.lints.into_iter().collect()
+}
+
+impl Linter<'_> {
+    fn report(&mut self, addr: u16, severity: Severity, message: impl Into<String>) {
+        self.lints.insert(Lint {
+            addr,
+            severity,
+            message: message.into(),
+        });
+    }
+
+    fn push(&mut self, state: State) {
+        if self.visited.insert(state) {
+            self.queue.push(state);
+        }
+    }
+
+    fn rom_end(&self) -> u32 {
+        u32::from(PROGRAM_START) + self.rom.len() as u32
+    }
+
+    /// continues at `target` if it is inside the rom
+    fn goto(&mut self, from: u16, target: u16, state: State) {
+        if u32::from(target) + 1 >= MEMORY_SIZE {
+            self.report(from, Severity::Error, format!("jumps to {:03X}, outside of memory", target));
+        } else if target < PROGRAM_START {
+            self.report(from, Severity::Error, format!("jumps to {:03X}, below the program start", target));
+        } else if u32::from(target) + 1 >= self.rom_end() {
+            self.report(from, Severity::Error, format!("jumps to {:03X}, past the end of the rom", target));
+        } else {
+            if !target.is_multiple_of(2) {
+                self.report(from, Severity::Warning, format!("jumps to the odd address {:03X}", target));
+            }
+            self.push(State { pc: target, ..state });
+        }
+    }
+
+    /// continues with the instruction `offset` bytes later
+    fn advance(&mut self, pc: u16, offset: u16, state: State) {
+        let next = pc.wrapping_add(offset);
+        if u32::from(next) + 1 >= self.rom_end() {
+            self.report(pc, Severity::Error, "execution runs past the end of the rom");
+        } else {
+            self.push(State { pc: next, ..state });
+        }
+    }
+
+    /// reports a read or write of `len` bytes at I that ends outside of memory
+    fn check_index(&mut self, pc: u16, index: Option<u16>, len: u32, what: &str) {
+        if let Some(index) = index {
+            if u32::from(index) + len > MEMORY_SIZE {
+                self.report(
+                    pc,
+                    Severity::Error,
+                    format!("{} {} bytes at {:03X} goes past the end of memory", what, len, index),
+                );
+            }
+        }
+    }
+
+    /// Records a write of `len` bytes at I to check against the code
+    fn write(&mut self, pc: u16, index: Option<u16>, len: u16) {
+        if let Some(index) = index {
+            self.writes.insert((pc, index, len));
+        }
+    }
+
+    /// Reports the writes that land on a reachable instruction: self-modifying code runs
+    /// differently from its disassembly
+    fn check_writes(&mut self) {
+        let code = self.visited.iter().map(|state| state.pc).collect::<BTreeSet<_>>();
+        for (pc, start, len) in std::mem::take(&mut self.writes) {
+            let end = start.saturating_add(len);
+            // an instruction covers its address and the next
+            if let Some(addr) = code.range(start.saturating_sub(1)..end).next() {
+                self.report(
+                    pc,
+                    Severity::Warning,
+                    format!("writes over the instruction at {:03X}, the rom modifies its own code", addr),
+                );
+            }
+        }
+    }
+
+    fn step(&mut self, state: State) {
+        let pc = state.pc;
+        let opcode = match analysis::fetch(self.rom, pc) {
+            Some(opcode) => opcode,
+            None => return,
+        };
+        let mut index = state.index;
+
+        let flow = match_opcodes! {
+            opcode;
+
+            noarg Opcode::ClearScreen => Flow::Next,
+            noarg Opcode::Return => Flow::Return,
+            long addr = Opcode::Jump => Flow::Jump(addr),
+            long addr = Opcode::Call => Flow::Call(addr),
+            (_reg, _val) = Opcode::SkipIfEq => Flow::Skip,
+            (_reg, _val) = Opcode::SkipIfNeq => Flow::Skip,
+            (_reg1, _reg2) = Opcode::SkipIfRegEq => Flow::Skip,
+            (_reg1, _reg2) = Opcode::SkipIfRegNeq => Flow::Skip,
+            _reg = Opcode::SkipIfKeyPressed => Flow::Skip,
+            _reg = Opcode::SkipIfKeyNotPressed => Flow::Skip,
+
+            (reg1, reg2) = Opcode::RShiftReg => {
+                if reg1 != reg2 {
+                    self.report(pc, Severity::Warning, "SHR shifts VX or VY depending on the interpreter");
+                }
+                Flow::Next
+            },
+            (reg1, reg2) = Opcode::LShiftReg => {
+                if reg1 != reg2 {
+                    self.report(pc, Severity::Warning, "SHL shifts VX or VY depending on the interpreter");
+                }
+                Flow::Next
+            },
+
+            (_reg, _val) = Opcode::SetReg => Flow::Next,
+            (_reg, _val) = Opcode::SAddReg => Flow::Next,
+            (_reg1, _reg2) = Opcode::MovReg => Flow::Next,
+            (_reg1, _reg2) = Opcode::OrReg => Flow::Next,
+            (_reg1, _reg2) = Opcode::AndReg => Flow::Next,
+            (_reg1, _reg2) = Opcode::XorReg => Flow::Next,
+            (_reg1, _reg2) = Opcode::AddReg => Flow::Next,
+            (_reg1, _reg2) = Opcode::SubReg => Flow::Next,
+            (_reg1, _reg2) = Opcode::RSubReg => Flow::Next,
+            (_reg, _val) = Opcode::Rand => Flow::Next,
+            _reg = Opcode::GetDelay => Flow::Next,
+            _reg = Opcode::BlockGetKey => Flow::Next,
+            _reg = Opcode::SetDelay => Flow::Next,
+            _reg = Opcode::SetSound => Flow::Next,
+
+            long addr = Opcode::SetIndex => {
+                index = Some(addr);
+                Flow::Next
+            },
+            _reg = Opcode::AddIndex => {
+                index = None;
+                Flow::Next
+            },
+            _reg = Opcode::GetSprite => {
+                index = None;
+                Flow::Next
+            },
+            long _addr = Opcode::JumpPlus => {
+                self.report(pc, Severity::Warning, "JP V0 adds V0 or VX depending on the interpreter");
+                Flow::Computed
+            },
+
+            (_x, _y, height) = Opcode::Draw => {
+                self.check_index(pc, index, u32::from(height), "sprite read of");
+                Flow::Next
+            },
+            _reg = Opcode::BinCoded => {
+                self.check_index(pc, index, 3, "BCD write of");
+                self.write(pc, index, 3);
+                Flow::Next
+            },
+            reg = Opcode::RegDump => {
+                self.check_index(pc, index, u32::from(reg) + 1, "register store of");
+                self.write(pc, index, u16::from(reg) + 1);
+                self.report(pc, Severity::Warning, "whether LD [I] increments I depends on the interpreter");
+                index = None;
+                Flow::Next
+            },
+            reg = Opcode::RegLoad => {
+                self.check_index(pc, index, u32::from(reg) + 1, "register load of");
+                self.report(pc, Severity::Warning, "whether LD Vx, [I] increments I depends on the interpreter");
+                index = None;
+                Flow::Next
+            },
+
+            otherwise x => {
+                if x == 0 {
+                    // the zero instruction ends the program
+                    Flow::Stop
+                } else if let Some((platform, name)) = compat::classify(x) {
+                    self.report(pc, Severity::Error, format!("{} is a {} instruction", name, platform));
+                    Flow::Stop
+                } else {
+                    self.report(pc, Severity::Error, format!("unknown instruction {:04X}", x));
+                    Flow::Stop
+                }
+            }
+        };
+
+        let next = State { index, ..state };
+        match flow {
+            Flow::Next => self.advance(pc, 2, next),
+            Flow::Skip => {
+                self.advance(pc, 2, next);
+                self.advance(pc, 4, next);
+            }
+            Flow::Jump(target) => self.goto(pc, target, next),
+            Flow::Call(target) => {
+                if state.depth >= DEFAULT_STACK_SIZE {
+                    self.report(pc, Severity::Error, "call nesting exceeds the default 16 entry stack");
+                    return;
+                }
+                self.goto(pc, target, State { depth: state.depth + 1, ..next });
+                // the subroutine may change I
+                self.advance(pc, 2, State { index: None, ..next });
+            }
+            Flow::Return => {
+                if state.depth == 0 {
+                    self.report(pc, Severity::Error, "returns with an empty stack");
+                }
+            }
+            Flow::Computed | Flow::Stop => {}
+        }
+    }
+}
@@ -1,118 +1,530 @@
 #[macro_use]
-extern crate glium;
-#[macro_use]
 extern crate failure;
 
 use failure::Error;
+use std::path::{Path, PathBuf};
+
+use chip8::*;
 
-pub mod keys;
-pub mod system;
-pub mod window;
+fn info(path: &Path, config: &config::Config, ratings: &ratings::Ratings) -> Result<(), Error> {
+    let container = container::load(path)?;
+    let data = container.rom;
+    let hash = rom::hash(&data);
+
+    println!("File: {}", path.display());
+    if let Some(name) = config.game(hash).and_then(|game| game.name.as_ref()) {
+        println!("Name: {}", name);
+    }
+    print!("{}", container.metadata);
+    println!("Size: {} bytes", data.len());
+    println!("Hash: {:016x}", hash);
+    if !rom::is_aligned(&data) {
+        println!("Warning: odd length, the last instruction reads past the loaded data");
+    }
+
+    let mut report = compat::CompatReport::default();
+    for (addr, instruction) in system::opcode::instructions(&data, system::PROGRAM_START) {
+        if let Err(word) = instruction {
+            if compat::classify(word).is_some() {
+                report.record(addr, word);
+            }
+        }
+    }
+    let platform = compat::detect(&data);
+    let game_quirks = config.game(hash).and_then(|game| game.quirks);
+    let quirks = match (game_quirks, container.metadata.quirks(), platform, config.detected_quirks(platform)) {
+        (Some(_), _, _, _) => "runs with the quirks of its game database entry".to_string(),
+        (None, Some(_), _, _) => "runs with the quirks of its container".to_string(),
+        (None, None, Some(platform), Some(_)) => format!("runs with the {} quirks", platform.profile()),
+        _ => "runs with the quirks of the config".to_string(),
+    };
+    match platform {
+        Some(platform) => println!("Platform: {}, detected from its code, {}", platform, quirks),
+        None => println!("Platform: CHIP-8, no extension opcodes in its code, {}", quirks),
+    }
+    if !report.is_empty() {
+        println!("Possible extension opcodes (may also be data):");
+        print!("{}", report);
+    }
+
+    if let Some(rating) = ratings.own(hash) {
+        println!("Rated: {}", rating);
+    }
+    for rating in ratings.imported(hash) {
+        println!("Rated by others: {}", rating);
+    }
 
-pub struct Chip8 {
-    system: system::System,
-    window: window::Window,
+    Ok(())
 }
 
-impl Chip8 {
-    pub fn new() -> Result<Self, Error> {
-        Ok(Chip8 {
-            system: system::System::default(),
-            window: window::Window::new()?,
-        })
+/// The settings `run` and `debug` share
+fn run_builder(args: &cli::RunArgs) -> emulator::EmulatorBuilder {
+    let mut builder = emulator::EmulatorBuilder::new()
+        .config_file(&args.config)
+        .display(args.display)
+        .diagnostics(args.diagnostics, args.vf_warnings);
+    if let Some(seed) = args.seed {
+        builder = builder.seed(seed);
     }
+    builder
+}
+
+/// The rom of `run` and `debug`, found by name in `rom_dirs` of the config if it is not a file
+fn find_rom(args: &cli::RunArgs, config: &config::Config) -> Result<PathBuf, Error> {
+    let dir = args.config.parent().unwrap_or(Path::new(""));
+    let dirs = config.rom_dirs.iter().map(|rom_dir| dir.join(rom_dir)).collect::<Vec<_>>();
+    romsearch::find(&args.rom, &dirs)
+}
 
-    pub fn draw(&mut self) -> Result<(), Error> {
-        self.window.draw(self.system.screen(), 64, 32)
+/// Applies `--patch-mem` to the loaded rom, then writes `--dump-mem`
+fn patch_and_dump_memory(emulator: &mut emulator::Emulator, args: &cli::RunArgs) -> Result<(), Error> {
+    if args.patch_mem.is_empty() && args.dump_mem.is_empty() {
+        return Ok(());
     }
+    let system = emulator.system_mut()?;
+    for (addr, path) in &args.patch_mem {
+        let data = std::fs::read(path).map_err(|err| format_err!("Could not read {}: {}", path.display(), err))?;
+        system
+            .write_slice(*addr, &data)
+            .map_err(|_| format_err!("{} ({} bytes) does not fit in memory at {:#X}", path.display(), data.len(), addr))?;
+        log::info!("Patched {} bytes at {:#X} from {}", data.len(), addr, path.display());
+    }
+    for (range, path) in &args.dump_mem {
+        std::fs::write(path, system.read_slice(range.clone())?)?;
+        log::info!("Dumped {:#X}..{:#X} to {}", range.start, range.end, path.display());
+    }
+    Ok(())
+}
 
-    fn display_loop(&mut self) -> Result<(), Error> {
-        loop {
-            self.draw()?;
-            std::thread::sleep(std::time::Duration::from_millis(
-                (1.0 / 30.0 * 1000.0f32) as u64,
-            ))
+fn run_command(command: cli::Command) -> Result<(), Error> {
+    match command {
+        cli::Command::Run(args) => {
+            let mut config = config::Config::load(&args.config)?;
+            let rom = find_rom(&args, &config)?;
+            config.timing.auto_speed |= args.auto_speed;
+            if let Some(cycles) = args.cycles_per_poll {
+                config.timing.cycles_per_poll = cycles;
+            }
+            let mut builder = run_builder(&args)
+                .config(config)
+                .rom_file(rom)
+                .memory_fill(args.memory_fill);
+            if args.compat_report {
+                builder = builder.compat_report();
+            }
+            if let Some(path) = &args.frames_out {
+                builder = builder.frames_out(path);
+            }
+            if let Some(path) = &args.record_audio {
+                builder = builder.record_audio(path);
+            }
+            if args.record_replay.is_some() {
+                builder = builder.record_replay();
+            }
+            if args.summary_json.is_some() {
+                builder = builder.summary();
+            }
+            if let Some(hz) = args.teach {
+                builder = builder.teach(hz);
+            }
+            if args.trace {
+                builder = builder.trace();
+            }
+            let mut emulator = builder.build()?;
+            patch_and_dump_memory(&mut emulator, &args)?;
+
+            let res = emulator.run();
+            // also when the program crashed, to repeat the crash
+            if let (Some(path), Some(replay)) = (&args.record_replay, emulator.replay()) {
+                replay.save(path)?;
+                log::info!("Wrote a replay of {} instructions to {}", replay.length, path.display());
+            }
+            if let Some(path) = &args.summary_json {
+                emulator.write_summary(path)?;
+            }
+            if args.dump_screen {
+                print!("{}", emulator.system().screen_ascii('#', '.'));
+            }
+            if let Some(report) = emulator.compat_report() {
+                print!("{}", report);
+            }
+            res
+        }
+
+        cli::Command::Start { config, watch: dir } => {
+            let mut builder = emulator::EmulatorBuilder::new().config_file(config);
+            if let Some(dir) = dir {
+                builder = builder.watch_dir(dir);
+            }
+            builder.build()?.run()
         }
-    }
 
-    pub fn run(&mut self) -> Result<(), Error> {
-        let delta = std::time::Duration::from_millis((1.0 / 60.0 * 1000.0f32) as u64);
-        let mut debug = system::debug::Debugger::disabled();
+        cli::Command::Latency { config: config_path, display } => {
+            let config = config::Config::load(&config_path)?;
+            let mut chip = Chip8::<system::System>::new(config, config_path, display)?;
+            chip.latency = Some(latency::LatencyProbe::default());
+            chip.load_rom(&latency::rom(), None)?;
 
-        let mut next_update = std::time::Instant::now() + delta;
-        loop {
-            let res = self.system.tick(&mut debug);
-            if let Err(system::SystemError::ZeroInstruction) = res {
-                println!("Reached the end of the program. Entering infinite loop");
-                self.display_loop()?;
-            } else {
-                res?;
+            println!(
+                "Press and release keypad key {:X} repeatedly, close the window to see the results",
+                latency::KEY
+            );
+            let res = chip.run(&mut clock::RealClock::new(chip.config.power.spin_wait()));
+            if let Some(probe) = &chip.latency {
+                println!("{}", probe.report());
+            }
+            res
+        }
+
+        cli::Command::Calibrate { config: config_path, seconds } => {
+            let mut config = config::Config::load(&config_path)?;
+            // skipping the waits would measure nothing
+            config.timing.auto_speed = false;
+            let (timing, power, audio) = (config.timing, config.power, config.audio.clone());
+
+            let measurements = std::rc::Rc::new(std::cell::RefCell::new(calibrate::Measurements::default()));
+            let frames = (f64::from(seconds) * timing.refresh_hz) as u64;
+            let probe = Box::new(calibrate::Probe::new(measurements.clone(), frames));
+            let mut chip = Chip8::<system::System>::with_window(config, config_path, cli::DisplayArgs::default(), probe)?;
+            chip.load_rom(&calibrate::rom(), None)?;
+
+            println!("Measuring for {} seconds", seconds);
+            let mut clock = calibrate::MeasuredClock::new(clock::RealClock::new(power.spin_wait()), measurements.clone());
+            chip.run(&mut clock)?;
+            let report = calibrate::Report::new(&measurements.borrow(), chip.trace.cycle(), &timing, &power, &audio);
+            print!("{}", report);
+            Ok(())
+        }
+
+        #[cfg(feature = "debugger")]
+        cli::Command::Debug(args) => {
+            let rom = find_rom(&args, &config::Config::load(&args.config)?)?;
+            let mut emulator = run_builder(&args).rom_file(rom).memory_fill(args.memory_fill).build()?;
+            patch_and_dump_memory(&mut emulator, &args)?;
+            let res = emulator.run_debug();
+            if args.dump_screen {
+                print!("{}", emulator.system().screen_ascii('#', '.'));
+            }
+            res
+        }
+        #[cfg(not(feature = "debugger"))]
+        cli::Command::Debug(_) => bail!("The debugger is not available, rebuild with `--features debugger`"),
+
+        cli::Command::Disasm { rom } => {
+            let data = container::load(&rom)?.rom;
+            for (addr, opcode, text) in disasm::listing(&data, system::PROGRAM_START) {
+                println!("{:03X}: {:04X}  {}", addr, opcode, text);
+            }
+            Ok(())
+        }
+
+        cli::Command::Opcodes => {
+            println!("Opcode  Syntax          Cycles  Description");
+            for info in system::opcode::OPCODES {
+                let quirk = info.quirk.map_or(String::new(), |quirk| format!(" (quirk: {})", quirk));
+                println!("{:<8}{:<16}{:>6}  {}{}", info.pattern_name(), info.syntax, info.cycles, info.description, quirk);
             }
+            Ok(())
+        }
+
+        #[cfg(feature = "analysis")]
+        cli::Command::Decompile { rom } => {
+            print!("{}", analysis::decompile::decompile(&container::load(&rom)?.rom));
+            Ok(())
+        }
+
+        #[cfg(not(feature = "analysis"))]
+        cli::Command::Decompile { .. } => bail!("The decompiler is not available, rebuild with `--features analysis`"),
+
+        #[cfg(feature = "assembler")]
+        cli::Command::Asm { src: path, output } => {
+            let src = std::fs::read_to_string(&path)?;
+            let data = if path.extension().is_some_and(|ext| ext == container::OCTO_EXTENSION) {
+                asm::octo::assemble(&src)?
+            } else {
+                asm::assemble(&src)?
+            };
+            std::fs::write(&output, &data)?;
+            println!("{}: {} bytes", output.display(), data.len());
+            Ok(())
+        }
 
-            if self.system.dec_timers() {
-                println!("Beep!");
+        #[cfg(not(feature = "assembler"))]
+        cli::Command::Asm { .. } => bail!("The assembler is not available, rebuild with `--features assembler`"),
+
+        cli::Command::Info { rom, config: config_path } => {
+            let config = config::Config::load(&config_path)?;
+            info(&rom, &config, &ratings::Ratings::load(config_path.with_file_name(ratings::FILE_NAME))?)
+        }
+
+        #[cfg(feature = "analysis")]
+        cli::Command::Lint { rom } => {
+            let data = container::load(&rom)?.rom;
+            let lints = lint::lint(&data);
+            for lint in &lints {
+                println!("{}", lint);
             }
+            println!("Register usage:");
+            print!("{}", analysis::registers::report(&analysis::registers::analyze(&analysis::Cfg::build(&data))));
 
-            let now = std::time::Instant::now();
-            if now >= next_update {
-                next_update = now + delta;
-                self.draw()?;
+            let errors = lints
+                .iter()
+                .filter(|lint| lint.severity == lint::Severity::Error)
+                .count();
+            println!("{} errors, {} warnings", errors, lints.len() - errors);
+            if errors > 0 {
+                bail!("{} errors", errors);
             }
+            Ok(())
+        }
 
-            std::thread::sleep(std::time::Duration::from_micros(
-                (2400.0f32.recip() * 1000000.0) as u64,
-            ));
+        #[cfg(not(feature = "analysis"))]
+        cli::Command::Lint { .. } => bail!("The linter is not available, rebuild with `--features analysis`"),
 
-            let mut err = None;
-            let mut exit = false;
-            let ev = &mut self.window.ev;
-            let sys = &mut self.system;
-            ev.poll_events(|event| {
-                match keys::map_key(event) {
-                    keys::MapKeyResult::Event { key, pressed } => {
-                        if let Err(e) = sys.process_key_event(key, pressed) {
-                            err = Some(e);
-                        }
-                    }
+        cli::Command::TestSuite { dir, cycles, bless, report } => {
+            let failed = testsuite::run(&dir, cycles, bless, &report)?;
+            if failed > 0 {
+                return Err(error::Failed::TestSuite { failed }.into());
+            }
+            Ok(())
+        }
 
-                    keys::MapKeyResult::Exit => {
-                        exit = true;
-                    }
+        cli::Command::Check { rom, script, seed } => {
+            let script = script::Script::parse(&std::fs::read_to_string(&script)?)
+                .map_err(|e| format_err!("{}: {}", script.display(), e))?;
+            let mut system = system::System::default();
+            system.seed(seed);
+            system.load_from_file(rom)?;
 
-                    keys::MapKeyResult::None => {}
+            let outcomes = script.run(system);
+            for outcome in &outcomes {
+                let result = if outcome.passed { "PASS" } else { "FAIL" };
+                println!("{} line {}: {}", result, outcome.line, outcome.message);
+            }
+            let failed = outcomes.iter().filter(|outcome| !outcome.passed).count();
+            if failed > 0 {
+                return Err(error::Failed::Assertions {
+                    failed,
+                    total: outcomes.len(),
                 }
-            });
-            if exit {
-                return Ok(())
+                .into());
             }
-            if let Some(err) = err {
-                return Err(err.into());
+            Ok(())
+        }
+
+        cli::Command::Tournament {
+            rom,
+            bots,
+            frames,
+            jobs,
+            score,
+            seed,
+            config,
+        } => {
+            let config = config::Config::load(&config)?;
+            let data = container::load(&rom)?.rom;
+            let hash = rom::hash(&data);
+            let region = score
+                .or_else(|| config.game(hash).and_then(|game| game.score))
+                .ok_or_else(|| format_err!("The game database has no score for {}, give one with --score", rom.display()))?;
+            let bots = bots
+                .iter()
+                .map(|path| {
+                    let script = script::Script::parse(&std::fs::read_to_string(path)?)
+                        .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+                    let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                    Ok(tournament::Bot { name, script })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            let system = || {
+                let mut system = system::System::default();
+                system.quirks = config.quirks_on(hash, compat::detect(&data));
+                system.seed(seed);
+                system.load(&data[..])?;
+                Ok(system)
+            };
+            let standings = tournament::run(system, &bots, region, frames, jobs)?;
+            tournament::write_leaderboard(&standings, std::io::stdout().lock())
+        }
+
+        cli::Command::Batch {
+            dir,
+            cycles,
+            jobs,
+            format,
+            output,
+        } => {
+            let results = batch::run(&dir, cycles, jobs)?;
+            match output {
+                Some(path) => batch::write_report(&results, format, std::fs::File::create(path)?)?,
+                None => batch::write_report(&results, format, std::io::stdout().lock())?,
             }
+
+            let count = |status| results.iter().filter(|result| result.status == status).count();
+            log::info!(
+                "{} roms: {} halted, {} crashed, {} in a stable loop, {} still running",
+                results.len(),
+                count(batch::Status::Halted),
+                count(batch::Status::Crashed),
+                count(batch::Status::Stable),
+                count(batch::Status::Running)
+            );
+            Ok(())
         }
-    }
 
-    pub fn run_debug(&mut self) -> Result<(), Error> {
-        let mut debug = system::debug::Debugger::enabled();
-        loop {
-            println!("{}", self.system.registers);
-            self.system.tick(&mut debug)?;
+        cli::Command::Replay { replay } => {
+            let replay = replay::Replay::load(&replay)?;
+            let system = replay.play()?;
+            print!("{}", system.screen_ascii('#', '.'));
+            println!(
+                "{} inputs over {} instructions, state hash {:016x}",
+                replay.events.iter().filter(|event| event.action != replay::Action::Timer).count(),
+                replay.length,
+                system.state_hash()
+            );
+            Ok(())
+        }
 
-            if self.system.dec_timers() {
-                println!("Beep!");
+        cli::Command::StateDiff { before, after } => {
+            let load = |path: &Path| -> Result<system::System, Error> {
+                let mut system = system::System::default();
+                std::fs::read(path)
+                    .map_err(Error::from)
+                    .and_then(|state| Ok(system.load_state(&state)?))
+                    .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+                Ok(system)
+            };
+            let diff = system::diff::StateDiff::between(&load(&before)?, &load(&after)?);
+            if diff.is_empty() {
+                println!("no differences");
+            }
+            for change in &diff.changes {
+                println!("{}", change);
             }
+            Ok(())
+        }
+
+        cli::Command::Audit {
+            rom,
+            frames,
+            seed,
+            log,
+            verify,
+        } => {
+            let data = std::fs::read(rom)?;
+            audit::run(&data, seed, frames, log.as_deref(), verify.as_deref())
+        }
 
-            self.draw()?;
-            std::io::stdin().read_line(&mut String::new())?;
+        cli::Command::Bench { rom, cycles, seed } => {
+            let data = std::fs::read(rom)?;
+            print!("{}", bench::run(&data, cycles, seed)?);
+            Ok(())
+        }
+
+        cli::Command::QuirkTest { output: Some(output), .. } => {
+            std::fs::write(output, quirktest::rom())?;
+            Ok(())
+        }
+
+        cli::Command::QuirkTest { config, output: None } => {
+            let config = config::Config::load(&config)?;
+            let report = quirktest::run(config.quirks)?;
+            print!("{}", report);
+            match report.mismatches() {
+                0 => Ok(()),
+                count => bail!("{} of the behaviors do not match the quirks", count),
+            }
+        }
+
+        cli::Command::SelfTest => {
+            let report = selftest::run();
+            print!("{}", report);
+            match report.failures() {
+                0 => Ok(()),
+                count => bail!("{} of the areas failed, this build does not run roms correctly", count),
+            }
+        }
+
+        cli::Command::Rom { command, input, output } => {
+            let data = std::fs::read(&input)?;
+            let result = match command {
+                cli::RomCommand::Trim => rom::trim(&data).to_vec(),
+                cli::RomCommand::Pad => rom::pad(&data),
+            };
+
+            std::fs::write(&output, &result)?;
+            println!("{}: {} -> {} bytes", output.display(), data.len(), result.len());
+            Ok(())
+        }
+
+        cli::Command::Rate {
+            rom,
+            status,
+            notes,
+            config: config_path,
+        } => {
+            let config = config::Config::load(&config_path)?;
+            let hash = rom::hash(&container::load(&rom)?.rom);
+            let name = config.game(hash).and_then(|game| game.name.clone());
+            let rating = ratings::Rating::new(status, name, notes, config.quirks_for(hash));
+            println!("{}: {}", rom.display(), rating);
+
+            let path = config_path.with_file_name(ratings::FILE_NAME);
+            let mut ratings = ratings::Ratings::load(&path)?;
+            ratings.rate(hash, rating);
+            ratings.save(&path)
+        }
+
+        cli::Command::Ratings { command, path, config } => {
+            let ratings_path = config.with_file_name(ratings::FILE_NAME);
+            let mut ratings = ratings::Ratings::load(&ratings_path)?;
+            match command {
+                cli::RatingsCommand::Export => std::fs::write(&path, ratings.export()?)?,
+                cli::RatingsCommand::Import => {
+                    let json = std::fs::read_to_string(&path)?;
+                    let added = ratings.import(&json).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+                    println!("{} new ratings", added);
+                    ratings.save(&ratings_path)?;
+                }
+            }
+            Ok(())
+        }
+
+        cli::Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
         }
     }
 }
 
-fn main() {
-    let mut chip = Chip8::new().unwrap();
+/// Logs to stderr, filtered by `RUST_LOG` (e.g. `RUST_LOG=input=trace`).
+/// Targets: `cpu`, `display`, `input`, `audio`
+fn init_logger(command: &cli::Command) {
+    // the instruction by instruction log is what stepping is for
+    let default = match command {
+        cli::Command::Debug(_) => "info,cpu=debug",
+        cli::Command::Run(args) if args.trace => "info,cpu=debug",
+        _ => "info",
+    };
 
-    chip.system
-        .load_from_file(std::env::args().skip(1).next().unwrap())
-        .unwrap();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default))
+        .format_timestamp(None)
+        .init();
+}
 
-    chip.run().unwrap();
+fn main() {
+    crash::install_panic_hook();
+    let command = match cli::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Error: {}", error::describe(&e));
+            std::process::exit(error::ExitStatus::Usage as i32);
+        }
+    };
+    init_logger(&command);
+    if let Err(e) = run_command(command) {
+        eprintln!("Error: {}", error::describe(&e));
+        std::process::exit(error::ExitStatus::of(&e));
+    }
 }
@@ -0,0 +1,139 @@
+use crate::system::{Stack, System, Timers};
+
+#[cfg(feature = "debugger")]
+pub mod console;
+pub mod cost;
+#[cfg(feature = "debugger")]
+pub mod debugger;
+pub mod heatmap;
+pub mod keypad;
+pub mod keymap;
+pub mod notify;
+pub mod perf;
+pub mod pixel;
+pub mod slots;
+pub mod teach;
+pub mod text;
+
+/// Width to height ratio of the window, used to keep text pixels square
+pub const ASPECT: f32 = 2.0;
+
+/// Axis-aligned rectangle in window space: (0, 0) is the top left corner and (1, 1) the bottom
+/// right one
+#[derive(Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub pos: [f32; 2],
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32, color: [f32; 4]) -> Self {
+        Rect {
+            pos: [x, y],
+            size: [w, h],
+            color,
+        }
+    }
+}
+
+const PANEL_X: f32 = 0.78;
+const PANEL_Y: f32 = 0.03;
+const PANEL_WIDTH: f32 = 0.2;
+const PADDING: f32 = 0.01;
+
+const BACKGROUND: [f32; 4] = [0.1, 0.1, 0.1, 0.75];
+const EMPTY: [f32; 4] = [0.3, 0.3, 0.3, 0.75];
+const STACK: [f32; 4] = [0.2, 0.8, 0.3, 1.0];
+const DELAY: [f32; 4] = [0.9, 0.8, 0.2, 1.0];
+const SOUND: [f32; 4] = [0.9, 0.3, 0.2, 1.0];
+
+#[derive(Default)]
+pub struct Overlay {
+    pub enabled: bool,
+}
+
+impl Overlay {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// builds the list of rectangles to draw on top of the screen
+    pub fn rects(&self, system: &System) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        if !self.enabled {
+            return rects;
+        }
+
+        let mut y = PANEL_Y + PADDING;
+        let mut panel = Vec::new();
+        y = stack_meter(&mut panel, &system.stack, system.quirks.stack_depth(), y);
+        y = timer_bars(&mut panel, &system.timers, y + PADDING);
+
+        rects.push(Rect::new(PANEL_X, PANEL_Y, PANEL_WIDTH, y - PANEL_Y, BACKGROUND));
+        rects.extend(panel);
+        rects
+    }
+}
+
+const BANNER_TEXT_SIZE: f32 = 0.006;
+const BANNER: [f32; 4] = [0.1, 0.1, 0.1, 0.85];
+const BANNER_TEXT: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+
+/// A line of text in a box centered at the top of the window
+pub fn banner(message: &str) -> Vec<Rect> {
+    let width = text::width(message, BANNER_TEXT_SIZE);
+    let height = text::height(BANNER_TEXT_SIZE);
+    let x = (1.0 - width) / 2.0;
+    let y = PANEL_Y + PADDING;
+
+    let mut rects = vec![Rect::new(
+        x - PADDING,
+        y - PADDING * ASPECT,
+        width + PADDING * 2.0,
+        height + PADDING * ASPECT * 2.0,
+        BANNER,
+    )];
+    text::draw(&mut rects, x, y, BANNER_TEXT_SIZE, BANNER_TEXT, message);
+    rects
+}
+
+/// one cell per stack slot, filled up to the stack pointer
+fn stack_meter(rects: &mut Vec<Rect>, stack: &Stack, slots: usize, y: f32) -> f32 {
+    let inner = PANEL_WIDTH - PADDING * 2.0;
+    let cell = inner / slots as f32;
+    let height = 0.04;
+
+    for i in 0..slots {
+        let color = if i < stack.sp as usize { STACK } else { EMPTY };
+        rects.push(Rect::new(
+            PANEL_X + PADDING + cell * i as f32,
+            y,
+            cell * 0.8,
+            height,
+            color,
+        ));
+    }
+
+    y + height + PADDING
+}
+
+/// delay and sound timers as bars proportional to their value
+fn timer_bars(rects: &mut Vec<Rect>, timers: &Timers, mut y: f32) -> f32 {
+    let inner = PANEL_WIDTH - PADDING * 2.0;
+    let height = 0.03;
+
+    for &(value, color) in &[(timers.delay, DELAY), (timers.sound, SOUND)] {
+        rects.push(Rect::new(PANEL_X + PADDING, y, inner, height, EMPTY));
+        rects.push(Rect::new(
+            PANEL_X + PADDING,
+            y,
+            inner * value as f32 / f32::from(u8::MAX),
+            height,
+            color,
+        ));
+        y += height + PADDING;
+    }
+
+    y
+}
@@ -0,0 +1,108 @@
+use winit::VirtualKeyCode;
+use std::collections::VecDeque;
+
+use super::{text, Rect, ASPECT};
+
+const TEXT_SIZE: f32 = 0.0035;
+const LINE: f32 = TEXT_SIZE * ASPECT * (text::GLYPH_HEIGHT as f32 + 2.0);
+const X: f32 = 0.02;
+/// output lines shown above the prompt
+const OUTPUT_LINES: usize = 5;
+/// lines the console takes at the bottom of the window, with the prompt
+pub const LINES: usize = OUTPUT_LINES + 1;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.9];
+const OUTPUT: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+const PROMPT: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+
+pub enum ConsoleAction {
+    None,
+    /// Return was pressed on a command
+    Submit(String),
+    Close,
+}
+
+/// The prompt of the debugger in the window, typed into with the keyboard, and the last lines
+/// of output above it. For when there is no terminal to run `chip8 debug` in
+#[derive(Default)]
+pub struct Console {
+    input: String,
+    output: VecDeque<String>,
+    /// the last command entered, Up brings it back
+    last: Option<String>,
+}
+
+impl Console {
+    pub fn print(&mut self, line: impl Into<String>) {
+        self.output.push_back(line.into());
+        while self.output.len() > OUTPUT_LINES {
+            self.output.pop_front();
+        }
+    }
+
+    pub fn handle_key(&mut self, keycode: VirtualKeyCode) -> ConsoleAction {
+        match keycode {
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                let line = std::mem::take(&mut self.input);
+                self.print(format!("> {}", line));
+                self.last = Some(line.clone());
+                return ConsoleAction::Submit(line);
+            }
+            VirtualKeyCode::Back => {
+                self.input.pop();
+            }
+            VirtualKeyCode::Up => {
+                if let Some(last) = &self.last {
+                    self.input = last.clone();
+                }
+            }
+            VirtualKeyCode::Escape | VirtualKeyCode::F1 => return ConsoleAction::Close,
+            keycode => self.input.extend(key_char(keycode)),
+        }
+        ConsoleAction::None
+    }
+
+    /// A panel over the bottom of the window, `status` in place of the prompt when given
+    pub fn rects(&self, status: Option<&str>) -> Vec<Rect> {
+        let top = 1.0 - LINES as f32 * LINE - TEXT_SIZE * ASPECT;
+        let mut rects = vec![Rect::new(0.0, top, 1.0, 1.0 - top, BACKGROUND)];
+        let line_y = |row: usize| top + TEXT_SIZE * ASPECT + row as f32 * LINE;
+
+        for (row, line) in self.output.iter().enumerate() {
+            text::draw(&mut rects, X, line_y(row), TEXT_SIZE, OUTPUT, line);
+        }
+        let prompt = match status {
+            Some(status) => status.to_string(),
+            None => format!("> {}_", self.input),
+        };
+        text::draw(&mut rects, X, line_y(OUTPUT_LINES), TEXT_SIZE, PROMPT, &prompt);
+        rects
+    }
+}
+
+/// What typing the key adds to the prompt, commands only need lowercase letters, digits and a
+/// few symbols
+fn key_char(keycode: VirtualKeyCode) -> Option<char> {
+    use VirtualKeyCode::*;
+
+    const LETTERS: [VirtualKeyCode; 26] = [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    ];
+    const DIGITS: [VirtualKeyCode; 10] = [Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+    const NUMPAD: [VirtualKeyCode; 10] = [
+        Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    ];
+
+    if let Some(i) = LETTERS.iter().position(|&key| key == keycode) {
+        return Some((b'a' + i as u8) as char);
+    }
+    if let Some(i) = DIGITS.iter().chain(&NUMPAD).position(|&key| key == keycode) {
+        return Some((b'0' + (i % 10) as u8) as char);
+    }
+    match keycode {
+        Space => Some(' '),
+        Minus | Subtract => Some('-'),
+        Period => Some('.'),
+        _ => None,
+    }
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant};
+
+/// The most building the overlays may take per frame, an eighth of a frame at 60 Hz. Past it
+/// they eat into the time the program runs in, which can change its pacing
+pub const BUDGET: Duration = Duration::from_millis(2);
+
+/// How long building each overlay took in the last frame
+#[derive(Default)]
+pub struct CostMeter {
+    laps: Vec<(&'static str, Duration)>,
+    lap_start: Option<Instant>,
+    /// all of the last frame's
+    pub last: Duration,
+    /// the warning about going over `BUDGET` is only given once
+    warned: bool,
+}
+
+impl CostMeter {
+    pub fn start(&mut self) {
+        self.laps.clear();
+        self.lap_start = Some(Instant::now());
+    }
+
+    /// Ends the time of the overlay called `name`, and starts the next
+    pub fn lap(&mut self, name: &'static str) {
+        let now = Instant::now();
+        if let Some(start) = self.lap_start {
+            self.laps.push((name, now - start));
+        }
+        self.lap_start = Some(now);
+    }
+
+    /// Adds up the frame's overlays, warning the first time they go over `BUDGET`
+    pub fn finish(&mut self) {
+        self.lap_start = None;
+        self.last = self.laps.iter().map(|(_, time)| *time).sum();
+        if self.last > BUDGET && !self.warned {
+            self.warned = true;
+            let (slowest, time) = self.laps.iter().max_by_key(|(_, time)| *time).copied().unwrap_or(("", Duration::ZERO));
+            log::warn!(
+                target: "display",
+                "Drawing the overlays took {:.2} ms of the {:.2} ms they may take per frame, the {} took {:.2} ms. Hiding it keeps the game at its pace",
+                self.last.as_secs_f64() * 1000.0,
+                BUDGET.as_secs_f64() * 1000.0,
+                slowest,
+                time.as_secs_f64() * 1000.0,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlay;
+    use crate::system::System;
+
+    #[test]
+    fn every_overlay_fits_in_the_budget() {
+        let mut system = System::default();
+        system.load(&[0x12, 0x00][..]).unwrap();
+        let mut heatmap = overlay::heatmap::Heatmap::default();
+        heatmap.toggle();
+        let mut perf = overlay::perf::PerfGraph::default();
+        perf.toggle();
+        perf.reset(60.0);
+        for frame in 0..300 {
+            perf.instructions(frame);
+            perf.frame(Duration::from_millis(u64::from(frame) * 16));
+        }
+
+        // the best of a few frames, the test runs next to others
+        let mut meter = CostMeter::default();
+        let mut best = Duration::MAX;
+        for _ in 0..20 {
+            meter.start();
+            let mut rects = overlay::Overlay { enabled: true }.rects(&system);
+            meter.lap("stack and timers");
+            rects.extend(heatmap.rects(&system));
+            meter.lap("heatmap");
+            rects.extend(perf.rects(meter.last));
+            meter.lap("performance graph");
+            #[cfg(feature = "debugger")]
+            rects.extend(overlay::debugger::rects(&system, overlay::debugger::ROWS, &Default::default()));
+            meter.lap("debugger");
+            meter.finish();
+            best = best.min(meter.last);
+            assert!(!rects.is_empty());
+        }
+        assert!(best < BUDGET, "the overlays took {:?}", best);
+    }
+}
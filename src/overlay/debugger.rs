@@ -0,0 +1,161 @@
+use super::{text, Rect, ASPECT};
+use crate::disasm;
+use crate::system::{System, MEMORY_SIZE};
+
+const TEXT_SIZE: f32 = 0.0035;
+const LINE: f32 = TEXT_SIZE * ASPECT * (text::GLYPH_HEIGHT as f32 + 2.0);
+const TOP: f32 = 0.04;
+/// lines that fit in the window
+pub const ROWS: usize = 19;
+const REGISTERS_X: f32 = 0.02;
+const DISASM_X: f32 = 0.3;
+const MEMORY_X: f32 = 0.68;
+/// instructions shown before the one at PC
+const DISASM_BEFORE: u16 = 6;
+const DISASM_LINES: u16 = 16;
+const MEMORY_ROWS: u16 = 16;
+const MEMORY_COLUMNS: u16 = 4;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.08, 0.9];
+const HEADING: [f32; 4] = [0.5, 0.7, 1.0, 1.0];
+const TEXT: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const DIM: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const CURRENT: [f32; 4] = [0.2, 0.35, 0.6, 1.0];
+
+/// Where the debugger is shown
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Placement {
+    /// a window of its own
+    Window,
+    /// over the game, for frontends that cannot open a second window
+    Overlay,
+}
+
+/// The parts of the debugger view shown, each toggled with the `panel` command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Panels {
+    pub registers: bool,
+    pub stack: bool,
+    pub disassembly: bool,
+    pub memory: bool,
+}
+
+impl Default for Panels {
+    fn default() -> Self {
+        Panels {
+            registers: true,
+            stack: true,
+            disassembly: true,
+            memory: true,
+        }
+    }
+}
+
+impl Panels {
+    pub const NAMES: [&'static str; 4] = ["registers", "stack", "disassembly", "memory"];
+
+    /// Shows or hides the panel called `name`, returns whether it is shown now or None if
+    /// there is no such panel
+    pub fn toggle(&mut self, name: &str) -> Option<bool> {
+        let shown = match name {
+            "registers" => &mut self.registers,
+            "stack" => &mut self.stack,
+            "disassembly" => &mut self.disassembly,
+            "memory" => &mut self.memory,
+            _ => return None,
+        };
+        *shown = !*shown;
+        Some(*shown)
+    }
+}
+
+/// Registers and stack, the disassembly around PC and the memory at I, filling the window,
+/// as far as `panels` shows them. Only the first `rows` lines of it are used, up to `ROWS`
+pub fn rects(system: &System, rows: usize, panels: &Panels) -> Vec<Rect> {
+    let mut rects = vec![Rect::new(0.0, 0.0, 1.0, 1.0, BACKGROUND)];
+    let rows = rows.min(ROWS);
+    // the stack goes below the registers, or in their place
+    let stack_row = if panels.registers { registers(&mut rects, system, rows) } else { 0 };
+    if panels.stack {
+        stack(&mut rects, system, stack_row, rows);
+    }
+    if panels.disassembly {
+        disassembly(&mut rects, system, rows);
+    }
+    if panels.memory {
+        memory(&mut rects, system, rows);
+    }
+    rects
+}
+
+fn line(rects: &mut Vec<Rect>, x: f32, row: usize, color: [f32; 4], line: &str) {
+    text::draw(rects, x, TOP + row as f32 * LINE, TEXT_SIZE, color, line);
+}
+
+/// Returns the row after them
+fn registers(rects: &mut Vec<Rect>, system: &System, rows: usize) -> usize {
+    let registers = &system.registers;
+    let mut lines = vec![
+        format!("PC {:03X}  I {:03X}", registers.pc, registers.index),
+        format!("DT {:02X}   ST {:02X}", system.timers.delay, system.timers.sound),
+    ];
+    lines.extend((0..8).map(|i| {
+        format!("V{:X} {:02X}   V{:X} {:02X}", i, registers.reg[i], i + 8, registers.reg[i + 8])
+    }));
+    // the keys held, and the last one pressed
+    let held = (0..16).filter(|key| system.keys.pressed(*key).unwrap_or(false)).map(|key| format!("{:X}", key)).collect::<String>();
+    let last = system.keys.last_pressed().map_or("-".to_string(), |key| format!("{:X}", key));
+    lines.push(format!("KEYS {:<4} LAST {}", if held.is_empty() { "-" } else { &held }, last));
+
+    line(rects, REGISTERS_X, 0, HEADING, "REGISTERS");
+    for (row, text) in lines.iter().enumerate().take(rows.saturating_sub(1)) {
+        line(rects, REGISTERS_X, row + 1, TEXT, text);
+    }
+
+    lines.len() + 2
+}
+
+fn stack(rects: &mut Vec<Rect>, system: &System, row: usize, rows: usize) {
+    if row >= rows {
+        return;
+    }
+    let sp = usize::from(system.stack.sp).min(system.stack.stack.len());
+    line(rects, REGISTERS_X, row, HEADING, &format!("STACK {}/{}", sp, system.quirks.stack_depth()));
+    // innermost call first, as many as fit
+    for (i, addr) in system.stack.stack[..sp].iter().rev().take(rows - row - 1).enumerate() {
+        line(rects, REGISTERS_X, row + 1 + i, TEXT, &format!("{:03X}", addr));
+    }
+}
+
+fn disassembly(rects: &mut Vec<Rect>, system: &System, rows: usize) {
+    line(rects, DISASM_X, 0, HEADING, "DISASSEMBLY");
+
+    let pc = system.registers.pc;
+    let start = pc.saturating_sub(DISASM_BEFORE * 2);
+    let lines = DISASM_LINES.min(rows.saturating_sub(1) as u16);
+    for (row, (addr, instruction)) in (1..).zip(system.iter_instructions(start..start + lines * 2)) {
+        let opcode = instruction.map_or_else(|word| word, |instruction| instruction.word);
+        if addr == pc {
+            let y = TOP + row as f32 * LINE - TEXT_SIZE * ASPECT;
+            rects.push(Rect::new(DISASM_X - TEXT_SIZE, y, MEMORY_X - DISASM_X - 0.02, LINE, CURRENT));
+        }
+        let color = if addr == pc { TEXT } else { DIM };
+        let text = format!("{:03X} {:04X} {}", addr, opcode, disasm::format(instruction));
+        line(rects, DISASM_X, row, color, &text);
+    }
+}
+
+fn memory(rects: &mut Vec<Rect>, system: &System, rows: usize) {
+    line(rects, MEMORY_X, 0, HEADING, "MEMORY AT I");
+
+    let start = system.registers.index & !(MEMORY_COLUMNS - 1);
+    for row in 0..MEMORY_ROWS.min(rows.saturating_sub(1) as u16) {
+        let addr = start + row * MEMORY_COLUMNS;
+        let bytes = match system.read_slice(addr..(addr + MEMORY_COLUMNS).min(MEMORY_SIZE)) {
+            Ok(bytes) if !bytes.is_empty() => bytes,
+            _ => break,
+        };
+        let bytes = bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>();
+        line(rects, MEMORY_X, usize::from(row) + 1, TEXT, &format!("{:03X} {}", addr, bytes.join(" ")));
+    }
+}
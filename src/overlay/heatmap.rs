@@ -0,0 +1,78 @@
+use super::{text, Rect, ASPECT};
+use crate::system::{System, PROGRAM_START};
+
+/// addresses per heatmap row, 4 KiB make a 64x64 square
+const ROW: usize = 64;
+
+const MAP_X: f32 = 0.02;
+const MAP_Y: f32 = 0.08;
+const MAP_HEIGHT: f32 = 0.88;
+const MAP_WIDTH: f32 = MAP_HEIGHT / ASPECT;
+const PADDING: f32 = 0.01;
+const TEXT_SIZE: f32 = 0.004;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 0.85];
+const RESERVED: [f32; 4] = [0.15, 0.15, 0.2, 0.85];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Memory map colored by recent activity: red for writes, green for reads, blue for execution
+#[derive(Default)]
+pub struct Heatmap {
+    pub enabled: bool,
+}
+
+impl Heatmap {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    pub fn rects(&self, system: &System) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        if !self.enabled {
+            return rects;
+        }
+
+        let levels = system.activity.levels();
+        let rows = levels.len() / ROW;
+        let cell_width = MAP_WIDTH / ROW as f32;
+        let cell_height = MAP_HEIGHT / rows as f32;
+
+        let title_y = MAP_Y - PADDING - text::height(TEXT_SIZE);
+        rects.push(Rect::new(
+            MAP_X - PADDING,
+            title_y - PADDING,
+            MAP_WIDTH + PADDING * 2.0,
+            MAP_Y + MAP_HEIGHT + PADDING - (title_y - PADDING),
+            BACKGROUND,
+        ));
+        text::draw(&mut rects, MAP_X, title_y, TEXT_SIZE, TEXT, "MEMORY");
+
+        // font and interpreter area, below the program start
+        let reserved_rows = PROGRAM_START as usize / ROW;
+        rects.push(Rect::new(
+            MAP_X,
+            MAP_Y,
+            MAP_WIDTH,
+            cell_height * reserved_rows as f32,
+            RESERVED,
+        ));
+
+        for (addr, &[read, write, execute]) in levels.iter().enumerate() {
+            let max = read.max(write).max(execute);
+            if max == 0 {
+                continue;
+            }
+
+            let channel = |level: u8| f32::from(level) / f32::from(u8::MAX);
+            rects.push(Rect::new(
+                MAP_X + cell_width * (addr % ROW) as f32,
+                MAP_Y + cell_height * (addr / ROW) as f32,
+                cell_width,
+                cell_height,
+                [channel(write), channel(read), channel(execute), channel(max)],
+            ));
+        }
+
+        rects
+    }
+}
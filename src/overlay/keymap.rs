@@ -0,0 +1,108 @@
+use winit::VirtualKeyCode;
+
+use super::{text, Rect};
+use crate::input::{self, Keymap};
+
+/// Hex keys in the order they are laid out on the original keypad
+pub const LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, //
+    0x4, 0x5, 0x6, 0xD, //
+    0x7, 0x8, 0x9, 0xE, //
+    0xA, 0x0, 0xB, 0xF, //
+];
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 0.9];
+const CELL: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+const SELECTED: [f32; 4] = [0.2, 0.45, 0.8, 1.0];
+const WAITING: [f32; 4] = [0.85, 0.55, 0.1, 1.0];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const HINT: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+
+const GRID_X: f32 = 0.1;
+const GRID_Y: f32 = 0.16;
+const GRID_WIDTH: f32 = 0.8;
+const GRID_HEIGHT: f32 = 0.7;
+const CELL_GAP: f32 = 0.01;
+
+pub enum EditorAction {
+    None,
+    Close,
+    /// the keymap was changed
+    Bound,
+}
+
+/// Screen for rebinding the hex keypad: arrows select a cell, enter waits for the physical key
+/// to bind to it
+#[derive(Default)]
+pub struct KeymapEditor {
+    selected: usize,
+    waiting: bool,
+}
+
+impl KeymapEditor {
+    pub fn handle_key(&mut self, keycode: VirtualKeyCode, keymap: &mut Keymap) -> EditorAction {
+        if self.waiting {
+            self.waiting = false;
+            if keycode != VirtualKeyCode::Escape {
+                keymap.bind(LAYOUT[self.selected], keycode);
+                return EditorAction::Bound;
+            }
+            return EditorAction::None;
+        }
+
+        let (col, row) = (self.selected % 4, self.selected / 4);
+        match keycode {
+            VirtualKeyCode::Left => self.selected = row * 4 + (col + 3) % 4,
+            VirtualKeyCode::Right => self.selected = row * 4 + (col + 1) % 4,
+            VirtualKeyCode::Up => self.selected = (row + 3) % 4 * 4 + col,
+            VirtualKeyCode::Down => self.selected = (row + 1) % 4 * 4 + col,
+            VirtualKeyCode::Return => self.waiting = true,
+            VirtualKeyCode::Escape | VirtualKeyCode::F3 => return EditorAction::Close,
+            _ => {}
+        }
+
+        EditorAction::None
+    }
+
+    pub fn rects(&self, keymap: &Keymap) -> Vec<Rect> {
+        let mut rects = vec![Rect::new(0.0, 0.0, 1.0, 1.0, BACKGROUND)];
+
+        text::draw(&mut rects, GRID_X, 0.05, 0.008, TEXT, "KEYMAP");
+
+        let cell_width = GRID_WIDTH / 4.0;
+        let cell_height = GRID_HEIGHT / 4.0;
+        for (i, key) in LAYOUT.iter().enumerate() {
+            let x = GRID_X + (i % 4) as f32 * cell_width;
+            let y = GRID_Y + (i / 4) as f32 * cell_height;
+
+            let color = match (i == self.selected, self.waiting) {
+                (true, true) => WAITING,
+                (true, false) => SELECTED,
+                _ => CELL,
+            };
+            rects.push(Rect::new(
+                x,
+                y,
+                cell_width - CELL_GAP,
+                cell_height - CELL_GAP,
+                color,
+            ));
+
+            let pad = 0.01;
+            text::draw(&mut rects, x + pad, y + pad, 0.012, TEXT, &format!("{:X}", key));
+
+            let name = input::key_name(keymap.get(*key));
+            let name_y = y + cell_height - CELL_GAP - pad - text::height(0.004);
+            text::draw(&mut rects, x + pad, name_y, 0.004, TEXT, &name);
+        }
+
+        let hint = if self.waiting {
+            "PRESS A KEY TO BIND, ESC TO CANCEL"
+        } else {
+            "ARROWS SELECT  ENTER BIND  ESC CLOSE"
+        };
+        text::draw(&mut rects, GRID_X, 0.9, 0.004, HINT, hint);
+
+        rects
+    }
+}
@@ -0,0 +1,70 @@
+use super::keymap::LAYOUT;
+use super::{text, Rect};
+use crate::input::{self, Keymap};
+use crate::system::Keys;
+
+const CELL_WIDTH: f32 = 0.06;
+const CELL_HEIGHT: f32 = 0.1;
+const CELL_GAP: f32 = 0.005;
+const PADDING: f32 = 0.01;
+const RIGHT: f32 = 0.98;
+const BOTTOM: f32 = 0.97;
+const DIGIT_SIZE: f32 = 0.005;
+const NAME_SIZE: f32 = 0.002;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 0.8];
+const RELEASED: [f32; 4] = [0.25, 0.25, 0.25, 1.0];
+const PRESSED: [f32; 4] = [0.9, 0.75, 0.2, 1.0];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const PRESSED_TEXT: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// The 4x4 keypad in the corner of the window with the keys the game sees as pressed lit, and
+/// the physical key bound to each one
+#[derive(Default)]
+pub struct Keypad {
+    pub enabled: bool,
+}
+
+impl Keypad {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    pub fn rects(&self, keys: &Keys, keymap: &Keymap) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        if !self.enabled {
+            return rects;
+        }
+
+        let left = RIGHT - CELL_WIDTH * 4.0 - PADDING;
+        let top = BOTTOM - CELL_HEIGHT * 4.0 - PADDING;
+        rects.push(Rect::new(
+            left - PADDING,
+            top - PADDING,
+            CELL_WIDTH * 4.0 + PADDING * 2.0,
+            CELL_HEIGHT * 4.0 + PADDING * 2.0,
+            BACKGROUND,
+        ));
+
+        for (i, &key) in LAYOUT.iter().enumerate() {
+            let x = left + (i % 4) as f32 * CELL_WIDTH;
+            let y = top + (i / 4) as f32 * CELL_HEIGHT;
+            let pressed = keys.pressed(key).unwrap_or(false);
+            let (color, text_color) = if pressed { (PRESSED, PRESSED_TEXT) } else { (RELEASED, TEXT) };
+            rects.push(Rect::new(x, y, CELL_WIDTH - CELL_GAP, CELL_HEIGHT - CELL_GAP, color));
+
+            let pad = 0.005;
+            text::draw(&mut rects, x + pad, y + pad, DIGIT_SIZE, text_color, &format!("{:X}", key));
+
+            // long names are cut to the width of the cell
+            let mut name = input::key_name(keymap.get(key));
+            while text::width(&name, NAME_SIZE) > CELL_WIDTH - CELL_GAP - pad * 2.0 {
+                name.pop();
+            }
+            let name_y = y + CELL_HEIGHT - CELL_GAP - pad - text::height(NAME_SIZE);
+            text::draw(&mut rects, x + pad, name_y, NAME_SIZE, text_color, &name);
+        }
+
+        rects
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::VecDeque;
+
+use super::{text, Rect, ASPECT};
+
+/// frames a notification stays visible, about two seconds
+const SHOW_FRAMES: u32 = 120;
+/// frames at the end it spends fading out
+const FADE_FRAMES: u32 = 30;
+/// shown at once, the oldest go first
+const MAX_SHOWN: usize = 4;
+
+const X: f32 = 0.02;
+const Y: f32 = 0.03;
+const PADDING: f32 = 0.01;
+const TEXT_SIZE: f32 = 0.004;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 0.8];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+struct Notification {
+    /// a newer one on the same topic replaces it, e.g. "turbo" for turbo on and off
+    topic: &'static str,
+    message: String,
+    frames: u32,
+}
+
+/// Short messages in the top left corner confirming what a hotkey did, newest at the bottom
+#[derive(Default)]
+pub struct Notifications {
+    shown: VecDeque<Notification>,
+}
+
+impl Notifications {
+    pub fn show(&mut self, topic: &'static str, message: impl Into<String>) {
+        self.shown.retain(|notification| notification.topic != topic);
+        self.shown.push_back(Notification {
+            topic,
+            message: message.into().to_uppercase(),
+            frames: SHOW_FRAMES,
+        });
+        while self.shown.len() > MAX_SHOWN {
+            self.shown.pop_front();
+        }
+    }
+
+    /// counts down the time each one stays visible, called once per frame
+    pub fn update(&mut self) {
+        for notification in &mut self.shown {
+            notification.frames = notification.frames.saturating_sub(1);
+        }
+        self.shown.retain(|notification| notification.frames > 0);
+    }
+
+    pub fn rects(&self) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        let line = text::height(TEXT_SIZE) + PADDING * ASPECT * 2.0;
+        for (i, notification) in self.shown.iter().enumerate() {
+            let alpha = (notification.frames as f32 / FADE_FRAMES as f32).min(1.0);
+            let fade = |color: [f32; 4]| [color[0], color[1], color[2], color[3] * alpha];
+            let y = Y + i as f32 * (line + PADDING);
+            let width = text::width(&notification.message, TEXT_SIZE) + PADDING * 2.0;
+
+            rects.push(Rect::new(X, y, width, line, fade(BACKGROUND)));
+            text::draw(
+                &mut rects,
+                X + PADDING,
+                y + PADDING * ASPECT,
+                TEXT_SIZE,
+                fade(TEXT),
+                &notification.message,
+            );
+        }
+        rects
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::{text, Rect, ASPECT};
+
+/// presented frames shown, four seconds at 60Hz
+const HISTORY: usize = 240;
+
+const GRAPH_X: f32 = 0.02;
+const GRAPH_WIDTH: f32 = 0.4;
+const GRAPH_HEIGHT: f32 = 0.12;
+const BOTTOM: f32 = 0.97;
+const PADDING: f32 = 0.01;
+const TEXT_SIZE: f32 = 0.003;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 0.8];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const OPS: [f32; 4] = [0.3, 0.8, 0.4, 1.0];
+const FRAME: [f32; 4] = [0.3, 0.6, 0.9, 1.0];
+/// frames that took over 1.5 times as long as they should
+const SLOW_FRAME: [f32; 4] = [0.9, 0.3, 0.2, 1.0];
+const TARGET: [f32; 4] = [1.0, 1.0, 1.0, 0.5];
+
+#[derive(Clone, Copy)]
+struct Sample {
+    instructions: u32,
+    frame_time: Duration,
+}
+
+/// Rolling graphs of the instructions executed per presented frame and of the time between
+/// frames, to spot pacing problems
+#[derive(Default)]
+pub struct PerfGraph {
+    pub enabled: bool,
+    samples: VecDeque<Sample>,
+    /// executed since the last frame
+    instructions: u32,
+    last_frame: Option<Duration>,
+    /// the time a frame should take, drawn as a line
+    target: Duration,
+}
+
+impl PerfGraph {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// Starts over for frames presented at `refresh_hz`
+    pub fn reset(&mut self, refresh_hz: f64) {
+        self.samples.clear();
+        self.instructions = 0;
+        self.last_frame = None;
+        self.target = crate::timing::period_of(refresh_hz);
+    }
+
+    pub fn instructions(&mut self, count: u32) {
+        self.instructions += count;
+    }
+
+    /// Records a frame presented at `now`, by the clock of the run loop
+    pub fn frame(&mut self, now: Duration) {
+        if let Some(last) = self.last_frame {
+            if self.samples.len() == HISTORY {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(Sample {
+                instructions: self.instructions,
+                frame_time: now.saturating_sub(last),
+            });
+        }
+        self.last_frame = Some(now);
+        self.instructions = 0;
+    }
+
+    /// `overlay_cost` is how long the overlays took to build in the last frame
+    pub fn rects(&self, overlay_cost: Duration) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        if !self.enabled {
+            return rects;
+        }
+
+        let label_height = text::height(TEXT_SIZE);
+        let section = label_height + PADDING + GRAPH_HEIGHT;
+        let top = BOTTOM - section * 2.0 - PADDING * 3.0;
+        rects.push(Rect::new(
+            GRAPH_X - PADDING,
+            top - PADDING,
+            GRAPH_WIDTH + PADDING * 2.0,
+            BOTTOM - top + PADDING,
+            BACKGROUND,
+        ));
+
+        let last = self.samples.back().copied().unwrap_or(Sample {
+            instructions: 0,
+            frame_time: Duration::from_secs(0),
+        });
+        let bar = GRAPH_WIDTH / HISTORY as f32;
+        let start = GRAPH_X + bar * (HISTORY - self.samples.len()) as f32;
+
+        let ops_y = top;
+        text::draw(&mut rects, GRAPH_X, ops_y, TEXT_SIZE, TEXT, &format!("OPS/FRAME {}", last.instructions));
+        let max_ops = self.samples.iter().map(|sample| sample.instructions).max().unwrap_or(0).max(1);
+        let graph_y = ops_y + label_height + PADDING;
+        for (i, sample) in self.samples.iter().enumerate() {
+            let height = GRAPH_HEIGHT * sample.instructions as f32 / max_ops as f32;
+            rects.push(Rect::new(start + bar * i as f32, graph_y + GRAPH_HEIGHT - height, bar, height, OPS));
+        }
+
+        let frame_y = graph_y + GRAPH_HEIGHT + PADDING * 2.0;
+        let millis = last.frame_time.as_secs_f64() * 1000.0;
+        let overlay_millis = overlay_cost.as_secs_f64() * 1000.0;
+        let label = format!("FRAME {:.1} MS  OVERLAYS {:.2} MS", millis, overlay_millis);
+        text::draw(&mut rects, GRAPH_X, frame_y, TEXT_SIZE, TEXT, &label);
+        // twice the target fits, longer frames are cut off
+        let max_time = self.target.as_secs_f32() * 2.0;
+        let graph_y = frame_y + label_height + PADDING;
+        for (i, sample) in self.samples.iter().enumerate() {
+            let time = sample.frame_time.as_secs_f32();
+            let height = GRAPH_HEIGHT * (time / max_time).min(1.0);
+            let color = if time > self.target.as_secs_f32() * 1.5 { SLOW_FRAME } else { FRAME };
+            rects.push(Rect::new(start + bar * i as f32, graph_y + GRAPH_HEIGHT - height, bar, height, color));
+        }
+        let line = PADDING / ASPECT / 2.0;
+        rects.push(Rect::new(GRAPH_X, graph_y + GRAPH_HEIGHT / 2.0 - line / 2.0, GRAPH_WIDTH, line, TARGET));
+
+        rects
+    }
+}
@@ -0,0 +1,57 @@
+use super::{text, Rect, ASPECT};
+use crate::system::System;
+
+const TEXT_SIZE: f32 = 0.0035;
+const LINE: f32 = TEXT_SIZE * ASPECT * (text::GLYPH_HEIGHT as f32 + 2.0);
+const PADDING: f32 = 0.01;
+/// between the cursor and the tooltip
+const OFFSET: f32 = 0.02;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 0.9];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// What is known about the pixel (`x`, `y`) of the screen: its place in the framebuffer and
+/// the sprite byte that last drew it
+pub fn lines(system: &System, x: u8, y: u8) -> Vec<String> {
+    let pixel = usize::from(y) * 64 + usize::from(x);
+    let (byte, bit) = (pixel / 8, 7 - pixel % 8);
+    let on = system.screen[byte] >> bit & 1 != 0;
+
+    let mut lines = vec![
+        format!("X {} Y {} {}", x, y, if on { "ON" } else { "OFF" }),
+        format!("SCREEN BYTE {:02X} BIT {}", byte, bit),
+    ];
+    match system.draws.at(x, y) {
+        Some(sprite) => {
+            lines.push(format!("SPRITE {:03X} BIT {}", sprite.addr, sprite.bit));
+            lines.push(format!("DRAWN AT {:03X}", sprite.pc));
+        }
+        None => lines.push("NOT DRAWN".to_string()),
+    }
+    lines
+}
+
+/// `lines` for the pixel at (`x`, `y`) in a box next to the cursor at `position` in window
+/// space, kept inside the window
+pub fn tooltip(system: &System, (x, y): (u32, u32), position: [f32; 2]) -> Vec<Rect> {
+    let lines = lines(system, x.min(63) as u8, y.min(31) as u8);
+    let width = lines.iter().map(|line| text::width(line, TEXT_SIZE)).fold(0.0, f32::max) + PADDING * 2.0;
+    let height = lines.len() as f32 * LINE + PADDING * ASPECT;
+
+    let mut left = position[0] + OFFSET;
+    if left + width > 1.0 {
+        left = position[0] - OFFSET - width;
+    }
+    let mut top = position[1] + OFFSET * ASPECT;
+    if top + height > 1.0 {
+        top = position[1] - OFFSET * ASPECT - height;
+    }
+    let (left, top) = (left.max(0.0), top.max(0.0));
+
+    let mut rects = vec![Rect::new(left, top, width, height, BACKGROUND)];
+    for (row, line) in lines.iter().enumerate() {
+        let y = top + PADDING * ASPECT + row as f32 * LINE;
+        text::draw(&mut rects, left + PADDING, y, TEXT_SIZE, TEXT, line);
+    }
+    rects
+}
@@ -0,0 +1,150 @@
+use super::{text, Rect, ASPECT};
+use crate::storage::{Kind, Key, Storage};
+use crate::system::System;
+
+/// save state slots per rom
+pub const SLOTS: usize = 10;
+
+/// frames the browser stays visible after a slot key, about two seconds
+const SHOW_FRAMES: u32 = 120;
+
+const COLUMNS: usize = 5;
+const THUMB_WIDTH: f32 = 0.16;
+const THUMB_HEIGHT: f32 = THUMB_WIDTH * ASPECT / 2.0;
+const GAP: f32 = 0.02;
+const PADDING: f32 = 0.01;
+const TEXT_SIZE: f32 = 0.003;
+const GRID_X: f32 = (1.0 - (THUMB_WIDTH * COLUMNS as f32 + GAP * (COLUMNS - 1) as f32)) / 2.0;
+const GRID_Y: f32 = 0.2;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.05, 0.9];
+const THUMB: [f32; 4] = [0.15, 0.15, 0.15, 1.0];
+const PIXEL: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const SELECTED: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// The selected save state slot of the rom and the screens saved in every slot
+pub struct SlotBrowser {
+    pub selected: usize,
+    /// screens of the saved states, one byte per pixel as `System::screen` returns them
+    thumbnails: Vec<Option<Vec<u8>>>,
+    visible_frames: u32,
+}
+
+impl Default for SlotBrowser {
+    fn default() -> Self {
+        SlotBrowser {
+            selected: 0,
+            thumbnails: vec![None; SLOTS],
+            visible_frames: 0,
+        }
+    }
+}
+
+impl SlotBrowser {
+    /// Reads the thumbnails of the rom's slots, unreadable states show as empty
+    pub fn refresh(&mut self, storage: &impl Storage, rom: u64) {
+        for (slot, thumbnail) in self.thumbnails.iter_mut().enumerate() {
+            let state = storage.get(Key::new(rom, slot as u64, Kind::State)).ok().flatten();
+            *thumbnail = state.and_then(|state| {
+                let mut system = System::default();
+                system.load_state(&state).ok()?;
+                Some(system.screen())
+            });
+        }
+    }
+
+    /// Selects the slot `offset` away from the current one, wrapping around
+    pub fn select(&mut self, offset: isize) {
+        self.selected = (self.selected as isize + offset).rem_euclid(SLOTS as isize) as usize;
+        self.show();
+    }
+
+    /// Records the screen saved to the selected slot
+    pub fn saved(&mut self, screen: Vec<u8>) {
+        self.thumbnails[self.selected] = Some(screen);
+        self.show();
+    }
+
+    pub fn show(&mut self) {
+        self.visible_frames = SHOW_FRAMES;
+    }
+
+    /// counts down the time the browser stays visible, called once per frame
+    pub fn update(&mut self) {
+        self.visible_frames = self.visible_frames.saturating_sub(1);
+    }
+
+    pub fn rects(&self) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        if self.visible_frames == 0 {
+            return rects;
+        }
+
+        let rows = SLOTS.div_ceil(COLUMNS);
+        let label_height = text::height(TEXT_SIZE) + PADDING;
+        let cell_height = THUMB_HEIGHT + label_height + GAP;
+        rects.push(Rect::new(
+            GRID_X - GAP,
+            GRID_Y - GAP,
+            THUMB_WIDTH * COLUMNS as f32 + GAP * (COLUMNS + 1) as f32,
+            cell_height * rows as f32 + GAP,
+            BACKGROUND,
+        ));
+
+        for (slot, thumbnail) in self.thumbnails.iter().enumerate() {
+            let x = GRID_X + (THUMB_WIDTH + GAP) * (slot % COLUMNS) as f32;
+            let y = GRID_Y + cell_height * (slot / COLUMNS) as f32;
+
+            if slot == self.selected {
+                rects.push(Rect::new(
+                    x - PADDING / 2.0,
+                    y - PADDING / 2.0 * ASPECT,
+                    THUMB_WIDTH + PADDING,
+                    THUMB_HEIGHT + PADDING * ASPECT,
+                    SELECTED,
+                ));
+            }
+            rects.push(Rect::new(x, y, THUMB_WIDTH, THUMB_HEIGHT, THUMB));
+            if let Some(screen) = thumbnail {
+                thumbnail_pixels(&mut rects, screen, x, y);
+            }
+
+            let label = match thumbnail {
+                Some(_) => format!("SLOT {}", slot),
+                None => format!("SLOT {} EMPTY", slot),
+            };
+            text::draw(&mut rects, x, y + THUMB_HEIGHT + PADDING, TEXT_SIZE, TEXT, &label);
+        }
+
+        rects
+    }
+}
+
+/// lit pixels of a 64x32 screen, merged into one rect per horizontal run
+fn thumbnail_pixels(rects: &mut Vec<Rect>, screen: &[u8], x: f32, y: f32) {
+    let pixel_width = THUMB_WIDTH / 64.0;
+    let pixel_height = THUMB_HEIGHT / 32.0;
+
+    for (row, pixels) in screen.chunks(64).enumerate() {
+        let mut column = 0;
+        while column < pixels.len() {
+            if pixels[column] == 0 {
+                column += 1;
+                continue;
+            }
+
+            let start = column;
+            while column < pixels.len() && pixels[column] != 0 {
+                column += 1;
+            }
+            rects.push(Rect::new(
+                x + pixel_width * start as f32,
+                y + pixel_height * row as f32,
+                pixel_width * (column - start) as f32,
+                pixel_height,
+                PIXEL,
+            ));
+        }
+    }
+}
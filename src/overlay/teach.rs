@@ -0,0 +1,168 @@
+use super::{text, Rect, ASPECT};
+use crate::disasm;
+use crate::system::opcode::{Instruction, Opcode};
+use crate::system::System;
+
+/// instructions per second of `--teach` without a rate
+pub const DEFAULT_HZ: f64 = 2.0;
+
+const TEXT_SIZE: f32 = 0.0035;
+const LINE: f32 = TEXT_SIZE * ASPECT * (text::GLYPH_HEIGHT as f32 + 2.0);
+const X: f32 = 0.02;
+const LINES: usize = 6;
+const TOP: f32 = 1.0 - LINES as f32 * LINE - 0.02;
+/// width of a register in the register rows, "V0 00" and a space
+const REGISTER_WIDTH: f32 = 6.0 * (text::GLYPH_WIDTH as f32 + 1.0) * TEXT_SIZE;
+
+const BACKGROUND: [f32; 4] = [0.05, 0.05, 0.08, 0.85];
+const HEADING: [f32; 4] = [0.5, 0.7, 1.0, 1.0];
+const TEXT: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const DIM: [f32; 4] = [0.5, 0.5, 0.5, 1.0];
+const TOUCHED: [f32; 4] = [1.0, 0.8, 0.2, 1.0];
+
+/// `--teach`: runs the program a few instructions per second, or one per press of Space,
+/// and explains the last instruction in a panel at the bottom of the window
+pub struct Teacher {
+    /// instructions per second, 0 to only step on Space
+    hz: f64,
+    /// presses of Space not run yet
+    requested: u32,
+    /// address and word of the last instruction run
+    last: Option<(u16, u16)>,
+    /// V0 to VF and I before the last instruction
+    before: ([u8; 16], u16),
+    /// bit n for Vn, bit 16 for I: what the last instruction read or wrote
+    touched: u32,
+}
+
+impl Teacher {
+    pub fn new(hz: f64) -> Self {
+        Teacher {
+            hz,
+            requested: 0,
+            last: None,
+            before: ([0; 16], 0),
+            touched: 0,
+        }
+    }
+
+    /// The rate for the clock, which needs one even when only Space steps
+    pub fn cpu_hz(&self) -> f64 {
+        self.hz.max(1.0)
+    }
+
+    pub fn request_step(&mut self) {
+        self.requested += 1;
+    }
+
+    /// How many of the `due` instructions to run, with the steps asked for on top
+    pub fn instructions(&mut self, due: u32) -> u32 {
+        let auto = if self.hz > 0.0 { due } else { 0 };
+        auto + std::mem::take(&mut self.requested)
+    }
+
+    /// Called right before each instruction
+    pub fn before(&mut self, system: &System) {
+        self.before = (system.registers.reg, system.registers.index);
+        self.last = system.fetch_instruction().ok().map(|word| (system.registers.pc, word));
+    }
+
+    /// Called right after each instruction, finds the registers it touched: those named in its
+    /// description and those that changed
+    pub fn after(&mut self, system: &System) {
+        let (registers, index) = self.before;
+        self.touched = self.last.map_or(0, |(_, word)| named_registers(word));
+        for (i, (old, new)) in registers.iter().zip(&system.registers.reg).enumerate() {
+            if old != new {
+                self.touched |= 1 << i;
+            }
+        }
+        if index != system.registers.index {
+            self.touched |= 1 << 16;
+        }
+    }
+
+    pub fn rects(&self, system: &System) -> Vec<Rect> {
+        let mut rects = vec![Rect::new(0.0, TOP - 0.01, 1.0, 1.0 - TOP + 0.01, BACKGROUND)];
+        let line = |rects: &mut Vec<Rect>, x: f32, row: usize, color: [f32; 4], line: &str| {
+            text::draw(rects, x, TOP + row as f32 * LINE, TEXT_SIZE, color, line);
+        };
+
+        let rate = if self.hz > 0.0 { format!("{} PER SECOND", self.hz) } else { "SPACE TO STEP".to_string() };
+        line(&mut rects, X, 0, HEADING, &format!("TEACH  {}", rate));
+
+        // the next instruction until one has run
+        let (pc, word, touched) = match self.last {
+            Some((pc, word)) => (pc, word, self.touched),
+            None => (system.registers.pc, system.fetch_instruction().unwrap_or(0), 0),
+        };
+        let instruction = Instruction::decode(word);
+        line(&mut rects, X, 1, TEXT, &format!("{:03X}  {:04X}  {}", pc, word, disasm::format(instruction)));
+        if let Ok(instruction) = instruction {
+            line(&mut rects, X, 2, TEXT, &disasm::explain(instruction.info, word));
+            if let Some(quirk) = instruction.info.quirk {
+                line(&mut rects, X, 3, DIM, &format!("quirk: {}", quirk));
+            }
+        }
+
+        let registers = &system.registers;
+        for i in 0..16 {
+            let color = if touched & 1 << i != 0 { TOUCHED } else { TEXT };
+            let x = X + (i % 8) as f32 * REGISTER_WIDTH;
+            line(&mut rects, x, 4 + i / 8, color, &format!("V{:X} {:02X}", i, registers.reg[i]));
+        }
+        let color = if touched & 1 << 16 != 0 { TOUCHED } else { TEXT };
+        line(&mut rects, X + 8.0 * REGISTER_WIDTH, 4, color, &format!("I {:03X}", registers.index));
+        rects
+    }
+}
+
+/// The registers the description of `word` names, with V0 to VX for the register dumps and
+/// loads
+fn named_registers(word: u16) -> u32 {
+    let instruction = match Instruction::decode(word) {
+        Ok(instruction) => instruction,
+        Err(_) => return 0,
+    };
+    let x = (word >> 8) & 0xF;
+    let mut touched = 0;
+    for name in instruction.info.description.split(|c: char| !c.is_ascii_alphanumeric()) {
+        touched |= match name {
+            "VX" => 1 << x,
+            "VY" => 1 << ((word >> 4) & 0xF),
+            "VF" => 1 << 0xF,
+            "V0" => 1,
+            "I" => 1 << 16,
+            _ => 0,
+        };
+    }
+    if matches!(instruction.info.opcode, Opcode::RegDump | Opcode::RegLoad) {
+        touched |= (2 << x) - 1;
+    }
+    touched
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_and_highlights_the_operands() {
+        let add = Instruction::decode(0x8124).unwrap();
+        assert_eq!(disasm::explain(add.info, 0x8124), "V1 += V2, VF = carry");
+        assert_eq!(named_registers(0x8124), 1 << 1 | 1 << 2 | 1 << 0xF);
+        // LD [I], V2
+        assert_eq!(named_registers(0xF255), 0b111 | 1 << 16);
+
+        let mut system = System::default();
+        system.load(&[0x63, 0x05][..]).unwrap();
+        let mut teacher = Teacher::new(0.0);
+        assert_eq!(teacher.instructions(10), 0);
+        teacher.request_step();
+        assert_eq!(teacher.instructions(10), 1);
+        teacher.before(&system);
+        system.tick(&mut crate::system::debug::Debugger::disabled()).unwrap();
+        teacher.after(&system);
+        assert_eq!(teacher.touched, 1 << 3);
+    }
+}
@@ -0,0 +1,10 @@
+#version 150
+
+in vec2 pos;
+in vec4 color;
+out vec4 v_color;
+
+void main() {
+    gl_Position = vec4(pos.x * 2.0 - 1.0, 1.0 - pos.y * 2.0, 0, 1);
+    v_color = color;
+}
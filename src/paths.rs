@@ -0,0 +1,119 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+use crate::storage;
+
+/// name of the directory in each of the platform's directories
+const APP_DIR: &str = "chip8";
+
+/// Where the emulator keeps its files. A config file in the working directory or given with
+/// `--config` keeps everything next to it, as a portable install. Otherwise they go to the
+/// platform's directories: `$XDG_CONFIG_HOME`, `$XDG_DATA_HOME` and `$XDG_CACHE_HOME` on Linux
+/// (`~/.config`, `~/.local/share` and `~/.cache` by default), `~/Library/Application Support`
+/// and `~/Library/Caches` on macOS, `%APPDATA%` and `%LOCALAPPDATA%` on Windows.
+/// `CHIP8_CONFIG_DIR`, `CHIP8_DATA_DIR` and `CHIP8_CACHE_DIR` override either
+#[derive(Clone, Debug, PartialEq)]
+pub struct Paths {
+    /// the config file, with the keymap and the game database
+    pub config: PathBuf,
+    /// save states, the window geometry and crash dumps
+    pub data: PathBuf,
+    /// files that can be made again
+    pub cache: PathBuf,
+}
+
+impl Paths {
+    /// The directories that go with the config file at `config`
+    pub fn for_config(config: impl Into<PathBuf>) -> Self {
+        let config = config.into();
+        let portable = Some(config.as_path()) != platform_config_file().as_deref();
+        let dir = config.parent().unwrap_or(Path::new(""));
+        let (data, cache) = if portable {
+            (dir.join(storage::DATA_DIR), dir.join(storage::DATA_DIR).join("cache"))
+        } else {
+            let data = platform_dir(Kind::Data).unwrap_or_else(|| dir.join(storage::DATA_DIR));
+            let cache = platform_dir(Kind::Cache).unwrap_or_else(|| data.join("cache"));
+            (data, cache)
+        };
+        Paths {
+            data: override_dir("CHIP8_DATA_DIR").unwrap_or(data),
+            cache: override_dir("CHIP8_CACHE_DIR").unwrap_or(cache),
+            config,
+        }
+    }
+}
+
+/// The config file used without `--config`: `chip8.toml` in the working directory if there
+/// is one, else the one in the platform's config directory
+pub fn default_config() -> PathBuf {
+    let local = PathBuf::from(config::DEFAULT_PATH);
+    if local.exists() && override_dir("CHIP8_CONFIG_DIR").is_none() {
+        return local;
+    }
+    platform_config_file().unwrap_or(local)
+}
+
+fn platform_config_file() -> Option<PathBuf> {
+    platform_dir(Kind::Config).map(|dir| dir.join(config::DEFAULT_PATH))
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Config,
+    Data,
+    Cache,
+}
+
+/// The platform's directory of `kind` for the emulator, None if the variables it is found
+/// from are not set
+fn platform_dir(kind: Kind) -> Option<PathBuf> {
+    if let (Kind::Config, Some(dir)) = (kind, override_dir("CHIP8_CONFIG_DIR")) {
+        return Some(dir);
+    }
+
+    let home = || override_dir("HOME");
+    let base = if cfg!(windows) {
+        match kind {
+            Kind::Config => override_dir("APPDATA"),
+            Kind::Data | Kind::Cache => override_dir("LOCALAPPDATA"),
+        }
+    } else if cfg!(target_os = "macos") {
+        match kind {
+            Kind::Config | Kind::Data => home().map(|home| home.join("Library/Application Support")),
+            Kind::Cache => home().map(|home| home.join("Library/Caches")),
+        }
+    } else {
+        match kind {
+            Kind::Config => override_dir("XDG_CONFIG_HOME").or_else(|| home().map(|home| home.join(".config"))),
+            Kind::Data => override_dir("XDG_DATA_HOME").or_else(|| home().map(|home| home.join(".local/share"))),
+            Kind::Cache => override_dir("XDG_CACHE_HOME").or_else(|| home().map(|home| home.join(".cache"))),
+        }
+    };
+    let dir = base?.join(APP_DIR);
+    match kind {
+        // the data is in the same directory
+        Kind::Cache if cfg!(windows) => Some(dir.join("cache")),
+        _ => Some(dir),
+    }
+}
+
+/// The directory in the variable `name`, if it is set to an absolute path. The XDG spec says
+/// relative ones are to be ignored
+fn override_dir(name: &str) -> Option<PathBuf> {
+    env::var_os(name).map(PathBuf::from).filter(|dir| dir.is_absolute())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn portable_configs_keep_data_next_to_them() {
+        let paths = Paths::for_config("/games/chip8/chip8.toml");
+        if env::var_os("CHIP8_DATA_DIR").is_none() {
+            assert_eq!(paths.data, Path::new("/games/chip8/chip8-data"));
+        }
+        assert_eq!(paths.config, Path::new("/games/chip8/chip8.toml"));
+    }
+}
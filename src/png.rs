@@ -0,0 +1,339 @@
+use failure::Error;
+
+/// A decoded image, 4 bytes per pixel
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+/// Decodes a non-interlaced PNG of any color type. 16 bit channels are cut to 8 bits, and only
+/// the transparency of palette images is kept
+pub fn decode(data: &[u8]) -> Result<Image, Error> {
+    if data.len() < 8 || data[..8] != b"\x89PNG\r\n\x1a\n"[..] {
+        bail!("not a PNG file");
+    }
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let len = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let chunk = data
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| format_err!("truncated chunk"))?;
+        match kind {
+            b"IHDR" if chunk.len() >= 13 => header = Some(chunk),
+            b"PLTE" => palette = chunk,
+            b"tRNS" => transparency = chunk,
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _ => {}
+        }
+        // length, type and CRC
+        pos += 12 + len;
+    }
+
+    let header = header.ok_or_else(|| format_err!("no IHDR chunk"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if interlace != 0 {
+        bail!("interlaced images are not supported");
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => bail!("unknown color type {}", color_type),
+    };
+    if ![1, 2, 4, 8, 16].contains(&depth) || (color_type != 0 && color_type != 3 && depth < 8) {
+        bail!("unsupported bit depth {} for color type {}", depth, color_type);
+    }
+    if width == 0 || height == 0 || u64::from(width) * u64::from(height) > 1 << 26 {
+        bail!("unsupported size {}x{}", width, height);
+    }
+
+    // zlib header, then a deflate stream
+    let raw = inflate(compressed.get(2..).unwrap_or(&[]))?;
+    let bits = usize::from(depth) * channels;
+    let stride = (width as usize * bits).div_ceil(8);
+    let pixels = unfilter(&raw, stride, height as usize, bits.div_ceil(8))?;
+
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in pixels.chunks_exact(stride) {
+        for x in 0..width as usize {
+            let sample = |channel: usize| -> u8 {
+                match depth {
+                    16 => row[(x * channels + channel) * 2],
+                    8 => row[x * channels + channel],
+                    _ => {
+                        let bit = x * usize::from(depth);
+                        let value = row[bit / 8] >> (8 - usize::from(depth) - bit % 8) & ((1 << depth) - 1);
+                        // palette indices stay as they are, gray levels are scaled to 8 bits
+                        if color_type == 3 {
+                            value
+                        } else {
+                            (u32::from(value) * 255 / ((1 << depth) - 1)) as u8
+                        }
+                    }
+                }
+            };
+            let pixel = match color_type {
+                0 => [sample(0), sample(0), sample(0), 0xFF],
+                2 => [sample(0), sample(1), sample(2), 0xFF],
+                3 => {
+                    let index = usize::from(sample(0));
+                    let color = palette
+                        .get(index * 3..index * 3 + 3)
+                        .ok_or_else(|| format_err!("palette index {} out of range", index))?;
+                    let alpha = transparency.get(index).copied().unwrap_or(0xFF);
+                    [color[0], color[1], color[2], alpha]
+                }
+                4 => [sample(0), sample(0), sample(0), sample(1)],
+                _ => [sample(0), sample(1), sample(2), sample(3)],
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+
+    Ok(Image { width, height, rgba })
+}
+
+/// Undoes the per-row filters, `bpp` is the bytes per complete pixel (at least 1)
+fn unfilter(raw: &[u8], stride: usize, height: usize, bpp: usize) -> Result<Vec<u8>, Error> {
+    if raw.len() < (stride + 1) * height {
+        bail!("image data is too short");
+    }
+
+    let mut out = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = out.split_at_mut(y * stride);
+        let prev = if y == 0 { None } else { Some(&done[(y - 1) * stride..]) };
+        let current = &mut rest[..stride];
+
+        for i in 0..stride {
+            let a = if i >= bpp { current[i - bpp] } else { 0 };
+            let b = prev.map_or(0, |prev| prev[i]);
+            let c = if i >= bpp { prev.map_or(0, |prev| prev[i - bpp]) } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((u16::from(a) + u16::from(b)) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => bail!("unknown filter type {}", filter),
+            };
+            current[i] = line[i].wrapping_add(predicted);
+        }
+    }
+
+    Ok(out)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+struct Bits<'a> {
+    data: &'a [u8],
+    /// in bits
+    pos: usize,
+}
+
+impl Bits<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, Error> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self
+                .data
+                .get(self.pos / 8)
+                .ok_or_else(|| format_err!("compressed data is truncated"))?;
+            value |= u32::from(byte >> (self.pos % 8) & 1) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+}
+
+/// Canonical Huffman code as the number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[usize::from(offsets[usize::from(len)])] = symbol as u16;
+                offsets[usize::from(len)] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(self.counts[len]);
+            if code - count < first {
+                return self
+                    .symbols
+                    .get((index + code - first) as usize)
+                    .copied()
+                    .ok_or_else(|| format_err!("invalid Huffman code"));
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("invalid Huffman code")
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163,
+    195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049,
+    3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// order the code length code lengths of a dynamic block are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompresses a raw deflate stream
+fn inflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut bits = Bits { data, pos: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.pos = bits.pos.div_ceil(8) * 8;
+                let start = bits.pos / 8;
+                let header = data
+                    .get(start..start + 4)
+                    .ok_or_else(|| format_err!("compressed data is truncated"))?;
+                let len = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                let block = data
+                    .get(start + 4..start + 4 + len)
+                    .ok_or_else(|| format_err!("compressed data is truncated"))?;
+                out.extend_from_slice(block);
+                bits.pos = (start + 4 + len) * 8;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].iter_mut().for_each(|len| *len = 8);
+                lengths[144..256].iter_mut().for_each(|len| *len = 9);
+                lengths[256..280].iter_mut().for_each(|len| *len = 7);
+                lengths[280..].iter_mut().for_each(|len| *len = 8);
+                let literals = Huffman::new(&lengths);
+                let distances = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let literal_count = bits.bits(5)? as usize + 257;
+                let distance_count = bits.bits(5)? as usize + 1;
+                let code_count = bits.bits(4)? as usize + 4;
+
+                let mut code_lengths = [0u8; 19];
+                for &i in &CODE_LENGTH_ORDER[..code_count] {
+                    code_lengths[i] = bits.bits(3)? as u8;
+                }
+                let code = Huffman::new(&code_lengths);
+
+                let mut lengths = Vec::with_capacity(literal_count + distance_count);
+                while lengths.len() < literal_count + distance_count {
+                    let (value, repeat) = match code.decode(&mut bits)? {
+                        symbol @ 0..=15 => (symbol as u8, 1),
+                        16 => {
+                            let prev = *lengths.last().ok_or_else(|| format_err!("repeat without a length"))?;
+                            (prev, 3 + bits.bits(2)?)
+                        }
+                        17 => (0, 3 + bits.bits(3)?),
+                        _ => (0, 11 + bits.bits(7)?),
+                    };
+                    lengths.extend(std::iter::repeat_n(value, repeat as usize));
+                }
+                if lengths.len() > literal_count + distance_count {
+                    bail!("code lengths overrun");
+                }
+
+                let literals = Huffman::new(&lengths[..literal_count]);
+                let distances = Huffman::new(&lengths[literal_count..]);
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => bail!("invalid block type"),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), Error> {
+    loop {
+        let symbol = usize::from(literals.decode(bits)?);
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let i = symbol - 257;
+                if i >= LENGTH_BASE.len() {
+                    bail!("invalid length code");
+                }
+                let len = usize::from(LENGTH_BASE[i]) + bits.bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+
+                let d = usize::from(distances.decode(bits)?);
+                if d >= DISTANCE_BASE.len() {
+                    bail!("invalid distance code");
+                }
+                let distance = usize::from(DISTANCE_BASE[d]) + bits.bits(u32::from(DISTANCE_EXTRA[d]))? as usize;
+                if distance > out.len() {
+                    bail!("distance past the start of the data");
+                }
+
+                // the copy may overlap what it writes
+                let start = out.len() - distance;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! The `chip8` Python module: `System` for scripting the emulator, e.g. to test roms or to
+//! feed frames to a model. Frames are bytes numpy reads without a copy:
+//! `numpy.frombuffer(system.framebuffer(), numpy.uint8).reshape(system.height, system.width)`
+
+// the code pyo3 generates for the methods converts their errors to the same type
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::system::debug::Debugger;
+use crate::system::{self, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+fn runtime_error(e: impl std::fmt::Display) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+/// A chip8 machine with the default quirks
+#[pyclass(unsendable)]
+pub struct System {
+    system: system::System,
+    debugger: Debugger,
+}
+
+#[pymethods]
+impl System {
+    /// `seed` makes the random numbers of `RND` the same on every run
+    #[new]
+    #[pyo3(signature = (seed=None))]
+    fn new(seed: Option<u64>) -> Self {
+        let mut system = system::System::default();
+        if let Some(seed) = seed {
+            system.seed(seed);
+        }
+        System {
+            system,
+            debugger: Debugger::disabled(),
+        }
+    }
+
+    /// Loads a rom and starts it from scratch
+    fn load(&mut self, rom: &[u8]) -> PyResult<()> {
+        self.system.load(rom).map_err(runtime_error)
+    }
+
+    /// Executes `count` instructions, raising on one that fails
+    #[pyo3(signature = (count=1))]
+    fn step(&mut self, count: u32) -> PyResult<()> {
+        for _ in 0..count {
+            self.system.tick(&mut self.debugger).map_err(runtime_error)?;
+        }
+        Ok(())
+    }
+
+    /// Decrements the delay and sound timers, 60 times per emulated second. Returns whether
+    /// the sound timer runs
+    fn tick_timers(&mut self) -> bool {
+        self.system.dec_timers();
+        self.system.timers.sound > 0
+    }
+
+    /// Presses or releases hex key `key`, 0 to 0xF
+    fn set_key(&mut self, key: u8, pressed: bool) -> PyResult<()> {
+        self.system
+            .process_key_event(key, pressed)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn key_pressed(&self, key: u8) -> PyResult<bool> {
+        self.system.keys.pressed(key).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// The screen, one byte per pixel row by row from the top left: 0 unlit, 255 lit
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.system.screen())
+    }
+
+    /// Everything the machine holds, for `load_state`
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.system.save_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.system.load_state(state).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[getter]
+    fn width(&self) -> u8 {
+        SCREEN_WIDTH
+    }
+
+    #[getter]
+    fn height(&self) -> u8 {
+        SCREEN_HEIGHT
+    }
+
+    /// V0 to VF
+    #[getter]
+    fn registers(&self) -> Vec<u8> {
+        self.system.registers.reg.to_vec()
+    }
+
+    #[getter]
+    fn pc(&self) -> u16 {
+        self.system.registers.pc
+    }
+
+    #[getter]
+    fn index(&self) -> u16 {
+        self.system.registers.index
+    }
+
+    /// The 4 KiB of memory
+    #[getter]
+    fn memory<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.system.mem)
+    }
+}
+
+#[pymodule]
+fn chip8(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<System>()
+}
@@ -0,0 +1,171 @@
+use failure::Error;
+use std::fmt;
+
+use crate::headless::Headless;
+use crate::rom::Builder;
+use crate::system::quirks::Quirks;
+use crate::system::System;
+
+/// instructions the rom needs to finish, with plenty to spare
+const CYCLES: u32 = 1000;
+
+/// Register each test leaves its result in, and what each result means
+const TESTS: [(&str, u8, &[&str]); 4] = [
+    ("jump with offset", 0xA, &["BNNN adds V0 (VIP)", "BXNN adds VX (SCHIP)"]),
+    ("shift", 0x8, &["shifts VX in place (SCHIP)", "shifts VY into VX (VIP)"]),
+    (
+        "load/store index",
+        0x9,
+        &["leaves I unchanged (SCHIP)", "adds X to I (CHIP-48)", "adds X + 1 to I (VIP)"],
+    ),
+    ("draw wrapping", 0xB, &["clips at the screen edge", "wraps to the other side"]),
+];
+
+/// The quirk detection rom. Each test leaves its result in a register, which the rom then
+/// shows as a row of hex digits in the order of `TESTS`, so it also works in a window
+pub fn rom() -> Vec<u8> {
+    Builder::new()
+        // BNNN jumps to NNN + V0, BXNN to XNN + VX. X is the high nibble of the target, 2 for
+        // a target this early in the rom
+        .set_reg(0, 0)
+        .set_reg(2, 2)
+        .set_reg(0xA, 0)
+        .op_to(0xB000, "jump_target")
+        .label("jump_target")
+        .jump("jump_done")
+        .set_reg(0xA, 1)
+        .label("jump_done")
+        // 8XY6 with VX = 0x10 and VY = 0x01
+        .set_reg(8, 0)
+        .set_reg(0, 0x10)
+        .set_reg(1, 0x01)
+        .alu(6, 0, 1)
+        .skip_eq(0, 0x08)
+        .set_reg(8, 1)
+        // store V0 and V1 and read back V0 from where I points afterwards
+        .set_reg(0, 0xA0)
+        .set_reg(1, 0xA1)
+        .set_index("scratch")
+        .op(0xF155) // LD [I], V1
+        .op(0xF065) // LD V0, [I]
+        .set_reg(9, 0)
+        .skip_neq(0, 0xA1)
+        .set_reg(9, 1)
+        .skip_neq(0, 0xB2)
+        .set_reg(9, 2)
+        // a row of 8 pixels from x = 60 and one pixel at x = 0 collide if the row wraps
+        .op(0x00E0) // CLS
+        .set_reg(0, 60)
+        .set_reg(1, 0)
+        .set_index("row")
+        .op(0xD011) // DRW V0, V1, 1
+        .set_reg(0, 0)
+        .set_index("dot")
+        .op(0xD011)
+        .alu(0, 0xB, 0xF)
+        .op(0x00E0)
+        // the results as digits
+        .set_reg(1, 12)
+        .set_reg(0, 8)
+        .op(0xFA29) // LD F, VA
+        .op(0xD015) // DRW V0, V1, 5
+        .set_reg(0, 20)
+        .op(0xF829)
+        .op(0xD015)
+        .set_reg(0, 32)
+        .op(0xF929)
+        .op(0xD015)
+        .set_reg(0, 44)
+        .op(0xFB29)
+        .op(0xD015)
+        .label("end")
+        .jump("end")
+        .label("scratch")
+        .bytes(&[0x00, 0x00, 0xB2])
+        .label("row")
+        .bytes(&[0xFF])
+        .label("dot")
+        .bytes(&[0x80])
+        .build()
+        .expect("the quirk test rom has no undefined labels")
+}
+
+/// The result of every test, in the order of `TESTS`
+#[derive(Debug, PartialEq)]
+pub struct QuirkReport {
+    detected: [u8; 4],
+    /// what the emulator should do with the quirks it ran with
+    expected: [u8; 4],
+}
+
+impl QuirkReport {
+    /// Behaviors the quirks do not select are the same for every game
+    fn expected(quirks: &Quirks) -> [u8; 4] {
+        [0, 0, 0, u8::from(quirks.wrap_sprites)]
+    }
+
+    /// tests that detected something other than the quirks select
+    pub fn mismatches(&self) -> usize {
+        self.detected.iter().zip(&self.expected).filter(|(a, b)| a != b).count()
+    }
+}
+
+fn describe(results: &[&str], result: u8) -> String {
+    match results.get(usize::from(result)) {
+        Some(description) => description.to_string(),
+        None => format!("unexpected result {}", result),
+    }
+}
+
+impl fmt::Display for QuirkReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ((name, _, results), (&detected, &expected)) in TESTS.iter().zip(self.detected.iter().zip(&self.expected)) {
+            write!(f, "{:<18} {}", name, describe(results, detected))?;
+            if detected != expected {
+                write!(f, ", expected: {}", describe(results, expected))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the quirk detection rom headlessly with `quirks`
+pub fn run(quirks: Quirks) -> Result<QuirkReport, Error> {
+    let mut system = System::default();
+    system.quirks = quirks;
+    system.load(&rom()[..])?;
+
+    let mut headless = Headless::new(system);
+    for _ in 0..CYCLES {
+        headless.step()?;
+    }
+
+    let registers = &headless.system.registers;
+    let mut detected = [0; 4];
+    for ((_, reg, _), result) in TESTS.iter().zip(&mut detected) {
+        *result = registers.read(*reg)?;
+    }
+
+    Ok(QuirkReport {
+        detected,
+        expected: QuirkReport::expected(&quirks),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_quirks() {
+        for wrap_sprites in [false, true] {
+            let report = run(Quirks {
+                wrap_sprites,
+                ..Quirks::default()
+            })
+            .unwrap();
+            assert_eq!(report.mismatches(), 0, "{}", report);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::system::quirks::{self, Quirks};
+
+/// file the ratings are kept in, next to the config file
+pub const FILE_NAME: &str = "chip8-ratings.toml";
+/// version of the JSON files `export` writes, `import` refuses newer ones
+const EXPORT_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Works,
+    MinorIssues,
+    Broken,
+}
+
+impl Status {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "works" => Some(Status::Works),
+            "minor-issues" => Some(Status::MinorIssues),
+            "broken" => Some(Status::Broken),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Status::Works => "works",
+            Status::MinorIssues => "minor issues",
+            Status::Broken => "broken",
+        })
+    }
+}
+
+/// How well a rom runs with the quirks it was tried with
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rating {
+    pub status: Status,
+    /// name from the game database, for people without the same entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+    /// the profile of `quirks::PROFILES` the quirks match, `custom` if none
+    pub profile: String,
+    pub quirks: Quirks,
+}
+
+impl Rating {
+    pub fn new(status: Status, name: Option<String>, notes: String, quirks: Quirks) -> Self {
+        let profile = quirks::PROFILES
+            .iter()
+            .find(|profile| quirks.with_profile(profile) == Some(quirks))
+            .map_or("custom", |profile| profile);
+        Rating {
+            status,
+            name,
+            notes,
+            profile: profile.to_string(),
+            quirks,
+        }
+    }
+}
+
+impl fmt::Display for Rating {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} with {} quirks", self.status, self.profile)?;
+        if !self.notes.is_empty() {
+            write!(f, ": {}", self.notes)?;
+        }
+        Ok(())
+    }
+}
+
+/// What `export` writes and `import` reads
+#[derive(Serialize, Deserialize)]
+struct Export {
+    version: u32,
+    /// keyed by the hex `rom::hash` like the game database
+    ratings: BTreeMap<String, Rating>,
+}
+
+/// The ratings given with `chip8 rate`, and those imported from others
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ratings {
+    /// keyed by the hex `rom::hash` like the game database
+    own: BTreeMap<String, Rating>,
+    imported: BTreeMap<String, Vec<Rating>>,
+}
+
+impl Ratings {
+    /// Loads the ratings, starting with none if the file does not exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(src) => Ok(toml::from_str(&src)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Ratings::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Sets the rating of the rom, replacing an earlier one
+    pub fn rate(&mut self, rom_hash: u64, rating: Rating) {
+        self.own.insert(format!("{:016x}", rom_hash), rating);
+    }
+
+    pub fn own(&self, rom_hash: u64) -> Option<&Rating> {
+        self.own.get(&format!("{:016x}", rom_hash))
+    }
+
+    pub fn imported(&self, rom_hash: u64) -> &[Rating] {
+        self.imported.get(&format!("{:016x}", rom_hash)).map_or(&[], Vec::as_slice)
+    }
+
+    /// The own ratings as JSON to share
+    pub fn export(&self) -> Result<String, Error> {
+        let export = Export {
+            version: EXPORT_VERSION,
+            ratings: self.own.clone(),
+        };
+        Ok(serde_json::to_string_pretty(&export)? + "\n")
+    }
+
+    /// Adds the ratings of an `export` to the imported ones, skipping those already there.
+    /// Returns how many were added
+    pub fn import(&mut self, json: &str) -> Result<usize, Error> {
+        let export: Export = serde_json::from_str(json)?;
+        if export.version > EXPORT_VERSION {
+            bail!(
+                "The ratings are in version {} of the format, this build reads up to version {}",
+                export.version,
+                EXPORT_VERSION
+            );
+        }
+        let mut added = 0;
+        for (hash, rating) in export.ratings {
+            if u64::from_str_radix(&hash, 16).is_err() {
+                bail!("{} is not a rom hash", hash);
+            }
+            let ratings = self.imported.entry(hash).or_default();
+            if !ratings.contains(&rating) {
+                ratings.push(rating);
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_and_imports_ratings() {
+        let mut mine = Ratings::default();
+        let schip = Quirks::default().with_profile("schip").unwrap();
+        mine.rate(0x1234, Rating::new(Status::MinorIssues, None, "flickers".to_string(), schip));
+        mine.rate(0x5678, Rating::new(Status::Works, Some("Pong".to_string()), String::new(), Quirks { stack_size: 12, ..schip }));
+        assert_eq!(mine.own(0x1234).unwrap().profile, "schip");
+        assert_eq!(mine.own(0x5678).unwrap().profile, "custom");
+
+        let json = mine.export().unwrap();
+        let mut theirs = Ratings::default();
+        assert_eq!(theirs.import(&json).unwrap(), 2);
+        // importing the same file again adds nothing
+        assert_eq!(theirs.import(&json).unwrap(), 0);
+        assert_eq!(theirs.imported(0x1234), [mine.own(0x1234).unwrap().clone()]);
+        assert_eq!(theirs.imported(0x1234)[0].to_string(), "minor issues with schip quirks: flickers");
+        assert!(theirs.own(0x1234).is_none());
+
+        // and they survive the local file
+        let reloaded: Ratings = toml::from_str(&toml::to_string(&theirs).unwrap()).unwrap();
+        assert_eq!(reloaded.imported(0x5678).len(), 1);
+        assert!(theirs.import(&json.replace("\"version\": 1", "\"version\": 2")).is_err());
+    }
+}
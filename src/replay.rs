@@ -0,0 +1,248 @@
+use failure::Error;
+use std::path::Path;
+
+use crate::system::debug::Debugger;
+use crate::system::quirks::Quirks;
+use crate::system::{System, SystemError};
+
+const MAGIC: &[u8; 4] = b"C8RP";
+
+/// What happened to the machine from outside between two instructions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Press(u8),
+    Release(u8),
+    /// the delay and sound timers were decremented
+    Timer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    /// instructions executed before the event was applied
+    pub instruction: u64,
+    pub action: Action,
+}
+
+/// A run that can be repeated exactly: the state it started from and every input and timer
+/// tick against the instruction it came before. Host timing and speed settings only decide
+/// where the events fall, so a replay plays back the same whatever they are on either side
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub quirks: Quirks,
+    /// `System::save_state` after the rom was loaded
+    pub start: Vec<u8>,
+    pub events: Vec<Event>,
+    /// instructions executed in the whole run
+    pub length: u64,
+}
+
+impl Replay {
+    /// `MAGIC`, the quirks as TOML and the start state, each after its u32 length, the length
+    /// and event count as u64 and u32, then per event its instruction as u64 and one byte:
+    /// 0x0K releases key K, 0x1K presses it and 0x20 ticks the timers. Numbers are big-endian
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let quirks = toml::to_string(&self.quirks)?;
+        let mut out = MAGIC.to_vec();
+        for section in &[quirks.as_bytes(), &self.start[..]] {
+            out.extend_from_slice(&(section.len() as u32).to_be_bytes());
+            out.extend_from_slice(section);
+        }
+        out.extend_from_slice(&self.length.to_be_bytes());
+        out.extend_from_slice(&(self.events.len() as u32).to_be_bytes());
+        for event in &self.events {
+            out.extend_from_slice(&event.instruction.to_be_bytes());
+            out.push(match event.action {
+                Action::Release(key) => key,
+                Action::Press(key) => 0x10 | key,
+                Action::Timer => 0x20,
+            });
+        }
+
+        Ok(out)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        let mut reader = Reader { data };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            bail!("Not a replay");
+        }
+        let len = reader.number(4)? as usize;
+        let quirks = toml::from_str(std::str::from_utf8(reader.bytes(len)?)?)?;
+        let len = reader.number(4)? as usize;
+        let start = reader.bytes(len)?.to_vec();
+        let length = reader.number(8)?;
+        let count = reader.number(4)?;
+
+        let mut events: Vec<Event> = Vec::new();
+        for _ in 0..count {
+            let instruction = reader.number(8)?;
+            let action = match reader.number(1)? as u8 {
+                key @ 0x00..=0x0F => Action::Release(key),
+                key @ 0x10..=0x1F => Action::Press(key & 0xF),
+                0x20 => Action::Timer,
+                action => bail!("Unknown replay event {:#04X}", action),
+            };
+            if events.last().is_some_and(|last| last.instruction > instruction) || instruction > length {
+                bail!("Replay events are out of order");
+            }
+            events.push(Event { instruction, action });
+        }
+        if !reader.data.is_empty() {
+            bail!("Unexpected data after the replay events");
+        }
+
+        Ok(Replay {
+            quirks,
+            start,
+            events,
+            length,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::from_bytes(&std::fs::read(path)?).map_err(|e| format_err!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    /// Runs the replay from its start state, applying every event right before the instruction
+    /// it was recorded before. The program reaching the zero instruction ends it early, like
+    /// it ended the recorded run
+    pub fn play(&self) -> Result<System, Error> {
+        let mut system = System::default();
+        system.quirks = self.quirks;
+        system.load_state(&self.start)?;
+
+        let mut dbg = Debugger::disabled();
+        let mut executed = 0;
+        let mut events = self.events.iter().peekable();
+        loop {
+            while let Some(event) = events.next_if(|event| event.instruction == executed) {
+                match event.action {
+                    Action::Press(key) => system.process_key_event(key, true)?,
+                    Action::Release(key) => system.process_key_event(key, false)?,
+                    Action::Timer => {
+                        system.dec_timers();
+                    }
+                }
+            }
+            if executed == self.length {
+                break;
+            }
+            match system.tick(&mut dbg) {
+                Ok(()) => executed += 1,
+                Err(SystemError::ZeroInstruction) => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(system)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < len {
+            bail!("The replay is cut short");
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    /// a big-endian number of `len` bytes
+    fn number(&mut self, len: usize) -> Result<u64, Error> {
+        Ok(self.bytes(len)?.iter().fold(0, |value, &byte| value << 8 | u64::from(byte)))
+    }
+}
+
+/// Builds the replay of the rom being run, with `--record-replay`
+#[derive(Default)]
+pub struct Recorder {
+    replay: Option<Replay>,
+    /// a state was loaded, which the replay cannot repeat
+    stopped: bool,
+}
+
+impl Recorder {
+    /// Starts over from the state of a rom that was just loaded
+    pub fn start(&mut self, system: &System) {
+        self.replay = Some(Replay {
+            quirks: system.quirks,
+            start: system.save_state(),
+            events: Vec::new(),
+            length: 0,
+        });
+        self.stopped = false;
+    }
+
+    /// Keeps what was recorded so far and ignores everything after
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// counts an instruction executed, or attempted if it failed
+    pub fn instruction(&mut self) {
+        if let (Some(replay), false) = (&mut self.replay, self.stopped) {
+            replay.length += 1;
+        }
+    }
+
+    pub fn event(&mut self, action: Action) {
+        if let (Some(replay), false) = (&mut self.replay, self.stopped) {
+            replay.events.push(Event {
+                instruction: replay.length,
+                action,
+            });
+        }
+    }
+
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plays_back_the_recorded_run() {
+        // V0 = random, then count presses of key 5 in V1 while drawing V0's digit
+        let rom = [
+            0xC0, 0xFF, 0xE5, 0xA1, 0x71, 0x01, 0xF0, 0x29, 0xD0, 0x15, 0x12, 0x02,
+        ];
+        let mut system = System::default();
+        system.seed(7);
+        system.load(&rom[..]).unwrap();
+
+        let mut recorder = Recorder::default();
+        recorder.start(&system);
+        let mut dbg = Debugger::disabled();
+        for cycle in 0..500u32 {
+            if cycle % 37 == 0 {
+                let pressed = !system.keys.pressed(5).unwrap();
+                recorder.event(if pressed { Action::Press(5) } else { Action::Release(5) });
+                system.process_key_event(5, pressed).unwrap();
+            }
+            if cycle % 11 == 0 {
+                recorder.event(Action::Timer);
+                system.dec_timers();
+            }
+            system.tick(&mut dbg).unwrap();
+            recorder.instruction();
+        }
+
+        let replay = Replay::from_bytes(&recorder.replay().unwrap().to_bytes().unwrap()).unwrap();
+        assert_eq!(&replay, recorder.replay().unwrap());
+        assert_eq!(replay.play().unwrap().state_hash(), system.state_hash());
+        assert!(Replay::from_bytes(&replay.to_bytes().unwrap()[..20]).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use failure::Fail;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::system::PROGRAM_START;
+
+/// Returns the rom without trailing zero bytes.
+/// The result is kept at an even length so the last instruction is not cut in half
+pub fn trim(rom: &[u8]) -> &[u8] {
+    let len = rom.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+    let len = (len + len % 2).min(rom.len());
+
+    &rom[..len]
+}
+
+/// Returns the rom padded with a zero byte if its length is odd
+pub fn pad(rom: &[u8]) -> Vec<u8> {
+    let mut padded = rom.to_vec();
+    if !is_aligned(rom) {
+        padded.push(0);
+    }
+
+    padded
+}
+
+/// Odd-length roms end in half an instruction, fetching which reads past the loaded data
+pub fn is_aligned(rom: &[u8]) -> bool {
+    rom.len().is_multiple_of(2)
+}
+
+/// 64-bit FNV-1a hash of the rom contents, used to key per-rom data
+pub fn hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Last bytes of a rom that carries a metadata block, see `append_metadata`
+pub const METADATA_MAGIC: &[u8; 4] = b"C8MD";
+
+/// Appends settings to a rom as a metadata block: `text` (TOML key/values, as in a rom
+/// container), its length as 2 big-endian bytes and `METADATA_MAGIC`. The program never
+/// reaches the block, and loaders that don't know it load it as unused data past the code
+pub fn append_metadata(rom: &mut Vec<u8>, text: &str) -> Result<(), BuildError> {
+    let len = u16::try_from(text.len()).map_err(|_| BuildError::MetadataTooLarge { len: text.len() })?;
+    rom.extend_from_slice(text.as_bytes());
+    rom.extend_from_slice(&len.to_be_bytes());
+    rom.extend_from_slice(METADATA_MAGIC);
+    Ok(())
+}
+
+/// Splits a metadata block from the end of a rom, returns the program and the text of the
+/// block, None if there is no block or it is not UTF-8
+pub fn split_metadata(rom: &[u8]) -> (&[u8], Option<&str>) {
+    let rest = match rom.strip_suffix(METADATA_MAGIC) {
+        Some(rest) if rest.len() >= 2 => rest,
+        _ => return (rom, None),
+    };
+    let (rest, len) = rest.split_at(rest.len() - 2);
+    let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+    if len > rest.len() {
+        return (rom, None);
+    }
+    let (program, text) = rest.split_at(rest.len() - len);
+    match std::str::from_utf8(text) {
+        Ok(text) => (program, Some(text)),
+        Err(_) => (rom, None),
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum BuildError {
+    #[fail(display = "Unknown label: {}", label)]
+    UnknownLabel { label: String },
+    #[fail(display = "Duplicate label: {}", label)]
+    DuplicateLabel { label: String },
+    #[fail(display = "Metadata of {} bytes does not fit in a block, the limit is 65535", len)]
+    MetadataTooLarge { len: usize },
+}
+
+enum Item {
+    Bytes(Vec<u8>),
+    /// `opcode` with the address of `label` in its lower 12 bits
+    Fixup { opcode: u16, label: String },
+}
+
+/// Builds roms in code, resolving labels to addresses when the rom is finished.
+/// Meant for generating test programs:
+///
+/// ```
+/// use chip8::rom::Builder;
+///
+/// let rom = Builder::new()
+///     .set_reg(0, 0)
+///     .label("loop")
+///     .add(0, 1)
+///     .skip_eq(0, 10)
+///     .jump("loop")
+///     .build()?;
+/// assert_eq!(rom, [0x60, 0x00, 0x70, 0x01, 0x30, 0x0A, 0x12, 0x02]);
+/// # Ok::<(), chip8::rom::BuildError>(())
+/// ```
+pub struct Builder {
+    items: Vec<Item>,
+    labels: HashMap<String, u16>,
+    addr: u16,
+    duplicate: Option<String>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Builder {
+            items: Vec::new(),
+            labels: HashMap::new(),
+            addr: PROGRAM_START,
+            duplicate: None,
+        }
+    }
+
+    /// address the next instruction is placed at
+    pub fn addr(&self) -> u16 {
+        self.addr
+    }
+
+    /// Names the address of the next instruction
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        if self.labels.insert(name.to_string(), self.addr).is_some() && self.duplicate.is_none() {
+            self.duplicate = Some(name.to_string());
+        }
+        self
+    }
+
+    /// Appends a raw instruction
+    pub fn op(&mut self, opcode: u16) -> &mut Self {
+        self.bytes(&opcode.to_be_bytes())
+    }
+
+    /// Appends an instruction that takes the address of `label`, e.g. 0x1000 for a jump
+    pub fn op_to(&mut self, opcode: u16, label: &str) -> &mut Self {
+        self.items.push(Item::Fixup {
+            opcode,
+            label: label.to_string(),
+        });
+        self.addr = self.addr.wrapping_add(2);
+        self
+    }
+
+    /// Appends data, which may leave the following instructions at odd addresses
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.items.push(Item::Bytes(bytes.to_vec()));
+        self.addr = self.addr.wrapping_add(bytes.len() as u16);
+        self
+    }
+
+    pub fn jump(&mut self, label: &str) -> &mut Self {
+        self.op_to(0x1000, label)
+    }
+
+    pub fn call(&mut self, label: &str) -> &mut Self {
+        self.op_to(0x2000, label)
+    }
+
+    pub fn ret(&mut self) -> &mut Self {
+        self.op(0x00EE)
+    }
+
+    pub fn set_index(&mut self, label: &str) -> &mut Self {
+        self.op_to(0xA000, label)
+    }
+
+    pub fn skip_eq(&mut self, reg: u8, value: u8) -> &mut Self {
+        self.op(0x3000 | reg_x(reg) | u16::from(value))
+    }
+
+    pub fn skip_neq(&mut self, reg: u8, value: u8) -> &mut Self {
+        self.op(0x4000 | reg_x(reg) | u16::from(value))
+    }
+
+    pub fn set_reg(&mut self, reg: u8, value: u8) -> &mut Self {
+        self.op(0x6000 | reg_x(reg) | u16::from(value))
+    }
+
+    pub fn add(&mut self, reg: u8, value: u8) -> &mut Self {
+        self.op(0x7000 | reg_x(reg) | u16::from(value))
+    }
+
+    /// 8XYN register to register instruction
+    pub fn alu(&mut self, op: u8, x: u8, y: u8) -> &mut Self {
+        self.op(0x8000 | reg_x(x) | reg_x(y) >> 4 | u16::from(op & 0xF))
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>, BuildError> {
+        if let Some(label) = &self.duplicate {
+            return Err(BuildError::DuplicateLabel {
+                label: label.clone(),
+            });
+        }
+
+        let mut rom = Vec::new();
+        for item in &self.items {
+            match item {
+                Item::Bytes(bytes) => rom.extend_from_slice(bytes),
+                Item::Fixup { opcode, label } => {
+                    let addr = self
+                        .labels
+                        .get(label)
+                        .ok_or_else(|| BuildError::UnknownLabel {
+                            label: label.clone(),
+                        })?;
+                    rom.extend_from_slice(&(opcode | addr & 0xFFF).to_be_bytes());
+                }
+            }
+        }
+
+        Ok(rom)
+    }
+}
+
+fn reg_x(reg: u8) -> u16 {
+    u16::from(reg & 0xF) << 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::debug::Debugger;
+    use crate::system::System;
+    use proptest::prelude::*;
+
+    /// Instructions whose results do not depend on the quirks. VF is never an operand, so the
+    /// flags they set do not matter
+    #[derive(Clone, Debug)]
+    enum Op {
+        Set(u8, u8),
+        Add(u8, u8),
+        /// 8XYN without the shifts
+        Alu(u8, u8, u8),
+        SkipEq(u8, u8),
+        SkipNeq(u8, u8),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        let reg = 0..0xFu8;
+        prop_oneof![
+            (reg.clone(), any::<u8>()).prop_map(|(reg, value)| Op::Set(reg, value)),
+            (reg.clone(), any::<u8>()).prop_map(|(reg, value)| Op::Add(reg, value)),
+            (prop::sample::select(vec![0, 1, 2, 3, 4, 5, 7]), reg.clone(), reg.clone())
+                .prop_map(|(op, x, y)| Op::Alu(op, x, y)),
+            (reg.clone(), any::<u8>()).prop_map(|(reg, value)| Op::SkipEq(reg, value)),
+            (reg, any::<u8>()).prop_map(|(reg, value)| Op::SkipNeq(reg, value)),
+        ]
+    }
+
+    /// V0 to VE after `ops`, worked out without the emulator
+    fn expected(ops: &[Op]) -> [u8; 15] {
+        let mut reg = [0u8; 15];
+        let mut skip = false;
+        for op in ops {
+            if std::mem::take(&mut skip) {
+                continue;
+            }
+            match *op {
+                Op::Set(x, value) => reg[usize::from(x)] = value,
+                Op::Add(x, value) => reg[usize::from(x)] = reg[usize::from(x)].wrapping_add(value),
+                Op::Alu(op, x, y) => {
+                    let (vx, vy) = (reg[usize::from(x)], reg[usize::from(y)]);
+                    reg[usize::from(x)] = match op {
+                        0 => vy,
+                        1 => vx | vy,
+                        2 => vx & vy,
+                        3 => vx ^ vy,
+                        4 => vx.wrapping_add(vy),
+                        5 => vx.wrapping_sub(vy),
+                        _ => vy.wrapping_sub(vx),
+                    };
+                }
+                Op::SkipEq(x, value) => skip = reg[usize::from(x)] == value,
+                Op::SkipNeq(x, value) => skip = reg[usize::from(x)] != value,
+            }
+        }
+        reg
+    }
+
+    proptest! {
+        #[test]
+        fn built_programs_leave_the_expected_registers(ops in prop::collection::vec(op(), 0..64)) {
+            let mut rom = Builder::new();
+            for op in &ops {
+                match *op {
+                    Op::Set(x, value) => rom.set_reg(x, value),
+                    Op::Add(x, value) => rom.add(x, value),
+                    Op::Alu(op, x, y) => rom.alu(op, x, y),
+                    Op::SkipEq(x, value) => rom.skip_eq(x, value),
+                    Op::SkipNeq(x, value) => rom.skip_neq(x, value),
+                };
+            }
+            // something for a skip at the end to skip, VF is not checked
+            rom.set_reg(0xF, 0);
+            let end = rom.addr();
+            rom.label("end").jump("end");
+
+            let mut system = System::default();
+            system.load(&rom.build().unwrap()[..]).unwrap();
+            for _ in 0..=ops.len() + 1 {
+                if system.registers.pc == end {
+                    break;
+                }
+                system.tick(&mut Debugger::disabled()).unwrap();
+            }
+            prop_assert_eq!(system.registers.pc, end);
+            prop_assert_eq!(&system.registers.reg[..15], &expected(&ops)[..]);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use failure::Error;
+use std::path::{Path, PathBuf};
+
+/// extensions tried after a rom name given without one, in order
+const EXTENSIONS: &[&str] = &["ch8", "c8", "rom", "8o", "c8x"];
+/// close matches listed when no rom has the name
+const SUGGESTIONS: usize = 5;
+
+/// The rom `name` refers to: the file itself if there is one, else the rom of that name in one
+/// of `dirs`, ignoring case and with or without its extension, e.g. `pong` for `PONG.ch8`.
+/// Fails listing the roms it could be when several match, or close matches when none does
+pub fn find(name: &Path, dirs: &[PathBuf]) -> Result<PathBuf, Error> {
+    if name.exists() || dirs.is_empty() || name.components().count() > 1 {
+        return Ok(name.to_owned());
+    }
+    let wanted = name.to_string_lossy().to_lowercase();
+
+    let files = dirs.iter().flat_map(|dir| files_in(dir)).collect::<Vec<_>>();
+    let mut matches = files.iter().filter(|path| matches(path, &wanted)).collect::<Vec<_>>();
+    matches.sort_by_key(|path| extension_rank(path));
+    let same_game = |a: &Path, b: &Path| a.parent() == b.parent() && lowercase_name(a).1 == lowercase_name(b).1;
+    match matches.split_first() {
+        None => {}
+        // a game in several formats is the one with the first extension
+        Some((rom, rest)) if rest.iter().all(|other| same_game(rom, other)) => return Ok(rom.to_path_buf()),
+        Some(_) => bail!("{} could be any of: {}", name.display(), list(&matches)),
+    }
+
+    let mut close = files.iter().filter(|path| close_to(path, &wanted)).collect::<Vec<_>>();
+    close.truncate(SUGGESTIONS);
+    let searched = dirs.iter().map(|dir| dir.display().to_string()).collect::<Vec<_>>().join(", ");
+    let message = format!("No rom called {} here or in {}", name.display(), searched);
+    if close.is_empty() {
+        return Err(NotFound(message).into());
+    }
+    Err(NotFound(format!("{}, did you mean: {}", message, list(&close))).into())
+}
+
+/// No rom has the name given to `find`
+#[derive(Debug, Fail)]
+#[fail(display = "{}", _0)]
+pub struct NotFound(String);
+
+/// The files in `dir` with one of `EXTENSIONS`, sorted by name
+fn files_in(dir: &Path) -> Vec<PathBuf> {
+    let mut files = std::fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect::<Vec<_>>())
+        .unwrap_or_default();
+    files.retain(|path| extension_rank(path) < EXTENSIONS.len());
+    files.sort();
+    files
+}
+
+/// Where the extension of `path` is in `EXTENSIONS`, their length if it is not
+fn extension_rank(path: &Path) -> usize {
+    let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    EXTENSIONS
+        .iter()
+        .position(|known| extension.as_deref() == Some(*known))
+        .unwrap_or(EXTENSIONS.len())
+}
+
+fn lowercase_name(path: &Path) -> (String, String) {
+    let lower = |part: Option<&std::ffi::OsStr>| part.map(|part| part.to_string_lossy().to_lowercase()).unwrap_or_default();
+    (lower(path.file_name()), lower(path.file_stem()))
+}
+
+/// `wanted` is the file name or the name without the extension
+fn matches(path: &Path, wanted: &str) -> bool {
+    let (file_name, stem) = lowercase_name(path);
+    file_name == wanted || stem == wanted
+}
+
+/// the name contains `wanted` or the other way around, or it is a typo or two away
+fn close_to(path: &Path, wanted: &str) -> bool {
+    let (_, stem) = lowercase_name(path);
+    stem.contains(wanted) || wanted.contains(&stem) || edit_distance(&stem, wanted) <= 2
+}
+
+/// Insertions, deletions and substitutions that turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn list(paths: &[&PathBuf]) -> String {
+    paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_roms_by_name() {
+        let dir = std::env::temp_dir().join("chip8-romsearch-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("more")).unwrap();
+        for file in ["PONG.ch8", "Pong2.c8", "tetris.rom", "tetris.ch8", "notes.txt", "more/brix.ch8", "more/pong2.ch8"] {
+            std::fs::write(dir.join(file), [0x12, 0x00]).unwrap();
+        }
+        let dirs = [dir.clone(), dir.join("more")];
+
+        assert_eq!(find(Path::new("pong"), &dirs).unwrap(), dir.join("PONG.ch8"));
+        assert_eq!(find(Path::new("tetris.rom"), &dirs).unwrap(), dir.join("tetris.rom"));
+        // the .ch8 of a game in two formats
+        assert_eq!(find(Path::new("Tetris"), &dirs).unwrap(), dir.join("tetris.ch8"));
+        assert!(find(Path::new("pong2"), &dirs).unwrap_err().to_string().contains("could be any of"));
+        let missing = find(Path::new("brik"), &dirs).unwrap_err().to_string();
+        assert!(missing.ends_with(&format!("did you mean: {}", dir.join("more/brix.ch8").display())), "{}", missing);
+        assert!(!find(Path::new("notes"), &dirs).unwrap_err().to_string().contains("did you mean"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
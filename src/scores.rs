@@ -0,0 +1,143 @@
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::system::System;
+
+/// file the table is kept in, next to the config file
+pub const FILE_NAME: &str = "chip8-scores.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScoreFormat {
+    /// big-endian binary number
+    Binary,
+    /// one decimal digit per byte, most significant first, as written by FX33
+    Bcd,
+}
+
+/// Where a game keeps its score, set per game in the game database
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ScoreRegion {
+    pub addr: u16,
+    pub len: u16,
+    pub format: ScoreFormat,
+}
+
+impl ScoreRegion {
+    /// Reads `<addr>:<len>:<binary|bcd>`, e.g. `0x2F0:3:bcd`
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let number = |part: &str| match part.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => part.parse().ok(),
+        };
+        let parts = text.split(':').collect::<Vec<_>>();
+        let (addr, len, format) = match parts[..] {
+            [addr, len, format] => (number(addr), number(len), format),
+            _ => bail!("expected <addr>:<len>:<binary|bcd>, got {}", text),
+        };
+        let format = match format {
+            "binary" => ScoreFormat::Binary,
+            "bcd" => ScoreFormat::Bcd,
+            _ => bail!("unknown score format {}, expected binary or bcd", format),
+        };
+        match (addr, len) {
+            (Some(addr), Some(len)) if len > 0 => Ok(ScoreRegion { addr, len, format }),
+            _ => bail!("invalid score region {}", text),
+        }
+    }
+
+    /// Decodes the score, none if the region is out of memory or does not hold a valid number
+    pub fn read(&self, system: &System) -> Option<u64> {
+        let bytes = system.read_slice(self.addr..self.addr.checked_add(self.len)?).ok()?;
+        match self.format {
+            ScoreFormat::Binary if bytes.len() <= 8 => Some(
+                bytes
+                    .iter()
+                    .fold(0, |score, byte| score << 8 | u64::from(*byte)),
+            ),
+            ScoreFormat::Binary => None,
+            ScoreFormat::Bcd => bytes.iter().try_fold(0u64, |score, digit| {
+                if *digit > 9 {
+                    return None;
+                }
+                score.checked_mul(10)?.checked_add(u64::from(*digit))
+            }),
+        }
+    }
+}
+
+/// Best score of every game, keyed by the hex `rom::hash` like the game database
+#[derive(Default, Serialize, Deserialize)]
+pub struct HighScores {
+    scores: BTreeMap<String, u64>,
+}
+
+impl HighScores {
+    /// Loads the table, starting an empty one if the file does not exist
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(src) => Ok(toml::from_str(&src)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HighScores::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, rom_hash: u64) -> Option<u64> {
+        self.scores.get(&format!("{:016x}", rom_hash)).cloned()
+    }
+
+    pub fn set(&mut self, rom_hash: u64, score: u64) {
+        self.scores.insert(format!("{:016x}", rom_hash), score);
+    }
+}
+
+/// Watches the score of the running game
+pub struct ScoreTracker {
+    rom_hash: u64,
+    region: ScoreRegion,
+    /// best score before this session, a new high score has to beat it
+    previous_best: Option<u64>,
+    best: Option<u64>,
+}
+
+impl ScoreTracker {
+    pub fn new(rom_hash: u64, region: ScoreRegion, scores: &HighScores) -> Self {
+        let best = scores.get(rom_hash);
+        ScoreTracker {
+            rom_hash,
+            region,
+            previous_best: best,
+            best,
+        }
+    }
+
+    /// Reads the current score, returns it if it is a new high score
+    pub fn update(&mut self, system: &System) -> Option<u64> {
+        let score = self.region.read(system)?;
+        if self.best.is_some_and(|best| score <= best) {
+            return None;
+        }
+
+        self.best = Some(score);
+        // the first score of a game that was never played is not a record yet
+        self.previous_best.map(|_| score)
+    }
+
+    /// Stores the best score in the table if it improved
+    pub fn record(&self, scores: &mut HighScores) -> bool {
+        match self.best {
+            Some(best) if scores.get(self.rom_hash).is_none_or(|old| best > old) => {
+                scores.set(self.rom_hash, best);
+                true
+            }
+            _ => false,
+        }
+    }
+}
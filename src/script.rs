@@ -0,0 +1,429 @@
+use failure::Error;
+
+use crate::headless::{Headless, CYCLES_PER_FRAME};
+use crate::system::{System, SystemError, MEMORY_SIZE};
+
+/// A number in the script or a value read from the system
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    Number(u16),
+    Register(u8),
+    Index,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+    Memory(u16),
+    Pixel(u8, u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+}
+
+/// a condition is split at the first of these, the longer one where `<=` also matches `<`
+const COMPARISONS: [(&str, Comparison); 6] = [
+    ("==", Comparison::Eq),
+    ("!=", Comparison::Ne),
+    ("<=", Comparison::Le),
+    (">=", Comparison::Ge),
+    ("<", Comparison::Lt),
+    (">", Comparison::Gt),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+struct Condition {
+    left: Operand,
+    comparison: Comparison,
+    right: Operand,
+    /// as written in the script
+    text: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    /// all of the conditions hold
+    Assert(Vec<Condition>),
+    Press(u8),
+    Release(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Step {
+    line: usize,
+    /// instructions executed before the step, see `Headless::step`
+    cycle: u64,
+    action: Action,
+}
+
+/// A key held down while all of the conditions hold, checked at the end of every frame
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    conditions: Vec<Condition>,
+    key: u8,
+}
+
+/// The result of one assertion
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub line: usize,
+    pub passed: bool,
+    /// when it was checked, and the values that made it fail
+    pub message: String,
+}
+
+/// Checks on the state of a rom run headlessly, one per line:
+///
+/// ```text
+/// # comment
+/// at frame 10 press 5
+/// at frame 12 release 5
+/// at cycle 5000 assert v3 == 7 and pixel(10, 12) == on
+/// when mem[0x2F0] < v6 press 4
+/// ```
+///
+/// Times count from the start: `cycle` in instructions, `frame` in frames of
+/// `CYCLES_PER_FRAME` instructions. Operands are numbers (decimal or `0x` hex), `on`/`off`,
+/// `v0`..`vf`, `i`, `pc`, `sp`, `dt`, `st`, `mem[addr]` and `pixel(x, y)`. `when` lines make
+/// bots that play the game: after every frame, the key is held while any of its `when` lines
+/// holds and released otherwise
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Script {
+    /// in the order they happen, lines at the same time in the order they are written
+    steps: Vec<Step>,
+    rules: Vec<Rule>,
+}
+
+impl Script {
+    pub fn parse(src: &str) -> Result<Self, Error> {
+        let mut steps = Vec::new();
+        let mut rules = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let context = |e: Error| format_err!("line {}: {}", line_number, e);
+            match line.strip_prefix("when ") {
+                Some(rule) => rules.push(parse_rule(rule).map_err(context)?),
+                None => steps.push(parse_step(line, line_number).map_err(context)?),
+            }
+        }
+        steps.sort_by_key(|step| step.cycle);
+
+        Ok(Script { steps, rules })
+    }
+
+    /// Runs `frames` more frames of `headless`, pressing the keys of the script and skipping
+    /// its assertions
+    pub fn play(&self, headless: &mut Headless, frames: u64) -> Result<(), SystemError> {
+        let end = headless.frame + frames;
+        while headless.frame < end {
+            let frame = headless.frame;
+            let cycle = frame * u64::from(CYCLES_PER_FRAME) + u64::from(headless.system.frame_cycles);
+            let first = self.steps.partition_point(|step| step.cycle < cycle);
+            for step in self.steps[first..].iter().take_while(|step| step.cycle == cycle) {
+                press(&step.action, &mut headless.system);
+            }
+            headless.step()?;
+            if headless.frame != frame {
+                self.react(&mut headless.system);
+            }
+        }
+        Ok(())
+    }
+
+    /// Holds the keys of the `when` lines that hold and releases the others
+    fn react(&self, system: &mut System) {
+        let mut held = [None; 16];
+        for rule in &self.rules {
+            let holds = rule.conditions.iter().all(|condition| condition.holds(system));
+            let held = &mut held[usize::from(rule.key)];
+            *held = Some(held.unwrap_or(false) || holds);
+        }
+        for (key, held) in (0..).zip(held) {
+            if let Some(held) = held {
+                if system.keys.pressed(key).ok() != Some(held) {
+                    system.process_key_event(key, held).ok();
+                }
+            }
+        }
+    }
+
+    /// Runs `system` until the last step, returning the result of every assertion. An error
+    /// in the program fails the assertions that were still to come
+    pub fn run(&self, system: System) -> Vec<Outcome> {
+        let mut headless = Headless::new(system);
+        let mut cycle = 0;
+        let mut outcomes = Vec::new();
+        let mut crash = None;
+
+        for step in &self.steps {
+            while crash.is_none() && cycle < step.cycle {
+                let frame = headless.frame;
+                match headless.step() {
+                    Ok(()) => cycle += 1,
+                    Err(e) => crash = Some(format!("the program stopped at cycle {}: {}", cycle, e)),
+                }
+                if headless.frame != frame {
+                    self.react(&mut headless.system);
+                }
+            }
+
+            let system = &mut headless.system;
+            match (&step.action, &crash) {
+                (Action::Assert(_), Some(crash)) => outcomes.push(Outcome {
+                    line: step.line,
+                    passed: false,
+                    message: crash.clone(),
+                }),
+                (Action::Assert(conditions), None) => {
+                    let failed = conditions
+                        .iter()
+                        .filter(|condition| !condition.holds(system))
+                        .map(|condition| condition.explain(system))
+                        .collect::<Vec<_>>();
+                    let message = if failed.is_empty() {
+                        format!("at cycle {}", cycle)
+                    } else {
+                        format!("at cycle {}: {}", cycle, failed.join(", "))
+                    };
+                    outcomes.push(Outcome {
+                        line: step.line,
+                        passed: failed.is_empty(),
+                        message,
+                    });
+                }
+                (action, None) => press(action, system),
+                (_, Some(_)) => {}
+            }
+        }
+
+        outcomes
+    }
+}
+
+fn press(action: &Action, system: &mut System) {
+    // keys are checked when parsed
+    match action {
+        Action::Press(key) => system.process_key_event(*key, true).ok(),
+        Action::Release(key) => system.process_key_event(*key, false).ok(),
+        Action::Assert(_) => None,
+    };
+}
+
+/// `<conditions> press <key>` of a `when` line
+fn parse_rule(rule: &str) -> Result<Rule, Error> {
+    let (conditions, key) = rule
+        .rsplit_once(" press ")
+        .ok_or_else(|| format_err!("expected `when <conditions> press <key>`"))?;
+    Ok(Rule {
+        conditions: conditions.split(" and ").map(parse_condition).collect::<Result<_, _>>()?,
+        key: parse_key(key)?,
+    })
+}
+
+fn parse_step(line: &str, line_number: usize) -> Result<Step, Error> {
+    let mut words = line.splitn(4, char::is_whitespace).map(str::trim);
+    let (at, unit, time, rest) = (words.next(), words.next(), words.next(), words.next());
+    if at != Some("at") {
+        bail!("expected `at cycle <n>` or `at frame <n>`");
+    }
+    let time = parse_number(time.unwrap_or_default())?;
+    let cycle = match unit {
+        Some("cycle") => u64::from(time),
+        Some("frame") => u64::from(time) * u64::from(CYCLES_PER_FRAME),
+        _ => bail!("expected `cycle` or `frame` after `at`"),
+    };
+
+    let rest = rest.unwrap_or_default().trim();
+    let (verb, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    let action = match verb {
+        "assert" => Action::Assert(
+            args.split(" and ")
+                .map(parse_condition)
+                .collect::<Result<_, _>>()?,
+        ),
+        "press" => Action::Press(parse_key(args)?),
+        "release" => Action::Release(parse_key(args)?),
+        _ => bail!("expected `assert`, `press` or `release`, got `{}`", verb),
+    };
+
+    Ok(Step {
+        line: line_number,
+        cycle,
+        action,
+    })
+}
+
+fn parse_key(key: &str) -> Result<u8, Error> {
+    match u8::from_str_radix(key.trim(), 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => bail!("invalid hex key: {}", key.trim()),
+    }
+}
+
+fn parse_number(number: &str) -> Result<u16, Error> {
+    let number = number.trim();
+    let parsed = match number.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => number.parse(),
+    };
+    parsed.map_err(|_| format_err!("invalid number: {}", number))
+}
+
+fn parse_condition(text: &str) -> Result<Condition, Error> {
+    let text = text.trim();
+    let (position, symbol, comparison) = COMPARISONS
+        .iter()
+        .filter_map(|&(symbol, comparison)| Some((text.find(symbol)?, symbol, comparison)))
+        .min_by_key(|&(position, symbol, _)| (position, std::cmp::Reverse(symbol.len())))
+        .ok_or_else(|| format_err!("expected a comparison in `{}`", text))?;
+
+    Ok(Condition {
+        left: parse_operand(&text[..position])?,
+        comparison,
+        right: parse_operand(&text[position + symbol.len()..])?,
+        text: text.to_string(),
+    })
+}
+
+fn parse_operand(operand: &str) -> Result<Operand, Error> {
+    let operand = operand.trim().to_lowercase();
+    let inner = |prefix: &str, suffix: &str| operand.strip_prefix(prefix).and_then(|rest| rest.strip_suffix(suffix));
+
+    Ok(match operand.as_str() {
+        "on" => Operand::Number(1),
+        "off" => Operand::Number(0),
+        "i" => Operand::Index,
+        "pc" => Operand::Pc,
+        "sp" => Operand::Sp,
+        "dt" => Operand::Delay,
+        "st" => Operand::Sound,
+        _ if operand.len() == 2 && operand.starts_with('v') => Operand::Register(parse_key(&operand[1..])?),
+        _ => {
+            if let Some(addr) = inner("mem[", "]") {
+                match parse_number(addr)? {
+                    addr if addr < MEMORY_SIZE => Operand::Memory(addr),
+                    addr => bail!("address {:#X} is past the end of memory", addr),
+                }
+            } else if let Some(position) = inner("pixel(", ")") {
+                let (x, y) = position
+                    .split_once(',')
+                    .ok_or_else(|| format_err!("expected pixel(x, y), got {}", operand))?;
+                match (parse_number(x)?, parse_number(y)?) {
+                    (x, y) if x < 64 && y < 32 => Operand::Pixel(x as u8, y as u8),
+                    (x, y) => bail!("pixel ({}, {}) is off the screen", x, y),
+                }
+            } else {
+                Operand::Number(parse_number(&operand)?)
+            }
+        }
+    })
+}
+
+impl Operand {
+    /// as it is written in scripts
+    fn name(self) -> String {
+        match self {
+            Operand::Number(number) => number.to_string(),
+            Operand::Register(reg) => format!("v{:x}", reg),
+            Operand::Index => "i".to_string(),
+            Operand::Pc => "pc".to_string(),
+            Operand::Sp => "sp".to_string(),
+            Operand::Delay => "dt".to_string(),
+            Operand::Sound => "st".to_string(),
+            Operand::Memory(addr) => format!("mem[{:#X}]", addr),
+            Operand::Pixel(x, y) => format!("pixel({}, {})", x, y),
+        }
+    }
+
+    fn value(self, system: &System) -> u16 {
+        match self {
+            Operand::Number(number) => number,
+            Operand::Register(reg) => u16::from(system.registers.reg[usize::from(reg)]),
+            Operand::Index => system.registers.index,
+            Operand::Pc => system.registers.pc,
+            Operand::Sp => system.stack.sp,
+            Operand::Delay => u16::from(system.timers.delay),
+            Operand::Sound => u16::from(system.timers.sound),
+            Operand::Memory(addr) => u16::from(system.mem[usize::from(addr)]),
+            Operand::Pixel(x, y) => {
+                let pixel = usize::from(y) * 64 + usize::from(x);
+                u16::from(system.screen[pixel / 8] & (0x80 >> (pixel % 8)) != 0)
+            }
+        }
+    }
+}
+
+impl Condition {
+    fn holds(&self, system: &System) -> bool {
+        let (left, right) = (self.left.value(system), self.right.value(system));
+        match self.comparison {
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+            Comparison::Le => left <= right,
+            Comparison::Ge => left >= right,
+            Comparison::Lt => left < right,
+            Comparison::Gt => left > right,
+        }
+    }
+
+    /// the condition with the values it compared, e.g. "v3 == 7 (v3 is 5)"
+    fn explain(&self, system: &System) -> String {
+        let values = [self.left, self.right]
+            .iter()
+            .filter(|operand| !matches!(operand, Operand::Number(_)))
+            .map(|operand| {
+                let value = operand.value(system);
+                match operand {
+                    Operand::Pixel(..) => format!("{} is {}", operand.name(), if value != 0 { "on" } else { "off" }),
+                    _ => format!("{} is {} ({:#X})", operand.name(), value, value),
+                }
+            })
+            .collect::<Vec<_>>();
+        format!("{} ({})", self.text, values.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter() {
+        // V5 = 5, then V3 += 1 and wait for key 5 forever
+        let rom = [0x65, 0x05, 0x73, 0x01, 0xE5, 0x9E, 0x12, 0x02, 0x12, 0x08];
+        let script = Script::parse(
+            "# V3 counts up until 5 is pressed\n\
+             at cycle 7 assert v3 == 2 and pc == 0x202\n\
+             at frame 1 press 5\n\
+             at frame 2 assert v3 > 100 and pixel(0, 0) == off\n\
+             at cycle 10 assert v3 != 3\n",
+        )
+        .unwrap();
+
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        let outcomes = script.run(system);
+        let results = outcomes.iter().map(|outcome| (outcome.line, outcome.passed)).collect::<Vec<_>>();
+        assert_eq!(results, vec![(2, true), (5, false), (4, false)]);
+        assert_eq!(outcomes[1].message, "at cycle 10: v3 != 3 (v3 is 3 (0x3))");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Script::parse("at cycle 10 assert v3 = 7").is_err());
+        assert!(Script::parse("at minute 1 assert v3 == 7").is_err());
+        assert!(Script::parse("at frame 1 press 10").is_err());
+        assert!(Script::parse("at frame 1 assert pixel(64, 0) == on").is_err());
+        assert!(Script::parse("when v3 < 2 hold 5").is_err());
+    }
+}
@@ -0,0 +1,298 @@
+use failure::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::clock::{Clock, MockClock, Ticker};
+use crate::headless::{Headless, CYCLES_PER_FRAME};
+use crate::rom::Builder;
+use crate::system::debug::Debugger;
+use crate::system::System;
+use crate::timing;
+
+/// instructions each rom needs to finish, with plenty to spare
+const CYCLES: u32 = 500;
+
+/// Runs the rom of an area and checks what it left behind
+type Area = fn() -> Result<(), Error>;
+
+/// The parts of the core that are checked, each by a rom of its own
+const AREAS: [(&str, Area); 5] = [
+    ("arithmetic", arithmetic),
+    ("flags", flags),
+    ("draw collisions", draw_collisions),
+    ("bcd", bcd),
+    ("timers", timers),
+];
+
+/// What every area of `AREAS` found, a description of the first wrong result for those that
+/// failed
+pub struct SelfTestReport {
+    results: Vec<(&'static str, Result<(), String>)>,
+    elapsed: Duration,
+}
+
+impl SelfTestReport {
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|(_, result)| result.is_err()).count()
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, result) in &self.results {
+            match result {
+                Ok(()) => writeln!(f, "{:<16} PASS", name)?,
+                Err(e) => writeln!(f, "{:<16} FAIL: {}", name, e)?,
+            }
+        }
+        writeln!(f, "{} areas in {} ms", self.results.len(), self.elapsed.as_millis())
+    }
+}
+
+/// Runs the rom of every area headlessly with the default quirks
+pub fn run() -> SelfTestReport {
+    let start = Instant::now();
+    let results = AREAS
+        .iter()
+        .map(|(name, area)| (*name, area().map_err(|e| e.to_string())))
+        .collect();
+    SelfTestReport {
+        results,
+        elapsed: start.elapsed(),
+    }
+}
+
+/// The system after `rom` ran to its end
+fn run_rom(rom: &mut Builder) -> Result<System, Error> {
+    let mut system = System::default();
+    system.load(&rom.build()?[..])?;
+    let mut headless = Headless::new(system);
+    for _ in 0..CYCLES {
+        headless.step()?;
+    }
+    Ok(headless.system)
+}
+
+/// Fails on the first register, given with a description of what it holds, that does not
+/// have the value expected
+fn expect(system: &System, checks: &[(u8, u8, &str)]) -> Result<(), Error> {
+    for &(reg, expected, what) in checks {
+        let value = system.registers.read(reg)?;
+        if value != expected {
+            bail!("{} is {:02X}, expected {:02X}", what, value, expected);
+        }
+    }
+    Ok(())
+}
+
+fn arithmetic() -> Result<(), Error> {
+    let system = run_rom(
+        Builder::new()
+            .set_reg(0, 0x12)
+            .set_reg(8, 0x34)
+            .alu(4, 0, 8)
+            .set_reg(1, 0x50)
+            .set_reg(8, 0x20)
+            .alu(5, 1, 8)
+            .set_reg(2, 0x20)
+            .set_reg(8, 0x50)
+            .alu(7, 2, 8)
+            .set_reg(3, 0xF0)
+            .set_reg(8, 0x0F)
+            .alu(1, 3, 8)
+            .set_reg(4, 0xF0)
+            .set_reg(8, 0x3C)
+            .alu(2, 4, 8)
+            .set_reg(5, 0xFF)
+            .set_reg(8, 0x0F)
+            .alu(3, 5, 8)
+            .set_reg(6, 0xFE)
+            .add(6, 0x03)
+            // VY is VX, which shifts the same whether the interpreter shifts VX or VY
+            .set_reg(7, 0x81)
+            .alu(6, 7, 7)
+            .set_reg(9, 0x81)
+            .alu(0xE, 9, 9)
+            .alu(0, 0xA, 8)
+            .label("end")
+            .jump("end"),
+    )?;
+    expect(&system, &[
+        (0x0, 0x46, "12 + 34 (ADD Vx, Vy)"),
+        (0x1, 0x30, "50 - 20 (SUB)"),
+        (0x2, 0x30, "50 - 20 (SUBN)"),
+        (0x3, 0xFF, "F0 | 0F (OR)"),
+        (0x4, 0x30, "F0 & 3C (AND)"),
+        (0x5, 0xF0, "FF ^ 0F (XOR)"),
+        (0x6, 0x01, "FE + 3 (ADD Vx, nn)"),
+        (0x7, 0x40, "81 >> 1 (SHR)"),
+        (0x9, 0x02, "81 << 1 (SHL)"),
+        (0xA, 0x0F, "a copy of 0F (LD Vx, Vy)"),
+    ])
+}
+
+fn flags() -> Result<(), Error> {
+    let system = run_rom(
+        Builder::new()
+            .set_reg(0, 0xFF)
+            .set_reg(1, 0x02)
+            .alu(4, 0, 1)
+            .alu(0, 2, 0xF)
+            .set_reg(0, 0x01)
+            .alu(4, 0, 1)
+            .alu(0, 3, 0xF)
+            .set_reg(0, 0x05)
+            .set_reg(1, 0x03)
+            .alu(5, 0, 1)
+            .alu(0, 4, 0xF)
+            .set_reg(0, 0x03)
+            .set_reg(1, 0x05)
+            .alu(5, 0, 1)
+            .alu(0, 5, 0xF)
+            .alu(0, 6, 0)
+            .set_reg(0, 0x03)
+            .alu(6, 0, 0)
+            .alu(0, 7, 0xF)
+            .set_reg(0, 0x80)
+            .alu(0xE, 0, 0)
+            .alu(0, 8, 0xF)
+            // the flag is written after the result, even when the result goes to VF
+            .set_reg(0xF, 0xFF)
+            .set_reg(1, 0x02)
+            .alu(4, 0xF, 1)
+            .alu(0, 9, 0xF)
+            .label("end")
+            .jump("end"),
+    )?;
+    expect(&system, &[
+        (0x2, 1, "VF after an ADD with a carry"),
+        (0x3, 0, "VF after an ADD without a carry"),
+        (0x4, 1, "VF after a SUB without a borrow"),
+        (0x5, 0, "VF after a SUB with a borrow"),
+        (0x6, 0xFE, "3 - 5 (SUB)"),
+        (0x7, 1, "VF after shifting out a 1 to the right"),
+        (0x8, 1, "VF after shifting out a 1 to the left"),
+        (0x9, 1, "VF after ADD VF, Vy with a carry"),
+    ])
+}
+
+fn draw_collisions() -> Result<(), Error> {
+    let system = run_rom(
+        Builder::new()
+            .op(0x00E0) // CLS
+            .set_reg(0, 0)
+            .set_reg(1, 0)
+            .set_index("row")
+            .op(0xD011) // DRW V0, V1, 1
+            .alu(0, 2, 0xF)
+            .op(0xD011)
+            .alu(0, 3, 0xF)
+            .op(0xD011)
+            .alu(0, 4, 0xF)
+            .set_reg(0, 2)
+            .op(0xD011)
+            .alu(0, 5, 0xF)
+            .label("end")
+            .jump("end")
+            .label("row")
+            .bytes(&[0xF0]),
+    )?;
+    expect(&system, &[
+        (0x2, 0, "VF after drawing on a clear screen"),
+        (0x3, 1, "VF after erasing the sprite"),
+        (0x4, 0, "VF after drawing it again"),
+        (0x5, 1, "VF after drawing it 2 pixels to the right"),
+    ])?;
+    let screen = system.screen_ascii('#', '.');
+    let mut rows = screen.lines();
+    let expected = "##..##..";
+    match (rows.next(), rows.all(|row| !row.contains('#'))) {
+        (Some(first), true) if first.starts_with(expected) && !first[expected.len()..].contains('#') => Ok(()),
+        _ => bail!("the screen is not {} in its first row and clear elsewhere", expected),
+    }
+}
+
+fn bcd() -> Result<(), Error> {
+    let system = run_rom(
+        Builder::new()
+            .set_reg(0, 255)
+            .set_index("scratch")
+            .op(0xF033) // LD B, V0
+            .op(0xF265) // LD V2, [I]
+            .alu(0, 6, 0)
+            .alu(0, 7, 1)
+            .alu(0, 8, 2)
+            .set_reg(0, 7)
+            .set_index("scratch")
+            .op(0xF033)
+            .op(0xF265)
+            .label("end")
+            .jump("end")
+            .label("scratch")
+            .bytes(&[0xAA; 3]),
+    )?;
+    expect(&system, &[
+        (0x6, 2, "the hundreds of 255"),
+        (0x7, 5, "the tens of 255"),
+        (0x8, 5, "the ones of 255"),
+        (0x0, 0, "the hundreds of 7"),
+        (0x1, 0, "the tens of 7"),
+        (0x2, 7, "the ones of 7"),
+    ])
+}
+
+/// The delay and sound timers count down at 60 Hz, driven by a `MockClock` so the test takes
+/// no real time
+fn timers() -> Result<(), Error> {
+    let mut rom = Builder::new();
+    rom.set_reg(0, 10)
+        .op(0xF015) // LD DT, V0
+        .op(0xF018) // LD ST, V0
+        .label("wait")
+        .op(0xF107) // LD V1, DT
+        .skip_eq(1, 0)
+        .jump("wait");
+    let end = rom.addr();
+    rom.label("end").jump("end");
+    let mut system = System::default();
+    system.load(&rom.build()?[..])?;
+
+    let mut clock = MockClock::default();
+    let mut ticker = Ticker::new(60.0, clock.now());
+    let mut dbg = Debugger::disabled();
+    let mut ticks = 0;
+    while ticks < 12 {
+        for _ in 0..CYCLES_PER_FRAME {
+            system.tick(&mut dbg)?;
+        }
+        match (ticks, system.registers.pc == end) {
+            (0..=9, true) => bail!("the program saw the delay timer at 0 after {} of its 10 ticks", ticks),
+            (10.., false) => bail!("the delay timer is {} after its 10 ticks", system.timers.delay),
+            _ => {}
+        }
+        clock.advance(timing::period_of(60.0));
+        for _ in 0..ticker.due(clock.now()) {
+            system.dec_timers();
+            ticks += 1;
+            if ticks == 5 && (system.timers.delay, system.timers.sound) != (5, 5) {
+                bail!("the timers are {} and {} after 5 of 10 ticks", system.timers.delay, system.timers.sound);
+            }
+        }
+    }
+    match system.timers.sound {
+        0 => Ok(()),
+        sound => bail!("the sound timer is {} after its 10 ticks", sound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_area_passes() {
+        let report = run();
+        assert_eq!(report.failures(), 0, "{}", report);
+        assert_eq!(report.results.len(), AREAS.len());
+    }
+}
@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use crate::overlay::text::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+/// characters per line of text on the 64x32 screen
+pub const COLUMNS: usize = WIDTH / (GLYPH_WIDTH + 1);
+/// lines of text on the 64x32 screen
+pub const LINES: usize = HEIGHT / (GLYPH_HEIGHT + 1);
+
+/// extensions of the files the rom picker lists
+const ROM_EXTENSIONS: &[&str] = &["ch8", "8o", "c8x"];
+
+/// Renders lines of text as a 64x32 screen, one byte per pixel like `System::screen`.
+/// Lines past `LINES` and characters past `COLUMNS` are cut off
+pub fn text_screen(lines: &[String]) -> Vec<u8> {
+    let mut screen = vec![0; WIDTH * HEIGHT];
+    // the spare rows go above the text
+    let top = HEIGHT - LINES * (GLYPH_HEIGHT + 1) + 1;
+
+    for (line, text) in lines.iter().take(LINES).enumerate() {
+        let y = top + line * (GLYPH_HEIGHT + 1);
+        for (column, c) in text.chars().take(COLUMNS).enumerate() {
+            let x = column * (GLYPH_WIDTH + 1);
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits >> (GLYPH_WIDTH - 1 - col) & 1 != 0 {
+                        screen[(y + row) * WIDTH + x + col] = 255;
+                    }
+                }
+            }
+        }
+    }
+
+    screen
+}
+
+/// Splits `text` into lines of at most `COLUMNS` characters, breaking at spaces where possible
+pub fn wrap(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.chars().collect::<Vec<_>>();
+        while !word.is_empty() {
+            let used = line.chars().count();
+            let space = usize::from(used > 0);
+            if used + space + word.len() <= COLUMNS {
+                if space == 1 {
+                    line.push(' ');
+                }
+                line.extend(word.drain(..));
+            } else if used > 0 {
+                lines.push(std::mem::take(&mut line));
+            } else {
+                // a word longer than a line is split
+                line.extend(word.drain(..COLUMNS));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// The files in `dir` the rom picker lists, sorted by name
+pub fn roms_in(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| {
+                    path.extension()
+                        .and_then(|ext| ext.to_str())
+                        .is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    roms.sort();
+    roms
+}
+
+/// Lists the roms in a directory and lets one be chosen with the arrow keys
+pub struct RomPicker {
+    dir: PathBuf,
+    pub roms: Vec<PathBuf>,
+    pub selected: usize,
+}
+
+impl RomPicker {
+    pub fn new(dir: &Path) -> Self {
+        RomPicker {
+            dir: dir.to_owned(),
+            roms: roms_in(dir),
+            selected: 0,
+        }
+    }
+
+    /// Lists the directory again, keeping the selected rom selected if it is still there
+    pub fn refresh(&mut self) {
+        let selected = self.current().cloned();
+        self.roms = roms_in(&self.dir);
+        self.selected = selected
+            .and_then(|selected| self.roms.iter().position(|rom| *rom == selected))
+            .unwrap_or_else(|| self.selected.min(self.roms.len().saturating_sub(1)));
+    }
+
+    pub fn up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn down(&mut self) {
+        if self.selected + 1 < self.roms.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn current(&self) -> Option<&PathBuf> {
+        self.roms.get(self.selected)
+    }
+
+    /// a title line and a window of file names around the selected one
+    pub fn lines(&self) -> Vec<String> {
+        if self.roms.is_empty() {
+            let mut lines = vec!["NO ROMS FOUND".to_string()];
+            lines.extend(wrap("PUT .CH8 FILES IN THIS FOLDER"));
+            return lines;
+        }
+
+        let visible = LINES - 1;
+        let first = self.selected.saturating_sub(visible - 1);
+        let mut lines = vec![format!("ROMS {}/{}", self.selected + 1, self.roms.len())];
+        for (i, rom) in self.roms.iter().enumerate().skip(first).take(visible) {
+            let name = rom.file_stem().unwrap_or_default().to_string_lossy();
+            let marker = if i == self.selected { '>' } else { ' ' };
+            lines.push(format!("{}{}", marker, name));
+        }
+        lines
+    }
+}
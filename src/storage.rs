@@ -0,0 +1,301 @@
+use failure::Error;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::system::System;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Kind {
+    State,
+    Replay,
+    Stats,
+}
+
+impl Kind {
+    fn extension(self) -> &'static str {
+        match self {
+            Kind::State => "state",
+            Kind::Replay => "replay",
+            Kind::Stats => "stats",
+        }
+    }
+}
+
+/// Identifies a record: which rom (by `rom::hash`), which run of it, and what is stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key {
+    pub rom: u64,
+    pub run: u64,
+    pub kind: Kind,
+}
+
+impl Key {
+    pub fn new(rom: u64, run: u64, kind: Kind) -> Self {
+        Key { rom, run, kind }
+    }
+
+    /// big-endian encoding, so that records of one rom are adjacent and ordered by run
+    pub fn to_bytes(self) -> [u8; 17] {
+        let mut bytes = [0; 17];
+        bytes[..8].copy_from_slice(&self.rom.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.run.to_be_bytes());
+        bytes[16] = self.kind as u8;
+        bytes
+    }
+}
+
+pub trait Storage {
+    fn put(&mut self, key: Key, data: &[u8]) -> Result<(), Error>;
+    fn get(&self, key: Key) -> Result<Option<Vec<u8>>, Error>;
+    fn remove(&mut self, key: Key) -> Result<(), Error>;
+    /// ids of all runs that have at least one record for the rom, in ascending order
+    fn runs(&self, rom: u64) -> Result<Vec<u64>, Error>;
+}
+
+pub fn save_state(
+    storage: &mut impl Storage,
+    rom: u64,
+    run: u64,
+    system: &System,
+) -> Result<(), Error> {
+    storage.put(Key::new(rom, run, Kind::State), &system.save_state())
+}
+
+/// returns false if there is no state stored for the run
+pub fn load_state(
+    storage: &impl Storage,
+    rom: u64,
+    run: u64,
+    system: &mut System,
+) -> Result<bool, Error> {
+    match storage.get(Key::new(rom, run, Kind::State))? {
+        Some(state) => {
+            system.load_state(&state)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    records: BTreeMap<Key, Vec<u8>>,
+}
+
+impl Storage for MemoryStorage {
+    fn put(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
+        self.records.insert(key, data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, key: Key) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.records.get(&key).cloned())
+    }
+
+    fn remove(&mut self, key: Key) -> Result<(), Error> {
+        self.records.remove(&key);
+        Ok(())
+    }
+
+    fn runs(&self, rom: u64) -> Result<Vec<u64>, Error> {
+        let mut runs = self
+            .records
+            .range(Key::new(rom, 0, Kind::State)..=Key::new(rom, u64::MAX, Kind::Stats))
+            .map(|(key, _)| key.run)
+            .collect::<Vec<_>>();
+        runs.dedup();
+        Ok(runs)
+    }
+}
+
+/// Default name of the data directory, next to the config file
+pub const DATA_DIR: &str = "chip8-data";
+
+/// Records as files: `<dir>/<rom hash>/<run>.<kind>`
+pub struct FileStorage {
+    dir: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileStorage { dir: dir.into() }
+    }
+
+    fn rom_dir(&self, rom: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}", rom))
+    }
+
+    fn path(&self, key: Key) -> PathBuf {
+        self.rom_dir(key.rom)
+            .join(format!("{}.{}", key.run, key.kind.extension()))
+    }
+}
+
+impl Storage for FileStorage {
+    fn put(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
+        std::fs::create_dir_all(self.rom_dir(key.rom))?;
+        std::fs::write(self.path(key), data)?;
+        Ok(())
+    }
+
+    fn get(&self, key: Key) -> Result<Option<Vec<u8>>, Error> {
+        match std::fs::read(self.path(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn remove(&mut self, key: Key) -> Result<(), Error> {
+        match std::fs::remove_file(self.path(key)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn runs(&self, rom: u64) -> Result<Vec<u64>, Error> {
+        let entries = match std::fs::read_dir(self.rom_dir(rom)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut runs = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            // files not named by this storage are skipped
+            if let Some(run) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse().ok())
+            {
+                runs.push(run);
+            }
+        }
+        runs.sort_unstable();
+        runs.dedup();
+        Ok(runs)
+    }
+}
+
+#[cfg(feature = "sled")]
+pub struct SledStorage {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledStorage {
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        Ok(SledStorage {
+            db: sled::open(path)?,
+        })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Storage for SledStorage {
+    fn put(&mut self, key: Key, data: &[u8]) -> Result<(), Error> {
+        self.db.insert(&key.to_bytes()[..], data)?;
+        Ok(())
+    }
+
+    fn get(&self, key: Key) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.db.get(&key.to_bytes()[..])?.map(|value| value.to_vec()))
+    }
+
+    fn remove(&mut self, key: Key) -> Result<(), Error> {
+        self.db.remove(&key.to_bytes()[..])?;
+        Ok(())
+    }
+
+    fn runs(&self, rom: u64) -> Result<Vec<u64>, Error> {
+        let mut runs = Vec::new();
+        for entry in self.db.scan_prefix(&rom.to_be_bytes()[..]) {
+            let (key, _) = entry?;
+            let mut run = [0; 8];
+            run.copy_from_slice(&key[8..16]);
+            runs.push(u64::from_be_bytes(run));
+        }
+        runs.dedup();
+        Ok(runs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: u64 = 0x0123_4567_89AB_CDEF;
+
+    /// Stores records of `ROM` out of order and next to those of other roms, and reads them back
+    fn round_trip(storage: &mut impl Storage) {
+        let records = [
+            (Key::new(ROM, 10, Kind::Stats), &b"stats of 10"[..]),
+            (Key::new(ROM, 2, Kind::State), b"state of 2"),
+            (Key::new(ROM + 1, 1, Kind::State), b"another rom"),
+            (Key::new(ROM, 10, Kind::State), b"state of 10"),
+            (Key::new(ROM, 2, Kind::Replay), b"replay of 2"),
+            (Key::new(ROM, 300, Kind::Replay), b""),
+            (Key::new(ROM - 1, 7, Kind::Stats), b"yet another rom"),
+        ];
+        for (key, data) in records {
+            storage.put(key, data).unwrap();
+        }
+        for (key, data) in records {
+            assert_eq!(storage.get(key).unwrap().as_deref(), Some(data));
+        }
+        assert_eq!(storage.get(Key::new(ROM, 300, Kind::State)).unwrap(), None);
+
+        // ordered by number, each run once however many records it has
+        assert_eq!(storage.runs(ROM).unwrap(), [2, 10, 300]);
+        assert_eq!(storage.runs(ROM + 1).unwrap(), [1]);
+        assert!(storage.runs(ROM + 2).unwrap().is_empty());
+
+        storage.put(Key::new(ROM, 2, Kind::State), b"new state of 2").unwrap();
+        assert_eq!(storage.get(Key::new(ROM, 2, Kind::State)).unwrap().as_deref(), Some(&b"new state of 2"[..]));
+        storage.remove(Key::new(ROM, 300, Kind::Replay)).unwrap();
+        storage.remove(Key::new(ROM, 300, Kind::Replay)).unwrap();
+        storage.remove(Key::new(ROM, 10, Kind::State)).unwrap();
+        assert_eq!(storage.get(Key::new(ROM, 10, Kind::State)).unwrap(), None);
+        assert_eq!(storage.runs(ROM).unwrap(), [2, 10]);
+
+        // LD V0, 7 / LD DT, V0 / JP 204
+        let mut system = System::default();
+        system.load(&[0x60, 0x07, 0xF0, 0x15, 0x12, 0x04][..]).unwrap();
+        let mut dbg = crate::system::debug::Debugger::disabled();
+        for _ in 0..3 {
+            system.tick(&mut dbg).unwrap();
+        }
+        save_state(storage, ROM, 4, &system).unwrap();
+        let mut loaded = System::default();
+        assert!(load_state(storage, ROM, 4, &mut loaded).unwrap());
+        assert_eq!(loaded.save_state(), system.save_state());
+        assert!(!load_state(storage, ROM, 5, &mut loaded).unwrap());
+        assert_eq!(storage.runs(ROM).unwrap(), [2, 4, 10]);
+    }
+
+    #[test]
+    fn memory_storage_round_trips() {
+        round_trip(&mut MemoryStorage::default());
+    }
+
+    #[test]
+    fn file_storage_round_trips() {
+        let dir = std::env::temp_dir().join(format!("chip8-storage-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut storage = FileStorage::new(&dir);
+        round_trip(&mut storage);
+        // files not named by the storage are no runs
+        std::fs::write(storage.rom_dir(ROM).join("notes.txt"), b"").unwrap();
+        assert_eq!(storage.runs(ROM).unwrap(), [2, 4, 10]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn sled_storage_round_trips() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        round_trip(&mut SledStorage { db });
+    }
+}
@@ -0,0 +1,207 @@
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+
+use crate::headless::Headless;
+use crate::system::{System, SystemError};
+use crate::timing::FRAME;
+
+
+/// The screen after a 60 Hz frame that changed it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// frames run since the start, the ones in between left the screen as it was
+    pub number: u64,
+    /// one byte per pixel like `System::screen`, 64x32
+    pub screen: Vec<u8>,
+    pub beeping: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// the emulator stopped, because of an error or because its `Frames` were dropped
+#[derive(Clone, Copy, Debug, Fail)]
+#[fail(display = "The emulator is not running")]
+pub struct Stopped;
+
+#[derive(Default)]
+struct Shared {
+    /// the newest frame not yet taken, older ones are dropped for a slow reader
+    frame: Option<Frame>,
+    error: Option<SystemError>,
+    finished: bool,
+    waker: Option<Waker>,
+}
+
+/// The frames of the emulator started by `spawn`. Dropping it stops the emulator
+pub struct Frames {
+    shared: Arc<Mutex<Shared>>,
+    stop: Arc<AtomicBool>,
+}
+
+/// Key events for the emulator started by `spawn`, it can be cloned to send from several tasks
+#[derive(Clone)]
+pub struct Inputs {
+    sender: mpsc::Sender<KeyEvent>,
+}
+
+/// Runs `system` at 60 frames per second on a thread of its own. Frames that changed the
+/// screen come out of the stream and key events go in through the sink, neither ever blocks
+/// the task using them, so they work with any async runtime. An error in the program ends
+/// the stream after it
+pub fn spawn(system: System) -> (Frames, Inputs) {
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    let stop = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+
+    let frames = Frames {
+        shared: shared.clone(),
+        stop: stop.clone(),
+    };
+    std::thread::spawn(move || {
+        let error = run(Headless::new(system), &shared, &stop, &receiver).err();
+        let mut shared = shared.lock().unwrap();
+        shared.error = error;
+        shared.finished = true;
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+
+    (frames, Inputs { sender })
+}
+
+fn run(
+    mut headless: Headless,
+    shared: &Mutex<Shared>,
+    stop: &AtomicBool,
+    receiver: &mpsc::Receiver<KeyEvent>,
+) -> Result<(), SystemError> {
+    let mut last_screen = None;
+    let mut next = Instant::now();
+    while !stop.load(Ordering::Relaxed) {
+        for event in receiver.try_iter() {
+            headless.system.process_key_event(event.key, event.pressed)?;
+        }
+        // the screen is sent as the error left it too
+        let result = headless.run_frame();
+
+        let screen = headless.system.screen();
+        if last_screen.as_ref() != Some(&screen) {
+            last_screen = Some(screen.clone());
+            let mut shared = shared.lock().unwrap();
+            shared.frame = Some(Frame {
+                number: headless.frame,
+                screen,
+                beeping: headless.system.timers.sound > 0,
+            });
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+        result?;
+
+        next += FRAME;
+        match next.checked_duration_since(Instant::now()) {
+            Some(wait) => std::thread::sleep(wait),
+            // too slow to keep up, skip the lost time instead of running frames back to back
+            None => next = Instant::now(),
+        }
+    }
+    Ok(())
+}
+
+impl Stream for Frames {
+    type Item = Result<Frame, SystemError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(frame) = shared.frame.take() {
+            return Poll::Ready(Some(Ok(frame)));
+        }
+        if let Some(error) = shared.error.take() {
+            return Poll::Ready(Some(Err(error)));
+        }
+        if shared.finished {
+            return Poll::Ready(None);
+        }
+        shared.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Sink<KeyEvent> for Inputs {
+    type Error = Stopped;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Stopped>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, event: KeyEvent) -> Result<(), Stopped> {
+        self.sender.send(event).map_err(|_| Stopped)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Stopped>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Stopped>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// polls until the stream has something, sleeping in between
+    fn next(frames: &mut Frames) -> Option<Result<Frame, SystemError>> {
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(item) = Pin::new(&mut *frames).poll_next(&mut cx) {
+                return item;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn draws_on_key_press() {
+        // waits for key 5 and draws its digit, then returns with nowhere to go
+        let rom = [0xF0, 0x0A, 0xF0, 0x29, 0xD0, 0x05, 0x00, 0xEE];
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        let (mut frames, mut inputs) = spawn(system);
+
+        let first = next(&mut frames).unwrap().unwrap();
+        assert!(first.screen.iter().all(|&pixel| pixel == 0));
+        let mut cx = Context::from_waker(Waker::noop());
+        for pressed in [true, false] {
+            Pin::new(&mut inputs).start_send(KeyEvent { key: 5, pressed }).unwrap();
+        }
+        assert!(Pin::new(&mut inputs).poll_flush(&mut cx).is_ready());
+
+        let drawn = next(&mut frames).unwrap().unwrap();
+        assert!(drawn.number >= first.number);
+        assert!(drawn.screen.iter().any(|&pixel| pixel != 0));
+        assert!(matches!(next(&mut frames), Some(Err(SystemError::StackUnderflow))));
+        assert!(next(&mut frames).is_none());
+        assert!(Pin::new(&mut inputs).start_send(KeyEvent { key: 5, pressed: true }).is_err());
+    }
+}
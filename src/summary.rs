@@ -0,0 +1,106 @@
+use failure::Error;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::system::opcode::{self, Opcode};
+use crate::system::System;
+
+/// What a `run` did, written as JSON on exit with `--summary-json` to compare roms, or
+/// emulator versions on the same rom
+pub struct RunSummary {
+    start: Instant,
+    cycles: u64,
+    frames_rendered: u64,
+    /// timer ticks
+    emulated_frames: u64,
+    /// executions of every opcode word, grouped by instruction when written
+    opcodes: HashMap<u16, u64>,
+    draw_collisions: u64,
+    errors: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    cycles: u64,
+    frames_rendered: u64,
+    emulated_frames: u64,
+    /// by the syntax of the instruction, `unknown` for words that are none
+    opcodes: BTreeMap<&'static str, u64>,
+    errors: Vec<String>,
+    max_stack_depth: u16,
+    draw_collisions: u64,
+    wall_clock: WallClock,
+}
+
+#[derive(Serialize)]
+struct WallClock {
+    seconds: f64,
+    instructions_per_second: f64,
+    frames_per_second: f64,
+}
+
+impl RunSummary {
+    /// Starts the wall clock
+    pub fn start() -> Self {
+        RunSummary {
+            start: Instant::now(),
+            cycles: 0,
+            frames_rendered: 0,
+            emulated_frames: 0,
+            opcodes: HashMap::new(),
+            draw_collisions: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// counts `opcode`, which `system` just executed
+    pub fn instruction(&mut self, opcode: u16, system: &System) {
+        self.cycles += 1;
+        *self.opcodes.entry(opcode).or_default() += 1;
+        if Opcode::Draw.matches(opcode) && system.registers.reg[0xF] != 0 {
+            self.draw_collisions += 1;
+        }
+    }
+
+    pub fn frame_rendered(&mut self) {
+        self.frames_rendered += 1;
+    }
+
+    pub fn timer_tick(&mut self) {
+        self.emulated_frames += 1;
+    }
+
+    pub fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+
+    /// Writes the summary with the deepest the stack of `system` went
+    pub fn write(&self, path: &Path, system: &System) -> Result<(), Error> {
+        let mut opcodes = BTreeMap::new();
+        for (&word, &count) in &self.opcodes {
+            let name = opcode::decode(word).map_or("unknown", |info| info.syntax);
+            *opcodes.entry(name).or_default() += count;
+        }
+        let seconds = self.start.elapsed().as_secs_f64();
+        let rate = |count: u64| if seconds > 0.0 { count as f64 / seconds } else { 0.0 };
+
+        let report = Report {
+            cycles: self.cycles,
+            frames_rendered: self.frames_rendered,
+            emulated_frames: self.emulated_frames,
+            opcodes,
+            errors: self.errors.clone(),
+            max_stack_depth: system.stack.max_depth,
+            draw_collisions: self.draw_collisions,
+            wall_clock: WallClock {
+                seconds,
+                instructions_per_second: rate(self.cycles),
+                frames_per_second: rate(self.frames_rendered),
+            },
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&report)? + "\n")?;
+        Ok(())
+    }
+}
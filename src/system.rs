@@ -1,9 +1,29 @@
 use failure::{Error, Fail};
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
 
 #[macro_use]
-mod opcode;
-mod fonts;
+pub mod opcode;
+pub mod alu;
+pub mod activity;
+pub mod cache;
+pub mod fonts;
+mod rng;
+mod state;
 pub mod debug;
+pub mod device;
+pub mod extension;
+pub mod hooks;
+pub mod machine;
+pub mod diagnostics;
+pub mod diff;
+pub mod draws;
+mod idle;
+pub mod quirks;
+pub mod rewind;
+pub mod patch;
+pub mod selfmod;
+pub mod trace;
 
 #[derive(Debug, Fail)]
 pub enum SystemError {
@@ -21,9 +41,24 @@ pub enum SystemError {
     InvalidKey { key: u8 },
     #[fail(display = "Reached zero instruction")]
     ZeroInstruction,
+    #[fail(display = "Save state is malformed")]
+    InvalidState,
+    #[fail(
+        display = "Save state is version {}, this build reads up to version {}. It was saved by a newer build",
+        version, supported
+    )]
+    NewerState { version: u16, supported: u16 },
+    #[fail(display = "Unknown opcode: {:04X}", opcode)]
+    UnknownOpcode { opcode: u16 },
+    #[fail(display = "Call to machine code at {:03X}", addr)]
+    MachineCodeCall { addr: u16 },
+    #[fail(display = "Execution ran past the end of memory at {:03X}", pc)]
+    RanPastEndOfMemory { pc: u16 },
 }
 
-const PROGRAM_START: u16 = 0x200;
+pub const PROGRAM_START: u16 = 0x200;
+/// bytes of memory, addresses go up to one below it
+pub const MEMORY_SIZE: u16 = 0x1000;
 
 pub struct Registers {
     pub reg: [u8; 16],
@@ -96,15 +131,34 @@ pub struct Timers {
     pub sound: u8,
 }
 
-#[derive(Default)]
+/// the most return addresses any interpreter's stack holds, see `Quirks::stack_size`
+pub const MAX_STACK_SIZE: usize = 64;
+
 pub struct Stack {
-    pub stack: [u16; 16],
+    pub stack: [u16; MAX_STACK_SIZE],
     pub sp: u16,
+    /// the deepest `sp` has been since the rom was loaded
+    pub max_depth: u16,
 }
 
-#[derive(Default)]
+impl Default for Stack {
+    fn default() -> Self {
+        Stack {
+            stack: [0; MAX_STACK_SIZE],
+            sp: 0,
+            max_depth: 0,
+        }
+    }
+}
+
+/// The hex keypad: which keys are held, and the presses since the program was loaded
+#[derive(Default, Clone)]
 pub struct Keys {
     pub keys: [u8; 16],
+    last_pressed: Option<u8>,
+    presses: [u32; 16],
+    /// `System::cycles` at the last press of each key
+    pressed_at: [Option<u64>; 16],
 }
 
 impl Keys {
@@ -115,48 +169,239 @@ impl Keys {
             .map(|key| *key != 0)
     }
 
-    pub fn set_key_state(&mut self, key: u8, state: bool) -> Result<(), SystemError> {
-        self.keys
-            .get_mut(key as usize)
-            .ok_or(SystemError::InvalidKey{ key })
-            .map(|key| *key = state as u8)
+    /// Presses or releases `key` at instruction `cycle`. Pressing a held key is not a new press
+    pub fn set_key_state(&mut self, key: u8, state: bool, cycle: u64) -> Result<(), SystemError> {
+        let held = self.pressed(key)?;
+        let index = usize::from(key);
+        if state && !held {
+            self.last_pressed = Some(key);
+            self.presses[index] = self.presses[index].saturating_add(1);
+            self.pressed_at[index] = Some(cycle);
+        }
+        self.keys[index] = state as u8;
+        Ok(())
     }
+
+    pub fn any_pressed(&self) -> bool {
+        self.keys.iter().any(|key| *key != 0)
+    }
+
+    /// the key pressed most recently, held or not
+    pub fn last_pressed(&self) -> Option<u8> {
+        self.last_pressed
+    }
+
+    /// times `key` was pressed
+    pub fn presses(&self, key: u8) -> u32 {
+        self.presses.get(usize::from(key)).copied().unwrap_or(0)
+    }
+
+    /// the cycle `key` was last pressed at
+    pub fn pressed_at(&self, key: u8) -> Option<u64> {
+        self.pressed_at.get(usize::from(key)).copied().flatten()
+    }
+
+    /// Forgets the presses, keeping the keys held
+    fn clear_presses(&mut self) {
+        *self = Keys {
+            keys: self.keys,
+            ..Keys::default()
+        };
+    }
+}
+
+/// Why `System::run_batch` returned
+#[derive(Debug)]
+pub enum BatchStop {
+    /// all requested instructions were executed
+    Completed,
+    /// the last executed instruction changed the screen
+    Draw,
+    /// reached the zero instruction
+    Halt,
+    Error(SystemError),
+}
+
+#[derive(Debug)]
+pub struct BatchResult {
+    pub executed: u32,
+    pub stop: BatchStop,
+}
+
+/// Why `System::run_until` returned
+#[derive(Debug)]
+pub enum RunStop {
+    /// the deadline passed
+    Deadline,
+    /// the cancel flag was set
+    Cancelled,
+    /// blocked on `LD Vx, K`, nothing will happen until a key is pressed
+    WaitingForKey,
+    /// reached the zero instruction
+    Halt,
+    Error(SystemError),
 }
 
-const SCREEN_WIDTH: u8 = 64;
-const SCREEN_HEIGHT: u8 = 32;
+#[derive(Debug)]
+pub struct RunResult {
+    pub executed: u64,
+    pub stop: RunStop,
+}
+
+/// instructions `System::run_until` executes between looks at the clock and the cancel flag
+const RUN_CHECK_INTERVAL: u64 = 64;
+
+/// What the cpu is doing between instructions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Execution {
+    #[default]
+    Running,
+    /// blocked on `LD Vx, K` until a key is pressed
+    WaitingForKey,
+    /// reached the zero instruction
+    Halted,
+}
+
+pub const SCREEN_WIDTH: u8 = 64;
+pub const SCREEN_HEIGHT: u8 = 32;
 const SCREEN_LEN: usize = SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize / 8;
 
+/// A whole CHIP-8 machine. Everything it runs on is in here: there are no statics, and the
+/// random number generator is its own, so any number of systems can run side by side on as
+/// many threads without affecting each other. Only the seed of a new one comes from the
+/// thread's random generator, `seed` makes a run repeatable
 pub struct System {
-    pub mem: [u8; 4096],
+    pub mem: [u8; MEMORY_SIZE as usize],
     pub screen: [u8; SCREEN_LEN],
     pub registers: Registers,
     pub timers: Timers,
     pub stack: Stack,
     pub keys: Keys,
+    /// hex keys the program checked with `SKP` or `SKNP` or got from `LD Vx, K`, one bit per key
+    pub polled_keys: u16,
+    pub diagnostics: diagnostics::Diagnostics,
+    pub quirks: quirks::Quirks,
+    pub activity: activity::Activity,
+    /// instructions the program wrote over, when enabled
+    pub self_modification: selfmod::SelfModification,
+    /// bytes written over the program by hand, see `System::patch`
+    pub patches: patch::Patches,
+    /// which sprite drew each pixel
+    pub draws: draws::DrawTracker,
+    /// mapped into memory as the quirks say
+    pub devices: device::Devices,
+    pub execution: Execution,
+    /// instructions executed since the timers last ticked, how far into the frame the cpu is
+    pub frame_cycles: u32,
+    /// instructions executed since the program was loaded
+    pub cycles: u64,
+    /// what `load` fills memory and registers with before the program
+    pub memory_fill: diagnostics::MemoryFill,
+    /// the digits at 0x000, put in memory by `reset` and `load`
+    pub font: fonts::Font,
+    /// instructions added with `register_extension`
+    extensions: extension::Extensions,
+    /// added with `on_clear_screen` and the like
+    hooks: hooks::Hooks,
+    /// addresses of the ignored machine code calls warned about
+    machine_code_calls: BTreeSet<u16>,
     key_pressed: Option<u8>,
+    rng: rng::Rng,
 }
 
 impl Default for System {
     fn default() -> Self {
-        let mut mem = [0; 4096];
-        mem[..fonts::FONTS.len()].copy_from_slice(fonts::FONTS);
+        let mut mem = [0; MEMORY_SIZE as usize];
+        mem[..fonts::FONT_LEN].copy_from_slice(&fonts::SCHIP);
 
-        System {
-            mem: mem,
+        let mut system = System {
+            mem,
             screen: [0; SCREEN_LEN],
             registers: Default::default(),
             timers: Default::default(),
             stack: Default::default(),
             keys: Default::default(),
+            polled_keys: 0,
+            diagnostics: Default::default(),
+            quirks: Default::default(),
+            activity: Default::default(),
+            self_modification: Default::default(),
+            patches: Default::default(),
+            draws: Default::default(),
+            devices: Default::default(),
+            execution: Execution::Running,
+            frame_cycles: 0,
+            cycles: 0,
+            memory_fill: Default::default(),
+            font: fonts::SCHIP,
+            extensions: Default::default(),
+            hooks: Default::default(),
+            machine_code_calls: BTreeSet::new(),
             key_pressed: None,
-        }
+            rng: Default::default(),
+        };
+        system.forget_writes();
+        system
     }
 }
 
 impl System {
+    /// Resets the machine, keeping the quirks, diagnostics, self-modification tracking, memory
+    /// fill and font settings, the extensions and the hooks
     pub fn reset(&mut self) {
-        *self = System::default();
+        let mut system = System {
+            diagnostics: std::mem::take(&mut self.diagnostics),
+            self_modification: std::mem::take(&mut self.self_modification),
+            extensions: std::mem::take(&mut self.extensions),
+            hooks: std::mem::take(&mut self.hooks),
+            quirks: self.quirks,
+            memory_fill: self.memory_fill,
+            font: self.font,
+            ..System::default()
+        };
+        system.mem[..fonts::FONT_LEN].copy_from_slice(&system.font);
+        system.forget_writes();
+        system.self_modification.clear();
+        *self = system;
+    }
+
+    /// Only the fonts count as written for `Diagnostic::UninitializedRead`
+    fn forget_writes(&mut self) {
+        self.diagnostics.clear_initialized(self.mem.len());
+        self.diagnostics.set_initialized(0..fonts::FONT_LEN);
+    }
+
+    /// Puts the font in memory, then fills the rest of it and the registers as `memory_fill` says
+    fn fill_memory(&mut self) {
+        use diagnostics::MemoryFill;
+
+        let fill = self.memory_fill;
+        let mut rng = rng::Rng::new(self.rng.state());
+        let mut next = || match fill {
+            MemoryFill::Zero => 0,
+            MemoryFill::Ones => 0xFF,
+            MemoryFill::Random => rng.next_u8(),
+        };
+        self.mem[..fonts::FONT_LEN].copy_from_slice(&self.font);
+        for byte in self.mem[fonts::FONT_LEN..].iter_mut() {
+            *byte = next();
+        }
+        for reg in self.registers.reg.iter_mut() {
+            *reg = next();
+        }
+        self.registers.index = u16::from_be_bytes([next(), next()]) & 0xFFF;
+    }
+
+    /// Seeds the random number generator used by the Rand instruction.
+    /// Systems with the same seed, program and inputs behave identically
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = rng::Rng::new(seed);
+    }
+
+    /// Width and height of `screen`. Only the 64x32 mode of the CHIP-8 is emulated, the 128x64
+    /// one of the SUPER-CHIP is not
+    pub fn resolution(&self) -> (u32, u32) {
+        (u32::from(SCREEN_WIDTH), u32::from(SCREEN_HEIGHT))
     }
 
     pub fn screen(&self) -> Vec<u8> {
@@ -166,48 +411,135 @@ impl System {
             .collect()
     }
 
+    /// Screen as text, one line per row of 64 characters, `on` for lit pixels and `off` for unlit ones
+    pub fn screen_ascii(&self, on: char, off: char) -> String {
+        self.screen()
+            .chunks(SCREEN_WIDTH as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|pixel| if *pixel != 0 { on } else { off })
+                    .collect::<String>()
+                    + "\n"
+            })
+            .collect()
+    }
+
     pub fn load_from_file(&mut self, path: impl AsRef<std::path::Path>) -> Result<(), Error> {
         self.load(std::fs::File::open(path)?)
     }
 
+    /// Loads a program at `PROGRAM_START` and starts it from scratch: what a previous program
+    /// left in memory, the registers, stack, timers, screen and devices is cleared first.
+    /// The settings, the random number generator and the keys held are kept
     pub fn load(&mut self, mut src: impl std::io::Read) -> Result<(), Error> {
         let mut buf = Vec::new();
         src.read_to_end(&mut buf)?;
 
-        if buf.len() > self.mem.len() - PROGRAM_START as usize {
+        // the rest of a rom with banks goes to banks 1 and on
+        let fits = self.mem.len() - PROGRAM_START as usize;
+        let banked = match self.quirks.bank_port {
+            Some(_) => (device::BANKS - 1) * device::BANK_SIZE,
+            None => 0,
+        };
+        if buf.len() > fits + banked {
             bail!(SystemError::ProgramTooLarge);
         }
+        let (buf, banks) = buf.split_at(buf.len().min(fits));
 
-        self.mem[PROGRAM_START as usize..PROGRAM_START as usize + buf.len()].copy_from_slice(&buf);
+        self.clear_program();
+        self.fill_memory();
+        self.mem[PROGRAM_START as usize..PROGRAM_START as usize + buf.len()].copy_from_slice(buf);
+        self.diagnostics
+            .set_initialized(PROGRAM_START as usize..PROGRAM_START as usize + buf.len());
+        self.devices.bank_switch.load(banks);
 
         Ok(())
     }
 
+    /// `reset`, then `load`: as if the program was loaded into a new system with the same
+    /// settings, with the random number generator seeded anew
+    pub fn reload(&mut self, src: impl std::io::Read) -> Result<(), Error> {
+        self.reset();
+        self.load(src)
+    }
+
+    /// Everything a program changes while it runs, besides memory
+    fn clear_program(&mut self) {
+        self.screen = [0; SCREEN_LEN];
+        self.registers = Registers::default();
+        self.timers = Timers::default();
+        self.stack = Stack::default();
+        self.polled_keys = 0;
+        self.activity = Default::default();
+        self.draws.clear();
+        self.devices = Default::default();
+        self.execution = Execution::Running;
+        self.frame_cycles = 0;
+        self.cycles = 0;
+        self.keys.clear_presses();
+        self.key_pressed = None;
+        self.machine_code_calls.clear();
+        self.self_modification.clear();
+        self.patches.clear();
+        self.forget_writes();
+    }
+
     pub fn process_key_event(&mut self, key: u8, state: bool) -> Result<(), SystemError> {
-        self.keys.set_key_state(key, state)?;
+        self.keys.set_key_state(key, state, self.cycles)?;
         if state && self.key_pressed.is_none() {
             self.key_pressed = Some(key);
         }
         Ok(())
     }
 
+    /// Executes one instruction.
+    /// Arithmetic on `pc` and `I` wraps at 16 bits and never panics, an address that ends up
+    /// past the end of memory fails with `InvalidMemoryAccess` when it is accessed
     pub fn tick(&mut self, dbg: &mut debug::Debugger) -> Result<(), SystemError> {
+        let opcode = self.fetch_instruction()?;
+        self.execute(opcode, opcode::decode(opcode).map(|info| info.opcode), dbg)
+    }
+
+    /// `tick` with the instruction at `pc` already fetched as `opcode` and decoded as
+    /// `decoded`, for cores that keep instructions decoded
+    pub fn execute(
+        &mut self,
+        opcode: u16,
+        decoded: Option<opcode::Opcode>,
+        dbg: &mut debug::Debugger,
+    ) -> Result<(), SystemError> {
         use opcode::Opcode;
 
-        let opcode = self.fetch_instruction()?;
+        let pc = self.registers.pc;
+        self.diagnostics.check_pc(pc);
+        self.diagnostics.check_read(pc, pc);
+        match decoded {
+            Some(Opcode::AddReg | Opcode::SubReg | Opcode::RSubReg) => self.diagnostics.check_flag_register(pc, opcode, true),
+            Some(Opcode::RShiftReg | Opcode::LShiftReg) => self.diagnostics.check_flag_register(pc, opcode, false),
+            _ => {}
+        }
+        self.activity.record(pc, activity::Access::Execute);
+        self.activity.record(pc.wrapping_add(1), activity::Access::Execute);
+        self.self_modification.execute(pc);
 
-        dbg.debug(|| format!("OPCODE {:X}", opcode));
+        dbg.instruction(pc, opcode);
 
-        if opcode == 0 {
+        if opcode == 0 && self.quirks.zero_instruction != quirks::ZeroInstruction::Skip {
+            self.execution = Execution::Halted;
             return Err(SystemError::ZeroInstruction);
         }
+        self.execution = Execution::Running;
+        self.frame_cycles += 1;
+        self.cycles += 1;
 
         match_opcodes! {
-            opcode;
+            opcode, decoded;
 
             noarg Opcode::ClearScreen => {
                 dbg.debug("Clearing screen");
                 self.screen.copy_from_slice(&[0; SCREEN_LEN]);
+                self.draws.clear();
+                self.emit(hooks::Event::ClearScreen);
             },
 
             noarg Opcode::Return => {
@@ -216,7 +548,11 @@ impl System {
                 }
 
                 self.stack.sp -= 1;
-                self.registers.pc = self.stack.stack[self.stack.sp as usize];
+                self.registers.pc = *self
+                    .stack
+                    .stack
+                    .get(self.stack.sp as usize)
+                    .ok_or(SystemError::StackOverflow)?;
 
                 dbg.debug(|| format!("Returning to {:X} + 2", self.registers.pc));
             },
@@ -224,12 +560,13 @@ impl System {
             long addr = Opcode::Jump => {
                 dbg.debug(|| format!("Jumping to {:X}", addr));
 
+                self.diagnostics.check_jump(pc, addr);
                 self.registers.pc = addr;
                 return Ok(());
             },
 
             long addr = Opcode::Call => {
-                if self.stack.sp as usize >= self.stack.stack.len() {
+                if self.stack.sp as usize >= self.quirks.stack_depth() {
                     return Err(SystemError::StackOverflow);
                 }
 
@@ -237,7 +574,9 @@ impl System {
 
                 self.stack.stack[self.stack.sp as usize] = self.registers.pc;
                 self.stack.sp += 1;
+                self.stack.max_depth = self.stack.max_depth.max(self.stack.sp);
 
+                self.diagnostics.check_jump(pc, addr);
                 self.registers.pc = addr;
                 return Ok(());
             },
@@ -246,7 +585,7 @@ impl System {
                 dbg.debug(|| format!("Skip if v{:X} == {:X}", reg, val));
                 if self.registers.read(reg)? == val {
                     dbg.debug("Success");
-                    self.registers.pc += 2;
+                    self.registers.pc = self.registers.pc.wrapping_add(2);
                 } else {
                     dbg.debug("Fail");
                 }
@@ -256,7 +595,7 @@ impl System {
                 dbg.debug(|| format!("Skip if v{:X} != {:X}", reg, val));
                 if self.registers.read(reg)? != val {
                     dbg.debug("Success");
-                    self.registers.pc += 2;
+                    self.registers.pc = self.registers.pc.wrapping_add(2);
                 } else {
                     dbg.debug("Fail");
                 }
@@ -266,7 +605,7 @@ impl System {
                 dbg.debug(|| format!("Skip if v{:X} == v{:X}", reg1, reg2));
                 if self.registers.read(reg1)? == self.registers.read(reg2)? {
                     dbg.debug("Success");
-                    self.registers.pc += 2;
+                    self.registers.pc = self.registers.pc.wrapping_add(2);
                 } else {
                     dbg.debug("Fail");
                 }
@@ -276,7 +615,7 @@ impl System {
                 dbg.debug(|| format!("Skip if v{:X} != v{:X}", reg1, reg2));
                 if self.registers.read(reg1)? != self.registers.read(reg2)? {
                     dbg.debug("Success");
-                    self.registers.pc += 2;
+                    self.registers.pc = self.registers.pc.wrapping_add(2);
                 } else {
                     dbg.debug("Fail");
                 }
@@ -314,50 +653,32 @@ impl System {
             },
 
             (reg1, reg2) = Opcode::AddReg => {
-                let val = self.registers.read(reg2)?;
-                let carry = self.registers.with(reg1, |reg| {
-                    let (new, overflow) = reg.overflowing_add(val);
-                    *reg = new;
-                    overflow as u8
-                })?;
+                let (result, carry) = alu::add(self.registers.read(reg1)?, self.registers.read(reg2)?);
+                self.registers.write(reg1, result)?;
                 self.registers.carry_set(carry);
             },
 
             (reg1, reg2) = Opcode::SubReg => {
-                let val = self.registers.read(reg2)?;
-                let carry = self.registers.with(reg1, |reg| {
-                    let (new, overflow) = reg.overflowing_sub(val);
-                    *reg = new;
-                    !overflow as u8
-                })?;
+                let (result, carry) = alu::sub(self.registers.read(reg1)?, self.registers.read(reg2)?);
+                self.registers.write(reg1, result)?;
                 self.registers.carry_set(carry);
             },
 
             (reg, _a) = Opcode::RShiftReg => {
-                let carry = self.registers.with(reg, |reg| {
-                    let bit = *reg & 1;
-                    *reg >>= 1;
-                    bit
-                })?;
+                let (result, carry) = alu::shr(self.registers.read(reg)?);
+                self.registers.write(reg, result)?;
                 self.registers.carry_set(carry);
             },
 
             (reg1, reg2) = Opcode::RSubReg => {
-                let val = self.registers.read(reg2)?;
-                let carry = self.registers.with(reg1, |reg| {
-                    let (new, overflow) = val.overflowing_sub(*reg);
-                    *reg = new;
-                    !overflow as u8
-                })?;
+                let (result, carry) = alu::sub(self.registers.read(reg2)?, self.registers.read(reg1)?);
+                self.registers.write(reg1, result)?;
                 self.registers.carry_set(carry);
             },
 
             (reg, _a) = Opcode::LShiftReg => {
-                let carry = self.registers.with(reg, |reg| {
-                    let bit = (*reg >> 7) & 1;
-                    *reg <<= 1;
-                    bit
-                })?;
+                let (result, carry) = alu::shl(self.registers.read(reg)?);
+                self.registers.write(reg, result)?;
                 self.registers.carry_set(carry);
             },
 
@@ -366,27 +687,45 @@ impl System {
             },
 
             long addr = Opcode::JumpPlus => {
-                self.registers.pc = self.registers.read(0)? as u16 + addr;
+                self.registers.pc = u16::from(self.registers.read(0)?).wrapping_add(addr);
+                self.diagnostics.check_jump(pc, self.registers.pc);
                 return Ok(());
             },
 
             (reg, pattern) = Opcode::Rand => {
-                self.registers.write(reg, rand::random::<u8>() & pattern)?;
+                self.registers.write(reg, self.rng.next_u8() & pattern)?;
             },
 
             reg = Opcode::AddIndex => {
-                self.registers.index += self.registers.read(reg)? as u16;
+                self.registers.index = self
+                    .registers
+                    .index
+                    .wrapping_add(u16::from(self.registers.read(reg)?));
             },
 
-            key = Opcode::SkipIfKeyPressed => {
-                if self.keys.pressed(key)? {
-                    self.registers.pc += 2;
+            reg = Opcode::SkipIfKeyPressed => {
+                let key = self.registers.read(reg)?;
+                let pressed = self.keys.pressed(key)?;
+                self.polled_keys |= 1 << key;
+                dbg.debug(|| format!("Skip if key v{:X} = {:X} pressed", reg, key));
+                if pressed {
+                    dbg.debug("Success");
+                    self.registers.pc = self.registers.pc.wrapping_add(2);
+                } else {
+                    dbg.debug("Fail");
                 }
             },
 
-            key = Opcode::SkipIfKeyNotPressed => {
-                if !self.keys.pressed(key)? {
-                    self.registers.pc += 2;
+            reg = Opcode::SkipIfKeyNotPressed => {
+                let key = self.registers.read(reg)?;
+                let pressed = self.keys.pressed(key)?;
+                self.polled_keys |= 1 << key;
+                dbg.debug(|| format!("Skip if key v{:X} = {:X} not pressed", reg, key));
+                if !pressed {
+                    dbg.debug("Success");
+                    self.registers.pc = self.registers.pc.wrapping_add(2);
+                } else {
+                    dbg.debug("Fail");
                 }
             },
 
@@ -396,8 +735,10 @@ impl System {
 
             reg = Opcode::BlockGetKey => {
                 if let Some(key) = self.key_pressed {
+                    self.polled_keys |= 1 << key;
                     self.registers.write(reg, key)?;
                 } else {
+                    self.execution = Execution::WaitingForKey;
                     return Ok(());
                 }
             },
@@ -407,7 +748,13 @@ impl System {
             },
 
             reg = Opcode::SetSound => {
+                let prev_sound = self.timers.sound;
                 self.timers.sound = self.registers.read(reg)?;
+                match (prev_sound, self.timers.sound) {
+                    (0, 1..) => self.emit(hooks::Event::SoundStart),
+                    (1.., 0) => self.emit(hooks::Event::SoundStop),
+                    _ => {}
+                }
             },
 
             reg = Opcode::GetSprite => {
@@ -422,37 +769,66 @@ impl System {
                 val %= 10;
                 let third = val;
 
-                self.write_mem(self.registers.index, first)?;
-                self.write_mem(self.registers.index + 1, second)?;
-                self.write_mem(self.registers.index + 2, third)?;
+                let index = self.registers.index;
+                self.write_mem(index, first)?;
+                self.write_mem(index.wrapping_add(1), second)?;
+                self.write_mem(index.wrapping_add(2), third)?;
             },
 
             reg = Opcode::RegDump => {
                 for i in 0..=reg {
-                    self.write_mem(self.registers.index + i as u16, self.registers.read(i)?)?
+                    self.write_mem(self.registers.index.wrapping_add(u16::from(i)), self.registers.read(i)?)?
                 }
             },
 
             reg = Opcode::RegLoad => {
                 for i in 0..=reg {
-                    self.registers.write(i, self.read_mem(self.registers.index + i as u16)?)?;
+                    let value = self.read_mem(self.registers.index.wrapping_add(u16::from(i)))?;
+                    self.registers.write(i, value)?;
                 }
             },
 
             (x, y, height) = Opcode::Draw => {
-                let x = self.registers.read(x)?;
-                let y = self.registers.read(y)?;
+                let x = self.registers.read(x)? % SCREEN_WIDTH;
+                let y = self.registers.read(y)? % SCREEN_HEIGHT;
+                let height = match height {
+                    0 => self.quirks.zero_height_draw.rows(),
+                    height => height,
+                };
+
+                // all rows are read before any is drawn, a sprite past the end of memory fails
+                // without leaving half of it on the screen
+                let mut rows = [(0u16, 0u8); 16];
+                let rows = &mut rows[..usize::from(height)];
+                for (byte, row) in (0u16..).zip(rows.iter_mut()) {
+                    let addr = match self.quirks.sprite_past_memory {
+                        quirks::SpritePastMemory::Error => self.registers.index.wrapping_add(byte),
+                        quirks::SpritePastMemory::Wrap => self.registers.index.wrapping_add(byte) % MEMORY_SIZE,
+                    };
+                    if addr >= MEMORY_SIZE {
+                        return Err(SystemError::InvalidMemoryAccess { addr });
+                    }
+                    *row = (addr, self.read_mem(addr)?);
+                }
 
                 let mut carry = false;
-
-                for byte in 0..height {
-                    let value: u8 = self.read_mem(self.registers.index + byte as u16)?;
+                for (byte, &(addr, value)) in rows.iter().enumerate() {
                     for pixel in 0..8 {
-                        if self.draw(
-                            ((x as u16 + pixel as u16) % SCREEN_WIDTH as u16) as u8,
-                            ((y as u16 + byte as u16) % SCREEN_HEIGHT as u16) as u8,
-                            (value >> (7 - pixel)) & 1 != 0
-                        ) {
+                        let mut px = x as u16 + pixel as u16;
+                        let mut py = y as u16 + byte as u16;
+                        if self.quirks.wrap_sprites {
+                            px %= SCREEN_WIDTH as u16;
+                            py %= SCREEN_HEIGHT as u16;
+                        } else if px >= SCREEN_WIDTH as u16 || py >= SCREEN_HEIGHT as u16 {
+                            continue;
+                        }
+
+                        let bit = 7 - pixel;
+                        let set = (value >> bit) & 1 != 0;
+                        if set {
+                            self.draws.record(px as u8, py as u8, draws::SpritePixel { pc, addr, bit });
+                        }
+                        if self.draw(px as u8, py as u8, set) {
                             carry = true;
                         }
                     }
@@ -462,24 +838,173 @@ impl System {
             },
 
             otherwise x => {
-                unimplemented!("Unknown opcode: {:X}", x);
+                match self.run_extension(x)? {
+                    Some(extension::Step::Next) => {}
+                    Some(extension::Step::Jump) => return Ok(()),
+                    // 0000 only gets here to be skipped
+                    None if x == 0 => {}
+                    None if is_machine_code_call(x) => self.machine_code_call(pc, x & 0xFFF)?,
+                    None => return Err(SystemError::UnknownOpcode { opcode: x }),
+                }
             }
         }
 
-        self.registers.pc += 2;
+        let next = self.registers.pc.wrapping_add(2);
         self.key_pressed = None;
+        self.registers.pc = match self.quirks.pc_past_memory {
+            _ if next <= MEMORY_SIZE - 2 => next,
+            quirks::PcPastMemory::Error => return Err(SystemError::RanPastEndOfMemory { pc }),
+            quirks::PcPastMemory::Wrap => PROGRAM_START,
+        };
 
         Ok(())
     }
 
+    /// Adds an instruction for experiments without changing the decoder: opcodes that no
+    /// built-in instruction decodes and where `opcode & mask == pattern & mask` run `handler`
+    /// instead of failing with `UnknownOpcode`. The first registered match wins. After it the
+    /// program goes on to the next instruction, unless the handler returns `Step::Jump`
+    pub fn register_extension(
+        &mut self,
+        pattern: u16,
+        mask: u16,
+        handler: impl FnMut(&mut System, u16) -> Result<extension::Step, SystemError> + Send + 'static,
+    ) {
+        self.extensions.register(pattern, mask, Box::new(handler));
+    }
+
+    /// `SYS addr` at `pc`, as the `machine_code_call` quirk says
+    fn machine_code_call(&mut self, pc: u16, addr: u16) -> Result<(), SystemError> {
+        match self.quirks.machine_code_call {
+            quirks::MachineCodeCall::Error => Err(SystemError::MachineCodeCall { addr }),
+            quirks::MachineCodeCall::Ignore => {
+                if self.machine_code_calls.insert(pc) {
+                    log::warn!(target: "cpu", "{:03X}: ignoring a call to machine code at {:03X}", pc, addr);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Calls `hook` after every `CLS`
+    pub fn on_clear_screen(&mut self, hook: impl FnMut(&System) + Send + 'static) {
+        self.hooks.add(hooks::Event::ClearScreen, Box::new(hook));
+    }
+
+    /// Calls `hook` after every tick of the timers, the end of an emulated frame
+    pub fn on_frame_end(&mut self, hook: impl FnMut(&System) + Send + 'static) {
+        self.hooks.add(hooks::Event::FrameEnd, Box::new(hook));
+    }
+
+    /// Calls `hook` when the sound timer is set while it is 0, when the beep starts
+    pub fn on_sound_start(&mut self, hook: impl FnMut(&System) + Send + 'static) {
+        self.hooks.add(hooks::Event::SoundStart, Box::new(hook));
+    }
+
+    /// Calls `hook` when the sound timer runs out or is set to 0 while it runs
+    pub fn on_sound_stop(&mut self, hook: impl FnMut(&System) + Send + 'static) {
+        self.hooks.add(hooks::Event::SoundStop, Box::new(hook));
+    }
+
+    fn emit(&mut self, event: hooks::Event) {
+        if self.hooks.is_empty() {
+            return;
+        }
+        let mut hooks = std::mem::take(&mut self.hooks);
+        hooks.call(self, event);
+        self.hooks = hooks;
+    }
+
+    fn run_extension(&mut self, opcode: u16) -> Result<Option<extension::Step>, SystemError> {
+        // the handler gets the whole system, its own list included
+        let mut extensions = std::mem::take(&mut self.extensions);
+        let step = extensions.run(self, opcode);
+        self.extensions = extensions;
+        step.transpose()
+    }
+
+    /// Applies `inputs` and executes up to `n` instructions,
+    /// returning early after a draw, on the zero instruction or on error
+    pub fn run_batch(&mut self, n: u32, inputs: &Keys) -> BatchResult {
+        use opcode::Opcode;
+
+        for (key, state) in inputs.keys.iter().enumerate() {
+            if (self.keys.keys[key] != 0) == (*state != 0) {
+                continue;
+            }
+            if let Err(e) = self.process_key_event(key as u8, *state != 0) {
+                return BatchResult { executed: 0, stop: BatchStop::Error(e) };
+            }
+        }
+
+        let mut dbg = debug::Debugger::disabled();
+        for executed in 0..n {
+            let opcode = match self.fetch_instruction() {
+                Ok(opcode) => opcode,
+                Err(e) => return BatchResult { executed, stop: BatchStop::Error(e) },
+            };
+            let decoded = opcode::decode(opcode).map(|info| info.opcode);
+            let draws = matches!(decoded, Some(Opcode::Draw | Opcode::ClearScreen));
+
+            match self.execute(opcode, decoded, &mut dbg) {
+                Ok(()) if draws => {
+                    return BatchResult { executed: executed + 1, stop: BatchStop::Draw };
+                }
+                Ok(()) => {}
+                Err(SystemError::ZeroInstruction) => {
+                    return BatchResult { executed, stop: BatchStop::Halt };
+                }
+                Err(e) => return BatchResult { executed, stop: BatchStop::Error(e) },
+            }
+        }
+
+        BatchResult { executed: n, stop: BatchStop::Completed }
+    }
+
+    /// Executes instructions until `deadline` passes or `cancel` is set, for embedders that need
+    /// control back regularly. Both are checked every `RUN_CHECK_INTERVAL` instructions, so it
+    /// can overrun the deadline by that many. Timers are not decremented
+    pub fn run_until(&mut self, deadline: std::time::Instant, cancel: &std::sync::atomic::AtomicBool) -> RunResult {
+        use std::sync::atomic::Ordering;
+
+        let mut dbg = debug::Debugger::disabled();
+        let mut executed = 0;
+        loop {
+            if executed % RUN_CHECK_INTERVAL == 0 {
+                if cancel.load(Ordering::Relaxed) {
+                    return RunResult { executed, stop: RunStop::Cancelled };
+                }
+                if std::time::Instant::now() >= deadline {
+                    return RunResult { executed, stop: RunStop::Deadline };
+                }
+            }
+
+            match self.tick(&mut dbg) {
+                Ok(()) => executed += 1,
+                Err(SystemError::ZeroInstruction) => return RunResult { executed, stop: RunStop::Halt },
+                Err(e) => return RunResult { executed, stop: RunStop::Error(e) },
+            }
+            if self.execution == Execution::WaitingForKey {
+                return RunResult { executed, stop: RunStop::WaitingForKey };
+            }
+        }
+    }
+
     /// decrements delay and sound timers
     /// returns true if sound timer is reduced to zero
     pub fn dec_timers(&mut self) -> bool {
+        self.frame_cycles = 0;
+        self.devices.frame();
         self.timers.delay = self.timers.delay.saturating_sub(1);
         let prev_sound = self.timers.sound;
         self.timers.sound = self.timers.sound.saturating_sub(1);
 
-        prev_sound != 0 && self.timers.sound == 0
+        let sound_ended = prev_sound != 0 && self.timers.sound == 0;
+        if sound_ended {
+            self.emit(hooks::Event::SoundStop);
+        }
+        self.emit(hooks::Event::FrameEnd);
+        sound_ended
     }
 
     pub fn draw(&mut self, x: u8, y: u8, value: bool) -> bool {
@@ -496,11 +1021,23 @@ impl System {
             return current_bit && value;
         }
 
-        return false;
+        false
     }
 
+    /// moves past the current instruction without executing it, the whole of the extension
+    /// instructions longer than a word, see `compat::length`
+    pub fn skip_instruction(&mut self) {
+        let len = self.fetch_instruction().map_or(2, crate::compat::length);
+        self.registers.pc = self.registers.pc.wrapping_add(len);
+    }
+
+    /// The instruction at `pc`, which a jump may have left where no whole instruction fits
     pub fn fetch_instruction(&self) -> Result<u16, SystemError> {
-        self.read_mem_pair(self.registers.pc)
+        let pc = self.registers.pc;
+        if pc > MEMORY_SIZE - 2 {
+            return Err(SystemError::RanPastEndOfMemory { pc });
+        }
+        self.read_mem_pair(pc)
     }
 
     pub fn read_mem_pair(&self, ptr: u16) -> Result<u16, SystemError> {
@@ -511,7 +1048,7 @@ impl System {
         let snd = *self
             .mem
             .get(ptr as usize + 1)
-            .ok_or(SystemError::InvalidMemoryAccess { addr: ptr })?;
+            .ok_or(SystemError::InvalidMemoryAccess { addr: ptr.wrapping_add(1) })?;
 
         Ok((fst as u16) << 8 | snd as u16)
     }
@@ -526,24 +1063,584 @@ impl System {
 
         self.mem[ptr as usize] = fst;
         self.mem[ptr as usize + 1] = snd;
+        self.diagnostics.set_initialized(ptr as usize..ptr as usize + 2);
+        self.check_self_modification(ptr);
+        self.check_self_modification(ptr + 1);
+        self.activity.record(ptr, activity::Access::Write);
+        self.activity.record(ptr + 1, activity::Access::Write);
 
         Ok(())
     }
 
-    pub fn read_mem(&self, ptr: u16) -> Result<u8, SystemError> {
-        self.mem
+    /// Reads a byte on behalf of the program, recording the access in `activity`
+    pub fn read_mem(&mut self, ptr: u16) -> Result<u8, SystemError> {
+        if let Some((device, offset)) = self.devices.at(&self.quirks, ptr) {
+            let value = device.read(offset);
+            self.activity.record(ptr, activity::Access::Read);
+            return Ok(value);
+        }
+        let value = self
+            .mem
             .get(ptr as usize)
             .cloned()
-            .ok_or(SystemError::InvalidMemoryAccess { addr: ptr })
+            .ok_or(SystemError::InvalidMemoryAccess { addr: ptr })?;
+        self.diagnostics.check_read(self.registers.pc, ptr);
+        self.activity.record(ptr, activity::Access::Read);
+
+        Ok(value)
+    }
+
+    /// Memory in `range` for tools like the debugger and the analysis. Unlike `read_mem` the
+    /// read is not recorded and devices are not read, it is memory as it is
+    pub fn read_slice(&self, range: std::ops::Range<u16>) -> Result<&[u8], SystemError> {
+        check_range(&range)?;
+        Ok(&self.mem[usize::from(range.start)..usize::from(range.end)])
+    }
+
+    /// The words of memory in `range` decoded, see `opcode::instructions`. Like `read_slice`
+    /// devices are not read, and the part of `range` past the end of memory is left out
+    pub fn iter_instructions(
+        &self,
+        range: std::ops::Range<u16>,
+    ) -> impl Iterator<Item = (u16, Result<opcode::Instruction, u16>)> + '_ {
+        let end = range.end.min(MEMORY_SIZE);
+        let bytes = self.read_slice(range.start.min(end)..end).unwrap_or_default();
+        opcode::instructions(bytes, range.start)
+    }
+
+    /// Writes `data` from `addr` on behalf of tools, counting it as initialized memory but
+    /// not as a write of the program. Nothing is written when part of it is out of memory
+    pub fn write_slice(&mut self, addr: u16, data: &[u8]) -> Result<(), SystemError> {
+        let end = u16::try_from(usize::from(addr) + data.len()).unwrap_or(u16::MAX);
+        check_range(&(addr..end))?;
+        let range = usize::from(addr)..usize::from(end);
+        self.mem[range.clone()].copy_from_slice(data);
+        self.diagnostics.set_initialized(range);
+        Ok(())
     }
 
     pub fn write_mem(&mut self, ptr: u16, data: u8) -> Result<(), SystemError> {
+        if let Some((device, offset)) = self.devices.at(&self.quirks, ptr) {
+            device.write(offset, data);
+            self.devices.update_memory(&mut self.mem);
+            self.activity.record(ptr, activity::Access::Write);
+            return Ok(());
+        }
         if ptr as usize >= self.mem.len() {
             return Err(SystemError::InvalidMemoryAccess { addr: ptr });
         }
 
         self.mem[ptr as usize] = data;
+        self.diagnostics.set_initialized(ptr as usize..ptr as usize + 1);
+        self.activity.record(ptr, activity::Access::Write);
+        self.check_self_modification(ptr);
 
         Ok(())
     }
+
+    /// Reports a write of the program to `addr` over an instruction that ran
+    fn check_self_modification(&mut self, addr: u16) {
+        let pc = self.registers.pc;
+        if self.self_modification.write(pc, addr) {
+            self.diagnostics.report(diagnostics::Diagnostic::SelfModifying { pc, addr });
+        }
+    }
+}
+
+/// An error at the first address of `range` out of memory
+fn check_range(range: &std::ops::Range<u16>) -> Result<(), SystemError> {
+    if range.start > range.end || range.start > MEMORY_SIZE {
+        return Err(SystemError::InvalidMemoryAccess { addr: range.start });
+    }
+    if range.end > MEMORY_SIZE {
+        return Err(SystemError::InvalidMemoryAccess { addr: MEMORY_SIZE });
+    }
+    Ok(())
+}
+
+/// 0NNN besides 00E0, 00EE and the words of extension instructions
+pub fn is_machine_code_call(opcode: u16) -> bool {
+    opcode & 0xF000 == 0
+        && !opcode::Opcode::ClearScreen.matches(opcode)
+        && !opcode::Opcode::Return.matches(opcode)
+        && crate::compat::classify(opcode).is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_over_a_used_system() {
+        // draws the 0 digit, calls into the rest of the longer rom and fills V0..VF from it
+        let long = [
+            0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x61, 0x07, 0xF1, 0x15, 0x22, 0x0E, 0x00, 0x00, 0xA2, 0x00, 0xFF,
+            0x65, 0x12, 0x12,
+        ];
+        // spins at its start
+        let short = [0x12, 0x00];
+
+        let mut system = System::default();
+        system.seed(3);
+        system.load(&long[..]).unwrap();
+        let mut dbg = debug::Debugger::disabled();
+        for _ in 0..8 {
+            system.tick(&mut dbg).unwrap();
+        }
+        assert_eq!((system.stack.sp, system.timers.delay), (1, 7));
+        assert_ne!(system.screen, [0; SCREEN_LEN]);
+
+        system.load(&short[..]).unwrap();
+        assert!(system.mem[PROGRAM_START as usize + short.len()..].iter().all(|&byte| byte == 0));
+        assert_eq!(system.registers.pc, PROGRAM_START);
+        assert_eq!(system.registers.reg, [0; 16]);
+        assert_eq!((system.stack.sp, system.timers.delay), (0, 0));
+        assert_eq!(system.screen, [0; SCREEN_LEN]);
+        system.tick(&mut dbg).unwrap();
+        assert_eq!(system.registers.pc, PROGRAM_START);
+
+        let mut fresh = System::default();
+        fresh.load(&short[..]).unwrap();
+        system.reload(&short[..]).unwrap();
+        // a new system's generator is seeded from the clock
+        fresh.seed(5);
+        system.seed(5);
+        assert_eq!(system.state_hash(), fresh.state_hash());
+    }
+
+    #[test]
+    fn runs_extensions() {
+        // FX75 is not a CHIP-8 instruction, the extension stores X in VF
+        let rom = [0xF3, 0x75, 0x00, 0xE0, 0xF1, 0x76];
+        let mut system = System::default();
+        system.register_extension(0xF075, 0xF0FF, |system, opcode| {
+            system.registers.reg[0xF] = (opcode >> 8 & 0xF) as u8;
+            Ok(extension::Step::Next)
+        });
+        system.register_extension(0x00E0, 0xFFFF, |_, _| panic!("CLS is built in"));
+        system.reset();
+        system.load(&rom[..]).unwrap();
+
+        let mut dbg = debug::Debugger::disabled();
+        system.tick(&mut dbg).unwrap();
+        assert_eq!((system.registers.reg[0xF], system.registers.pc), (3, 0x202));
+        system.tick(&mut dbg).unwrap();
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::UnknownOpcode { opcode: 0xF176 })));
+    }
+
+    #[test]
+    fn calls_hooks() {
+        use hooks::Event;
+        use std::sync::{Arc, Mutex};
+
+        // ST = 2, CLS
+        let rom = [0x60, 0x02, 0xF0, 0x18, 0x00, 0xE0];
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut system = System::default();
+        for event in [Event::ClearScreen, Event::FrameEnd, Event::SoundStart, Event::SoundStop] {
+            let events = events.clone();
+            let hook = move |_: &System| events.lock().unwrap().push(event);
+            match event {
+                Event::ClearScreen => system.on_clear_screen(hook),
+                Event::FrameEnd => system.on_frame_end(hook),
+                Event::SoundStart => system.on_sound_start(hook),
+                Event::SoundStop => system.on_sound_stop(hook),
+            }
+        }
+        system.reset();
+        system.load(&rom[..]).unwrap();
+
+        let mut dbg = debug::Debugger::disabled();
+        for _ in 0..3 {
+            system.tick(&mut dbg).unwrap();
+        }
+        system.dec_timers();
+        assert!(system.dec_timers());
+        assert_eq!(
+            *events.lock().unwrap(),
+            [Event::SoundStart, Event::ClearScreen, Event::FrameEnd, Event::SoundStop, Event::FrameEnd]
+        );
+    }
+
+    #[test]
+    fn zero_height_draws_per_quirk() {
+        // DRW V0, V0, 0 twice with I at the font, 16 bytes of digits 0 to 3
+        let rom = [0xA0, 0x00, 0xD0, 0x00, 0xD0, 0x00];
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        for _ in 0..2 {
+            system.tick(&mut dbg).unwrap();
+        }
+        assert_eq!((system.screen, system.registers.reg[0xF]), ([0; SCREEN_LEN], 0));
+
+        system.quirks.zero_height_draw = quirks::ZeroHeightDraw::SixteenRows;
+        system.load(&rom[..]).unwrap();
+        for _ in 0..2 {
+            system.tick(&mut dbg).unwrap();
+        }
+        let rows = |system: &System| [0, 15, 16].map(|y| system.screen[y * 8]);
+        assert_eq!((rows(&system), system.registers.reg[0xF]), ([0xF0, 0xF0, 0], 0));
+        system.tick(&mut dbg).unwrap();
+        assert_eq!((rows(&system), system.registers.reg[0xF]), ([0; 3], 1));
+    }
+
+    #[test]
+    fn draws_wrap_their_start_and_clip_or_wrap_past_the_edge() {
+        let lit = |system: &System| {
+            let screen = system.screen_ascii('#', '.');
+            let mut lit = Vec::new();
+            for (y, row) in screen.lines().enumerate() {
+                lit.extend(row.match_indices('#').map(|(x, _)| (x, y)));
+            }
+            lit
+        };
+        // LD V0, x / LD V1, y / LD I, 20A / DRW V0, V1, 2 / JP 208, then the sprite
+        let run = |wrap_sprites: bool, x: u8, y: u8, sprite: [u8; 2]| {
+            let mut system = System::default();
+            system.quirks.wrap_sprites = wrap_sprites;
+            let rom = [0x60, x, 0x61, y, 0xA2, 0x0A, 0xD0, 0x12, 0x12, 0x08, sprite[0], sprite[1]];
+            system.load(&rom[..]).unwrap();
+            let mut dbg = debug::Debugger::disabled();
+            for _ in 0..4 {
+                system.tick(&mut dbg).unwrap();
+            }
+            lit(&system)
+        };
+
+        // the start is always wrapped, whatever the quirk
+        for wrap_sprites in [false, true] {
+            assert_eq!(run(wrap_sprites, 68, 35, [0x81, 0x80]), [(4, 3), (11, 3), (4, 4)]);
+        }
+
+        // starts at (60, 31), 4 columns and 1 row of it are past the edge
+        assert_eq!(run(false, 124, 63, [0xFF, 0xFF]), [(60, 31), (61, 31), (62, 31), (63, 31)]);
+        let wrapped = run(true, 124, 63, [0xFF, 0xFF]);
+        let columns = [60, 61, 62, 63, 0, 1, 2, 3];
+        let mut expected = [31, 0].iter().flat_map(|&y| columns.iter().map(move |&x| (x, y))).collect::<Vec<_>>();
+        expected.sort_by_key(|&(x, y)| (y, x));
+        assert_eq!(wrapped, expected);
+    }
+
+    #[test]
+    fn iter_instructions() {
+        let rom = [0x00, 0xE0, 0x12, 0x34, 0xFF, 0xFF];
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        let decoded = system
+            .iter_instructions(PROGRAM_START..PROGRAM_START + 6)
+            .map(|(addr, instruction)| (addr, instruction.map(|instruction| instruction.info.opcode)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decoded,
+            [(0x200, Ok(opcode::Opcode::ClearScreen)), (0x202, Ok(opcode::Opcode::Jump)), (0x204, Err(0xFFFF))]
+        );
+        assert_eq!(system.iter_instructions(0xFFE..0x1010).count(), 1);
+    }
+
+    #[test]
+    fn sprite_past_memory_per_quirk() {
+        // DRW V0, V0, 4 with I two bytes before the end of memory
+        let rom = [0xAF, 0xFE, 0xD0, 0x04];
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        system.mem[0xFFE] = 0xFF;
+        system.tick(&mut dbg).unwrap();
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::InvalidMemoryAccess { addr: 0x1000 })));
+        assert_eq!(system.screen, [0; SCREEN_LEN]);
+
+        system.quirks.sprite_past_memory = quirks::SpritePastMemory::Wrap;
+        system.load(&rom[..]).unwrap();
+        system.mem[0xFFE] = 0xFF;
+        system.tick(&mut dbg).unwrap();
+        system.tick(&mut dbg).unwrap();
+        // 0xFFE, 0xFFF and then the top two rows of the font's 0
+        let rows = [0, 1, 2, 3].map(|y| system.screen[y * 8]);
+        assert_eq!(rows, [0xFF, 0, 0xF0, 0x90]);
+    }
+
+    #[test]
+    fn zero_instruction_per_quirk() {
+        let rom = [0x00, 0x00, 0x60, 0x01];
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::ZeroInstruction)));
+        assert_eq!(system.execution, Execution::Halted);
+
+        system.quirks.zero_instruction = quirks::ZeroInstruction::Skip;
+        system.tick(&mut dbg).unwrap();
+        system.tick(&mut dbg).unwrap();
+        assert_eq!((system.registers.reg[0], system.execution), (1, Execution::Running));
+    }
+
+    #[test]
+    fn machine_code_calls_per_quirk() {
+        // SYS 0x123, then the SCHIP high resolution instruction
+        let rom = [0x01, 0x23, 0x00, 0xFF];
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        system.tick(&mut dbg).unwrap();
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::UnknownOpcode { opcode: 0x00FF })));
+
+        system.quirks.machine_code_call = quirks::MachineCodeCall::Error;
+        system.load(&rom[..]).unwrap();
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::MachineCodeCall { addr: 0x123 })));
+    }
+
+    #[test]
+    fn reports_vf_in_arithmetic() {
+        let rom = [
+            0x80, 0x14, // ADD V0, V1
+            0x8F, 0x14, // ADD VF, V1
+            0x80, 0xF5, // SUB V0, VF
+            0x80, 0xF6, // SHR V0 (VF is not read)
+        ];
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        system.diagnostics.flag_register = true;
+        system.load(&rom[..]).unwrap();
+        for _ in 0..4 {
+            system.tick(&mut dbg).unwrap();
+        }
+        // each instruction is reported once
+        system.registers.pc = 0x202;
+        system.tick(&mut dbg).unwrap();
+
+        let reported = system.diagnostics.take();
+        assert_eq!(reported, [
+            diagnostics::Diagnostic::FlagRegister { pc: 0x202, opcode: 0x8F14 },
+            diagnostics::Diagnostic::FlagRegister { pc: 0x204, opcode: 0x80F5 },
+        ]);
+        assert_eq!(reported[0].to_string(), "202: 8F14 writes its result to VF, the flag overwrites it");
+    }
+
+    #[test]
+    fn slices_are_bounds_checked() {
+        let mut system = System::default();
+        system.write_slice(0xFFE, &[1, 2]).unwrap();
+        assert_eq!(system.read_slice(0xFFD..MEMORY_SIZE).unwrap(), &[0, 1, 2]);
+        assert!(system.read_slice(0x200..0x200).unwrap().is_empty());
+
+        assert!(matches!(
+            system.read_slice(0xFFF..0x1001),
+            Err(SystemError::InvalidMemoryAccess { addr: MEMORY_SIZE })
+        ));
+        assert!(system.write_slice(0xFFF, &[3, 4]).is_err());
+        assert_eq!(system.mem[0xFFF], 2);
+    }
+
+    #[test]
+    fn stops_or_wraps_past_the_end_of_memory() {
+        let mut system = System::default();
+        let mut dbg = debug::Debugger::disabled();
+        // LD V0, 1 in the last word
+        system.write_slice(0xFFE, &[0x60, 0x01]).unwrap();
+        system.registers.pc = 0xFFE;
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::RanPastEndOfMemory { pc: 0xFFE })));
+        assert_eq!(system.registers.pc, 0xFFE);
+
+        system.quirks.pc_past_memory = quirks::PcPastMemory::Wrap;
+        system.tick(&mut dbg).unwrap();
+        assert_eq!(system.registers.pc, PROGRAM_START);
+
+        // a jump can still land on the last byte
+        system.registers.pc = 0xFFF;
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::RanPastEndOfMemory { pc: 0xFFF })));
+    }
+
+    #[test]
+    fn skips_off_the_end_of_memory_per_quirk() {
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        // SE V0, 0 in the second to last word skips the last one
+        system.write_slice(0xFFC, &[0x30, 0x00, 0x60, 0x01]).unwrap();
+        system.registers.pc = 0xFFC;
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::RanPastEndOfMemory { pc: 0xFFC })));
+
+        system.quirks.pc_past_memory = quirks::PcPastMemory::Wrap;
+        system.registers.pc = 0xFFC;
+        system.tick(&mut dbg).unwrap();
+        assert_eq!(system.registers.pc, PROGRAM_START);
+    }
+
+    #[test]
+    fn index_arithmetic_wraps_at_16_bits() {
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        // ADD I, V0
+        system.load(&[0xF0, 0x1E][..]).unwrap();
+        system.registers.reg[0] = 0xFF;
+        system.registers.index = 0xFFFF;
+        system.tick(&mut dbg).unwrap();
+        assert_eq!(system.registers.index, 0x00FE);
+    }
+
+    #[test]
+    fn memory_instructions_fail_at_the_end_of_memory() {
+        let mut dbg = debug::Debugger::disabled();
+        // LD B, V0 / LD [I], V2 / LD V2, [I], each run with I at the end of memory and past it
+        for opcode in [0xF033, 0xF255, 0xF265] {
+            for (index, addr) in [(0xFFE, 0x1000), (0xFFFF, 0xFFFF)] {
+                let mut system = System::default();
+                system.load(&[(opcode >> 8) as u8, opcode as u8][..]).unwrap();
+                system.registers.reg[0] = 123;
+                system.registers.index = index;
+                let result = system.tick(&mut dbg);
+                assert!(
+                    matches!(result, Err(SystemError::InvalidMemoryAccess { addr: a }) if a == addr),
+                    "{:04X} with I at {:04X}: {:?}",
+                    opcode,
+                    index,
+                    result
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn jumps_past_memory_with_v0_fail_on_fetch() {
+        let mut dbg = debug::Debugger::disabled();
+        let mut system = System::default();
+        // JP V0, FFF
+        system.load(&[0xBF, 0xFF][..]).unwrap();
+        system.registers.reg[0] = 0xFF;
+        system.tick(&mut dbg).unwrap();
+        assert_eq!(system.registers.pc, 0x10FE);
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::RanPastEndOfMemory { pc: 0x10FE })));
+    }
+
+    #[test]
+    fn runs_until_stopped() {
+        use std::sync::atomic::AtomicBool;
+        use std::time::{Duration, Instant};
+
+        // spins at its start
+        let mut system = System::default();
+        system.load(&[0x12, 0x00][..]).unwrap();
+        let later = Instant::now() + Duration::from_secs(60);
+
+        let result = system.run_until(later, &AtomicBool::new(true));
+        assert!(matches!(result.stop, RunStop::Cancelled));
+        assert_eq!(result.executed, 0);
+        let result = system.run_until(Instant::now() + Duration::from_millis(5), &AtomicBool::new(false));
+        assert!(matches!(result.stop, RunStop::Deadline));
+        assert!(result.executed > 0);
+
+        // V0 = 1, then waits for a key
+        system.load(&[0x60, 0x01, 0xF1, 0x0A][..]).unwrap();
+        let result = system.run_until(later, &AtomicBool::new(false));
+        assert!(matches!(result.stop, RunStop::WaitingForKey));
+        system.load(&[0x60, 0x01][..]).unwrap();
+        let result = system.run_until(later, &AtomicBool::new(false));
+        assert!(matches!(result.stop, RunStop::Halt));
+        assert_eq!(result.executed, 1);
+    }
+
+    #[test]
+    fn run_batch_stops_per_reason() {
+        // spins at its start
+        let mut system = System::default();
+        system.load(&[0x12, 0x00][..]).unwrap();
+        let result = system.run_batch(100, &Keys::default());
+        assert!(matches!(result.stop, BatchStop::Completed));
+        assert_eq!((result.executed, system.cycles), (100, 100));
+
+        // LD V0, 1 / CLS / DRW V0, V0, 1 / LD V1, 2, then the zero instruction
+        system.load(&[0x60, 0x01, 0x00, 0xE0, 0xD0, 0x01, 0x61, 0x02][..]).unwrap();
+        let mut inputs = Keys::default();
+        inputs.keys[7] = 1;
+        let result = system.run_batch(100, &inputs);
+        assert!(matches!(result.stop, BatchStop::Draw));
+        assert_eq!((result.executed, system.registers.pc), (2, 0x204));
+        assert!(system.keys.pressed(7).unwrap());
+        let result = system.run_batch(100, &inputs);
+        assert!(matches!(result.stop, BatchStop::Draw));
+        assert_eq!(result.executed, 1);
+        assert_ne!(system.screen, [0; SCREEN_LEN]);
+        let result = system.run_batch(100, &inputs);
+        assert!(matches!(result.stop, BatchStop::Halt));
+        assert_eq!((result.executed, system.registers.reg[1]), (1, 2));
+    }
+
+    #[test]
+    fn the_trace_counts_cycles_while_off() {
+        // SKP V5 / JP 200
+        let mut system = System::default();
+        system.load(&[0xE5, 0x9E, 0x12, 0x00][..]).unwrap();
+        let mut trace = debug::Debugger::disabled();
+        for _ in 0..3 {
+            system.tick(&mut trace).unwrap();
+        }
+        trace.set_enabled(true);
+        system.tick(&mut trace).unwrap();
+        assert_eq!(trace.cycle(), 4);
+    }
+
+    #[test]
+    fn systems_run_independently_on_threads() {
+        // RND V0, FF / RND V1, 1F / LD I, 20C / DRW V0, V1, 1 / JP 200, a one pixel sprite
+        let rom = [0xC0, 0xFF, 0xC1, 0x1F, 0xA2, 0x0C, 0xD0, 0x11, 0x12, 0x00, 0x00, 0x00, 0x80];
+        let run = move |seed: u64| {
+            let mut system = System::default();
+            system.load(&rom[..]).unwrap();
+            system.seed(seed);
+            let mut dbg = debug::Debugger::disabled();
+            for _ in 0..2000 {
+                system.tick(&mut dbg).unwrap();
+            }
+            system.state_hash()
+        };
+
+        let threads = (0..100).map(|seed| std::thread::spawn(move || run(seed))).collect::<Vec<_>>();
+        let hashes = threads.into_iter().map(|thread| thread.join().unwrap()).collect::<Vec<_>>();
+        assert_eq!(hashes, (0..100).map(run).collect::<Vec<_>>());
+        assert_eq!(hashes.iter().collect::<BTreeSet<_>>().len(), 100);
+    }
+
+    #[test]
+    fn skips_on_the_key_in_vx() {
+        // LD V3, A / SKP V3 / LD V0, 1 / SKNP V3 / LD V1, 1
+        let rom = [0x63, 0x0A, 0xE3, 0x9E, 0x60, 0x01, 0xE3, 0xA1, 0x61, 0x01];
+        let mut system = System::default();
+        system.load(&rom[..]).unwrap();
+        system.process_key_event(0xA, true).unwrap();
+        let mut dbg = debug::Debugger::disabled();
+        for _ in 0..4 {
+            system.tick(&mut dbg).unwrap();
+        }
+        assert_eq!((system.registers.pc, system.registers.reg[0], system.registers.reg[1]), (0x20A, 0, 1));
+        assert_eq!(system.polled_keys, 1 << 0xA);
+
+        // a value in VX that is no key fails
+        system.load(&[0x63, 0x10, 0xE3, 0x9E][..]).unwrap();
+        system.tick(&mut dbg).unwrap();
+        assert!(matches!(system.tick(&mut dbg), Err(SystemError::InvalidKey { key: 0x10 })));
+    }
+
+    #[test]
+    fn counts_key_presses_with_their_cycle() {
+        let mut system = System::default();
+        system.load(&[0x12, 0x00][..]).unwrap();
+        let mut dbg = debug::Debugger::disabled();
+        system.tick(&mut dbg).unwrap();
+        system.process_key_event(5, true).unwrap();
+        // a held key pressed again is no new press
+        system.process_key_event(5, true).unwrap();
+        system.tick(&mut dbg).unwrap();
+        system.process_key_event(0xA, true).unwrap();
+        system.process_key_event(5, false).unwrap();
+
+        assert_eq!((system.keys.presses(5), system.keys.pressed_at(5)), (1, Some(1)));
+        assert_eq!((system.keys.presses(0xA), system.keys.pressed_at(0xA)), (1, Some(2)));
+        assert_eq!(system.keys.last_pressed(), Some(0xA));
+        assert!(system.keys.any_pressed());
+
+        // a new program starts counting again, with the keys still held
+        system.load(&[0x12, 0x00][..]).unwrap();
+        assert_eq!((system.keys.presses(0xA), system.keys.last_pressed()), (0, None));
+        assert!(system.keys.pressed(0xA).unwrap());
+    }
 }
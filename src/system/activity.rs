@@ -0,0 +1,40 @@
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read = 0,
+    Write = 1,
+    Execute = 2,
+}
+
+/// Recent memory activity: per address and kind of access, a level that is set on access
+/// and fades away with `decay`
+pub struct Activity {
+    levels: Vec<[u8; 3]>,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Activity {
+            levels: vec![[0; 3]; 4096],
+        }
+    }
+}
+
+impl Activity {
+    pub fn record(&mut self, addr: u16, access: Access) {
+        if let Some(level) = self.levels.get_mut(addr as usize) {
+            level[access as usize] = u8::MAX;
+        }
+    }
+
+    /// Fades all levels, called once per frame. An access stays visible for about a second
+    pub fn decay(&mut self) {
+        for level in self.levels.iter_mut().flatten() {
+            *level = level.saturating_sub(*level / 16 + 1);
+        }
+    }
+
+    /// read, write and execute levels of every address
+    pub fn levels(&self) -> &[[u8; 3]] {
+        &self.levels
+    }
+}
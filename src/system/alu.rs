@@ -0,0 +1,82 @@
+//! Results and VF flags of the 8XYN arithmetic instructions.
+//! The flag has to be written after the result, so that it wins when VF is also the destination
+
+/// 8XY4: the sum and 1 on carry
+pub fn add(a: u8, b: u8) -> (u8, u8) {
+    let (result, carry) = a.overflowing_add(b);
+    (result, carry as u8)
+}
+
+/// 8XY5 computes `sub(x, y)`, 8XY7 `sub(y, x)`: the difference and 1 when there is no borrow
+pub fn sub(a: u8, b: u8) -> (u8, u8) {
+    let (result, borrow) = a.overflowing_sub(b);
+    (result, !borrow as u8)
+}
+
+/// 8XY6: the value shifted right and the bit shifted out
+pub fn shr(a: u8) -> (u8, u8) {
+    (a >> 1, a & 1)
+}
+
+/// 8XYE: the value shifted left and the bit shifted out
+pub fn shl(a: u8) -> (u8, u8) {
+    (a << 1, a >> 7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::{debug::Debugger, System};
+
+    fn pairs() -> impl Iterator<Item = (u8, u8)> {
+        (0..=255).flat_map(|a| (0..=255).map(move |b| (a, b)))
+    }
+
+    #[test]
+    fn add_all_pairs() {
+        for (a, b) in pairs() {
+            let sum = u16::from(a) + u16::from(b);
+            assert_eq!(add(a, b), (sum as u8, (sum > 0xFF) as u8), "{} + {}", a, b);
+        }
+    }
+
+    #[test]
+    fn sub_all_pairs() {
+        for (a, b) in pairs() {
+            let difference = i16::from(a) - i16::from(b);
+            assert_eq!(sub(a, b), (difference as u8, (difference >= 0) as u8), "{} - {}", a, b);
+        }
+    }
+
+    #[test]
+    fn shifts_all_values() {
+        for a in 0..=255u8 {
+            assert_eq!(shr(a), (a / 2, a % 2), "{} >> 1", a);
+            assert_eq!(shl(a), ((u16::from(a) * 2) as u8, (a >= 0x80) as u8), "{} << 1", a);
+        }
+    }
+
+    /// Runs `opcode` with V1 = `x` and VF = `f`, returning VF afterwards
+    fn run_with_vf(opcode: u16, x: u8, f: u8) -> u8 {
+        let mut system = System::default();
+        system.load(&opcode.to_be_bytes()[..]).unwrap();
+        system.registers.reg[1] = x;
+        system.registers.reg[15] = f;
+        system.tick(&mut Debugger::disabled()).unwrap();
+        system.registers.reg[15]
+    }
+
+    #[test]
+    fn flag_is_written_after_the_result() {
+        for (x, f) in pairs() {
+            assert_eq!(run_with_vf(0x8F14, x, f), add(f, x).1, "VF = {} + {}", f, x);
+            assert_eq!(run_with_vf(0x8F15, x, f), sub(f, x).1, "VF = {} - {}", f, x);
+            assert_eq!(run_with_vf(0x8F17, x, f), sub(x, f).1, "VF = {} - {}", x, f);
+            assert_eq!(run_with_vf(0x81F4, x, f), add(x, f).1, "V1 = {} + {}", x, f);
+        }
+        for f in 0..=255u8 {
+            assert_eq!(run_with_vf(0x8F06, 0, f), shr(f).1, "VF = {} >> 1", f);
+            assert_eq!(run_with_vf(0x8F0E, 0, f), shl(f).1, "VF = {} << 1", f);
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use failure::Error;
+
+use super::debug::Debugger;
+use super::machine::Chip8Core;
+use super::opcode::{self, Opcode};
+use super::{System, SystemError, MEMORY_SIZE};
+
+/// instructions translated at most into one block
+const MAX_BLOCK: usize = 64;
+/// blocks kept before all of them are dropped, a program that keeps rewriting itself would
+/// otherwise grow the cache forever
+const MAX_BLOCKS: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+struct Instruction {
+    /// the word it was decoded from
+    opcode: u16,
+    decoded: Option<Opcode>,
+}
+
+/// Instructions from `start` up to and including the first that jumps, calls or returns
+struct Block {
+    start: u16,
+    /// empty once invalidated
+    instructions: Vec<Instruction>,
+}
+
+impl Block {
+    fn covers(&self, addr: u16) -> bool {
+        addr >= self.start && usize::from(addr - self.start) < self.instructions.len() * 2
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub blocks: u64,
+    /// blocks dropped because memory under them changed
+    pub invalidated: u64,
+    /// the program wrote over an instruction it ran, so the rest of it is interpreted
+    pub disabled: bool,
+}
+
+/// An experimental core that decodes basic blocks once and runs them again without decoding,
+/// for fast headless runs. Every instruction is checked against the word in memory before it
+/// runs, a block with a word that changed (written by the program, a bank switch, a loaded
+/// state or the debugger) is dropped and translated again. A program that writes over its
+/// own instructions would keep doing that, so the core stops translating once one does, until
+/// the next rom is loaded. Runs exactly like `System`
+pub struct CachedCore {
+    system: System,
+    blocks: Vec<Block>,
+    /// index in `blocks` of the block that starts at each address
+    starts: Vec<Option<usize>>,
+    /// address, block and instruction in it that run next, if the program goes on in a
+    /// straight line
+    next: Option<(u16, usize, usize)>,
+    pub stats: CacheStats,
+}
+
+impl Default for CachedCore {
+    fn default() -> Self {
+        CachedCore::new(System::default())
+    }
+}
+
+impl CachedCore {
+    pub fn new(mut system: System) -> Self {
+        system.self_modification.enabled = true;
+        CachedCore {
+            system,
+            blocks: Vec::new(),
+            starts: vec![None; usize::from(MEMORY_SIZE)],
+            next: None,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn flush(&mut self) {
+        self.blocks.clear();
+        self.starts.iter_mut().for_each(|start| *start = None);
+        self.next = None;
+    }
+
+    /// Drops every block with an instruction at `addr`
+    fn invalidate(&mut self, addr: u16) {
+        for block in self.blocks.iter_mut().filter(|block| block.covers(addr)) {
+            self.starts[usize::from(block.start)] = None;
+            block.instructions.clear();
+            self.stats.invalidated += 1;
+        }
+        self.next = None;
+    }
+
+    /// Decodes the block at `start`, returning its index
+    fn translate(&mut self, start: u16) -> usize {
+        if self.blocks.len() >= MAX_BLOCKS {
+            self.flush();
+        }
+
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        while let (true, Ok(opcode)) = (instructions.len() < MAX_BLOCK, self.system.read_mem_pair(addr)) {
+            let decoded = opcode::decode(opcode).map(|info| info.opcode);
+            instructions.push(Instruction { opcode, decoded });
+            match decoded {
+                Some(Opcode::Jump | Opcode::JumpPlus | Opcode::Call | Opcode::Return) | None => break,
+                _ => addr = addr.wrapping_add(2),
+            }
+        }
+
+        self.blocks.push(Block { start, instructions });
+        self.starts[usize::from(start)] = Some(self.blocks.len() - 1);
+        self.stats.blocks += 1;
+        self.blocks.len() - 1
+    }
+
+    /// The decoded instruction at `pc` and where it is in the cache, translating it if it is
+    /// not there or changed
+    fn instruction(&mut self, pc: u16, opcode: u16) -> (usize, usize, Instruction) {
+        let cached = match self.next {
+            Some((next, block, index)) if next == pc => Some((block, index)),
+            _ => self.starts[usize::from(pc)].map(|block| (block, 0)),
+        };
+        if let Some((block, index)) = cached {
+            let instruction = self.blocks[block].instructions[index];
+            if instruction.opcode == opcode {
+                return (block, index, instruction);
+            }
+            self.invalidate(pc);
+        }
+
+        let block = self.translate(pc);
+        (block, 0, self.blocks[block].instructions[0])
+    }
+}
+
+impl Chip8Core for CachedCore {
+    fn load(&mut self, rom: &[u8]) -> Result<(), Error> {
+        self.flush();
+        self.stats.disabled = false;
+        self.system.load(rom)
+    }
+
+    fn reset(&mut self) {
+        self.flush();
+        self.stats.disabled = false;
+        self.system.reset();
+    }
+
+    fn step(&mut self, dbg: &mut Debugger) -> Result<(), SystemError> {
+        if self.stats.disabled {
+            return self.system.tick(dbg);
+        }
+        let pc = self.system.registers.pc;
+        let opcode = self.system.fetch_instruction()?;
+        let (block, index, instruction) = self.instruction(pc, opcode);
+
+        self.next = None;
+        self.system.execute(instruction.opcode, instruction.decoded, dbg)?;
+        if self.system.self_modification.is_self_modifying() {
+            self.stats.invalidated += self.blocks.iter().filter(|block| !block.instructions.is_empty()).count() as u64;
+            self.flush();
+            self.stats.disabled = true;
+            return Ok(());
+        }
+        let next = pc.wrapping_add(2);
+        if self.system.registers.pc == next && index + 1 < self.blocks[block].instructions.len() {
+            self.next = Some((next, block, index + 1));
+        }
+        Ok(())
+    }
+
+    fn tick_timers(&mut self) -> bool {
+        self.system.dec_timers()
+    }
+
+    fn key_event(&mut self, key: u8, pressed: bool) -> Result<(), SystemError> {
+        self.system.process_key_event(key, pressed)
+    }
+
+    fn state(&self) -> &System {
+        &self.system
+    }
+
+    fn state_mut(&mut self) -> &mut System {
+        &mut self.system
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_like_the_interpreter() {
+        // counts in V1 and once it reaches 100 rewrites the second ADD into one of V2
+        let rom = [
+            0xA2, 0x06, // LD I, 0x206
+            0x60, 0x72, // LD V0, 0x72
+            0x71, 0x01, // ADD V1, 1
+            0x71, 0x01, // ADD V1, 1 (rewritten)
+            0x31, 0x64, // SE V1, 100
+            0x12, 0x04, // JP 0x204
+            0xF0, 0x55, // LD [I], V0
+            0x12, 0x04, // JP 0x204
+        ];
+        let mut system = System::default();
+        system.seed(1);
+        let mut cached = CachedCore::new(system);
+        Chip8Core::load(&mut cached, &rom).unwrap();
+        let mut interpreter = System::default();
+        interpreter.seed(1);
+        interpreter.load(&rom[..]).unwrap();
+
+        let mut dbg = Debugger::disabled();
+        for _ in 0..1000 {
+            interpreter.tick(&mut dbg).unwrap();
+            cached.step(&mut dbg).unwrap();
+        }
+        assert_eq!(cached.state().state_hash(), interpreter.state_hash());
+        assert_ne!(interpreter.registers.reg[2], 0);
+        assert!(cached.stats.invalidated > 0);
+        assert!(cached.stats.disabled);
+        assert_eq!(cached.state().self_modification.modified().next(), Some((0x206, 0x20C)));
+    }
+}
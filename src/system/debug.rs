@@ -1,25 +1,57 @@
+/// The instruction trace, logged to the `cpu` target at debug level while enabled. Every line
+/// starts with the cycle: the number of the instruction it is about, or for a key event the
+/// instruction it came after, so presses line up with the instructions that polled the keys
 pub struct Debugger {
-    enabled: bool
+    enabled: bool,
+    /// instructions seen, also while disabled
+    cycle: u64,
 }
 
 impl Debugger {
     pub fn enabled() -> Self {
         Debugger {
             enabled: true,
+            cycle: 0,
         }
     }
 
     pub fn disabled() -> Self {
         Debugger {
             enabled: false,
+            cycle: 0,
         }
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// instructions started so far
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
     pub fn debug(&mut self, s: impl DebugSource) {
         if self.enabled {
-            eprintln!("{}", s.get().as_ref());
+            log::debug!(target: "cpu", "{:>10}  {}", self.cycle, s.get().as_ref());
         }
     }
+
+    /// Starts the entry of the instruction at `pc`, the lines after it until the next one
+    /// have its cycle
+    pub fn instruction(&mut self, pc: u16, opcode: u16) {
+        self.cycle += 1;
+        self.debug(|| format!("{:03X} OPCODE {:04X}", pc, opcode));
+    }
+
+    /// A key pressed or released after the last instruction
+    pub fn key_event(&mut self, key: u8, pressed: bool) {
+        self.debug(|| format!("KEY {:X} {}", key, if pressed { "pressed" } else { "released" }));
+    }
 }
 
 pub trait DebugSource {
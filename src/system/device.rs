@@ -0,0 +1,188 @@
+use super::quirks::Quirks;
+
+/// Hardware mapped into memory: the program's reads of its addresses return its registers and
+/// writes go to it instead of to memory. No original interpreter had any, they are extensions
+/// for homebrew, mapped where the quirks say and nowhere by default
+pub trait Device {
+    /// addresses taken from the start of the mapping
+    fn size(&self) -> u16;
+    fn read(&self, offset: u16) -> u8;
+    fn write(&mut self, offset: u16, value: u8);
+    /// called when the timers tick, 60 times per second
+    fn frame(&mut self) {}
+}
+
+/// Counts timer ticks. Reads as a big-endian u16 that wraps around, writing either byte
+/// resets it to 0, to time a stretch of code in frames
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameCounter {
+    pub frames: u16,
+}
+
+impl Device for FrameCounter {
+    fn size(&self) -> u16 {
+        2
+    }
+
+    fn read(&self, offset: u16) -> u8 {
+        self.frames.to_be_bytes()[usize::from(offset & 1)]
+    }
+
+    fn write(&mut self, _offset: u16, _value: u8) {
+        self.frames = 0;
+    }
+
+    fn frame(&mut self) {
+        self.frames = self.frames.wrapping_add(1);
+    }
+}
+
+/// Start of the memory that is switched between banks
+pub const BANKED_START: usize = 0x800;
+pub const BANK_SIZE: usize = 0x800;
+/// the first one is in memory at start
+pub const BANKS: usize = 16;
+
+/// Switches the upper 2 KiB of memory between `BANKS` banks, so a rom can be up to
+/// `(BANKS - 1) * BANK_SIZE` (30 KiB) larger than memory, bank 0 being the rom's own upper
+/// 2 KiB. Reads as the selected bank, writing selects bank `value % BANKS`.
+/// Bank 1 starts right after the part of the rom that fits in memory, each next one
+/// `BANK_SIZE` bytes later. Banks are writable, what the program writes there is kept
+/// while another bank is selected
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BankSwitch {
+    pub selected: u8,
+    /// set by a write, the memory is swapped by `Devices::switch_banks`
+    requested: Option<u8>,
+    /// `BANKS` banks, the selected one is stale while it is in memory.
+    /// Empty until a rom with banks is loaded or the program switches
+    pub banks: Vec<[u8; BANK_SIZE]>,
+}
+
+impl BankSwitch {
+    /// Puts `data` in banks 1 and on
+    pub fn load(&mut self, data: &[u8]) {
+        *self = BankSwitch::default();
+        for (bank, chunk) in self.banks_mut().iter_mut().skip(1).zip(data.chunks(BANK_SIZE)) {
+            bank[..chunk.len()].copy_from_slice(chunk);
+        }
+    }
+
+    pub fn banks_mut(&mut self) -> &mut [[u8; BANK_SIZE]] {
+        if self.banks.is_empty() {
+            self.banks = vec![[0; BANK_SIZE]; BANKS];
+        }
+        &mut self.banks
+    }
+
+    /// Swaps the requested bank into `mem`
+    fn switch(&mut self, mem: &mut [u8]) {
+        let bank = match self.requested.take() {
+            Some(bank) if bank != self.selected => bank,
+            _ => return,
+        };
+        let selected = usize::from(self.selected);
+        let upper = &mut mem[BANKED_START..BANKED_START + BANK_SIZE];
+        let banks = self.banks_mut();
+        banks[selected].copy_from_slice(upper);
+        upper.copy_from_slice(&banks[usize::from(bank)]);
+        self.selected = bank;
+    }
+}
+
+impl Device for BankSwitch {
+    fn size(&self) -> u16 {
+        1
+    }
+
+    fn read(&self, _offset: u16) -> u8 {
+        self.selected
+    }
+
+    fn write(&mut self, _offset: u16, value: u8) {
+        self.requested = Some(value % BANKS as u8);
+    }
+}
+
+/// Every device, mapped or not
+#[derive(Clone, Default)]
+pub struct Devices {
+    pub frame_counter: FrameCounter,
+    pub bank_switch: BankSwitch,
+}
+
+impl Devices {
+    /// The device mapped at `addr` with `quirks`, and `addr`'s offset into it
+    pub fn at(&mut self, quirks: &Quirks, addr: u16) -> Option<(&mut dyn Device, u16)> {
+        if let Some(start) = quirks.frame_counter {
+            let offset = addr.wrapping_sub(start);
+            if offset < self.frame_counter.size() {
+                return Some((&mut self.frame_counter, offset));
+            }
+        }
+        if quirks.bank_port == Some(addr) {
+            return Some((&mut self.bank_switch, 0));
+        }
+        None
+    }
+
+    /// Applies what was written to the devices that change memory
+    pub fn update_memory(&mut self, mem: &mut [u8]) {
+        self.bank_switch.switch(mem);
+    }
+
+    pub fn frame(&mut self) {
+        self.frame_counter.frame();
+    }
+}
+
+#[cfg(all(test, feature = "assembler"))]
+mod tests {
+    use crate::system::debug::Debugger;
+    use crate::system::quirks::Quirks;
+    use crate::system::System;
+
+    #[test]
+    fn bank_switching() {
+        let rom = crate::asm::assemble(
+            "    LD I, 0x1FF\n\
+                 LD V0, 2\n\
+                 LD [I], V0\n\
+                 JP 0x800\n\
+             BANK 1\n\
+                 DB 0xAA\n\
+             BANK 2\n\
+             far:\n\
+                 LD V5, 0x42\n\
+                 LD I, 0x1FF\n\
+                 LD V0, [I]\n\
+             halt:\n\
+                 JP halt\n",
+        )
+        .unwrap();
+        assert_eq!(rom.len(), 0xE00 + 0x800 + 8);
+
+        let mut system = System::default();
+        assert!(system.load(&rom[..]).is_err());
+        system.quirks = Quirks {
+            bank_port: Some(0x1FF),
+            ..Quirks::default()
+        };
+        system.load(&rom[..]).unwrap();
+        assert_eq!(system.devices.bank_switch.banks[1][0], 0xAA);
+
+        let mut dbg = Debugger::disabled();
+        for _ in 0..8 {
+            system.tick(&mut dbg).unwrap();
+        }
+        assert_eq!((system.registers.reg[5], system.registers.reg[0]), (0x42, 2));
+        assert_eq!(system.registers.pc, 0x806);
+
+        let mut restored = System {
+            quirks: system.quirks,
+            ..System::default()
+        };
+        restored.load_state(&system.save_state()).unwrap();
+        assert_eq!(restored.devices.bank_switch, system.devices.bank_switch);
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use super::PROGRAM_START;
+
+/// Suspicious but legal behavior of the running program
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Diagnostic {
+    /// an instruction is executed from an odd address
+    OddPc { pc: u16 },
+    /// control is transferred below the program start, into the font or interpreter area
+    JumpIntoReserved { pc: u16, target: u16 },
+    /// memory that neither the rom nor the program wrote is read or executed, reported once
+    /// per instruction
+    UninitializedRead { pc: u16, addr: u16 },
+    /// an arithmetic instruction that sets VF has VF as its destination, so the flag overwrites
+    /// the result, or reads VF, which holds the flag of an earlier instruction. Interpreters
+    /// differ in whether the result or the flag ends up in VF
+    FlagRegister { pc: u16, opcode: u16 },
+    /// memory holding an instruction that already ran is written, the program changes its own
+    /// code. Reported once per address
+    SelfModifying { pc: u16, addr: u16 },
+}
+
+impl Diagnostic {
+    pub fn pc(&self) -> u16 {
+        match self {
+            Diagnostic::OddPc { pc }
+            | Diagnostic::JumpIntoReserved { pc, .. }
+            | Diagnostic::UninitializedRead { pc, .. }
+            | Diagnostic::FlagRegister { pc, .. }
+            | Diagnostic::SelfModifying { pc, .. } => *pc,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::OddPc { pc } => write!(f, "{:03X}: executing from an odd address", pc),
+            Diagnostic::JumpIntoReserved { pc, target } => {
+                let area = if (*target as usize) < super::fonts::FONT_LEN {
+                    "font data"
+                } else {
+                    "interpreter area"
+                };
+                write!(f, "{:03X}: jump to {:03X}, into the {}", pc, target, area)
+            }
+            Diagnostic::UninitializedRead { pc, addr } if pc == addr => {
+                write!(f, "{:03X}: executing memory that was never written", pc)
+            }
+            Diagnostic::UninitializedRead { pc, addr } => {
+                write!(f, "{:03X}: read of {:03X}, which was never written", pc, addr)
+            }
+            Diagnostic::FlagRegister { pc, opcode } if opcode & 0x0F00 == 0x0F00 => {
+                write!(f, "{:03X}: {:04X} writes its result to VF, the flag overwrites it", pc, opcode)
+            }
+            Diagnostic::FlagRegister { pc, opcode } => {
+                write!(f, "{:03X}: {:04X} reads VF, the flag of an earlier instruction", pc, opcode)
+            }
+            Diagnostic::SelfModifying { pc, addr } => {
+                write!(f, "{:03X}: writes {:03X}, an instruction that already ran", pc, addr)
+            }
+        }
+    }
+}
+
+/// Collects diagnostics while the system runs. Disabled by default;
+/// each diagnostic is reported once
+#[derive(Default)]
+pub struct Diagnostics {
+    pub enabled: bool,
+    /// report `FlagRegister`, on its own as games also read VF on purpose, e.g. to add a carry
+    pub flag_register: bool,
+    pending: Vec<Diagnostic>,
+    seen: HashSet<Diagnostic>,
+    /// addresses holding the fonts, the rom or something the program wrote
+    initialized: Vec<bool>,
+    /// instructions already reported for `UninitializedRead`
+    uninitialized_reads: HashSet<u16>,
+}
+
+impl Diagnostics {
+    pub fn report(&mut self, diagnostic: Diagnostic) {
+        if self.enabled {
+            self.report_once(diagnostic);
+        }
+    }
+
+    fn report_once(&mut self, diagnostic: Diagnostic) {
+        if self.seen.insert(diagnostic.clone()) {
+            self.pending.push(diagnostic);
+        }
+    }
+
+    /// diagnostics reported since the last call
+    pub fn take(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.pending)
+    }
+
+    pub(super) fn check_pc(&mut self, pc: u16) {
+        if pc & 1 != 0 {
+            self.report(Diagnostic::OddPc { pc });
+        }
+    }
+
+    /// Forgets what was written, for a machine with `len` bytes of memory
+    pub(super) fn clear_initialized(&mut self, len: usize) {
+        self.initialized = vec![false; len];
+        self.uninitialized_reads.clear();
+    }
+
+    pub(super) fn set_initialized(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.initialized.len());
+        for initialized in self.initialized.get_mut(range.start..end).unwrap_or(&mut []) {
+            *initialized = true;
+        }
+    }
+
+    /// whether the rom or the program wrote `addr`, true past the end of memory
+    pub fn initialized(&self, addr: u16) -> bool {
+        self.initialized.get(usize::from(addr)).copied().unwrap_or(true)
+    }
+
+    pub(super) fn check_read(&mut self, pc: u16, addr: u16) {
+        if self.enabled && !self.initialized(addr) && self.uninitialized_reads.insert(pc) {
+            self.report(Diagnostic::UninitializedRead { pc, addr });
+        }
+    }
+
+    /// Checks an arithmetic instruction that sets VF, `reads_y` if it has VY as an operand
+    pub(super) fn check_flag_register(&mut self, pc: u16, opcode: u16, reads_y: bool) {
+        let x = opcode >> 8 & 0xF;
+        let y = opcode >> 4 & 0xF;
+        if self.flag_register && (x == 0xF || reads_y && y == 0xF) {
+            self.report_once(Diagnostic::FlagRegister { pc, opcode });
+        }
+    }
+
+    pub(super) fn check_jump(&mut self, pc: u16, target: u16) {
+        if target < PROGRAM_START {
+            self.report(Diagnostic::JumpIntoReserved { pc, target });
+        }
+    }
+}
+
+/// What memory and registers hold at reset. Real interpreters do not clear memory, so a rom
+/// that reads it before writing may only work on emulators that do
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MemoryFill {
+    #[default]
+    Zero,
+    /// every byte 0xFF
+    Ones,
+    /// bytes from the random number generator, the same for the same seed
+    Random,
+}
+
+impl MemoryFill {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zero" => Some(MemoryFill::Zero),
+            "ff" => Some(MemoryFill::Ones),
+            "random" => Some(MemoryFill::Random),
+            _ => None,
+        }
+    }
+}
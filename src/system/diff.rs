@@ -0,0 +1,215 @@
+use std::fmt;
+
+use super::{Execution, System, SCREEN_WIDTH};
+
+/// memory ranges longer than this are shown by length only
+const MAX_SHOWN_BYTES: usize = 8;
+/// toggled pixels listed one by one, more are shown as the area they are in
+const MAX_SHOWN_PIXELS: usize = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    Pc { before: u16, after: u16 },
+    Register { reg: u8, before: u8, after: u8 },
+    Index { before: u16, after: u16 },
+    DelayTimer { before: u8, after: u8 },
+    SoundTimer { before: u8, after: u8 },
+    /// the addresses on the stack, bottom first
+    Stack { before: Vec<u16>, after: Vec<u16> },
+    /// a run of changed bytes
+    Memory { start: u16, before: Vec<u8>, after: Vec<u8> },
+    /// pixels that are lit in one system and not the other, as (x, y)
+    Pixels { toggled: Vec<(u8, u8)> },
+    Execution { before: Execution, after: Execution },
+}
+
+/// Everything that differs between two systems, in the order of `Change`'s variants
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateDiff {
+    pub changes: Vec<Change>,
+}
+
+impl StateDiff {
+    pub fn between(before: &System, after: &System) -> Self {
+        let mut changes = Vec::new();
+
+        let (b, a) = (&before.registers, &after.registers);
+        if b.pc != a.pc {
+            changes.push(Change::Pc { before: b.pc, after: a.pc });
+        }
+        for reg in 0..16 {
+            if b.reg[reg] != a.reg[reg] {
+                changes.push(Change::Register {
+                    reg: reg as u8,
+                    before: b.reg[reg],
+                    after: a.reg[reg],
+                });
+            }
+        }
+        if b.index != a.index {
+            changes.push(Change::Index { before: b.index, after: a.index });
+        }
+
+        let (b, a) = (&before.timers, &after.timers);
+        if b.delay != a.delay {
+            changes.push(Change::DelayTimer { before: b.delay, after: a.delay });
+        }
+        if b.sound != a.sound {
+            changes.push(Change::SoundTimer { before: b.sound, after: a.sound });
+        }
+
+        let (b, a) = (live_stack(before), live_stack(after));
+        if b != a {
+            changes.push(Change::Stack { before: b, after: a });
+        }
+
+        let mut i = 0;
+        while i < before.mem.len() {
+            if before.mem[i] == after.mem[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < before.mem.len() && before.mem[i] != after.mem[i] {
+                i += 1;
+            }
+            changes.push(Change::Memory {
+                start: start as u16,
+                before: before.mem[start..i].to_vec(),
+                after: after.mem[start..i].to_vec(),
+            });
+        }
+
+        let toggled = before
+            .screen
+            .iter()
+            .zip(after.screen.iter())
+            .enumerate()
+            .flat_map(|(byte, (b, a))| {
+                let changed = b ^ a;
+                (0..8).filter(move |bit| changed & (0x80 >> bit) != 0).map(move |bit| {
+                    let pixel = byte * 8 + bit;
+                    ((pixel % SCREEN_WIDTH as usize) as u8, (pixel / SCREEN_WIDTH as usize) as u8)
+                })
+            })
+            .collect::<Vec<_>>();
+        if !toggled.is_empty() {
+            changes.push(Change::Pixels { toggled });
+        }
+
+        if before.execution != after.execution {
+            changes.push(Change::Execution {
+                before: before.execution,
+                after: after.execution,
+            });
+        }
+
+        StateDiff { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+fn live_stack(system: &System) -> Vec<u16> {
+    let sp = (system.stack.sp as usize).min(system.stack.stack.len());
+    system.stack.stack[..sp].to_vec()
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Pc { before, after } => write!(f, "PC {:03X} -> {:03X}", before, after),
+            Change::Register { reg, before, after } => write!(f, "V{:X} {:02X} -> {:02X}", reg, before, after),
+            Change::Index { before, after } => write!(f, "I {:03X} -> {:03X}", before, after),
+            Change::DelayTimer { before, after } => write!(f, "DT {:02X} -> {:02X}", before, after),
+            Change::SoundTimer { before, after } => write!(f, "ST {:02X} -> {:02X}", before, after),
+            Change::Stack { before, after } => {
+                let addresses = |stack: &[u16]| stack.iter().map(|addr| format!("{:03X}", addr)).collect::<Vec<_>>().join(" ");
+                write!(f, "stack [{}] -> [{}]", addresses(before), addresses(after))
+            }
+            Change::Memory { start, before, after } => {
+                if before.len() > MAX_SHOWN_BYTES {
+                    write!(f, "mem {:03X}..{:03X}: {} bytes", start, *start as usize + before.len(), before.len())
+                } else {
+                    write!(f, "mem {:03X}: {} -> {}", start, hex_bytes(before), hex_bytes(after))
+                }
+            }
+            Change::Pixels { toggled } => {
+                write!(f, "{} pixel{} toggled ", toggled.len(), if toggled.len() == 1 { "" } else { "s" })?;
+                if toggled.len() > MAX_SHOWN_PIXELS {
+                    let (xs, ys) = (toggled.iter().map(|p| p.0), toggled.iter().map(|p| p.1));
+                    let (left, right) = (xs.clone().min().unwrap_or(0), xs.max().unwrap_or(0));
+                    let (top, bottom) = (ys.clone().min().unwrap_or(0), ys.max().unwrap_or(0));
+                    write!(f, "in ({},{})..({},{})", left, top, right, bottom)
+                } else {
+                    let pixels = toggled.iter().map(|(x, y)| format!("({},{})", x, y)).collect::<Vec<_>>();
+                    write!(f, "at {}", pixels.join(" "))
+                }
+            }
+            Change::Execution { before, after } => write!(f, "{:?} -> {:?}", before, after),
+        }
+    }
+}
+
+/// The changes on one line, separated by commas
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.changes.is_empty() {
+            return write!(f, "no differences");
+        }
+        let changes = self.changes.iter().map(|change| change.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", changes.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::debug::Debugger;
+
+    /// Runs the program up to `steps` instructions and returns the diff of the last one
+    fn diff_of_last(program: &[u8], steps: usize) -> StateDiff {
+        let mut system = System::default();
+        system.load(program).unwrap();
+        for _ in 1..steps {
+            system.tick(&mut Debugger::disabled()).unwrap();
+        }
+        let mut before = System::default();
+        before.load_state(&system.save_state()).unwrap();
+        system.tick(&mut Debugger::disabled()).unwrap();
+        StateDiff::between(&before, &system)
+    }
+
+    #[test]
+    fn add_with_carry() {
+        // V1 = FF, V2 = 02, V1 += V2
+        let diff = diff_of_last(&[0x61, 0xFF, 0x62, 0x02, 0x81, 0x24], 3);
+        assert_eq!(
+            diff.changes,
+            vec![
+                Change::Pc { before: 0x204, after: 0x206 },
+                Change::Register { reg: 1, before: 0xFF, after: 0x01 },
+                Change::Register { reg: 15, before: 0x00, after: 0x01 },
+            ]
+        );
+        assert_eq!(diff.to_string(), "PC 204 -> 206, V1 FF -> 01, VF 00 -> 01");
+    }
+
+    #[test]
+    fn call_store_and_draw() {
+        // CALL 206, then LD B, V0 over the font at I = 0, LD I, 20C and DRW V0, V0, 1 with F0 at 20C
+        let program = [0x22, 0x06, 0, 0, 0, 0, 0xF0, 0x33, 0xA2, 0x0C, 0xD0, 0x01, 0xF0, 0x00];
+        let diff = diff_of_last(&program, 1);
+        assert_eq!(diff.to_string(), "PC 200 -> 206, stack [] -> [200]");
+        let diff = diff_of_last(&program, 2);
+        assert_eq!(diff.to_string(), "PC 206 -> 208, mem 000: F0 90 90 -> 00 00 00");
+        let diff = diff_of_last(&program, 4);
+        assert_eq!(diff.to_string(), "PC 20A -> 20C, 4 pixels toggled at (0,0) (1,0) (2,0) (3,0)");
+    }
+}
@@ -0,0 +1,42 @@
+/// Where a lit pixel came from: the sprite byte and bit that drew it and the `DRW` that did
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpritePixel {
+    /// address of the `DRW` instruction
+    pub pc: u16,
+    /// the sprite byte in memory
+    pub addr: u16,
+    /// 7 for the leftmost pixel of the byte, 0 for the rightmost
+    pub bit: u8,
+}
+
+/// The last sprite pixel drawn to every pixel of the screen, for the pixel inspector.
+/// Cleared with the screen
+#[derive(Clone)]
+pub struct DrawTracker {
+    pixels: Vec<Option<SpritePixel>>,
+}
+
+impl Default for DrawTracker {
+    fn default() -> Self {
+        DrawTracker {
+            pixels: vec![None; 64 * 32],
+        }
+    }
+}
+
+impl DrawTracker {
+    pub fn record(&mut self, x: u8, y: u8, pixel: SpritePixel) {
+        if let Some(slot) = self.pixels.get_mut(usize::from(y) * 64 + usize::from(x)) {
+            *slot = Some(pixel);
+        }
+    }
+
+    /// None if no sprite drew to the pixel since the screen was cleared
+    pub fn at(&self, x: u8, y: u8) -> Option<SpritePixel> {
+        self.pixels.get(usize::from(y) * 64 + usize::from(x)).copied().flatten()
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|pixel| *pixel = None);
+    }
+}
@@ -0,0 +1,43 @@
+use super::{System, SystemError};
+
+/// Where the program goes after an extension's instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// on to the next instruction
+    Next,
+    /// the handler set `registers.pc` itself
+    Jump,
+}
+
+/// Executes an extension's instruction, given the whole opcode
+pub type Handler = Box<dyn FnMut(&mut System, u16) -> Result<Step, SystemError> + Send>;
+
+struct Extension {
+    pattern: u16,
+    mask: u16,
+    handler: Handler,
+}
+
+/// Instructions added by embedding code, see `System::register_extension`
+#[derive(Default)]
+pub struct Extensions {
+    list: Vec<Extension>,
+}
+
+impl Extensions {
+    pub fn register(&mut self, pattern: u16, mask: u16, handler: Handler) {
+        self.list.push(Extension {
+            pattern: pattern & mask,
+            mask,
+            handler,
+        });
+    }
+
+    /// Runs the first extension that matches `opcode`, None if none does
+    pub(super) fn run(&mut self, system: &mut System, opcode: u16) -> Option<Result<Step, SystemError>> {
+        self.list
+            .iter_mut()
+            .find(|extension| opcode & extension.mask == extension.pattern)
+            .map(|extension| (extension.handler)(system, opcode))
+    }
+}
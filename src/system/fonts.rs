@@ -1,4 +1,164 @@
-pub const FONTS: &'static [u8] = &[
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+/// Bytes of a font: the glyphs of the 16 hex digits, 5 rows each, loaded at 0x000
+pub const FONT_LEN: usize = 80;
+
+pub type Font = [u8; FONT_LEN];
+
+/// The fonts interpreters shipped with, the digits `LD F, Vx` points at
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FontStyle {
+    /// the COSMAC VIP's, with a square 4 and 7 and serifs on B and D
+    Vip,
+    /// SCHIP's, which most later interpreters copied
+    Schip,
+    /// rounded digits where 0 and D, 8 and B tell apart more easily
+    Modern,
+}
+
+impl FontStyle {
+    pub fn font(self) -> Font {
+        match self {
+            FontStyle::Vip => VIP,
+            FontStyle::Schip => SCHIP,
+            FontStyle::Modern => MODERN,
+        }
+    }
+}
+
+/// Either a style name or `{ file = "font.bin" }`, 80 bytes in the order of the digits
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FontConfig {
+    Style(FontStyle),
+    File { file: PathBuf },
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        FontConfig::Style(FontStyle::Schip)
+    }
+}
+
+impl FontConfig {
+    /// The font, with a file relative to `dir`
+    pub fn load(&self, dir: &Path) -> Result<Font, Error> {
+        match self {
+            FontConfig::Style(style) => Ok(style.font()),
+            FontConfig::File { file } => {
+                let path = dir.join(file);
+                let data = std::fs::read(&path).map_err(|e| format_err!("{}: {}", path.display(), e))?;
+                Font::try_from(data.as_slice())
+                    .map_err(|_| format_err!("{}: a font is {} bytes, got {}", path.display(), FONT_LEN, data.len()))
+            }
+        }
+    }
+}
+
+pub const VIP: Font = [
+    0b11110000,
+    0b10010000,
+    0b10010000,
+    0b10010000,
+    0b11110000,
+    //
+    0b01100000,
+    0b00100000,
+    0b00100000,
+    0b00100000,
+    0b01110000,
+    //
+    0b11110000,
+    0b00010000,
+    0b11110000,
+    0b10000000,
+    0b11110000,
+    //
+    0b11110000,
+    0b00010000,
+    0b11110000,
+    0b00010000,
+    0b11110000,
+    //
+    0b10100000,
+    0b10100000,
+    0b11110000,
+    0b00100000,
+    0b00100000,
+    //
+    0b11110000,
+    0b10000000,
+    0b11110000,
+    0b00010000,
+    0b11110000,
+    //
+    0b11110000,
+    0b10000000,
+    0b11110000,
+    0b10010000,
+    0b11110000,
+    //
+    0b11110000,
+    0b00010000,
+    0b00010000,
+    0b00010000,
+    0b00010000,
+    //
+    0b11110000,
+    0b10010000,
+    0b11110000,
+    0b10010000,
+    0b11110000,
+    //
+    0b11110000,
+    0b10010000,
+    0b11110000,
+    0b00010000,
+    0b11110000,
+    //
+    0b11110000,
+    0b10010000,
+    0b11110000,
+    0b10010000,
+    0b10010000,
+    //
+    0b11110000,
+    0b01010000,
+    0b01110000,
+    0b01010000,
+    0b11110000,
+    //
+    0b11110000,
+    0b10000000,
+    0b10000000,
+    0b10000000,
+    0b11110000,
+    //
+    0b11110000,
+    0b01010000,
+    0b01010000,
+    0b01010000,
+    0b11110000,
+    //
+    0b11110000,
+    0b10000000,
+    0b11110000,
+    0b10000000,
+    0b11110000,
+    //
+    0b11110000,
+    0b10000000,
+    0b11110000,
+    0b10000000,
+    0b10000000,
+];
+
+/// loaded unless another font is set
+pub const SCHIP: Font = [
     0b11110000,
     0b10010000,
     0b10010000,
@@ -95,3 +255,101 @@ pub const FONTS: &'static [u8] = &[
     0b10000000,
     0b10000000,
 ];
+
+pub const MODERN: Font = [
+    0b01100000,
+    0b10010000,
+    0b10010000,
+    0b10010000,
+    0b01100000,
+    //
+    0b00100000,
+    0b01100000,
+    0b00100000,
+    0b00100000,
+    0b01110000,
+    //
+    0b11100000,
+    0b00010000,
+    0b01100000,
+    0b10000000,
+    0b11110000,
+    //
+    0b11100000,
+    0b00010000,
+    0b01100000,
+    0b00010000,
+    0b11100000,
+    //
+    0b00110000,
+    0b01010000,
+    0b10010000,
+    0b11110000,
+    0b00010000,
+    //
+    0b11110000,
+    0b10000000,
+    0b11100000,
+    0b00010000,
+    0b11100000,
+    //
+    0b01100000,
+    0b10000000,
+    0b11100000,
+    0b10010000,
+    0b01100000,
+    //
+    0b11110000,
+    0b00010000,
+    0b00100000,
+    0b01000000,
+    0b01000000,
+    //
+    0b01100000,
+    0b10010000,
+    0b01100000,
+    0b10010000,
+    0b01100000,
+    //
+    0b01100000,
+    0b10010000,
+    0b01110000,
+    0b00010000,
+    0b01100000,
+    //
+    0b01100000,
+    0b10010000,
+    0b11110000,
+    0b10010000,
+    0b10010000,
+    //
+    0b11100000,
+    0b10010000,
+    0b11100000,
+    0b10010000,
+    0b11100000,
+    //
+    0b01110000,
+    0b10000000,
+    0b10000000,
+    0b10000000,
+    0b01110000,
+    //
+    0b11100000,
+    0b10010000,
+    0b10010000,
+    0b10010000,
+    0b11100000,
+    //
+    0b11110000,
+    0b10000000,
+    0b11100000,
+    0b10000000,
+    0b11110000,
+    //
+    0b11110000,
+    0b10000000,
+    0b11100000,
+    0b10000000,
+    0b10000000,
+];
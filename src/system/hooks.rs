@@ -0,0 +1,41 @@
+use super::System;
+
+/// What the machine does that a hook can be called for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// `CLS` was executed
+    ClearScreen,
+    /// the timers ticked, once per emulated frame
+    FrameEnd,
+    /// the sound timer was set from 0
+    SoundStart,
+    /// the sound timer ran out or was set to 0
+    SoundStop,
+}
+
+/// Called with the system right after the event, see `System::on_clear_screen` and the like
+pub type Hook = Box<dyn FnMut(&System) + Send>;
+
+/// Callbacks added by frontends and embedding code, so they can react to the machine without
+/// polling its state after every instruction
+#[derive(Default)]
+pub struct Hooks {
+    list: Vec<(Event, Hook)>,
+}
+
+impl Hooks {
+    pub fn add(&mut self, event: Event, hook: Hook) {
+        self.list.push((event, hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Calls the hooks of `event` in the order they were added
+    pub(super) fn call(&mut self, system: &System, event: Event) {
+        for (_, hook) in self.list.iter_mut().filter(|(on, _)| *on == event) {
+            hook(system);
+        }
+    }
+}
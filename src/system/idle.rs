@@ -0,0 +1,155 @@
+use super::opcode::{self, Opcode};
+use super::{Execution, System};
+
+impl System {
+    /// Whether the program is spinning in the classic delay timer wait loop:
+    ///
+    /// ```text
+    /// wait: LD Vx, DT
+    ///       SE Vx, 0
+    ///       JP wait
+    /// ```
+    ///
+    /// Such a loop does nothing until the timer runs out, so it can be skipped over
+    pub fn in_delay_wait(&self) -> bool {
+        let pc = self.registers.pc;
+        (0..3).any(|i| pc.checked_sub(i * 2).is_some_and(|start| self.is_delay_wait(start)))
+    }
+
+    fn is_delay_wait(&self, start: u16) -> bool {
+        let ops = [start, start.wrapping_add(2), start.wrapping_add(4)]
+            .iter()
+            .map(|addr| self.read_mem_pair(*addr))
+            .collect::<Result<Vec<_>, _>>();
+        let ops = match ops {
+            Ok(ops) => ops,
+            Err(_) => return false,
+        };
+
+        let x = ops[0] & 0x0F00;
+        ops[0] & 0xF0FF == 0xF007 && ops[1] == 0x3000 | x && ops[2] == 0x1000 | (start & 0xFFF)
+    }
+
+    /// Whether the program is waiting for a key: blocked on `LD Vx, K`, or polling the keypad in a
+    /// loop that does nothing else:
+    ///
+    /// ```text
+    /// wait: LD V0, 5      ; optional
+    ///       SKP V0        ; or SKNP, waiting for a release
+    ///       JP wait
+    /// ```
+    ///
+    /// Nothing happens until a key changes, so the loop can run much slower
+    pub fn in_key_wait(&self) -> bool {
+        if self.execution == Execution::WaitingForKey && self.key_pressed.is_none() {
+            return true;
+        }
+        let pc = self.registers.pc;
+        (2..=3).any(|len| (0..len).any(|i| pc.checked_sub(i * 2).is_some_and(|start| self.is_key_poll(start, len))))
+    }
+
+    /// `len` instructions from `start`, the last one jumping back to `start`
+    fn is_key_poll(&self, start: u16, len: u16) -> bool {
+        let ops = (0..len)
+            .map(|i| self.read_mem_pair(start.wrapping_add(i * 2)))
+            .collect::<Result<Vec<_>, _>>();
+        let ops = match ops {
+            Ok(ops) => ops,
+            Err(_) => return false,
+        };
+
+        let is_key_skip = |op: u16| op & 0xF0FF == 0xE09E || op & 0xF0FF == 0xE0A1;
+        let is_set_reg = |op: u16| op & 0xF000 == 0x6000;
+        let (jump, body) = ops.split_last().unwrap();
+        *jump == 0x1000 | (start & 0xFFF)
+            && body.iter().any(|&op| is_key_skip(op))
+            && body.iter().all(|&op| is_key_skip(op) || is_set_reg(op))
+    }
+
+    /// Whether the program is stuck in a loop it can never leave: a jump to itself, the usual
+    /// way to end a program,
+    ///
+    /// ```text
+    /// end: JP end
+    /// ```
+    ///
+    /// or one instruction and a jump back to it, where that instruction neither reads the keys,
+    /// skips, jumps, draws nor writes memory. Only the timers change while it runs
+    pub fn in_halt_loop(&self) -> bool {
+        let pc = self.registers.pc;
+        let jump_to = |addr: u16| 0x1000 | (addr & 0xFFF);
+        if self.read_mem_pair(pc).is_ok_and(|op| op == jump_to(pc)) {
+            return true;
+        }
+        (0..2).any(|i| {
+            pc.checked_sub(i * 2).is_some_and(|start| {
+                self.read_mem_pair(start.wrapping_add(2)).is_ok_and(|op| op == jump_to(start))
+                    && self.read_mem_pair(start).is_ok_and(stays_in_loop)
+            })
+        })
+    }
+
+    /// Runs the timers until the delay timer reaches zero.
+    /// Returns true if the sound timer reached zero along the way
+    pub fn skip_delay_wait(&mut self) -> bool {
+        let mut sound_ended = false;
+        while self.timers.delay > 0 {
+            sound_ended |= self.dec_timers();
+        }
+        sound_ended
+    }
+}
+
+/// Instructions that only change registers and timers, and go on to the next one
+fn stays_in_loop(op: u16) -> bool {
+    use Opcode::*;
+
+    opcode::decode(op).is_some_and(|info| {
+        matches!(
+            info.opcode,
+            SetReg
+                | SAddReg
+                | MovReg
+                | OrReg
+                | AndReg
+                | XorReg
+                | AddReg
+                | SubReg
+                | RShiftReg
+                | RSubReg
+                | LShiftReg
+                | SetIndex
+                | Rand
+                | GetDelay
+                | SetDelay
+                | SetSound
+                | AddIndex
+                | GetSprite
+                | RegLoad
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_loops_that_never_end() {
+        let halted = |rom: &[u8], pc: u16| {
+            let mut system = System::default();
+            system.load(rom).unwrap();
+            system.registers.pc = pc;
+            system.in_halt_loop()
+        };
+        // JP 200
+        assert!(halted(&[0x12, 0x00], 0x200));
+        // ADD V0, 1 / JP 200, at either instruction
+        assert!(halted(&[0x70, 0x01, 0x12, 0x00], 0x200));
+        assert!(halted(&[0x70, 0x01, 0x12, 0x00], 0x202));
+        // SKP V0 / JP 200 waits for a key
+        assert!(!halted(&[0xE0, 0x9E, 0x12, 0x00], 0x200));
+        // LD V0, DT / SE V0, 0 / JP 200 ends with the timer
+        assert!(!halted(&[0xF0, 0x07, 0x30, 0x00, 0x12, 0x00], 0x202));
+    }
+}
@@ -0,0 +1,63 @@
+use failure::Error;
+
+use super::debug::Debugger;
+use super::{System, SystemError};
+
+/// A CHIP-8 implementation the frontend runs. The window, input and audio code only execute
+/// the program through it, so another core (one that caches decoded instructions, one timed
+/// like the VIP, one that explains every step) can take the place of `System`.
+///
+/// The machine state stays a `System`, which the overlays, save states and the debugger read.
+/// They may also change it between steps, loading a state or setting a register, and a core
+/// has to run on from what it finds there
+pub trait Chip8Core: Default {
+    /// Loads a program and starts it from scratch, see `System::load`
+    fn load(&mut self, rom: &[u8]) -> Result<(), Error>;
+
+    /// Clears everything but the settings before another program is loaded, see `System::reset`
+    fn reset(&mut self);
+
+    /// Executes one instruction
+    fn step(&mut self, dbg: &mut Debugger) -> Result<(), SystemError>;
+
+    /// Decrements the delay and sound timers, 60 times per second. Returns true if the sound
+    /// timer reached zero
+    fn tick_timers(&mut self) -> bool;
+
+    fn key_event(&mut self, key: u8, pressed: bool) -> Result<(), SystemError>;
+
+    fn state(&self) -> &System;
+
+    fn state_mut(&mut self) -> &mut System;
+}
+
+/// The interpreter, its state is itself
+impl Chip8Core for System {
+    fn load(&mut self, rom: &[u8]) -> Result<(), Error> {
+        System::load(self, rom)
+    }
+
+    fn reset(&mut self) {
+        System::reset(self)
+    }
+
+    fn step(&mut self, dbg: &mut Debugger) -> Result<(), SystemError> {
+        self.tick(dbg)
+    }
+
+    fn tick_timers(&mut self) -> bool {
+        self.dec_timers()
+    }
+
+    fn key_event(&mut self, key: u8, pressed: bool) -> Result<(), SystemError> {
+        self.process_key_event(key, pressed)
+    }
+
+    fn state(&self) -> &System {
+        self
+    }
+
+    fn state_mut(&mut self) -> &mut System {
+        self
+    }
+}
@@ -1,226 +1,358 @@
-#[derive(Clone, Copy)]
-#[repr(u16)]
-pub enum Opcode {
-    ClearScreen = 0x00E0, //
+/// How the operands are packed into the 12 bits after the first nibble
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operands {
+    /// the whole word is fixed
+    None,
+    /// `_NNN`, a 12 bit address
+    Addr,
+    /// `_X__`, a register
+    X,
+    /// `_XNN`, a register and a byte
+    XByte,
+    /// `_XY_`, two registers
+    XY,
+    /// `_XYN`, two registers and a nibble
+    XYN,
+}
 
-    Return = 0x00EE, //
-    Jump = 0x1000, //
-    Call = 0x2000, //
+impl Operands {
+    /// bits of the word that identify the instruction
+    pub fn mask(self) -> u16 {
+        match self {
+            Operands::None => 0xFFFF,
+            Operands::Addr | Operands::XByte | Operands::XYN => 0xF000,
+            Operands::XY => 0xF00F,
+            Operands::X => 0xF0FF,
+        }
+    }
+}
 
-    SkipIfEq = 0x3000, //
-    SkipIfNeq = 0x4000, //
-    SkipIfRegEq = 0x5000, //
+/// An operand in the syntax of an instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// `Vx`
+    X,
+    /// `Vy`
+    Y,
+    /// `n`, a nibble
+    Nibble,
+    /// `nn`
+    Byte,
+    /// `nnn`
+    Addr,
+    /// a fixed operand: `I`, `[I]`, `DT`, `ST`, `K`, `F`, `B` or `V0`
+    Literal(&'static str),
+}
 
-    SetReg = 0x6000, //
-    SAddReg = 0x7000, //
+/// Everything known about an instruction, see `OPCODES`
+#[derive(Debug)]
+pub struct OpcodeInfo {
+    pub opcode: Opcode,
+    /// the word with all operands zero
+    pub pattern: u16,
+    pub operands: Operands,
+    /// assembly syntax with `Vx`, `Vy`, `n`, `nn` and `nnn` standing for the operands
+    pub syntax: &'static str,
+    /// approximate cost in COSMAC VIP machine cycles. DRW, LD B and the register dumps and loads
+    /// take longer the more data they touch
+    pub cycles: u32,
+    /// behavior that differs between interpreters, if any
+    pub quirk: Option<&'static str>,
+    pub description: &'static str,
+}
+
+impl OpcodeInfo {
+    pub fn matches(&self, code: u16) -> bool {
+        code & self.operands.mask() == self.pattern
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.syntax.split(' ').next().unwrap_or(self.syntax)
+    }
+
+    pub fn tokens(&self) -> Vec<Token> {
+        let operands = match self.syntax.find(' ') {
+            Some(pos) => &self.syntax[pos + 1..],
+            None => return Vec::new(),
+        };
+        operands
+            .split(", ")
+            .map(|token| match token {
+                "Vx" => Token::X,
+                "Vy" => Token::Y,
+                "n" => Token::Nibble,
+                "nn" => Token::Byte,
+                "nnn" => Token::Addr,
+                literal => Token::Literal(literal),
+            })
+            .collect()
+    }
 
-    MovReg = 0x8000, //
-    OrReg = 0x8001, //
-    AndReg = 0x8002, //
-    XorReg = 0x8003, //
-    AddReg = 0x8004, //
-    SubReg = 0x8005, //
-    RShiftReg = 0x8006, //
-    RSubReg = 0x8007, //
-    LShiftReg = 0x800E, //
+    /// The pattern as it is usually written, e.g. `8XY4` or `FX1E`
+    pub fn pattern_name(&self) -> String {
+        let hex = format!("{:04X}", self.pattern);
+        let operands = match self.operands {
+            Operands::None => return hex,
+            Operands::Addr => "NNN",
+            Operands::X => "X",
+            Operands::XByte => "XNN",
+            Operands::XY => "XY",
+            Operands::XYN => "XYN",
+        };
+        format!("{}{}{}", &hex[..1], operands, &hex[1 + operands.len()..])
+    }
+}
 
-    SkipIfRegNeq = 0x9000, //
+/// Declares `Opcode` and `OPCODES` from one list, so the variants and the table stay in the
+/// same order
+macro_rules! opcodes {
+    ($($name:ident = $pattern:literal, $operands:ident, $syntax:literal, $cycles:literal, $quirk:expr, $description:literal;)*) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum Opcode {
+            $($name,)*
+        }
 
-    SetIndex = 0xA000, //
-    JumpPlus = 0xB000, //
-    Rand = 0xC000,
+        /// Every CHIP-8 instruction. The decoder, the disassembler, the assembler and
+        /// `chip8 opcodes` are all driven by this table
+        pub const OPCODES: &[OpcodeInfo] = &[
+            $(OpcodeInfo {
+                opcode: Opcode::$name,
+                pattern: $pattern,
+                operands: Operands::$operands,
+                syntax: $syntax,
+                cycles: $cycles,
+                quirk: $quirk,
+                description: $description,
+            },)*
+        ];
+    };
+}
 
-    Draw = 0xD000, //
+opcodes! {
+    ClearScreen = 0x00E0, None, "CLS", 24, None, "clear the screen";
+    Return = 0x00EE, None, "RET", 10, None, "return from a subroutine";
+    Jump = 0x1000, Addr, "JP nnn", 12, None, "jump to nnn";
+    Call = 0x2000, Addr, "CALL nnn", 26, None, "call the subroutine at nnn";
+    SkipIfEq = 0x3000, XByte, "SE Vx, nn", 10, None, "skip the next instruction if VX == nn";
+    SkipIfNeq = 0x4000, XByte, "SNE Vx, nn", 10, None, "skip the next instruction if VX != nn";
+    SkipIfRegEq = 0x5000, XY, "SE Vx, Vy", 14, None, "skip the next instruction if VX == VY";
+    SetReg = 0x6000, XByte, "LD Vx, nn", 6, None, "VX = nn";
+    SAddReg = 0x7000, XByte, "ADD Vx, nn", 10, None, "VX += nn, without carry";
+    MovReg = 0x8000, XY, "LD Vx, Vy", 44, None, "VX = VY";
+    OrReg = 0x8001, XY, "OR Vx, Vy", 44, Some("the VIP resets VF"), "VX |= VY";
+    AndReg = 0x8002, XY, "AND Vx, Vy", 44, Some("the VIP resets VF"), "VX &= VY";
+    XorReg = 0x8003, XY, "XOR Vx, Vy", 44, Some("the VIP resets VF"), "VX ^= VY";
+    AddReg = 0x8004, XY, "ADD Vx, Vy", 44, None, "VX += VY, VF = carry";
+    SubReg = 0x8005, XY, "SUB Vx, Vy", 44, None, "VX -= VY, VF = no borrow";
+    RShiftReg = 0x8006, XY, "SHR Vx, Vy", 44, Some("shifts VX or VY"), "VX = VY >> 1, VF = shifted out bit";
+    RSubReg = 0x8007, XY, "SUBN Vx, Vy", 44, None, "VX = VY - VX, VF = no borrow";
+    LShiftReg = 0x800E, XY, "SHL Vx, Vy", 44, Some("shifts VX or VY"), "VX = VY << 1, VF = shifted out bit";
+    SkipIfRegNeq = 0x9000, XY, "SNE Vx, Vy", 14, None, "skip the next instruction if VX != VY";
+    SetIndex = 0xA000, Addr, "LD I, nnn", 12, None, "I = nnn";
+    JumpPlus = 0xB000, Addr, "JP V0, nnn", 22, Some("adds V0 or VX"), "jump to nnn + V0";
+    Rand = 0xC000, XByte, "RND Vx, nn", 36, None, "VX = random byte & nn";
+    Draw = 0xD000, XYN, "DRW Vx, Vy, n", 22, Some("clips or wraps at the screen edge"), "draw n rows of the sprite at I at (VX, VY), VF = collision";
+    SkipIfKeyPressed = 0xE09E, X, "SKP Vx", 14, None, "skip the next instruction if key VX is pressed";
+    SkipIfKeyNotPressed = 0xE0A1, X, "SKNP Vx", 14, None, "skip the next instruction if key VX is not pressed";
+    GetDelay = 0xF007, X, "LD Vx, DT", 10, None, "VX = delay timer";
+    BlockGetKey = 0xF00A, X, "LD Vx, K", 10, None, "wait for a key press, VX = key";
+    SetDelay = 0xF015, X, "LD DT, Vx", 10, None, "delay timer = VX";
+    SetSound = 0xF018, X, "LD ST, Vx", 10, None, "sound timer = VX";
+    AddIndex = 0xF01E, X, "ADD I, Vx", 16, None, "I += VX";
+    GetSprite = 0xF029, X, "LD F, Vx", 20, None, "I = address of the font sprite of digit VX";
+    BinCoded = 0xF033, X, "LD B, Vx", 84, None, "store the decimal digits of VX at I, I+1, I+2";
+    RegDump = 0xF055, X, "LD [I], Vx", 14, Some("increments I or not"), "store V0 to VX at I";
+    RegLoad = 0xF065, X, "LD Vx, [I]", 14, Some("increments I or not"), "load V0 to VX from I";
+}
 
-    SkipIfKeyPressed = 0xE09E, //
-    SkipIfKeyNotPressed = 0xE0A1, //
+/// The instruction a word encodes
+pub fn decode(code: u16) -> Option<&'static OpcodeInfo> {
+    OPCODES.iter().find(|info| info.matches(code))
+}
 
-    GetDelay = 0xF007, //
-    BlockGetKey = 0xF00A,
+/// A word of memory and the instruction it decodes to
+#[derive(Clone, Copy, Debug)]
+pub struct Instruction {
+    pub word: u16,
+    pub info: &'static OpcodeInfo,
+}
 
-    SetDelay = 0xF015, //
-    SetSound = 0xF018, //
+impl Instruction {
+    /// The word back as the error if it is not an instruction
+    pub fn decode(word: u16) -> Result<Self, u16> {
+        decode(word).map(|info| Instruction { word, info }).ok_or(word)
+    }
 
-    AddIndex = 0xF01E, //
-    GetSprite = 0xF029, //
+    /// The word of the instruction: its pattern with the operand bits of `word`. Every bit of a
+    /// word is either identifying or an operand, so this is the word it was decoded from
+    pub fn encode(self) -> u16 {
+        self.info.pattern | (self.word & !self.info.operands.mask())
+    }
+}
 
-    BinCoded = 0xF033, //
-    RegDump = 0xF055, //
-    RegLoad = 0xF065, //
+/// Any instruction of `OPCODES` with any operands, for fuzzing the decoder, the disassembler and
+/// the assembler
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Instruction {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let info = u.choose(OPCODES)?;
+        let operands = u16::arbitrary(u)? & !info.operands.mask();
+        Ok(Instruction {
+            word: info.pattern | operands,
+            info,
+        })
+    }
+}
+
+/// Every word of `bytes` as if they were loaded at `start`, decoded. Words that are not
+/// instructions, data most likely, come back as errors. An odd last byte is left out
+pub fn instructions(bytes: &[u8], start: u16) -> impl Iterator<Item = (u16, Result<Instruction, u16>)> + '_ {
+    bytes.chunks_exact(2).zip((start..).step_by(2)).map(|(word, addr)| {
+        let word = u16::from_be_bytes([word[0], word[1]]);
+        (addr, Instruction::decode(word))
+    })
+}
+
+/// The instructions closest to a word that decodes to none, for a guess at what was meant:
+/// those with its first nibble whose other identifying bits are nearest in value
+pub fn nearest(code: u16) -> Vec<&'static OpcodeInfo> {
+    if decode(code).is_some() {
+        return Vec::new();
+    }
+    let distance = |info: &OpcodeInfo| (code & info.operands.mask() & 0x0FFF).abs_diff(info.pattern & 0x0FFF);
+    let family = OPCODES.iter().filter(|info| info.pattern >> 12 == code >> 12);
+    let closest = family.clone().map(distance).min();
+    family.filter(|info| Some(distance(info)) == closest).collect()
 }
 
 impl Opcode {
-    pub fn cmp(self, code: u16) -> bool {
-        match self {
-            Opcode::ClearScreen |
-            Opcode::Return => code == self as u16,
-
-            Opcode::Jump |
-            Opcode::Call |
-            Opcode::SkipIfEq |
-            Opcode::SkipIfNeq |
-            Opcode::SetReg |
-            Opcode::SAddReg |
-            Opcode::SetIndex |
-            Opcode::JumpPlus |
-            Opcode::Rand |
-            Opcode::Draw => code & 0xF000 == self as u16,
-
-            Opcode::SkipIfRegEq |
-            Opcode::MovReg |
-            Opcode::OrReg |
-            Opcode::AndReg |
-            Opcode::XorReg |
-            Opcode::AddReg |
-            Opcode::SubReg |
-            Opcode::RShiftReg |
-            Opcode::RSubReg |
-            Opcode::LShiftReg |
-            Opcode::SkipIfRegNeq => code & 0xF00F == self as u16,
-
-            Opcode::SkipIfKeyPressed |
-            Opcode::SkipIfKeyNotPressed |
-            Opcode::GetDelay |
-            Opcode::BlockGetKey |
-            Opcode::SetDelay |
-            Opcode::SetSound |
-            Opcode::AddIndex |
-            Opcode::GetSprite |
-            Opcode::BinCoded |
-            Opcode::RegDump |
-            Opcode::RegLoad => code & 0xF0FF == self as u16,
-        }
+    pub fn info(self) -> &'static OpcodeInfo {
+        &OPCODES[self as usize]
+    }
+
+    pub fn matches(self, code: u16) -> bool {
+        self.info().matches(code)
     }
 
     pub fn get_arg1_u16(self, code: u16) -> u16 {
-        match self {
-            Opcode::Jump |
-            Opcode::Call |
-            Opcode::SetIndex |
-            Opcode::JumpPlus => code & 0x0FFF,
+        match self.info().operands {
+            Operands::Addr => code & 0x0FFF,
             _ => panic!("Expected opcode with one 12bit argument"),
         }
     }
 
     pub fn get_arg1_u8(self, code: u16) -> u8 {
-        match self {
-            Opcode::SkipIfKeyPressed |
-            Opcode::SkipIfKeyNotPressed |
-            Opcode::GetDelay |
-            Opcode::BlockGetKey |
-            Opcode::SetDelay |
-            Opcode::SetSound |
-            Opcode::AddIndex |
-            Opcode::GetSprite |
-            Opcode::BinCoded |
-            Opcode::RegDump |
-            Opcode::RegLoad => ((code & 0x0F00) >> 8) as u8,
+        match self.info().operands {
+            Operands::X => x(code),
             _ => panic!("Expected opcode with one 4bit or 8bit argument"),
         }
     }
 
     pub fn get_arg2(self, code: u16) -> (u8, u8) {
-        match self {
-            Opcode::SkipIfEq |
-            Opcode::SkipIfNeq |
-            Opcode::SetReg |
-            Opcode::SAddReg |
-            Opcode::Rand => (
-                ((code & 0x0F00) >> 8) as u8,
-                (code & 0x00FF) as u8
-            ),
-
-            Opcode::SkipIfRegEq |
-            Opcode::MovReg |
-            Opcode::OrReg |
-            Opcode::AndReg |
-            Opcode::XorReg |
-            Opcode::AddReg |
-            Opcode::SubReg |
-            Opcode::RShiftReg |
-            Opcode::RSubReg |
-            Opcode::LShiftReg |
-            Opcode::SkipIfRegNeq => (
-                ((code & 0x0F00) >> 8) as u8,
-                ((code & 0x00F0) >> 4) as u8
-            ),
+        match self.info().operands {
+            Operands::XByte => (x(code), (code & 0x00FF) as u8),
+            Operands::XY => (x(code), y(code)),
             _ => panic!("Expected opcode with two arguments"),
         }
     }
 
     pub fn get_arg3(self, code: u16) -> (u8, u8, u8) {
-        match self {
-            Opcode::Draw => (
-                ((code & 0x0F00) >> 8) as u8,
-                ((code & 0x00F0) >> 4) as u8,
-                (code & 0x000F) as u8,
-            ),
+        match self.info().operands {
+            Operands::XYN => (x(code), y(code), (code & 0x000F) as u8),
             _ => panic!("Expected opcode with three arguments"),
         }
     }
 }
 
+fn x(code: u16) -> u8 {
+    ((code & 0x0F00) >> 8) as u8
+}
+
+fn y(code: u16) -> u8 {
+    ((code & 0x00F0) >> 4) as u8
+}
+
+/// Runs the branch of the instruction `value` decodes to. `value, decoded;` takes the
+/// `Option<Opcode>` it decodes to instead of decoding it again
 #[macro_export]
 macro_rules! match_opcodes {
     ($value:expr; $($rest:tt)*) => {
         loop {
             let value = $value;
+            let decoded = $crate::system::opcode::decode(value).map(|info| info.opcode);
+
+            match_opcodes!(@branches{value, decoded} $($rest)*);
+
+            #[allow(unreachable_code)]
+            {
+                unimplemented!("Unknown opcode: {:X}", value);
+            }
+        }
+    };
+
+    ($value:expr, $decoded:expr; $($rest:tt)*) => {
+        loop {
+            let value = $value;
+            let decoded: Option<$crate::system::opcode::Opcode> = $decoded;
 
-            match_opcodes!(@branches{value} $($rest)*);
+            match_opcodes!(@branches{value, decoded} $($rest)*);
 
-            unimplemented!("Unknown opcode: {:X}", value);
+            #[allow(unreachable_code)]
+            {
+                unimplemented!("Unknown opcode: {:X}", value);
+            }
         }
     };
 
-    (@branches{$value:expr} noarg $opcode:expr => $body:expr, $($rest:tt)*) => {
-        if $opcode.cmp($value) {
+    (@branches{$value:expr, $decoded:expr} noarg $opcode:expr => $body:expr, $($rest:tt)*) => {
+        if $decoded == Some($opcode) {
             #[allow(unreachable_code)]
             break $body;
         }
-        match_opcodes!(@branches{$value} $($rest)*)
+        match_opcodes!(@branches{$value, $decoded} $($rest)*)
     };
 
-    (@branches{$value:expr} $x:ident = $opcode:expr => $body:expr, $($rest:tt)*) => {
-        if $opcode.cmp($value) {
+    (@branches{$value:expr, $decoded:expr} $x:ident = $opcode:expr => $body:expr, $($rest:tt)*) => {
+        if $decoded == Some($opcode) {
             let $x = $opcode.get_arg1_u8($value);
             #[allow(unreachable_code)]
             break $body;
         }
-        match_opcodes!(@branches{$value} $($rest)*)
+        match_opcodes!(@branches{$value, $decoded} $($rest)*)
     };
 
-    (@branches{$value:expr} long $x:ident = $opcode:expr => $body:expr, $($rest:tt)*) => {
-        if $opcode.cmp($value) {
+    (@branches{$value:expr, $decoded:expr} long $x:ident = $opcode:expr => $body:expr, $($rest:tt)*) => {
+        if $decoded == Some($opcode) {
             let $x = $opcode.get_arg1_u16($value);
             #[allow(unreachable_code)]
             break $body;
         }
-        match_opcodes!(@branches{$value} $($rest)*)
+        match_opcodes!(@branches{$value, $decoded} $($rest)*)
     };
 
-    (@branches{$value:expr} ($x1:ident, $x2:ident) = $opcode:expr => $body:expr, $($rest:tt)*) => {
-        if $opcode.cmp($value) {
+    (@branches{$value:expr, $decoded:expr} ($x1:ident, $x2:ident) = $opcode:expr => $body:expr, $($rest:tt)*) => {
+        if $decoded == Some($opcode) {
             let ($x1, $x2) = $opcode.get_arg2($value);
             #[allow(unreachable_code)]
             break $body;
         }
-        match_opcodes!(@branches{$value} $($rest)*)
+        match_opcodes!(@branches{$value, $decoded} $($rest)*)
     };
 
-    (@branches{$value:expr} ($x1:ident, $x2:ident, $x3:ident) = $opcode:expr => $body:expr, $($rest:tt)*) => {
-        if $opcode.cmp($value) {
+    (@branches{$value:expr, $decoded:expr} ($x1:ident, $x2:ident, $x3:ident) = $opcode:expr => $body:expr, $($rest:tt)*) => {
+        if $decoded == Some($opcode) {
             let ($x1, $x2, $x3) = $opcode.get_arg3($value);
             #[allow(unreachable_code)]
             break $body;
         }
-        match_opcodes!(@branches{$value} $($rest)*)
+        match_opcodes!(@branches{$value, $decoded} $($rest)*)
     };
 
-    (@branches{$value:expr} otherwise $x:ident => $body:expr) => {
+    (@branches{$value:expr, $decoded:expr} otherwise $x:ident => $body:expr) => {
         let $x = $value;
-        #[allow(unreachable_code)]
+        #[allow(unreachable_code, clippy::diverging_sub_expression)]
         break $body;
     };
 
-    (@branches {$value:expr}) => {};
+    (@branches {$value:expr, $decoded:expr}) => {};
 }
@@ -0,0 +1,145 @@
+use failure::Error;
+use std::convert::{TryFrom, TryInto};
+
+use super::{System, SystemError, PROGRAM_START};
+
+/// starts every IPS file
+const IPS_HEADER: &[u8] = b"PATCH";
+/// ends every IPS file
+const IPS_FOOTER: &[u8] = b"EOF";
+
+/// Bytes written over memory by hand, e.g. in the debugger
+#[derive(Clone, Debug, PartialEq)]
+pub struct Patch {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    /// what memory held before, put back while the patch is off
+    pub original: Vec<u8>,
+    pub enabled: bool,
+}
+
+/// The patches made to the loaded program, kept apart from the bytes it was loaded with so
+/// they can be listed, turned off and on, and shared as an IPS file against the rom
+#[derive(Default)]
+pub struct Patches {
+    list: Vec<Patch>,
+}
+
+impl Patches {
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    /// in the order they were made
+    pub fn list(&self) -> &[Patch] {
+        &self.list
+    }
+
+    /// The patches that are on as an IPS file, with offsets into the rom, which starts at
+    /// `PROGRAM_START`
+    pub fn to_ips(&self) -> Result<Vec<u8>, Error> {
+        let mut out = IPS_HEADER.to_vec();
+        for patch in self.list.iter().filter(|patch| patch.enabled) {
+            if patch.addr < PROGRAM_START {
+                bail!("The patch at {:03X} is below the rom, which starts at {:03X}", patch.addr, PROGRAM_START);
+            }
+            let offset = u32::from(patch.addr - PROGRAM_START);
+            out.extend_from_slice(&offset.to_be_bytes()[1..]);
+            out.extend_from_slice(&(patch.bytes.len() as u16).to_be_bytes());
+            out.extend_from_slice(&patch.bytes);
+        }
+        out.extend_from_slice(IPS_FOOTER);
+        Ok(out)
+    }
+}
+
+/// The records of an IPS file as addresses in memory and the bytes to write there, runs of
+/// one byte included
+pub fn parse_ips(data: &[u8]) -> Result<Vec<(u16, Vec<u8>)>, Error> {
+    let mut rest = data.strip_prefix(IPS_HEADER).ok_or_else(|| format_err!("Not an IPS file"))?;
+    let mut take = |len: usize| {
+        let (taken, left) = rest.split_at_checked(len).ok_or_else(|| format_err!("The IPS file ends in the middle of a record"))?;
+        rest = left;
+        Ok::<_, Error>(taken)
+    };
+
+    let mut records = Vec::new();
+    loop {
+        let offset = take(3)?;
+        if offset == IPS_FOOTER {
+            return Ok(records);
+        }
+        let offset = u32::from_be_bytes([0, offset[0], offset[1], offset[2]]);
+        let len = u16::from_be_bytes(take(2)?.try_into()?);
+        let bytes = match len {
+            // a run of one byte
+            0 => {
+                let run = u16::from_be_bytes(take(2)?.try_into()?);
+                vec![take(1)?[0]; usize::from(run)]
+            }
+            len => take(usize::from(len))?.to_vec(),
+        };
+        let addr = u16::try_from(offset + u32::from(PROGRAM_START))
+            .ok()
+            .filter(|addr| usize::from(*addr) + bytes.len() <= super::MEMORY_SIZE as usize)
+            .ok_or_else(|| format_err!("The IPS record at offset {:X} is past the end of memory", offset))?;
+        records.push((addr, bytes));
+    }
+}
+
+impl System {
+    /// Writes `bytes` at `addr` as a new patch, which is on, and returns its index
+    pub fn patch(&mut self, addr: u16, bytes: &[u8]) -> Result<usize, SystemError> {
+        let end = u16::try_from(usize::from(addr) + bytes.len()).unwrap_or(u16::MAX);
+        let original = self.read_slice(addr..end)?.to_vec();
+        self.write_slice(addr, bytes)?;
+        self.patches.list.push(Patch {
+            addr,
+            bytes: bytes.to_vec(),
+            original,
+            enabled: true,
+        });
+        Ok(self.patches.list.len() - 1)
+    }
+
+    /// Turns the patch at `index` off, putting back what memory held under it, or on again.
+    /// Returns whether it is on now, None if there is no such patch
+    pub fn toggle_patch(&mut self, index: usize) -> Option<bool> {
+        let patch = self.patches.list.get_mut(index)?;
+        patch.enabled = !patch.enabled;
+        let (addr, enabled) = (patch.addr, patch.enabled);
+        let bytes = if enabled { patch.bytes.clone() } else { patch.original.clone() };
+        // in memory when it was made
+        self.write_slice(addr, &bytes).ok()?;
+        Some(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_and_exports_patches() {
+        let mut system = System::default();
+        system.load(&[0x60, 0x01, 0x12, 0x02][..]).unwrap();
+        assert_eq!(system.patch(0x201, &[0x05]).unwrap(), 0);
+        system.patch(0x100, &[0xAA]).unwrap();
+        assert_eq!(system.read_slice(0x200..0x202).unwrap(), [0x60, 0x05]);
+
+        assert_eq!(system.toggle_patch(0), Some(false));
+        assert_eq!(system.read_slice(0x200..0x202).unwrap(), [0x60, 0x01]);
+        // the patch under the rom is refused, off it is left out
+        assert!(system.patches.to_ips().is_err());
+        system.toggle_patch(1);
+        assert_eq!(system.patches.to_ips().unwrap(), b"PATCHEOF");
+
+        system.toggle_patch(0);
+        let ips = system.patches.to_ips().unwrap();
+        assert_eq!(ips, b"PATCH\x00\x00\x01\x00\x01\x05EOF");
+        assert_eq!(parse_ips(&ips).unwrap(), [(0x201, vec![0x05])]);
+        // a run of 3 bytes of 0xFF at offset 2
+        assert_eq!(parse_ips(b"PATCH\x00\x00\x02\x00\x00\x00\x03\xFFEOF").unwrap(), [(0x202, vec![0xFF; 3])]);
+        assert!(parse_ips(b"PATCH\x00\x00\x02\x00\x04\x01EOF").is_err());
+    }
+}
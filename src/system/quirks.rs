@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+use super::MAX_STACK_SIZE;
+
+/// Behaviors that differ between interpreters.
+/// The defaults follow the original COSMAC VIP interpreter
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    /// sprite pixels that go past the screen edge wrap to the other side instead of being clipped.
+    /// The start coordinates of a sprite always wrap
+    pub wrap_sprites: bool,
+    /// Return addresses the stack holds. The VIP had 12, most later interpreters 16 and some
+    /// more, which homebrew written for them may rely on. Up to `MAX_STACK_SIZE`
+    pub stack_size: u8,
+    /// Address of a `device::FrameCounter`, an extension for homebrew: two bytes there read as
+    /// the number of frames since the rom started instead of memory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_counter: Option<u16>,
+    /// Address of a `device::BankSwitch`, an extension for homebrew larger than memory: writing
+    /// a bank number there switches which bank of the rom is at 0x800-0xFFF
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bank_port: Option<u16>,
+    /// what `DRW Vx, Vy, 0` does
+    pub zero_height_draw: ZeroHeightDraw,
+    /// what the word 0000 does
+    pub zero_instruction: ZeroInstruction,
+    /// what `SYS nnn`, a call to 1802 machine code, does
+    pub machine_code_call: MachineCodeCall,
+    /// what `DRW` does when the sprite at I runs past the end of memory
+    pub sprite_past_memory: SpritePastMemory,
+    /// what happens when execution goes on past the last instruction in memory, at 0xFFE
+    pub pc_past_memory: PcPastMemory,
+}
+
+/// `DRW Vx, Vy, 0` draws a 16x16 sprite on SCHIP, on other interpreters it draws no rows or
+/// 16 rows of 8 pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZeroHeightDraw {
+    /// draws nothing and clears VF, like the VIP
+    #[default]
+    Nothing,
+    /// draws 16 rows of one byte from I
+    SixteenRows,
+}
+
+/// 0000 calls machine code at address 0 on the VIP, which no rom means to do. Most roms reach it
+/// by running off the end of their code, some by a bug worth looking into
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ZeroInstruction {
+    /// the program ended: keep showing the screen
+    #[default]
+    Halt,
+    /// stop with an error, like an unknown opcode
+    Error,
+    /// break into the debugger, halt without it
+    Break,
+    /// go on to the next instruction, like a machine code call that returns at once
+    Skip,
+}
+
+/// 0NNN ran 1802 machine code on the VIP, which no emulator can. Words that are SCHIP or
+/// XO-CHIP instructions are not calls and stay unknown opcodes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MachineCodeCall {
+    /// go on to the next instruction, warning once per call
+    #[default]
+    Ignore,
+    /// strict: stop with an error
+    Error,
+}
+
+/// Interpreters differ on a sprite at I that goes past 0xFFF: some wrap the address around,
+/// others read whatever follows their memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpritePastMemory {
+    /// stop with an invalid memory access, drawing nothing
+    #[default]
+    Error,
+    /// read the rows past 0xFFF from 0x000 on
+    Wrap,
+}
+
+/// The program counter moving on from 0xFFE, where no instruction fits. Roms only get there by
+/// running off the end of their code, or on purpose on interpreters where it wraps
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PcPastMemory {
+    /// stop with `SystemError::RanPastEndOfMemory`
+    #[default]
+    Error,
+    /// go on at the program start, 0x200
+    Wrap,
+}
+
+impl ZeroHeightDraw {
+    /// rows of the sprite drawn for a height of 0
+    pub fn rows(self) -> u8 {
+        match self {
+            ZeroHeightDraw::Nothing => 0,
+            ZeroHeightDraw::SixteenRows => 16,
+        }
+    }
+}
+
+pub const DEFAULT_STACK_SIZE: u8 = 16;
+
+/// names of the interpreters `Quirks::with_profile` knows
+pub const PROFILES: [&str; 3] = ["vip", "schip", "xo-chip"];
+
+impl Quirks {
+    /// `stack_size` limited to what the stack can hold
+    pub fn stack_depth(&self) -> usize {
+        usize::from(self.stack_size).clamp(1, MAX_STACK_SIZE)
+    }
+
+    /// These quirks with the behaviors of the interpreter `name` from `PROFILES`. The
+    /// extensions and the handling of errors stay as they are
+    pub fn with_profile(self, name: &str) -> Option<Quirks> {
+        let (wrap_sprites, zero_height_draw) = match name {
+            "vip" => (false, ZeroHeightDraw::Nothing),
+            "schip" => (false, ZeroHeightDraw::SixteenRows),
+            "xo-chip" => (true, ZeroHeightDraw::SixteenRows),
+            _ => return None,
+        };
+        Some(Quirks {
+            wrap_sprites,
+            zero_height_draw,
+            stack_size: DEFAULT_STACK_SIZE,
+            ..self
+        })
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            wrap_sprites: false,
+            stack_size: DEFAULT_STACK_SIZE,
+            frame_counter: None,
+            bank_port: None,
+            zero_height_draw: ZeroHeightDraw::Nothing,
+            zero_instruction: ZeroInstruction::Halt,
+            machine_code_call: MachineCodeCall::Ignore,
+            sprite_past_memory: SpritePastMemory::Error,
+            pc_past_memory: PcPastMemory::Error,
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// The `[rewind]` config section
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RewindConfig {
+    /// record a state every frame to play the game back while Backspace is held
+    pub enabled: bool,
+    /// memory the recorded states may use, in KiB. The oldest second goes first. Only the
+    /// compressed segments count, the newest state is also kept uncompressed on top of this
+    pub budget_kib: usize,
+    /// frames between full states, the others only store what changed since the one before
+    pub keyframe_interval: usize,
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        RewindConfig {
+            enabled: true,
+            budget_kib: 8 * 1024,
+            keyframe_interval: 60,
+        }
+    }
+}
+
+/// A full state and the frames after it, as the bytes that changed from one to the next
+struct Segment {
+    /// `compress`ed save state
+    keyframe: Vec<u8>,
+    /// `compress`ed XOR of each state with the one before
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Segment {
+    fn size(&self) -> usize {
+        self.keyframe.len() + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    /// the state of its last frame
+    fn last_state(&self) -> Vec<u8> {
+        let mut state = decompress(&self.keyframe);
+        for delta in &self.deltas {
+            xor(&mut state, &decompress(delta));
+        }
+        state
+    }
+}
+
+/// The save states of the last frames, newest last. Consecutive states differ in a few bytes,
+/// so most frames are stored as the run-length encoded XOR against the previous one, with a
+/// full state every `keyframe_interval` frames and whenever the length of the state changes
+pub struct RewindBuffer {
+    segments: VecDeque<Segment>,
+    /// the newest state as it is, deltas are XORed into it going back
+    last: Option<Vec<u8>>,
+    /// bytes stored in `segments`
+    size: usize,
+    config: RewindConfig,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig) -> Self {
+        RewindBuffer {
+            segments: VecDeque::new(),
+            last: None,
+            size: 0,
+            config,
+        }
+    }
+
+    /// Applies a new budget and interval, keeping the states that fit
+    pub fn set_config(&mut self, config: RewindConfig) {
+        self.config = config;
+        if !config.enabled {
+            self.clear();
+        }
+        self.trim();
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.last = None;
+        self.size = 0;
+    }
+
+    /// frames stored
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|segment| 1 + segment.deltas.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// bytes the compressed states take
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Records the state of a frame, dropping the oldest segments that no longer fit
+    pub fn push(&mut self, state: Vec<u8>) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let interval = self.config.keyframe_interval.max(1);
+        match (&self.last, self.segments.back_mut()) {
+            (Some(last), Some(segment)) if last.len() == state.len() && segment.deltas.len() + 1 < interval => {
+                let mut delta = state.clone();
+                xor(&mut delta, last);
+                let delta = compress(&delta);
+                self.size += delta.len();
+                segment.deltas.push(delta);
+            }
+            _ => {
+                let keyframe = compress(&state);
+                self.size += keyframe.len();
+                self.segments.push_back(Segment { keyframe, deltas: Vec::new() });
+            }
+        }
+        self.last = Some(state);
+        self.trim();
+    }
+
+    /// Removes and returns the newest state
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let state = self.last.take()?;
+        let segment = self.segments.back_mut()?;
+        match segment.deltas.pop() {
+            Some(delta) => {
+                self.size -= delta.len();
+                let mut previous = state.clone();
+                xor(&mut previous, &decompress(&delta));
+                self.last = Some(previous);
+            }
+            None => {
+                self.size -= segment.keyframe.len();
+                self.segments.pop_back();
+                self.last = self.segments.back().map(Segment::last_state);
+            }
+        }
+        Some(state)
+    }
+
+    /// drops whole segments from the front, the newest one stays whatever its size
+    fn trim(&mut self) {
+        while self.size > self.config.budget_kib * 1024 && self.segments.len() > 1 {
+            if let Some(segment) = self.segments.pop_front() {
+                self.size -= segment.size();
+            }
+        }
+    }
+}
+
+fn xor(state: &mut [u8], other: &[u8]) {
+    for (byte, other) in state.iter_mut().zip(other) {
+        *byte ^= other;
+    }
+}
+
+/// Zero bytes as `0, count` pairs of up to 255, other bytes as they are. States are mostly
+/// empty memory and deltas mostly zeros, so this is all the compression they need
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != 0 {
+            out.push(data[i]);
+            i += 1;
+            continue;
+        }
+        let run = data[i..].iter().take(255).take_while(|&&byte| byte == 0).count();
+        out.push(0);
+        out.push(run as u8);
+        i += run;
+    }
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            0 => out.extend(std::iter::repeat_n(0, usize::from(*bytes.next().unwrap_or(&0)))),
+            byte => out.push(byte),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pops_what_was_pushed() {
+        let mut buffer = RewindBuffer::new(RewindConfig {
+            keyframe_interval: 4,
+            ..RewindConfig::default()
+        });
+        let states = (0..10u8)
+            .map(|i| {
+                let mut state = vec![0; 600];
+                state[usize::from(i) * 7] = i + 1;
+                state[599] = i;
+                state
+            })
+            .collect::<Vec<_>>();
+        for state in &states {
+            buffer.push(state.clone());
+        }
+        // a longer state starts a new segment
+        buffer.push(vec![1; 700]);
+        assert_eq!(buffer.len(), 11);
+        assert!(buffer.size() < 10 * 600 / 4);
+
+        assert_eq!(buffer.pop(), Some(vec![1; 700]));
+        for state in states.iter().rev() {
+            assert_eq!(buffer.pop().as_ref(), Some(state));
+        }
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.size(), 0);
+    }
+}
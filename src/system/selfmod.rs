@@ -0,0 +1,58 @@
+use std::collections::BTreeMap;
+
+use super::MEMORY_SIZE;
+
+/// Tracks which addresses ran as instructions and which of them the program wrote afterwards.
+/// Self-modifying programs run differently from their disassembly and keep invalidating the
+/// blocks of `cache::CachedCore`. Disabled by default, as it costs a check on every write
+#[derive(Default)]
+pub struct SelfModification {
+    pub enabled: bool,
+    executed: Vec<bool>,
+    /// written addresses that had run, with the first instruction that wrote each
+    modified: BTreeMap<u16, u16>,
+}
+
+impl SelfModification {
+    /// Forgets what ran and was written, for a new program
+    pub fn clear(&mut self) {
+        self.executed.clear();
+        self.modified.clear();
+    }
+
+    pub(super) fn execute(&mut self, pc: u16) {
+        if !self.enabled {
+            return;
+        }
+        if self.executed.is_empty() {
+            self.executed = vec![false; usize::from(MEMORY_SIZE)];
+        }
+        for addr in [pc, pc.wrapping_add(1)] {
+            if let Some(executed) = self.executed.get_mut(usize::from(addr)) {
+                *executed = true;
+            }
+        }
+    }
+
+    /// Records a write of the instruction at `pc` to `addr`, returns true if `addr` had run
+    /// and was not written before
+    pub(super) fn write(&mut self, pc: u16, addr: u16) -> bool {
+        let executed = self.executed.get(usize::from(addr)).copied().unwrap_or(false);
+        if !executed || self.modified.contains_key(&addr) {
+            return false;
+        }
+        self.modified.insert(addr, pc);
+        true
+    }
+
+    /// whether the program wrote over an instruction it ran
+    pub fn is_self_modifying(&self) -> bool {
+        !self.modified.is_empty()
+    }
+
+    /// The addresses of instructions the program wrote over, each with the address of the
+    /// first instruction that wrote it
+    pub fn modified(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        self.modified.iter().map(|(addr, pc)| (*addr, *pc))
+    }
+}
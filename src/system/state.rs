@@ -0,0 +1,395 @@
+use super::device::{BANKS, BANK_SIZE};
+use super::quirks::Quirks;
+use super::{Execution, System, SystemError, MEMORY_SIZE, SCREEN_LEN};
+
+/// stack entries always in the state, deeper ones are only saved while in use
+const BASE_STACK_SIZE: usize = 16;
+
+/// Starts every state since version 1, followed by the version as a u16. States without it are
+/// version 0, they start with memory, which starts with the font
+const MAGIC: &[u8; 4] = b"C8ST";
+/// The version `save_state` writes. A change to the format bumps it and adds the step from the
+/// previous version to `upgrade`, so states saved by older builds keep loading
+pub const STATE_VERSION: u16 = 1;
+
+/// bytes from the start of memory to the end of the random number generator state, the part
+/// that has not changed since version 0
+const FIXED_LEN: usize = MEMORY_SIZE as usize + SCREEN_LEN + 16 + 2 + 2 + 1 + 1 + 2 * BASE_STACK_SIZE + 2 + 16 + 1 + 8;
+/// where the stack pointer is, in front of the keys, the key pressed and the generator state
+const SP_OFFSET: usize = FIXED_LEN - 8 - 1 - 16 - 2;
+
+/// the devices a state holds the registers of, one bit each
+const FRAME_COUNTER: u8 = 1;
+const BANK_SWITCH: u8 = 2;
+
+impl System {
+    /// Serializes the complete machine state, as version `STATE_VERSION`
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.mem.len() + SCREEN_LEN + 64);
+
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&STATE_VERSION.to_be_bytes());
+
+        out.extend_from_slice(&self.mem);
+        out.extend_from_slice(&self.screen);
+
+        out.extend_from_slice(&self.registers.reg);
+        out.extend_from_slice(&self.registers.index.to_be_bytes());
+        out.extend_from_slice(&self.registers.pc.to_be_bytes());
+
+        out.push(self.timers.delay);
+        out.push(self.timers.sound);
+
+        for addr in &self.stack.stack[..BASE_STACK_SIZE] {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+        out.extend_from_slice(&self.stack.sp.to_be_bytes());
+
+        out.extend_from_slice(&self.keys.keys);
+        out.push(self.key_pressed.unwrap_or(0xFF));
+
+        out.extend_from_slice(&self.rng.state().to_be_bytes());
+
+        out.push(self.execution as u8);
+        out.extend_from_slice(&self.frame_cycles.to_be_bytes());
+
+        // only the devices that are mapped, so states of roms that do not use them stay small
+        let devices = if self.quirks.frame_counter.is_some() { FRAME_COUNTER } else { 0 }
+            | if self.quirks.bank_port.is_some() { BANK_SWITCH } else { 0 };
+        out.push(devices);
+        if devices & FRAME_COUNTER != 0 {
+            out.extend_from_slice(&self.devices.frame_counter.frames.to_be_bytes());
+        }
+        if devices & BANK_SWITCH != 0 {
+            let mut bank_switch = self.devices.bank_switch.clone();
+            out.push(bank_switch.selected);
+            for bank in bank_switch.banks_mut().iter() {
+                out.extend_from_slice(bank);
+            }
+        }
+
+        for addr in self.stack.stack.get(BASE_STACK_SIZE..self.stack.sp as usize).unwrap_or(&[]) {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+
+        out
+    }
+
+    /// Hash of the complete machine state, see `save_state`
+    pub fn state_hash(&self) -> u64 {
+        crate::rom::hash(&self.save_state())
+    }
+
+    /// Restores a state produced by `save_state`, upgrading states of older versions.
+    /// The system is left untouched if the state is malformed or from a newer version
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SystemError> {
+        let (version, body) = match data.strip_prefix(MAGIC) {
+            Some(rest) if rest.len() >= 2 => (u16::from_be_bytes([rest[0], rest[1]]), &rest[2..]),
+            Some(_) => return Err(SystemError::InvalidState),
+            None => (0, data),
+        };
+        if version > STATE_VERSION {
+            return Err(SystemError::NewerState {
+                version,
+                supported: STATE_VERSION,
+            });
+        }
+        let body = upgrade(version, body, &self.quirks)?;
+
+        let mut reader = Reader { data: &body };
+        let mut system = System::default();
+
+        let mem_len = system.mem.len();
+        system.mem.copy_from_slice(reader.bytes(mem_len)?);
+        system.screen.copy_from_slice(reader.bytes(SCREEN_LEN)?);
+
+        system.registers.reg.copy_from_slice(reader.bytes(16)?);
+        system.registers.index = reader.u16()?;
+        system.registers.pc = reader.u16()?;
+
+        system.timers.delay = reader.u8()?;
+        system.timers.sound = reader.u8()?;
+
+        for addr in system.stack.stack[..BASE_STACK_SIZE].iter_mut() {
+            *addr = reader.u16()?;
+        }
+        system.stack.sp = reader.u16()?;
+        if system.stack.sp as usize > self.quirks.stack_depth() {
+            return Err(SystemError::InvalidState);
+        }
+
+        system.keys.keys.copy_from_slice(reader.bytes(16)?);
+        system.key_pressed = match reader.u8()? {
+            0xFF => None,
+            key => Some(key),
+        };
+
+        system.rng.set_state(reader.u64()?);
+
+        system.execution = match reader.u8()? {
+            0 => Execution::Running,
+            1 => Execution::WaitingForKey,
+            2 => Execution::Halted,
+            _ => return Err(SystemError::InvalidState),
+        };
+        system.frame_cycles = reader.u32()?;
+
+        // a device the state has and the quirks do not map keeps its registers unused
+        let devices = reader.u8()?;
+        if devices & !(FRAME_COUNTER | BANK_SWITCH) != 0 {
+            return Err(SystemError::InvalidState);
+        }
+        if devices & FRAME_COUNTER != 0 {
+            system.devices.frame_counter.frames = reader.u16()?;
+        }
+        if devices & BANK_SWITCH != 0 {
+            let bank_switch = &mut system.devices.bank_switch;
+            bank_switch.selected = reader.u8()?;
+            if usize::from(bank_switch.selected) >= BANKS {
+                return Err(SystemError::InvalidState);
+            }
+            for bank in bank_switch.banks_mut() {
+                bank.copy_from_slice(reader.bytes(BANK_SIZE)?);
+            }
+        }
+
+        let sp = system.stack.sp as usize;
+        for addr in system.stack.stack.get_mut(BASE_STACK_SIZE..sp).unwrap_or(&mut []) {
+            *addr = reader.u16()?;
+        }
+
+        if !reader.data.is_empty() {
+            return Err(SystemError::InvalidState);
+        }
+
+        system.diagnostics = std::mem::take(&mut self.diagnostics);
+        // what the program wrote before the state was saved is unknown
+        system.diagnostics.set_initialized(0..mem_len);
+        system.extensions = std::mem::take(&mut self.extensions);
+        system.hooks = std::mem::take(&mut self.hooks);
+        system.quirks = self.quirks;
+        system.memory_fill = self.memory_fill;
+        system.stack.max_depth = self.stack.max_depth.max(system.stack.sp);
+        *self = system;
+
+        Ok(())
+    }
+}
+
+/// Brings the body of a state of `version` up to `STATE_VERSION`, one version at a time
+fn upgrade(version: u16, body: &[u8], quirks: &Quirks) -> Result<Vec<u8>, SystemError> {
+    let mut body = body.to_vec();
+    for version in version..STATE_VERSION {
+        body = match version {
+            0 => upgrade_v0(&body, quirks)?,
+            _ => return Err(SystemError::InvalidState),
+        };
+    }
+    Ok(body)
+}
+
+/// Version 0 had no header and no record of the devices in it: the registers of a device
+/// followed when the quirks mapped it and anything was left. The execution state and frame
+/// cycles are missing from the oldest ones, which ended with the deep stack entries
+fn upgrade_v0(body: &[u8], quirks: &Quirks) -> Result<Vec<u8>, SystemError> {
+    let sp = match body.get(SP_OFFSET..SP_OFFSET + 2) {
+        Some(sp) => usize::from(u16::from_be_bytes([sp[0], sp[1]])),
+        None => return Err(SystemError::InvalidState),
+    };
+    let deep_len = 2 * sp.saturating_sub(BASE_STACK_SIZE);
+    let end = body
+        .len()
+        .checked_sub(deep_len)
+        .filter(|&end| end >= FIXED_LEN)
+        .ok_or(SystemError::InvalidState)?;
+    let (fixed, rest) = body[..end].split_at(FIXED_LEN);
+
+    let mut out = fixed.to_vec();
+    if rest.is_empty() {
+        out.push(Execution::Running as u8);
+        out.extend_from_slice(&0u32.to_be_bytes());
+        out.push(0);
+    } else {
+        if rest.len() < 5 {
+            return Err(SystemError::InvalidState);
+        }
+        let (execution, registers) = rest.split_at(5);
+        let mut devices = 0;
+        let mut left = registers.len();
+        if quirks.frame_counter.is_some() && left != 0 {
+            devices |= FRAME_COUNTER;
+            left = left.saturating_sub(2);
+        }
+        if quirks.bank_port.is_some() && left != 0 {
+            devices |= BANK_SWITCH;
+        }
+        out.extend_from_slice(execution);
+        out.push(devices);
+        out.extend_from_slice(registers);
+    }
+    out.extend_from_slice(&body[end..]);
+
+    Ok(out)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SystemError> {
+        if self.data.len() < len {
+            return Err(SystemError::InvalidState);
+        }
+
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, SystemError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SystemError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, SystemError> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    fn u64(&mut self) -> Result<u64, SystemError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::debug::Debugger;
+    use super::super::extension::Step;
+    use super::super::hooks::Event;
+    use super::super::rng::Rng;
+    use super::*;
+
+    /// A system with every part of the state filled with random values that are valid
+    fn random_system(rng: &mut Rng) -> System {
+        let mut system = System::default();
+        system.quirks.stack_size = 64;
+        if rng.next_u8() & 1 == 1 {
+            system.quirks.frame_counter = Some(0xFF0);
+        }
+        if rng.next_u8() & 1 == 1 {
+            system.quirks.bank_port = Some(0x1FF);
+        }
+
+        system.mem.iter_mut().for_each(|byte| *byte = rng.next_u8());
+        system.screen.iter_mut().for_each(|pixel| *pixel = rng.next_u8() & 1);
+        system.registers.reg.iter_mut().for_each(|reg| *reg = rng.next_u8());
+        system.registers.index = rng.next_u64() as u16;
+        system.registers.pc = rng.next_u64() as u16;
+        system.timers.delay = rng.next_u8();
+        system.timers.sound = rng.next_u8();
+        system.stack.sp = u16::from(rng.next_u8() % 65);
+        system.stack.stack.iter_mut().for_each(|addr| *addr = rng.next_u64() as u16);
+        system.keys.keys.iter_mut().for_each(|key| *key = rng.next_u8() & 1);
+        system.key_pressed = Some(rng.next_u8() % 17).filter(|key| *key < 16);
+        system.rng.set_state(rng.next_u64());
+        system.execution = [Execution::Running, Execution::WaitingForKey, Execution::Halted][usize::from(rng.next_u8() % 3)];
+        system.frame_cycles = rng.next_u64() as u32;
+        system.devices.frame_counter.frames = rng.next_u64() as u16;
+        let bank_switch = &mut system.devices.bank_switch;
+        bank_switch.selected = rng.next_u8() % BANKS as u8;
+        for bank in bank_switch.banks_mut() {
+            bank.iter_mut().for_each(|byte| *byte = rng.next_u8());
+        }
+        system
+    }
+
+    fn restore(quirks: Quirks, state: &[u8]) -> Result<System, SystemError> {
+        let mut system = System {
+            quirks,
+            ..System::default()
+        };
+        system.load_state(state)?;
+        Ok(system)
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut rng = Rng::new(1);
+        for _ in 0..200 {
+            let system = random_system(&mut rng);
+            let state = system.save_state();
+            let restored = restore(system.quirks, &state).unwrap();
+            assert_eq!(restored.save_state(), state);
+
+            // the same state as version 0 upgrades to it, with the execution state and the
+            // oldest form without it as well
+            let mut v0 = state[MAGIC.len() + 2..].to_vec();
+            v0.remove(FIXED_LEN + 5);
+            assert_eq!(restore(system.quirks, &v0).unwrap().save_state(), state);
+            if system.quirks.frame_counter.is_none() && system.quirks.bank_port.is_none() {
+                v0.drain(FIXED_LEN..FIXED_LEN + 5);
+                let restored = restore(system.quirks, &v0).unwrap();
+                let saved = usize::from(system.stack.sp).max(BASE_STACK_SIZE);
+                assert_eq!(restored.stack.stack[..saved], system.stack.stack[..saved]);
+                assert_eq!(restored.execution, Execution::Running);
+            }
+
+            // cut short or longer is malformed
+            assert!(restore(system.quirks, &state[..state.len() - 1]).is_err());
+            assert!(restore(system.quirks, &[&state[..], &[0]].concat()).is_err());
+        }
+    }
+
+    #[test]
+    fn keeps_extensions() {
+        // FX75 is not a CHIP-8 instruction, the extension stores X in VF
+        let mut system = System::default();
+        system.register_extension(0xF075, 0xF0FF, |system, opcode| {
+            system.registers.reg[0xF] = (opcode >> 8 & 0xF) as u8;
+            Ok(Step::Next)
+        });
+        system.load(&[0xF3, 0x75][..]).unwrap();
+        let state = system.save_state();
+        system.load_state(&state).unwrap();
+
+        system.tick(&mut Debugger::disabled()).unwrap();
+        assert_eq!((system.registers.reg[0xF], system.registers.pc), (3, 0x202));
+    }
+
+    #[test]
+    fn keeps_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut system = System::default();
+        let clears = events.clone();
+        system.on_clear_screen(move |_| clears.lock().unwrap().push(Event::ClearScreen));
+        // CLS
+        system.load(&[0x00, 0xE0][..]).unwrap();
+        let state = system.save_state();
+        system.load_state(&state).unwrap();
+
+        system.tick(&mut Debugger::disabled()).unwrap();
+        assert_eq!(*events.lock().unwrap(), [Event::ClearScreen]);
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut state = System::default().save_state();
+        state[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(STATE_VERSION + 1).to_be_bytes());
+        match restore(Quirks::default(), &state) {
+            Err(SystemError::NewerState { version, supported }) => {
+                assert_eq!((version, supported), (STATE_VERSION + 1, STATE_VERSION))
+            }
+            other => panic!("loaded a newer state: {:?}", other.map(|_| ())),
+        }
+    }
+}
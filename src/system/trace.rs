@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+use super::machine::Chip8Core;
+use super::{System, SystemError};
+
+/// bytes of undo data kept by default
+const DEFAULT_CAPACITY: usize = 16 * 1024 * 1024;
+
+/// unchanged bytes between two changed ones that are still stored in the same run,
+/// cheaper than the 4 byte header of a new run
+const MAX_GAP: usize = 4;
+
+struct Entry {
+    pc: u16,
+    opcode: u16,
+    /// the length of the save state from before the instruction as a `u32`, which differs
+    /// from the one after when the stack grows past `BASE_STACK_SIZE`, then runs of
+    /// `offset: u16, len: u16, bytes` restoring it
+    undo: Vec<u8>,
+}
+
+/// Per-instruction history of the system. Each entry holds the bytes of the save state from
+/// before an instruction that the instruction changed, uncompressed, in runs found by comparing
+/// the whole save states before and after it. Oldest entries are dropped once their runs take
+/// `capacity` bytes
+pub struct Trace {
+    entries: VecDeque<Entry>,
+    size: usize,
+    capacity: usize,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Trace::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Trace {
+    pub fn new(capacity: usize) -> Self {
+        Trace {
+            entries: VecDeque::new(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Runs `step` on the core and records how to undo everything it changed in its state,
+    /// even if it fails halfway
+    pub fn record<C: Chip8Core, T>(
+        &mut self,
+        core: &mut C,
+        step: impl FnOnce(&mut C) -> Result<T, SystemError>,
+    ) -> Result<T, SystemError> {
+        let pc = core.state().registers.pc;
+        let opcode = core.state().read_mem_pair(pc).unwrap_or(0);
+        let before = core.state().save_state();
+        let res = step(core);
+        let after = core.state().save_state();
+
+        let undo = diff(&before, &after);
+        self.size += undo.len();
+        self.entries.push_back(Entry { pc, opcode, undo });
+        while self.size > self.capacity {
+            match self.entries.pop_front() {
+                Some(entry) => self.size -= entry.undo.len(),
+                None => break,
+            }
+        }
+
+        res
+    }
+
+    /// Restores the state from before the last recorded instruction.
+    /// Returns its address and opcode, or none at the start of the trace
+    pub fn step_back(&mut self, system: &mut System) -> Result<Option<(u16, u16)>, SystemError> {
+        let entry = match self.entries.pop_back() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.size -= entry.undo.len();
+
+        let mut state = system.save_state();
+        patch(&mut state, &entry.undo)?;
+        system.load_state(&state)?;
+
+        Ok(Some((entry.pc, entry.opcode)))
+    }
+}
+
+/// the length of `before` and the runs of its bytes that differ from `after`, or that `after`
+/// does not have
+fn diff(before: &[u8], after: &[u8]) -> Vec<u8> {
+    let differs = |i: usize| after.get(i) != Some(&before[i]);
+    let mut out = (before.len() as u32).to_be_bytes().to_vec();
+    let mut i = 0;
+    while i < before.len() {
+        if !differs(i) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let mut end = i + 1;
+        while end < before.len() {
+            let next_change = (end..before.len().min(end + MAX_GAP + 1)).find(|&j| differs(j));
+            match next_change {
+                Some(j) => end = j + 1,
+                None => break,
+            }
+        }
+
+        out.extend_from_slice(&(start as u16).to_be_bytes());
+        out.extend_from_slice(&((end - start) as u16).to_be_bytes());
+        out.extend_from_slice(&before[start..end]);
+        i = end;
+    }
+
+    out
+}
+
+fn patch(state: &mut Vec<u8>, undo: &[u8]) -> Result<(), SystemError> {
+    let (len, mut undo) = undo.split_first_chunk::<4>().ok_or(SystemError::InvalidState)?;
+    state.resize(u32::from_be_bytes(*len) as usize, 0);
+    while !undo.is_empty() {
+        if undo.len() < 4 {
+            return Err(SystemError::InvalidState);
+        }
+        let offset = usize::from(u16::from_be_bytes([undo[0], undo[1]]));
+        let len = usize::from(u16::from_be_bytes([undo[2], undo[3]]));
+        let bytes = undo.get(4..4 + len).ok_or(SystemError::InvalidState)?;
+        state
+            .get_mut(offset..offset + len)
+            .ok_or(SystemError::InvalidState)?
+            .copy_from_slice(bytes);
+        undo = &undo[4 + len..];
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::debug::Debugger;
+
+    #[test]
+    fn steps_back_through_calls_past_the_base_stack() {
+        // V0 += 1, calls itself until V0 is 20, then returns all the way
+        let rom = [0x70, 0x01, 0x30, 0x14, 0x22, 0x00, 0x00, 0xEE];
+        let mut system = System::default();
+        system.quirks.stack_size = 32;
+        system.load(&rom[..]).unwrap();
+
+        let mut trace = Trace::default();
+        let mut states = Vec::new();
+        loop {
+            states.push(system.save_state());
+            if trace.record(&mut system, |system| system.tick(&mut Debugger::disabled())).is_err() {
+                break;
+            }
+        }
+        assert_eq!(system.stack.sp, 0);
+
+        while let Some(state) = states.pop() {
+            trace.step_back(&mut system).unwrap().expect("the trace ended early");
+            assert_eq!(system.save_state(), state);
+        }
+        assert_eq!(trace.step_back(&mut system).unwrap(), None);
+    }
+
+    #[test]
+    fn stepping_back_restores_the_earlier_state_hash() {
+        // draws a digit at a random place, counts and loops
+        let rom = [0xC0, 0x3F, 0xC1, 0x1F, 0xF2, 0x29, 0xD0, 0x15, 0x72, 0x01, 0x12, 0x00];
+        let mut system = System::default();
+        system.seed(7);
+        system.load(&rom[..]).unwrap();
+
+        let mut trace = Trace::default();
+        let mut hashes = Vec::new();
+        for _ in 0..60 {
+            hashes.push(system.state_hash());
+            trace.record(&mut system, |system| system.tick(&mut Debugger::disabled())).unwrap();
+        }
+        for steps in 1..=hashes.len() {
+            trace.step_back(&mut system).unwrap().unwrap();
+            assert_eq!(system.state_hash(), hashes[hashes.len() - steps], "after {} steps back", steps);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use failure::Error;
+use std::path::{Path, PathBuf};
+
+use crate::capture;
+use crate::system::{debug::Debugger, System, SystemError};
+
+/// image pixels per screen pixel in the failure images
+const SCALE: usize = 4;
+/// between the panels
+const GAP: usize = 8;
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+const BACKGROUND: [u8; 4] = [0x20, 0x20, 0x20, 0xFF];
+const OFF: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+const ON: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+/// in the diff panel: lit in both, only in the reference and only in the run
+const BOTH: [u8; 4] = [0x40, 0x40, 0x40, 0xFF];
+const MISSING: [u8; 4] = [0xE0, 0x30, 0x30, 0xFF];
+const EXTRA: [u8; 4] = [0x30, 0xD0, 0x40, 0xFF];
+
+/// Runs the rom for up to `cycles` instructions, returning the final screen
+fn run_rom(path: &Path, cycles: u32) -> Result<String, Error> {
+    let mut system = System::default();
+    system.load_from_file(path)?;
+
+    let mut dbg = Debugger::disabled();
+    for _ in 0..cycles {
+        match system.tick(&mut dbg) {
+            Ok(()) => {}
+            Err(SystemError::ZeroInstruction) => break,
+            Err(e) => return Err(e.into()),
+        }
+        system.dec_timers();
+    }
+
+    Ok(system.screen_ascii('#', '.'))
+}
+
+/// Lit pixels of a screen from `System::screen_ascii`, what is missing counts as unlit
+fn pixels(screen: &str) -> Vec<bool> {
+    let mut pixels = vec![false; WIDTH * HEIGHT];
+    for (y, line) in screen.lines().take(HEIGHT).enumerate() {
+        for (x, c) in line.chars().take(WIDTH).enumerate() {
+            pixels[y * WIDTH + x] = c == '#';
+        }
+    }
+    pixels
+}
+
+/// A PNG of the expected screen, the actual one and where they differ, side by side
+fn composite(expected: &str, actual: &str) -> Vec<u8> {
+    let (expected, actual) = (pixels(expected), pixels(actual));
+    let panel = WIDTH * SCALE;
+    let (width, height) = (panel * 3 + GAP * 4, HEIGHT * SCALE + GAP * 2);
+    let mut rgba = BACKGROUND.repeat(width * height);
+
+    for i in 0..WIDTH * HEIGHT {
+        let diff = match (expected[i], actual[i]) {
+            (true, true) => BOTH,
+            (true, false) => MISSING,
+            (false, true) => EXTRA,
+            (false, false) => OFF,
+        };
+        let colors = [
+            if expected[i] { ON } else { OFF },
+            if actual[i] { ON } else { OFF },
+            diff,
+        ];
+        let (x, y) = (i % WIDTH * SCALE, i / WIDTH * SCALE + GAP);
+        for (n, color) in colors.iter().enumerate() {
+            let left = GAP + n * (panel + GAP) + x;
+            for row in y..y + SCALE {
+                let start = (row * width + left) * 4;
+                rgba[start..start + SCALE * 4].copy_from_slice(&color.repeat(SCALE));
+            }
+        }
+    }
+
+    capture::png(&rgba, width as u32, height as u32)
+}
+
+/// Runs every `.ch8` rom in `dir` and compares the final screen against the `.txt` file
+/// next to it. With `bless` the reference files are (re)written instead. For every mismatch
+/// `<rom>.png` in `report` shows the expected screen, the actual one and their difference.
+/// Returns the number of failed roms
+pub fn run(dir: &Path, cycles: u32, bless: bool, report: &Path) -> Result<usize, Error> {
+    let mut roms = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    roms.retain(|path| path.extension().is_some_and(|ext| ext == "ch8"));
+    roms.sort();
+
+    let mut failed = 0;
+    for rom in &roms {
+        let name = rom.file_name().unwrap_or_default().to_string_lossy();
+        let expected_path = rom.with_extension("txt");
+
+        let screen = match run_rom(rom, cycles) {
+            Ok(screen) => screen,
+            Err(e) => {
+                println!("FAIL {}: {}", name, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        if bless {
+            std::fs::write(&expected_path, &screen)?;
+            println!("BLESS {}", name);
+            continue;
+        }
+
+        match std::fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == screen => println!("PASS {}", name),
+            Ok(expected) => {
+                std::fs::create_dir_all(report)?;
+                let image = report.join(rom.with_extension("png").file_name().unwrap_or_default());
+                std::fs::write(&image, composite(&expected, &screen))?;
+                println!("FAIL {}: screen mismatch, see {}", name, image.display());
+                failed += 1;
+            }
+            Err(_) => println!("SKIP {}: no {}", name, expected_path.display()),
+        }
+    }
+
+    println!("{} roms, {} failed", roms.len(), failed);
+
+    Ok(failed)
+}
@@ -0,0 +1,59 @@
+//! The rates the emulator runs at and the durations they come to. Periods are whole
+//! nanoseconds worked out with integers, so ticks add up the same on every platform and
+//! the rounding happens once, here
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// delay and sound timers count down at 60Hz whatever the cpu speed
+pub const TIMER_HZ: u32 = 60;
+/// one tick of the timers, an emulated frame
+pub const FRAME: Duration = period(TIMER_HZ);
+
+/// redraws per second of the screens that only wait for a key: the menu, the rom picker and
+/// the end of a program
+pub const MENU_HZ: u32 = 30;
+pub const MENU_FRAME: Duration = period(MENU_HZ);
+
+/// The time between ticks at `hz` per second, to the nearest nanosecond
+pub const fn period(hz: u32) -> Duration {
+    let hz = if hz == 0 { 1 } else { hz as u64 };
+    Duration::from_nanos((NANOS_PER_SEC + hz / 2) / hz)
+}
+
+/// `period` for rates from the config, which may have a fraction. They count to the
+/// millihertz, and anything below 1 Hz runs at 1 Hz
+pub fn period_of(hz: f64) -> Duration {
+    let millihertz = (hz.max(1.0) * 1000.0).round() as u64;
+    Duration::from_nanos((NANOS_PER_SEC * 1000 + millihertz / 2) / millihertz)
+}
+
+/// Timer ticks `duration` takes, rounded up
+pub fn frames_in(duration: Duration) -> u32 {
+    let ticks = (duration.as_nanos() * u128::from(TIMER_HZ)).div_ceil(u128::from(NANOS_PER_SEC));
+    u32::try_from(ticks).unwrap_or(u32::MAX)
+}
+
+/// Samples at `rate` per second that make up `ticks` timer ticks, rounded down
+pub fn samples_in(ticks: u64, rate: u32) -> u64 {
+    ticks * u64::from(rate) / u64::from(TIMER_HZ)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_the_durations() {
+        assert_eq!(FRAME, Duration::from_nanos(16_666_667));
+        assert_eq!(MENU_FRAME, Duration::from_nanos(33_333_333));
+        assert_eq!(period_of(2400.0), Duration::from_nanos(416_667));
+        assert_eq!(period_of(59.94), Duration::from_nanos(16_683_350));
+        assert_eq!(period_of(0.0), Duration::from_secs(1));
+        assert_eq!(frames_in(Duration::from_millis(50)), 3);
+        assert_eq!(frames_in(Duration::from_millis(1000)), 60);
+        assert_eq!(samples_in(1, 44100), 735);
+    }
+}
@@ -0,0 +1,171 @@
+use failure::Error;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::headless::Headless;
+use crate::scores::ScoreRegion;
+use crate::script::Script;
+use crate::system::System;
+
+/// frames a bot plays before its thread moves on to the bot that waited longest
+const SLICE_FRAMES: u64 = 60;
+
+/// A bot script with a name for the leaderboard, usually its file name
+pub struct Bot {
+    pub name: String,
+    pub script: Script,
+}
+
+/// A bot's game between its slices. Not a `Headless`, whose sound can not move to another
+/// thread
+struct Game {
+    bot: usize,
+    system: System,
+    frame: u64,
+    best: Option<u64>,
+    error: Option<String>,
+}
+
+/// How a bot did
+#[derive(Debug, PartialEq)]
+pub struct Standing {
+    pub bot: String,
+    /// the best score read at the end of a frame, none if it never held a valid number
+    pub score: Option<u64>,
+    /// frames played, fewer than asked if the program stopped with an error
+    pub frames: u64,
+    pub error: Option<String>,
+}
+
+/// Lets every bot play `frames` frames of its own copy of the game made by `system`, on
+/// `jobs` threads. The threads take turns on the games a slice of frames at a time, so they
+/// all advance together however many there are. Returns the standings, best score first
+pub fn run(
+    system: impl Fn() -> Result<System, Error>,
+    bots: &[Bot],
+    region: ScoreRegion,
+    frames: u64,
+    jobs: usize,
+) -> Result<Vec<Standing>, Error> {
+    let games = bots
+        .iter()
+        .enumerate()
+        .map(|(bot, _)| {
+            Ok(Game {
+                bot,
+                system: system()?,
+                frame: 0,
+                best: None,
+                error: None,
+            })
+        })
+        .collect::<Result<VecDeque<_>, Error>>()?;
+
+    let playing = AtomicUsize::new(games.len());
+    let queue = Mutex::new(games);
+    let done = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, bots.len().max(1)) {
+            scope.spawn(|| {
+                while playing.load(Ordering::Acquire) > 0 {
+                    let game = queue.lock().expect("tournament queue poisoned").pop_front();
+                    let Some(mut game) = game else {
+                        // the last games are being played by other threads
+                        std::thread::yield_now();
+                        continue;
+                    };
+                    let script = &bots[game.bot].script;
+                    play_slice(&mut game, script, region, frames);
+                    if game.error.is_some() || game.frame >= frames {
+                        done.lock().expect("tournament results poisoned").push(game);
+                        playing.fetch_sub(1, Ordering::Release);
+                    } else {
+                        queue.lock().expect("tournament queue poisoned").push_back(game);
+                    }
+                }
+            });
+        }
+    });
+
+    let mut standings = done
+        .into_inner()
+        .expect("tournament results poisoned")
+        .into_iter()
+        .map(|game| Standing {
+            bot: bots[game.bot].name.clone(),
+            score: game.best,
+            frames: game.frame,
+            error: game.error,
+        })
+        .collect::<Vec<_>>();
+    standings.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.bot.cmp(&b.bot)));
+    Ok(standings)
+}
+
+/// Plays up to `SLICE_FRAMES` frames, reading the score after each
+fn play_slice(game: &mut Game, script: &Script, region: ScoreRegion, frames: u64) {
+    let mut headless = Headless::new(std::mem::take(&mut game.system));
+    headless.frame = game.frame;
+    let end = frames.min(headless.frame + SLICE_FRAMES);
+    while headless.frame < end {
+        if let Err(e) = script.play(&mut headless, 1) {
+            game.error = Some(format!("the program stopped in frame {}: {}", headless.frame, e));
+            break;
+        }
+        if let Some(score) = region.read(&headless.system) {
+            game.best = game.best.max(Some(score));
+        }
+    }
+    game.frame = headless.frame;
+    game.system = headless.system;
+}
+
+pub fn write_leaderboard(standings: &[Standing], mut out: impl std::io::Write) -> Result<(), Error> {
+    writeln!(out, "{:>4}  {:<24} {:>10} {:>8}", "rank", "bot", "score", "frames")?;
+    for (rank, standing) in standings.iter().enumerate() {
+        let score = standing.score.map_or("-".to_string(), |score| score.to_string());
+        write!(out, "{:>4}  {:<24} {:>10} {:>8}", rank + 1, standing.bot, score, standing.frames)?;
+        match &standing.error {
+            Some(error) => writeln!(out, "  {}", error)?,
+            None => writeln!(out)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scores::ScoreFormat;
+
+    #[test]
+    fn ranks_bots_by_score() {
+        // V3 += 1 while key 5 (in V5) is held, stored at 0x300 every loop
+        let rom = [0x65, 0x05, 0xE5, 0xA1, 0x73, 0x01, 0xA3, 0x00, 0x80, 0x30, 0xF0, 0x55, 0x12, 0x02];
+        let system = || {
+            let mut system = System::default();
+            system.load(&rom[..])?;
+            Ok(system)
+        };
+        let bot = |name: &str, script: &str| Bot {
+            name: name.to_string(),
+            script: Script::parse(script).unwrap(),
+        };
+        let bots = [
+            bot("idle", ""),
+            bot("eager", "when v3 < 200 press 5"),
+            bot("late", "at frame 1 press 5\nat frame 2 release 5"),
+        ];
+        let region = ScoreRegion {
+            addr: 0x300,
+            len: 1,
+            format: ScoreFormat::Binary,
+        };
+
+        let standings = run(system, &bots, region, 150, 2).unwrap();
+        let board = standings.iter().map(|standing| (standing.bot.as_str(), standing.score)).collect::<Vec<_>>();
+        assert_eq!(board, [("eager", Some(200)), ("late", Some(7)), ("idle", Some(0))]);
+        assert!(standings.iter().all(|standing| standing.frames == 150 && standing.error.is_none()));
+    }
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::splash;
+
+/// how often the directory is listed
+const INTERVAL: Duration = Duration::from_millis(250);
+/// a rom modified more recently than this may still be being written
+const SETTLE: Duration = Duration::from_millis(200);
+
+/// Notices roms in a directory being written, for `--watch-dir`: a rom that an external
+/// assembler such as Octo rebuilds or adds is run at once
+pub struct RomWatcher {
+    pub dir: PathBuf,
+    /// of every rom as last listed
+    modified: HashMap<PathBuf, SystemTime>,
+    last_check: Instant,
+}
+
+impl RomWatcher {
+    /// Roms already in `dir` only count once they are written again
+    pub fn new(dir: PathBuf) -> Self {
+        let modified = splash::roms_in(&dir)
+            .into_iter()
+            .filter_map(|rom| modified(&rom).map(|time| (rom, time)))
+            .collect();
+        RomWatcher {
+            dir,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// The most recently modified of the roms written or added since the last call
+    pub fn poll(&mut self) -> Option<PathBuf> {
+        if self.last_check.elapsed() < INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let now = SystemTime::now();
+        let mut changed: Option<(PathBuf, SystemTime)> = None;
+        for rom in splash::roms_in(&self.dir) {
+            let time = match modified(&rom) {
+                Some(time) => time,
+                None => continue,
+            };
+            // left for the next call, which sees it finished
+            if now.duration_since(time).is_ok_and(|age| age < SETTLE) {
+                continue;
+            }
+            if self.modified.insert(rom.clone(), time) != Some(time)
+                && changed.as_ref().is_none_or(|&(_, latest)| time > latest)
+            {
+                changed = Some((rom, time));
+            }
+        }
+
+        changed.map(|(rom, _)| rom)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,165 @@
+use failure::Error;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::system::System;
+
+/// longest the watchdog thread sleeps between looks at the heartbeat
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What the run loop last told the watchdog thread
+struct Heartbeat {
+    start: Instant,
+    /// milliseconds from `start` to the last beat, 0 while disarmed
+    last: AtomicU64,
+    pc: AtomicU16,
+    cycles: AtomicU64,
+    rom_hash: AtomicU64,
+    /// set by the thread when the loop stalls, taken by the loop when it beats again
+    stalled: AtomicBool,
+}
+
+impl Heartbeat {
+    fn now(&self) -> u64 {
+        // a beat at 0 would read as disarmed
+        self.start.elapsed().as_millis() as u64 + 1
+    }
+}
+
+/// Watches the run loop from a thread of its own. A loop that stops finishing frames, blocked
+/// on a deadlock, a pathological rom or the audio backend, is reported after `timeout`, and
+/// the process exits after `exit_after` if it never comes back
+pub struct Watchdog {
+    heartbeat: Arc<Heartbeat>,
+}
+
+impl Watchdog {
+    /// Starts the thread, which writes its report to `dir` when it gives up. A zero
+    /// `exit_after` waits for the loop forever
+    pub fn spawn(timeout: Duration, exit_after: Duration, dir: PathBuf) -> Result<Self, Error> {
+        let heartbeat = Arc::new(Heartbeat {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            pc: AtomicU16::new(0),
+            cycles: AtomicU64::new(0),
+            rom_hash: AtomicU64::new(0),
+            stalled: AtomicBool::new(false),
+        });
+        let watched = heartbeat.clone();
+        std::thread::Builder::new()
+            .name("watchdog".to_string())
+            .spawn(move || watch(&watched, timeout, exit_after, &dir))?;
+        Ok(Watchdog { heartbeat })
+    }
+
+    /// Tells the thread the loop is making progress, arming it if it was not. Returns how long
+    /// the loop was stalled if the thread reported it
+    pub fn beat(&self, system: &System, rom_hash: u64) -> Option<Duration> {
+        let heartbeat = &self.heartbeat;
+        heartbeat.pc.store(system.registers.pc, Ordering::Relaxed);
+        heartbeat.cycles.store(system.cycles, Ordering::Relaxed);
+        heartbeat.rom_hash.store(rom_hash, Ordering::Relaxed);
+        let now = heartbeat.now();
+        let last = heartbeat.last.swap(now, Ordering::AcqRel);
+        if heartbeat.stalled.swap(false, Ordering::AcqRel) && last != 0 {
+            Some(Duration::from_millis(now - last))
+        } else {
+            None
+        }
+    }
+
+    /// Stops watching until the next `beat`, for waits on the user
+    pub fn disarm(&self) {
+        self.heartbeat.last.store(0, Ordering::Release);
+        self.heartbeat.stalled.store(false, Ordering::Release);
+    }
+}
+
+fn watch(heartbeat: &Arc<Heartbeat>, timeout: Duration, exit_after: Duration, dir: &Path) {
+    // the `Watchdog` holds the other reference
+    while Arc::strong_count(heartbeat) > 1 {
+        std::thread::sleep(CHECK_INTERVAL.min(timeout / 4));
+        let last = heartbeat.last.load(Ordering::Acquire);
+        if last == 0 {
+            continue;
+        }
+        let stalled = Duration::from_millis(heartbeat.now().saturating_sub(last));
+        let (pc, cycles) = (heartbeat.pc.load(Ordering::Relaxed), heartbeat.cycles.load(Ordering::Relaxed));
+        if stalled >= timeout && !heartbeat.stalled.swap(true, Ordering::AcqRel) {
+            log::error!(
+                "The emulator has not finished a frame in {:.1} s, it stopped at {:03X} after {} instructions",
+                stalled.as_secs_f64(),
+                pc,
+                cycles
+            );
+        }
+        if !exit_after.is_zero() && stalled >= exit_after && heartbeat.last.load(Ordering::Acquire) == last {
+            let report = StallReport::new(stalled, pc, cycles, heartbeat.rom_hash.load(Ordering::Relaxed));
+            match report.write(dir) {
+                Ok(path) => log::error!("Stall report written to {}", path.display()),
+                Err(e) => log::error!("Could not write a stall report: {}", e),
+            }
+            log::error!("The emulator did not recover in {:.0} s, exiting", exit_after.as_secs_f64());
+            std::process::exit(1);
+        }
+    }
+}
+
+/// What the watchdog thread knows of a loop that never recovered, written like a
+/// `crash::CrashDump`. The state stays with the stalled loop
+#[derive(Serialize)]
+pub struct StallReport {
+    /// seconds since the Unix epoch
+    pub time: u64,
+    pub reason: String,
+    /// hex `rom::hash` of the rom
+    pub rom_hash: String,
+    pub pc: String,
+    /// instructions executed before the stall
+    pub cycles: u64,
+}
+
+impl StallReport {
+    fn new(stalled: Duration, pc: u16, cycles: u64, rom_hash: u64) -> Self {
+        StallReport {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+            reason: format!("the emulator stalled for {:.1} s", stalled.as_secs_f64()),
+            rom_hash: format!("{:016x}", rom_hash),
+            pc: format!("{:03X}", pc),
+            cycles,
+        }
+    }
+
+    /// Writes `stall-<time>.json` to `dir`, creating it if needed, and returns its path
+    pub fn write(&self, dir: &Path) -> Result<PathBuf, Error> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("stall-{}.json", self.time));
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_stall_once_the_loop_beats_again() {
+        let timeout = Duration::from_millis(40);
+        let watchdog = Watchdog::spawn(timeout, Duration::ZERO, PathBuf::new()).unwrap();
+        let system = System::default();
+        assert_eq!(watchdog.beat(&system, 0), None);
+        std::thread::sleep(timeout * 5);
+        let stalled = watchdog.beat(&system, 0).expect("the stall was not seen");
+        assert!(stalled >= timeout);
+        assert_eq!(watchdog.beat(&system, 0), None);
+
+        // waits on the user are not stalls
+        watchdog.disarm();
+        std::thread::sleep(timeout * 5);
+        assert_eq!(watchdog.beat(&system, 0), None);
+    }
+}
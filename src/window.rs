@@ -1,4 +1,13 @@
+#![allow(deprecated)]
+
 use failure::Error;
+use std::time::Instant;
+
+use crate::framebuffer::FrameBuffer;
+use crate::frontend::geometry::{Monitor, WindowGeometry};
+use crate::frontend::{bezel, blend, palette, shader, Frontend, Input, Layout, PresentMode, Region, Scaling, Viewport};
+use crate::input;
+use crate::overlay;
 
 #[derive(Clone, Copy)]
 struct Vertex {
@@ -15,34 +24,96 @@ impl From<[i32; 2]> for Vertex {
 
 implement_vertex!(Vertex, pos);
 
+#[derive(Clone, Copy)]
+struct OverlayVertex {
+    pos: [f32; 2],
+    color: [f32; 4],
+}
+
+implement_vertex!(OverlayVertex, pos, color);
+
+/// The glium (OpenGL) frontend
 pub struct Window {
-    pub ev: glium::glutin::EventsLoop,
+    ev: glium::glutin::EventsLoop,
     display: glium::Display,
     program: glium::Program,
+    overlay_program: glium::Program,
+    bezel_program: glium::Program,
     vb: glium::VertexBuffer<Vertex>,
     ib: glium::IndexBuffer<u16>,
+    blender: blend::FrameBlender,
+    palette: palette::Palette,
+    tone: palette::Tone,
+    scaling: Scaling,
+    bezel: Option<(bezel::Bezel, glium::texture::Texture2d)>,
+    debug: Option<DebugWindow>,
+    /// single-channel shades of the last frame, kept to be written over by the next one
+    screen: Option<glium::texture::Texture2d>,
+    /// where the last frame went, None before the first
+    layout: Option<Layout>,
+    /// winit can set it but not tell
+    fullscreen: bool,
+    /// for the `time` uniform of the screen shader
+    start: Instant,
+}
+
+/// A second window on the same events loop, showing the debugger
+struct DebugWindow {
+    display: glium::Display,
+    overlay_program: glium::Program,
+}
+
+/// the debugger window has the aspect ratio of the game's, which overlay text is made for
+const DEBUG_WINDOW_SIZE: (u32, u32) = (960, 480);
+
+/// `viewport` with the origin in the bottom left corner, as OpenGL has it
+fn gl_rect(window: (u32, u32), viewport: Viewport) -> glium::Rect {
+    glium::Rect {
+        left: viewport.x,
+        bottom: window.1.saturating_sub(viewport.y + viewport.height),
+        width: viewport.width,
+        height: viewport.height,
+    }
 }
 
 impl Window {
-    fn program(facade: &impl glium::backend::Facade) -> Result<glium::Program, Error> {
+    fn program(facade: &impl glium::backend::Facade, shader: &shader::Shader) -> Result<glium::Program, Error> {
+        glium::Program::from_source(facade, &shader.vertex, &shader.fragment, None).map_err(Into::into)
+    }
+
+    fn overlay_program(facade: &impl glium::backend::Facade) -> Result<glium::Program, Error> {
+        glium::Program::from_source(
+            facade,
+            include_str!("overlay_vertex.glsl"),
+            include_str!("overlay_fragment.glsl"),
+            None,
+        )
+        .map_err(Into::into)
+    }
+
+    fn bezel_program(facade: &impl glium::backend::Facade) -> Result<glium::Program, Error> {
         glium::Program::from_source(
             facade,
-            include_str!("vertex.glsl"),
-            include_str!("fragment.glsl"),
+            shader::VERTEX,
+            include_str!("bezel_fragment.glsl"),
             None,
         )
         .map_err(Into::into)
     }
 
-    pub fn new() -> Result<Self, Error> {
+    pub fn new(dimensions: (u32, u32), present: PresentMode) -> Result<Self, Error> {
         let ev = glium::glutin::EventsLoop::new();
         let wb = glium::glutin::WindowBuilder::new()
-            .with_dimensions((800, 400).into())
+            .with_dimensions(dimensions.into())
             .with_title("Chip8");
-        let cb = glium::glutin::ContextBuilder::new();
+        // OpenGL has no mailbox, vsync is the closest without tearing
+        let vsync = matches!(present, PresentMode::Vsync | PresentMode::Mailbox);
+        let cb = glium::glutin::ContextBuilder::new().with_vsync(vsync);
 
         let display = glium::Display::new(wb, cb, &ev)?;
-        let program = Self::program(&display)?;
+        let program = Self::program(&display, &shader::Shader::default())?;
+        let overlay_program = Self::overlay_program(&display)?;
+        let bezel_program = Self::bezel_program(&display)?;
         let vb = glium::VertexBuffer::new(
             &display,
             &[
@@ -65,46 +136,339 @@ impl Window {
             ev,
             display,
             program,
+            overlay_program,
+            bezel_program,
             vb,
             ib,
+            blender: blend::FrameBlender::default(),
+            palette: palette::Palette::default(),
+            tone: palette::Tone::default(),
+            scaling: Scaling::default(),
+            bezel: None,
+            debug: None,
+            screen: None,
+            layout: None,
+            fullscreen: false,
+            start: Instant::now(),
         })
     }
 
-    pub fn draw(&mut self, data: Vec<u8>, width: u32, height: u32) -> Result<(), Error> {
+    /// Writes the shades of a frame to the screen texture, making a new one when the size changes.
+    /// The texture has a single 8 bit channel, the palette is applied by the fragment shader
+    fn upload_screen(&mut self, data: Vec<u8>, width: u32, height: u32) -> Result<&glium::texture::Texture2d, Error> {
+        let image = glium::texture::RawImage2d {
+            data: data.into(),
+            width,
+            height,
+            format: glium::texture::ClientFormat::U8,
+        };
+        match &self.screen {
+            Some(texture) if (texture.width(), texture.height()) == (width, height) => {
+                let rect = glium::Rect {
+                    left: 0,
+                    bottom: 0,
+                    width,
+                    height,
+                };
+                texture.write(rect, image);
+            }
+            _ => {
+                self.screen = Some(glium::texture::Texture2d::with_format(
+                    &self.display,
+                    image,
+                    glium::texture::UncompressedFloatFormat::U8,
+                    glium::texture::MipmapsOption::NoMipmap,
+                )?);
+            }
+        }
+        Ok(self.screen.as_ref().unwrap())
+    }
+
+    fn draw_bezel(&self, frame: &mut glium::Frame, viewport: Viewport) -> Result<(), Error> {
         use glium::Surface;
 
-        let texture = glium::texture::Texture2d::new(
-            &self.display,
-            glium::texture::RawImage2d {
-                data: data.into(),
-                width,
-                height,
-                format: glium::texture::ClientFormat::U8,
+        let texture = match &self.bezel {
+            Some((_, texture)) => texture,
+            None => return Ok(()),
+        };
+        frame.draw(
+            &self.vb,
+            &self.ib,
+            &self.bezel_program,
+            &uniform! {
+                tex: glium::uniforms::Sampler::new(texture)
+                    .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+            },
+            &glium::DrawParameters {
+                blend: glium::Blend::alpha_blending(),
+                viewport: Some(gl_rect(frame.get_dimensions(), viewport)),
+                ..Default::default()
             },
         )?;
 
+        Ok(())
+    }
+}
+
+/// Draws `rects` over what is in `frame`
+fn draw_overlay(
+    display: &glium::Display,
+    program: &glium::Program,
+    frame: &mut glium::Frame,
+    rects: &[overlay::Rect],
+) -> Result<(), Error> {
+    use glium::Surface;
+
+    let vertices = rects
+        .iter()
+        .flat_map(|rect| {
+            let [x, y] = rect.pos;
+            let [w, h] = rect.size;
+            let color = rect.color;
+            vec![
+                [x, y],
+                [x + w, y],
+                [x + w, y + h],
+                [x + w, y + h],
+                [x, y + h],
+                [x, y],
+            ]
+            .into_iter()
+            .map(move |pos| OverlayVertex { pos, color })
+        })
+        .collect::<Vec<_>>();
+
+    let vb = glium::VertexBuffer::new(display, &vertices)?;
+
+    frame.draw(
+        &vb,
+        glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList),
+        program,
+        &glium::uniforms::EmptyUniforms,
+        &glium::DrawParameters {
+            blend: glium::Blend::alpha_blending(),
+            ..Default::default()
+        },
+    )?;
+
+    Ok(())
+}
+
+impl Frontend for Window {
+    fn set_palette(&mut self, palette: palette::Palette) {
+        self.palette = palette;
+    }
+
+    fn set_anti_flicker(&mut self, settings: blend::AntiFlicker) {
+        self.blender.set_settings(settings);
+    }
+
+    fn set_tone(&mut self, tone: palette::Tone) {
+        self.tone = tone.clamped();
+    }
+
+    fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+    }
+
+    fn set_bezel(&mut self, bezel: Option<bezel::Bezel>) {
+        self.bezel = bezel.and_then(|bezel| {
+            let image = glium::texture::RawImage2d::from_raw_rgba(bezel.rgba.clone(), (bezel.width, bezel.height));
+            match glium::texture::Texture2d::new(&self.display, image) {
+                Ok(texture) => Some((bezel, texture)),
+                Err(e) => {
+                    log::warn!(target: "display", "Could not create the bezel texture: {}", e);
+                    None
+                }
+            }
+        });
+    }
+
+    fn set_shader(&mut self, shader: Option<shader::Shader>) -> Result<(), Error> {
+        self.program = Self::program(&self.display, &shader.unwrap_or_default())?;
+        Ok(())
+    }
+
+    fn draw(&mut self, screen: FrameBuffer, _dirty: Option<&[Region]>, overlay: &[overlay::Rect]) -> Result<(), Error> {
+        let (width, height) = (screen.width(), screen.height());
+        use glium::Surface;
+
+        let data = self.blender.blend(screen.into_pixels());
+        self.upload_screen(data, width, height)?;
+        let texture = self.screen.as_ref().unwrap();
+
         let mut frame = self.display.draw();
+        let window = frame.get_dimensions();
+        let (bezel, screen) = match &self.bezel {
+            Some((bezel, _)) => {
+                let (area, screen) = bezel.layout(window, (width, height), self.scaling);
+                (Some((area, bezel.layer)), Some(screen))
+            }
+            None => (None, self.scaling.viewport(window, (width, height))),
+        };
+        let viewport = screen.map(|screen| gl_rect(window, screen));
+        self.layout = Some(Layout::new(window, screen, (width, height)));
 
-        frame.clear_color(0.0, 0.0, 0.0, 1.0);
+        let [r, g, b] = self.tone.apply_f32(self.palette.background().to_f32());
+        frame.clear_color(r, g, b, 1.0);
+        if let Some((area, bezel::Layer::Back)) = bezel {
+            self.draw_bezel(&mut frame, area)?;
+        }
         frame.draw(
             &self.vb,
             &self.ib,
             &self.program,
             &uniform! {
-                tex: glium::uniforms::Sampler::new(&texture)
+                tex: glium::uniforms::Sampler::new(texture)
                     .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest),
+                background: self.palette.colors[0].to_f32(),
+                foreground: self.palette.colors[1].to_f32(),
+                brightness: self.tone.brightness as f32,
+                gamma: self.tone.gamma as f32,
+                time: self.start.elapsed().as_secs_f32(),
+                resolution: [width as f32, height as f32],
+                output_size: viewport.map_or([window.0 as f32, window.1 as f32], |rect| [rect.width as f32, rect.height as f32]),
             },
             &glium::DrawParameters {
                 depth: glium::Depth {
                     // test: glium::DepthTest::Ignore,
                     ..Default::default()
                 },
+                viewport,
                 ..Default::default()
             },
         )?;
+        if let Some((area, bezel::Layer::Front)) = bezel {
+            self.draw_bezel(&mut frame, area)?;
+        }
+
+        if !overlay.is_empty() {
+            draw_overlay(&self.display, &self.overlay_program, &mut frame, overlay)?;
+        }
 
         frame.finish()?;
 
         Ok(())
     }
+
+    fn open_debug_window(&mut self) -> Result<bool, Error> {
+        if self.debug.is_none() {
+            let wb = glium::glutin::WindowBuilder::new()
+                .with_dimensions(DEBUG_WINDOW_SIZE.into())
+                .with_title("Chip8 debugger");
+            let display = glium::Display::new(wb, glium::glutin::ContextBuilder::new(), &self.ev)?;
+            let overlay_program = Self::overlay_program(&display)?;
+            self.debug = Some(DebugWindow { display, overlay_program });
+        }
+        Ok(true)
+    }
+
+    fn close_debug_window(&mut self) {
+        self.debug = None;
+    }
+
+    fn debug_window_open(&self) -> bool {
+        self.debug.is_some()
+    }
+
+    fn draw_debug_window(&mut self, rects: &[overlay::Rect]) -> Result<(), Error> {
+        use glium::Surface;
+
+        if let Some(debug) = &self.debug {
+            let mut frame = debug.display.draw();
+            frame.clear_color(0.0, 0.0, 0.0, 1.0);
+            draw_overlay(&debug.display, &debug.overlay_program, &mut frame, rects)?;
+            frame.finish()?;
+        }
+        Ok(())
+    }
+
+    fn geometry(&self) -> Option<WindowGeometry> {
+        let gl_window = self.display.gl_window();
+        let window = gl_window.window();
+        let hidpi = window.get_hidpi_factor();
+        let size = window.get_inner_size()?.to_physical(hidpi);
+        Some(WindowGeometry {
+            width: size.width.round() as u32,
+            height: size.height.round() as u32,
+            position: window.get_position().map(|position| {
+                let position = position.to_physical(hidpi);
+                (position.x.round() as i32, position.y.round() as i32)
+            }),
+            fullscreen: self.fullscreen,
+        })
+    }
+
+    fn set_geometry(&mut self, geometry: &WindowGeometry, size: bool) {
+        use glium::glutin::dpi::{PhysicalPosition, PhysicalSize};
+
+        {
+            let gl_window = self.display.gl_window();
+            let window = gl_window.window();
+            let hidpi = window.get_hidpi_factor();
+            let monitors = window
+                .get_available_monitors()
+                .map(|monitor| {
+                    let (position, dimensions) = (monitor.get_position(), monitor.get_dimensions());
+                    Monitor {
+                        x: position.x as i32,
+                        y: position.y as i32,
+                        width: dimensions.width as u32,
+                        height: dimensions.height as u32,
+                    }
+                })
+                .collect::<Vec<_>>();
+            if let Some((x, y)) = geometry.position_on(&monitors) {
+                window.set_position(PhysicalPosition::new(f64::from(x), f64::from(y)).to_logical(hidpi));
+            }
+            if size {
+                let physical = PhysicalSize::new(f64::from(geometry.width), f64::from(geometry.height));
+                window.set_inner_size(physical.to_logical(hidpi));
+            }
+        }
+        self.set_fullscreen(geometry.fullscreen);
+    }
+
+    fn set_fullscreen(&mut self, fullscreen: bool) {
+        let gl_window = self.display.gl_window();
+        let window = gl_window.window();
+        window.set_fullscreen(if fullscreen { Some(window.get_current_monitor()) } else { None });
+        self.fullscreen = fullscreen;
+    }
+
+    fn set_title(&mut self, title: &str) {
+        self.display.gl_window().window().set_title(title);
+    }
+
+    fn poll_input(&mut self) -> Vec<Input> {
+        use glium::glutin::{Event, WindowEvent};
+
+        let debug_id = self.debug.as_ref().map(|debug| debug.display.gl_window().window().id());
+        let (layout, hidpi) = (self.layout, self.display.gl_window().get_hidpi_factor());
+        let mut close_debug = false;
+        let mut input = Vec::new();
+        self.ev.poll_events(|event| {
+            if let Event::WindowEvent { event, window_id } = event {
+                match event {
+                    // keys pressed in either window reach the game, closing the debugger
+                    // window only closes it
+                    WindowEvent::CloseRequested if Some(window_id) == debug_id => close_debug = true,
+                    WindowEvent::KeyboardInput { input: key, .. } => input.extend(input::glutin::key(key)),
+                    WindowEvent::CloseRequested => input.push(Input::Close),
+                    // the layout is in physical pixels, like the frame
+                    WindowEvent::CursorMoved { position, .. } if Some(window_id) != debug_id => {
+                        let position = position.to_physical(hidpi);
+                        input.push(Input::Cursor(layout.map(|layout| layout.cursor(position.x, position.y))));
+                    }
+                    WindowEvent::CursorLeft { .. } if Some(window_id) != debug_id => input.push(Input::Cursor(None)),
+                    _ => {}
+                }
+            }
+        });
+        if close_debug {
+            self.debug = None;
+        }
+
+        input
+    }
 }